@@ -23,6 +23,13 @@ CRATES = {
     "ordered-float": "3.0.0",
     "bencher": "0.1.5",
     "cpu-time": "1.0.0",
+    "tracing": "0.1",
+    "tracing-subscriber": dict(
+        version = "0.3",
+        features = ["env-filter"],
+    ),
+    "tracing-chrome": "0.7",
+    "directories": "4.0",
 
     # serde
     "serde": dict(
@@ -32,6 +39,16 @@ CRATES = {
     "serde_with": "1.14.0",
     "serde_json": "1.0",
     "toml": "0.5",
+    "bincode": "1.3",
+    "flate2": "1.0",
+    "schemars": dict(
+        version = "0.8",
+        features = ["chrono"],
+    ),
+    "jsonschema": dict(
+        version = "0.17",
+        default_features = False,
+    ),
 
     # math
     "cgmath": dict(
@@ -1,12 +1,15 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+use memory_size::MemorySize;
 use serde::{Deserialize, Serialize};
 use uom::si::time::hour;
 use uom::si::u64::Time;
 
 use crate::agent_data::AgentData;
-use crate::agent_route_state::{AgentRoutePhase, AgentRouteState, RouteType};
-use crate::common::{agent_log, agent_log_timestamp, Error};
+use crate::agent_log::{agent_log, agent_log_timestamp};
+use crate::agent_route_state::{AgentRoutePhase, AgentRouteState, RouteDetail, RouteType};
+use crate::common::Error;
+use crate::workplace_happiness::{HappinessComponent, WorkplaceHappiness};
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,9 +29,31 @@ pub struct Agent {
     pub housing: quadtree::Address,
     pub workplace: Option<quadtree::Address>,
     pub state: AgentState,
+    /// the household this agent belongs to, if any; see Household
+    #[serde(default)]
+    pub household: Option<u64>,
     parked_car: Option<quadtree::Address>,
     /// estimate of commute duration, in seconds
-    pub route_lengths: HashMap<RouteType, f32>,
+    pub route_lengths: BTreeMap<RouteType, f32>,
+    /// the industry of the agent's workplace when they were hired
+    #[serde(default)]
+    pub workplace_industry: Option<tiles::Industry>,
+    /// the time at which the agent was hired at their current workplace, if known
+    #[serde(default)]
+    pub hired_at: Option<u64>,
+}
+
+/// Agents in transit own their route, which is usually most of their memory.
+impl MemorySize for Agent {
+    fn heap_bytes(&self) -> usize {
+        let state = match &self.state {
+            AgentState::Route(route_state) => {
+                route_state.route.heap_bytes() + memory_size::vec_bytes(&route_state.legs)
+            }
+            AgentState::Tile(_) | AgentState::Unknown => 0,
+        };
+        memory_size::btree_map_bytes(&self.route_lengths) + state
+    }
 }
 
 impl Agent {
@@ -39,27 +64,50 @@ impl Agent {
         workplace: Option<quadtree::Address>,
     ) -> Self {
         use enum_iterator::IntoEnumIterator;
-        let mut route_lengths = HashMap::new();
+        let mut route_lengths = BTreeMap::new();
         for route_type in RouteType::into_enum_iter() {
             route_lengths.insert(route_type, 0.0);
         }
+        // by default, agents start out at home
+        let parked_car = data.owns_car.then_some(housing);
         Self {
             id,
             data,
             housing,
             workplace,
-            // by default, agents start out at home
-            parked_car: Some(housing),
+            household: None,
+            parked_car,
             route_lengths,
+            workplace_industry: None,
+            hired_at: None,
             state: AgentState::Tile(housing),
         }
     }
 
+    /// Start working at the given workplace, which is in the given industry.
+    pub fn take_job(&mut self, workplace: quadtree::Address, industry: tiles::Industry, time: u64) {
+        self.workplace = Some(workplace);
+        self.workplace_industry = Some(industry);
+        self.hired_at = Some(time);
+    }
+
+    pub fn leave_job(&mut self) {
+        self.workplace = None;
+        self.workplace_industry = None;
+        self.hired_at = None;
+    }
+
+    /**
+     * Start following the given route. Returns the time at which the route should next be
+     * advanced, or None if the route has no edges, in which case it is already finished and
+     * finish_route should be called right away.
+     */
     pub fn begin_route<F: state::Fields>(
         &mut self,
         route: route::Route,
         start_time: u64,
         route_type: RouteType,
+        detail: RouteDetail,
         world_state: &mut route::WorldStateImpl,
         state: &state::State<F>,
     ) -> Result<Option<u64>, Error> {
@@ -68,6 +116,7 @@ impl Agent {
             route,
             start_time,
             route_type,
+            detail,
             world_state,
             state,
             self.parked_car,
@@ -104,71 +153,92 @@ impl Agent {
         Ok(())
     }
 
-    /// Teleport an agent home if they are at work and no route could be found for returning home.
-    pub fn teleport_home(&mut self, world_state: &mut route::WorldStateImpl) -> Result<(), Error> {
+    /**
+     * Teleport an agent home if they are out and no route could be found for returning home. The
+     * given route is recorded as having taken a long time.
+     */
+    pub fn teleport_home(
+        &mut self,
+        route_type: RouteType,
+        world_state: &mut route::WorldStateImpl,
+    ) -> Result<(), Error> {
         assert!(matches!(
             self.state,
             AgentState::Tile(_) | AgentState::Unknown
         ));
 
+        // agents without a car still don't have one once they get home
         if let Some(parked_car) = self.parked_car {
+            self.log(|| format!("relocating car home from {:?}", parked_car));
             world_state.decrement_parking(parked_car)?;
+            self.parked_car = Some(self.housing);
+            world_state.increment_parking(self.housing)?;
         }
-        self.parked_car = Some(self.housing);
-        world_state.increment_parking(self.housing)?;
         self.state = AgentState::Tile(self.housing);
 
-        self.record_route_time(
-            RouteType::CommuteFromWork,
-            Time::new::<hour>(4).value as f32,
-        );
+        self.record_route_time(route_type, Time::new::<hour>(4).value as f32);
 
         Ok(())
     }
 
+    /**
+     * Stop following the current route, leaving the agent in an unknown state until they are given
+     * somewhere to be. A car that is already parked stays where it is; a car that is being driven,
+     * or that is looking for parking, is parked back at home. The world state is updated to match
+     * either way, so the car is never lost.
+     */
     pub fn abort_route(&mut self, world_state: &mut route::WorldStateImpl) -> Result<(), Error> {
         match &self.state {
-            AgentState::Route(AgentRouteState {
-                route_type,
-                phase:
-                    AgentRoutePhase::InProgress {
-                        current_edge,
-                        current_edge_start,
-                        current_edge_total,
-                        ..
-                    },
-                route,
-                parked_car,
-                ..
-            }) => {
+            AgentState::Route(
+                route_state @ AgentRouteState {
+                    route_type,
+                    phase:
+                        AgentRoutePhase::InProgress {
+                            current_edge_start,
+                            current_edge_total,
+                            ..
+                        },
+                    parked_car,
+                    ..
+                },
+            ) => {
                 let route_type = *route_type;
 
                 match *parked_car {
                     Some(parked_car) => {
-                        self.log(|| "agent already parked");
+                        self.log(|| format!("car already parked at {:?}", parked_car));
 
                         self.parked_car = Some(parked_car);
                     }
-                    None => {
-                        // teleport car to the destination
-
-                        let destination = route.end();
-
+                    None if route_state.is_using_car() => {
+                        // the car is on the road (or looking for parking), where the world state
+                        // doesn't count it, so park it back at home
+                        let housing = self.housing;
                         self.log(|| {
                             format!(
-                                "agent currently driving; teleporting to destination: {:?}",
-                                destination
+                                "agent currently driving; relocating car home: {:?}",
+                                housing
                             )
                         });
 
-                        world_state.increment_parking(destination)?;
-                        self.parked_car = Some(destination);
+                        world_state.increment_parking(housing)?;
+                        self.parked_car = Some(housing);
+                    }
+                    None => {
+                        // agents without a car, or who haven't taken one out, don't get one
+                        self.log(|| "no car to park");
+
+                        self.parked_car = None;
                     }
                 }
 
-                // make sure to decrement the edge so that congestion totals are consistent
-                let edge = &route.edges[*current_edge as usize];
-                world_state.decrement_edge(edge)?;
+                // make sure to decrement the edges so that congestion totals are consistent
+                let edges = route_state
+                    .current_edges()
+                    .expect("route edge out of bounds");
+                for edge in edges {
+                    world_state.decrement_edge(edge)?;
+                }
 
                 let total_time = current_edge_start + current_edge_total;
                 self.record_route_time(route_type, total_time);
@@ -191,19 +261,100 @@ impl Agent {
         Ok(())
     }
 
+    /**
+     * Stop following the current route partway, at the given address, e.g. to find a new route
+     * once the rest of this one has been closed. The agent is left at the address, and a car that
+     * is being driven, or that is looking for parking, is parked there. Returns the interrupted
+     * route.
+     */
+    pub fn interrupt_route(
+        &mut self,
+        address: quadtree::Address,
+        world_state: &mut route::WorldStateImpl,
+    ) -> Result<AgentRouteState, Error> {
+        let route_state = match std::mem::replace(&mut self.state, AgentState::Tile(address)) {
+            AgentState::Route(route_state) => route_state,
+            _ => panic!("agent not in route state"),
+        };
+
+        for edge in route_state
+            .current_edges()
+            .expect("route edge out of bounds")
+        {
+            world_state.decrement_edge(edge)?;
+        }
+
+        self.parked_car = if route_state.is_using_car() {
+            self.log(|| format!("route interrupted while driving; parking at {:?}", address));
+            world_state.increment_parking(address)?;
+            Some(address)
+        } else {
+            route_state.parked_car
+        };
+
+        Ok(route_state)
+    }
+
     pub fn average_commute_length(&self) -> f32 {
         let sum = self.route_lengths[&RouteType::CommuteToWork]
             + self.route_lengths[&RouteType::CommuteFromWork];
         sum / 2.0
     }
 
-    /// How happy this agent is with their current workplace.
+    /**
+     * How happy this agent is with their current workplace, and why. Parts that the agent has no
+     * information for, like the education fit of agents hired before industries were recorded,
+     * are left out.
+     */
+    pub fn workplace_happiness(
+        &self,
+        config: &state::Config,
+        current_time: u64,
+    ) -> Option<WorkplaceHappiness> {
+        use HappinessComponent::*;
+
+        self.workplace?;
+        let archetypes = &config.archetypes;
+        let config = &config.workplace_happiness;
+        let tenure = self.hired_at.map(|hired_at| {
+            let tenure = current_time.saturating_sub(hired_at) as f32;
+            (tenure / config.tenure_horizon.max(1) as f32).min(1.0)
+        });
+        Some(WorkplaceHappiness::combine(
+            &[
+                (
+                    Commute,
+                    Some(
+                        self.data
+                            .commute_satisfaction(archetypes, self.average_commute_length()),
+                    ),
+                ),
+                // TODO: workplaces don't pay wages yet
+                (Wage, None),
+                (
+                    EducationFit,
+                    self.workplace_industry.map(|industry| {
+                        crate::workplace_happiness::education_fit(
+                            self.data.years_of_education,
+                            industry,
+                        )
+                    }),
+                ),
+                (Tenure, tenure),
+            ],
+            config,
+        ))
+    }
+
+    /// The overall score of workplace_happiness.
     /// 0.0 means they want to quit immediately and 1.0 means they definitely don't want to leave.
-    pub fn workplace_happiness_score(&self) -> Option<f32> {
-        self.workplace.map(|_| {
-            self.data
-                .expected_workplace_happiness(self.average_commute_length())
-        })
+    pub fn workplace_happiness_score(
+        &self,
+        config: &state::Config,
+        current_time: u64,
+    ) -> Option<f32> {
+        self.workplace_happiness(config, current_time)
+            .map(|happiness| happiness.score)
     }
 
     pub fn parked_car(&self) -> Option<quadtree::Address> {
@@ -220,7 +371,16 @@ impl Agent {
         }
     }
 
+    /**
+     * Whether this agent has a car of their own. Household members may still be able to drive using
+     * one of the household's cars.
+     */
     pub fn owns_car(&self) -> bool {
+        self.data.owns_car
+    }
+
+    /// Is this agent currently holding a car, either parked somewhere or being driven?
+    pub fn has_car(&self) -> bool {
         self.parked_car().is_some()
             || matches!(
                 &self.state,
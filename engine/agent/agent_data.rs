@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -62,6 +63,45 @@ impl Age {
     pub fn is_working_age(&self) -> bool {
         self.0 >= 15 && self.0 < 65
     }
+
+    pub fn bucket(&self) -> AgeBucket {
+        if self.is_senior() {
+            AgeBucket::Senior
+        } else if self.is_adult() {
+            AgeBucket::Adult
+        } else {
+            AgeBucket::Child
+        }
+    }
+}
+
+/// Coarse age groups, for where people live rather than what they do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AgeBucket {
+    /// 0-17
+    Child,
+    /// 18-64
+    Adult,
+    /// 65 and up
+    Senior,
+}
+
+impl AgeBucket {
+    pub const COUNT: usize = 3;
+    pub const ALL: [AgeBucket; Self::COUNT] = [Self::Child, Self::Adult, Self::Senior];
+
+    /// A unique index in [0, COUNT), for storing per-bucket data in arrays.
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Child => "children",
+            Self::Adult => "adults",
+            Self::Senior => "seniors",
+        }
+    }
 }
 
 impl std::fmt::Display for Age {
@@ -70,12 +110,54 @@ impl std::fmt::Display for Age {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct AgentData {
     /// used to compute age
     pub birthday: chrono::NaiveDate,
     /// total years of schooling
     pub years_of_education: u32,
+    /// whether this agent has a car of their own; members of a household share its cars instead
+    #[serde(default = "default_owns_car")]
+    pub owns_car: bool,
+    /// multiplier for the standard walking speed, e.g. 0.5 for someone who walks at half speed
+    #[serde(default = "default_walking_speed")]
+    pub walking_speed: f64,
+    /// index into the archetypes in the config; agents from before archetypes get the first one
+    #[serde(default)]
+    pub archetype: u32,
+    /// how this agent differs from the rest of their archetype
+    #[serde(default)]
+    pub noise: BehaviorNoise,
+}
+
+/**
+ * Per-agent multipliers for the values of their archetype, so that agents of the same archetype
+ * don't all behave identically. These stay fixed, while the archetype itself can be edited.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct BehaviorNoise {
+    /// multiplier for both housing and workplace stickiness
+    pub stickiness: f32,
+    pub commute_length_tolerance: f32,
+}
+
+impl Default for BehaviorNoise {
+    fn default() -> Self {
+        Self {
+            stickiness: 1.0,
+            commute_length_tolerance: 1.0,
+        }
+    }
+}
+
+fn default_owns_car() -> bool {
+    // before this was tracked, every agent started out with a car
+    true
+}
+
+fn default_walking_speed() -> f64 {
+    1.0
 }
 
 impl AgentData {
@@ -97,26 +179,51 @@ impl AgentData {
         EducationDegree::from_years_of_education(self.years_of_education)
     }
 
+    pub fn archetype<'a>(
+        &self,
+        archetypes: &'a state::AgentArchetypes,
+    ) -> &'a state::AgentArchetype {
+        archetypes.get(self.archetype)
+    }
+
+    /// The mobility profile to use for this agent's route queries.
+    pub fn mobility_profile(&self, archetypes: &state::AgentArchetypes) -> route::MobilityProfile {
+        let archetype = self.archetype(archetypes);
+        route::MobilityProfile {
+            walking_speed: self.walking_speed * archetype.walking_speed,
+            transfer_penalty: archetype.transfer_penalty,
+        }
+    }
+
     /// How much this agent likes to stay in the same housing situation.
     /// 1.0 means they never move; 0.0 means they constantly want to move.
-    pub fn housing_stickiness(&self) -> f32 {
-        0.7
+    pub fn housing_stickiness(&self, archetypes: &state::AgentArchetypes) -> f32 {
+        let stickiness = self.archetype(archetypes).housing_stickiness * self.noise.stickiness;
+        stickiness.clamp(0.0, 1.0)
     }
 
     /// How much this agent likes to stay at the same job.
     /// 1.0 means they never leave their job; 0.0 means they constantly look for new jobs.
-    pub fn workplace_stickiness(&self) -> f32 {
-        0.5
+    pub fn workplace_stickiness(&self, archetypes: &state::AgentArchetypes) -> f32 {
+        let stickiness = self.archetype(archetypes).workplace_stickiness * self.noise.stickiness;
+        stickiness.clamp(0.0, 1.0)
     }
 
     /// How long this agent is willing to drive to/from work (each way), in seconds.
-    pub fn commute_length_tolerance(&self) -> u32 {
-        60 * 60 // 1 hour
+    pub fn commute_length_tolerance(&self, archetypes: &state::AgentArchetypes) -> u32 {
+        let tolerance = self.archetype(archetypes).commute_length_tolerance as f32
+            * self.noise.commute_length_tolerance;
+        (tolerance.round() as u32).max(1)
     }
 
-    pub fn expected_workplace_happiness(&self, commute_length: f32) -> f32 {
-        // TODO: a more nuanced approximation here; also, should incorporate other information
-        let fraction = commute_length / self.commute_length_tolerance() as f32;
+    /// How satisfied this agent is with a commute of the given length, in [0, 1].
+    pub fn commute_satisfaction(
+        &self,
+        archetypes: &state::AgentArchetypes,
+        commute_length: f32,
+    ) -> f32 {
+        // TODO: a more nuanced approximation here
+        let fraction = commute_length / self.commute_length_tolerance(archetypes) as f32;
         assert!(fraction >= 0.0);
         1.0 - fraction.min(1.0)
     }
@@ -130,6 +237,10 @@ mod agent_data_tests {
         AgentData {
             birthday: chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap(),
             years_of_education: 0,
+            owns_car: true,
+            walking_speed: 1.0,
+            archetype: 0,
+            noise: BehaviorNoise::default(),
         }
     }
 
@@ -163,6 +274,35 @@ mod agent_data_tests {
         );
     }
 
+    #[test]
+    fn age_bucket() {
+        assert_eq!(Age(0).bucket(), AgeBucket::Child);
+        assert_eq!(Age(17).bucket(), AgeBucket::Child);
+        assert_eq!(Age(18).bucket(), AgeBucket::Adult);
+        assert_eq!(Age(64).bucket(), AgeBucket::Adult);
+        assert_eq!(Age(65).bucket(), AgeBucket::Senior);
+    }
+
+    #[test]
+    fn archetype() {
+        let mut archetypes = state::AgentArchetypes::default();
+        let mut agent = with_birthday(2000, 2, 15);
+        assert_eq!(agent.housing_stickiness(&archetypes), 0.7);
+        assert_eq!(agent.commute_length_tolerance(&archetypes), 3600);
+        assert!(agent.mobility_profile(&archetypes).is_standard());
+
+        agent.noise = BehaviorNoise {
+            stickiness: 2.0,
+            commute_length_tolerance: 0.5,
+        };
+        assert_eq!(agent.housing_stickiness(&archetypes), 1.0);
+        assert_eq!(agent.commute_length_tolerance(&archetypes), 1800);
+
+        // the archetype is read every time, so editing it affects the agent right away
+        archetypes.get_mut(0).unwrap().commute_length_tolerance = 7200;
+        assert_eq!(agent.commute_length_tolerance(&archetypes), 3600);
+    }
+
     #[test]
     fn education_degree() {
         assert!(EducationDegree::NoDegree < EducationDegree::HighSchool);
@@ -0,0 +1,374 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The number of recent log lines kept for each watched agent, unless otherwise specified.
+pub const AGENT_LOG_CAPACITY: usize = 200;
+
+/// The recent log lines of a single watched agent.
+#[derive(Debug)]
+struct AgentLog {
+    lines: VecDeque<String>,
+    capacity: usize,
+    /// every line is also written here, for traces that are longer than the ring
+    file: Option<std::io::LineWriter<std::fs::File>>,
+}
+
+impl AgentLog {
+    fn push(&mut self, id: u64, line: String) {
+        if let Some(file) = &mut self.file {
+            if let Err(err) = writeln!(file, "{}", line) {
+                eprintln!(
+                    "Failed to write log for agent {}, closing file: {}",
+                    id, err
+                );
+                self.file = None;
+            }
+        }
+        if self.capacity > 0 {
+            if self.lines.len() == self.capacity {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(line);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// an agent whose every log line is printed, whether or not it is watched
+    traced: Option<u64>,
+    watched: Mutex<HashMap<u64, AgentLog>>,
+    /// Checked before taking the lock, so that logging is free when no agents are being watched.
+    any_watched: AtomicBool,
+}
+
+/**
+ * The agents being watched in one engine, along with their captured log lines. Agent ids are only
+ * unique within one engine, so each engine has its own.
+ *
+ * Agents log from places that don't have access to the engine, so the engine makes its logs the
+ * current ones for the thread (see enter) in each of its entry points that can make agents log,
+ * and agent_log writes to whichever logs are current. Work that the engine hands off to other
+ * threads enters a sink (see sink) on the worker. Lines logged while no logs are current are
+ * discarded.
+ *
+ * Cloning gives a fresh set that traces the same agent but watches none, so that a copy of an
+ * engine doesn't capture lines from the original or vice versa.
+ */
+#[derive(Debug)]
+pub struct AgentLogs {
+    inner: Arc<Inner>,
+}
+
+impl Default for AgentLogs {
+    /// For debugging purposes, the user may optionally set DEBUG_TRACE_AGENT=id for some agent id
+    /// to print a trace of all of that agent's actions.
+    fn default() -> Self {
+        Self::new(
+            std::env::var("DEBUG_TRACE_AGENT")
+                .ok()
+                .and_then(|val| val.parse().ok()),
+        )
+    }
+}
+
+impl Clone for AgentLogs {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.traced)
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<Inner>>> = RefCell::new(None);
+}
+
+/**
+ * A handle to the logs of one engine that can be sent to other threads, so that work done on them
+ * on behalf of the engine can log too; see AgentLogs::sink.
+ */
+#[derive(Debug, Clone)]
+pub struct AgentLogSink {
+    inner: Arc<Inner>,
+}
+
+impl AgentLogSink {
+    /// Like AgentLogs::enter, for the thread that this is used on.
+    pub fn enter(&self) -> AgentLogsGuard {
+        enter(&self.inner)
+    }
+}
+
+fn enter(inner: &Arc<Inner>) -> AgentLogsGuard {
+    let previous = CURRENT.with(|current| current.borrow_mut().replace(Arc::clone(inner)));
+    AgentLogsGuard { previous }
+}
+
+/// Makes the previously current logs current again when dropped; see AgentLogs::enter.
+#[must_use]
+pub struct AgentLogsGuard {
+    previous: Option<Arc<Inner>>,
+}
+
+impl Drop for AgentLogsGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+impl AgentLogs {
+    /// Logs that print every line of the given agent, if any, and watch no agents.
+    pub fn new(traced: Option<u64>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                traced,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /**
+     * Make these the logs that agent_log writes to on this thread until the guard is dropped. This
+     * nests, so one engine can be run in the middle of another's update.
+     */
+    pub fn enter(&self) -> AgentLogsGuard {
+        enter(&self.inner)
+    }
+
+    /// A handle to these logs for entering them on another thread, e.g. in a thread pool.
+    pub fn sink(&self) -> AgentLogSink {
+        AgentLogSink {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    /**
+     * Start keeping the most recent `capacity` log lines of the given agent. If a file is given,
+     * every line is also appended to it. Watching an agent that is already watched replaces its
+     * settings but keeps the lines captured so far.
+     */
+    pub fn watch(
+        &self,
+        id: u64,
+        capacity: usize,
+        file: Option<&std::path::Path>,
+    ) -> std::io::Result<()> {
+        let file = match file {
+            Some(path) => Some(std::io::LineWriter::new(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?,
+            )),
+            None => None,
+        };
+
+        let mut watched = self.inner.watched.lock().unwrap();
+        let log = watched.entry(id).or_insert_with(|| AgentLog {
+            lines: VecDeque::new(),
+            capacity,
+            file: None,
+        });
+        log.capacity = capacity;
+        log.file = file;
+        while log.lines.len() > capacity {
+            log.lines.pop_front();
+        }
+        self.inner.any_watched.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Stop watching the given agent, discarding its captured lines.
+    pub fn unwatch(&self, id: u64) {
+        let mut watched = self.inner.watched.lock().unwrap();
+        watched.remove(&id);
+        self.inner
+            .any_watched
+            .store(!watched.is_empty(), Ordering::Relaxed);
+    }
+
+    pub fn is_watched(&self, id: u64) -> bool {
+        self.inner.any_watched.load(Ordering::Relaxed)
+            && self.inner.watched.lock().unwrap().contains_key(&id)
+    }
+
+    /// The captured log lines of the given agent, oldest first. Empty if the agent isn't watched.
+    pub fn lines(&self, id: u64) -> Vec<String> {
+        match self.inner.watched.lock().unwrap().get(&id) {
+            Some(log) => log.lines.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Inner {
+    fn log<F, S>(&self, id: u64, msg: F)
+    where
+        F: Fn() -> S,
+        S: Into<String>,
+    {
+        let traced = self.traced == Some(id);
+        if !traced && !self.any_watched.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut watched = self.watched.lock().unwrap();
+        let log = watched.get_mut(&id);
+        if !traced && log.is_none() {
+            return;
+        }
+
+        let msg = msg().into();
+        if traced {
+            println!("Log for agent {}: {}", id, msg);
+        }
+        if let Some(log) = log {
+            log.push(id, msg);
+        }
+    }
+}
+
+/**
+ * Log a message for a particular agent to the current logs (see AgentLogs::enter). This does
+ * nothing unless the agent is being watched or traced; in particular, the message is only formatted
+ * if it will be kept, so this is cheap to call on hot paths.
+ */
+pub fn agent_log<F, S>(id: u64, msg: F)
+where
+    F: Fn() -> S,
+    S: Into<String>,
+{
+    CURRENT.with(|current| {
+        if let Some(inner) = &*current.borrow() {
+            inner.log(id, msg);
+        }
+    });
+}
+
+pub fn agent_log_timestamp<F, S>(id: u64, msg: F, timestamp: u64)
+where
+    F: Fn() -> S,
+    S: Into<String>,
+{
+    agent_log(id, || format!("{}: {}", timestamp, msg().into()));
+}
+
+#[cfg(test)]
+mod agent_log_tests {
+    use crate::agent_log::*;
+
+    #[test]
+    fn unwatched_is_free() {
+        let calls = std::cell::Cell::new(0);
+        let msg = || {
+            calls.set(calls.get() + 1);
+            "message"
+        };
+
+        let logs = AgentLogs::new(None);
+        let _guard = logs.enter();
+        agent_log(1, msg);
+        agent_log_timestamp(1, msg, 5);
+
+        // watching a different agent doesn't make this one's messages get formatted
+        logs.watch(2, AGENT_LOG_CAPACITY, None).unwrap();
+        agent_log(1, msg);
+        agent_log(2, msg);
+        logs.unwatch(2);
+        agent_log(2, msg);
+
+        assert_eq!(calls.get(), 1);
+        assert!(logs.lines(1).is_empty());
+        assert!(logs.lines(2).is_empty());
+    }
+
+    #[test]
+    fn watched_in_order() {
+        let logs = AgentLogs::new(None);
+        let _guard = logs.enter();
+        logs.watch(1, AGENT_LOG_CAPACITY, None).unwrap();
+        assert!(logs.is_watched(1));
+        agent_log(1, || "first");
+        agent_log_timestamp(1, || "second", 30);
+        assert_eq!(logs.lines(1), vec!["first", "30: second"]);
+
+        logs.unwatch(1);
+        assert!(!logs.is_watched(1));
+        assert!(logs.lines(1).is_empty());
+    }
+
+    #[test]
+    fn ring_truncates() {
+        let logs = AgentLogs::new(None);
+        let _guard = logs.enter();
+        logs.watch(1, 3, None).unwrap();
+        for i in 0..5 {
+            agent_log(1, || format!("line {}", i));
+        }
+        assert_eq!(logs.lines(1), vec!["line 2", "line 3", "line 4"]);
+
+        // shrinking the ring drops the oldest lines
+        logs.watch(1, 1, None).unwrap();
+        assert_eq!(logs.lines(1), vec!["line 4"]);
+    }
+
+    #[test]
+    fn file_sink() {
+        let path = std::env::temp_dir().join(format!("agent_log_{}.txt", std::process::id()));
+        let logs = AgentLogs::new(None);
+        let _guard = logs.enter();
+        logs.watch(1, 1, Some(&path)).unwrap();
+        agent_log(1, || "first");
+        agent_log(1, || "second");
+        logs.unwatch(1);
+
+        // the file keeps everything, not just what fits in the ring
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "first\nsecond\n");
+    }
+
+    #[test]
+    fn separate_logs() {
+        let first = AgentLogs::new(None);
+        let second = AgentLogs::new(None);
+        first.watch(1, AGENT_LOG_CAPACITY, None).unwrap();
+        second.watch(1, AGENT_LOG_CAPACITY, None).unwrap();
+
+        // nothing is current, so this goes nowhere
+        agent_log(1, || "dropped");
+        {
+            let _guard = first.enter();
+            agent_log(1, || "first");
+            {
+                let _guard = second.enter();
+                agent_log(1, || "second");
+            }
+            agent_log(1, || "first again");
+        }
+        assert_eq!(first.lines(1), vec!["first", "first again"]);
+        assert_eq!(second.lines(1), vec!["second"]);
+
+        // sinks write to the logs they were made from, on whichever thread they are entered
+        let sink = first.sink();
+        std::thread::spawn(move || {
+            let _guard = sink.enter();
+            agent_log(1, || "from another thread");
+        })
+        .join()
+        .unwrap();
+        assert_eq!(
+            first.lines(1),
+            vec!["first", "first again", "from another thread"]
+        );
+
+        // clones start out watching nothing
+        let clone = first.clone();
+        assert!(!clone.is_watched(1));
+        first.unwatch(1);
+        assert!(second.is_watched(1));
+    }
+}
@@ -3,7 +3,8 @@ use serde::{Deserialize, Serialize};
 use uom::si::time::minute;
 use uom::si::u64::Time;
 
-use crate::common::{agent_log, Error};
+use crate::agent_log::agent_log;
+use crate::common::Error;
 
 #[derive(
     Debug,
@@ -21,8 +22,27 @@ use crate::common::{agent_log, Error};
 pub enum RouteType {
     CommuteToWork,
     CommuteFromWork,
+    /// an evening trip from home to a park
+    ToLeisure,
+    /// heading back home from a park
+    FromLeisure,
 }
 
+/**
+ * How finely an agent's progress along a route is simulated. Coarse routes skip advancing edge by
+ * edge, which costs far fewer triggers when the simulation is running too fast to watch anyway.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RouteDetail {
+    /// advance one edge at a time, waiting behind jammed edges
+    Fine,
+    /// advance a few chunks of edges at a time; see AgentRouteState::begin_chunk
+    Coarse,
+}
+
+/// the number of chunks that coarse routes are split into
+const COARSE_ROUTE_CHUNKS: usize = 3;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AgentRoutePhase {
     InProgress {
@@ -35,6 +55,11 @@ pub enum AgentRoutePhase {
         current_edge_total: f32,
         /// the current mode of transport
         current_mode: route::Mode,
+        /// for coarse routes, the index just past the last edge of the current chunk; the agent
+        /// is on every edge from current_edge up to it at once, and current_edge_start and
+        /// current_edge_total cover the whole chunk
+        #[serde(default)]
+        chunk_end: Option<u32>,
     },
     Finished {
         /// total time taken for the agent to finish the route; subsecond precision
@@ -42,6 +67,14 @@ pub enum AgentRoutePhase {
     },
 }
 
+/// A stretch of a route spent in a single mode of transport.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TravelLeg {
+    pub mode: route::Mode,
+    /// subsecond precision
+    pub duration: f32,
+}
+
 // TODO: The duplication of id and parked_car here is kind of bad. These associated functions should
 // really be moved to Agent.
 #[derive(Clone, Serialize, Deserialize)]
@@ -54,6 +87,10 @@ pub struct AgentRouteState {
     pub route_type: RouteType,
     pub phase: AgentRoutePhase,
     pub parked_car: Option<quadtree::Address>,
+    /// time spent in each mode on the edges that are already done, or for coarse routes, on the
+    /// edges up to the end of the current chunk; only recorded when the travel diary is enabled
+    #[serde(default)]
+    pub legs: Vec<TravelLeg>,
 }
 
 // Route output is really unwieldy by default. This cleans it up a bit.
@@ -97,6 +134,7 @@ impl AgentRouteState {
         route: route::Route,
         start_time: u64,
         route_type: RouteType,
+        detail: RouteDetail,
         world_state: &mut route::WorldStateImpl,
         state: &state::State<F>,
         parked_car: Option<quadtree::Address>,
@@ -105,32 +143,102 @@ impl AgentRouteState {
         let mut ret = Self {
             id,
             start_time,
-            phase: match route.edges.first() {
-                Some(first) => {
-                    world_state.increment_edge(first)?;
-
-                    AgentRoutePhase::InProgress {
-                        current_edge: 0,
-                        current_edge_start: 0.0,
-                        current_edge_total: first.cost(world_state, state, Some(start_time)) as f32,
-                        current_mode: first.mode_transition().unwrap_or(route.start_mode),
-                    }
-                }
-                None => AgentRoutePhase::Finished { total_time: 0.0 },
-            },
+            phase: AgentRoutePhase::Finished { total_time: 0.0 },
             route_type,
             route,
             parked_car,
+            legs: Vec::new(),
         };
 
-        // maybe adjust parked car
-        if let Some(first) = ret.route.edges.first() {
-            Self::handle_parking(id, &mut ret.parked_car, first)?;
-        }
+        ret.phase = match (ret.route.edges.first(), detail) {
+            (Some(first), RouteDetail::Fine) => {
+                Self::enter_edge(id, &mut ret.parked_car, world_state, first)?;
+
+                AgentRoutePhase::InProgress {
+                    current_edge: 0,
+                    current_edge_start: 0.0,
+                    current_edge_total: first.profile_cost(
+                        first.cost(world_state, state, Some(start_time)),
+                        &ret.route.query_input.profile,
+                    ) as f32,
+                    current_mode: first.mode_transition().unwrap_or(ret.route.start_mode),
+                    chunk_end: None,
+                }
+            }
+            (Some(_), RouteDetail::Coarse) => {
+                let start_mode = ret.route.start_mode;
+                ret.begin_chunk(0, 0.0, start_mode, world_state, state)?
+            }
+            (None, _) => AgentRoutePhase::Finished { total_time: 0.0 },
+        };
 
         Ok(ret)
     }
 
+    /**
+     * Whether the agent is circling around looking for parking while on this edge. The car is only
+     * parked, and counted in the world state, once the search is over.
+     */
+    fn is_parking_search(edge: &route::Edge) -> bool {
+        matches!(
+            edge,
+            route::Edge::ModeTransition {
+                from: route::Mode::Driving,
+                to: route::Mode::Walking,
+                ..
+            }
+        )
+    }
+
+    fn enter_edge(
+        id: u64,
+        parked_car: &mut Option<quadtree::Address>,
+        world_state: &mut route::WorldStateImpl,
+        edge: &route::Edge,
+    ) -> Result<(), Error> {
+        match (edge, *parked_car) {
+            _ if Self::is_parking_search(edge) => {
+                agent_log(id, || "looking for parking");
+                return Ok(());
+            }
+            (
+                route::Edge::ModeTransition {
+                    from: route::Mode::Walking,
+                    to: route::Mode::Driving,
+                    address,
+                },
+                Some(car),
+            ) if car != *address => {
+                // the map is dynamic, so the car may have been moved since the route was planned;
+                // take it from where it actually is so that the world state stays consistent
+                agent_log(id, || {
+                    format!("car is at {:?} instead of {:?}", car, address)
+                });
+                world_state.decrement_parking(car)?;
+            }
+            _ => world_state.increment_edge(edge)?,
+        }
+        // maybe adjust parked car
+        Self::handle_parking(id, parked_car, edge)?;
+        Ok(())
+    }
+
+    fn leave_edge(
+        id: u64,
+        parked_car: &mut Option<quadtree::Address>,
+        world_state: &mut route::WorldStateImpl,
+        edge: &route::Edge,
+    ) -> Result<(), Error> {
+        if Self::is_parking_search(edge) {
+            // found a space, so park the car
+            world_state.increment_edge(edge)?;
+            Self::handle_parking(id, parked_car, edge)?;
+        } else {
+            world_state.decrement_edge(edge)?;
+        }
+        Ok(())
+    }
+
     fn handle_parking(
         id: u64,
         parked_car: &mut Option<quadtree::Address>,
@@ -145,7 +253,8 @@ impl AgentRouteState {
             } => {
                 agent_log(id, || format!("un-parking at {:?}", address));
 
-                // NOTE: we might not have parked_car == Some(address) because the map is dynamic
+                // NOTE: we might not have parked_car == Some(address) because the map is dynamic;
+                // enter_edge takes the car from wherever it actually is
                 assert!(parked_car.is_some());
                 *parked_car = None;
             }
@@ -165,9 +274,75 @@ impl AgentRouteState {
         Ok(())
     }
 
+    /// The index just past the last edge of the coarse chunk that starts at the given edge.
+    fn chunk_end(&self, first_edge: usize) -> usize {
+        let edges = &self.route.edges;
+        let chunk_len = (edges.len() + COARSE_ROUTE_CHUNKS - 1) / COARSE_ROUTE_CHUNKS;
+        let end = (first_edge + chunk_len).min(edges.len());
+        // end the chunk as soon as the car is parked, so that it is never un-parked before then
+        edges[first_edge..end]
+            .iter()
+            .position(Self::is_parking_search)
+            .map_or(end, |index| first_edge + index + 1)
+    }
+
+    /**
+     * Enter every edge in the coarse chunk starting at the given edge, and return the phase that
+     * covers the whole chunk. Each edge is costed for the time that the agent would reach it, but
+     * with the traffic as of the start of the chunk, and jammed edges are not waited out. So coarse
+     * routes only put congestion in roughly the right places at roughly the right times, and take
+     * roughly as long as fine routes; once a chunk is left, though, the world state is the same as
+     * if the agent had gone edge by edge.
+     */
+    fn begin_chunk<F: state::Fields>(
+        &mut self,
+        first_edge: u32,
+        chunk_start: f32,
+        mut current_mode: route::Mode,
+        world_state: &mut route::WorldStateImpl,
+        state: &state::State<F>,
+    ) -> Result<AgentRoutePhase, Error> {
+        let chunk_end = self.chunk_end(first_edge as usize);
+        let edges = &self.route.edges[first_edge as usize..chunk_end];
+
+        for edge in edges {
+            Self::enter_edge(self.id, &mut self.parked_car, world_state, edge)?;
+        }
+
+        let record_legs = state.config.travel_diary.enabled;
+        let mut chunk_total = 0.0;
+        for edge in edges {
+            let arrival = self.start_time + (chunk_start + chunk_total).floor() as u64;
+            let cost = edge.profile_cost(
+                edge.cost(world_state, state, Some(arrival)),
+                &self.route.query_input.profile,
+            ) as f32;
+            assert!(cost >= 0.0);
+
+            if record_legs {
+                Self::push_leg(&mut self.legs, Self::edge_mode(edge, current_mode), cost);
+            }
+            chunk_total += cost;
+            current_mode = edge.mode_transition().unwrap_or(current_mode);
+        }
+
+        agent_log(self.id, || {
+            format!("coarse chunk of edges {}..{}", first_edge, chunk_end)
+        });
+
+        Ok(AgentRoutePhase::InProgress {
+            current_edge: first_edge,
+            current_edge_start: chunk_start,
+            current_edge_total: chunk_total,
+            current_mode,
+            chunk_end: Some(chunk_end as u32),
+        })
+    }
+
     /**
      * Advance the agent to the next edge in the route. This should only be done each time the
-     * simulation time has passed the value of next_trigger.
+     * simulation time has passed the value of next_trigger. Coarse routes advance to the next
+     * chunk instead.
      */
     pub fn advance<F: state::Fields>(
         &mut self,
@@ -180,12 +355,38 @@ impl AgentRouteState {
                 current_edge_start,
                 current_edge_total,
                 current_mode,
+                chunk_end: Some(chunk_end),
+            } => {
+                for edge in &self.route.edges[current_edge as usize..chunk_end as usize] {
+                    Self::leave_edge(self.id, &mut self.parked_car, world_state, edge)?;
+                }
+
+                let chunk_start = current_edge_start + current_edge_total;
+                self.phase = if chunk_end as usize == self.route.edges.len() {
+                    AgentRoutePhase::Finished {
+                        total_time: chunk_start,
+                    }
+                } else {
+                    self.begin_chunk(chunk_end, chunk_start, current_mode, world_state, state)?
+                };
+            }
+            AgentRoutePhase::InProgress {
+                current_edge,
+                current_edge_start,
+                current_edge_total,
+                current_mode,
+                chunk_end: None,
             } => {
                 let old_edge = &self.route.edges[current_edge as usize];
+                let record_legs = state.config.travel_diary.enabled;
+                let old_mode = Self::edge_mode(old_edge, current_mode);
 
                 let new_edge_index = current_edge + 1;
                 self.phase = if new_edge_index as usize == self.route.edges.len() {
-                    world_state.decrement_edge(old_edge)?;
+                    Self::leave_edge(self.id, &mut self.parked_car, world_state, old_edge)?;
+                    if record_legs {
+                        Self::push_leg(&mut self.legs, old_mode, current_edge_total);
+                    }
 
                     AgentRoutePhase::Finished {
                         total_time: current_edge_start + current_edge_total,
@@ -208,19 +409,21 @@ impl AgentRouteState {
                             current_edge_start,
                             current_edge_total: current_edge_total + wait,
                             current_mode,
+                            chunk_end: None,
                         }
                     } else {
-                        world_state.decrement_edge(old_edge)?;
-                        world_state.increment_edge(new_edge)?;
-
-                        // maybe adjust parked car
-                        Self::handle_parking(self.id, &mut self.parked_car, new_edge)?;
+                        Self::leave_edge(self.id, &mut self.parked_car, world_state, old_edge)?;
+                        Self::enter_edge(self.id, &mut self.parked_car, world_state, new_edge)?;
+                        if record_legs {
+                            Self::push_leg(&mut self.legs, old_mode, current_edge_total);
+                        }
 
                         let start_time = current_edge_start + current_edge_total;
 
-                        let cost =
-                            new_edge.cost(world_state, state, Some(start_time.floor() as u64))
-                                as f32;
+                        let cost = new_edge.profile_cost(
+                            new_edge.cost(world_state, state, Some(start_time.floor() as u64)),
+                            &self.route.query_input.profile,
+                        ) as f32;
                         assert!(cost >= 0.0);
 
                         AgentRoutePhase::InProgress {
@@ -228,6 +431,7 @@ impl AgentRouteState {
                             current_edge_start: start_time,
                             current_edge_total: cost,
                             current_mode: new_edge.mode_transition().unwrap_or(current_mode),
+                            chunk_end: None,
                         }
                     }
                 };
@@ -238,6 +442,74 @@ impl AgentRouteState {
         Ok(())
     }
 
+    /// The mode that the agent is in while on the edge. Changing modes counts as the old mode.
+    fn edge_mode(edge: &route::Edge, current_mode: route::Mode) -> route::Mode {
+        match edge {
+            route::Edge::ModeTransition { from, .. } => *from,
+            _ => current_mode,
+        }
+    }
+
+    fn push_leg(legs: &mut Vec<TravelLeg>, mode: route::Mode, duration: f32) {
+        match legs.last_mut() {
+            Some(last) if last.mode == mode => last.duration += duration,
+            _ => legs.push(TravelLeg { mode, duration }),
+        }
+    }
+
+    /**
+     * The time spent in each mode, including the current edge if the route is still in progress.
+     * Finished edges are only recorded while the travel diary is enabled.
+     */
+    pub fn travel_legs(&self) -> Vec<TravelLeg> {
+        let mut legs = self.legs.clone();
+        if let AgentRoutePhase::InProgress {
+            current_edge,
+            current_edge_total,
+            current_mode,
+            chunk_end: None,
+            ..
+        } = self.phase
+        {
+            let edge = &self.route.edges[current_edge as usize];
+            Self::push_leg(
+                &mut legs,
+                Self::edge_mode(edge, current_mode),
+                current_edge_total,
+            );
+        }
+        legs
+    }
+
+    /**
+     * The edges that the agent is counted on in the world state: the current edge, or for coarse
+     * routes, every edge in the current chunk. Returns None if the phase points past the end of the
+     * route, which should never happen.
+     */
+    pub fn current_edges(&self) -> Option<&[route::Edge]> {
+        match self.phase {
+            AgentRoutePhase::InProgress {
+                current_edge,
+                chunk_end,
+                ..
+            } => self
+                .route
+                .edges
+                .get(current_edge as usize..chunk_end.unwrap_or(current_edge + 1) as usize),
+            AgentRoutePhase::Finished { .. } => Some(&[]),
+        }
+    }
+
+    /// The edges that the agent has yet to finish, including the ones that it is on now.
+    pub fn remaining_edges(&self) -> &[route::Edge] {
+        match self.phase {
+            AgentRoutePhase::InProgress { current_edge, .. } => {
+                &self.route.edges[current_edge as usize..]
+            }
+            AgentRoutePhase::Finished { .. } => &[],
+        }
+    }
+
     /**
      * If not finished, returns the next simulation time at which advance should be called.
      * If finished, returns None.
@@ -259,6 +531,67 @@ impl AgentRouteState {
         }
     }
 
+    /**
+     * The simulation time by which the agent should have moved on: the end of the current edge, or
+     * the end of the route if it is finished. An agent that is still on the route long after this
+     * has lost the trigger that advances it.
+     */
+    pub fn expected_progress_time(&self) -> u64 {
+        match self.phase {
+            AgentRoutePhase::InProgress { .. } => self.next_trigger().unwrap(),
+            AgentRoutePhase::Finished { total_time } => self.start_time + total_time.ceil() as u64,
+        }
+    }
+
+    /**
+     * Whether the agent is currently looking for parking at the end of a drive. Coarse routes are
+     * looking for parking for their whole chunk if it ends in a search.
+     */
+    pub fn is_looking_for_parking(&self) -> bool {
+        self.current_edges()
+            .and_then(|edges| edges.last())
+            .map_or(false, Self::is_parking_search)
+    }
+
+    /**
+     * Whether the agent has taken a car out for this route and hasn't parked it yet, including
+     * while looking for parking.
+     */
+    pub fn is_using_car(&self) -> bool {
+        let entered = match self.phase {
+            _ if self.parked_car.is_some() => return false,
+            AgentRoutePhase::InProgress {
+                current_edge,
+                chunk_end,
+                ..
+            } => chunk_end.unwrap_or(current_edge + 1) as usize,
+            AgentRoutePhase::Finished { .. } => return false,
+        };
+        self.route.edges[..entered].iter().any(|edge| {
+            matches!(
+                edge,
+                route::Edge::ModeTransition {
+                    from: route::Mode::Walking,
+                    to: route::Mode::Driving,
+                    ..
+                }
+            )
+        })
+    }
+
+    /// A short description of what the agent is doing, e.g. for showing in a UI.
+    pub fn phase_description(&self) -> String {
+        match self.phase {
+            AgentRoutePhase::InProgress { .. } if self.is_looking_for_parking() => {
+                "looking for parking".to_string()
+            }
+            AgentRoutePhase::InProgress { current_mode, .. } => {
+                format!("traveling ({:?})", current_mode)
+            }
+            AgentRoutePhase::Finished { .. } => "finished".to_string(),
+        }
+    }
+
     /**
      * Whether the agent has finished its route.
      */
@@ -287,20 +620,29 @@ impl AgentRouteState {
                 current_edge_start,
                 current_edge_total,
                 current_mode,
+                chunk_end,
             } => {
                 let relative_time =
                     current_time as f32 - current_edge_start - self.start_time as f32;
+                let fraction = relative_time / current_edge_total;
 
-                let edge = &self.route.edges[current_edge as usize];
-                let pred = &self.route.nodes[current_edge as usize];
-                let succ = &self.route.nodes[(current_edge + 1) as usize];
+                let position = match chunk_end {
+                    // coarse routes don't know where the agent is within the chunk, so just go
+                    // straight from one end to the other
+                    Some(chunk_end) => {
+                        let (x1, y1) = self.route.nodes[current_edge as usize].location_f32();
+                        let (x2, y2) = self.route.nodes[chunk_end as usize].location_f32();
+                        let fraction = fraction.clamp(0.0, 1.0);
+                        (x1 + (x2 - x1) * fraction, y1 + (y2 - y1) * fraction)
+                    }
+                    None => {
+                        let edge = &self.route.edges[current_edge as usize];
+                        let pred = &self.route.nodes[current_edge as usize];
+                        let succ = &self.route.nodes[(current_edge + 1) as usize];
 
-                let position = edge.interpolate_position(
-                    state,
-                    pred,
-                    succ,
-                    relative_time / current_edge_total,
-                );
+                        edge.interpolate_position(state, pred, succ, fraction)
+                    }
+                };
 
                 Some(route::RouteKey {
                     position,
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::Error;
+
+/**
+ * A group of agents that live together and share a pool of cars. Members check out a car when they
+ * leave home and check it back in when they get home again, so a household with two cars can have
+ * at most two members driving at once; everyone else has to find another way to get around.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Household {
+    pub housing: quadtree::Address,
+    pub members: Vec<u64>,
+    /// total number of cars owned by the household
+    cars: u32,
+    /// number of cars currently held by members
+    checked_out: u32,
+}
+
+impl Household {
+    pub fn new(housing: quadtree::Address, cars: u32) -> Self {
+        Self {
+            housing,
+            members: Vec::new(),
+            cars,
+            checked_out: 0,
+        }
+    }
+
+    pub fn cars(&self) -> u32 {
+        self.cars
+    }
+
+    pub fn available_cars(&self) -> u32 {
+        self.cars - self.checked_out
+    }
+
+    pub fn add_car(&mut self) {
+        self.cars += 1;
+    }
+
+    /// Returns false if every car is already checked out.
+    pub fn check_out_car(&mut self) -> bool {
+        if self.checked_out < self.cars {
+            self.checked_out += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn check_in_car(&mut self) -> Result<(), Error> {
+        if self.checked_out == 0 {
+            return Err(Error::NoCarCheckedOut);
+        }
+        self.checked_out -= 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod household_tests {
+    use crate::household::*;
+
+    #[test]
+    fn check_out_until_empty() {
+        let mut household = Household::new(quadtree::Address::from_xy(0, 0, 1), 2);
+        assert!(household.check_out_car());
+        assert!(household.check_out_car());
+        assert!(!household.check_out_car());
+        assert_eq!(household.available_cars(), 0);
+
+        household.check_in_car().unwrap();
+        assert_eq!(household.available_cars(), 1);
+        household.check_in_car().unwrap();
+        assert!(household.check_in_car().is_err());
+        assert_eq!(household.available_cars(), 2);
+    }
+}
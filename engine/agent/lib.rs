@@ -1,9 +1,22 @@
 mod agent;
 mod agent_data;
+mod agent_log;
 mod agent_route_state;
 mod common;
+mod household;
+mod workplace_happiness;
 
 pub use crate::agent::{Agent, AgentState};
-pub use crate::agent_data::{AgentData, EducationDegree};
-pub use crate::agent_route_state::{AgentRoutePhase, AgentRouteState, RouteType};
-pub use crate::common::{agent_log, agent_log_timestamp, Error};
+pub use crate::agent_data::{AgeBucket, AgentData, BehaviorNoise, EducationDegree};
+pub use crate::agent_log::{
+    agent_log, agent_log_timestamp, AgentLogSink, AgentLogs, AgentLogsGuard, AGENT_LOG_CAPACITY,
+};
+pub use crate::agent_route_state::{
+    AgentRoutePhase, AgentRouteState, RouteDetail, RouteType, TravelLeg,
+};
+pub use crate::common::Error;
+pub use crate::household::Household;
+pub use crate::workplace_happiness::{
+    education_fit, typical_years_of_education, HappinessComponent, HappinessComponentScore,
+    WorkplaceHappiness,
+};
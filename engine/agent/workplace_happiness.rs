@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+
+/// The parts that an agent's workplace happiness is made up of. See state::WorkplaceHappinessConfig.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    enum_iterator::IntoEnumIterator,
+)]
+pub enum HappinessComponent {
+    Commute,
+    Wage,
+    EducationFit,
+    Tenure,
+}
+
+impl HappinessComponent {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Commute => "Commute",
+            Self::Wage => "Wage",
+            Self::EducationFit => "Education fit",
+            Self::Tenure => "Tenure",
+        }
+    }
+
+    pub fn weight(&self, config: &state::WorkplaceHappinessConfig) -> f32 {
+        match self {
+            Self::Commute => config.commute,
+            Self::Wage => config.wage,
+            Self::EducationFit => config.education_fit,
+            Self::Tenure => config.tenure,
+        }
+    }
+}
+
+impl std::fmt::Display for HappinessComponent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+/// One part of an agent's workplace happiness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HappinessComponentScore {
+    pub component: HappinessComponent,
+    /// in [0, 1], where 1 is as happy as this part can make the agent
+    pub value: f32,
+    /// the weight from the config, normalized so that the weights of all of the parts that apply
+    /// to the agent add up to one
+    pub weight: f32,
+}
+
+impl HappinessComponentScore {
+    /// How much this part lowers the overall score below one.
+    pub fn shortfall(&self) -> f32 {
+        self.weight * (1.0 - self.value)
+    }
+}
+
+/**
+ * How happy an agent is with their workplace, along with the parts that went into it, so that it's
+ * possible to tell why agents are unhappy.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkplaceHappiness {
+    /// 0.0 means the agent wants to quit immediately and 1.0 means they definitely don't want to
+    /// leave
+    pub score: f32,
+    /// the parts that apply to the agent, in the order of HappinessComponent
+    pub components: Vec<HappinessComponentScore>,
+}
+
+impl WorkplaceHappiness {
+    /**
+     * The weighted average of the given parts. Parts that don't apply (None) or have no weight are
+     * left out, and if nothing is left, the agent is perfectly happy.
+     */
+    pub fn combine(
+        values: &[(HappinessComponent, Option<f32>)],
+        config: &state::WorkplaceHappinessConfig,
+    ) -> Self {
+        let present: Vec<(HappinessComponent, f32, f32)> = values
+            .iter()
+            .filter_map(|(component, value)| {
+                let weight = component.weight(config);
+                value
+                    .filter(|_| weight > 0.0)
+                    .map(|value| (*component, value.clamp(0.0, 1.0), weight))
+            })
+            .collect();
+        let total_weight: f32 = present.iter().map(|(_, _, weight)| weight).sum();
+        if total_weight <= 0.0 {
+            return Self {
+                score: 1.0,
+                components: vec![],
+            };
+        }
+
+        let components: Vec<HappinessComponentScore> = present
+            .into_iter()
+            .map(|(component, value, weight)| HappinessComponentScore {
+                component,
+                value,
+                weight: weight / total_weight,
+            })
+            .collect();
+        let score = components
+            .iter()
+            .map(|component| component.weight * component.value)
+            .sum::<f32>()
+            .clamp(0.0, 1.0);
+        Self { score, components }
+    }
+
+    /// The part that lowers the score the most, if any part lowers it at all.
+    pub fn main_shortfall(&self) -> Option<&HappinessComponentScore> {
+        self.components
+            .iter()
+            .filter(|component| component.shortfall() > 0.0)
+            .max_by(|a, b| a.shortfall().partial_cmp(&b.shortfall()).unwrap())
+    }
+}
+
+/// The typical years of education for a job in the given industry.
+pub fn typical_years_of_education(industry: tiles::Industry) -> u32 {
+    use tiles::Industry::*;
+    match industry {
+        Office => 16,
+        Retail => 12,
+        Industrial => 12,
+        Education => 17,
+        Healthcare => 16,
+    }
+}
+
+/// how many years of education away from the typical amount an agent can be before the job is a
+/// complete mismatch, whether they are under- or overqualified
+const EDUCATION_FIT_RANGE: f32 = 8.0;
+
+/// How well a job in the given industry matches the given years of education, in [0, 1].
+pub fn education_fit(years_of_education: u32, industry: tiles::Industry) -> f32 {
+    let difference = years_of_education.abs_diff(typical_years_of_education(industry)) as f32;
+    1.0 - (difference / EDUCATION_FIT_RANGE).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::workplace_happiness::*;
+
+    fn config() -> state::WorkplaceHappinessConfig {
+        state::WorkplaceHappinessConfig {
+            commute: 2.0,
+            wage: 1.0,
+            education_fit: 1.0,
+            tenure: 0.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn weighting() {
+        use HappinessComponent::*;
+
+        let happiness = WorkplaceHappiness::combine(
+            &[
+                (Commute, Some(0.5)),
+                (Wage, Some(1.0)),
+                (EducationFit, Some(0.0)),
+                (Tenure, Some(1.0)),
+            ],
+            &config(),
+        );
+        // tenure has no weight, so it is left out
+        assert_eq!(
+            happiness
+                .components
+                .iter()
+                .map(|component| (component.component, component.weight))
+                .collect::<Vec<_>>(),
+            vec![(Commute, 0.5), (Wage, 0.25), (EducationFit, 0.25)]
+        );
+        assert!((happiness.score - (0.5 * 0.5 + 0.25)).abs() < 1e-6);
+        assert_eq!(happiness.main_shortfall().unwrap().component, EducationFit);
+
+        // without wages, the other weights make up the difference
+        let happiness = WorkplaceHappiness::combine(
+            &[
+                (Commute, Some(0.5)),
+                (Wage, None),
+                (EducationFit, Some(0.0)),
+            ],
+            &config(),
+        );
+        assert!((happiness.score - 2.0 / 3.0 * 0.5).abs() < 1e-6);
+        assert_eq!(happiness.main_shortfall().unwrap().component, EducationFit);
+
+        // nothing to be unhappy about
+        let happiness = WorkplaceHappiness::combine(&[(Tenure, Some(0.0))], &config());
+        assert_eq!(happiness.score, 1.0);
+        assert_eq!(happiness.main_shortfall(), None);
+    }
+
+    #[test]
+    fn education() {
+        assert_eq!(education_fit(12, tiles::Industry::Retail), 1.0);
+        assert_eq!(education_fit(16, tiles::Industry::Retail), 0.5);
+        assert_eq!(education_fit(8, tiles::Industry::Office), 0.0);
+        assert_eq!(education_fit(30, tiles::Industry::Office), 0.0);
+    }
+}
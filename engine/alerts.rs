@@ -0,0 +1,474 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use uom::si::time::hour;
+use uom::si::u64::Time;
+
+use crate::engine::Error;
+
+/// the most alerts that are kept around; older alerts are dropped first
+const MAX_ALERTS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Severity {
+    Warning,
+    /// serious enough that the simulation can be paused automatically; see Alerts::auto_pause
+    Critical,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/** The condition that a watcher checks for. Each watcher raises at most one alert at a time. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Watcher {
+    /// too many parking or edge counting errors in the last hour
+    ErrorRate,
+    /// too many agents failed to find a route in the last hour
+    RouteFailureRate,
+    /// every route query in the last hour failed
+    AllRoutesFailing,
+    /// the population dropped too much over the last day
+    PopulationDrop,
+    /// the trigger queue grew at every evaluation for a long time, e.g. a trigger that schedules
+    /// more than one copy of itself
+    TriggerQueueGrowth,
+    /// too many agents had to wait for their route query in the last hour; see RouteLatency
+    RouteQueryOverload,
+    /// a segment was closed or reopened; these are raised as they happen rather than by the
+    /// periodic evaluation, so there can be more than one at a time
+    SegmentClosure,
+}
+
+impl Watcher {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::ErrorRate
+            | Self::RouteFailureRate
+            | Self::TriggerQueueGrowth
+            | Self::RouteQueryOverload
+            | Self::SegmentClosure => Severity::Warning,
+            Self::AllRoutesFailing | Self::PopulationDrop => Severity::Critical,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    /// increases by one with each alert, so that new alerts can be told apart from old ones
+    pub id: u64,
+    pub watcher: Watcher,
+    pub severity: Severity,
+    pub message: String,
+    /// the simulation time when the alert was raised
+    pub time: u64,
+    /// whether the user has dismissed the alert
+    pub acknowledged: bool,
+}
+
+/** When each of the watchers fires. */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertThresholds {
+    /// parking and edge counting errors per hour
+    pub errors_per_hour: u64,
+    /// agents that failed to find a route per hour
+    pub route_failures_per_hour: u64,
+    /// the number of route queries in an hour before AllRoutesFailing is checked, so that a single
+    /// unlucky agent in the middle of the night doesn't pause the simulation
+    pub min_route_queries: u64,
+    /// the fraction of the population that can be lost over a day
+    pub population_drop: f64,
+    /// how many consecutive evaluations the trigger queue can grow for
+    pub trigger_queue_growth: usize,
+    /// route starts per hour that had to wait for their route query
+    pub route_deadline_misses_per_hour: u64,
+}
+
+impl Default for AlertThresholds {
+    fn default() -> Self {
+        Self {
+            errors_per_hour: 10,
+            route_failures_per_hour: 100,
+            min_route_queries: 10,
+            population_drop: 0.2,
+            trigger_queue_growth: 24,
+            route_deadline_misses_per_hour: 100,
+        }
+    }
+}
+
+/** Counts of events since the watchers were last evaluated. */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventCounts {
+    pub parking_errors: u64,
+    pub edge_counting_errors: u64,
+    pub routes_found: u64,
+    pub route_failures: u64,
+    /// route starts that had to wait for their route query
+    #[serde(default)]
+    pub route_deadline_misses: u64,
+    /// how long those route starts waited in total, in wall-clock milliseconds
+    #[serde(default)]
+    pub route_blocked_ms: u64,
+}
+
+/**
+ * Watches for signs that the simulation has gone off the rails, such as bursts of errors that
+ * would otherwise only be logged, or the population collapsing. The watchers are evaluated
+ * periodically by the EvaluateAlerts trigger, and each one raises an alert when its condition
+ * starts holding.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Alerts {
+    pub thresholds: AlertThresholds,
+    /// pause the simulation when a critical alert is raised
+    pub auto_pause: bool,
+    alerts: VecDeque<Alert>,
+    next_id: u64,
+    /// events since the last evaluation
+    counts: EventCounts,
+    /// events since the start of the simulation
+    totals: EventCounts,
+    /// watchers whose condition held at the last evaluation
+    active: BTreeSet<Watcher>,
+    /// (time, population) at each evaluation over the last day
+    population: VecDeque<(u64, usize)>,
+    /// trigger queue length at the most recent evaluations
+    trigger_queue: VecDeque<usize>,
+}
+
+impl Alerts {
+    /// How often the watchers are evaluated, in seconds.
+    pub fn evaluation_period() -> u64 {
+        Time::new::<hour>(1).value
+    }
+
+    /// All alerts that are still kept around, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Alert> {
+        self.alerts.iter()
+    }
+
+    pub fn unacknowledged(&self) -> impl Iterator<Item = &Alert> {
+        self.alerts.iter().filter(|alert| !alert.acknowledged)
+    }
+
+    pub fn acknowledge_all(&mut self) {
+        for alert in &mut self.alerts {
+            alert.acknowledged = true;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.alerts.clear();
+    }
+
+    pub fn totals(&self) -> &EventCounts {
+        &self.totals
+    }
+
+    fn count<F: Fn(&mut EventCounts) -> &mut u64>(&mut self, f: F) {
+        *f(&mut self.counts) += 1;
+        *f(&mut self.totals) += 1;
+    }
+
+    /**
+     * Errors that only affect a single agent's trip, and which leave the rest of the simulation in
+     * a usable state. Instead of stopping the simulation, these are counted so that the watchers
+     * can report them.
+     */
+    pub fn is_recoverable(err: &Error) -> bool {
+        use route::Error::{EdgeCountingError, ParkingError};
+        matches!(
+            err,
+            Error::RouteError(EdgeCountingError(_) | ParkingError(_))
+                | Error::AgentError(agent::Error::RouteError(
+                    EdgeCountingError(_) | ParkingError(_)
+                ))
+        )
+    }
+
+    /// Count a recoverable error; see is_recoverable.
+    pub(crate) fn record_error(&mut self, err: &Error) {
+        use route::Error::{EdgeCountingError, ParkingError};
+        match err {
+            Error::RouteError(ParkingError(_))
+            | Error::AgentError(agent::Error::RouteError(ParkingError(_))) => {
+                self.count(|c| &mut c.parking_errors)
+            }
+            Error::RouteError(EdgeCountingError(_))
+            | Error::AgentError(agent::Error::RouteError(EdgeCountingError(_))) => {
+                self.count(|c| &mut c.edge_counting_errors)
+            }
+            _ => panic!("not a recoverable error: {}", err),
+        }
+    }
+
+    pub(crate) fn record_route_found(&mut self) {
+        self.count(|c| &mut c.routes_found);
+    }
+
+    pub(crate) fn record_route_failure(&mut self) {
+        self.count(|c| &mut c.route_failures);
+    }
+
+    /// Count a route start that blocked for the given wall-clock time waiting for its query.
+    pub(crate) fn record_route_deadline_miss(&mut self, blocked: std::time::Duration) {
+        self.count(|c| &mut c.route_deadline_misses);
+        let millis = blocked.as_millis() as u64;
+        self.counts.route_blocked_ms += millis;
+        self.totals.route_blocked_ms += millis;
+    }
+
+    /// Raise an alert for a segment being closed or reopened.
+    pub(crate) fn record_closure(&mut self, message: String, time: u64) {
+        self.raise(Watcher::SegmentClosure, message, time);
+    }
+
+    /**
+     * Check each of the watchers, raising alerts for the ones whose condition started holding
+     * since the last evaluation. Returns true if the simulation should be paused.
+     */
+    pub(crate) fn evaluate(&mut self, time: u64, population: usize, trigger_queue: usize) -> bool {
+        let counts = std::mem::take(&mut self.counts);
+        let thresholds = self.thresholds.clone();
+
+        let day_ago = time.saturating_sub(Self::evaluation_period() * 24);
+        while matches!(self.population.front(), Some((t, _)) if *t < day_ago) {
+            self.population.pop_front();
+        }
+        self.population.push_back((time, population));
+
+        self.trigger_queue.push_back(trigger_queue);
+        while self.trigger_queue.len() > thresholds.trigger_queue_growth + 1 {
+            self.trigger_queue.pop_front();
+        }
+
+        let mut conditions = Vec::new();
+
+        let errors = counts.parking_errors + counts.edge_counting_errors;
+        if errors > thresholds.errors_per_hour {
+            conditions.push((
+                Watcher::ErrorRate,
+                format!(
+                    "{} errors in the last hour ({} parking, {} edge counting)",
+                    errors, counts.parking_errors, counts.edge_counting_errors
+                ),
+            ));
+        }
+
+        if counts.route_failures > thresholds.route_failures_per_hour {
+            conditions.push((
+                Watcher::RouteFailureRate,
+                format!(
+                    "{} agents failed to find a route in the last hour",
+                    counts.route_failures
+                ),
+            ));
+        }
+
+        if counts.routes_found == 0 && counts.route_failures >= thresholds.min_route_queries {
+            conditions.push((
+                Watcher::AllRoutesFailing,
+                format!(
+                    "all {} route queries in the last hour failed",
+                    counts.route_failures
+                ),
+            ));
+        }
+
+        if counts.route_deadline_misses > thresholds.route_deadline_misses_per_hour {
+            conditions.push((
+                Watcher::RouteQueryOverload,
+                format!(
+                    "{} agents waited for their route query in the last hour, for {:.1} s in \
+                     total; consider raising scheduling.route_start_deadline",
+                    counts.route_deadline_misses,
+                    counts.route_blocked_ms as f64 / 1000.0
+                ),
+            ));
+        }
+
+        if let Some((_, previous)) = self.population.front() {
+            let lost = previous.saturating_sub(population);
+            if *previous > 0 && lost as f64 > *previous as f64 * thresholds.population_drop {
+                conditions.push((
+                    Watcher::PopulationDrop,
+                    format!(
+                        "population dropped from {} to {} over the last day",
+                        previous, population
+                    ),
+                ));
+            }
+        }
+
+        if thresholds.trigger_queue_growth > 0
+            && self.trigger_queue.len() == thresholds.trigger_queue_growth + 1
+            && self
+                .trigger_queue
+                .iter()
+                .zip(self.trigger_queue.iter().skip(1))
+                .all(|(before, after)| after > before)
+        {
+            conditions.push((
+                Watcher::TriggerQueueGrowth,
+                format!(
+                    "trigger queue grew from {} to {} over {} evaluations",
+                    self.trigger_queue.front().unwrap(),
+                    trigger_queue,
+                    thresholds.trigger_queue_growth
+                ),
+            ));
+        }
+
+        let mut pause = false;
+        let mut active = BTreeSet::new();
+        for (watcher, message) in conditions {
+            active.insert(watcher);
+            // only alert when the condition starts holding, otherwise we would alert every hour
+            if !self.active.contains(&watcher) {
+                pause |= self.auto_pause && watcher.severity() >= Severity::Critical;
+                self.raise(watcher, message, time);
+            }
+        }
+        self.active = active;
+
+        pause
+    }
+
+    fn raise(&mut self, watcher: Watcher, message: String, time: u64) {
+        if self.alerts.len() >= MAX_ALERTS {
+            self.alerts.pop_front();
+        }
+        self.alerts.push_back(Alert {
+            id: self.next_id,
+            watcher,
+            severity: watcher.severity(),
+            message,
+            time,
+            acknowledged: false,
+        });
+        self.next_id += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alerts::*;
+
+    #[test]
+    fn alerts_raised_once() {
+        let mut alerts = Alerts::default();
+        let period = Alerts::evaluation_period();
+
+        assert!(!alerts.evaluate(0, 10, 5));
+        for _ in 0..=alerts.thresholds.errors_per_hour {
+            alerts.record_error(&Error::RouteError(route::Error::ParkingError(
+                "test".to_string(),
+            )));
+        }
+        assert!(!alerts.evaluate(period, 10, 5));
+        assert_eq!(alerts.iter().count(), 1);
+        let alert = alerts.iter().next().unwrap();
+        assert_eq!(alert.watcher, Watcher::ErrorRate);
+        assert_eq!(alert.severity, Severity::Warning);
+        assert_eq!(alert.time, period);
+
+        // the error rate stays high, but we have already alerted
+        for _ in 0..=alerts.thresholds.errors_per_hour {
+            alerts.record_error(&Error::AgentError(agent::Error::RouteError(
+                route::Error::EdgeCountingError("test".to_string()),
+            )));
+        }
+        assert!(!alerts.evaluate(period * 2, 10, 5));
+        assert_eq!(alerts.iter().count(), 1);
+
+        // once the condition clears, it can alert again
+        assert!(!alerts.evaluate(period * 3, 10, 5));
+        for _ in 0..=alerts.thresholds.errors_per_hour {
+            alerts.record_error(&Error::RouteError(route::Error::ParkingError(
+                "test".to_string(),
+            )));
+        }
+        assert!(!alerts.evaluate(period * 4, 10, 5));
+        assert_eq!(alerts.iter().count(), 2);
+        assert_eq!(alerts.totals().parking_errors, 22);
+        assert_eq!(alerts.totals().edge_counting_errors, 11);
+
+        alerts.acknowledge_all();
+        assert_eq!(alerts.unacknowledged().count(), 0);
+    }
+
+    #[test]
+    fn population_drop() {
+        let mut alerts = Alerts {
+            auto_pause: true,
+            ..Default::default()
+        };
+        let period = Alerts::evaluation_period();
+
+        for i in 0..24 {
+            // a slow decline is fine
+            assert!(!alerts.evaluate(period * i, 100 - i as usize / 4, 5));
+        }
+        assert!(alerts.evaluate(period * 24, 50, 5));
+        assert_eq!(alerts.iter().count(), 1);
+        assert_eq!(
+            alerts.iter().next().unwrap().watcher,
+            Watcher::PopulationDrop
+        );
+    }
+
+    #[test]
+    fn trigger_queue_growth() {
+        let mut alerts = Alerts::default();
+        let period = Alerts::evaluation_period();
+        let growth = alerts.thresholds.trigger_queue_growth as u64;
+
+        // an occasional dip resets the watcher
+        for i in 0..growth {
+            alerts.evaluate(period * i, 10, if i == 3 { 1 } else { 10 + i as usize });
+        }
+        assert_eq!(alerts.iter().count(), 0);
+
+        let mut size = 100;
+        for i in growth..(growth * 2 + 1) {
+            size *= 2;
+            alerts.evaluate(period * i, 10, size);
+        }
+        assert_eq!(alerts.iter().count(), 1);
+        assert_eq!(
+            alerts.iter().next().unwrap().watcher,
+            Watcher::TriggerQueueGrowth
+        );
+    }
+
+    #[test]
+    fn route_query_overload() {
+        let mut alerts = Alerts::default();
+        let period = Alerts::evaluation_period();
+
+        for _ in 0..alerts.thresholds.route_deadline_misses_per_hour {
+            alerts.record_route_deadline_miss(std::time::Duration::from_millis(10));
+        }
+        assert!(!alerts.evaluate(period, 10, 5));
+        assert_eq!(alerts.iter().count(), 0);
+
+        for _ in 0..=alerts.thresholds.route_deadline_misses_per_hour {
+            alerts.record_route_deadline_miss(std::time::Duration::from_millis(10));
+        }
+        assert!(!alerts.evaluate(period * 2, 10, 5));
+        let alert = alerts.iter().next().unwrap();
+        assert_eq!(alert.watcher, Watcher::RouteQueryOverload);
+        assert!(alert.message.contains("route_start_deadline"));
+        assert_eq!(alerts.totals().route_deadline_misses, 201);
+        assert_eq!(alerts.totals().route_blocked_ms, 2010);
+    }
+}
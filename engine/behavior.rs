@@ -1,13 +1,15 @@
 // NOTE: caused by enum-kinds on Trigger
 #![allow(clippy::extra_unused_lifetimes)]
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
-use uom::si::time::{day, hour, minute};
+use uom::si::time::{day, hour};
 use uom::si::u64::Time;
 
-use crate::engine::{Engine, Error};
+use crate::custom_trigger::CustomTrigger;
+use crate::engine::{Engine, Error, InsertPolicy, TimedRoute};
+use crate::job_quits::JobQuit;
 
 #[enum_dispatch::enum_dispatch]
 pub trait TriggerType: std::fmt::Debug + PartialEq + Eq + PartialOrd + Ord {
@@ -19,7 +21,8 @@ pub trait TriggerType: std::fmt::Debug + PartialEq + Eq + PartialOrd + Ord {
     fn debug_context(&self, state: &Engine) -> Option<String>;
 }
 
-// NOTE: all implementations of TriggerType must be listed here
+// NOTE: all implementations of TriggerType must be listed here. Triggers defined in other crates
+// can implement DynTriggerType instead, and are wrapped in CustomTrigger.
 #[allow(clippy::enum_variant_names)]
 #[enum_dispatch::enum_dispatch(TriggerType)]
 #[derive(
@@ -44,13 +47,67 @@ pub enum Trigger {
     UpdateTrafficReceiver,
     AgentPlanCommuteToWork,
     AgentPlanCommuteHome,
+    AgentPlanLeisureTrip,
+    AgentPlanLeisureReturn,
     AgentRouteStart,
     AgentRouteAdvance,
     AgentLifeDecisions,
     WorkplaceDecisions,
     AdvanceNetworkTombstones,
+    ReopenSegment,
+    #[cfg(feature = "replay")]
+    RecordAgentKeyframe,
+    EvaluateAlerts,
+    ApplyScenarioEvent,
     DummyTrigger,
     DoublingTrigger,
+    CustomTrigger,
+}
+
+impl Trigger {
+    /// The agent that this trigger acts on, if any.
+    pub fn agent(&self) -> Option<u64> {
+        match self {
+            Self::AgentPlanCommuteToWork(trigger) => Some(trigger.agent),
+            Self::AgentPlanCommuteHome(trigger) => Some(trigger.agent),
+            Self::AgentPlanLeisureTrip(trigger) => Some(trigger.agent),
+            Self::AgentPlanLeisureReturn(trigger) => Some(trigger.agent),
+            Self::AgentRouteStart(trigger) => Some(trigger.agent),
+            Self::AgentRouteAdvance(trigger) => Some(trigger.agent),
+            Self::AgentLifeDecisions(trigger) => Some(trigger.agent),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * The serialized type of each trigger that is compiled out of this build, along with the feature
+ * that it needs. Saves from builds with more features enabled may still contain these triggers.
+ */
+pub(crate) const DISABLED_TRIGGERS: &[(&str, &str)] = &[
+    #[cfg(not(feature = "replay"))]
+    ("RecordAgentKeyframe", "replay"),
+];
+
+impl Engine {
+    /**
+     * How often (in seconds) triggers of the given kind re-schedule themselves, or None for triggers
+     * that only run in response to something else, like agents planning their routes. Most of these
+     * come from the trigger_cadence section of the config.
+     */
+    pub fn trigger_cadence(&self, kind: TriggerKind) -> Option<u64> {
+        let cadence = &self.state.config.trigger_cadence;
+        match kind {
+            TriggerKind::UpdateFields => Some(cadence.update_fields),
+            TriggerKind::UpdateCollectTiles => Some(cadence.update_collect_tiles),
+            TriggerKind::UpdateTrafficSender => Some(self.world_state_history.snapshot_period()),
+            TriggerKind::AgentLifeDecisions => Some(cadence.agent_life_decisions),
+            TriggerKind::WorkplaceDecisions => Some(cadence.workplace_decisions),
+            TriggerKind::AdvanceNetworkTombstones => Some(cadence.advance_network_tombstones),
+            TriggerKind::EvaluateAlerts => Some(crate::alerts::Alerts::evaluation_period()),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Default, derivative::Derivative)]
@@ -78,6 +135,19 @@ impl<T> Receiver<T> {
     fn receive(&mut self) -> Option<Result<T, Error>> {
         self.receiver.as_mut().map(|r| r.recv().unwrap())
     }
+
+    /// Like receive, but also returns how long it blocked, if the result wasn't ready yet.
+    fn receive_timed(&mut self) -> Option<(Result<T, Error>, Option<std::time::Duration>)> {
+        let receiver = self.receiver.as_mut()?;
+        match receiver.try_recv() {
+            Ok(result) => Some((result, None)),
+            Err(_) => {
+                let start = std::time::Instant::now();
+                let result = receiver.recv().unwrap();
+                Some((result, Some(start.elapsed())))
+            }
+        }
+    }
 }
 
 // This is a common place to define triggers which produce important behavior.
@@ -90,10 +160,8 @@ impl TriggerType for UpdateFields {
         // TODO: only re-run these when the underlying data updates
         engine.update_fields()?;
 
-        // re-trigger every day of simulated time
-        engine
-            .trigger_queue
-            .push_rel(self, Time::new::<day>(1).value);
+        let cadence = engine.state.config.trigger_cadence.update_fields;
+        engine.trigger_queue.push_rel(self, cadence);
 
         Ok(())
     }
@@ -110,10 +178,8 @@ impl TriggerType for UpdateCollectTiles {
     fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
         engine.state.update_collect_tiles()?;
 
-        // re-trigger every hour of simulated time
-        engine
-            .trigger_queue
-            .push_rel(self, Time::new::<hour>(1).value);
+        let cadence = engine.state.config.trigger_cadence.update_collect_tiles;
+        engine.trigger_queue.push_rel(self, cadence);
 
         Ok(())
     }
@@ -123,16 +189,6 @@ impl TriggerType for UpdateCollectTiles {
     }
 }
 
-lazy_static::lazy_static! {
-    /// How far into the future we want the prediction to be when it is applied. This can be
-    /// arbitrarily large as long as it is less than the scale of history tracking (i.e. one day)
-    /// minus the deadline.
-    static ref UPDATE_TRAFFIC_HORIZON: u64 = Time::new::<minute>(30).value;
-    /// How long we allow the thread to compute the traffic for before joining it. If the value is
-    /// too small, we risk blocking until the computation finishes.
-    static ref UPDATE_TRAFFIC_DEADLINE: u64 = Time::new::<minute>(60).value;
-}
-
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct UpdateTrafficSender {}
 
@@ -141,26 +197,33 @@ impl UpdateTrafficSender {}
 impl TriggerType for UpdateTrafficSender {
     fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
         engine.record_traffic_snapshot();
+        #[cfg(feature = "replay")]
+        engine.record_replay_world_state()?;
 
         // TODO: it could make sense to have this apply to route queries as well
-        lazy_static::lazy_static! {
-            static ref SINGLE_THREAD: bool = std::env::var("DEBUG_SINGLE_THREAD").is_ok();
-        }
-
-        if *SINGLE_THREAD {
-            engine.update_route_weights(*UPDATE_TRAFFIC_HORIZON);
+        let state::SchedulingConfig {
+            traffic_horizon,
+            traffic_deadline,
+            single_thread_traffic,
+            ..
+        } = engine.state.config.scheduling;
+
+        if single_thread_traffic {
+            engine.update_route_weights(traffic_horizon);
         } else {
             // This choice of horizon is important; it guarantees that if the engine is serialized and
             // deserialized during the computation, we can still update the traffic serially with the
-            // correct horizon when we encounter the receiver trigger.
-            let receiver = engine
-                .update_route_weights_async(*UPDATE_TRAFFIC_HORIZON + *UPDATE_TRAFFIC_DEADLINE);
+            // correct horizon when we encounter the receiver trigger. Config::validate makes sure
+            // that the horizon plus the deadline fits within the history period.
+            let horizon = traffic_horizon + traffic_deadline;
+            let receiver = engine.update_route_weights_async(horizon);
 
             engine.trigger_queue.push_rel(
                 UpdateTrafficReceiver {
                     receiver: Receiver::new(receiver),
+                    prediction_time: engine.time_state.current_time + horizon,
                 },
-                *UPDATE_TRAFFIC_DEADLINE,
+                traffic_deadline,
             );
         }
 
@@ -181,6 +244,9 @@ impl TriggerType for UpdateTrafficSender {
 pub struct UpdateTrafficReceiver {
     #[serde(skip)]
     receiver: Receiver<(route::FastGraphWrapper, u64)>,
+    /// the time that the new weights predict traffic for
+    #[serde(default)]
+    prediction_time: u64,
 }
 
 impl TriggerType for UpdateTrafficReceiver {
@@ -189,14 +255,14 @@ impl TriggerType for UpdateTrafficReceiver {
             Some(Ok((graph, version)))
                 if version == engine.base_graph.read().unwrap().version() =>
             {
-                engine.update_route_weights_async_callback(graph)
+                engine.update_route_weights_async_callback(graph, self.prediction_time)
             }
             Some(Err(err)) => return Err(err),
             _ => {
                 // We don't have a graph because the engine state was serialized between when the
                 // query was queued and now. The best we can do is re-compute it here.
                 // This also gets triggered when the networks were just updated.
-                engine.update_route_weights(*UPDATE_TRAFFIC_HORIZON);
+                engine.update_route_weights(engine.state.config.scheduling.traffic_horizon);
             }
         }
 
@@ -215,17 +281,34 @@ pub struct AgentPlanCommuteToWork {
 
 impl TriggerType for AgentPlanCommuteToWork {
     fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
+        if !engine.agents.contains_key(&self.agent) {
+            // the agent has been removed since this trigger was queued
+            return Ok(());
+        }
+
+        use chrono::Datelike;
+
+        let schedule = engine.work_schedule(self.agent);
+        let is_weekend = matches!(
+            engine.time_state.current_date().weekday(),
+            chrono::Weekday::Sat | chrono::Weekday::Sun
+        );
+
         let agent = engine.agents.get_mut(&self.agent).expect("missing agent");
         let id = agent.id;
 
-        if let agent::AgentState::Route(_) = agent.state {
+        if let agent::AgentState::Route(route_state) = &agent.state {
+            record_travel!(engine, route_state);
             agent.log_timestamp(|| "aborting route", engine.time_state.current_time);
 
             // the agent hasn't finished their previous route yet.
             agent.abort_route(&mut engine.world_state)?;
+            engine.routing_health.record_route_aborted();
         }
 
-        if let Some(workplace) = &agent.workplace {
+        if is_weekend && !schedule.works_weekends {
+            agent.log_timestamp(|| "not working today", engine.time_state.current_time);
+        } else if let Some(workplace) = agent.workplace {
             // morning commute to work
 
             agent.log_timestamp(
@@ -233,15 +316,24 @@ impl TriggerType for AgentPlanCommuteToWork {
                 engine.time_state.current_time,
             );
 
+            // household members only get to drive if one of the household's cars is free
+            engine.return_household_car(id)?;
+            let has_car = engine.check_out_household_car(id);
+
             let query_input = route::QueryInput {
-                start: agent.housing,
-                end: *workplace,
-                car_config: agent.parked_car().map(|_| route::CarConfig::StartWithCar),
+                start: engine.agents[&id].housing,
+                end: workplace,
+                car_config: has_car.then_some(route::CarConfig::StartWithCar),
+                profile: engine.agents[&id]
+                    .data
+                    .mobility_profile(&engine.state.config.archetypes),
+                allowed_modes: Default::default(),
             };
 
-            let start_time = engine.time_state.current_time + AgentRouteStart::DEADLINE;
+            let start_time = engine.time_state.current_time
+                + engine.state.config.scheduling.route_start_deadline;
 
-            let receiver = engine.query_route_async(query_input);
+            let receiver = engine.query_agent_route_async(id, query_input);
             engine.trigger_queue.push(
                 AgentRouteStart {
                     agent: id,
@@ -252,18 +344,36 @@ impl TriggerType for AgentPlanCommuteToWork {
                 start_time,
             );
 
-            // come home from work after 8 hours
+            // come home from work at the end of the shift
             // TODO: it would be better to use estimated time or something
             // we had this originally, but it's tougher with parallelism
             engine.trigger_queue.push(
                 AgentPlanCommuteHome { agent: id },
-                start_time + Time::new::<hour>(8).value,
+                start_time + Time::new::<hour>(schedule.shift_hours).value,
             );
         }
 
-        engine
-            .trigger_queue
-            .push_rel(self, Time::new::<day>(1).value);
+        let day_length = Time::new::<day>(1).value;
+        let today = engine.time_state.current_time / day_length * day_length;
+
+        // some evenings, agents head out to a park once they are home
+        let leisure = &engine.state.config.leisure;
+        if leisure.enabled && rand::Rng::gen_bool(&mut engine.rng, leisure.probability) {
+            let leisure_time = today + Time::new::<hour>(leisure.start_hour).value;
+            if leisure_time > engine.time_state.current_time {
+                engine
+                    .trigger_queue
+                    .push(AgentPlanLeisureTrip { agent: id }, leisure_time);
+            }
+        }
+
+        // plan tomorrow's commute for the start of tomorrow's shift; the agent may have changed
+        // jobs by then, but it will be corrected the day after
+        let tomorrow = today + day_length;
+        engine.trigger_queue.push(
+            self,
+            tomorrow + Time::new::<hour>(schedule.start_hour).value,
+        );
 
         Ok(())
     }
@@ -280,14 +390,21 @@ pub struct AgentPlanCommuteHome {
 
 impl TriggerType for AgentPlanCommuteHome {
     fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
+        if !engine.agents.contains_key(&self.agent) {
+            // the agent has been removed since this trigger was queued
+            return Ok(());
+        }
+
         let agent = engine.agents.get_mut(&self.agent).expect("missing agent");
         let id = agent.id;
 
-        if let agent::AgentState::Route(_) = agent.state {
+        if let agent::AgentState::Route(route_state) = &agent.state {
+            record_travel!(engine, route_state);
             agent.log_timestamp(|| "aborting route", engine.time_state.current_time);
 
             // the agent hasn't finished their previous route yet.
             agent.abort_route(&mut engine.world_state)?;
+            engine.routing_health.record_route_aborted();
         }
 
         if let Some(workplace) = &agent.workplace {
@@ -305,11 +422,14 @@ impl TriggerType for AgentPlanCommuteHome {
                 car_config: agent
                     .parked_car()
                     .map(|address| route::CarConfig::CollectParkedCar { address }),
+                profile: agent.data.mobility_profile(&engine.state.config.archetypes),
+                allowed_modes: Default::default(),
             };
 
-            let start_time = engine.time_state.current_time + AgentRouteStart::DEADLINE;
+            let start_time = engine.time_state.current_time
+                + engine.state.config.scheduling.route_start_deadline;
 
-            let receiver = engine.query_route_async(query_input);
+            let receiver = engine.query_agent_route_async(id, query_input);
             engine.trigger_queue.push(
                 AgentRouteStart {
                     agent: id,
@@ -331,6 +451,154 @@ impl TriggerType for AgentPlanCommuteHome {
     }
 }
 
+/// An agent who is home in the evening heads out to the nearest park, if there is one.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct AgentPlanLeisureTrip {
+    pub agent: u64,
+}
+
+impl TriggerType for AgentPlanLeisureTrip {
+    fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
+        if !engine.agents.contains_key(&self.agent) {
+            // the agent has been removed since this trigger was queued
+            return Ok(());
+        }
+
+        let agent = &engine.agents[&self.agent];
+        let id = agent.id;
+        let housing = agent.housing;
+
+        if !matches!(agent.state, agent::AgentState::Tile(address) if address == housing) {
+            // e.g. the agent is still at work
+            agent.log_timestamp(
+                || "not home; skipping leisure trip",
+                engine.time_state.current_time,
+            );
+            return Ok(());
+        }
+        let park = match engine.nearest_poi(housing, tiles::PoiKind::Park) {
+            Some(park) => park,
+            None => return Ok(()),
+        };
+
+        agent.log_timestamp(|| "planning leisure trip", engine.time_state.current_time);
+
+        engine.return_household_car(id)?;
+        let has_car = engine.check_out_household_car(id);
+
+        let query_input = route::QueryInput {
+            start: housing,
+            end: park,
+            car_config: has_car.then_some(route::CarConfig::StartWithCar),
+            profile: engine.agents[&id]
+                .data
+                .mobility_profile(&engine.state.config.archetypes),
+            allowed_modes: Default::default(),
+        };
+
+        let start_time =
+            engine.time_state.current_time + engine.state.config.scheduling.route_start_deadline;
+
+        let receiver = engine.query_agent_route_async(id, query_input);
+        engine.trigger_queue.push(
+            AgentRouteStart {
+                agent: id,
+                receiver: Receiver::new(receiver),
+                route_type: agent::RouteType::ToLeisure,
+                query_input,
+            },
+            start_time,
+        );
+
+        let duration = Time::new::<hour>(engine.state.config.leisure.duration_hours).value;
+        engine.trigger_queue.push(
+            AgentPlanLeisureReturn { agent: id },
+            engine.time_state.current_time + duration,
+        );
+
+        Ok(())
+    }
+
+    fn debug_context(&self, state: &Engine) -> Option<String> {
+        Some(format!("{:#?}", state.agents.get(&self.agent)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct AgentPlanLeisureReturn {
+    pub agent: u64,
+}
+
+impl TriggerType for AgentPlanLeisureReturn {
+    fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
+        if !engine.agents.contains_key(&self.agent) {
+            // the agent has been removed since this trigger was queued
+            return Ok(());
+        }
+
+        let agent = engine.agents.get_mut(&self.agent).expect("missing agent");
+        let id = agent.id;
+
+        let start = match &agent.state {
+            agent::AgentState::Route(route_state)
+                if route_state.route_type == agent::RouteType::ToLeisure =>
+            {
+                route_state.route.end()
+            }
+            agent::AgentState::Tile(address) if *address != agent.housing => *address,
+            _ => {
+                // the agent never made it out, or has moved on since
+                return Ok(());
+            }
+        };
+
+        if let agent::AgentState::Route(route_state) = &agent.state {
+            record_travel!(engine, route_state);
+            agent.log_timestamp(|| "aborting route", engine.time_state.current_time);
+
+            // the agent hasn't made it to the park yet, so they turn around once they get there
+            agent.abort_route(&mut engine.world_state)?;
+            engine.routing_health.record_route_aborted();
+        }
+
+        agent.log_timestamp(
+            || "planning trip home from leisure",
+            engine.time_state.current_time,
+        );
+
+        let query_input = route::QueryInput {
+            start,
+            end: agent.housing,
+            // if a car is parked somewhere, account for it
+            car_config: agent
+                .parked_car()
+                .map(|address| route::CarConfig::CollectParkedCar { address }),
+            profile: agent.data.mobility_profile(&engine.state.config.archetypes),
+            allowed_modes: Default::default(),
+        };
+
+        let start_time =
+            engine.time_state.current_time + engine.state.config.scheduling.route_start_deadline;
+
+        let receiver = engine.query_agent_route_async(id, query_input);
+        engine.trigger_queue.push(
+            AgentRouteStart {
+                agent: id,
+                receiver: Receiver::new(receiver),
+                route_type: agent::RouteType::FromLeisure,
+                query_input,
+            },
+            start_time,
+        );
+
+        Ok(())
+    }
+
+    fn debug_context(&self, state: &Engine) -> Option<String> {
+        Some(format!("{:#?}", state.agents.get(&self.agent)))
+    }
+}
+
 // NOTE: if we are loading from a serialized copy, the spawned thread is dead, so we need to
 // do a blocking compute from the query input.
 #[derive(Debug, Clone, Serialize, Deserialize, derivative::Derivative)]
@@ -339,18 +607,35 @@ pub struct AgentRouteStart {
     agent: u64,
     route_type: agent::RouteType,
     #[serde(skip)]
-    receiver: Receiver<Option<route::Route>>,
+    receiver: Receiver<TimedRoute>,
     #[derivative(PartialEq = "ignore", PartialOrd = "ignore", Ord = "ignore")]
     query_input: route::QueryInput,
 }
 
 impl AgentRouteStart {
-    /// how long (in simulation time) we should wait before joining the calculation worker
-    const DEADLINE: u64 = 5;
+    /// Start querying the route, to be picked up when the trigger executes.
+    pub(crate) fn query(
+        engine: &Engine,
+        agent: u64,
+        route_type: agent::RouteType,
+        query_input: route::QueryInput,
+    ) -> Self {
+        Self {
+            agent,
+            route_type,
+            receiver: Receiver::new(engine.query_agent_route_async(agent, query_input)),
+            query_input,
+        }
+    }
 }
 
 impl TriggerType for AgentRouteStart {
     fn execute(mut self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
+        if !engine.agents.contains_key(&self.agent) {
+            // the agent has been removed since this trigger was queued
+            return Ok(());
+        }
+
         agent::agent_log_timestamp(
             self.agent,
             || "starting route",
@@ -359,8 +644,22 @@ impl TriggerType for AgentRouteStart {
 
         // This blocks if the route has not been computed yet.
         // We can adjust how likely we are to block by twiddling the deadline.
-        let route = match self.receiver.receive() {
-            Some(route) => route,
+        let route = match self.receiver.receive_timed() {
+            Some((route, blocked)) => {
+                engine.route_latency.record_route_start(blocked);
+                if let Some(blocked) = blocked {
+                    agent::agent_log_timestamp(
+                        self.agent,
+                        || format!("blocked for {:?} waiting for route query", blocked),
+                        engine.time_state.current_time,
+                    );
+                    engine.alerts.record_route_deadline_miss(blocked);
+                }
+                route.map(|(route, duration)| {
+                    engine.route_latency.record_query(duration);
+                    route
+                })
+            }
             None => {
                 agent::agent_log_timestamp(
                     self.agent,
@@ -374,41 +673,98 @@ impl TriggerType for AgentRouteStart {
             }
         };
 
+        // The network may have been edited since the route was queried. Routes already underway
+        // can finish on removed segments, but new routes can't start on them.
+        let route = match route {
+            Ok(Some(route)) if engine.route_uses_removed_segments(&route) => {
+                agent::agent_log_timestamp(
+                    self.agent,
+                    || "route uses removed segments; requerying",
+                    engine.time_state.current_time,
+                );
+                engine.routing_health.record_route_requeried();
+                engine.query_route(self.query_input)
+            }
+            route => route,
+        };
+
+        let detail = engine.route_detail();
         let agent = engine.agents.get_mut(&self.agent).expect("missing agent");
 
         if let agent::AgentState::Route(_) = agent.state {
             panic!("route should have been aborted before it was queued");
         }
 
-        if let Some(route) = route.unwrap() {
+        let route = route.unwrap();
+        match route {
+            Some(_) => engine.alerts.record_route_found(),
+            None => engine.alerts.record_route_failure(),
+        }
+
+        if let Some(route) = route {
             agent.log_timestamp(|| "route found; starting", engine.time_state.current_time);
 
             let next_trigger = agent.begin_route(
                 route,
                 engine.time_state.current_time,
                 self.route_type,
+                detail,
                 &mut engine.world_state,
                 &engine.state,
             )?;
 
-            if let Some(next_trigger) = next_trigger {
-                engine
-                    .trigger_queue
-                    .push(AgentRouteAdvance { agent: self.agent }, next_trigger);
+            match next_trigger {
+                Some(next_trigger) => {
+                    engine.trigger_queue.push(
+                        AgentRouteAdvance {
+                            agent: self.agent,
+                            route_start: Some(engine.time_state.current_time),
+                        },
+                        next_trigger,
+                    );
+                }
+                None => {
+                    // the route has no edges, so the agent is already there; nothing would ever
+                    // advance the route, so finish it right away
+                    if let agent::AgentState::Route(route_state) = &agent.state {
+                        record_travel!(engine, route_state);
+                    }
+                    agent
+                        .log_timestamp(|| "empty route; finishing", engine.time_state.current_time);
+                    agent.finish_route()?;
+                    engine.return_household_car(self.agent)?;
+                }
             }
-        } else if let agent::RouteType::CommuteFromWork = self.route_type {
-            agent.log_timestamp(
-                || "no route found; teleporting home",
-                engine.time_state.current_time,
-            );
-
-            // teleport the agent home
-            agent.teleport_home(&mut engine.world_state)?;
         } else {
-            agent.log_timestamp(
-                || "no route found; staying put",
-                engine.time_state.current_time,
-            );
+            match self.route_type {
+                agent::RouteType::CommuteFromWork | agent::RouteType::FromLeisure => {
+                    agent.log_timestamp(
+                        || "no route found; teleporting home",
+                        engine.time_state.current_time,
+                    );
+
+                    // teleport the agent home
+                    agent.teleport_home(self.route_type, &mut engine.world_state)?;
+                    engine.routing_health.record_teleported_home();
+                    engine.return_household_car(self.agent)?;
+                }
+                agent::RouteType::CommuteToWork => {
+                    agent.log_timestamp(
+                        || "no route found; staying put",
+                        engine.time_state.current_time,
+                    );
+                    engine
+                        .routing_health
+                        .record_failed_to_work(engine.time_state.current_time);
+                }
+                agent::RouteType::ToLeisure => {
+                    agent.log_timestamp(
+                        || "no route found; staying home",
+                        engine.time_state.current_time,
+                    );
+                    engine.return_household_car(self.agent)?;
+                }
+            }
         }
 
         Ok(())
@@ -422,14 +778,33 @@ impl TriggerType for AgentRouteStart {
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct AgentRouteAdvance {
     agent: u64,
+    /// the start time of the route that scheduled this trigger, so that a trigger left over from
+    /// an interrupted route doesn't advance the agent's next route; missing in older saves
+    #[serde(default)]
+    route_start: Option<u64>,
 }
 
 impl TriggerType for AgentRouteAdvance {
     fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
+        if !engine.agents.contains_key(&self.agent) {
+            // the agent has been removed since this trigger was queued
+            return Ok(());
+        }
+
         let agent = engine.agents.get_mut(&self.agent).expect("missing agent");
 
         agent.log_timestamp(|| "advancing", engine.time_state.current_time);
 
+        let current_route = matches!(
+            &agent.state,
+            agent::AgentState::Route(route_state)
+                if self.route_start.map_or(true, |start| start == route_state.start_time)
+        );
+        if !current_route {
+            // this route was aborted because it took too long, or interrupted by a closure
+            return Ok(());
+        }
+
         if let agent::AgentState::Route(route_state) = &mut agent.state {
             route_state.advance(&mut engine.world_state, &engine.state)?;
             match route_state.next_trigger() {
@@ -438,12 +813,20 @@ impl TriggerType for AgentRouteAdvance {
                     engine.trigger_queue.push(self, next_trigger);
                 }
                 None => {
+                    record_travel!(engine, route_state);
+                    if route_state.route_type == agent::RouteType::CommuteToWork {
+                        engine.od_matrices.record(
+                            &engine.state.config,
+                            engine.time_state.current_time,
+                            route_state.route.start(),
+                            route_state.route.end(),
+                        );
+                    }
                     agent.log_timestamp(|| "finishing route", engine.time_state.current_time);
                     agent.finish_route()?;
+                    engine.return_household_car(self.agent)?;
                 }
             }
-        } else {
-            // this route was aborted because it took too long
         }
 
         Ok(())
@@ -460,7 +843,7 @@ pub struct AgentLifeDecisions {
 }
 
 impl AgentLifeDecisions {
-    fn get_agent<'a>(&self, agents: &'a HashMap<u64, agent::Agent>) -> &'a agent::Agent {
+    fn get_agent<'a>(&self, agents: &'a BTreeMap<u64, agent::Agent>) -> &'a agent::Agent {
         agents.get(&self.agent).expect("missing agent")
     }
 
@@ -474,11 +857,31 @@ impl AgentLifeDecisions {
 
     fn maybe_quit_job(&self, engine: &mut Engine) {
         let agent = self.get_agent(&engine.agents);
+        let config = &engine.state.config;
+        let current_time = engine.time_state.current_time;
 
-        if let Some(workplace_happiness_score) = agent.workplace_happiness_score() {
-            if workplace_happiness_score < 0.1 {
+        if let Some(happiness) = agent.workplace_happiness(config, current_time) {
+            if happiness.score < config.workplace_happiness.quit_threshold {
                 if let Some(workplace) = agent.workplace {
                     let agent_id = agent.id;
+                    let reason = happiness
+                        .main_shortfall()
+                        .map(|component| component.component);
+                    agent.log(|| {
+                        format!(
+                            "quitting job at {:?} with happiness {:.2}, mostly because of {}",
+                            workplace,
+                            happiness.score,
+                            reason.map_or("nothing in particular", |reason| reason.label()),
+                        )
+                    });
+                    engine.job_quits.record(JobQuit {
+                        time: current_time,
+                        agent: agent_id,
+                        score: happiness.score,
+                        reason,
+                    });
+
                     match engine.state.qtree.get_leaf_mut(workplace) {
                         Ok(state::LeafState {
                             tile: tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { agents, .. }),
@@ -488,13 +891,13 @@ impl AgentLifeDecisions {
                         }
                         _ => panic!("missing workplace or non-workplace tile"),
                     }
-                    self.modify_agent(engine, |agent| agent.workplace = None);
+                    self.modify_agent(engine, |agent| agent.leave_job());
                 }
             }
         }
     }
 
-    fn maybe_find_new_job(&self, engine: &mut Engine) {
+    fn maybe_find_new_job(&self, engine: &mut Engine) -> Result<(), Error> {
         let agent = self.get_agent(&engine.agents);
 
         // NOTE: if this is slow, it should be easy to parallelize
@@ -504,79 +907,96 @@ impl AgentLifeDecisions {
             // TODO: be smarter about picking workplace candidates; sampling the map at random will
             // lead to the majority being too far away
             let vacant = &engine.state.collect_tiles.vacant_workplaces[..];
-            let best = vacant.choose_multiple(&mut engine.rng, 100).filter_map(|address| {
-                // the CollectTilesVisitor could be out-of-date; make sure the information is still
-                // valid
-                match engine.state.qtree.get_leaf(*address) {
+            let candidates: Vec<quadtree::Address> = vacant
+                .choose_multiple(&mut engine.rng, 100)
+                .filter(|address| {
+                    // the CollectTilesVisitor could be out-of-date; make sure the information is
+                    // still valid
+                    matches!(
+                        engine.state.qtree.get_leaf(**address),
+                        Ok(state::LeafState {
+                            tile: tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { density, agents, .. }),
+                            ..
+                        }) if agents.len() < *density
+                    )
+                })
+                .copied()
+                .collect();
+            if candidates.is_empty() {
+                tracing::debug!(agent = agent.id, "no vacant workplaces, nothing to do");
+                return Ok(());
+            }
+
+            // TODO: query for what congestion *would* be during normal commuting hours
+            let commute_length_tolerance = agent
+                .data
+                .commute_length_tolerance(&engine.state.config.archetypes)
+                as f64;
+            let costs = engine.query_route_costs(
+                agent.housing,
+                &candidates,
+                engine
+                    .has_car_access(agent.id)
+                    .then_some(route::CarConfig::StartWithCar),
+                Some(commute_length_tolerance),
+            )?;
+
+            // candidates that cannot be reached within the commute length tolerance are None
+            let best = candidates
+                .into_iter()
+                .zip(costs)
+                .filter_map(|(address, cost)| cost.map(|cost| (address, cost)))
+                .min_by(|(_, cost1), (_, cost2)| cost1.partial_cmp(cost2).unwrap());
+            if let Some((address, _)) = best {
+                let agent_id = agent.id;
+                let hired = match engine.state.qtree.get_leaf_mut(address) {
                     Ok(state::LeafState {
-                        tile: tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { density, agents }),
+                        tile:
+                            tiles::Tile::WorkplaceTile(tiles::WorkplaceTile {
+                                density,
+                                agents,
+                                industry,
+                            }),
                         ..
                     }) => {
-                        if agents.len() >= *density {
-                            return None;
+                        if agents.len() < *density {
+                            agents.push(agent_id);
+                            Some(*industry)
+                        } else {
+                            None
                         }
                     }
-                    _ => return None,
+                    _ => None,
                 };
-
-                // TODO: running a bunch of queries is too expensive
-
-                // let query_input = route::QueryInput {
-                //     start: agent.housing,
-                //     end: *address,
-                //     car_config: Some(route::CarConfig::StartWithCar),
-                // };
-                // // TODO: query for what congestion *would* be during normal commuting hours
-                // // TODO: we don't need to construct the route, this is a significant source of
-                // // wasted time
-                // let route = engine.query_route(query_input).unwrap();
-                // route.map(|route| (*address, route.total_cost()))
-
-                use cgmath::MetricSpace;
-
-                let (x1, y1) = agent.housing.to_xy_f64();
-                let (x2, y2) = address.to_xy_f64();
-
-                let dist_sq = cgmath::Vector2::from((x1, y1)).distance2((x2, y2).into());
-                Some((*address, -dist_sq))
-            }).max_by(|(_, score1), (_, score2)| score1.partial_cmp(score2).unwrap());
-            if let Some((address, neg_dist_sq)) = best {
-                // TODO: this is a gross approximation, would be better to actually compute the
-                // route cost
-                if (-neg_dist_sq).sqrt() < (agent.data.commute_length_tolerance() as f64) / 10.0 {
-                    let agent_id = agent.id;
-                    if match engine.state.qtree.get_leaf_mut(address) {
-                        Ok(state::LeafState {
-                            tile:
-                                tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { density, agents }),
-                            ..
-                        }) => {
-                            if agents.len() < *density {
-                                agents.push(agent_id);
-                                true
-                            } else {
-                                false
-                            }
-                        }
-                        _ => false,
-                    } {
-                        self.modify_agent(engine, |agent| agent.workplace = Some(address));
-                    }
+                if let Some(industry) = hired {
+                    let current_time = engine.time_state.current_time;
+                    self.modify_agent(engine, |agent| {
+                        agent.take_job(address, industry, current_time)
+                    });
                 }
             }
         }
+
+        Ok(())
     }
 }
 
 impl TriggerType for AgentLifeDecisions {
     fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
+        if !engine.agents.contains_key(&self.agent) {
+            // the agent has been removed since this trigger was queued
+            return Ok(());
+        }
+
+        // agents should never be left on a route with nothing to advance them, but if they are,
+        // they would never commute again
+        engine.recover_stuck_agent(self.agent)?;
+
         self.maybe_quit_job(engine);
-        self.maybe_find_new_job(engine);
+        self.maybe_find_new_job(engine)?;
 
-        // TODO: a longer cadence would make sense, but doing this for testing purposes
-        engine
-            .trigger_queue
-            .push_rel(self, Time::new::<day>(2).value);
+        let cadence = engine.state.config.trigger_cadence.agent_life_decisions;
+        engine.trigger_queue.push_rel(self, cadence);
 
         Ok(())
     }
@@ -589,39 +1009,103 @@ impl TriggerType for AgentLifeDecisions {
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct WorkplaceDecisions {}
 
+impl WorkplaceDecisions {
+    /// how many levels up the quadtree to look for nearby jobs when choosing an industry
+    const NEARBY_LEVELS: usize = 3;
+    /// the blurred field that new workplaces are sampled from
+    const DRIVER: &'static str = crate::field_update::WORKPLACE_DEMAND;
+    /// how many times to resample when landing on a tile that can't be built on
+    const MAX_ATTEMPTS: usize = 10;
+
+    /**
+     * Choose an industry for a new workplace. Half of the weight comes from the configured industry
+     * weights and half from the mix of jobs nearby, so that e.g. industrial areas tend to attract
+     * more industry. If there are no jobs nearby, only the configured weights are used.
+     */
+    fn choose_industry(engine: &mut Engine, address: quadtree::Address) -> tiles::Industry {
+        use rand::distributions::{Distribution, WeightedIndex};
+
+        let config = &engine.state.config.industry_weights;
+        let total_configured: f64 = tiles::Industry::ALL
+            .iter()
+            .map(|industry| config.get(*industry))
+            .sum();
+
+        // find the smallest surrounding area with any jobs
+        let nearby = std::iter::successors(address.parent(), |address| address.parent())
+            .take(Self::NEARBY_LEVELS)
+            .filter_map(|address| engine.state.qtree.get_branch(address).ok())
+            .map(|branch| &branch.fields.employment)
+            .find(|employment| employment.jobs.total > 0);
+
+        let weights = tiles::Industry::ALL.map(|industry| {
+            let configured = config.get(industry) / total_configured;
+            let local = match nearby {
+                Some(employment) => {
+                    employment.jobs_by_industry[industry].total as f64
+                        / employment.jobs.total as f64
+                }
+                None => configured,
+            };
+            (configured + local) / 2.0
+        });
+
+        match WeightedIndex::new(weights) {
+            Ok(distr) => tiles::Industry::ALL[distr.sample(&mut engine.rng)],
+            // e.g. all of the configured weights are zero
+            Err(_) => tiles::Industry::default(),
+        }
+    }
+}
+
 impl TriggerType for WorkplaceDecisions {
     fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
-        let root_branch = engine.state.qtree.get_root_branch().unwrap();
+        let root_fields = engine.root_fields()?;
         // this should be a reasonable number
-        let new_workplaces = root_branch.fields.raw_demand.raw_workplace_demand.count / 100;
+        let new_workplaces = root_fields.raw_demand.raw_workplace_demand.count / 100;
+        if new_workplaces == 0 {
+            tracing::debug!("no demand for new workplaces, nothing to do");
+        }
 
-        for _ in 0..new_workplaces {
-            let address = match engine
-                .blurred_fields
-                .workplace_demand
-                .sample(&mut engine.rng, &engine.state.qtree)
-            {
-                Some(address) => address,
-                None => {
-                    // no valid distribution, so just give up
-                    break;
-                }
-            };
+        'workplaces: for _ in 0..new_workplaces {
+            for _ in 0..Self::MAX_ATTEMPTS {
+                let address = match engine.blurred_fields.get(Self::DRIVER).and_then(|field| {
+                    field.sample_where(
+                        &mut engine.rng,
+                        &engine.state.qtree,
+                        Self::MAX_ATTEMPTS,
+                        |leaf| InsertPolicy::Preserve.allows(&leaf.tile),
+                    )
+                }) {
+                    Some(address) => address,
+                    None => {
+                        // no valid distribution, e.g. on an empty map, so just give up until next
+                        // time
+                        tracing::debug!("nowhere to place new workplaces, nothing to do");
+                        break 'workplaces;
+                    }
+                };
 
-            engine
-                .insert_tile(
+                let industry = Self::choose_industry(engine, address);
+                match engine.insert_tile(
                     address,
                     tiles::Tile::WorkplaceTile(tiles::WorkplaceTile {
                         density: 1,
                         agents: vec![],
+                        industry,
                     }),
-                )
-                .unwrap();
+                    InsertPolicy::Preserve,
+                ) {
+                    Ok(_) => continue 'workplaces,
+                    // shouldn't happen since the sample was checked, but it's just a bad sample
+                    Err(err) if err.is_insert_rejection() => continue,
+                    Err(err) => return Err(err),
+                }
+            }
         }
 
-        engine
-            .trigger_queue
-            .push_rel(self, Time::new::<day>(2).value);
+        let cadence = engine.state.config.trigger_cadence.workplace_decisions;
+        engine.trigger_queue.push_rel(self, cadence);
 
         Ok(())
     }
@@ -637,9 +1121,107 @@ pub struct AdvanceNetworkTombstones {}
 impl TriggerType for AdvanceNetworkTombstones {
     fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
         engine.state.advance_network_tombstones();
+        let cadence = engine
+            .state
+            .config
+            .trigger_cadence
+            .advance_network_tombstones;
+        engine.trigger_queue.push_rel(self, cadence);
+        Ok(())
+    }
+
+    fn debug_context(&self, _state: &Engine) -> Option<String> {
+        None
+    }
+}
+
+/// Reopens a segment that was closed with Engine::close_segment, once the closure is over.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ReopenSegment {
+    pub segment: crate::closures::NetworkSegment,
+}
+
+impl TriggerType for ReopenSegment {
+    fn execute(self, engine: &mut Engine, time: u64) -> Result<(), Error> {
+        engine.reopen_segment(self.segment, time);
+        Ok(())
+    }
+
+    fn debug_context(&self, engine: &Engine) -> Option<String> {
+        Some(format!(
+            "{}: {:?}",
+            self.segment,
+            engine.segment_change_state(self.segment)
+        ))
+    }
+}
+
+#[cfg(feature = "replay")]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RecordAgentKeyframe {
+    /// the recording that scheduled this trigger; see Engine::start_recording
+    pub generation: u64,
+}
+
+#[cfg(feature = "replay")]
+impl TriggerType for RecordAgentKeyframe {
+    fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
+        // stop re-triggering once the recording that scheduled us has finished or filled up
+        if engine.recording.generation != self.generation {
+            return Ok(());
+        }
+        let interval = match engine.recorder() {
+            Some(recorder) if !recorder.is_full() => recorder.config().keyframe_interval,
+            _ => return Ok(()),
+        };
+
+        engine.record_replay_agent_keyframe()?;
+        engine.trigger_queue.push_rel(self, interval);
+        Ok(())
+    }
+
+    fn debug_context(&self, _state: &Engine) -> Option<String> {
+        None
+    }
+}
+
+/// Applies a single event of the scenario that was scheduled with Engine::schedule_scenario.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ApplyScenarioEvent {
+    /// the scenario that scheduled this trigger; stale triggers do nothing
+    pub generation: u64,
+    pub index: usize,
+}
+
+impl TriggerType for ApplyScenarioEvent {
+    fn execute(self, engine: &mut Engine, _time: u64) -> Result<(), Error> {
+        engine.apply_scenario_event(self.generation, self.index)
+    }
+
+    fn debug_context(&self, engine: &Engine) -> Option<String> {
+        engine
+            .scenario_event(self.generation, self.index)
+            .map(|event| event.action.label())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EvaluateAlerts {}
+
+impl TriggerType for EvaluateAlerts {
+    fn execute(self, engine: &mut Engine, time: u64) -> Result<(), Error> {
+        let pause = engine
+            .alerts
+            .evaluate(time, engine.agents.len(), engine.trigger_queue.len());
+        if pause {
+            // also cancel any pending skip, which would otherwise continue while paused
+            engine.time_state.paused = true;
+            engine.time_state.target_time = time;
+        }
+
         engine
             .trigger_queue
-            .push_rel(self, Time::new::<day>(1).value);
+            .push_rel(self, crate::alerts::Alerts::evaluation_period());
         Ok(())
     }
 
@@ -0,0 +1,251 @@
+use std::time::{Duration, Instant};
+
+use crate::behavior::TriggerKind;
+use crate::engine::{Engine, Error};
+use crate::trigger::{IncrementalStats, TriggerMap};
+
+/// How many times one kind of trigger ran during a benchmark, and how long they took in total.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TriggerReport {
+    pub kind: TriggerKind,
+    pub count: u128,
+    /// thread CPU time spent executing these triggers
+    pub total: Duration,
+}
+
+/**
+ * The results of a benchmark run. Triggers are listed in order of total time, most expensive first,
+ * and kinds that never ran are left out.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkReport {
+    /// simulated seconds
+    pub sim_duration: u64,
+    pub wall_time: Duration,
+    pub triggers: Vec<TriggerReport>,
+    /// routes that agents started or failed to find
+    pub route_queries: u64,
+    /// the peak resident memory of the whole process in bytes, if the platform reports it
+    pub peak_memory: Option<u64>,
+}
+
+impl BenchmarkReport {
+    /// Assemble a report from trigger profiling stats taken before and after the run.
+    pub fn new(
+        sim_duration: u64,
+        wall_time: Duration,
+        before: &TriggerMap<IncrementalStats>,
+        after: &TriggerMap<IncrementalStats>,
+        route_queries: u64,
+        peak_memory: Option<u64>,
+    ) -> Self {
+        let mut triggers: Vec<TriggerReport> = after
+            .iter()
+            .map(|(kind, stats)| TriggerReport {
+                kind,
+                count: stats.count - before[kind].count,
+                total: Duration::from_nanos((stats.sum - before[kind].sum) as u64),
+            })
+            .filter(|report| report.count > 0)
+            .collect();
+        triggers.sort_by(|a, b| b.total.cmp(&a.total).then(a.kind.cmp(&b.kind)));
+
+        Self {
+            sim_duration,
+            wall_time,
+            triggers,
+            route_queries,
+            peak_memory,
+        }
+    }
+
+    /// Simulated seconds per wall-clock second.
+    pub fn sim_rate(&self) -> f64 {
+        self.sim_duration as f64 / self.wall_time.as_secs_f64()
+    }
+
+    /// The report as plain text, suitable for copying into a bug report or a commit message.
+    pub fn to_text(&self) -> String {
+        use std::fmt::Write;
+
+        let mut text = String::new();
+        writeln!(text, "Simulated: {} s", self.sim_duration).unwrap();
+        writeln!(text, "Wall time: {:.3} s", self.wall_time.as_secs_f64()).unwrap();
+        writeln!(text, "Sim rate: {:.1}x", self.sim_rate()).unwrap();
+        writeln!(text, "Route queries: {}", self.route_queries).unwrap();
+        match self.peak_memory {
+            Some(bytes) => writeln!(text, "Peak memory: {:.1} MiB", bytes as f64 / 1048576.0),
+            None => writeln!(text, "Peak memory: n/a"),
+        }
+        .unwrap();
+        writeln!(text, "Triggers (count, total CPU time):").unwrap();
+        for trigger in &self.triggers {
+            writeln!(
+                text,
+                "  {:?}: {}, {:.3} s",
+                trigger.kind,
+                trigger.count,
+                trigger.total.as_secs_f64()
+            )
+            .unwrap();
+        }
+        text
+    }
+}
+
+/**
+ * The peak resident memory of the process in bytes. Only available on Linux, where it is read from
+ * /proc.
+ */
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/**
+ * Runs the simulation forward a fixed amount of simulated time as fast as possible, ignoring the
+ * playback rate. The work is done in chunks so that a UI can stay responsive in between. Trigger
+ * profiling is turned on for the duration of the run.
+ */
+#[derive(Debug)]
+pub struct Benchmark {
+    start_time: u64,
+    end_time: u64,
+    wall_time: Duration,
+    stats_before: TriggerMap<IncrementalStats>,
+    queries_before: u64,
+    was_profiling: bool,
+    was_paused: bool,
+}
+
+impl Benchmark {
+    /// Prepare to run the given number of simulated seconds from the current time.
+    pub fn start(engine: &mut Engine, duration: u64) -> Self {
+        let benchmark = Self {
+            start_time: engine.time_state.current_time,
+            end_time: engine.time_state.current_time + duration,
+            wall_time: Duration::ZERO,
+            stats_before: engine.trigger_stats.stats.clone(),
+            queries_before: Self::route_queries(engine),
+            was_profiling: engine.trigger_stats.profiling_enabled,
+            was_paused: engine.time_state.paused,
+        };
+        engine.trigger_stats.enable_profiling();
+        // NOTE: pausing keeps triggers that pause the simulation, e.g. for alerts, from cutting
+        // the run short
+        engine.time_state.paused = true;
+        benchmark
+    }
+
+    fn route_queries(engine: &Engine) -> u64 {
+        let totals = engine.alerts.totals();
+        totals.routes_found + totals.route_failures
+    }
+
+    pub fn is_done(&self, engine: &Engine) -> bool {
+        engine.time_state.current_time >= self.end_time
+    }
+
+    /// The fraction of the simulated duration that has been run so far.
+    pub fn progress(&self, engine: &Engine) -> f32 {
+        let done = engine
+            .time_state
+            .current_time
+            .saturating_sub(self.start_time);
+        (done as f64 / (self.end_time - self.start_time).max(1) as f64).min(1.0) as f32
+    }
+
+    /// Run the simulation for up to `budget` of wall time. Returns true once the run is complete.
+    pub fn run_chunk(&mut self, engine: &mut Engine, budget: Duration) -> Result<bool, Error> {
+        if !self.is_done(engine) {
+            let start = Instant::now();
+            let remaining = self.end_time - engine.time_state.current_time;
+            let result = engine.advance_trigger_queue(remaining, budget.as_secs_f64());
+            self.wall_time += start.elapsed();
+            result?;
+        }
+        Ok(self.is_done(engine))
+    }
+
+    /// Stop the run, restoring the engine's profiling and pause settings, and build the report.
+    pub fn finish(self, engine: &mut Engine) -> BenchmarkReport {
+        let report = BenchmarkReport::new(
+            engine.time_state.current_time - self.start_time,
+            self.wall_time,
+            &self.stats_before,
+            &engine.trigger_stats.stats,
+            Self::route_queries(engine) - self.queries_before,
+            peak_memory(),
+        );
+        engine.trigger_stats.profiling_enabled = self.was_profiling;
+        engine.time_state.paused = self.was_paused;
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::benchmark::*;
+
+    fn stats(entries: &[(TriggerKind, &[u128])]) -> TriggerMap<IncrementalStats> {
+        let mut map = TriggerMap::each(|_| IncrementalStats::new());
+        for (kind, durations) in entries {
+            for duration in *durations {
+                map[*kind].observe(*duration);
+            }
+        }
+        map
+    }
+
+    #[test]
+    fn report_from_profile() {
+        let before = stats(&[(TriggerKind::UpdateFields, &[1_000_000_000])]);
+        let after = stats(&[
+            (TriggerKind::UpdateFields, &[1_000_000_000, 500_000_000]),
+            (
+                TriggerKind::AgentRouteStart,
+                &[300_000_000, 300_000_000, 400_000_000],
+            ),
+            (TriggerKind::EvaluateAlerts, &[1_000_000]),
+        ]);
+
+        let report = BenchmarkReport::new(
+            86400,
+            Duration::from_secs(4),
+            &before,
+            &after,
+            3,
+            Some(64 * 1048576),
+        );
+
+        // only what ran during the benchmark counts, most expensive first
+        assert_eq!(
+            report.triggers,
+            vec![
+                TriggerReport {
+                    kind: TriggerKind::AgentRouteStart,
+                    count: 3,
+                    total: Duration::from_secs(1),
+                },
+                TriggerReport {
+                    kind: TriggerKind::UpdateFields,
+                    count: 1,
+                    total: Duration::from_millis(500),
+                },
+                TriggerReport {
+                    kind: TriggerKind::EvaluateAlerts,
+                    count: 1,
+                    total: Duration::from_millis(1),
+                },
+            ]
+        );
+        assert_eq!(report.sim_rate(), 21600.0);
+
+        let text = report.to_text();
+        assert!(text.contains("Sim rate: 21600.0x"), "{}", text);
+        assert!(text.contains("Peak memory: 64.0 MiB"), "{}", text);
+        assert!(text.contains("  AgentRouteStart: 3, 1.000 s"), "{}", text);
+    }
+}
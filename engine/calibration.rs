@@ -0,0 +1,374 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use highway::timing::HighwayTiming;
+use route::WorldState;
+
+use crate::engine::{Engine, Error};
+
+const SECONDS_PER_HOUR: u64 = 60 * 60;
+const HOURS_PER_DAY: u32 = (state::TRAFFIC_HISTORY_PERIOD / SECONDS_PER_HOUR) as u32;
+
+/**
+ * Real-world traffic counts for one highway segment. The segment is given either by its id, as
+ * returned by SegmentHandle::inner, or by the OSM way it was imported from, in which case the counts
+ * apply to every segment the way ended up in.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SegmentObservation {
+    #[serde(default)]
+    pub segment: Option<u64>,
+    #[serde(default)]
+    pub osm_way: Option<u64>,
+    /// observed vehicles per hour in one direction, keyed by the hour of the day
+    pub hourly_counts: BTreeMap<u32, f64>,
+}
+
+/**
+ * The mapping from OSM ways to the highway segments they were baked into, as written by the
+ * importer when it is given an id map path.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OsmIdMap {
+    pub highway_segments: HashMap<u64, Vec<u64>>,
+}
+
+impl OsmIdMap {
+    pub fn load(data: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(data)?)
+    }
+
+    pub fn load_file(path: &std::path::Path) -> Result<Self, Error> {
+        Self::load(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// A set of real-world traffic counts to compare the simulation against.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Observations {
+    pub segments: Vec<SegmentObservation>,
+    /// needed to resolve observations that are given by OSM way
+    #[serde(skip)]
+    pub id_map: Option<OsmIdMap>,
+}
+
+impl Observations {
+    pub fn load(data: &str) -> Result<Self, Error> {
+        let observations: Self = serde_json::from_str(data)?;
+        observations.validate()?;
+        Ok(observations)
+    }
+
+    pub fn load_file(path: &std::path::Path) -> Result<Self, Error> {
+        Self::load(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn with_id_map(mut self, id_map: OsmIdMap) -> Self {
+        self.id_map = Some(id_map);
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        for observation in &self.segments {
+            if observation.segment.is_some() == observation.osm_way.is_some() {
+                return Err(Error::CalibrationError(String::from(
+                    "each observation needs exactly one of segment or osm_way",
+                )));
+            }
+            for (hour, count) in &observation.hourly_counts {
+                if *hour >= HOURS_PER_DAY {
+                    return Err(Error::CalibrationError(format!("invalid hour: {}", hour)));
+                }
+                if !count.is_finite() || *count < 0.0 {
+                    return Err(Error::CalibrationError(format!(
+                        "invalid count for hour {}: {}",
+                        hour, count
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Mean absolute percentage error and root mean square error between observed and simulated counts.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ErrorMetrics {
+    /// as a percentage; only hours with a non-zero observed count contribute, so this is None if
+    /// there are none
+    pub mape: Option<f64>,
+    /// in vehicles per hour
+    pub rmse: f64,
+    pub samples: usize,
+}
+
+impl ErrorMetrics {
+    /// Computes the metrics from (observed, simulated) pairs.
+    pub fn new(pairs: impl IntoIterator<Item = (f64, f64)>) -> Self {
+        let mut samples = 0;
+        let mut squared_error = 0.0;
+        let mut percentage_error = 0.0;
+        let mut percentage_samples = 0;
+        for (observed, simulated) in pairs {
+            samples += 1;
+            squared_error += (simulated - observed).powi(2);
+            if observed > 0.0 {
+                percentage_error += (simulated - observed).abs() / observed;
+                percentage_samples += 1;
+            }
+        }
+        Self {
+            mape: (percentage_samples > 0)
+                .then(|| percentage_error / percentage_samples as f64 * 100.0),
+            rmse: if samples > 0 {
+                (squared_error / samples as f64).sqrt()
+            } else {
+                0.0
+            },
+            samples,
+        }
+    }
+}
+
+/// An observed hourly count alongside the simulated count for the same segment and hour.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationSample {
+    pub segment: network::SegmentHandle,
+    pub hour: u32,
+    pub observed: f64,
+    pub simulated: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentCalibration {
+    pub segment: network::SegmentHandle,
+    pub metrics: ErrorMetrics,
+    pub observed_total: f64,
+    pub simulated_total: f64,
+}
+
+impl SegmentCalibration {
+    /**
+     * Simulated traffic as a fraction of observed traffic over all observed hours, so above 1 when
+     * the simulated segment is too busy. None if nothing was observed.
+     */
+    pub fn ratio(&self) -> Option<f64> {
+        (self.observed_total > 0.0).then(|| self.simulated_total / self.observed_total)
+    }
+}
+
+/// How well simulated traffic matches a set of observations.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CalibrationReport {
+    /// ordered by segment, then hour
+    pub samples: Vec<CalibrationSample>,
+    /// ordered by segment
+    pub segments: Vec<SegmentCalibration>,
+    pub aggregate: ErrorMetrics,
+    /// observations whose segment or OSM way could not be found in the map
+    pub unresolved: Vec<SegmentObservation>,
+}
+
+impl CalibrationReport {
+    pub fn segment(&self, segment: network::SegmentHandle) -> Option<&SegmentCalibration> {
+        self.segments
+            .binary_search_by_key(&segment, |calibration| calibration.segment)
+            .ok()
+            .map(|i| &self.segments[i])
+    }
+
+    /// One row per sample, with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("segment,hour,observed,simulated\n");
+        for sample in &self.samples {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                sample.segment.inner(),
+                sample.hour,
+                sample.observed,
+                sample.simulated,
+            ));
+        }
+        csv
+    }
+}
+
+impl Engine {
+    /**
+     * The predicted number of vehicles per hour passing through a highway segment in the middle of
+     * the given hour of the day, based on the traffic history. None if the segment doesn't exist.
+     */
+    pub fn simulated_hourly_flow(&self, segment: network::SegmentHandle, hour: u32) -> Option<f64> {
+        let highway_segment = self.state.highways.segments().get(&segment)?;
+        let time = hour as u64 * SECONDS_PER_HOUR + SECONDS_PER_HOUR / 2;
+        let travelers = self
+            .world_state_history
+            .get_predictor(time)
+            .get_highway_segment_travelers(segment);
+        if travelers <= 0.0 {
+            return Some(0.0);
+        }
+
+        let people_per_sim = self.state.config.people_per_sim;
+        let travel_time = highway_segment.congested_travel_time(
+            self.state.config.min_tile_size,
+            people_per_sim,
+            travelers,
+        );
+        Some(travelers * people_per_sim * SECONDS_PER_HOUR as f64 / travel_time)
+    }
+
+    /**
+     * Compare observed traffic counts against the traffic history at the matching hours.
+     * Observations that don't match any active highway segment are listed in the report rather
+     * than treated as an error, since real-world data rarely lines up with the map exactly.
+     */
+    pub fn calibration_report(
+        &self,
+        observations: &Observations,
+    ) -> Result<CalibrationReport, Error> {
+        observations.validate()?;
+
+        let segment_ids: HashMap<u64, network::SegmentHandle> = self
+            .state
+            .highways
+            .segments()
+            .iter()
+            .filter(|(_, segment)| segment.change_state.is_active())
+            .map(|(handle, _)| (handle.inner(), *handle))
+            .collect();
+
+        let mut by_segment: BTreeMap<network::SegmentHandle, Vec<CalibrationSample>> =
+            BTreeMap::new();
+        let mut unresolved = Vec::new();
+        for observation in &observations.segments {
+            let ids = match (observation.segment, observation.osm_way) {
+                (Some(segment), _) => vec![segment],
+                (None, Some(way)) => observations
+                    .id_map
+                    .iter()
+                    .flat_map(|id_map| id_map.highway_segments.get(&way))
+                    .flatten()
+                    .copied()
+                    .collect(),
+                (None, None) => vec![],
+            };
+            let segments: Vec<_> = ids
+                .iter()
+                .filter_map(|id| segment_ids.get(id).copied())
+                .collect();
+            if segments.is_empty() {
+                unresolved.push(observation.clone());
+                continue;
+            }
+
+            for segment in segments {
+                for (hour, observed) in &observation.hourly_counts {
+                    let simulated = self.simulated_hourly_flow(segment, *hour).unwrap();
+                    by_segment
+                        .entry(segment)
+                        .or_default()
+                        .push(CalibrationSample {
+                            segment,
+                            hour: *hour,
+                            observed: *observed,
+                            simulated,
+                        });
+                }
+            }
+        }
+
+        let mut report = CalibrationReport {
+            unresolved,
+            ..Default::default()
+        };
+        for (segment, mut samples) in by_segment {
+            samples.sort_by_key(|sample| sample.hour);
+            report.segments.push(SegmentCalibration {
+                segment,
+                metrics: ErrorMetrics::new(
+                    samples
+                        .iter()
+                        .map(|sample| (sample.observed, sample.simulated)),
+                ),
+                observed_total: samples.iter().map(|sample| sample.observed).sum(),
+                simulated_total: samples.iter().map(|sample| sample.simulated).sum(),
+            });
+            report.samples.extend(samples);
+        }
+        report.aggregate = ErrorMetrics::new(
+            report
+                .samples
+                .iter()
+                .map(|sample| (sample.observed, sample.simulated)),
+        );
+        Ok(report)
+    }
+
+    /// Write the samples of a calibration report as CSV.
+    pub fn export_calibration_csv(
+        &self,
+        path: &std::path::Path,
+        observations: &Observations,
+    ) -> Result<(), Error> {
+        std::fs::write(path, self.calibration_report(observations)?.to_csv())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calibration::*;
+
+    #[test]
+    fn exact_match() {
+        let metrics = ErrorMetrics::new(vec![(10.0, 10.0), (0.0, 0.0), (250.0, 250.0)]);
+        assert_eq!(metrics.mape, Some(0.0));
+        assert_eq!(metrics.rmse, 0.0);
+        assert_eq!(metrics.samples, 3);
+    }
+
+    #[test]
+    fn scaled_observations() {
+        let simulated = [10.0, 40.0, 250.0];
+
+        // simulating half of what was observed is off by 50%
+        let metrics = ErrorMetrics::new(simulated.iter().map(|s| (s * 2.0, *s)));
+        assert!((metrics.mape.unwrap() - 50.0).abs() < 1e-9);
+
+        // and simulating double is off by 100%
+        let metrics = ErrorMetrics::new(simulated.iter().map(|s| (s / 2.0, *s)));
+        assert!((metrics.mape.unwrap() - 100.0).abs() < 1e-9);
+        let expected_rmse = (simulated.iter().map(|s| (s / 2.0).powi(2)).sum::<f64>() / 3.0).sqrt();
+        assert!((metrics.rmse - expected_rmse).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nothing_observed() {
+        let metrics = ErrorMetrics::new(vec![(0.0, 5.0)]);
+        assert_eq!(metrics.mape, None);
+        assert_eq!(metrics.rmse, 5.0);
+
+        let metrics = ErrorMetrics::new(vec![]);
+        assert_eq!(metrics, ErrorMetrics::default());
+    }
+
+    #[test]
+    fn invalid_observations() {
+        let valid = r#"{"segments": [{"segment": 3, "hourly_counts": {"7": 100.0}}]}"#;
+        let observations = Observations::load(valid).unwrap();
+        assert_eq!(observations.segments[0].hourly_counts[&7], 100.0);
+
+        for invalid in [
+            r#"{"segments": [{"segment": 3, "hourly_counts": {"24": 100.0}}]}"#,
+            r#"{"segments": [{"segment": 3, "hourly_counts": {"7": -1.0}}]}"#,
+            r#"{"segments": [{"hourly_counts": {"7": 100.0}}]}"#,
+            r#"{"segments": [{"segment": 3, "osm_way": 4, "hourly_counts": {}}]}"#,
+        ] {
+            assert!(Observations::load(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
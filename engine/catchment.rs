@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use state::{BranchState, LeafState};
+
+use crate::engine::{Engine, Error};
+use crate::fields::FieldsState;
+
+/// The people and jobs within walking distance of a single station.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StationCatchment {
+    pub station: metro::Station,
+    pub people: usize,
+    pub jobs: usize,
+    /// people that are closer to this station than to any other, so that they are only counted once
+    pub unique_people: usize,
+    /// jobs that are closer to this station than to any other, so that they are only counted once
+    pub unique_jobs: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct StationCatchments {
+    pub max_walk_minutes: f64,
+    /// one entry for each station, in the order that they first appear on the metro lines
+    pub stations: Vec<StationCatchment>,
+}
+
+impl StationCatchments {
+    pub fn get(&self, address: quadtree::Address) -> Option<&StationCatchment> {
+        self.stations
+            .iter()
+            .find(|catchment| catchment.station.address == address)
+    }
+
+    /// The most people in the catchment of any one station, e.g. for scaling colors.
+    pub fn max_people(&self) -> usize {
+        self.stations
+            .iter()
+            .map(|catchment| catchment.people)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// One row per station, with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("station,x,y,people,jobs,unique_people,unique_jobs\n");
+        for catchment in &self.stations {
+            let (x, y) = catchment.station.address.to_xy();
+            csv.push_str(&format!(
+                "\"{}\",{},{},{},{},{},{}\n",
+                catchment.station.name.replace('"', "\"\""),
+                x,
+                y,
+                catchment.people,
+                catchment.jobs,
+                catchment.unique_people,
+                catchment.unique_jobs,
+            ));
+        }
+        csv
+    }
+}
+
+/// Everything that the catchments depend on. Both the base graph and the fields change slowly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CatchmentKey {
+    graph_version: u64,
+    fields_version: u64,
+    /// f64 doesn't implement Eq, so this is stored as bits
+    max_walk_minutes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CatchmentCache(Option<(CatchmentKey, Arc<StationCatchments>)>);
+
+struct CatchmentLeaf {
+    address: quadtree::Address,
+    people: usize,
+    jobs: usize,
+}
+
+struct CollectCatchmentLeavesVisitor {
+    leaves: Vec<CatchmentLeaf>,
+}
+
+impl quadtree::Visitor<BranchState<FieldsState>, LeafState<FieldsState>, Error>
+    for CollectCatchmentLeavesVisitor
+{
+    fn visit_branch_pre(
+        &mut self,
+        _branch: &BranchState<FieldsState>,
+        _data: &quadtree::VisitData,
+    ) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn visit_leaf(
+        &mut self,
+        leaf: &LeafState<FieldsState>,
+        data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        self.leaves.push(CatchmentLeaf {
+            address: data.address,
+            people: leaf.fields.population.people.total,
+            jobs: leaf.fields.employment.jobs.total,
+        });
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &BranchState<FieldsState>,
+        _data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Engine {
+    /**
+     * The people and jobs within the given walking time of each station that is served by a metro
+     * line. Each leaf is included if its center can be reached in time, walking along the network
+     * from the station and then straight to the leaf. This uses the computed fields, so make sure
+     * that update_fields has been called. Results are cached until the base graph or the fields
+     * change.
+     */
+    pub fn station_catchments(
+        &mut self,
+        max_walk_minutes: f64,
+    ) -> Result<Arc<StationCatchments>, Error> {
+        let key = CatchmentKey {
+            graph_version: self.base_graph.read().unwrap().version(),
+            fields_version: self.fields_version,
+            max_walk_minutes: max_walk_minutes.to_bits(),
+        };
+        if let Some((cached_key, catchments)) = &self.catchment_cache.0 {
+            if *cached_key == key {
+                return Ok(catchments.clone());
+            }
+        }
+
+        let catchments = Arc::new(self.calculate_station_catchments(max_walk_minutes)?);
+        self.catchment_cache.0 = Some((key, catchments.clone()));
+        Ok(catchments)
+    }
+
+    fn calculate_station_catchments(
+        &self,
+        max_walk_minutes: f64,
+    ) -> Result<StationCatchments, Error> {
+        let _span = tracing::debug_span!("station_catchments", max_walk_minutes).entered();
+
+        let max_walk = max_walk_minutes * 60.0;
+        let walking_speed = route::Mode::Walking.linear_speed();
+
+        let mut seen = HashSet::new();
+        let stations: Vec<metro::Station> = self
+            .state
+            .metros
+            .metro_lines()
+            .values()
+            .flat_map(|metro_line| metro_line.stations(&self.state.railways))
+            .filter(|station| seen.insert(station.address))
+            .cloned()
+            .collect();
+
+        let base_graph = self.base_graph.write().unwrap();
+        // TODO: this is necessary to make sure the base graph is constructed
+        let _ = base_graph.get_base_graph(&self.state);
+        let graph = base_graph.get_thread_base_graph();
+        // the farthest anyone can walk, in tiles
+        let radius = max_walk * walking_speed / graph.tile_size;
+        let width = self.state.qtree.width();
+
+        let mut catchments = Vec::with_capacity(stations.len());
+        // for each leaf in any catchment, the index of the closest station and the walking time
+        let mut closest: HashMap<quadtree::Address, (usize, f64)> = HashMap::new();
+        let mut totals: HashMap<quadtree::Address, (usize, usize)> = HashMap::new();
+
+        for (i, station) in stations.into_iter().enumerate() {
+            let nodes = route::calculate_mode_only_travel_times(
+                &graph,
+                station.address,
+                route::Mode::Walking,
+                max_walk,
+            )?;
+
+            let mut catchment = StationCatchment {
+                station,
+                people: 0,
+                jobs: 0,
+                unique_people: 0,
+                unique_jobs: 0,
+            };
+
+            if !nodes.is_empty() {
+                let clamp = |v: f64| (v.max(0.0) as u64).min(width);
+                let (xs, ys): (Vec<_>, Vec<_>) =
+                    nodes.iter().map(|(location, _)| *location).unzip();
+                let min = |vs: &[f64]| vs.iter().copied().fold(f64::INFINITY, f64::min);
+                let max = |vs: &[f64]| vs.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                let bounds = quadtree::Rect::corners(
+                    clamp(min(&xs) - radius),
+                    clamp(min(&ys) - radius),
+                    clamp((max(&xs) + radius).ceil()),
+                    clamp((max(&ys) + radius).ceil()),
+                );
+
+                let mut visitor = CollectCatchmentLeavesVisitor { leaves: Vec::new() };
+                self.state.qtree.visit_rect(&mut visitor, &bounds)?;
+
+                for leaf in visitor.leaves {
+                    let (x, y) = leaf.address.to_xy_f64();
+                    let walk = nodes
+                        .iter()
+                        .map(|((node_x, node_y), time)| {
+                            let distance = ((x - node_x).powi(2) + (y - node_y).powi(2)).sqrt();
+                            time + distance * graph.tile_size / walking_speed
+                        })
+                        .fold(f64::INFINITY, f64::min);
+                    if walk > max_walk {
+                        continue;
+                    }
+
+                    catchment.people += leaf.people;
+                    catchment.jobs += leaf.jobs;
+                    totals.insert(leaf.address, (leaf.people, leaf.jobs));
+                    let entry = closest.entry(leaf.address).or_insert((i, walk));
+                    if walk < entry.1 {
+                        *entry = (i, walk);
+                    }
+                }
+            }
+
+            catchments.push(catchment);
+        }
+
+        for (address, (i, _)) in closest {
+            let (people, jobs) = totals[&address];
+            catchments[i].unique_people += people;
+            catchments[i].unique_jobs += jobs;
+        }
+
+        Ok(StationCatchments {
+            max_walk_minutes,
+            stations: catchments,
+        })
+    }
+}
@@ -0,0 +1,409 @@
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{Engine, Error};
+
+/// Identifies a change set opened with Engine::begin_change_set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChangeSetHandle(u64);
+
+/// A metro line that will be added when the change set is committed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedMetroLine {
+    pub data: metro::MetroLineData,
+    pub segments: Vec<network::SegmentHandle>,
+}
+
+/// An existing metro line that will be moved onto different railway segments when the change set
+/// is committed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedMetroLineReroute {
+    pub metro_line: metro::MetroLineHandle,
+    pub segments: Vec<network::SegmentHandle>,
+}
+
+/// A single edit that is part of a change set, in the order that it was staged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StagedChange {
+    AddHighwaySegment(network::SegmentHandle),
+    RemoveHighwaySegment(network::SegmentHandle),
+    AddRailwaySegment(network::SegmentHandle),
+    RemoveRailwaySegment(network::SegmentHandle),
+    /// NOTE: metro lines don't have a change state, so they aren't added to the metros until the
+    /// change set is committed
+    AddMetroLine(StagedMetroLine),
+    /// NOTE: like AddMetroLine, the metro line isn't changed until the change set is committed
+    RerouteMetroLine(StagedMetroLineReroute),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Add,
+    Remove,
+}
+
+/**
+ * The staged geometry of a change set, for rendering planned changes. The segments are already
+ * part of the networks, but they don't affect the simulation until the change set is committed.
+ */
+#[derive(Debug)]
+pub struct ChangeSetPreview<'a> {
+    pub changes: &'a [StagedChange],
+    pub highway_segments: Vec<(&'a network::Segment<highway::HighwaySegment>, ChangeKind)>,
+    pub railway_segments: Vec<(&'a network::Segment<metro::RailwaySegment>, ChangeKind)>,
+    pub metro_lines: Vec<&'a StagedMetroLine>,
+    pub rerouted_metro_lines: Vec<&'a StagedMetroLineReroute>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct ChangeSets {
+    open: Option<(ChangeSetHandle, Vec<StagedChange>)>,
+    counter: u64,
+}
+
+impl Engine {
+    /**
+     * Start staging a set of network edits. Staged edits are recorded in the networks using the
+     * existing change states, so they can be rendered, but they don't affect routing until the
+     * change set is committed. Only one change set can be open at a time.
+     */
+    pub fn begin_change_set(&mut self) -> Result<ChangeSetHandle, Error> {
+        if let Some((handle, _)) = &self.change_sets.open {
+            return Err(Error::ChangeSetAlreadyOpen(*handle));
+        }
+        let handle = ChangeSetHandle(self.change_sets.counter);
+        self.change_sets.counter += 1;
+        self.change_sets.open = Some((handle, Vec::new()));
+        Ok(handle)
+    }
+
+    /// The currently open change set, if any.
+    pub fn open_change_set(&self) -> Option<ChangeSetHandle> {
+        self.change_sets.open.as_ref().map(|(handle, _)| *handle)
+    }
+
+    pub(crate) fn staged_changes(
+        &self,
+        handle: ChangeSetHandle,
+    ) -> Result<&Vec<StagedChange>, Error> {
+        match &self.change_sets.open {
+            Some((open, changes)) if *open == handle => Ok(changes),
+            _ => Err(Error::ChangeSetNotOpen(handle)),
+        }
+    }
+
+    fn staged_changes_mut(
+        &mut self,
+        handle: ChangeSetHandle,
+    ) -> Result<&mut Vec<StagedChange>, Error> {
+        match &mut self.change_sets.open {
+            Some((open, changes)) if *open == handle => Ok(changes),
+            _ => Err(Error::ChangeSetNotOpen(handle)),
+        }
+    }
+
+    /// Stage a new highway junction, e.g. as an endpoint for staged_add_highway_segment.
+    pub fn staged_add_highway_junction(
+        &mut self,
+        handle: ChangeSetHandle,
+        location: (f64, f64),
+        data: highway::HighwayJunction,
+    ) -> Result<network::JunctionHandle, Error> {
+        self.staged_changes(handle)?;
+        Ok(self.state.highways.add_staged_junction(location, data))
+    }
+
+    pub fn staged_add_highway_segment(
+        &mut self,
+        handle: ChangeSetHandle,
+        data: highway::HighwaySegment,
+        start: network::JunctionHandle,
+        end: network::JunctionHandle,
+        keys: Option<Vec<network::Key>>,
+    ) -> Result<network::SegmentHandle, Error> {
+        self.staged_changes(handle)?;
+        let segment = self
+            .state
+            .highways
+            .add_staged_segment(data, start, end, keys);
+        self.staged_changes_mut(handle)?
+            .push(StagedChange::AddHighwaySegment(segment));
+        Ok(segment)
+    }
+
+    pub fn staged_remove_highway_segment(
+        &mut self,
+        handle: ChangeSetHandle,
+        segment: network::SegmentHandle,
+    ) -> Result<(), Error> {
+        self.staged_changes(handle)?;
+        match self.state.highways.segments().get(&segment) {
+            Some(data) if data.change_state == network::ChangeState::Active => (),
+            _ => return Err(Error::InvalidHighwaySegment(segment)),
+        }
+        self.state.highways.stage_segment_removal(segment);
+        self.staged_changes_mut(handle)?
+            .push(StagedChange::RemoveHighwaySegment(segment));
+        Ok(())
+    }
+
+    /// Stage a new railway junction, e.g. as an endpoint for staged_add_railway_segment.
+    pub fn staged_add_railway_junction(
+        &mut self,
+        handle: ChangeSetHandle,
+        location: (f64, f64),
+        data: metro::RailwayJunction,
+    ) -> Result<network::JunctionHandle, Error> {
+        self.staged_changes(handle)?;
+        Ok(self.state.railways.add_staged_junction(location, data))
+    }
+
+    pub fn staged_add_railway_segment(
+        &mut self,
+        handle: ChangeSetHandle,
+        data: metro::RailwaySegment,
+        start: network::JunctionHandle,
+        end: network::JunctionHandle,
+        keys: Option<Vec<network::Key>>,
+    ) -> Result<network::SegmentHandle, Error> {
+        self.staged_changes(handle)?;
+        let segment = self
+            .state
+            .railways
+            .add_staged_segment(data, start, end, keys);
+        self.staged_changes_mut(handle)?
+            .push(StagedChange::AddRailwaySegment(segment));
+        Ok(segment)
+    }
+
+    /// Railway segments that are used by a metro line can't be removed.
+    pub fn staged_remove_railway_segment(
+        &mut self,
+        handle: ChangeSetHandle,
+        segment: network::SegmentHandle,
+    ) -> Result<(), Error> {
+        self.staged_changes(handle)?;
+        match self.state.railways.segments().get(&segment) {
+            Some(data) if data.change_state == network::ChangeState::Active => (),
+            _ => {
+                return Err(Error::InvalidStagedChange(format!(
+                    "railway segment {:?} is not active",
+                    segment
+                )))
+            }
+        }
+        if !self
+            .state
+            .metros
+            .railway_segment_metro_lines(segment)
+            .is_empty()
+        {
+            return Err(Error::InvalidStagedChange(format!(
+                "railway segment {:?} is used by a metro line",
+                segment
+            )));
+        }
+        self.state.railways.stage_segment_removal(segment);
+        self.staged_changes_mut(handle)?
+            .push(StagedChange::RemoveRailwaySegment(segment));
+        Ok(())
+    }
+
+    /**
+     * Stage a new metro line along the given railway segments, which may themselves be staged. The
+     * segments must not be staged for removal.
+     */
+    pub fn staged_add_metro_line(
+        &mut self,
+        handle: ChangeSetHandle,
+        data: metro::MetroLineData,
+        segments: Vec<network::SegmentHandle>,
+    ) -> Result<(), Error> {
+        self.staged_changes(handle)?;
+        for segment in &segments {
+            match self.state.railways.segments().get(segment) {
+                Some(data) if data.change_state.is_staged_active() => (),
+                _ => {
+                    return Err(Error::InvalidStagedChange(format!(
+                        "railway segment {:?} won't exist once the change set is committed",
+                        segment
+                    )))
+                }
+            }
+        }
+        self.staged_changes_mut(handle)?
+            .push(StagedChange::AddMetroLine(StagedMetroLine {
+                data,
+                segments,
+            }));
+        Ok(())
+    }
+
+    /**
+     * Stage moving an existing metro line onto the given railway segments, which may themselves be
+     * staged. The segments must not be staged for removal, and must connect end to end.
+     */
+    pub fn staged_reroute_metro_line(
+        &mut self,
+        handle: ChangeSetHandle,
+        metro_line: metro::MetroLineHandle,
+        segments: Vec<network::SegmentHandle>,
+    ) -> Result<(), Error> {
+        self.staged_changes(handle)?;
+        if self.state.metros.try_metro_line(metro_line).is_none() {
+            return Err(Error::InvalidStagedChange(format!(
+                "metro line {:?} does not exist",
+                metro_line
+            )));
+        }
+        if segments.is_empty() {
+            return Err(metro::Error::EmptyLine(metro_line).into());
+        }
+        for segment in &segments {
+            match self.state.railways.segments().get(segment) {
+                Some(data) if data.change_state.is_staged_active() => (),
+                _ => {
+                    return Err(Error::InvalidStagedChange(format!(
+                        "railway segment {:?} won't exist once the change set is committed",
+                        segment
+                    )))
+                }
+            }
+        }
+        metro::orient_segments(&segments, &self.state.railways)?;
+        self.staged_changes_mut(handle)?
+            .push(StagedChange::RerouteMetroLine(StagedMetroLineReroute {
+                metro_line,
+                segments,
+            }));
+        Ok(())
+    }
+
+    /**
+     * Remove the staged change at the given index, undoing its effect on the networks. Railway
+     * segments can't be removed while a staged metro line or reroute still uses them.
+     */
+    pub fn unstage(
+        &mut self,
+        handle: ChangeSetHandle,
+        index: usize,
+    ) -> Result<StagedChange, Error> {
+        let changes = self.staged_changes(handle)?;
+        let change = changes.get(index).cloned().ok_or_else(|| {
+            Error::InvalidStagedChange(format!("no staged change at index {}", index))
+        })?;
+        if let StagedChange::AddRailwaySegment(segment) = &change {
+            let in_use = changes.iter().any(|change| match change {
+                StagedChange::AddMetroLine(metro_line) => metro_line.segments.contains(segment),
+                StagedChange::RerouteMetroLine(reroute) => reroute.segments.contains(segment),
+                _ => false,
+            });
+            if in_use {
+                return Err(Error::InvalidStagedChange(format!(
+                    "railway segment {:?} is used by a staged metro line",
+                    segment
+                )));
+            }
+        }
+
+        match change {
+            StagedChange::AddHighwaySegment(segment)
+            | StagedChange::RemoveHighwaySegment(segment) => {
+                self.state.highways.unstage_segment(segment)
+            }
+            StagedChange::AddRailwaySegment(segment)
+            | StagedChange::RemoveRailwaySegment(segment) => {
+                self.state.railways.unstage_segment(segment)
+            }
+            StagedChange::AddMetroLine(_) | StagedChange::RerouteMetroLine(_) => (),
+        }
+
+        Ok(self.staged_changes_mut(handle)?.remove(index))
+    }
+
+    pub fn preview(&self, handle: ChangeSetHandle) -> Result<ChangeSetPreview<'_>, Error> {
+        let changes = self.staged_changes(handle)?;
+        let mut preview = ChangeSetPreview {
+            changes,
+            highway_segments: Vec::new(),
+            railway_segments: Vec::new(),
+            metro_lines: Vec::new(),
+            rerouted_metro_lines: Vec::new(),
+        };
+
+        for change in changes {
+            match change {
+                StagedChange::AddHighwaySegment(segment) => preview
+                    .highway_segments
+                    .push((self.state.highways.segment(*segment), ChangeKind::Add)),
+                StagedChange::RemoveHighwaySegment(segment) => preview
+                    .highway_segments
+                    .push((self.state.highways.segment(*segment), ChangeKind::Remove)),
+                StagedChange::AddRailwaySegment(segment) => preview
+                    .railway_segments
+                    .push((self.state.railways.segment(*segment), ChangeKind::Add)),
+                StagedChange::RemoveRailwaySegment(segment) => preview
+                    .railway_segments
+                    .push((self.state.railways.segment(*segment), ChangeKind::Remove)),
+                StagedChange::AddMetroLine(metro_line) => preview.metro_lines.push(metro_line),
+                StagedChange::RerouteMetroLine(reroute) => {
+                    preview.rerouted_metro_lines.push(reroute)
+                }
+            }
+        }
+
+        Ok(preview)
+    }
+
+    /**
+     * Apply every staged change at once. The routing graph is only rebuilt once, the next time it
+     * is needed.
+     */
+    pub fn commit_change_set(&mut self, handle: ChangeSetHandle) -> Result<(), Error> {
+        self.staged_changes(handle)?;
+        let (_, changes) = self.change_sets.open.take().unwrap();
+
+        // NOTE: this also clears the base graph
+        self.apply_change_set();
+
+        for change in changes {
+            match change {
+                StagedChange::AddMetroLine(StagedMetroLine { data, segments }) => {
+                    self.state
+                        .metros
+                        .add_metro_line(data, segments, &self.state.railways);
+                }
+                StagedChange::RerouteMetroLine(StagedMetroLineReroute {
+                    metro_line,
+                    segments,
+                }) => {
+                    self.reroute_metro_line(metro_line, segments)?;
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Undo every staged change, leaving the networks as they were before the change set.
+    pub fn discard_change_set(&mut self, handle: ChangeSetHandle) -> Result<(), Error> {
+        self.staged_changes(handle)?;
+        self.change_sets.open = None;
+
+        // staged items are never part of the base graph, so it doesn't need to be rebuilt
+        self.state.highways.clear_change_set();
+        self.state.railways.clear_change_set();
+
+        Ok(())
+    }
+
+    /**
+     * A copy of the engine with the change set committed, to see how the changes would play out
+     * without affecting this engine.
+     */
+    pub fn fork_with_change_set(&self, handle: ChangeSetHandle) -> Result<Engine, Error> {
+        let mut fork = self.clone();
+        fork.commit_change_set(handle)?;
+        Ok(fork)
+    }
+}
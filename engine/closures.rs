@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use uom::si::time::hour;
+use uom::si::u64::Time;
+
+use crate::engine::{Engine, Error};
+
+/// A segment of either network. Highways and railways number their segments separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum NetworkSegment {
+    Highway(network::SegmentHandle),
+    Railway(network::SegmentHandle),
+}
+
+impl NetworkSegment {
+    /// Whether traveling along the edge means traveling along this segment.
+    pub fn is_used_by(&self, edge: &route::Edge) -> bool {
+        match (self, edge) {
+            (Self::Highway(id), route::Edge::Highway { segment, .. }) => segment == id,
+            (
+                Self::Railway(id),
+                route::Edge::MetroSegment {
+                    oriented_segment, ..
+                },
+            ) => oriented_segment.segment == *id,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for NetworkSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Highway(id) => write!(f, "highway segment {}", id.inner()),
+            Self::Railway(id) => write!(f, "railway segment {}", id.inner()),
+        }
+    }
+}
+
+impl Engine {
+    /// The change state of the segment, or None if it doesn't exist.
+    pub fn segment_change_state(&self, segment: NetworkSegment) -> Option<network::ChangeState> {
+        match segment {
+            NetworkSegment::Highway(id) => self
+                .state
+                .highways
+                .try_segment(id)
+                .map(|segment| segment.change_state),
+            NetworkSegment::Railway(id) => self
+                .state
+                .railways
+                .try_segment(id)
+                .map(|segment| segment.change_state),
+        }
+    }
+
+    fn set_segment_change_state(
+        &mut self,
+        segment: NetworkSegment,
+        change_state: network::ChangeState,
+    ) {
+        let mut base_graph = self.base_graph.write().unwrap();
+        match segment {
+            NetworkSegment::Highway(id) => {
+                self.state.highways.segment_mut(id).change_state = change_state
+            }
+            NetworkSegment::Railway(id) => {
+                self.state.railways.segment_mut(id).change_state = change_state
+            }
+        }
+        base_graph.clear();
+    }
+
+    /**
+     * Temporarily close a highway or railway segment for the given number of simulated seconds,
+     * e.g. to model an incident. Closed segments are left out of the routing graph, so new routes
+     * avoid them, and agents that still have to travel along the segment find new routes from
+     * wherever they are. The segment reopens on its own once the time is up. Closing a segment
+     * that is already closed extends the closure if it would otherwise reopen sooner.
+     *
+     * Unlike removing the segment with a change set, the segment's traffic history is kept, so
+     * predictions for it pick up where they left off once it reopens.
+     */
+    pub fn close_segment(&mut self, segment: NetworkSegment, duration: u64) -> Result<(), Error> {
+        if duration == 0 {
+            return Err(Error::InvalidClosure(format!(
+                "{} can't be closed for no time",
+                segment
+            )));
+        }
+        let current_time = self.time_state.current_time;
+        let until = match self.segment_change_state(segment) {
+            Some(network::ChangeState::Active) => current_time + duration,
+            Some(network::ChangeState::Closed { until }) => until.max(current_time + duration),
+            Some(change_state) => {
+                return Err(Error::InvalidClosure(format!(
+                    "{} is {:?}",
+                    segment, change_state
+                )))
+            }
+            None => return Err(Error::InvalidClosure(format!("{} does not exist", segment))),
+        };
+
+        self.set_segment_change_state(segment, network::ChangeState::Closed { until });
+        self.trigger_queue
+            .push(crate::behavior::ReopenSegment { segment }, until);
+
+        let rerouted = {
+            let _logs = self.agent_logs.enter();
+            self.reroute_agents_off(segment)?
+        };
+        self.alerts.record_closure(
+            format!(
+                "{} closed until {}; {} agent(s) rerouted",
+                segment,
+                self.time_state.pretty_date_time(until),
+                rerouted
+            ),
+            current_time,
+        );
+
+        Ok(())
+    }
+
+    /// Like close_segment, but for a whole number of hours.
+    pub fn close_segment_for_hours(
+        &mut self,
+        segment: NetworkSegment,
+        hours: u64,
+    ) -> Result<(), Error> {
+        self.close_segment(segment, Time::new::<hour>(hours).value)
+    }
+
+    /**
+     * Reopen a segment that was closed with close_segment, if its closure is over as of the given
+     * time. Closures that were extended since, or segments that have been edited since, are left
+     * alone.
+     */
+    pub(crate) fn reopen_segment(&mut self, segment: NetworkSegment, time: u64) {
+        match self.segment_change_state(segment) {
+            Some(network::ChangeState::Closed { until }) if until <= time => (),
+            _ => return,
+        }
+
+        self.set_segment_change_state(segment, network::ChangeState::Active);
+        self.alerts
+            .record_closure(format!("{} reopened", segment), time);
+    }
+
+    /**
+     * Interrupt the routes of agents that have yet to travel along the segment, and have them find
+     * new routes to the same destinations, starting from the tiles that they are on now. Returns
+     * how many agents were rerouted.
+     */
+    fn reroute_agents_off(&mut self, segment: NetworkSegment) -> Result<usize, Error> {
+        let current_time = self.time_state.current_time;
+        let max_coord = self.state.qtree.width().saturating_sub(1) as f32;
+
+        let mut affected = Vec::new();
+        for (id, agent) in &self.agents {
+            if let agent::AgentState::Route(route_state) = &agent.state {
+                if !route_state
+                    .remaining_edges()
+                    .iter()
+                    .any(|edge| segment.is_used_by(edge))
+                {
+                    continue;
+                }
+                let address = match route_state.sample(current_time, &self.state) {
+                    Some(key) => {
+                        let (x, y) = key.position;
+                        self.state.qtree.get_address(
+                            x.clamp(0.0, max_coord) as u64,
+                            y.clamp(0.0, max_coord) as u64,
+                        )?
+                    }
+                    None => route_state.route.query_input.start,
+                };
+                affected.push((*id, address));
+            }
+        }
+
+        for (id, address) in &affected {
+            let agent = self.agents.get_mut(id).expect("missing agent");
+            agent.log_timestamp(
+                || format!("{} closed; rerouting from {:?}", segment, address),
+                current_time,
+            );
+            let route_state = agent.interrupt_route(*address, &mut self.world_state)?;
+            record_travel!(self, &route_state);
+
+            let query_input = route_state.route.query_input;
+            // the car comes along if the agent was driving it or is going back to get it;
+            // otherwise it stays where it is
+            let car_config = match (query_input.car_config, agent.parked_car()) {
+                (Some(route::CarConfig::CollectParkedCar { .. }), Some(parked_car)) => {
+                    Some(route::CarConfig::CollectParkedCar {
+                        address: parked_car,
+                    })
+                }
+                (Some(route::CarConfig::StartWithCar), Some(parked_car))
+                    if parked_car == *address =>
+                {
+                    Some(route::CarConfig::StartWithCar)
+                }
+                _ => None,
+            };
+            let query_input = route::QueryInput {
+                start: *address,
+                car_config,
+                ..query_input
+            };
+
+            let trigger = crate::behavior::AgentRouteStart::query(
+                self,
+                *id,
+                route_state.route_type,
+                query_input,
+            );
+            let start_time = current_time + self.state.config.scheduling.route_start_deadline;
+            self.trigger_queue.push(trigger, start_time);
+            self.routing_health.record_route_aborted();
+        }
+
+        Ok(affected.len())
+    }
+}
@@ -1,9 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use quadtree::VisitData;
 use state::{BranchState, LeafState};
 
-use crate::engine::Engine;
+use crate::engine::{Engine, Error};
 use crate::fields::FieldsState;
 
 #[derive(thiserror::Error, Debug)]
@@ -20,6 +20,8 @@ pub enum ConsistencyError {
     ParkingError(String),
     #[error("Parking errors: {0:?}")]
     ParkingErrors(Vec<String>),
+    #[error("Non-finite values: {0:?}")]
+    NonFiniteErrors(Vec<String>),
 }
 
 impl Engine {
@@ -32,9 +34,69 @@ impl Engine {
         self.agent_housing_workplace_consistency_check()?;
         self.traffic_consistency_check()?;
         self.parking_consistency_check()?;
+        self.finite_check()?;
         Ok(())
     }
 
+    /**
+     * Resets any NaN or infinite values in the world state, traffic history, and fields to zero.
+     * This is a last resort for recovering from corrupted state; the fields will be recomputed
+     * properly on the next update, but the traffic data is lost. Returns the number of values that
+     * were reset.
+     */
+    pub fn repair_non_finite(&mut self) -> usize {
+        let mut repaired = self.world_state.repair() + self.world_state_history.repair();
+
+        let mut visitor = RepairFieldsVisitor { repaired: 0 };
+        self.state
+            .qtree
+            .visit_mut(&mut visitor)
+            .expect("should be impossible");
+        repaired += visitor.repaired;
+
+        if repaired > 0 {
+            eprintln!("Reset {} non-finite values to zero", repaired);
+        }
+        repaired
+    }
+
+    /**
+     * Drops traffic entries that no longer carry travelers, and entries in the traffic history
+     * for segments that have been removed. Entries in the world state for removed segments that
+     * still carry travelers are kept so that agents on them can leave cleanly, but they are
+     * reported. Returns the stale handles that were found in the world state.
+     */
+    pub fn compact_traffic(&mut self) -> Vec<network::SegmentHandle> {
+        let stale = self
+            .world_state
+            .compact(&self.state.highways, &self.state.railways, false);
+        let dropped = self
+            .world_state_history
+            .compact(&self.state.highways, &self.state.railways);
+        if !stale.is_empty() || dropped > 0 {
+            eprintln!(
+                "Found stale traffic entries for removed segments: {:?}; dropped {} from history",
+                stale, dropped
+            );
+        }
+        stale
+    }
+
+    /**
+     * Moves junctions, segments, and stations that are outside of the map just inside of it; see
+     * State::repair_out_of_bounds. The base graph is rebuilt if anything moved.
+     */
+    pub fn repair_out_of_bounds(&mut self) -> state::BoundsRepairReport {
+        let report = self.state.repair_out_of_bounds();
+        for repair in &report.repairs {
+            eprintln!("Repaired out-of-bounds location: {}", repair);
+        }
+        if !report.is_empty() {
+            self.base_graph.write().unwrap().clear();
+        }
+        report
+    }
+
     fn agent_housing_workplace_consistency_check(&self) -> Result<(), ConsistencyError> {
         let mut find_agents = FindAgentVisitor {
             agents: &self.agents,
@@ -113,25 +175,27 @@ impl Engine {
         let mut world_state_comparison = route::WorldStateImpl::new(&self.state.config);
 
         for agent in self.agents.values() {
-            if let agent::AgentState::Route(agent::AgentRouteState {
-                route,
-                phase: agent::AgentRoutePhase::InProgress { current_edge, .. },
-                ..
-            }) = &agent.state
-            {
-                world_state_comparison
-                    .increment_edge_no_parking(route.edges.get(*current_edge as usize).ok_or_else(
-                        || {
-                            ConsistencyError::TrafficError(format!(
-                                "route edge out of bounds: {}",
-                                current_edge
-                            ))
-                        },
-                    )?)
-                    .expect("should be impossible");
+            if let agent::AgentState::Route(route_state) = &agent.state {
+                let edges = route_state.current_edges().ok_or_else(|| {
+                    ConsistencyError::TrafficError(format!(
+                        "route edge out of bounds: {:?}",
+                        route_state.phase
+                    ))
+                })?;
+                for edge in edges {
+                    world_state_comparison
+                        .increment_edge_no_parking(edge)
+                        .expect("should be impossible");
+                }
             }
         }
 
+        for (segment, count) in self.injected_highway_travelers() {
+            world_state_comparison
+                .add_highway_segment_travelers(*segment, *count)
+                .expect("should be impossible");
+        }
+
         let traffic_errs = self.world_state.check_same_traffic(&world_state_comparison);
         if !traffic_errs.is_empty() {
             return Err(ConsistencyError::TrafficErrors(traffic_errs));
@@ -140,56 +204,203 @@ impl Engine {
         Ok(())
     }
 
-    fn parking_consistency_check(&self) -> Result<(), ConsistencyError> {
-        // re-construct parking state so that we can compare to real world state
-        let mut world_state_comparison = route::WorldStateImpl::new(&self.state.config);
+    fn finite_check(&self) -> Result<(), ConsistencyError> {
+        let mut errors = self.world_state.validate();
+        errors.extend(
+            self.world_state_history
+                .validate()
+                .into_iter()
+                .map(|err| format!("traffic history {}", err)),
+        );
+
+        let mut visitor = FiniteFieldsVisitor { errors: Vec::new() };
+        self.state.qtree.visit(&mut visitor)?;
+        errors.extend(visitor.errors);
+
+        if !errors.is_empty() {
+            return Err(ConsistencyError::NonFiniteErrors(errors));
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Where each car should be parked according to the agents and households that hold them, as a
+     * world state with nothing but parking, along with who holds the cars at each address.
+     */
+    fn claimed_parking(
+        &self,
+    ) -> (
+        route::WorldStateImpl,
+        BTreeMap<quadtree::Address, Vec<String>>,
+    ) {
+        let mut world_state = route::WorldStateImpl::new(&self.state.config);
+        let mut claims: BTreeMap<_, Vec<_>> = BTreeMap::new();
 
         for agent in self.agents.values() {
-            let parked_car = agent.parked_car();
+            if let Some(parked_car) = agent.parked_car() {
+                world_state
+                    .increment_parking(parked_car)
+                    .expect("should be impossible");
+                claims
+                    .entry(parked_car)
+                    .or_default()
+                    .push(format!("agent {}", agent.id));
+            }
+        }
+
+        // household cars that no member has checked out are parked at home
+        for (id, household) in &self.households {
+            for _ in 0..household.available_cars() {
+                world_state
+                    .increment_parking(household.housing)
+                    .expect("should be impossible");
+                claims
+                    .entry(household.housing)
+                    .or_default()
+                    .push(format!("household {}", id));
+            }
+        }
 
+        (world_state, claims)
+    }
+
+    /**
+     * Every car parked in the world state must be held by exactly one agent (or household) that
+     * says it is parked there, and vice versa. Agents' modes of travel must agree with whether
+     * they have their car with them.
+     */
+    fn parking_consistency_check(&self) -> Result<(), ConsistencyError> {
+        for agent in self.agents.values() {
             // make sure current mode of travel is consistent
-            if let agent::AgentState::Route(agent::AgentRouteState {
-                phase: agent::AgentRoutePhase::InProgress { current_mode, .. },
-                ..
-            }) = &agent.state
+            if let agent::AgentState::Route(
+                route_state @ agent::AgentRouteState {
+                    phase: agent::AgentRoutePhase::InProgress { current_mode, .. },
+                    ..
+                },
+            ) = &agent.state
             {
-                if parked_car.is_some() {
-                    if *current_mode == route::Mode::Driving {
-                        return Err(ConsistencyError::ParkingError(
-                            "car is parked but still driving".to_string(),
-                        ));
-                    }
-                } else {
-                    // TODO: we will need to update this check once not all agents have cars
-                    if *current_mode != route::Mode::Driving {
+                if route_state.parked_car.is_some() && *current_mode == route::Mode::Driving {
+                    return Err(ConsistencyError::ParkingError(format!(
+                        "agent {}'s car is parked but still driving",
+                        agent.id
+                    )));
+                }
+                if route_state.is_using_car() {
+                    if *current_mode != route::Mode::Driving
+                        && !route_state.is_looking_for_parking()
+                    {
                         return Err(ConsistencyError::ParkingError(format!(
-                            "car isn't parked but {} instead of driving",
-                            *current_mode
+                            "agent {}'s car isn't parked but {} instead of driving",
+                            agent.id, *current_mode
                         )));
                     }
+                } else if *current_mode == route::Mode::Driving {
+                    return Err(ConsistencyError::ParkingError(format!(
+                        "agent {} is driving without having taken out a car",
+                        agent.id
+                    )));
                 }
             }
+        }
 
-            if let Some(parked_car) = parked_car {
-                // add parking to re-constructed parking state
-                world_state_comparison
-                    .increment_parking(parked_car)
-                    .expect("should be impossible");
+        let (world_state_comparison, claims) = self.claimed_parking();
+
+        let mut parking_errs = Vec::new();
+        let addresses: BTreeSet<_> = self
+            .world_state
+            .parked_cars()
+            .map(|(address, _)| address)
+            .chain(claims.keys().copied())
+            .collect();
+        for address in addresses {
+            let parked = self.world_state.parked_cars_at(address);
+            let holders = claims.get(&address).map_or(&[][..], |holders| &holders[..]);
+            let claimed = holders.len() as u64;
+            if parked > claimed {
+                parking_errs.push(format!(
+                    "{} unclaimed car(s) parked at {:?}; held by {:?}",
+                    parked - claimed,
+                    address,
+                    holders
+                ));
+            } else if parked < claimed {
+                parking_errs.push(format!(
+                    "{} car(s) missing at {:?}; held by {:?}",
+                    claimed - parked,
+                    address,
+                    holders
+                ));
             }
         }
-
-        let parking_errs = self.world_state.check_same_parking(&world_state_comparison);
+        if parking_errs.is_empty() {
+            // the cars are all accounted for, but the parking zones may still disagree
+            parking_errs = self.world_state.check_same_parking(&world_state_comparison);
+        }
         if !parking_errs.is_empty() {
             return Err(ConsistencyError::ParkingErrors(parking_errs));
         }
 
         Ok(())
     }
+
+    /**
+     * Makes the cars parked in the world state match where the agents and households that hold
+     * them say they are (see consistency_check). Unclaimed cars are moved to where claimed cars
+     * are missing, and any left over are removed; claimed cars that are still missing are added.
+     * Each change is logged. Returns the number of cars that were moved, removed, or added.
+     */
+    pub fn repair_parked_cars(&mut self) -> Result<usize, Error> {
+        let (_, claims) = self.claimed_parking();
+
+        let mut unclaimed = Vec::new();
+        let mut missing = Vec::new();
+        let addresses: BTreeSet<_> = self
+            .world_state
+            .parked_cars()
+            .map(|(address, _)| address)
+            .chain(claims.keys().copied())
+            .collect();
+        for address in addresses {
+            let parked = self.world_state.parked_cars_at(address);
+            let claimed = claims
+                .get(&address)
+                .map_or(0, |holders| holders.len() as u64);
+            for _ in claimed..parked {
+                unclaimed.push(address);
+            }
+            for _ in parked..claimed {
+                missing.push((address, &claims[&address]));
+            }
+        }
+
+        let repaired = unclaimed.len().max(missing.len());
+        let mut missing = missing.into_iter();
+        for from in unclaimed {
+            self.world_state.decrement_parking(from)?;
+            match missing.next() {
+                Some((to, holders)) => {
+                    eprintln!(
+                        "Moved unclaimed car parked at {:?} to {:?}, held by {:?}",
+                        from, to, holders
+                    );
+                    self.world_state.increment_parking(to)?;
+                }
+                None => eprintln!("Removed unclaimed car parked at {:?}", from),
+            }
+        }
+        for (to, holders) in missing {
+            eprintln!("Added missing car at {:?}, held by {:?}", to, holders);
+            self.world_state.increment_parking(to)?;
+        }
+
+        Ok(repaired)
+    }
 }
 
 #[derive(Debug, Clone)]
 struct FindAgentVisitor<'a> {
-    agents: &'a HashMap<u64, agent::Agent>,
+    agents: &'a BTreeMap<u64, agent::Agent>,
     housing: HashMap<u64, quadtree::Address>,
     workplaces: HashMap<u64, quadtree::Address>,
 }
@@ -227,7 +438,9 @@ impl<'a> quadtree::Visitor<BranchState<FieldsState>, LeafState<FieldsState>, Con
                     }
                 }
             }
-            tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { density, agents }) => {
+            tiles::Tile::WorkplaceTile(tiles::WorkplaceTile {
+                density, agents, ..
+            }) => {
                 if agents.len() > *density {
                     return Err(ConsistencyError::TileError(format!(
                         "workplace tile at {:?} has too many agents; density: {}, agents: {:?}",
@@ -256,3 +469,129 @@ impl<'a> quadtree::Visitor<BranchState<FieldsState>, LeafState<FieldsState>, Con
         Ok(())
     }
 }
+
+#[derive(Debug, Clone)]
+struct FiniteFieldsVisitor {
+    errors: Vec<String>,
+}
+
+impl FiniteFieldsVisitor {
+    fn check(&mut self, fields: &FieldsState, data: &VisitData) {
+        for field in fields.non_finite_fields() {
+            self.errors.push(format!(
+                "field {} at {:?} is not finite",
+                field, data.address
+            ));
+        }
+    }
+}
+
+impl quadtree::Visitor<BranchState<FieldsState>, LeafState<FieldsState>, ConsistencyError>
+    for FiniteFieldsVisitor
+{
+    fn visit_branch_pre(
+        &mut self,
+        branch: &BranchState<FieldsState>,
+        data: &VisitData,
+    ) -> Result<bool, ConsistencyError> {
+        self.check(&branch.fields, data);
+        Ok(true)
+    }
+
+    fn visit_leaf(
+        &mut self,
+        leaf: &LeafState<FieldsState>,
+        data: &VisitData,
+    ) -> Result<(), ConsistencyError> {
+        self.check(&leaf.fields, data);
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &BranchState<FieldsState>,
+        _data: &VisitData,
+    ) -> Result<(), ConsistencyError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RepairFieldsVisitor {
+    repaired: usize,
+}
+
+impl quadtree::MutVisitor<BranchState<FieldsState>, LeafState<FieldsState>, ConsistencyError>
+    for RepairFieldsVisitor
+{
+    fn visit_branch_pre(
+        &mut self,
+        branch: &mut BranchState<FieldsState>,
+        _data: &VisitData,
+    ) -> Result<bool, ConsistencyError> {
+        self.repaired += branch.fields.repair_non_finite();
+        Ok(true)
+    }
+
+    fn visit_leaf(
+        &mut self,
+        leaf: &mut LeafState<FieldsState>,
+        _data: &VisitData,
+    ) -> Result<(), ConsistencyError> {
+        self.repaired += leaf.fields.repair_non_finite();
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &mut BranchState<FieldsState>,
+        _data: &VisitData,
+    ) -> Result<(), ConsistencyError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consistency::ConsistencyError;
+    use crate::Engine;
+
+    #[test]
+    fn non_finite_fields() {
+        let mut engine = Engine::new(state::Config {
+            max_depth: 3,
+            people_per_sim: 1.0,
+            min_tile_size: 1,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        });
+        assert!(engine.consistency_check().is_ok());
+
+        let root = quadtree::Address::from((vec![], 3));
+        engine
+            .state
+            .qtree
+            .get_leaf_mut(root)
+            .unwrap()
+            .fields
+            .land_value
+            .land_value
+            .value = f64::NAN;
+
+        match engine.consistency_check() {
+            Err(ConsistencyError::NonFiniteErrors(errors)) => assert_eq!(errors.len(), 1),
+            res => panic!("expected non-finite errors, got {:?}", res),
+        }
+
+        assert_eq!(engine.repair_non_finite(), 1);
+        assert!(engine.consistency_check().is_ok());
+    }
+}
@@ -0,0 +1,188 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::behavior::TriggerType;
+use crate::engine::{Engine, Error};
+
+/**
+ * An object-safe version of TriggerType, for triggers that are defined outside of this crate.
+ * These are wrapped in a CustomTrigger so that they can be added to the trigger queue.
+ *
+ * Custom triggers are serialized using `payload`, and deserialized by the factory registered for
+ * their tag with Engine::register_trigger_factory.
+ */
+pub trait DynTriggerType: std::fmt::Debug + Send + Sync {
+    /// Identifies the factory used to deserialize this trigger, so it must be unique.
+    fn tag(&self) -> &'static str;
+
+    fn execute(self: Box<Self>, engine: &mut Engine, time: u64) -> Result<(), Error>;
+
+    /// The serialized form of this trigger, which the registered factory must be able to read.
+    fn payload(&self) -> Result<String, Error>;
+
+    fn clone_box(&self) -> Box<dyn DynTriggerType>;
+
+    /// A short description, e.g. for showing the trigger in a UI.
+    fn label(&self) -> String {
+        self.tag().to_string()
+    }
+
+    fn debug_context(&self, _engine: &Engine) -> Option<String> {
+        None
+    }
+}
+
+/// Creates a custom trigger from its payload.
+pub type TriggerFactory = fn(&str) -> Result<Box<dyn DynTriggerType>, Error>;
+
+lazy_static::lazy_static! {
+    // NOTE: This is global rather than stored on the engine because the factories are needed while
+    // deserializing the engine, before it exists. It is shared by every engine in the process,
+    // which is harmless since factories only decode payloads and keep no state of their own.
+    static ref TRIGGER_FACTORIES: RwLock<HashMap<String, TriggerFactory>> =
+        RwLock::new(HashMap::new());
+}
+
+impl Engine {
+    /**
+     * Register the factory used to deserialize custom triggers with the given tag. This must be
+     * called before loading any engine that contains such triggers. Registering the same tag again
+     * replaces the previous factory.
+     */
+    pub fn register_trigger_factory(tag: &str, factory: TriggerFactory) {
+        TRIGGER_FACTORIES
+            .write()
+            .unwrap()
+            .insert(tag.to_string(), factory);
+    }
+}
+
+/**
+ * A trigger defined outside of this crate. Custom triggers are ordered by tag and then by payload,
+ * so that the queue is deterministic when several are scheduled for the same time.
+ */
+pub struct CustomTrigger {
+    tag: String,
+    payload: String,
+    inner: Box<dyn DynTriggerType>,
+}
+
+impl CustomTrigger {
+    pub fn new<T: DynTriggerType + 'static>(trigger: T) -> Result<Self, Error> {
+        Self::from_box(Box::new(trigger))
+    }
+
+    pub fn from_box(inner: Box<dyn DynTriggerType>) -> Result<Self, Error> {
+        Ok(Self {
+            tag: inner.tag().to_string(),
+            payload: inner.payload()?,
+            inner,
+        })
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn label(&self) -> String {
+        self.inner.label()
+    }
+}
+
+impl TriggerType for CustomTrigger {
+    fn execute(self, engine: &mut Engine, time: u64) -> Result<(), Error> {
+        self.inner.execute(engine, time)
+    }
+
+    fn debug_context(&self, engine: &Engine) -> Option<String> {
+        self.inner.debug_context(engine)
+    }
+}
+
+impl std::fmt::Debug for CustomTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl Clone for CustomTrigger {
+    fn clone(&self) -> Self {
+        Self {
+            tag: self.tag.clone(),
+            payload: self.payload.clone(),
+            inner: self.inner.clone_box(),
+        }
+    }
+}
+
+impl PartialEq for CustomTrigger {
+    fn eq(&self, other: &Self) -> bool {
+        self.tag == other.tag && self.payload == other.payload
+    }
+}
+
+impl Eq for CustomTrigger {}
+
+impl Ord for CustomTrigger {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.tag, &self.payload).cmp(&(&other.tag, &other.payload))
+    }
+}
+
+impl PartialOrd for CustomTrigger {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedCustomTrigger {
+    tag: String,
+    payload: String,
+}
+
+impl Serialize for CustomTrigger {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedCustomTrigger {
+            tag: self.tag.clone(),
+            payload: self.payload.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomTrigger {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error as _;
+
+        let serialized = SerializedCustomTrigger::deserialize(deserializer)?;
+        let factory = TRIGGER_FACTORIES
+            .read()
+            .unwrap()
+            .get(&serialized.tag)
+            .copied()
+            .ok_or_else(|| {
+                D::Error::custom(format!(
+                    "no factory registered for custom trigger '{}'; \
+                     call Engine::register_trigger_factory before loading",
+                    serialized.tag
+                ))
+            })?;
+        let inner = factory(&serialized.payload).map_err(D::Error::custom)?;
+        if inner.tag() != serialized.tag {
+            return Err(D::Error::custom(format!(
+                "factory for custom trigger '{}' created a trigger with tag '{}'",
+                serialized.tag,
+                inner.tag()
+            )));
+        }
+        Ok(Self {
+            tag: serialized.tag,
+            payload: serialized.payload,
+            inner,
+        })
+    }
+}
@@ -1,16 +1,22 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashSet};
 use std::sync::{Arc, RwLock};
 
+use memory_size::MemorySize;
 use serde::{Deserialize, Serialize};
 use uom::si::time::hour;
 use uom::si::u64::Time;
 
+use crate::alerts::Alerts;
 use crate::fields::FieldsState;
+use crate::load_overrides::LoadOverrides;
+use crate::route_latency::RouteLatency;
+use crate::routing_health::RoutingHealth;
 use crate::time_state::TimeState;
 use crate::trigger::{TriggerQueue, TriggerStats};
 
-/// number of times to record traffic history per day
-pub const WORLD_STATE_HISTORY_SNAPSHOTS: usize = 48;
+/// how many hours an agent can go without making progress on their route before they are
+/// considered stuck; see Engine::recover_stuck_agent
+const STUCK_ROUTE_TIMEOUT: u64 = 6;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -30,8 +36,177 @@ pub enum Error {
     QuadtreeError(#[from] quadtree::Error),
     #[error("Agent error: {0}")]
     AgentError(#[from] agent::Error),
+    #[error("Invalid highway segment: {0:?}")]
+    InvalidHighwaySegment(network::SegmentHandle),
+    #[error("Invalid agent: {0}")]
+    InvalidAgent(u64),
+    #[error("Invalid household: {0}")]
+    InvalidHousehold(u64),
+    #[error("Metro error: {0}")]
+    MetroError(#[from] metro::Error),
+    #[error("Bincode error: {0}")]
+    BincodeError(#[from] bincode::Error),
+    #[error("Replay error: {0}")]
+    ReplayError(String),
+    #[error("Traffic history file error: {0}")]
+    HistoryFileError(String),
+    #[error("Calibration error: {0}")]
+    CalibrationError(String),
+    #[error("Not a housing tile: {0:?}")]
+    NotAHousingTile(quadtree::Address),
+    #[error("Housing is already full: {0:?}")]
+    HousingFull(quadtree::Address),
+    #[error("Not a workplace tile: {0:?}")]
+    NotAWorkplaceTile(quadtree::Address),
+    #[error("Workplace is already full: {0:?}")]
+    WorkplaceFull(quadtree::Address),
+    #[error("The map has no {0} tiles")]
+    NoTiles(&'static str),
+    #[error("Invalid {name}: {reason}")]
+    InvalidArgument { name: &'static str, reason: String },
+    #[error("A change set is already open: {0:?}")]
+    ChangeSetAlreadyOpen(crate::change_set::ChangeSetHandle),
+    #[error("Change set is not open: {0:?}")]
+    ChangeSetNotOpen(crate::change_set::ChangeSetHandle),
+    #[error("Invalid staged change: {0}")]
+    InvalidStagedChange(String),
+    #[error("Invalid segment data: {0}")]
+    InvalidSegmentData(String),
+    #[error("Invalid segment closure: {0}")]
+    InvalidClosure(String),
+    #[error("Blurred field is already registered: {0}")]
+    DuplicateBlurredField(String),
+    #[error("Invalid scenario: {0}")]
+    InvalidScenario(String),
+    #[error("Cannot insert a tile on top of an occupied {kind} tile: {address:?}")]
+    TargetOccupied {
+        address: quadtree::Address,
+        kind: &'static str,
+    },
+    #[error("Cannot insert a tile on top of water: {0:?}")]
+    TargetIsWater(quadtree::Address),
+    #[error("Cannot insert a tile on top of a metro station: {0:?}")]
+    TargetIsStation(quadtree::Address),
+    #[error("Not a metro station that is unserved by any metro line: {0:?}")]
+    NotAnOrphanStation(quadtree::Address),
+    #[error("{context}: {source}")]
+    Context { context: String, source: Box<Error> },
+}
+
+impl Error {
+    /// The underlying error, without any context that was attached to it.
+    pub fn without_context(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.without_context(),
+            err => err,
+        }
+    }
+
+    /**
+     * Whether this is Engine::insert_tile refusing the tile that is already there, as opposed to
+     * something actually going wrong. Callers that sample where to build can just try again.
+     */
+    pub fn is_insert_rejection(&self) -> bool {
+        matches!(
+            self.without_context(),
+            Error::TargetOccupied { .. } | Error::TargetIsWater(_) | Error::TargetIsStation(_)
+        )
+    }
+}
+
+/// What Engine::insert_tile may do with the tile that is already at the target address.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InsertPolicy {
+    /**
+     * Only insert into empty tiles, which are replaced, and housing and workplaces without anyone
+     * living or working there, which are split.
+     */
+    #[default]
+    Preserve,
+    /**
+     * Also replace water, and split occupied housing and workplaces. Their agents keep their
+     * homes and jobs, but end up in a smaller tile.
+     */
+    Overwrite,
+}
+
+impl InsertPolicy {
+    /**
+     * Whether a tile can be inserted on top of the given tile. Metro stations are never allowed,
+     * since moving them would break the railways and the base graph; they have to be removed
+     * first, e.g. by replacing the leaf with Engine::set_leaf_data.
+     */
+    pub fn allows(&self, tile: &tiles::Tile) -> bool {
+        use tiles::Tile;
+        match (self, tile) {
+            (_, Tile::EmptyTile(_)) => true,
+            (_, Tile::MetroStationTile(_)) => false,
+            (Self::Overwrite, _) => true,
+            (Self::Preserve, Tile::WaterTile(_)) => false,
+            (Self::Preserve, Tile::HousingTile(tile)) => tile.agents.is_empty(),
+            (Self::Preserve, Tile::WorkplaceTile(tile)) => tile.agents.is_empty(),
+            (Self::Preserve, _) => true,
+        }
+    }
+
+    /// The error for a tile that this policy doesn't allow inserting on top of.
+    fn rejection(tile: &tiles::Tile, address: quadtree::Address) -> Error {
+        use tiles::{Tile, TileType};
+        match tile {
+            Tile::WaterTile(_) => Error::TargetIsWater(address),
+            Tile::MetroStationTile(_) => Error::TargetIsStation(address),
+            tile => Error::TargetOccupied {
+                address,
+                kind: tile.name(),
+            },
+        }
+    }
+}
+
+/**
+ * Attach context to an error, e.g. the tile or handle involved, so that the message says what
+ * failed instead of only the bare variant. Works for any error that converts into an Error.
+ */
+pub trait ErrorContext<T> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T, Error>;
+
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, f: F) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> ErrorContext<T> for Result<T, E> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T, Error> {
+        self.with_context(|| context)
+    }
+
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, f: F) -> Result<T, Error> {
+        self.map_err(|err| Error::Context {
+            context: f().into(),
+            source: Box::new(err.into()),
+        })
+    }
 }
 
+/// e.g. "failed to split tile at (512, 768)"
+fn tile_context(action: &str, address: quadtree::Address) -> String {
+    let (x, y) = address.to_xy();
+    format!("failed to {} tile at ({}, {})", action, x, y)
+}
+
+fn route_cost_components<W: route::WorldState>(
+    route: &route::Route,
+    world_state: &W,
+    state: &state::State<FieldsState>,
+) -> Vec<route::CostComponents> {
+    route
+        .edges
+        .iter()
+        .map(|edge| edge.cost_components(world_state, state, None))
+        .collect()
+}
+
+/// a route from Engine::query_route_async, along with how long the query took
+pub type TimedRoute = (Option<route::Route>, std::time::Duration);
+
 #[derive(Debug)]
 pub struct BaseGraph {
     base_graph: once_cell::sync::OnceCell<route::Graph>,
@@ -41,6 +216,15 @@ pub struct BaseGraph {
     version: u64,
 }
 
+/// Only counts the shared graph, not the per-thread copies, which are each about as big again.
+impl MemorySize for BaseGraph {
+    fn heap_bytes(&self) -> usize {
+        self.base_graph
+            .get()
+            .map_or(0, |graph| graph.estimated_bytes())
+    }
+}
+
 impl Default for BaseGraph {
     fn default() -> Self {
         Self {
@@ -61,6 +245,36 @@ impl Clone for BaseGraph {
     }
 }
 
+/**
+ * The base graph of one engine, shared with the threads that query routes on its behalf. Cloning
+ * the engine copies the graph rather than sharing it, since the copy's networks can diverge from
+ * the original's; otherwise a change to either would clear the graph of both, and whichever engine
+ * queried next would rebuild it from its own state for the other to use.
+ */
+#[derive(Debug, Default)]
+pub struct SharedBaseGraph(Arc<RwLock<BaseGraph>>);
+
+impl Clone for SharedBaseGraph {
+    fn clone(&self) -> Self {
+        Self(Arc::new(RwLock::new(self.0.read().unwrap().clone())))
+    }
+}
+
+impl std::ops::Deref for SharedBaseGraph {
+    type Target = RwLock<BaseGraph>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl SharedBaseGraph {
+    /// Another handle to the same graph, e.g. to hand off to a worker thread.
+    pub fn share(&self) -> Arc<RwLock<BaseGraph>> {
+        Arc::clone(&self.0)
+    }
+}
+
 impl BaseGraph {
     pub fn construct_base_graph_filter<F: state::Fields>(
         state: &state::State<F>,
@@ -83,9 +297,16 @@ impl BaseGraph {
         Self::construct_base_graph_filter(state, None, None)
     }
 
+    /// Construct the base graph, stamped with the current network version.
+    fn construct_versioned<F: state::Fields>(&self, state: &state::State<F>) -> route::Graph {
+        let mut graph = Self::construct_base_graph(state).unwrap();
+        graph.version.network = self.version;
+        graph
+    }
+
     pub fn get_base_graph<F: state::Fields>(&self, state: &state::State<F>) -> &route::Graph {
         self.base_graph
-            .get_or_init(|| Self::construct_base_graph(state).unwrap())
+            .get_or_init(|| self.construct_versioned(state))
     }
 
     pub fn get_base_graph_mut<F: state::Fields>(
@@ -98,7 +319,7 @@ impl BaseGraph {
         let base_graph = self
             .base_graph
             .take()
-            .unwrap_or_else(|| Self::construct_base_graph(state).unwrap());
+            .unwrap_or_else(|| self.construct_versioned(state));
         self.base_graph.set(base_graph).unwrap();
         self.base_graph.get_mut().unwrap()
     }
@@ -137,23 +358,78 @@ impl BaseGraph {
     pub fn version(&self) -> u64 {
         self.version
     }
+
+    /**
+     * The version of the graph that routes are currently computed against. If the graph hasn't
+     * been constructed since the last network change, the traffic version is zero, since that is
+     * what the graph will have once it is constructed.
+     */
+    pub fn graph_version(&self) -> route::GraphVersion {
+        match self.base_graph.get() {
+            Some(graph) => graph.version,
+            None => route::GraphVersion {
+                network: self.version,
+                traffic: 0,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Engine {
     pub state: state::State<FieldsState>,
     pub world_state: route::WorldStateImpl,
+    /// NOTE: This is saved separately from the rest of the engine; see dump_file. Older saves
+    /// that still have the history inline load it from there instead.
+    #[serde(default, skip_serializing)]
     pub world_state_history: route::WorldStateHistory,
+    /// the content hash of the history in the sidecar file that goes with this save, if any
+    #[serde(default)]
+    history_hash: Option<u64>,
+    /// traffic snapshots taken since the sidecar file was last written
+    #[serde(default)]
+    snapshots_since_history_save: usize,
+    /// travelers added with inject_highway_travelers, which are not associated with any agent
+    #[serde(default)]
+    injected_highway_travelers: BTreeMap<network::SegmentHandle, f64>,
     #[serde(skip)]
-    pub base_graph: Arc<RwLock<BaseGraph>>,
+    pub base_graph: SharedBaseGraph,
     pub time_state: TimeState,
-    pub agents: HashMap<u64, agent::Agent>,
+    /// NOTE: Anything that can change how the simulation evolves must iterate in a deterministic
+    /// order, for the same reason as the note on rng below; otherwise the order in which agents are
+    /// visited could feed into a random draw or a first-match decision. So collections keyed by ID,
+    /// like agents and households, are BTreeMaps. HashMaps and HashSets are still fine for lookups
+    /// and for iteration that only feeds the viewers, but anywhere else they must be sorted by key
+    /// before iterating. The determinism_test checks this.
+    pub agents: BTreeMap<u64, agent::Agent>,
     agent_counter: u64,
+    #[serde(default)]
+    pub households: BTreeMap<u64, agent::Household>,
+    #[serde(default)]
+    household_counter: u64,
     pub trigger_queue: TriggerQueue,
+    /// set by init_trigger_queue, after which agents need to be scheduled as they are added
+    #[serde(skip)]
+    triggers_initialized: bool,
+    /// NOTE: Clones of the engine share this pool, including its size; see set_num_threads.
     #[serde(skip, default = "Engine::create_thread_pool")]
     pub(crate) thread_pool: threadpool::ThreadPool,
+    /// the debug logs of watched agents, which are made current while triggers execute
+    #[serde(skip)]
+    pub(crate) agent_logs: agent::AgentLogs,
     #[serde(skip)]
     pub(crate) blurred_fields: crate::field_update::BlurredFields,
+    /// incremented each time the fields are updated, so that anything derived from them can be
+    /// cached
+    #[serde(skip)]
+    pub(crate) fields_version: u64,
+    #[serde(skip)]
+    pub(crate) catchment_cache: crate::catchment::CatchmentCache,
+    #[serde(skip)]
+    pub(crate) route_cost_cache: crate::route_estimate::RouteCostCache,
+    /// see StatsSnapshot
+    #[serde(skip)]
+    pub(crate) stats_snapshot: crate::stats_snapshot::StatsSnapshot,
     /// NOTE: This is the main RNG. For determinism, all randomness in the simulation must be
     /// derived from this RNG. Note that this implies that all random values must be obtained on the
     /// main simulation thread, since a mutable reference to rng is needed and it is not behind a
@@ -162,6 +438,32 @@ pub struct Engine {
     pub rng: rand_chacha::ChaCha12Rng,
     #[serde(skip)]
     pub trigger_stats: TriggerStats,
+    #[serde(skip)]
+    pub(crate) route_latency: RouteLatency,
+    /// see set_route_query_delay
+    #[serde(skip)]
+    route_query_delay: Option<std::time::Duration>,
+    /// see set_route_detail
+    #[serde(skip)]
+    route_detail: Option<agent::RouteDetail>,
+    #[serde(default)]
+    pub(crate) routing_health: RoutingHealth,
+    #[serde(default)]
+    pub alerts: Alerts,
+    #[serde(default)]
+    pub job_quits: crate::job_quits::JobQuits,
+    #[serde(default)]
+    pub(crate) change_sets: crate::change_set::ChangeSets,
+    #[cfg(feature = "replay")]
+    #[serde(skip)]
+    pub(crate) recording: crate::replay::Recording,
+    #[cfg(feature = "travel_diary")]
+    #[serde(skip)]
+    pub(crate) travel_diary: crate::travel_diary::TravelDiary,
+    #[serde(skip)]
+    pub(crate) od_matrices: crate::od_matrix::OdMatrices,
+    #[serde(default)]
+    pub(crate) scenario: crate::scenario::LoadedScenario,
 }
 
 impl Engine {
@@ -171,19 +473,43 @@ impl Engine {
             world_state: route::WorldStateImpl::new(&config),
             world_state_history: route::WorldStateHistory::new(
                 &config,
-                WORLD_STATE_HISTORY_SNAPSHOTS,
+                config.traffic_history.snapshots,
             ),
+            history_hash: None,
+            snapshots_since_history_save: 0,
+            injected_highway_travelers: BTreeMap::new(),
             state: state::State::new(config),
-            base_graph: Arc::new(RwLock::new(BaseGraph::default())),
+            base_graph: Default::default(),
             time_state: TimeState::new(),
-            agents: HashMap::new(),
+            agents: BTreeMap::new(),
             agent_counter: 0,
+            households: BTreeMap::new(),
+            household_counter: 0,
             trigger_queue: TriggerQueue::new(),
+            triggers_initialized: false,
             thread_pool: Self::create_thread_pool(),
+            agent_logs: Default::default(),
             blurred_fields: Default::default(),
+            fields_version: 0,
+            catchment_cache: Default::default(),
+            route_cost_cache: Default::default(),
+            stats_snapshot: Default::default(),
             // initialize once randomly
             rng: rand_chacha::ChaCha12Rng::from_rng(rand::thread_rng()).unwrap(),
             trigger_stats: TriggerStats::new(false),
+            route_latency: Default::default(),
+            route_query_delay: None,
+            route_detail: None,
+            routing_health: RoutingHealth::default(),
+            alerts: Alerts::default(),
+            job_quits: Default::default(),
+            change_sets: Default::default(),
+            #[cfg(feature = "replay")]
+            recording: Default::default(),
+            #[cfg(feature = "travel_diary")]
+            travel_diary: Default::default(),
+            od_matrices: Default::default(),
+            scenario: Default::default(),
         }
     }
 
@@ -194,80 +520,465 @@ impl Engine {
         threadpool::ThreadPool::new(parallelism)
     }
 
+    /// Resize the pool that route queries run on. Clones of this engine share the pool, so they are
+    /// resized as well.
     pub fn set_num_threads(&mut self, num_threads: usize) {
         self.thread_pool.set_num_threads(num_threads);
     }
 
+    /**
+     * Load an engine from a string produced by dump. The traffic history is not part of the dump,
+     * so it starts over with fresh priors; use load_file to also load it.
+     */
     pub fn load(data: &str) -> Result<Self, Error> {
-        Ok(serde_json::from_str(data)?)
+        Self::load_with_overrides(data, &LoadOverrides::default())
     }
 
+    /**
+     * Load an engine from a file written by dump_file, along with the traffic history from the
+     * sidecar file next to it. If the sidecar is missing or doesn't match the save, the traffic
+     * history starts over with fresh priors.
+     */
     pub fn load_file(path: &std::path::Path) -> Result<Self, Error> {
-        Self::load(&std::fs::read_to_string(path)?)
+        Self::load_file_with_overrides(path, &LoadOverrides::default())
+    }
+
+    /**
+     * Like load, but with some of the map's config overridden first, e.g. to use less memory on
+     * devices that don't have much of it.
+     */
+    pub fn load_with_overrides(data: &str, overrides: &LoadOverrides) -> Result<Self, Error> {
+        Self::load_with_history(data, None, overrides)
+    }
+
+    /// Like load_file, but with some of the map's config overridden first; see load_with_overrides.
+    pub fn load_file_with_overrides(
+        path: &std::path::Path,
+        overrides: &LoadOverrides,
+    ) -> Result<Self, Error> {
+        std::fs::read_to_string(path)
+            .map_err(Error::from)
+            .and_then(|data| {
+                Self::load_with_history(
+                    &data,
+                    Some(&crate::history_file::history_path(path)),
+                    overrides,
+                )
+            })
+            .with_context(|| format!("failed to load map from {}", path.display()))
+    }
+
+    fn load_with_history(
+        data: &str,
+        history_path: Option<&std::path::Path>,
+        overrides: &LoadOverrides,
+    ) -> Result<Self, Error> {
+        let mut engine: Self = serde_json::from_str(data)?;
+        overrides.apply(&mut engine.state.config);
+        engine.state.config.validate().map_err(state::Error::from)?;
+        engine.state.index_tiles()?;
+        engine.state.metros.index_stations(&engine.state.railways);
+        if !engine.world_state.grid_matches(&engine.state.config) {
+            engine.rebuild_world_state()?;
+        }
+        if !engine
+            .world_state_history
+            .matches_config(&engine.state.config)
+        {
+            engine.attach_history(history_path);
+        }
+        // saved traffic data may have been corrupted, so make sure it won't poison route weights
+        engine.repair_non_finite();
+        // older versions could lose track of cars when routes were aborted
+        engine.repair_parked_cars()?;
+        Ok(engine)
+    }
+
+    /**
+     * Replace the placeholder traffic history with the one from the sidecar file, if it is the one
+     * that this save refers to. Otherwise start over with a fresh history.
+     */
+    fn attach_history(&mut self, history_path: Option<&std::path::Path>) {
+        let snapshots = self.state.config.traffic_history.snapshots;
+        let expected_hash = self.history_hash.take();
+
+        if let (Some(expected_hash), Some(path)) = (expected_hash, history_path) {
+            match crate::history_file::read(path) {
+                Ok((hash, history))
+                    if hash == expected_hash && history.matches_config(&self.state.config) =>
+                {
+                    self.world_state_history = history;
+                    self.history_hash = Some(hash);
+                    return;
+                }
+                Ok(_) => eprintln!(
+                    "Traffic history in {} does not match the save, starting with fresh traffic priors",
+                    path.display()
+                ),
+                Err(err) => eprintln!(
+                    "Failed to load traffic history from {}, starting with fresh traffic priors: {}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+
+        self.world_state_history = route::WorldStateHistory::new(&self.state.config, snapshots);
+        self.snapshots_since_history_save = 0;
     }
 
+    /// Serialize everything except the traffic history, which dump_file saves separately.
     pub fn dump(&self) -> Result<String, Error> {
         Ok(serde_json::to_string(self)?)
     }
 
-    pub fn dump_file(&self, path: &std::path::Path) -> Result<(), Error> {
-        Ok(std::fs::write(path, self.dump()?)?)
+    /**
+     * Save the engine to a file. The traffic history goes in a sidecar file next to it (see
+     * history_path), which is only rewritten once enough new snapshots have been taken since the
+     * last time it was saved, to keep saving cheap. In between, the save keeps referring to the
+     * previously saved history. Use dump_history_file to always rewrite it. The map's modified
+     * time (see state::MapMetadata) is updated as well.
+     */
+    pub fn dump_file(&mut self, path: &std::path::Path) -> Result<(), Error> {
+        self.state.metadata.touch_now();
+
+        let history_path = crate::history_file::history_path(path);
+        let sidecar_hash = crate::history_file::read_hash(&history_path).ok();
+        if self.history_hash.is_none()
+            || sidecar_hash != self.history_hash
+            || self.snapshots_since_history_save >= self.state.config.traffic_history.save_interval
+        {
+            self.dump_history_file(path)?;
+        }
+
+        self.dump()
+            .and_then(|data| Ok(std::fs::write(path, data)?))
+            .with_context(|| format!("failed to save map to {}", path.display()))
+    }
+
+    /**
+     * Write the current traffic history to the sidecar file for the given save path, even if it
+     * hasn't changed much since it was last written. The next save will refer to it.
+     */
+    pub fn dump_history_file(&mut self, path: &std::path::Path) -> Result<(), Error> {
+        let history_path = crate::history_file::history_path(path);
+        let hash = self.world_state_history.content_hash();
+        crate::history_file::write(&history_path, hash, &self.world_state_history).with_context(
+            || {
+                format!(
+                    "failed to save traffic history to {}",
+                    history_path.display()
+                )
+            },
+        )?;
+        self.history_hash = Some(hash);
+        self.snapshots_since_history_save = 0;
+        Ok(())
     }
 
+    /**
+     * Add an agent living at the given housing tile and optionally working at the given workplace
+     * tile. The agent takes a spot in each tile, their car starts out parked at home, and once the
+     * simulation is running, their triggers are scheduled. Either all of this happens or, if either
+     * tile is the wrong kind or already full, none of it does.
+     */
     pub fn add_agent(
         &mut self,
         data: agent::AgentData,
         housing: quadtree::Address,
         workplace: Option<quadtree::Address>,
-    ) -> u64 {
+    ) -> Result<u64, Error> {
         let id = self.agent_counter;
+
+        self.claim_tile_spot(housing, id, false)?;
+        if let Some(workplace) = workplace {
+            if let Err(err) = self.claim_tile_spot(workplace, id, true) {
+                self.release_tile_spot(housing, id);
+                return Err(err);
+            }
+        }
+
+        // initialize parking data
+        if data.owns_car {
+            if let Err(err) = self.world_state.increment_parking(housing) {
+                self.release_tile_spot(housing, id);
+                if let Some(workplace) = workplace {
+                    self.release_tile_spot(workplace, id);
+                }
+                return Err(err.into());
+            }
+        }
+
         self.agent_counter += 1;
+        let mut agent = agent::Agent::new(id, data, housing, None);
+        if let Some(workplace) = workplace {
+            if let tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { industry, .. }) =
+                self.state.qtree.get_leaf(workplace)?.tile
+            {
+                agent.take_job(workplace, industry, self.time_state.current_time);
+            }
+        }
+        self.agents.insert(id, agent);
 
-        match self.state.qtree.get_leaf_mut(housing) {
-            Ok(state::LeafState {
-                tile: tiles::Tile::HousingTile(tiles::HousingTile { density, agents }),
-                ..
-            }) => {
-                assert!(agents.len() < *density);
-                agents.push(id);
+        // before then, init_trigger_queue schedules every agent
+        if self.triggers_initialized {
+            self.schedule_agent(id);
+        }
+
+        Ok(id)
+    }
+
+    /// Add the agent to a housing or workplace tile, if it is the right kind and has room.
+    fn claim_tile_spot(
+        &mut self,
+        address: quadtree::Address,
+        id: u64,
+        workplace: bool,
+    ) -> Result<(), Error> {
+        let leaf = self.state.qtree.get_leaf_mut(address)?;
+        let (density, agents, full) = match (&mut leaf.tile, workplace) {
+            (tiles::Tile::HousingTile(tiles::HousingTile { density, agents }), false) => {
+                (*density, agents, Error::HousingFull(address))
             }
-            Ok(tile) => panic!(
-                "missing housing tile at {:?}, found tile: {:?}",
-                housing, tile
-            ),
-            Err(err) => panic!(
-                "missing housing tile at {:?}, no tile found, error: {:?}",
-                housing, err
-            ),
+            (
+                tiles::Tile::WorkplaceTile(tiles::WorkplaceTile {
+                    density, agents, ..
+                }),
+                true,
+            ) => (*density, agents, Error::WorkplaceFull(address)),
+            (_, false) => return Err(Error::NotAHousingTile(address)),
+            (_, true) => return Err(Error::NotAWorkplaceTile(address)),
         };
+        if agents.len() >= density {
+            return Err(full);
+        }
+        agents.push(id);
+        Ok(())
+    }
 
-        if let Some(workplace) = workplace {
-            match self.state.qtree.get_leaf_mut(workplace) {
-                Ok(state::LeafState {
-                    tile: tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { density, agents }),
-                    ..
-                }) => {
-                    assert!(agents.len() < *density);
-                    agents.push(id);
-                }
-                Ok(tile) => panic!(
-                    "missing workplace tile at {:?}, found tile: {:?}",
-                    workplace, tile
-                ),
-                Err(err) => panic!(
-                    "missing workplace tile at {:?}, no tile found, error: {:?}",
-                    workplace, err
-                ),
+    /// Undo claim_tile_spot.
+    fn release_tile_spot(&mut self, address: quadtree::Address, id: u64) {
+        if let Ok(state::LeafState {
+            tile:
+                tiles::Tile::HousingTile(tiles::HousingTile { agents, .. })
+                | tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { agents, .. }),
+            ..
+        }) = self.state.qtree.get_leaf_mut(address)
+        {
+            agents.retain(|agent_id| *agent_id != id);
+        }
+    }
+
+    /**
+     * Create a household at the given housing tile with a pool of cars shared by its members. Use
+     * add_household_agent to add members.
+     */
+    pub fn add_household(&mut self, housing: quadtree::Address, cars: u32) -> Result<u64, Error> {
+        let id = self.household_counter;
+        self.household_counter += 1;
+
+        // household cars start out parked at home
+        for _ in 0..cars {
+            self.world_state.increment_parking(housing)?;
+        }
+
+        self.households
+            .insert(id, agent::Household::new(housing, cars));
+
+        Ok(id)
+    }
+
+    /**
+     * Like add_agent, but the agent lives in the given household and shares its cars. If the agent
+     * has a car of their own, it is added to the household instead.
+     */
+    pub fn add_household_agent(
+        &mut self,
+        household: u64,
+        mut data: agent::AgentData,
+        workplace: Option<quadtree::Address>,
+    ) -> Result<u64, Error> {
+        let housing = self
+            .households
+            .get(&household)
+            .ok_or(Error::InvalidHousehold(household))?
+            .housing;
+
+        let owns_car = data.owns_car;
+        data.owns_car = false;
+        let id = self.add_agent(data, housing, workplace)?;
+
+        let household_data = self.households.get_mut(&household).unwrap();
+        household_data.members.push(id);
+        if owns_car {
+            household_data.add_car();
+            self.world_state.increment_parking(housing)?;
+        }
+        self.agents.get_mut(&id).unwrap().household = Some(household);
+
+        Ok(id)
+    }
+
+    /**
+     * Whether the given agent can drive on a typical day, either with their own car or with one of
+     * their household's cars.
+     */
+    pub fn has_car_access(&self, agent: u64) -> bool {
+        let agent = self.agents.get(&agent).expect("missing agent");
+        agent.owns_car()
+            || agent.has_car()
+            || matches!(agent.household, Some(household) if self.households[&household].cars() > 0)
+    }
+
+    /**
+     * Make sure the given agent has a car for leaving home, checking one out from their household
+     * if necessary. Returns false if the agent has no car and none of the household's cars are
+     * available.
+     */
+    pub(crate) fn check_out_household_car(&mut self, agent: u64) -> bool {
+        let agent = self.agents.get_mut(&agent).expect("missing agent");
+        if agent.parked_car().is_some() {
+            return true;
+        }
+        match agent.household {
+            Some(household) if self.households.get_mut(&household).unwrap().check_out_car() => {
+                *agent.parked_car_mut() = Some(agent.housing);
+                true
             }
+            _ => false,
         }
+    }
 
-        // initialize parking data
-        self.world_state.increment_parking(housing).unwrap();
+    /**
+     * If the given agent is home and holding one of their household's cars, return it to the
+     * household so that other members can use it.
+     */
+    pub(crate) fn return_household_car(&mut self, agent: u64) -> Result<(), Error> {
+        let agent = self.agents.get_mut(&agent).expect("missing agent");
+        if let agent::AgentState::Route(_) = agent.state {
+            return Ok(());
+        }
+        if let (Some(household), Some(parked_car)) = (agent.household, agent.parked_car()) {
+            if parked_car == agent.housing {
+                *agent.parked_car_mut() = None;
+                self.households
+                    .get_mut(&household)
+                    .unwrap()
+                    .check_in_car()?;
+            }
+        }
+        Ok(())
+    }
 
-        self.agents
-            .insert(id, agent::Agent::new(id, data, housing, workplace));
+    /**
+     * When the given agent works, which depends on the industry of their workplace. Agents without
+     * a job get the default schedule.
+     */
+    pub fn work_schedule(&self, agent: u64) -> tiles::WorkSchedule {
+        let agent = self.agents.get(&agent).expect("missing agent");
+        match agent
+            .workplace
+            .map(|workplace| self.state.qtree.get_leaf(workplace))
+        {
+            Some(Ok(state::LeafState {
+                tile: tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { industry, .. }),
+                ..
+            })) => industry.schedule(),
+            _ => tiles::WorkSchedule::default(),
+        }
+    }
+
+    /**
+     * The closest point of interest of the given kind to the given address. Points of interest are
+     * only found as of the last time the tiles were collected, and are left out if they have been
+     * removed since.
+     */
+    pub fn nearest_poi(
+        &self,
+        address: quadtree::Address,
+        kind: tiles::PoiKind,
+    ) -> Option<quadtree::Address> {
+        let (x, y) = address.to_xy_f64();
+        let distance = |other: &quadtree::Address| {
+            let (other_x, other_y) = other.to_xy_f64();
+            (other_x - x).hypot(other_y - y)
+        };
+        self.state
+            .collect_tiles
+            .pois
+            .iter()
+            .filter(|(poi, poi_kind)| {
+                *poi_kind == kind
+                    && matches!(
+                        self.state.qtree.get_leaf(*poi),
+                        Ok(state::LeafState { poi: Some(poi), .. }) if poi.kind == kind
+                    )
+            })
+            .map(|(poi, _)| *poi)
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+    }
+
+    /**
+     * Counters for agents that failed to route, as of the current time. A sudden increase usually
+     * means that an edit disconnected part of the map.
+     */
+    pub fn routing_health(&self) -> RoutingHealth {
+        self.routing_health.at(self.time_state.current_time)
+    }
+
+    /**
+     * The geometry of every active highway segment as polylines in model coordinates, with samples
+     * spaced `resolution` apart. Meant for plotting the simulation outside of the app.
+     */
+    pub fn highway_geometry(&self, resolution: f64) -> Vec<Vec<(f64, f64)>> {
+        self.state
+            .highways
+            .segments()
+            .values()
+            .filter(|segment| segment.change_state.is_active())
+            .map(|segment| segment.sample_polyline(resolution))
+            .collect()
+    }
+
+    /**
+     * Like highway_geometry, but for the railway segments that are part of at least one metro line.
+     */
+    pub fn metro_geometry(&self, resolution: f64) -> Vec<Vec<(f64, f64)>> {
+        self.state
+            .railways
+            .segments()
+            .values()
+            .filter(|segment| {
+                segment.change_state.is_active()
+                    && !self
+                        .state
+                        .metros
+                        .railway_segment_metro_lines(segment.id)
+                        .is_empty()
+            })
+            .map(|segment| segment.sample_polyline(resolution))
+            .collect()
+    }
 
-        id
+    /**
+     * The current position of each agent, by agent ID. Agents at a tile are placed at the center
+     * of the tile, and traveling agents are placed along their route. Unlike the population
+     * fields, which count residents at their homes, this reflects where agents actually are.
+     */
+    pub fn live_agent_positions(&self) -> impl Iterator<Item = (u64, (f32, f32))> + '_ {
+        let current_time = self.time_state.current_time;
+        self.agents
+            .values()
+            .filter_map(move |agent| match &agent.state {
+                agent::AgentState::Tile(address) => {
+                    let (x, y) = address.to_xy();
+                    Some((agent.id, (x as f32, y as f32)))
+                }
+                agent::AgentState::Route(route_state) => route_state
+                    .sample(current_time, &self.state)
+                    .map(|key| (agent.id, key.position)),
+                agent::AgentState::Unknown => None,
+            })
     }
 
     /**
@@ -299,6 +1010,12 @@ impl Engine {
             _ => (),
         }
 
+        for household in self.households.values_mut() {
+            if household.housing == from {
+                household.housing = to;
+            }
+        }
+
         // TODO: it would be better to not have to iterate through all of the agents each time
         for agent in self.agents.values_mut() {
             let parked_car = agent.parked_car_mut();
@@ -314,15 +1031,98 @@ impl Engine {
         Ok(())
     }
 
+    pub fn get_leaf(
+        &self,
+        address: quadtree::Address,
+    ) -> Result<&state::LeafState<FieldsState>, Error> {
+        self.state
+            .qtree
+            .get_leaf(address)
+            .with_context(|| tile_context("get", address))
+    }
+
+    /**
+     * Split the leaf at the given address into four leaves. The existing tile is discarded, so
+     * this is meant for editing maps rather than for use while agents are living on them. The new
+     * leaves get new tile ids, whatever ids they were given.
+     */
+    pub fn split_tile(
+        &mut self,
+        address: quadtree::Address,
+        branch: state::BranchState<FieldsState>,
+        leaves: quadtree::QuadMap<state::LeafState<FieldsState>>,
+    ) -> Result<(), Error> {
+        self.state
+            .split(address, branch, leaves)
+            // report problems with the address the same way as the rest of the tile methods
+            .map_err(|err| match err {
+                state::Error::QuadtreeError(err) => Error::from(err),
+                err => Error::from(err),
+            })
+            .with_context(|| tile_context("split", address))?;
+        self.base_graph.write().unwrap().clear();
+        Ok(())
+    }
+
+    pub fn get_leaf_data(
+        &self,
+        address: quadtree::Address,
+        format: state::SerdeFormat,
+    ) -> Result<String, Error> {
+        self.state
+            .get_leaf_data(address, format)
+            .with_context(|| tile_context("read", address))
+    }
+
+    /// Replace the leaf at the given address with one decoded from the given data.
+    pub fn set_leaf_data(
+        &mut self,
+        address: quadtree::Address,
+        data: &str,
+        format: state::SerdeFormat,
+    ) -> Result<(), Error> {
+        self.state
+            .set_leaf_data(address, data, format)
+            .with_context(|| tile_context("edit", address))?;
+        self.base_graph.write().unwrap().clear();
+        Ok(())
+    }
+
+    /// Set or clear the point of interest at the given leaf, keeping its tile.
+    pub fn set_poi(
+        &mut self,
+        address: quadtree::Address,
+        poi: Option<tiles::Poi>,
+    ) -> Result<(), Error> {
+        self.state
+            .qtree
+            .get_leaf_mut(address)
+            .with_context(|| tile_context("edit", address))?
+            .poi = poi;
+        Ok(())
+    }
+
     /**
      * Forwards to State::insert_tile, but takes care of calling patch_tile. This should always be
      * used instead of calling insert_tile in State directly.
+     *
+     * The policy decides whether the tile that is already there may be built on; see InsertPolicy.
      */
     pub fn insert_tile(
         &mut self,
         address: quadtree::Address,
         tile: tiles::Tile,
+        policy: InsertPolicy,
     ) -> Result<(Option<quadtree::Address>, Option<quadtree::Address>), Error> {
+        let leaf = self.state.qtree.get_leaf_mut(address)?;
+        if !policy.allows(&leaf.tile) {
+            return Err(InsertPolicy::rejection(&leaf.tile, address));
+        }
+        if let tiles::Tile::WaterTile(_) = leaf.tile {
+            // water is replaced rather than split
+            leaf.tile = tiles::EmptyTile {}.into();
+        }
+
         let new_addresses = self.state.insert_tile(
             address,
             tile,
@@ -336,37 +1136,553 @@ impl Engine {
         Ok(new_addresses)
     }
 
-    pub fn query_route(
-        &self,
-        query_input: route::QueryInput,
-    ) -> Result<Option<route::Route>, Error> {
-        // TODO: using the thread local mechanism isn't necessary here, but currently
-        // route::best_route is written to accept RefMut so we have to do this
-        let base_graph = self.base_graph.write().unwrap();
-        // TODO: this is necessary to make sure the base graph is constructed
-        let _ = base_graph.get_base_graph(&self.state);
-        Ok(route::best_route(
-            base_graph.get_thread_base_graph(),
-            query_input,
-        )?)
+    /**
+     * Remove an agent from the simulation entirely, along with their car and any traffic they are
+     * currently contributing to. Any pending triggers for the agent are dropped when they fire.
+     */
+    pub fn remove_agent(&mut self, id: u64) -> Result<agent::Agent, Error> {
+        let _logs = self.agent_logs.enter();
+        let mut agent = self.agents.remove(&id).ok_or(Error::InvalidAgent(id))?;
+
+        if let agent::AgentState::Route(route_state) = &agent.state {
+            record_travel!(self, route_state);
+            agent.abort_route(&mut self.world_state)?;
+        }
+        if let Some(household) = agent
+            .household
+            .and_then(|household| self.households.get_mut(&household))
+        {
+            household.members.retain(|member| *member != id);
+            // household cars stay with the household
+            if let Some(parked_car) = agent.parked_car_mut().take() {
+                self.world_state.decrement_parking(parked_car)?;
+                self.world_state.increment_parking(household.housing)?;
+                household.check_in_car()?;
+            }
+        }
+        if let Some(parked_car) = agent.parked_car() {
+            self.world_state.decrement_parking(parked_car)?;
+        }
+
+        if let Ok(state::LeafState {
+            tile: tiles::Tile::HousingTile(tiles::HousingTile { agents, .. }),
+            ..
+        }) = self.state.qtree.get_leaf_mut(agent.housing)
+        {
+            agents.retain(|agent_id| *agent_id != id);
+        }
+        if let Some(Ok(state::LeafState {
+            tile: tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { agents, .. }),
+            ..
+        })) = agent
+            .workplace
+            .map(|workplace| self.state.qtree.get_leaf_mut(workplace))
+        {
+            agents.retain(|agent_id| *agent_id != id);
+        }
+
+        Ok(agent)
+    }
+
+    /**
+     * Teleport the given agent home if they are still on a route more than STUCK_ROUTE_TIMEOUT
+     * hours after they should have made progress on it, which means that the trigger advancing
+     * the route was lost. The route is aborted first so that parking and traffic stay balanced.
+     * Returns whether the agent was stuck.
+     */
+    pub fn recover_stuck_agent(&mut self, id: u64) -> Result<bool, Error> {
+        let _logs = self.agent_logs.enter();
+        let current_time = self.time_state.current_time;
+        let agent = self.agents.get_mut(&id).ok_or(Error::InvalidAgent(id))?;
+        let route_state = match &agent.state {
+            agent::AgentState::Route(route_state) => route_state,
+            _ => return Ok(false),
+        };
+        let stuck_since = route_state.expected_progress_time();
+        if stuck_since + Time::new::<hour>(STUCK_ROUTE_TIMEOUT).value > current_time {
+            return Ok(false);
+        }
+
+        tracing::warn!(
+            agent = id,
+            stuck_since,
+            "agent is stuck on a route; teleporting home"
+        );
+        record_travel!(self, route_state);
+        agent.log_timestamp(|| "stuck on route; teleporting home", current_time);
+        let route_type = route_state.route_type;
+        agent.abort_route(&mut self.world_state)?;
+        agent.teleport_home(route_type, &mut self.world_state)?;
+        self.routing_health.record_stuck_agent_recovered();
+        self.return_household_car(id)?;
+
+        Ok(true)
+    }
+
+    /**
+     * Start capturing the debug log of the given agent, which is otherwise discarded. The most
+     * recent lines are kept in memory; if a file is given, every line is also appended to it.
+     */
+    pub fn watch_agent(&self, id: u64, file: Option<&std::path::Path>) -> Result<(), Error> {
+        if !self.agents.contains_key(&id) {
+            return Err(Error::InvalidAgent(id));
+        }
+        self.agent_logs.watch(id, agent::AGENT_LOG_CAPACITY, file)?;
+        Ok(())
+    }
+
+    pub fn unwatch_agent(&self, id: u64) {
+        self.agent_logs.unwatch(id);
+    }
+
+    pub fn is_agent_watched(&self, id: u64) -> bool {
+        self.agent_logs.is_watched(id)
+    }
+
+    /// The most recent debug log lines of a watched agent, oldest first.
+    pub fn agent_log(&self, id: u64) -> Vec<String> {
+        self.agent_logs.lines(id)
+    }
+
+    /**
+     * Forwards to State::bulk_apply, but takes care of updating the rest of the engine. This should
+     * always be used instead of calling bulk_apply in State directly.
+     */
+    pub fn bulk_apply(
+        &mut self,
+        addresses: &[quadtree::Address],
+        op: &state::BulkOp,
+    ) -> state::BulkReport<Error> {
+        let report = self
+            .state
+            .bulk_apply(addresses, op, self.time_state.current_time as i64)
+            .map_err(Error::from);
+
+        if let state::BulkOp::SplitToDepth(depth) = op {
+            // anything that referred to a leaf that was split now refers to one of its children
+            for address in &report.applied {
+                let mut child = *address;
+                while child.depth() < *depth as usize {
+                    child = child.child(quadtree::Quadrant::NW);
+                }
+                if child != *address {
+                    self.patch_tile(*address, child)
+                        .expect("should be impossible");
+                    self.world_state
+                        .move_parked_cars(*address, child)
+                        .expect("should be impossible");
+                }
+            }
+        }
+
+        if !report.applied.is_empty() {
+            self.base_graph.write().unwrap().clear();
+        }
+
+        report
+    }
+
+    /**
+     * Remove the agents that live at any of the given addresses, and fire the agents that work at
+     * any of them. Other tiles are unaffected.
+     */
+    pub fn clear_agents(&mut self, addresses: &[quadtree::Address]) -> state::BulkReport<Error> {
+        let mut report = state::BulkReport::default();
+        for address in addresses {
+            match self.clear_agents_at(*address) {
+                Ok(()) => report.applied.push(*address),
+                Err(err) => report.errors.push((*address, err)),
+            }
+        }
+        report
+    }
+
+    fn clear_agents_at(&mut self, address: quadtree::Address) -> Result<(), Error> {
+        match &mut self.state.qtree.get_leaf_mut(address)?.tile {
+            tiles::Tile::HousingTile(tiles::HousingTile { agents, .. }) => {
+                for agent in agents.clone() {
+                    self.remove_agent(agent)?;
+                }
+            }
+            tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { agents, .. }) => {
+                for agent in std::mem::take(agents) {
+                    self.agents
+                        .get_mut(&agent)
+                        .ok_or(Error::InvalidAgent(agent))?
+                        .leave_job();
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /**
+     * Add travelers to a highway segment that are not associated with any agent, e.g. to test how
+     * routes respond to a jammed highway. The travelers are added to both the current world state
+     * and the traffic history, so call update_route_weights afterwards for newly computed routes
+     * to take them into account. The count may be negative to remove previously injected
+     * travelers.
+     */
+    pub fn inject_highway_travelers(
+        &mut self,
+        segment: network::SegmentHandle,
+        count: f64,
+    ) -> Result<(), Error> {
+        match self.state.highways.segments().get(&segment) {
+            Some(highway_segment) if highway_segment.change_state.is_active() => (),
+            _ => return Err(Error::InvalidHighwaySegment(segment)),
+        }
+
+        self.world_state
+            .add_highway_segment_travelers(segment, count)?;
+        self.world_state_history
+            .add_highway_segment_travelers(segment, count)?;
+
+        let injected = self
+            .injected_highway_travelers
+            .entry(segment)
+            .or_insert(0.0);
+        *injected += count;
+        if injected.abs() < f64::EPSILON {
+            self.injected_highway_travelers.remove(&segment);
+        }
+
+        Ok(())
+    }
+
+    pub fn injected_highway_travelers(&self) -> &BTreeMap<network::SegmentHandle, f64> {
+        &self.injected_highway_travelers
+    }
+
+    pub fn query_route(
+        &self,
+        query_input: route::QueryInput,
+    ) -> Result<Option<route::Route>, Error> {
+        // TODO: using the thread local mechanism isn't necessary here, but currently
+        // route::best_route is written to accept RefMut so we have to do this
+        let base_graph = self.base_graph.write().unwrap();
+        // TODO: this is necessary to make sure the base graph is constructed
+        let _ = base_graph.get_base_graph(&self.state);
+        Ok(route::best_route(
+            base_graph.get_thread_base_graph(),
+            query_input,
+        )?)
+    }
+
+    /**
+     * Like query_route, but also breaks down the cost of each edge the way the router weighted it.
+     * If the router was using predicted traffic, the best free-flow route is computed too, so that
+     * it is possible to see where traffic made the route deviate from it.
+     * NOTE: This recomputes the whole graph for the free-flow route, so it is only meant for
+     * debugging. Between a traffic snapshot and the arrival of the new weights it predicts, the
+     * components may be slightly off from the weights that were used.
+     */
+    pub fn query_route_debug(
+        &self,
+        query_input: route::QueryInput,
+    ) -> Result<Option<route::RouteDebug>, Error> {
+        let route = match self.query_route(query_input)? {
+            Some(route) => route,
+            None => return Ok(None),
+        };
+
+        let base_graph = self.base_graph.read().unwrap();
+        let graph = base_graph.get_base_graph(&self.state);
+        let free_flow_state = route::WorldStateImpl::new(&self.state.config);
+
+        let traffic_time = graph.traffic_time;
+        let components = |route: &route::Route| match traffic_time {
+            Some(time) => route_cost_components(
+                route,
+                &self.world_state_history.get_predictor(time),
+                &self.state,
+            ),
+            None => route_cost_components(route, &free_flow_state, &self.state),
+        };
+
+        let free_flow_route = match traffic_time {
+            Some(_) => {
+                let mut free_flow_graph = graph.clone();
+                free_flow_graph
+                    .graph
+                    .update_weights(&free_flow_state, &self.state);
+                free_flow_graph.traffic_time = None;
+                route::best_route(
+                    std::cell::RefCell::new(free_flow_graph).borrow_mut(),
+                    query_input,
+                )?
+                .filter(|free_flow_route| free_flow_route.nodes != route.nodes)
+            }
+            // the route was already computed without traffic
+            None => None,
+        };
+
+        let (free_flow_components, divergences) = match &free_flow_route {
+            Some(free_flow_route) => (
+                components(free_flow_route),
+                route::find_divergences(&route, free_flow_route),
+            ),
+            None => (vec![], vec![]),
+        };
+
+        Ok(Some(route::RouteDebug {
+            components: components(&route),
+            route,
+            traffic_time,
+            free_flow_route,
+            free_flow_components,
+            divergences,
+        }))
+    }
+
+    /// The version of the graph that newly queried routes and isochrones are computed against.
+    pub fn graph_version(&self) -> route::GraphVersion {
+        self.base_graph.read().unwrap().graph_version()
+    }
+
+    /// Incremented each time the fields are updated, for caching anything derived from them.
+    pub fn fields_version(&self) -> u64 {
+        self.fields_version
+    }
+
+    /**
+     * Whether the route was computed against the current networks and traffic predictions, i.e.
+     * whether querying it again would give the same result. Routes computed before a network edit
+     * or a traffic update are still usable, but may no longer be the best option.
+     */
+    pub fn is_route_current(&self, route: &route::Route) -> bool {
+        route.graph_version == self.graph_version()
+    }
+
+    /**
+     * Whether the route travels along any highway or railway segment that has been removed,
+     * edited, or closed since the route was computed, or rides a metro line that has since been
+     * rerouted off of a segment. Such a route can't be started, because the segments are going away
+     * once the routes already underway on them have finished, or are closed for now.
+     */
+    pub fn route_uses_removed_segments(&self, route: &route::Route) -> bool {
+        if route.graph_version.network == self.base_graph.read().unwrap().version() {
+            // nothing has changed since the route was computed
+            return false;
+        }
+
+        let highways = &self.state.highways;
+        let railways = &self.state.railways;
+        let metros = &self.state.metros;
+        route.edges.iter().any(|edge| {
+            let segment = match edge {
+                route::Edge::Highway { segment, .. } => highways
+                    .try_segment(*segment)
+                    .map(|segment| segment.change_state),
+                route::Edge::MetroSegment {
+                    metro_line,
+                    oriented_segment,
+                    ..
+                } => {
+                    if !metros
+                        .railway_segment_metro_lines(oriented_segment.segment)
+                        .contains(metro_line)
+                    {
+                        return true;
+                    }
+                    railways
+                        .try_segment(oriented_segment.segment)
+                        .map(|segment| segment.change_state)
+                }
+                _ => return false,
+            };
+            !matches!(segment, Some(change_state) if change_state.is_active())
+        })
+    }
+
+    /**
+     * Like query_route, but the route starts and ends at precise model coordinates instead of
+     * being snapped to addresses.
+     */
+    pub fn query_route_between(
+        &self,
+        start: (f64, f64),
+        end: (f64, f64),
+        car_config: Option<route::CarConfig>,
+    ) -> Result<Option<route::Route>, Error> {
+        let base_graph = self.base_graph.write().unwrap();
+        // TODO: this is necessary to make sure the base graph is constructed
+        let _ = base_graph.get_base_graph(&self.state);
+        Ok(route::best_route_between(
+            base_graph.get_thread_base_graph(),
+            start,
+            end,
+            car_config,
+        )?)
     }
 
     /**
      * Performs the same work as query_route, but passes the work off to a thread pool which sends
-     * the route response on a channel to the returned reciever when it finishes.
+     * the route response on a channel to the returned reciever when it finishes, along with the
+     * wall-clock time between calling this and the query finishing.
      */
     pub fn query_route_async(
         &self,
         query_input: route::QueryInput,
-    ) -> crossbeam::channel::Receiver<Result<Option<route::Route>, Error>> {
+    ) -> crossbeam::channel::Receiver<Result<TimedRoute, Error>> {
+        self.spawn_route_query(None, query_input)
+    }
+
+    /// Like query_route_async, but the outcome of the query is logged for the given agent.
+    pub(crate) fn query_agent_route_async(
+        &self,
+        agent: u64,
+        query_input: route::QueryInput,
+    ) -> crossbeam::channel::Receiver<Result<TimedRoute, Error>> {
+        self.spawn_route_query(Some(agent), query_input)
+    }
+
+    fn spawn_route_query(
+        &self,
+        agent: Option<u64>,
+        query_input: route::QueryInput,
+    ) -> crossbeam::channel::Receiver<Result<TimedRoute, Error>> {
         let (sender, receiver) = crossbeam::channel::bounded(1);
 
-        let base_graph = self.base_graph.clone();
+        let base_graph = self.base_graph.share();
+        let logs = self.agent_logs.sink();
+        let submitted = std::time::Instant::now();
+        let delay = self.route_query_delay;
 
         self.thread_pool.execute(move || {
+            let _logs = logs.enter();
+            if let Some(delay) = delay {
+                std::thread::sleep(delay);
+            }
             let base_graph = base_graph.read().unwrap();
             let route = route::best_route(base_graph.get_thread_base_graph(), query_input);
-            sender.send(route.map_err(|e| e.into())).unwrap();
+            if let Some(agent) = agent {
+                agent::agent_log(agent, || match &route {
+                    Ok(Some(_)) => format!("route query finished in {:?}", submitted.elapsed()),
+                    Ok(None) => String::from("route query found no route"),
+                    Err(err) => format!("route query failed: {}", err),
+                });
+            }
+            sender
+                .send(
+                    route
+                        .map(|route| (route, submitted.elapsed()))
+                        .map_err(|e| e.into()),
+                )
+                .unwrap();
+        });
+
+        receiver
+    }
+
+    /// How long async route queries have been taking, and how often agents had to wait for them.
+    pub fn route_latency(&self) -> &RouteLatency {
+        &self.route_latency
+    }
+
+    /**
+     * Make every async route query take at least the given wall-clock time, to see how the engine
+     * copes with slow queries. This is meant for tests.
+     */
+    pub fn set_route_query_delay(&mut self, delay: Option<std::time::Duration>) {
+        self.route_query_delay = delay;
+    }
+
+    /**
+     * How finely routes that start now will be advanced. Unless overridden with set_route_detail,
+     * routes are coarse whenever the playback rate is too fast to watch agents move anyway. Routes
+     * that are already underway keep the detail that they started with.
+     */
+    pub fn route_detail(&self) -> agent::RouteDetail {
+        self.route_detail
+            .unwrap_or(if self.time_state.should_render_motion() {
+                agent::RouteDetail::Fine
+            } else {
+                agent::RouteDetail::Coarse
+            })
+    }
+
+    /// Make new routes have the given detail regardless of the playback rate, e.g. for tests.
+    pub fn set_route_detail(&mut self, detail: Option<agent::RouteDetail>) {
+        self.route_detail = detail;
+    }
+
+    /**
+     * Pick a random housing tile and workplace tile, e.g. as the start and end of a route query.
+     * Fails if the map doesn't have any of either.
+     */
+    pub fn random_commute<R: rand::Rng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<(quadtree::Address, quadtree::Address), Error> {
+        use rand::seq::SliceRandom;
+
+        // make sure these lists are up-to-date
+        self.state.update_collect_tiles()?;
+        let collect_tiles = &self.state.collect_tiles;
+        let housing = collect_tiles
+            .housing
+            .choose(rng)
+            .ok_or(Error::NoTiles("housing"))?;
+        let workplace = collect_tiles
+            .workplaces
+            .choose(rng)
+            .ok_or(Error::NoTiles("workplace"))?;
+        Ok((*housing, *workplace))
+    }
+
+    /**
+     * Computes the cost of the best route from `start` to each of `candidates`, without
+     * constructing the routes. This is much cheaper than calling query_route for each candidate.
+     * Candidates that cannot be reached within `max_cost` (if specified) are None.
+     */
+    pub fn query_route_costs(
+        &self,
+        start: quadtree::Address,
+        candidates: &[quadtree::Address],
+        car_config: Option<route::CarConfig>,
+        max_cost: Option<f64>,
+    ) -> Result<Vec<Option<f64>>, Error> {
+        let base_graph = self.base_graph.read().unwrap();
+        Ok(route::best_route_one_to_many(
+            base_graph.get_base_graph(&self.state),
+            start,
+            candidates,
+            car_config,
+            max_cost,
+        )?)
+    }
+
+    /**
+     * Performs the same work as query_route_costs, but passes the work off to a thread pool which
+     * sends the costs on a channel to the returned reciever when it finishes.
+     */
+    pub fn query_route_costs_async(
+        &self,
+        start: quadtree::Address,
+        candidates: Vec<quadtree::Address>,
+        car_config: Option<route::CarConfig>,
+        max_cost: Option<f64>,
+    ) -> crossbeam::channel::Receiver<Result<Vec<Option<f64>>, Error>> {
+        let (sender, receiver) = crossbeam::channel::bounded(1);
+
+        // make sure the base graph is constructed before handing it off
+        let _ = self.base_graph.read().unwrap().get_base_graph(&self.state);
+        let base_graph = self.base_graph.share();
+        let logs = self.agent_logs.sink();
+
+        self.thread_pool.execute(move || {
+            let _logs = logs.enter();
+            let base_graph = base_graph.read().unwrap();
+            let costs = route::best_route_one_to_many(
+                &base_graph.get_thread_base_graph(),
+                start,
+                &candidates,
+                car_config,
+                max_cost,
+            );
+            sender.send(costs.map_err(|e| e.into())).unwrap();
         });
 
         receiver
@@ -376,14 +1692,17 @@ impl Engine {
         &self,
         focus: quadtree::Address,
         mode: route::Mode,
-    ) -> Result<route::Isochrone, Error> {
+        profile: route::MobilityProfile,
+    ) -> Result<route::IsochroneResult<route::Isochrone>, Error> {
         let base_graph = self.base_graph.write().unwrap();
         // TODO: this is necessary to make sure the base graph is constructed
         let _ = base_graph.get_base_graph(&self.state);
         Ok(route::calculate_isochrone(
             base_graph.get_thread_base_graph(),
+            &self.state,
             focus,
             mode,
+            profile,
         )?)
     }
 
@@ -391,13 +1710,16 @@ impl Engine {
         &self,
         focus: quadtree::Address,
         mode: route::Mode,
-    ) -> Result<route::IsochroneMap, Error> {
-        let isochrone = self.query_isochrone(focus, mode)?;
-        Ok(route::calculate_isochrone_map(
-            isochrone,
-            &self.state.config,
-            crate::field_update::BLOCK_SIZE,
-        )?)
+        profile: route::MobilityProfile,
+    ) -> Result<route::IsochroneResult<route::IsochroneMap>, Error> {
+        self.query_isochrone(focus, mode, profile)?
+            .try_map(|isochrone| {
+                Ok(route::calculate_isochrone_map(
+                    isochrone,
+                    &self.state.config,
+                    crate::field_update::BLOCK_SIZE,
+                )?)
+            })
     }
 
     /// Update history so that future predictions will use the new data.
@@ -405,6 +1727,7 @@ impl Engine {
     pub fn record_traffic_snapshot(&mut self) {
         self.world_state_history
             .take_snapshot(&self.world_state, self.time_state.current_time);
+        self.snapshots_since_history_save += 1;
     }
 
     /**
@@ -414,15 +1737,14 @@ impl Engine {
      */
     pub fn update_route_weights(&mut self, horizon: u64) {
         // predict future traffic
-        let predicted_state = &self
-            .world_state_history
-            .get_predictor(self.time_state.current_time + horizon);
+        let prediction_time = self.time_state.current_time + horizon;
+        let predicted_state = &self.world_state_history.get_predictor(prediction_time);
 
         let mut base_graph = self.base_graph.write().unwrap();
-        base_graph
-            .get_base_graph_mut(&self.state)
-            .graph
-            .update_weights(predicted_state, &self.state);
+        let graph = base_graph.get_base_graph_mut(&self.state);
+        graph.graph.update_weights(predicted_state, &self.state);
+        graph.version.traffic += 1;
+        graph.traffic_time = Some(prediction_time);
         // force the thread-local copies to be invalidated
         base_graph.clear_thread_cache();
     }
@@ -450,9 +1772,18 @@ impl Engine {
         receiver
     }
 
-    pub fn update_route_weights_async_callback(&mut self, graph: route::FastGraphWrapper) {
+    /// Install weights computed by update_route_weights_async, which predicted traffic for the
+    /// given time.
+    pub fn update_route_weights_async_callback(
+        &mut self,
+        graph: route::FastGraphWrapper,
+        prediction_time: u64,
+    ) {
         let mut base_graph = self.base_graph.write().unwrap();
-        base_graph.get_base_graph_mut(&self.state).graph = graph;
+        let base = base_graph.get_base_graph_mut(&self.state);
+        base.graph = graph;
+        base.version.traffic += 1;
+        base.traffic_time = Some(prediction_time);
         base_graph.clear_thread_cache();
     }
 
@@ -462,36 +1793,145 @@ impl Engine {
         base_graph.clear();
     }
 
+    /**
+     * Insert a station partway along a segment of a metro line, splitting the underlying railway
+     * segment. See metro::Metros::insert_station.
+     */
+    pub fn insert_metro_station(
+        &mut self,
+        metro_line: metro::MetroLineHandle,
+        segment_index: usize,
+        station: metro::Station,
+    ) -> Result<network::JunctionHandle, Error> {
+        let mut base_graph = self.base_graph.write().unwrap();
+        let junction = self.state.metros.insert_station(
+            metro_line,
+            segment_index,
+            station,
+            &mut self.state.railways,
+        )?;
+        base_graph.clear();
+        Ok(junction)
+    }
+
+    /**
+     * Reroute a metro line over a different chain of railway segments. See
+     * metro::Metros::update_line_segments. Routes that use the segments that the line no longer runs
+     * on are reported by route_uses_removed_segments, and agents already riding on them finish
+     * their trips.
+     */
+    pub fn reroute_metro_line(
+        &mut self,
+        metro_line: metro::MetroLineHandle,
+        segments: Vec<network::SegmentHandle>,
+    ) -> Result<metro::LineSegmentsDiff, Error> {
+        let mut base_graph = self.base_graph.write().unwrap();
+        let diff =
+            self.state
+                .metros
+                .update_line_segments(metro_line, segments, &self.state.railways)?;
+        base_graph.clear();
+
+        let unused = diff
+            .removed
+            .iter()
+            .copied()
+            .filter(|segment| {
+                self.state
+                    .metros
+                    .railway_segment_metro_lines(*segment)
+                    .is_empty()
+            })
+            .collect::<Vec<_>>();
+        self.world_state.drop_metro_segments(unused);
+
+        for (station, serves) in diff
+            .stations_removed
+            .iter()
+            .map(|station| (station, false))
+            .chain(diff.stations_added.iter().map(|station| (station, true)))
+        {
+            if let tiles::Tile::MetroStationTile(tile) =
+                &mut self.state.qtree.get_leaf_mut(station.address)?.tile
+            {
+                tile.ids.retain(|id| *id != metro_line.inner());
+                if serves {
+                    tile.ids.push(metro_line.inner());
+                }
+            }
+        }
+
+        Ok(diff)
+    }
+
+    /**
+     * Remove a station from a metro line, merging the railway segments on either side if possible.
+     * See metro::Metros::remove_station.
+     */
+    pub fn remove_metro_station(
+        &mut self,
+        metro_line: metro::MetroLineHandle,
+        station: &metro::Station,
+    ) -> Result<(), Error> {
+        let mut base_graph = self.base_graph.write().unwrap();
+        self.state
+            .metros
+            .remove_station(metro_line, station, &mut self.state.railways)?;
+        base_graph.clear();
+        Ok(())
+    }
+
+    /**
+     * Schedule the triggers that drive an agent's behavior, starting now. This is done for all
+     * agents by init_trigger_queue, so it is only needed for agents added after that.
+     */
+    pub fn schedule_agent(&mut self, agent: u64) {
+        let current_time = self.time_state.current_time;
+        self.trigger_queue
+            .push(crate::behavior::AgentLifeDecisions { agent }, current_time);
+
+        // start the day when the agent's shift starts
+        let day = state::TRAFFIC_HISTORY_PERIOD;
+        let shift_start = Time::new::<hour>(self.work_schedule(agent).start_hour).value;
+        let mut start = current_time - current_time % day + shift_start;
+        if start < current_time {
+            start += day;
+        }
+        self.trigger_queue
+            .push(crate::behavior::AgentPlanCommuteToWork { agent }, start);
+    }
+
     /**
      * Only adds triggers for a freshly-generated state, so that we don't clobber triggers when
      * loading a map. We do this here so that we don't need to regenerate the map every time we
-     * update the trigger queue. This should also make it easier to perform testing.
+     * update the trigger queue. This should also make it easier to perform testing. This must be
+     * called before running the simulation, including after loading a map, so that agents added
+     * later get scheduled too.
      */
     pub fn init_trigger_queue(&mut self) {
+        self.triggers_initialized = true;
         if self.time_state.current_time == 0 {
             self.trigger_queue.push(crate::behavior::UpdateFields {}, 0);
             self.trigger_queue
                 .push(crate::behavior::UpdateCollectTiles {}, 0);
             self.trigger_queue
                 .push(crate::behavior::UpdateTrafficSender {}, 0);
-            for agent in self.agents.values() {
-                self.trigger_queue
-                    .push(crate::behavior::AgentLifeDecisions { agent: agent.id }, 0);
-
-                // start the day at 8 am
-                self.trigger_queue.push(
-                    crate::behavior::AgentPlanCommuteToWork { agent: agent.id },
-                    Time::new::<hour>(8).value,
-                );
+            let agents: Vec<u64> = self.agents.keys().copied().collect();
+            for agent in agents {
+                self.schedule_agent(agent);
             }
             self.trigger_queue
                 .push(crate::behavior::WorkplaceDecisions {}, 0);
             self.trigger_queue
                 .push(crate::behavior::AdvanceNetworkTombstones {}, 0);
+            self.trigger_queue
+                .push(crate::behavior::EvaluateAlerts {}, 0);
         }
     }
 
     pub fn update(&mut self, elapsed: f64, time_budget: f64) -> Result<(), Error> {
+        let _span = tracing::info_span!("Engine::update", elapsed).entered();
+
         // try to jump forward an amount dictated by the playback rate
         let rate_step = (self.time_state.playback_rate as f64 * elapsed) as u64;
         // if we have recently skipped forward, try to catch up to the skip target time
@@ -524,8 +1964,18 @@ mod trigger_tests {
     fn doubling_trigger() {
         let mut engine = Engine::new(state::Config {
             max_depth: 3,
-            people_per_sim: 1,
+            people_per_sim: 1.0,
             min_tile_size: 1,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
         });
 
         // NOTE: all triggers have to be defined in the same crate, so we define the trigger in trigger.rs.
@@ -540,4 +1990,300 @@ mod trigger_tests {
             assert_eq!(engine.trigger_queue.len(), 2_usize.pow(i));
         }
     }
+
+    #[test]
+    fn tick_day() {
+        use uom::si::time::day;
+        use uom::si::u64::Time;
+
+        let mut engine = Engine::new(state::Config {
+            max_depth: 3,
+            people_per_sim: 1.0,
+            min_tile_size: 100,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        });
+        // some triggers expect the root to be a branch
+        engine
+            .state
+            .qtree
+            .split(
+                engine.state.qtree.get_address(0, 0).unwrap(),
+                state::BranchState::default(),
+                quadtree::QuadMap::each(state::LeafState::default),
+            )
+            .unwrap();
+        engine.init_trigger_queue();
+        // the trigger stats count how many times each kind of trigger was executed
+        engine.trigger_stats.enable_profiling();
+
+        let count = |engine: &Engine, kind| engine.trigger_stats.stats[kind].count;
+
+        engine.tick(Time::new::<day>(1).value).unwrap();
+        assert_eq!(engine.time_state.current_time, Time::new::<day>(1).value);
+
+        assert_eq!(count(&engine, TriggerKind::UpdateFields), 1);
+        assert_eq!(count(&engine, TriggerKind::AdvanceNetworkTombstones), 1);
+        assert_eq!(count(&engine, TriggerKind::WorkplaceDecisions), 1);
+        assert_eq!(count(&engine, TriggerKind::UpdateCollectTiles), 24);
+        assert_eq!(
+            count(&engine, TriggerKind::UpdateTrafficSender),
+            (Time::new::<day>(1).value / engine.world_state_history.snapshot_period()) as u128
+        );
+
+        // the daily triggers scheduled for the start of the next day haven't run yet
+        engine.tick(Time::new::<day>(1).value).unwrap();
+        assert_eq!(count(&engine, TriggerKind::UpdateFields), 2);
+        assert_eq!(count(&engine, TriggerKind::AdvanceNetworkTombstones), 2);
+        // runs every other day
+        assert_eq!(count(&engine, TriggerKind::WorkplaceDecisions), 1);
+        assert_eq!(count(&engine, TriggerKind::UpdateCollectTiles), 48);
+    }
+
+    #[test]
+    fn trigger_cadence() {
+        use uom::si::time::day;
+        use uom::si::u64::Time;
+
+        let mut engine = Engine::new(state::Config {
+            max_depth: 3,
+            people_per_sim: 1.0,
+            min_tile_size: 100,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: state::TriggerCadenceConfig {
+                update_fields: Time::new::<day>(2).value,
+                ..Default::default()
+            },
+        });
+        engine
+            .state
+            .qtree
+            .split(
+                engine.state.qtree.get_address(0, 0).unwrap(),
+                state::BranchState::default(),
+                quadtree::QuadMap::each(state::LeafState::default),
+            )
+            .unwrap();
+        engine.init_trigger_queue();
+        engine.trigger_stats.enable_profiling();
+
+        let count = |engine: &Engine, kind| engine.trigger_stats.stats[kind].count;
+
+        assert_eq!(
+            engine.trigger_cadence(TriggerKind::UpdateFields),
+            Some(Time::new::<day>(2).value)
+        );
+        assert_eq!(engine.trigger_cadence(TriggerKind::AgentRouteStart), None);
+
+        // on days 0, 2, 4, and 6
+        engine.tick(Time::new::<day>(7).value).unwrap();
+        assert_eq!(count(&engine, TriggerKind::UpdateFields), 4);
+        // the other triggers keep their default cadence
+        assert_eq!(count(&engine, TriggerKind::AdvanceNetworkTombstones), 7);
+
+        // the trigger already scheduled for day 8 still runs then, and new cadences apply after that
+        engine.state.config.trigger_cadence.update_fields = Time::new::<day>(1).value;
+        engine.tick(Time::new::<day>(4).value).unwrap();
+        assert_eq!(count(&engine, TriggerKind::UpdateFields), 7);
+    }
+
+    #[test]
+    fn peek_triggers() {
+        let mut engine = Engine::new(state::Config {
+            max_depth: 3,
+            people_per_sim: 1.0,
+            min_tile_size: 1,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        });
+
+        engine.trigger_queue.push(DummyTrigger {}, 30);
+        engine
+            .trigger_queue
+            .push(AgentPlanCommuteToWork { agent: 7 }, 10);
+        engine.trigger_queue.push(DoublingTrigger {}, 20);
+        engine.trigger_queue.push(DummyTrigger {}, 40);
+
+        let peeked: Vec<_> = engine
+            .peek_triggers(3)
+            .into_iter()
+            .map(|(time, trigger)| (time, TriggerKind::from(trigger), trigger.agent()))
+            .collect();
+        assert_eq!(
+            peeked,
+            vec![
+                (10, TriggerKind::AgentPlanCommuteToWork, Some(7)),
+                (20, TriggerKind::DoublingTrigger, None),
+                (30, TriggerKind::DummyTrigger, None),
+            ]
+        );
+
+        // peeking doesn't modify the queue
+        assert_eq!(engine.trigger_queue.len(), 4);
+        assert_eq!(engine.peek_triggers(10).len(), 4);
+        assert!(engine.peek_triggers(0).is_empty());
+    }
+
+    /**
+     * Run the workplace growth trigger many times on a map with a mix of every kind of tile, and
+     * make sure that it only ever builds where it is allowed to.
+     */
+    #[test]
+    fn workplace_decisions_preserve_tiles() {
+        use rand::{Rng, SeedableRng};
+
+        const MAX_DEPTH: u32 = 6;
+        const SPLIT_DEPTH: u32 = 3;
+
+        let mut engine = Engine::new(state::Config {
+            max_depth: MAX_DEPTH,
+            people_per_sim: 1.0,
+            min_tile_size: 100,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        });
+        engine.rng = rand_chacha::ChaCha12Rng::seed_from_u64(0);
+
+        let mut addresses = vec![quadtree::Address::from((vec![], MAX_DEPTH))];
+        for _ in 0..SPLIT_DEPTH {
+            for address in std::mem::take(&mut addresses) {
+                engine
+                    .state
+                    .qtree
+                    .split(
+                        address,
+                        state::BranchState::default(),
+                        quadtree::QuadMap::each(state::LeafState::default),
+                    )
+                    .unwrap();
+                addresses.extend(quadtree::QUADRANTS.map(|quadrant| address.child(quadrant)));
+            }
+        }
+
+        let mut water = vec![];
+        let mut housing = vec![];
+        let mut workplaces = vec![];
+        for address in addresses {
+            let (x, y) = address.to_xy();
+            let tile: tiles::Tile = match engine.rng.gen_range(0..5) {
+                0 => {
+                    water.push(address);
+                    tiles::WaterTile {}.into()
+                }
+                1 => {
+                    let name = format!("{}, {}", x, y);
+                    engine.state.railways.add_junction(
+                        (x as f64, y as f64),
+                        metro::RailwayJunction::new(Some(metro::Station {
+                            name: name.clone(),
+                            address,
+                        })),
+                    );
+                    tiles::MetroStationTile {
+                        name,
+                        x,
+                        y,
+                        ids: vec![],
+                        parking: true,
+                    }
+                    .into()
+                }
+                2 => {
+                    housing.push(address);
+                    tiles::HousingTile {
+                        density: 1,
+                        agents: vec![],
+                    }
+                    .into()
+                }
+                3 => {
+                    workplaces.push(address);
+                    tiles::WorkplaceTile {
+                        density: 1,
+                        agents: vec![],
+                        industry: Default::default(),
+                    }
+                    .into()
+                }
+                _ => continue,
+            };
+            engine.state.qtree.get_leaf_mut(address).unwrap().tile = tile;
+        }
+        // fully-staffed workplaces drive demand for more
+        for (housing, workplace) in housing.iter().zip(&workplaces) {
+            let data = crate::AgentDataDistribution::default().sample(&mut engine.rng);
+            engine.add_agent(data, *housing, Some(*workplace)).unwrap();
+        }
+
+        let stations: Vec<metro::Station> = engine
+            .state
+            .railways
+            .junctions()
+            .values()
+            .filter_map(|junction| junction.data.station.clone())
+            .collect();
+        assert!(!water.is_empty() && !stations.is_empty());
+
+        let count_workplaces = |engine: &mut Engine| {
+            engine.state.update_collect_tiles().unwrap();
+            engine.state.collect_tiles.workplaces.len()
+        };
+        let initial_workplaces = count_workplaces(&mut engine);
+
+        for i in 0..1000 {
+            if i % 50 == 0 {
+                engine.update_fields().unwrap();
+                // enough demand for one new workplace each time
+                let root = quadtree::Address::from((vec![], MAX_DEPTH));
+                let root = engine.state.qtree.get_branch_mut(root).unwrap();
+                root.fields.raw_demand.raw_workplace_demand.count = 100;
+            }
+            WorkplaceDecisions {}.execute(&mut engine, 0).unwrap();
+
+            for address in &water {
+                let leaf = engine.state.qtree.get_leaf(*address).unwrap();
+                assert!(matches!(leaf.tile, tiles::Tile::WaterTile(_)));
+            }
+            for station in &stations {
+                let leaf = engine.state.qtree.get_leaf(station.address).unwrap();
+                assert!(matches!(&leaf.tile, tiles::Tile::MetroStationTile(tile)
+                    if tile.name == station.name));
+            }
+        }
+
+        assert!(count_workplaces(&mut engine) > initial_workplaces);
+        engine.consistency_check().unwrap();
+    }
 }
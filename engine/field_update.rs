@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use once_cell::unsync::OnceCell;
 use rand::distributions::weighted::WeightedError;
 use rand_distr::weighted_alias::WeightedAliasIndex;
@@ -11,15 +13,74 @@ use crate::fields::{FieldPass, FieldsComputationData, FieldsState, WeightedAvera
 // size of downsampled block, in meters. important for getting good performance out of the blur.
 pub(crate) const BLOCK_SIZE: f32 = 200.0;
 
+/// Built-in blurred fields, which are registered on every engine.
+pub const LAND_VALUE: &str = "land_value";
+pub const CONSTRUCTION_COST: &str = "construction_cost";
+pub const WORKPLACE_DEMAND: &str = "workplace_demand";
+
+/**
+ * Describes a scalar to extract from the fields of each leaf and blur. Blurring happens after the
+ * first field pass, so the getter should only read fields computed in that pass.
+ */
+#[derive(Clone, Copy)]
+pub struct BlurredFieldSpec {
+    pub getter: fn(&FieldsState) -> f64,
+    /// if set, the blurred value is written back into the fields of each leaf before the second
+    /// field pass
+    pub setter: Option<fn(&mut FieldsState) -> &mut WeightedAverage>,
+    /// the blurring radius, in meters
+    pub radius: f32,
+    /// Values are multiplied by this and converted to u8 before blurring, so it should map the
+    /// expected range of values onto [0, 255].
+    pub scale: f64,
+}
+
+impl std::fmt::Debug for BlurredFieldSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlurredFieldSpec")
+            .field("has_setter", &self.setter.is_some())
+            .field("radius", &self.radius)
+            .field("scale", &self.scale)
+            .finish()
+    }
+}
+
+/// A blurred raster of a field, downsampled into blocks of several tiles.
 #[derive(Debug, Clone, Default)]
-pub(crate) struct BlurredField {
+pub struct BlurredField {
     buffer: Vec<u8>,
     dim: u64,
     downsample: u64,
+    scale: f64,
     distr: OnceCell<Option<WeightedAliasIndex<u64>>>,
 }
 
 impl BlurredField {
+    /// The blurred value at the given tile, or None if it is out of bounds or the field has not
+    /// been computed yet.
+    pub fn value_at(&self, x: u64, y: u64) -> Option<f64> {
+        if self.downsample == 0 {
+            return None;
+        }
+        let (x, y) = (x / self.downsample, y / self.downsample);
+        if x >= self.dim || y >= self.dim {
+            return None;
+        }
+        Some(self.buffer[coords_to_index(x, y, self.dim)] as f64 / self.scale)
+    }
+
+    /// The largest blurred value anywhere on the map, e.g. for scaling an overlay.
+    pub fn max_value(&self) -> f64 {
+        self.buffer.iter().copied().max().unwrap_or(0) as f64 / self.scale
+    }
+
+    /// The sum of the blurred values over every tile on the map.
+    pub fn total(&self) -> f64 {
+        let sum: u64 = self.buffer.iter().map(|x| *x as u64).sum();
+        sum as f64 * self.downsample.pow(2) as f64 / self.scale
+    }
+
+    /// Pick a tile at random, weighted by the blurred value.
     pub fn sample<R: rand::Rng>(
         &self,
         rng: &mut R,
@@ -37,9 +98,11 @@ impl BlurredField {
                 Ok(distr) => Some(distr),
                 Err(WeightedError::AllWeightsZero) => {
                     // this is... fine, I guess?
-                    eprintln!("all workplace demand weights zero");
+                    eprintln!("all blurred field weights zero");
                     None
                 }
+                // the field hasn't been computed yet, so there is nothing to sample from
+                Err(WeightedError::NoItem) => None,
                 // other errors are not fine
                 Err(err) => {
                     panic!("Error creating weighted index distribution: {}", err)
@@ -64,92 +127,292 @@ impl BlurredField {
             qtree.get_address(x, y).unwrap()
         })
     }
+
+    /**
+     * Like sample, but rejects tiles for which `accept` returns false, e.g. to avoid building on
+     * water. Gives up and returns None after `max_attempts` rejections.
+     */
+    pub fn sample_where<R, F>(
+        &self,
+        rng: &mut R,
+        qtree: &quadtree::Quadtree<BranchState<FieldsState>, LeafState<FieldsState>>,
+        max_attempts: usize,
+        accept: F,
+    ) -> Option<quadtree::Address>
+    where
+        R: rand::Rng,
+        F: Fn(&LeafState<FieldsState>) -> bool,
+    {
+        for _ in 0..max_attempts {
+            let address = self.sample(rng, qtree)?;
+            if qtree.get_leaf(address).map(&accept).unwrap_or(false) {
+                return Some(address);
+            }
+        }
+        None
+    }
 }
 
-#[derive(Debug, Clone, Default)]
+/**
+ * The registry of blurred fields, which are all recomputed after the first field pass. Fields are
+ * blurred in the order that they were registered.
+ *
+ * NOTE: this is not serialized, so fields registered at runtime need to be registered again after
+ * loading a map.
+ */
+#[derive(Debug, Clone)]
 pub(crate) struct BlurredFields {
-    pub land_value: BlurredField,
-    pub construction_cost: BlurredField,
-    pub workplace_demand: BlurredField,
+    fields: Vec<(String, BlurredFieldSpec, BlurredField)>,
 }
 
-impl Engine {
-    fn perform_blur_weighted_average<G, S>(
-        field: &mut BlurredField,
-        qtree: &mut quadtree::Quadtree<BranchState<FieldsState>, LeafState<FieldsState>>,
-        config: &state::Config,
-        getter: G,
-        setter: S,
-        radius: f32,
-        block_size: f32,
-        scale: f64,
-    ) -> Result<(), Error>
-    where
-        G: Fn(&FieldsState) -> &WeightedAverage,
-        S: Fn(&mut FieldsState) -> &mut WeightedAverage,
-    {
-        perform_field_blur(
-            field,
-            qtree,
-            config,
-            |f| (getter(f).value * scale) as u8,
-            |f, v, data| {
-                *setter(f) = WeightedAverage {
-                    value: v as f64 / scale,
-                    count: data.width.pow(2) as usize,
-                }
+impl Default for BlurredFields {
+    fn default() -> Self {
+        let mut fields = Self { fields: Vec::new() };
+        fields.fields.push((
+            LAND_VALUE.to_string(),
+            BlurredFieldSpec {
+                getter: |f| f.raw_land_value.raw_land_value.value,
+                setter: Some(|f| &mut f.land_value.land_value),
+                radius: 800.0,
+                scale: 1.0,
+            },
+            BlurredField::default(),
+        ));
+        fields.fields.push((
+            CONSTRUCTION_COST.to_string(),
+            BlurredFieldSpec {
+                getter: |f| f.raw_land_value.raw_construction_cost.value,
+                setter: Some(|f| &mut f.land_value.construction_cost),
+                radius: 300.0,
+                scale: 1.0,
             },
-            radius,
-            block_size,
-        )
+            BlurredField::default(),
+        ));
+        fields.fields.push((
+            WORKPLACE_DEMAND.to_string(),
+            BlurredFieldSpec {
+                getter: |f| f.raw_demand.raw_workplace_demand.value,
+                setter: Some(|f| &mut f.demand.workplace_demand),
+                radius: 600.0,
+                scale: 30.0,
+            },
+            BlurredField::default(),
+        ));
+        fields
+    }
+}
+
+impl BlurredFields {
+    pub fn get(&self, name: &str) -> Option<&BlurredField> {
+        self.fields
+            .iter()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, _, field)| field)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(name, _, _)| name.as_str())
+    }
+}
+
+impl Engine {
+    /**
+     * Register a field to be blurred each time the fields are updated. It can then be sampled by
+     * name, e.g. by the growth triggers, and drawn as an overlay.
+     */
+    pub fn register_blurred_field(
+        &mut self,
+        name: &str,
+        spec: BlurredFieldSpec,
+    ) -> Result<(), Error> {
+        if self.blurred_fields.get(name).is_some() {
+            return Err(Error::DuplicateBlurredField(name.to_string()));
+        }
+        self.blurred_fields
+            .fields
+            .push((name.to_string(), spec, BlurredField::default()));
+        Ok(())
+    }
+
+    pub fn blurred_field(&self, name: &str) -> Option<&BlurredField> {
+        self.blurred_fields.get(name)
+    }
+
+    /// The names of all registered blurred fields, in the order that they are computed.
+    pub fn blurred_field_names(&self) -> Vec<String> {
+        self.blurred_fields.names().map(str::to_string).collect()
+    }
+
+    /**
+     * The cost of building the given railway segment, in units of the cost of a meter of at-grade
+     * track on empty land. Follows the local construction cost along the segment, which is one
+     * everywhere until the fields have been computed, and scales it by the alignment multiplier.
+     */
+    pub fn railway_construction_cost(
+        &self,
+        segment: &network::Segment<metro::RailwaySegment>,
+    ) -> f64 {
+        let config = &self.state.config;
+        let field = self.blurred_fields.get(CONSTRUCTION_COST);
+        let local_costs: Vec<f64> = segment
+            .sample_polyline(1.0)
+            .into_iter()
+            .map(|(x, y)| {
+                field
+                    .and_then(|field| field.value_at(x.max(0.0) as u64, y.max(0.0) as u64))
+                    .unwrap_or(1.0)
+                    .max(1.0)
+            })
+            .collect();
+        let average_cost = if local_costs.is_empty() {
+            1.0
+        } else {
+            local_costs.iter().sum::<f64>() / local_costs.len() as f64
+        };
+        let meters = segment.length() * config.min_tile_size as f64;
+        meters * average_cost * config.railways.get(segment.data.alignment).cost_multiplier
     }
 
     pub fn update_fields(&mut self) -> Result<(), Error> {
+        let _span = tracing::debug_span!("update_fields").entered();
+
         // TODO: Pass in more pieces of state once that is necessary. It's not possible to pass all
         // of Engine because it can't be borrowed both mutably and immutably at the same time.
+        let railway_penalties = railway_land_value_penalties(&self.state);
         let mut fold = UpdateFieldsFold::new(FieldsComputationData {
             config: &self.state.config,
             agents: &self.agents,
+            railway_penalties: &railway_penalties,
+            current_date: self.time_state.current_date(),
+            current_time: self.time_state.current_time,
         });
 
         fold.run_pass(&mut self.state.qtree, FieldPass::First)?;
 
-        Self::perform_blur_weighted_average(
-            &mut self.blurred_fields.land_value,
-            &mut self.state.qtree,
-            &self.state.config,
-            |f| &f.raw_land_value.raw_land_value,
-            |f| &mut f.land_value.land_value,
-            800.0,
-            BLOCK_SIZE,
-            1.0,
-        )?;
-
-        Self::perform_blur_weighted_average(
-            &mut self.blurred_fields.construction_cost,
-            &mut self.state.qtree,
-            &self.state.config,
-            |f| &f.raw_land_value.raw_construction_cost,
-            |f| &mut f.land_value.construction_cost,
-            300.0,
-            BLOCK_SIZE,
-            1.0,
-        )?;
-
-        Self::perform_blur_weighted_average(
-            &mut self.blurred_fields.workplace_demand,
-            &mut self.state.qtree,
-            &self.state.config,
-            |f| &f.raw_demand.raw_workplace_demand,
-            |f| &mut f.demand.workplace_demand,
-            600.0,
-            BLOCK_SIZE,
-            30.0,
-        )?;
+        for (name, spec, field) in &mut self.blurred_fields.fields {
+            let _span = tracing::debug_span!("blur", name = name.as_str()).entered();
+            perform_field_blur(
+                field,
+                &mut self.state.qtree,
+                &self.state.config,
+                *spec,
+                BLOCK_SIZE,
+            )?;
+        }
 
         // second pass runs after blurs
         fold.run_pass(&mut self.state.qtree, FieldPass::Second)?;
 
+        self.state.development = development_grid(&self.state)?;
+
+        self.fields_version += 1;
+        self.refresh_stats_snapshot()
+    }
+}
+
+/**
+ * The land value penalty of each leaf that a railway passes through, depending on the alignment of
+ * the railway. If several railways pass through the same leaf, the largest penalty wins.
+ */
+fn railway_land_value_penalties(
+    state: &state::State<FieldsState>,
+) -> HashMap<quadtree::Address, f64> {
+    let mut penalties = HashMap::new();
+    for segment in state.railways.segments().values() {
+        // closed railways are still there, even if nothing runs on them for now
+        if !segment.change_state.is_active() && segment.change_state.closed_until().is_none() {
+            continue;
+        }
+        let penalty = state
+            .config
+            .railways
+            .get(segment.data.alignment)
+            .land_value_penalty;
+        if penalty <= 0.0 {
+            continue;
+        }
+        // sample at least once per tile so that no tile along the way is skipped
+        for (x, y) in segment.sample_polyline(0.5) {
+            if x < 0.0 || y < 0.0 {
+                continue;
+            }
+            if let Ok(address) = state.qtree.get_address(x as u64, y as u64) {
+                let entry = penalties.entry(address).or_insert(0.0);
+                *entry = f64::max(*entry, penalty);
+            }
+        }
+    }
+    penalties
+}
+
+/**
+ * The number of residents and workers per square kilometer in each block of the local traffic grid,
+ * from the fields that were just computed.
+ */
+fn development_grid(state: &state::State<FieldsState>) -> Result<state::DevelopmentGrid, Error> {
+    let downsample = route::local_traffic::grid_downsample(&state.config);
+    let mut visitor = DevelopmentVisitor {
+        grid: state::DevelopmentGrid::new(state.config.tile_width() / downsample, downsample),
+        // real people per simulated person, per square kilometer of a block
+        scale: state.config.people_per_sim
+            / ((downsample * state.config.min_tile_size) as f64 / 1000.0).powi(2),
+    };
+    state.qtree.visit(&mut visitor)?;
+    Ok(visitor.grid)
+}
+
+struct DevelopmentVisitor {
+    grid: state::DevelopmentGrid,
+    scale: f64,
+}
+
+impl DevelopmentVisitor {
+    /// Spread the people in a node evenly across the blocks that it covers.
+    fn add(&mut self, fields: &FieldsState, data: &VisitData) {
+        let people = fields.population.people.total + fields.employment.workers.total;
+        if people == 0 {
+            return;
+        }
+        let downsample = self.grid.downsample() as u64;
+        let blocks = (data.width / downsample).max(1);
+        let density = people as f64 * self.scale / (blocks * blocks) as f64;
+        for y in 0..blocks {
+            for x in 0..blocks {
+                *self.grid.get_mut(
+                    (data.x / downsample + x) as i64,
+                    (data.y / downsample + y) as i64,
+                ) += density;
+            }
+        }
+    }
+}
+
+impl quadtree::Visitor<BranchState<FieldsState>, LeafState<FieldsState>, Error>
+    for DevelopmentVisitor
+{
+    fn visit_branch_pre(
+        &mut self,
+        branch: &BranchState<FieldsState>,
+        data: &VisitData,
+    ) -> Result<bool, Error> {
+        // branches as wide as a block already have the totals for the whole block
+        if data.width <= self.grid.downsample() as u64 {
+            self.add(&branch.fields, data);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn visit_leaf(&mut self, leaf: &LeafState<FieldsState>, data: &VisitData) -> Result<(), Error> {
+        self.add(&leaf.fields, data);
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &BranchState<FieldsState>,
+        _data: &VisitData,
+    ) -> Result<(), Error> {
         Ok(())
     }
 }
@@ -167,6 +430,7 @@ impl<'a, 'b> UpdateFieldsFold<'a, 'b> {
         }
     }
 
+    #[tracing::instrument(level = "debug", skip(self, qtree))]
     fn run_pass(
         &mut self,
         qtree: &mut quadtree::Quadtree<BranchState<FieldsState>, LeafState<FieldsState>>,
@@ -189,6 +453,7 @@ impl<'a, 'b>
     ) -> Result<(bool, FieldsState), Error> {
         let changed = leaf.fields.compute_leaf(
             &leaf.tile,
+            leaf.poi.as_ref(),
             leaf.creation_time,
             data,
             &self.field_computation_data,
@@ -224,35 +489,28 @@ pub(crate) fn index_to_coords(index: usize, dim: u64) -> (u64, u64) {
 }
 
 /**
- * Blur a field value.
+ * Blur a field value, and write the result back into the fields if the spec has a setter.
  *
- * Important: this function converts input values to u8. So make sure that the inputs work with
- * that.
+ * Important: this function converts scaled input values to u8. So make sure that the scale works
+ * with that.
  *
  * Arguments:
+ *   field: the blurred raster to fill in
  *   qtree: the qtree with fields to blur
  *   config: the map config
- *   getter: getter for accessing the raw field value from FieldsState
- *   setter: setter for setting the output field value in FieldsState
- *   radius: the blurring radius, in meters
+ *   spec: how to read and write the field value from FieldsState
  *   block_size: the width of downsampled block, in meters
  */
-fn perform_field_blur<G, S>(
+fn perform_field_blur(
     field: &mut BlurredField,
     qtree: &mut quadtree::Quadtree<BranchState<FieldsState>, LeafState<FieldsState>>,
     config: &state::Config,
-    getter: G,
-    setter: S,
-    radius: f32,
+    spec: BlurredFieldSpec,
     block_size: f32,
-) -> Result<(), Error>
-where
-    G: Fn(&FieldsState) -> u8,
-    S: Fn(&mut FieldsState, u8, &VisitData),
-{
+) -> Result<(), Error> {
     // round to power of two
     let downsample = config.even_downsample(block_size) as u64;
-    let sigma = radius / config.min_tile_size as f32 / downsample as f32;
+    let sigma = spec.radius / config.min_tile_size as f32 / downsample as f32;
     // from here on out, don't use radius and block_size, just use downsample and sigma
 
     let dim = qtree.width() / downsample;
@@ -263,9 +521,12 @@ where
         field.buffer.fill(0);
     }
 
+    let scale = spec.scale;
+    let getter = spec.getter;
     let mut input_visitor = BlurInputVisitor {
         buffer: &mut field.buffer,
-        getter,
+        // NOTE: casting saturates, so values that are out of range are clamped
+        getter: |f: &FieldsState| (getter(f) * scale) as u8,
         dim,
         downsample,
     };
@@ -279,16 +540,24 @@ where
         sigma,
     );
 
-    let mut output_visitor = BlurOutputVisitor {
-        buffer: &mut field.buffer,
-        setter,
-        dim,
-        downsample,
-    };
-    qtree.visit_mut(&mut output_visitor)?;
+    if let Some(setter) = spec.setter {
+        let mut output_visitor = BlurOutputVisitor {
+            buffer: &mut field.buffer,
+            setter: |f: &mut FieldsState, v: u8, data: &VisitData| {
+                *setter(f) = WeightedAverage {
+                    value: v as f64 / scale,
+                    count: data.width.pow(2) as usize,
+                }
+            },
+            dim,
+            downsample,
+        };
+        qtree.visit_mut(&mut output_visitor)?;
+    }
 
     field.dim = dim;
     field.downsample = downsample;
+    field.scale = scale;
     // reset the distribution so that we lazily compute it again as needed
     field.distr.take();
 
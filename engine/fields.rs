@@ -1,9 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use serde::{Deserialize, Serialize};
 
 struct ComputeLeafData<'a, 'b, 'c, 'd, 'e, 'f> {
     tile: &'a tiles::Tile,
+    poi: Option<&'a tiles::Poi>,
     creation_time: i64,
     data: &'b quadtree::VisitData,
     extra: &'c FieldsComputationData<'d, 'e>,
@@ -50,6 +51,68 @@ impl SimpleDensity {
     }
 }
 
+/// One value for each industry.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ByIndustry<T>([T; tiles::Industry::COUNT]);
+
+impl<T> ByIndustry<T> {
+    fn each<F: FnMut(tiles::Industry) -> T>(f: F) -> Self {
+        Self(tiles::Industry::ALL.map(f))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (tiles::Industry, &T)> {
+        tiles::Industry::ALL.into_iter().zip(self.0.iter())
+    }
+}
+
+impl<T> std::ops::Index<tiles::Industry> for ByIndustry<T> {
+    type Output = T;
+
+    fn index(&self, industry: tiles::Industry) -> &T {
+        &self.0[industry.index()]
+    }
+}
+
+impl<T: std::ops::Add<Output = T> + Copy> std::ops::Add for ByIndustry<T> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::each(|industry| self[industry] + other[industry])
+    }
+}
+
+/// One value for each age bucket.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ByAgeBucket<T>([T; agent::AgeBucket::COUNT]);
+
+impl<T> ByAgeBucket<T> {
+    pub fn iter(&self) -> impl Iterator<Item = (agent::AgeBucket, &T)> {
+        agent::AgeBucket::ALL.into_iter().zip(self.0.iter())
+    }
+}
+
+impl<T> std::ops::Index<agent::AgeBucket> for ByAgeBucket<T> {
+    type Output = T;
+
+    fn index(&self, bucket: agent::AgeBucket) -> &T {
+        &self.0[bucket.index()]
+    }
+}
+
+impl<T> std::ops::IndexMut<agent::AgeBucket> for ByAgeBucket<T> {
+    fn index_mut(&mut self, bucket: agent::AgeBucket) -> &mut T {
+        &mut self.0[bucket.index()]
+    }
+}
+
+impl<T: std::ops::Add<Output = T> + Copy> std::ops::Add for ByAgeBucket<T> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(agent::AgeBucket::ALL.map(|bucket| self[bucket] + other[bucket]))
+    }
+}
+
 #[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct WeightedAverage {
     pub value: f64,
@@ -85,15 +148,24 @@ impl std::ops::Add for WeightedAverage {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
+        // An average with no samples has no meaningful value, so it shouldn't contribute anything.
+        // This matters because multiplying by a share of zero would still propagate NaN or inf.
+        match (self.count, other.count) {
+            (0, 0) => return Self::zero(),
+            (0, _) => return other,
+            (_, 0) => return self,
+            _ => (),
+        }
+        debug_assert!(
+            self.value.is_finite() && other.value.is_finite(),
+            "adding non-finite weighted averages: {:?} + {:?}",
+            self,
+            other
+        );
+
         let total = self.count + other.count;
-        let (self_share, other_share) = if total > 0 {
-            (
-                self.count as f64 / total as f64,
-                other.count as f64 / total as f64,
-            )
-        } else {
-            (0.0, 0.0)
-        };
+        let self_share = self.count as f64 / total as f64;
+        let other_share = other.count as f64 / total as f64;
         Self {
             value: self.value * self_share + other.value * other_share,
             count: total,
@@ -130,6 +202,8 @@ impl From<i64> for MinMax {
 #[derive(Debug, Default, Copy, Clone, PartialEq, derive_more::Add)]
 pub struct Population {
     pub people: SimpleDensity,
+    /// the number of people living here in each age bucket, which add up to the total people
+    pub people_by_age: ByAgeBucket<usize>,
     pub housing: SimpleDensity,
     // NOTE: this is stored in Population rather than Employment because it is based on where people
     // live, not where they work
@@ -159,6 +233,15 @@ impl Population {
         self.housing.total - self.people.total
     }
 
+    /// the fraction of people in the given age bucket, or zero if nobody lives here
+    pub fn age_share(&self, bucket: agent::AgeBucket) -> f64 {
+        if self.people.total > 0 {
+            self.people_by_age[bucket] as f64 / self.people.total as f64
+        } else {
+            0.0
+        }
+    }
+
     /// the fraction of people that have jobs
     pub fn employment_rate(&self) -> f64 {
         // NOTE: the unemployment rate is not the opposite of this, it should take into account
@@ -174,6 +257,7 @@ impl Population {
 impl Field for Population {
     fn compute_leaf(leaf: ComputeLeafData) -> Option<Self> {
         let mut people = 0;
+        let mut people_by_age = ByAgeBucket::<usize>::default();
         let mut housing = 0;
         let mut employed_people = 0;
         let mut workplace_happiness = WeightedAverage::zero();
@@ -185,10 +269,14 @@ impl Field for Population {
             housing = *density;
             for agent_id in agents {
                 let agent = leaf.extra.agents.get(agent_id).expect("missing agent");
+                people_by_age[agent.data.age(leaf.extra.current_date).bucket()] += 1;
                 if agent.workplace.is_some() {
                     employed_people += 1;
-                    workplace_happiness
-                        .add_sample(agent.workplace_happiness_score().unwrap() as f64);
+                    workplace_happiness.add_sample(
+                        agent
+                            .workplace_happiness_score(&leaf.extra.config, leaf.extra.current_time)
+                            .unwrap() as f64,
+                    );
                     commute_duration.add_sample(agent.average_commute_length() as f64);
                 }
                 car_ownership.add_sample(agent.owns_car() as u64 as f64);
@@ -197,6 +285,7 @@ impl Field for Population {
 
         Some(Self {
             people: SimpleDensity::from_total(people, leaf.data),
+            people_by_age,
             housing: SimpleDensity::from_total(housing, leaf.data),
             employed_people,
             workplace_happiness,
@@ -216,6 +305,7 @@ impl Field for Population {
 pub struct Employment {
     pub workers: SimpleDensity,
     pub jobs: SimpleDensity,
+    pub jobs_by_industry: ByIndustry<SimpleDensity>,
     pub workplace_happiness: WeightedAverage,
     pub commute_duration: WeightedAverage,
 }
@@ -245,15 +335,26 @@ impl Field for Employment {
     fn compute_leaf(leaf: ComputeLeafData) -> Option<Self> {
         let mut workers = 0;
         let mut jobs = 0;
+        let mut jobs_industry = None;
         let mut workplace_happiness = WeightedAverage::zero();
         let mut commute_duration = WeightedAverage::zero();
 
-        if let tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { density, agents }) = leaf.tile {
+        if let tiles::Tile::WorkplaceTile(tiles::WorkplaceTile {
+            density,
+            agents,
+            industry,
+        }) = leaf.tile
+        {
             workers = agents.len();
             jobs = *density;
+            jobs_industry = Some(*industry);
             for agent_id in agents {
                 let agent = leaf.extra.agents.get(agent_id).expect("missing agent");
-                workplace_happiness.add_sample(agent.workplace_happiness_score().unwrap() as f64);
+                workplace_happiness.add_sample(
+                    agent
+                        .workplace_happiness_score(&leaf.extra.config, leaf.extra.current_time)
+                        .unwrap() as f64,
+                );
                 commute_duration.add_sample(agent.average_commute_length() as f64);
             }
         }
@@ -261,6 +362,14 @@ impl Field for Employment {
         Some(Self {
             workers: SimpleDensity::from_total(workers, leaf.data),
             jobs: SimpleDensity::from_total(jobs, leaf.data),
+            jobs_by_industry: ByIndustry::each(|industry| {
+                let total = if jobs_industry == Some(industry) {
+                    jobs
+                } else {
+                    0
+                };
+                SimpleDensity::from_total(total, leaf.data)
+            }),
             workplace_happiness,
             commute_duration,
         })
@@ -322,6 +431,21 @@ impl Field for RawLandValue {
             }
         }
 
+        // parks and schools make the land around them more desirable, which the blur spreads to
+        // the surrounding tiles
+        match leaf.poi.map(|poi| poi.kind) {
+            Some(tiles::PoiKind::Park) => raw_land_value.value += 200.0,
+            Some(tiles::PoiKind::School) => raw_land_value.value += 100.0,
+            Some(tiles::PoiKind::Hospital | tiles::PoiKind::Civic) | None => (),
+        }
+
+        // nobody wants to live next to the tracks, except at the station
+        if !matches!(leaf.tile, tiles::Tile::MetroStationTile(_)) {
+            if let Some(penalty) = leaf.extra.railway_penalties.get(&leaf.data.address) {
+                raw_land_value.value *= 1.0 - penalty;
+            }
+        }
+
         // this is a cost multiplier
         assert!(raw_construction_cost.value >= 1.0);
 
@@ -340,6 +464,39 @@ impl Field for RawLandValue {
     }
 }
 
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, derive_more::Add)]
+pub struct Terrain {
+    /// area covered by water
+    pub water: SimpleDensity,
+}
+
+impl Terrain {
+    /// the fraction of the area that is covered by water, e.g. for drawing coastlines when zoomed out
+    pub fn water_fraction(&self) -> f64 {
+        if self.water.area > 0 {
+            self.water.density()
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Field for Terrain {
+    fn compute_leaf(leaf: ComputeLeafData) -> Option<Self> {
+        let water_area = match leaf.tile {
+            tiles::Tile::WaterTile(_) => leaf.data.width.pow(2) as usize,
+            _ => 0,
+        };
+        Some(Self {
+            water: SimpleDensity::from_total(water_area, leaf.data),
+        })
+    }
+
+    fn compute_branch(branch: ComputeBranchData) -> Option<Self> {
+        Some(sum_iter(branch.fields.values().iter().map(|f| f.terrain)))
+    }
+}
+
 #[derive(Debug, Default, Copy, Clone, PartialEq, derive_more::Add)]
 pub struct RawDemand {
     pub raw_workplace_demand: WeightedAverage,
@@ -409,6 +566,25 @@ pub enum FieldPass {
     Second,
 }
 
+/// Invokes the given macro with every floating point value in FieldsState.
+macro_rules! for_each_float_field {
+    ($m:ident) => {
+        $m!(
+            population.workplace_happiness,
+            population.commute_duration,
+            population.car_ownership,
+            employment.workplace_happiness,
+            employment.commute_duration,
+            raw_land_value.raw_land_value,
+            raw_land_value.raw_construction_cost,
+            raw_demand.raw_workplace_demand,
+            land_value.land_value,
+            land_value.construction_cost,
+            demand.workplace_demand,
+        )
+    };
+}
+
 // TODO: write a procedural macro to make this less painful
 #[derive(Debug, Default, Clone, PartialEq)]
 #[non_exhaustive]
@@ -416,6 +592,7 @@ pub struct FieldsState {
     pub population: Population,
     pub employment: Employment,
     pub raw_land_value: RawLandValue,
+    pub terrain: Terrain,
     pub raw_demand: RawDemand,
     pub land_value: LandValue,
     pub demand: Demand,
@@ -424,13 +601,20 @@ pub struct FieldsState {
 // TODO: it could make sense to split this out of Engine into a separate state, like State
 pub struct FieldsComputationData<'a, 'b> {
     pub config: &'a state::Config,
-    pub agents: &'b HashMap<u64, agent::Agent>,
+    pub agents: &'b BTreeMap<u64, agent::Agent>,
+    /// the land value penalty for each leaf that a railway passes through
+    pub railway_penalties: &'b HashMap<quadtree::Address, f64>,
+    /// the date that ages are computed at, which is the same for the whole pass
+    pub current_date: chrono::NaiveDate,
+    /// the time that workplace happiness is computed at
+    pub current_time: u64,
 }
 
 impl FieldsState {
     pub(crate) fn compute_leaf<'a, 'b>(
         &mut self,
         tile: &tiles::Tile,
+        poi: Option<&tiles::Poi>,
         creation_time: i64,
         data: &quadtree::VisitData,
         extra: &FieldsComputationData<'a, 'b>,
@@ -442,6 +626,7 @@ impl FieldsState {
             ($field:ty, $name:ident) => {{
                 match <$field>::compute_leaf(ComputeLeafData {
                     tile,
+                    poi,
                     creation_time,
                     data,
                     extra,
@@ -461,6 +646,7 @@ impl FieldsState {
                 each_field!(Population, population);
                 each_field!(Employment, employment);
                 each_field!(RawLandValue, raw_land_value);
+                each_field!(Terrain, terrain);
                 each_field!(RawDemand, raw_demand);
             }
             FieldPass::Second => {
@@ -502,6 +688,7 @@ impl FieldsState {
                 each_field!(Population, population);
                 each_field!(Employment, employment);
                 each_field!(RawLandValue, raw_land_value);
+                each_field!(Terrain, terrain);
                 each_field!(RawDemand, raw_demand);
             }
             FieldPass::Second => {
@@ -514,6 +701,41 @@ impl FieldsState {
     }
 }
 
+impl FieldsState {
+    /// Returns the names of all fields that have NaN or infinite values.
+    pub fn non_finite_fields(&self) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        macro_rules! check {
+            ($($name:ident . $value:ident),* $(,)?) => {
+                $(
+                    if !self.$name.$value.value.is_finite() {
+                        fields.push(concat!(stringify!($name), ".", stringify!($value)));
+                    }
+                )*
+            };
+        }
+        for_each_float_field!(check);
+        fields
+    }
+
+    /// Resets any NaN or infinite values to zero. Returns the number of values that were reset.
+    pub fn repair_non_finite(&mut self) -> usize {
+        let mut repaired = 0;
+        macro_rules! repair {
+            ($($name:ident . $value:ident),* $(,)?) => {
+                $(
+                    if !self.$name.$value.value.is_finite() {
+                        self.$name.$value.value = 0.0;
+                        repaired += 1;
+                    }
+                )*
+            };
+        }
+        for_each_float_field!(repair);
+        repaired
+    }
+}
+
 impl state::Fields for FieldsState {}
 
 // NOTE: Dummy serde implementation, cannot actually be used. We never intend to serialize this
@@ -566,6 +788,105 @@ mod tests {
         );
     }
 
+    #[test]
+    fn weighted_average_empty_test() {
+        // an empty average contributes nothing, even if its value is garbage
+        let empty = WeightedAverage {
+            value: f64::NAN,
+            count: 0,
+        };
+        assert_eq!(empty + WeightedAverage::one(3.0), WeightedAverage::one(3.0));
+        assert_eq!(WeightedAverage::one(3.0) + empty, WeightedAverage::one(3.0));
+        assert_eq!(empty + empty, WeightedAverage::zero());
+        assert_eq!(
+            WeightedAverage::zero() + WeightedAverage::zero(),
+            WeightedAverage::zero()
+        );
+    }
+
+    #[test]
+    fn weighted_average_fuzz_test() {
+        use rand::{Rng, SeedableRng};
+
+        const EDGE_VALUES: &[f64] = &[
+            0.0,
+            -0.0,
+            1.0,
+            -1.0,
+            f64::MIN_POSITIVE,
+            f64::EPSILON,
+            1e-300,
+            1e300,
+            -1e300,
+        ];
+        const EDGE_COUNTS: &[usize] = &[0, 1, 2, 1 << 20, 1 << 40];
+
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+        let sample = |rng: &mut rand_chacha::ChaCha8Rng| WeightedAverage {
+            value: if rng.gen_bool(0.5) {
+                EDGE_VALUES[rng.gen_range(0..EDGE_VALUES.len())]
+            } else {
+                rng.gen_range(-1e6..1e6)
+            },
+            count: EDGE_COUNTS[rng.gen_range(0..EDGE_COUNTS.len())],
+        };
+
+        for _ in 0..10000 {
+            let a = sample(&mut rng);
+            let b = sample(&mut rng);
+            let sum = a + b;
+
+            assert!(sum.value.is_finite(), "{:?} + {:?} = {:?}", a, b, sum);
+            assert_eq!(sum.count, a.count + b.count);
+            assert_eq!(sum, b + a);
+
+            // the average is always between the values that have samples
+            let values: Vec<_> = [a, b]
+                .iter()
+                .filter(|avg| avg.count > 0)
+                .map(|avg| avg.value)
+                .collect();
+            if values.is_empty() {
+                assert_eq!(sum, WeightedAverage::zero());
+            } else {
+                let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+                let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                let tolerance = max.abs().max(min.abs()) * 1e-12;
+                assert!(
+                    sum.value >= min - tolerance && sum.value <= max + tolerance,
+                    "{:?} + {:?} = {:?}",
+                    a,
+                    b,
+                    sum
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fields_non_finite_test() {
+        let mut fields = FieldsState::default();
+        assert!(fields.non_finite_fields().is_empty());
+
+        fields.population.commute_duration = WeightedAverage::one(f64::NAN);
+        fields.land_value.construction_cost = WeightedAverage::one(f64::INFINITY);
+        assert_eq!(
+            fields.non_finite_fields(),
+            vec![
+                "population.commute_duration",
+                "land_value.construction_cost"
+            ]
+        );
+
+        assert_eq!(fields.repair_non_finite(), 2);
+        assert!(fields.non_finite_fields().is_empty());
+        assert_eq!(
+            fields.population.commute_duration,
+            WeightedAverage::one(0.0)
+        );
+        assert_eq!(fields.repair_non_finite(), 0);
+    }
+
     #[test]
     fn min_max_test() {
         assert_eq!(
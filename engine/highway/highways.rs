@@ -1,12 +1,13 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum RampDirection {
     OnRamp,
     OffRamp,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct HighwayJunction {
     pub ramp: Option<RampDirection>,
 }
@@ -17,7 +18,7 @@ impl HighwayJunction {
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct HighwaySegment {
     pub name: Option<String>,
     pub refs: Vec<String>,
@@ -41,4 +42,16 @@ impl HighwaySegment {
     }
 }
 
+impl network::SegmentData for HighwaySegment {
+    fn validate(&self) -> Result<(), String> {
+        if self.speed_limit == Some(0) {
+            return Err("highway speed limit must be positive".to_string());
+        }
+        if self.lanes == Some(0) {
+            return Err("highway must have at least one lane".to_string());
+        }
+        Ok(())
+    }
+}
+
 pub type Highways = network::Network<HighwayJunction, HighwaySegment>;
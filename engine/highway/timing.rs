@@ -28,10 +28,10 @@ pub const MAX_CONGESTED_TIME: f64 = 1200.0; // 20 minutes
 
 pub trait HighwayTiming {
     fn highway_travel_time(&self, tile_size: f64) -> f64;
-    fn critical_capacity(&self, tile_size: u32, people_per_sim: u32) -> f64;
-    fn congested_travel_factor(&self, tile_size: u32, people_per_sim: u32, travelers: f64) -> f64;
-    fn congested_travel_time(&self, tile_size: u32, people_per_sim: u32, travelers: f64) -> f64;
-    fn is_jammed(&self, tile_size: u32, people_per_sim: u32, travelers: f64) -> bool;
+    fn critical_capacity(&self, tile_size: u32, people_per_sim: f64) -> f64;
+    fn congested_travel_factor(&self, tile_size: u32, people_per_sim: f64, travelers: f64) -> f64;
+    fn congested_travel_time(&self, tile_size: u32, people_per_sim: f64, travelers: f64) -> f64;
+    fn is_jammed(&self, tile_size: u32, people_per_sim: f64, travelers: f64) -> bool;
 }
 
 impl HighwayTiming for network::Segment<HighwaySegment> {
@@ -54,27 +54,26 @@ impl HighwayTiming for network::Segment<HighwaySegment> {
      * NOTE: This is a really primitive modeling of traffic flow. It is sufficient for now,
      * but could be worth investigating more sophisticated techniques in the future.
      */
-    fn critical_capacity(&self, tile_size: u32, people_per_sim: u32) -> f64 {
+    fn critical_capacity(&self, tile_size: u32, people_per_sim: f64) -> f64 {
         let length = self.length() * tile_size as f64; // meters
         let speed = self.data.speed_limit.unwrap_or(DEFAULT_SPEED) as f64; // meters per second
         let lanes = self.data.lanes.unwrap_or(DEFAULT_LANES) as f64;
-        let car_factor = people_per_sim as f64;
-        (length * speed * lanes / car_factor * K_CRITICAL_CAPACITY).ceil()
+        (length * speed * lanes / people_per_sim * K_CRITICAL_CAPACITY).ceil()
     }
 
-    fn congested_travel_factor(&self, tile_size: u32, people_per_sim: u32, travelers: f64) -> f64 {
+    fn congested_travel_factor(&self, tile_size: u32, people_per_sim: f64, travelers: f64) -> f64 {
         let critical_capacity = self.critical_capacity(tile_size, people_per_sim);
         congested_travel_factor(critical_capacity, travelers)
     }
 
-    fn congested_travel_time(&self, tile_size: u32, people_per_sim: u32, travelers: f64) -> f64 {
+    fn congested_travel_time(&self, tile_size: u32, people_per_sim: f64, travelers: f64) -> f64 {
         let base_travel_time = self.highway_travel_time(tile_size as f64);
         let factor = self.congested_travel_factor(tile_size, people_per_sim, travelers);
         assert!(factor >= 1.0);
         (base_travel_time * factor).min(MAX_CONGESTED_TIME)
     }
 
-    fn is_jammed(&self, tile_size: u32, people_per_sim: u32, travelers: f64) -> bool {
+    fn is_jammed(&self, tile_size: u32, people_per_sim: f64, travelers: f64) -> bool {
         let critical_capacity = self.critical_capacity(tile_size, people_per_sim);
         is_jammed(critical_capacity, travelers)
     }
@@ -88,7 +87,7 @@ pub fn congested_travel_factor(critical_capacity: f64, travelers: f64) -> f64 {
         travelers
     );
 
-    if travelers <= critical_capacity {
+    let factor = if travelers <= critical_capacity {
         // we get linearly slower
         1.0 + travelers / critical_capacity * (K_LINEAR_FACTOR - 1.0)
     } else {
@@ -96,7 +95,11 @@ pub fn congested_travel_factor(critical_capacity: f64, travelers: f64) -> f64 {
         K_LINEAR_FACTOR
             + 2.0_f64.powf((travelers - critical_capacity) / critical_capacity)
                 * K_EXPONENTIAL_FACTOR
-    }
+    };
+
+    // this can overflow to infinity for very large numbers of travelers, which callers clamp
+    debug_assert!(!factor.is_nan(), "{} {}", critical_capacity, travelers);
+    factor
 }
 
 pub fn is_jammed(critical_capacity: f64, travelers: f64) -> bool {
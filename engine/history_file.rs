@@ -0,0 +1,70 @@
+use std::io::{Read, Write};
+
+use crate::engine::Error;
+
+/// identifies traffic history files, followed by the format version
+const MAGIC: &[u8; 8] = b"MSHISTRY";
+const VERSION: u32 = 1;
+
+/**
+ * The traffic history is saved next to the main save file rather than inside it, since it is much
+ * larger than the rest of the state and changes slowly. This is where it goes for a given save.
+ */
+pub fn history_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut history_path = path.as_os_str().to_owned();
+    history_path.push(".history");
+    history_path.into()
+}
+
+/**
+ * Write the history along with its content hash. The hash goes in the uncompressed header, so that
+ * it can be checked without reading the whole file.
+ */
+pub(crate) fn write(
+    path: &std::path::Path,
+    hash: u64,
+    history: &route::WorldStateHistory,
+) -> Result<(), Error> {
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+    bincode::serialize_into(&mut encoder, history)?;
+    let data = encoder.finish()?;
+
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&hash.to_le_bytes())?;
+    writer.write_all(&data)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_header(reader: &mut impl Read) -> Result<u64, Error> {
+    let mut header = [0; 8 + 4 + 8];
+    reader.read_exact(&mut header)?;
+    if &header[..8] != MAGIC {
+        return Err(Error::HistoryFileError(String::from(
+            "not a traffic history file",
+        )));
+    }
+    let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if version != VERSION {
+        return Err(Error::HistoryFileError(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+    Ok(u64::from_le_bytes(header[12..].try_into().unwrap()))
+}
+
+/// The content hash of the history stored in the file.
+pub(crate) fn read_hash(path: &std::path::Path) -> Result<u64, Error> {
+    read_header(&mut std::fs::File::open(path)?)
+}
+
+/// The history stored in the file, along with the content hash it was saved with.
+pub(crate) fn read(path: &std::path::Path) -> Result<(u64, route::WorldStateHistory), Error> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let hash = read_header(&mut reader)?;
+    let decoder = flate2::read::DeflateDecoder::new(reader);
+    Ok((hash, bincode::deserialize_from(decoder)?))
+}
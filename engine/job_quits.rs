@@ -0,0 +1,57 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+/// the most quits that are kept around; older quits are dropped first
+const MAX_JOB_QUITS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobQuit {
+    /// the simulation time when the agent quit
+    pub time: u64,
+    pub agent: u64,
+    /// the agent's workplace happiness when they quit
+    pub score: f32,
+    /// the part of the agent's workplace happiness that lowered it the most
+    pub reason: Option<agent::HappinessComponent>,
+}
+
+/**
+ * Agents that quit their jobs, along with what made them unhappy enough to quit. When many agents
+ * quit at once, this is where to look for why.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobQuits {
+    recent: VecDeque<JobQuit>,
+    /// quits since the start of the simulation, by reason
+    totals: BTreeMap<agent::HappinessComponent, u64>,
+    /// quits since the start of the simulation where no part stood out
+    unexplained: u64,
+}
+
+impl JobQuits {
+    pub(crate) fn record(&mut self, quit: JobQuit) {
+        match quit.reason {
+            Some(reason) => *self.totals.entry(reason).or_insert(0) += 1,
+            None => self.unexplained += 1,
+        }
+        if self.recent.len() >= MAX_JOB_QUITS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(quit);
+    }
+
+    /// The most recent quits that are still kept around, oldest first.
+    pub fn recent(&self) -> impl DoubleEndedIterator<Item = &JobQuit> {
+        self.recent.iter()
+    }
+
+    pub fn totals(&self) -> &BTreeMap<agent::HappinessComponent, u64> {
+        &self.totals
+    }
+
+    pub fn unexplained(&self) -> u64 {
+        self.unexplained
+    }
+}
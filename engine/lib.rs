@@ -1,12 +1,91 @@
+/// Record a route that an agent finished or abandoned in the travel diary, if it is compiled in.
+macro_rules! record_travel {
+    ($engine:expr, $route_state:expr) => {
+        #[cfg(feature = "travel_diary")]
+        $engine
+            .travel_diary
+            .record(&$engine.state.config.travel_diary, $route_state);
+        #[cfg(not(feature = "travel_diary"))]
+        let _ = $route_state;
+    };
+}
+
+mod alerts;
 mod behavior;
+mod benchmark;
+mod calibration;
+mod catchment;
+mod change_set;
+mod closures;
 mod consistency;
+mod custom_trigger;
 mod engine;
 mod field_update;
 mod fields;
+mod history_file;
+mod job_quits;
+mod load_overrides;
+mod memory_report;
+mod od_matrix;
+mod orphan_stations;
+mod populate;
+mod population;
+mod region;
+#[cfg(feature = "replay")]
+mod replay;
+mod route_estimate;
+mod route_latency;
+mod routing_health;
+mod scenario;
+mod schema;
+mod segment_edits;
+mod stats_snapshot;
 mod time_state;
+#[cfg(feature = "travel_diary")]
+mod travel_diary;
 mod trigger;
 
-pub use crate::behavior::TriggerType;
+pub use crate::alerts::{Alert, AlertThresholds, Alerts, EventCounts, Severity, Watcher};
+pub use crate::behavior::{Trigger, TriggerKind, TriggerType};
+pub use crate::benchmark::{Benchmark, BenchmarkReport, TriggerReport};
+pub use crate::calibration::{
+    CalibrationReport, CalibrationSample, ErrorMetrics, Observations, OsmIdMap, SegmentCalibration,
+    SegmentObservation,
+};
+pub use crate::catchment::{StationCatchment, StationCatchments};
+pub use crate::change_set::{
+    ChangeKind, ChangeSetHandle, ChangeSetPreview, StagedChange, StagedMetroLine,
+    StagedMetroLineReroute,
+};
+pub use crate::closures::NetworkSegment;
 pub use crate::consistency::ConsistencyError;
-pub use crate::engine::{BaseGraph, Engine, Error};
-pub use crate::fields::FieldsState;
+pub use crate::custom_trigger::{CustomTrigger, DynTriggerType, TriggerFactory};
+pub use crate::engine::{
+    BaseGraph, Engine, Error, ErrorContext, InsertPolicy, SharedBaseGraph, TimedRoute,
+};
+pub use crate::field_update::{
+    BlurredField, BlurredFieldSpec, CONSTRUCTION_COST, LAND_VALUE, WORKPLACE_DEMAND,
+};
+pub use crate::fields::{FieldsState, WeightedAverage};
+pub use crate::history_file::history_path;
+pub use crate::job_quits::{JobQuit, JobQuits};
+pub use crate::load_overrides::LoadOverrides;
+pub use crate::memory_report::{MemoryEntry, MemoryReport};
+pub use crate::od_matrix::{od_zone_size, OdFlow, OdMatrix, MAX_OD_ZONES};
+pub use crate::orphan_stations::ORPHAN_STATION_ADOPTION_RADIUS;
+pub use crate::populate::AgentDataDistribution;
+pub use crate::population::{
+    ImportPolicy, Population, PopulationAgent, PopulationHousehold, PopulationImportReport,
+    TileOccupancy,
+};
+pub use crate::region::{CopiedRegion, PastedRegionReport};
+#[cfg(feature = "replay")]
+pub use crate::replay::{AgentKeyframe, Replay, ReplayConfig, ReplayRecorder};
+pub use crate::route_estimate::{ModeProfile, RouteCostTable, MAX_ESTIMATED_COST};
+pub use crate::route_latency::{LatencyHistogram, RouteLatency, LATENCY_BUCKETS_MS};
+pub use crate::routing_health::RoutingHealth;
+pub use crate::scenario::{Scenario, ScenarioAction, ScenarioEvent};
+pub use crate::schema::{all_schemas, leaf_schema};
+pub use crate::stats_snapshot::StatsSnapshot;
+#[cfg(feature = "travel_diary")]
+pub use crate::travel_diary::{TravelDiary, TravelRecord};
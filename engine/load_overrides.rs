@@ -0,0 +1,52 @@
+use crate::engine::{Engine, Error};
+
+/**
+ * Config that a viewer can override when loading a map, to limit how much memory the traffic model
+ * uses. The overrides only ever make the model coarser than the map asks for, so they are limits
+ * rather than replacements.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadOverrides {
+    /// keep at most this many traffic history snapshots
+    pub max_traffic_history_snapshots: Option<usize>,
+    /// count local traffic and parking in blocks at least this large, in meters
+    pub min_traffic_grid_block_size: Option<u32>,
+}
+
+impl LoadOverrides {
+    pub(crate) fn apply(&self, config: &mut state::Config) {
+        let traffic_history = &mut config.traffic_history;
+        if let Some(snapshots) = self.max_traffic_history_snapshots {
+            traffic_history.snapshots = traffic_history.snapshots.min(snapshots);
+        }
+        if let Some(block_size) = self.min_traffic_grid_block_size {
+            traffic_history.grid_block_size = traffic_history.grid_block_size.max(block_size);
+        }
+    }
+}
+
+impl Engine {
+    /**
+     * Reconstruct the current traffic and parking from the agents, e.g. after the grid that they
+     * are counted in has changed. This is the same reconstruction that the consistency checks
+     * compare against.
+     */
+    pub(crate) fn rebuild_world_state(&mut self) -> Result<(), Error> {
+        let mut world_state = route::WorldStateImpl::new(&self.state.config);
+        for agent in self.agents.values() {
+            if let agent::AgentState::Route(route_state) = &agent.state {
+                for edge in route_state.current_edges().unwrap_or_default() {
+                    world_state.increment_edge_no_parking(edge)?;
+                }
+            }
+            if let Some(parked_car) = agent.parked_car() {
+                world_state.increment_parking(parked_car)?;
+            }
+        }
+        for (segment, count) in self.injected_highway_travelers() {
+            world_state.add_highway_segment_travelers(*segment, *count)?;
+        }
+        self.world_state = world_state;
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use memory_size::{btree_map_bytes, format_bytes, MemorySize};
+use tabled::Tabled;
+
+use crate::engine::Engine;
+
+/// The estimated memory used by one part of the engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryEntry {
+    pub name: &'static str,
+    /// what the estimate is made up of, e.g. the number of quadtree leaves or agents, if it is
+    /// meaningful
+    pub items: Option<usize>,
+    pub bytes: usize,
+}
+
+#[derive(Tabled)]
+struct MemoryRow {
+    name: String,
+    items: String,
+    size: String,
+}
+
+impl MemoryRow {
+    fn new(name: &str, items: Option<usize>, bytes: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            items: items.map(|items| items.to_string()).unwrap_or_default(),
+            size: format_bytes(bytes),
+        }
+    }
+}
+
+/**
+ * Estimated memory use of the engine, broken down by subsystem. The estimates come from explicit
+ * accounting of the big data structures (see memory_size::MemorySize), so they leave out smaller
+ * things and allocator overhead, but they are available on every platform.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// ordered from largest to smallest
+    pub entries: Vec<MemoryEntry>,
+}
+
+impl MemoryReport {
+    fn new(mut entries: Vec<MemoryEntry>) -> Self {
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.bytes));
+        Self { entries }
+    }
+
+    pub fn total(&self) -> usize {
+        self.entries.iter().map(|entry| entry.bytes).sum()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MemoryEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+}
+
+impl std::fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use tabled::object::{Columns, Rows, Segment};
+        use tabled::{Alignment, Modify, Style, Table};
+
+        let table = Table::new(
+            self.entries
+                .iter()
+                .map(|entry| MemoryRow::new(entry.name, entry.items, entry.bytes))
+                .chain(std::iter::once(MemoryRow::new("total", None, self.total()))),
+        )
+        .with(Style::modern())
+        .with(Modify::new(Segment::all()).with(Alignment::right()))
+        .with(Modify::new(Columns::first()).with(Alignment::left()))
+        .with(Modify::new(Rows::first()).with(Alignment::left()));
+
+        write!(f, "{}", table)
+    }
+}
+
+impl Engine {
+    /**
+     * Estimates how much memory the biggest parts of the engine use. This walks the whole quadtree
+     * and all agents, so it is too slow to call every frame on large maps.
+     */
+    pub fn memory_report(&self) -> MemoryReport {
+        let base_graph = self.base_graph.read().unwrap();
+
+        MemoryReport::new(vec![
+            MemoryEntry {
+                name: "quadtree",
+                items: Some(self.state.qtree.leaf_count()),
+                bytes: self.state.qtree.estimated_bytes(),
+            },
+            MemoryEntry {
+                name: "agents",
+                items: Some(self.agents.len()),
+                bytes: btree_map_bytes(&self.agents)
+                    + self
+                        .agents
+                        .values()
+                        .map(|agent| agent.heap_bytes())
+                        .sum::<usize>(),
+            },
+            MemoryEntry {
+                name: "world state",
+                items: None,
+                bytes: self.world_state.estimated_bytes(),
+            },
+            MemoryEntry {
+                name: "traffic history",
+                items: Some(self.world_state_history.num_snapshots()),
+                bytes: self.world_state_history.estimated_bytes(),
+            },
+            MemoryEntry {
+                name: "base graph",
+                items: base_graph.get_stats().map(|stats| stats.node_count),
+                bytes: base_graph.estimated_bytes(),
+            },
+            MemoryEntry {
+                name: "trigger queue",
+                items: Some(self.trigger_queue.len()),
+                bytes: self.trigger_queue.estimated_bytes(),
+            },
+        ])
+    }
+}
@@ -4,6 +4,12 @@ mod railways;
 mod schedule;
 
 pub use color::{Color, DEFAULT_COLORS};
-pub use metros::{MetroLine, MetroLineData, MetroLineHandle, Metros, OrientedSegment};
-pub use railways::{RailwayJunction, RailwaySegment, RailwayTiming, Railways, Station};
+pub use metros::{
+    adoptable_stations, orient_segments, AdoptableStation, Error, LineSegmentsDiff, MetroLine,
+    MetroLineData, MetroLineHandle, Metros, OrientedSegment, OrphanStation, StationIndexEntry,
+    ValidationIssue, ValidationReport,
+};
+pub use railways::{
+    RailwayAlignment, RailwayJunction, RailwaySegment, RailwayTiming, Railways, Station,
+};
 pub use schedule::Schedule;
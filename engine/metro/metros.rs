@@ -1,21 +1,53 @@
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::color::Color;
-use crate::railways::{Railways, Station};
+use crate::railways::{RailwayJunction, RailwaySegment, Railways, Station};
 use crate::schedule::Schedule;
 
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum Error {
+    #[error("Metro line {0:?} has no segment at index {1}")]
+    InvalidSegmentIndex(MetroLineHandle, usize),
+    #[error("Station {0:?} is not served by metro line {1:?}")]
+    StationNotOnLine(String, MetroLineHandle),
+    #[error("Station {0:?} is at the end of the segment, not partway along it")]
+    StationAtSegmentEnd(String),
+    #[error("Metro line turns too sharply at {0:?}")]
+    SharpTurn(network::Key),
+    #[error("Metro line {0:?} needs at least one segment")]
+    EmptyLine(MetroLineHandle),
+    #[error("Railway segment {0:?} does not exist or is not active")]
+    InactiveSegment(network::SegmentHandle),
+    #[error("Railway segments {0:?} and {1:?} are not connected")]
+    Disconnected(network::SegmentHandle, network::SegmentHandle),
+    #[error("Invalid data for railway segment {0:?}: {1}")]
+    InvalidSegmentData(network::SegmentHandle, String),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-pub struct MetroLineHandle(u64);
+pub struct MetroLineHandle(network::HandleId);
 
 impl MetroLineHandle {
+    /// The index and generation packed into one integer, which is unique among all handles.
     pub fn inner(&self) -> u64 {
+        self.0.to_bits()
+    }
+
+    pub fn id(&self) -> network::HandleId {
         self.0
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl std::fmt::Display for MetroLineHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MetroLineData {
     pub color: Color,
     pub name: String,
@@ -108,15 +140,57 @@ impl MetroLine {
     }
 }
 
+/**
+ * A station that no metro line serves, e.g. because map generation placed it before any lines
+ * existed. Orphan stations are found from the station tiles, which the railways don't know about.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrphanStation {
+    pub station: Station,
+    /// the exact location of the station, in absolute coordinates
+    pub position: (f64, f64),
+}
+
+/// A station in the list returned by Metros::station_index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StationIndexEntry {
+    pub station: Station,
+    /// no metro line serves the station yet
+    pub unserved: bool,
+}
+
+/// An orphan station that can become a stop partway along a railway segment; see adoptable_stations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdoptableStation {
+    pub orphan: OrphanStation,
+    pub segment: network::SegmentHandle,
+    /// how far the station is from the segment, in coordinates
+    pub distance: f64,
+}
+
+/// The railway segments and stations that a metro line gained and lost; see update_line_segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineSegmentsDiff {
+    pub added: BTreeSet<network::SegmentHandle>,
+    pub removed: BTreeSet<network::SegmentHandle>,
+    pub stations_added: Vec<Station>,
+    pub stations_removed: Vec<Station>,
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Metros {
     metro_lines: BTreeMap<MetroLineHandle, MetroLine>,
     metro_line_counter: u64,
-    railway_segment_metro_lines: BTreeMap<network::SegmentHandle, HashSet<MetroLineHandle>>,
+    railway_segment_metro_lines: BTreeMap<network::SegmentHandle, BTreeSet<MetroLineHandle>>,
+    /// stations served by metro lines, by name; names should be unique, but if they are not, all
+    /// of the stations sharing the name are kept, in the order they were added. This can be
+    /// derived from the metro lines, so it isn't serialized; see index_stations.
+    #[serde(skip)]
+    station_names: BTreeMap<String, Vec<Station>>,
 }
 
 lazy_static::lazy_static! {
-    static ref EMPTY_METRO_LINE_SET: HashSet<MetroLineHandle> = HashSet::new();
+    static ref EMPTY_METRO_LINE_SET: BTreeSet<MetroLineHandle> = BTreeSet::new();
 }
 
 impl Metros {
@@ -125,15 +199,22 @@ impl Metros {
     }
 
     pub fn metro_line(&self, id: MetroLineHandle) -> &MetroLine {
-        self.metro_lines
-            .get(&id)
-            .expect("invalid metro line handle")
+        self.try_metro_line(id)
+            .unwrap_or_else(|| panic!("invalid metro line handle {}", id))
+    }
+
+    /// Like metro_line(), but returns None if the handle does not refer to a metro line.
+    pub fn try_metro_line(&self, id: MetroLineHandle) -> Option<&MetroLine> {
+        self.metro_lines.get(&id)
     }
 
     pub fn metro_line_mut(&mut self, id: MetroLineHandle) -> &mut MetroLine {
-        self.metro_lines
-            .get_mut(&id)
-            .expect("invalid metro line handle")
+        self.try_metro_line_mut(id)
+            .unwrap_or_else(|| panic!("invalid metro line handle {}", id))
+    }
+
+    pub fn try_metro_line_mut(&mut self, id: MetroLineHandle) -> Option<&mut MetroLine> {
+        self.metro_lines.get_mut(&id)
     }
 
     pub fn add_metro_line(
@@ -142,23 +223,395 @@ impl Metros {
         segments: Vec<network::SegmentHandle>,
         railways: &Railways,
     ) -> MetroLineHandle {
-        let id = MetroLineHandle(self.metro_line_counter);
+        // metro lines are never removed, so there is no need to reuse slots
+        let id = MetroLineHandle(network::HandleId::new(self.metro_line_counter as u32, 0));
         self.metro_line_counter += 1;
 
         for segment in &segments {
             self.railway_segment_metro_lines
                 .entry(*segment)
-                .or_insert_with(HashSet::new)
+                .or_insert_with(BTreeSet::new)
                 .insert(id);
         }
 
-        let oriented_segments = orient_segments(&segments, railways);
+        let oriented_segments = orient_segments(&segments, railways)
+            .unwrap_or_else(|err| panic!("invalid metro line: {}", err));
+        let metro_line = MetroLine::new(id, data, oriented_segments);
+
+        for station in metro_line.stations(railways) {
+            self.index_station(station);
+        }
 
-        self.metro_lines
-            .insert(id, MetroLine::new(id, data, oriented_segments));
+        self.metro_lines.insert(id, metro_line);
         id
     }
 
+    /**
+     * Rebuild the index of stations by name from the metro lines, in the order the lines were
+     * added. This needs to be called after loading a map.
+     */
+    pub fn index_stations(&mut self, railways: &Railways) {
+        self.station_names.clear();
+        let stations: Vec<Station> = self
+            .metro_lines
+            .values()
+            .flat_map(|metro_line| metro_line.stations(railways))
+            .cloned()
+            .collect();
+        for station in &stations {
+            self.index_station(station);
+        }
+    }
+
+    fn index_station(&mut self, station: &Station) {
+        let stations = self
+            .station_names
+            .entry(station.name.clone())
+            .or_insert_with(Vec::new);
+        // the same station is usually served by multiple metro lines, which is fine
+        if !stations.contains(station) {
+            if let Some(existing) = stations.first() {
+                tracing::warn!(
+                    name = station.name.as_str(),
+                    existing = ?existing.address,
+                    duplicate = ?station.address,
+                    "duplicate station name"
+                );
+            }
+            stations.push(station.clone());
+        }
+    }
+
+    fn unindex_station(&mut self, station: &Station) {
+        if let Some(stations) = self.station_names.get_mut(&station.name) {
+            stations.retain(|other| other != station);
+            if stations.is_empty() {
+                self.station_names.remove(&station.name);
+            }
+        }
+    }
+
+    /**
+     * Insert a station partway along the segment at the given index of a metro line, at the point
+     * on the segment closest to the station. The railway segment is split in two at a new junction
+     * for the station, and every metro line using the segment is patched to use the two halves, so
+     * those lines stop at the new station too. Only the timing of the two halves needs to be
+     * computed; the rest of the line is untouched. Returns the junction for the new station.
+     *
+     * The old segment is kept as a tombstone for routes that are already using it. This does not
+     * update the routing graph, which must be reconstructed afterwards.
+     */
+    pub fn insert_station(
+        &mut self,
+        id: MetroLineHandle,
+        segment_index: usize,
+        station: Station,
+        railways: &mut Railways,
+    ) -> Result<network::JunctionHandle, Error> {
+        let segment = self
+            .metro_line(id)
+            .segments
+            .get(segment_index)
+            .ok_or(Error::InvalidSegmentIndex(id, segment_index))?
+            .segment;
+        let location = station.address.to_xy_f64();
+        self.split_segment_at_station(segment, station, location, railways)
+    }
+
+    /**
+     * Split a railway segment in two at the point closest to the given location, with a new
+     * junction for the station in between. Like insert_station, except that the segment doesn't
+     * need to be used by any metro line yet, e.g. while a new path for a line is being drawn.
+     * Every metro line using the segment is patched to use the two halves.
+     */
+    pub fn split_segment_at_station(
+        &mut self,
+        old: network::SegmentHandle,
+        station: Station,
+        location: (f64, f64),
+        railways: &mut Railways,
+    ) -> Result<network::JunctionHandle, Error> {
+        let segment = railways.segment(old);
+        let (first_keys, second_keys) = split_keys(segment.keys(), location.into())
+            .ok_or_else(|| Error::StationAtSegmentEnd(station.name.clone()))?;
+        for keys in [&first_keys, &second_keys] {
+            if let Some(key) = network::find_sharp_turn(keys) {
+                return Err(Error::SharpTurn(key));
+            }
+        }
+
+        let data = segment.data.clone();
+        let (start, end) = (segment.start_junction(), segment.end_junction());
+        let junction = railways.add_junction(
+            *second_keys.first().unwrap(),
+            RailwayJunction::new(Some(station.clone())),
+        );
+        let first = railways.add_segment(data.clone(), start, junction, Some(first_keys));
+        let second = railways.add_segment(data, junction, end, Some(second_keys));
+        retire_segment(railways, old);
+
+        let metro_lines = self
+            .railway_segment_metro_lines
+            .remove(&old)
+            .unwrap_or_default();
+        for metro_line in &metro_lines {
+            let metro_line = self.metro_lines.get_mut(metro_line).unwrap();
+            metro_line.segments = metro_line
+                .segments
+                .iter()
+                .flat_map(|oriented| {
+                    if oriented.segment != old {
+                        vec![*oriented]
+                    } else {
+                        let forward = oriented.forward;
+                        let halves = [
+                            OrientedSegment {
+                                segment: first,
+                                forward,
+                            },
+                            OrientedSegment {
+                                segment: second,
+                                forward,
+                            },
+                        ];
+                        if forward {
+                            halves.to_vec()
+                        } else {
+                            halves.iter().rev().copied().collect()
+                        }
+                    }
+                })
+                .collect();
+        }
+        let served = !metro_lines.is_empty();
+        self.railway_segment_metro_lines
+            .insert(first, metro_lines.clone());
+        self.railway_segment_metro_lines.insert(second, metro_lines);
+
+        // the station is only indexed once a metro line serves it
+        if served {
+            self.index_station(&station);
+        }
+        Ok(junction)
+    }
+
+    /**
+     * Remove a station from a metro line. Stations belong to railway junctions, so this removes the
+     * station from every metro line that serves it. If nothing else needs the junction, the
+     * segments on either side of it are merged back into one, unless the merged segment would turn
+     * too sharply.
+     *
+     * Merged segments are kept as tombstones for routes that are already using them. This does not
+     * update the routing graph, which must be reconstructed afterwards.
+     */
+    pub fn remove_station(
+        &mut self,
+        id: MetroLineHandle,
+        station: &Station,
+        railways: &mut Railways,
+    ) -> Result<(), Error> {
+        let junction = self
+            .metro_line(id)
+            .junctions(railways)
+            .find(|junction| railways.junction(*junction).data.station.as_ref() == Some(station))
+            .ok_or_else(|| Error::StationNotOnLine(station.name.clone(), id))?;
+
+        railways.junction_mut(junction).data.station = None;
+        self.unindex_station(station);
+
+        // trains no longer stop at the junction, so the timing of adjacent segments has changed
+        let adjacent = adjacent_segments(railways, junction);
+        for segment in &adjacent {
+            railways.segment_mut(*segment).clear_timing();
+        }
+
+        if let [a, b] = adjacent[..] {
+            self.merge_segments(junction, a, b, railways);
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Merge two segments that meet at the given junction into one, patching every metro line that
+     * uses them. Does nothing if any metro line doesn't pass straight through the junction (e.g.
+     * it terminates there), or if the result would be invalid.
+     */
+    fn merge_segments(
+        &mut self,
+        junction: network::JunctionHandle,
+        a: network::SegmentHandle,
+        b: network::SegmentHandle,
+        railways: &mut Railways,
+    ) {
+        let (a_segment, b_segment) = (railways.segment(a), railways.segment(b));
+        if a == b || a_segment.data != b_segment.data {
+            return;
+        }
+
+        // orient the keys so that they run from the far end of a, through the junction, to the far
+        // end of b
+        let mut keys = a_segment.keys().to_vec();
+        let start = if a_segment.end_junction() == junction {
+            a_segment.start_junction()
+        } else {
+            keys.reverse();
+            a_segment.end_junction()
+        };
+        let mut b_keys = b_segment.keys().to_vec();
+        let end = if b_segment.start_junction() == junction {
+            b_segment.end_junction()
+        } else {
+            b_keys.reverse();
+            b_segment.start_junction()
+        };
+        if start == end {
+            return;
+        }
+        if keys.last() == b_keys.first() {
+            b_keys.remove(0);
+        }
+        keys.extend(b_keys);
+        if network::find_sharp_turn(&keys).is_some() {
+            return;
+        }
+
+        let metro_lines: BTreeSet<MetroLineHandle> = self
+            .railway_segment_metro_lines(a)
+            .union(self.railway_segment_metro_lines(b))
+            .copied()
+            .collect();
+
+        // check that every metro line can be patched before changing anything; the merged segment
+        // doesn't exist yet, so stand in for it with a
+        for metro_line in &metro_lines {
+            let segments = &self.metro_line(*metro_line).segments;
+            if merge_oriented_segments(segments, (a, b), a, start, railways).is_none() {
+                return;
+            }
+        }
+
+        let data = a_segment.data.clone();
+        let merged = railways.add_segment(data, start, end, Some(keys));
+        for metro_line in &metro_lines {
+            let segments = &self.metro_line(*metro_line).segments;
+            let segments =
+                merge_oriented_segments(segments, (a, b), merged, start, railways).unwrap();
+            self.metro_line_mut(*metro_line).segments = segments;
+        }
+
+        retire_segment(railways, a);
+        retire_segment(railways, b);
+        retire_junction(railways, junction);
+
+        self.railway_segment_metro_lines.remove(&a);
+        self.railway_segment_metro_lines.remove(&b);
+        self.railway_segment_metro_lines.insert(merged, metro_lines);
+    }
+
+    /**
+     * Reroute a metro line over a different chain of railway segments, e.g. to add an infill
+     * station or to go around a closure. The segments must all be active and must connect end to
+     * end; otherwise nothing is changed. Returns the segments and stations that the line gained and
+     * lost, so that anything depending on them can be updated.
+     *
+     * Unlike insert_station, the railways themselves are untouched, so segments that the line no
+     * longer uses stay around for other lines and for routes that are already using them. This does
+     * not update the routing graph, which must be reconstructed afterwards.
+     */
+    pub fn update_line_segments(
+        &mut self,
+        id: MetroLineHandle,
+        segments: Vec<network::SegmentHandle>,
+        railways: &Railways,
+    ) -> Result<LineSegmentsDiff, Error> {
+        if segments.is_empty() {
+            return Err(Error::EmptyLine(id));
+        }
+        for segment in &segments {
+            match railways.try_segment(*segment) {
+                Some(data) if data.change_state.is_active() => (),
+                _ => return Err(Error::InactiveSegment(*segment)),
+            }
+        }
+        let oriented_segments = orient_segments(&segments, railways)?;
+
+        let metro_line = self.metro_line(id);
+        let old_segments: BTreeSet<_> = metro_line.segments.iter().map(|o| o.segment).collect();
+        let old_stations: Vec<Station> = metro_line.stations(railways).cloned().collect();
+        let new_segments: BTreeSet<_> = segments.into_iter().collect();
+
+        let removed: BTreeSet<_> = old_segments.difference(&new_segments).copied().collect();
+        let added: BTreeSet<_> = new_segments.difference(&old_segments).copied().collect();
+        for segment in &removed {
+            if let Some(metro_lines) = self.railway_segment_metro_lines.get_mut(segment) {
+                metro_lines.remove(&id);
+                if metro_lines.is_empty() {
+                    self.railway_segment_metro_lines.remove(segment);
+                }
+            }
+        }
+        for segment in &added {
+            self.railway_segment_metro_lines
+                .entry(*segment)
+                .or_default()
+                .insert(id);
+        }
+        self.metro_line_mut(id).segments = oriented_segments;
+
+        let new_stations: Vec<Station> = self.metro_line(id).stations(railways).cloned().collect();
+        let mut diff = LineSegmentsDiff {
+            added,
+            removed,
+            ..Default::default()
+        };
+        for station in &new_stations {
+            if !old_stations.contains(station) && !diff.stations_added.contains(station) {
+                diff.stations_added.push(station.clone());
+            }
+        }
+        for station in &old_stations {
+            if !new_stations.contains(station) && !diff.stations_removed.contains(station) {
+                diff.stations_removed.push(station.clone());
+            }
+        }
+
+        for station in &diff.stations_removed {
+            // other metro lines may still stop here
+            let served = self
+                .metro_lines
+                .values()
+                .any(|metro_line| metro_line.stations(railways).any(|other| other == station));
+            if !served {
+                self.unindex_station(station);
+            }
+        }
+        for station in &diff.stations_added {
+            self.index_station(station);
+        }
+
+        Ok(diff)
+    }
+
+    /**
+     * Replace the data of a railway segment, e.g. to change its speed limit, and return the metro
+     * lines that use it. Timetables are computed from the timing of each segment, so discarding
+     * the segment's cached timing is enough for the timetables of those lines to pick up the
+     * change; their other segments are untouched. This does not update the routing graph, which
+     * must be reconstructed afterwards.
+     */
+    pub fn update_railway_segment(
+        &self,
+        segment: network::SegmentHandle,
+        data: RailwaySegment,
+        railways: &mut Railways,
+    ) -> Result<BTreeSet<MetroLineHandle>, Error> {
+        railways
+            .update_segment_data(segment, data)
+            .map_err(|err| Error::InvalidSegmentData(segment, err))?;
+        Ok(self.railway_segment_metro_lines(segment).clone())
+    }
+
     pub fn metro_lines(&self) -> &BTreeMap<MetroLineHandle, MetroLine> {
         &self.metro_lines
     }
@@ -167,39 +620,331 @@ impl Metros {
     pub fn railway_segment_metro_lines(
         &self,
         railway: network::SegmentHandle,
-    ) -> &HashSet<MetroLineHandle> {
+    ) -> &BTreeSet<MetroLineHandle> {
         self.railway_segment_metro_lines
             .get(&railway)
             .unwrap_or(&*EMPTY_METRO_LINE_SET)
     }
 
-    /// Panics if any metro line has discontinuities.
-    pub fn validate(&self, railways: &Railways) {
+    /**
+     * Look up a station served by any metro line by its name. If multiple stations share the same
+     * name, returns the one that was added first.
+     */
+    pub fn station_by_name(&self, name: &str) -> Option<&Station> {
+        self.station_names
+            .get(name)
+            .and_then(|stations| stations.first())
+    }
+
+    /**
+     * Every station served by a metro line, along with the given orphan stations marked as
+     * unserved, sorted by name.
+     */
+    pub fn station_index(&self, orphans: &[OrphanStation]) -> Vec<StationIndexEntry> {
+        let mut index: Vec<StationIndexEntry> = self
+            .station_names
+            .values()
+            .flatten()
+            .map(|station| StationIndexEntry {
+                station: station.clone(),
+                unserved: false,
+            })
+            .collect();
+        for orphan in orphans {
+            if !index.iter().any(|entry| entry.station == orphan.station) {
+                index.push(StationIndexEntry {
+                    station: orphan.station.clone(),
+                    unserved: true,
+                });
+            }
+        }
+        index.sort_by(|a, b| a.station.name.cmp(&b.station.name));
+        index
+    }
+
+    /// Iterates through the station names that are shared by more than one station.
+    pub fn duplicate_station_names(&self) -> impl Iterator<Item = &str> {
+        self.station_names
+            .iter()
+            .filter(|(_, stations)| stations.len() > 1)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /**
+     * Check every metro line for discontinuities, and check the geometry of every railway segment
+     * used by a metro line for problems that would produce garbage travel times. Segments that are
+     * shared by several metro lines are only reported once.
+     */
+    #[must_use]
+    pub fn validate(&self, railways: &Railways) -> ValidationReport {
         use itertools::Itertools;
+
+        let mut report = ValidationReport::default();
+        let mut checked = HashSet::new();
         for metro_line in self.metro_lines.values() {
-            for (oriented_in_segment, oriented_out_segment) in
-                metro_line.segments.iter().tuple_windows()
+            for (index, (oriented_in_segment, oriented_out_segment)) in
+                metro_line.segments.iter().tuple_windows().enumerate()
             {
-                let in_segment = railways.segment(oriented_in_segment.segment);
-                let out_segment = railways.segment(oriented_out_segment.segment);
                 let in_end = oriented_in_segment.end_junction(railways);
                 let out_start = oriented_out_segment.start_junction(railways);
                 if in_end != out_start {
-                    panic!(
-                        "disconnected segments in metro line {:#?}: {:#?} and {:#?} end and start at {:#?} and {:#?}, respectively",
-                        metro_line, in_segment, out_segment, in_end, out_start
-                    );
+                    report.issues.push(ValidationIssue::Disconnected {
+                        metro_line: metro_line.id,
+                        index,
+                        in_end,
+                        out_start,
+                    });
+                }
+            }
+
+            for oriented_segment in &metro_line.segments {
+                if !checked.insert(oriented_segment.segment) {
+                    continue;
+                }
+                let keys = railways.segment(oriented_segment.segment).keys();
+                for issue in network::geometry_issues(keys) {
+                    report.issues.push(ValidationIssue::Geometry {
+                        metro_line: metro_line.id,
+                        segment: oriented_segment.segment,
+                        issue,
+                    });
                 }
             }
         }
+        report
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+    /// the segment at the index ends at a different junction than the next segment starts at
+    Disconnected {
+        metro_line: MetroLineHandle,
+        index: usize,
+        in_end: network::JunctionHandle,
+        out_start: network::JunctionHandle,
+    },
+    Geometry {
+        metro_line: MetroLineHandle,
+        segment: network::SegmentHandle,
+        issue: network::GeometryIssue,
+    },
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disconnected {
+                metro_line,
+                index,
+                in_end,
+                out_start,
+            } => write!(
+                f,
+                "metro line {} is disconnected after segment {}: junction {} is not junction {}",
+                metro_line.inner(),
+                index,
+                in_end.inner(),
+                out_start.inner()
+            ),
+            Self::Geometry {
+                metro_line,
+                segment,
+                issue,
+            } => write!(
+                f,
+                "railway segment {} on metro line {} has {}",
+                segment.inner(),
+                metro_line.inner(),
+                issue
+            ),
+        }
+    }
+}
+
+/// The problems found by Metros::validate.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/**
+ * The orphan stations within the given radius of any of the railway segments, e.g. the segments of
+ * a path being drawn for a metro line, along with the closest segment. Stations closest to either
+ * end of a segment are left out, since they would need a junction that already exists. The
+ * stations are ordered by the segment they are closest to, and then by distance.
+ */
+pub fn adoptable_stations(
+    segments: &[network::SegmentHandle],
+    orphans: &[OrphanStation],
+    radius: f64,
+    railways: &Railways,
+) -> Vec<AdoptableStation> {
+    use cgmath::MetricSpace;
+
+    let mut adoptable: Vec<(usize, AdoptableStation)> = orphans
+        .iter()
+        .filter_map(|orphan| {
+            let location = orphan.position.into();
+            segments
+                .iter()
+                .enumerate()
+                .filter_map(|(i, segment)| {
+                    let keys = railways.segment(*segment).keys();
+                    split_keys(keys, location)?;
+                    let (_, point) = closest_point(keys, location)?;
+                    Some((i, *segment, location.distance(point)))
+                })
+                .filter(|(_, _, distance)| *distance <= radius)
+                .min_by(|(_, _, d1), (_, _, d2)| d1.partial_cmp(d2).unwrap())
+                .map(|(i, segment, distance)| {
+                    (
+                        i,
+                        AdoptableStation {
+                            orphan: orphan.clone(),
+                            segment,
+                            distance,
+                        },
+                    )
+                })
+        })
+        .collect();
+    adoptable.sort_by(|(i1, a1), (i2, a2)| {
+        i1.cmp(i2)
+            .then(a1.distance.partial_cmp(&a2.distance).unwrap())
+    });
+    adoptable
+        .into_iter()
+        .map(|(_, adoptable)| adoptable)
+        .collect()
+}
+
+/**
+ * The index of the pair of keys containing the point closest to the given location, and the point
+ * itself. Returns None if there are fewer than two keys.
+ */
+fn closest_point(keys: &[network::Key], location: network::Key) -> Option<(usize, network::Key)> {
+    use cgmath::{InnerSpace, MetricSpace};
+
+    keys.windows(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            let (a, b) = (pair[0], pair[1]);
+            let ab = b - a;
+            let len2 = ab.magnitude2();
+            let t = if len2 == 0.0 {
+                0.0
+            } else {
+                ((location - a).dot(ab) / len2).clamp(0.0, 1.0)
+            };
+            (i, a + ab * t)
+        })
+        .min_by(|(_, p1), (_, p2)| {
+            location
+                .distance2(*p1)
+                .partial_cmp(&location.distance2(*p2))
+                .unwrap()
+        })
+}
+
+/**
+ * Split keys at the point closest to the given location, returning the keys on either side. Both
+ * halves include the split point. Returns None if the split point is at either end.
+ */
+fn split_keys(
+    keys: &[network::Key],
+    location: network::Key,
+) -> Option<(Vec<network::Key>, Vec<network::Key>)> {
+    let (index, point) = closest_point(keys, location)?;
+
+    let mut first = keys[..=index].to_vec();
+    if first.last() != Some(&point) {
+        first.push(point);
+    }
+    let mut second = keys[index + 1..].to_vec();
+    if second.first() != Some(&point) {
+        second.insert(0, point);
+    }
+
+    if first.len() < 2 || second.len() < 2 {
+        None
+    } else {
+        Some((first, second))
+    }
+}
+
+/// The active segments starting or ending at a junction.
+fn adjacent_segments(
+    railways: &Railways,
+    junction: network::JunctionHandle,
+) -> Vec<network::SegmentHandle> {
+    let junction = railways.junction(junction);
+    junction
+        .incoming_segments()
+        .iter()
+        .chain(junction.outgoing_segments())
+        .copied()
+        .filter(|segment| railways.segment(*segment).change_state.is_active())
+        .collect()
+}
+
+/**
+ * Replace each consecutive pair of segments a and b (in either order) with the merged segment,
+ * which starts at merged_start when traversed forward. Returns None if either segment appears
+ * without the other, in which case the segments can't be merged.
+ */
+fn merge_oriented_segments(
+    segments: &[OrientedSegment],
+    (a, b): (network::SegmentHandle, network::SegmentHandle),
+    merged: network::SegmentHandle,
+    merged_start: network::JunctionHandle,
+    railways: &Railways,
+) -> Option<Vec<OrientedSegment>> {
+    let mut merged_segments = Vec::new();
+    let mut iter = segments.iter();
+    while let Some(oriented) = iter.next() {
+        if oriented.segment == a || oriented.segment == b {
+            let other = if oriented.segment == a { b } else { a };
+            if iter.next()?.segment != other {
+                return None;
+            }
+            merged_segments.push(OrientedSegment {
+                segment: merged,
+                forward: oriented.start_junction(railways) == merged_start,
+            });
+        } else {
+            merged_segments.push(*oriented);
+        }
     }
+    Some(merged_segments)
+}
+
+/**
+ * Mark a segment for removal once routes that are using it have finished, like segments removed
+ * by applying a change set.
+ */
+fn retire_segment(railways: &mut Railways, segment: network::SegmentHandle) {
+    railways.segment_mut(segment).change_state = network::ChangeState::Tombstone { countdown: 2 };
+}
+
+fn retire_junction(railways: &mut Railways, junction: network::JunctionHandle) {
+    railways.junction_mut(junction).change_state = network::ChangeState::Tombstone { countdown: 2 };
 }
 
-/// Determine correct order for segments (since railways are bidirectional).
-fn orient_segments(
+/**
+ * Determine correct order for segments (since railways are bidirectional). Returns an error if
+ * consecutive segments don't share a junction.
+ */
+pub fn orient_segments(
     segments: &[network::SegmentHandle],
     railways: &Railways,
-) -> Vec<OrientedSegment> {
+) -> Result<Vec<OrientedSegment>, Error> {
     use itertools::Itertools;
 
     let mut oriented_segments = Vec::new();
@@ -221,7 +966,7 @@ fn orient_segments(
             {
                 false
             } else {
-                panic!("gap in metro line (constructing first)");
+                return Err(Error::Disconnected(*first, *second));
             };
 
             let oriented_segment = OrientedSegment {
@@ -257,10 +1002,7 @@ fn orient_segments(
                 });
                 false
             } else {
-                panic!(
-                    "gap in metro line (constructing rest of line); previous end: {:#?}, next segment: {:#?}",
-                    railways.junction(prev.end_junction(railways)), segment
-                );
+                return Err(Error::Disconnected(prev.segment, *segment_id));
             };
 
             let oriented_segment = OrientedSegment {
@@ -281,5 +1023,591 @@ fn orient_segments(
         }
     };
 
-    oriented_segments
+    Ok(oriented_segments)
+}
+
+#[cfg(test)]
+mod station_name_tests {
+    use crate::metros::*;
+    use crate::railways::{RailwayJunction, RailwaySegment};
+
+    pub(super) fn station(name: &str, x: u64, y: u64) -> Station {
+        Station {
+            name: name.to_string(),
+            address: quadtree::Address::from_xy(x, y, 5),
+        }
+    }
+
+    pub(super) fn data(name: &str) -> MetroLineData {
+        MetroLineData {
+            color: (255, 0, 0).into(),
+            name: name.to_string(),
+            schedule: Schedule::fixed_frequency(60),
+            speed_limit: 20,
+        }
+    }
+
+    /// Add a railway segment between two stations, returning the segment.
+    pub(super) fn add_segment(
+        railways: &mut Railways,
+        start: &Station,
+        end: &Station,
+    ) -> network::SegmentHandle {
+        let mut add_junction = |station: &Station| {
+            // reuse the junction if it already exists at this location
+            let location = station.address.to_xy_f64();
+            match railways
+                .junctions()
+                .values()
+                .find(|junction| junction.location == location.into())
+            {
+                Some(junction) => junction.id,
+                None => {
+                    railways.add_junction(location, RailwayJunction::new(Some(station.clone())))
+                }
+            }
+        };
+        let start_junction = add_junction(start);
+        let end_junction = add_junction(end);
+        railways.add_segment(
+            RailwaySegment::new(None),
+            start_junction,
+            end_junction,
+            Some(vec![
+                start.address.to_xy_f64().into(),
+                end.address.to_xy_f64().into(),
+            ]),
+        )
+    }
+
+    #[test]
+    fn lookup() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let a = station("A", 0, 0);
+        let b = station("B", 10, 0);
+        let c = station("C", 10, 10);
+
+        let ab = add_segment(&mut railways, &a, &b);
+        let bc = add_segment(&mut railways, &b, &c);
+        metros.add_metro_line(data("AB"), vec![ab], &railways);
+        metros.add_metro_line(data("BC"), vec![bc], &railways);
+
+        assert_eq!(metros.station_by_name("A"), Some(&a));
+        assert_eq!(metros.station_by_name("B"), Some(&b));
+        assert_eq!(metros.station_by_name("C"), Some(&c));
+        assert_eq!(metros.station_by_name("D"), None);
+
+        // B is served by two lines, but is still the same station
+        assert_eq!(metros.duplicate_station_names().count(), 0);
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let a = station("A", 0, 0);
+        let b = station("B", 10, 0);
+        let other_a = station("A", 20, 20);
+
+        let ab = add_segment(&mut railways, &a, &b);
+        let b_other_a = add_segment(&mut railways, &b, &other_a);
+        metros.add_metro_line(data("AB"), vec![ab], &railways);
+        metros.add_metro_line(data("BA"), vec![b_other_a], &railways);
+
+        // the first station with the name wins
+        assert_eq!(metros.station_by_name("A"), Some(&a));
+        assert_eq!(
+            metros.duplicate_station_names().collect::<Vec<_>>(),
+            vec!["A"]
+        );
+    }
+}
+
+#[cfg(test)]
+mod station_edit_tests {
+    use crate::metros::station_name_tests::{add_segment, data, station};
+    use crate::metros::*;
+    use crate::railways::{RailwaySegment, RailwayTiming};
+
+    const TILE_SIZE: f64 = 10.0;
+
+    /// The time at which each station is reached, starting from the first station.
+    fn timetable(metros: &Metros, id: MetroLineHandle, railways: &Railways) -> Vec<(String, f64)> {
+        let metro_line = metros.metro_line(id);
+        let mut time = 0.0;
+        let mut timetable = Vec::new();
+        for (i, junction) in metro_line.junctions(railways).enumerate() {
+            if i > 0 {
+                let segment = railways.segment(metro_line.segments()[i - 1].segment);
+                time +=
+                    segment.railway_travel_time(metro_line.data.speed_limit, TILE_SIZE, railways);
+            }
+            if let Some(station) = &railways.junction(junction).data.station {
+                timetable.push((station.name.clone(), time));
+            }
+        }
+        timetable
+    }
+
+    fn names(timetable: &[(String, f64)]) -> Vec<&str> {
+        timetable.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn insert_and_remove_station() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let a = station("A", 0, 0);
+        let c = station("C", 20, 0);
+        let ac = add_segment(&mut railways, &a, &c);
+        let line = metros.add_metro_line(data("AC"), vec![ac], &railways);
+
+        let before = timetable(&metros, line, &railways);
+        assert_eq!(names(&before), vec!["A", "C"]);
+
+        let b = station("B", 10, 3);
+        metros
+            .insert_station(line, 0, b.clone(), &mut railways)
+            .unwrap();
+        railways.validate();
+        assert!(metros.validate(&railways).is_ok());
+
+        let after = timetable(&metros, line, &railways);
+        assert_eq!(names(&after), vec!["A", "B", "C"]);
+        assert!(after[0].1 < after[1].1 && after[1].1 < after[2].1);
+        // stopping at B takes extra time
+        assert!(after[2].1 > before[1].1);
+        assert_eq!(metros.metro_line(line).segments().len(), 2);
+        assert_eq!(metros.station_by_name("B"), Some(&b));
+        // the old segment sticks around for routes that were already using it
+        assert!(!railways.segment(ac).change_state.is_active());
+        assert!(metros.railway_segment_metro_lines(ac).is_empty());
+
+        metros.remove_station(line, &b, &mut railways).unwrap();
+        railways.validate();
+        assert!(metros.validate(&railways).is_ok());
+
+        let removed = timetable(&metros, line, &railways);
+        assert_eq!(names(&removed), vec!["A", "C"]);
+        assert!((removed[1].1 - before[1].1).abs() < 1e-6);
+        assert_eq!(metros.metro_line(line).segments().len(), 1);
+        assert_eq!(metros.station_by_name("B"), None);
+    }
+
+    #[test]
+    fn shared_segment() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let a = station("A", 0, 0);
+        let c = station("C", 20, 0);
+        let d = station("D", 30, 0);
+        let ac = add_segment(&mut railways, &a, &c);
+        let cd = add_segment(&mut railways, &c, &d);
+        let line = metros.add_metro_line(data("AD"), vec![ac, cd], &railways);
+        let reversed = metros.add_metro_line(data("CA"), vec![ac], &railways);
+        // make sure the second line really does run in the other direction
+        metros.metro_line_mut(reversed).segments[0].forward = false;
+
+        metros
+            .insert_station(line, 0, station("B", 10, 0), &mut railways)
+            .unwrap();
+        assert!(metros.validate(&railways).is_ok());
+
+        // the station is on the railway, so both lines stop there
+        assert_eq!(
+            names(&timetable(&metros, line, &railways)),
+            vec!["A", "B", "C", "D"]
+        );
+        assert_eq!(
+            names(&timetable(&metros, reversed, &railways)),
+            vec!["C", "B", "A"]
+        );
+    }
+
+    #[test]
+    fn lower_speed_limit() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let a = station("A", 0, 0);
+        let b = station("B", 20, 0);
+        let c = station("C", 40, 0);
+        let ab = add_segment(&mut railways, &a, &b);
+        let bc = add_segment(&mut railways, &b, &c);
+        let line = metros.add_metro_line(data("AC"), vec![ab, bc], &railways);
+        let other = metros.add_metro_line(data("BC"), vec![bc], &railways);
+
+        let before = timetable(&metros, line, &railways);
+        let affected = metros
+            .update_railway_segment(ab, RailwaySegment::new(Some(5)), &mut railways)
+            .unwrap();
+        assert_eq!(affected, BTreeSet::from([line]));
+        assert_eq!(railways.segment(ab).data.speed_limit, Some(5));
+
+        let after = timetable(&metros, line, &railways);
+        assert!(after[1].1 > before[1].1, "{:?} {:?}", before, after);
+        assert!(after[2].1 > before[2].1, "{:?} {:?}", before, after);
+        // the time from B to C is untouched
+        assert!(((after[2].1 - after[1].1) - (before[2].1 - before[1].1)).abs() < 1e-6);
+        assert_eq!(names(&timetable(&metros, other, &railways)), vec!["B", "C"]);
+
+        assert_eq!(
+            metros.update_railway_segment(ab, RailwaySegment::new(Some(0)), &mut railways),
+            Err(Error::InvalidSegmentData(
+                ab,
+                "railway speed limit must be positive".to_string()
+            ))
+        );
+        assert_eq!(railways.segment(ab).data.speed_limit, Some(5));
+    }
+
+    #[test]
+    fn invalid_edits() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let a = station("A", 0, 0);
+        let b = station("B", 10, 0);
+        let c = station("C", 10, 10);
+        let ab = add_segment(&mut railways, &a, &b);
+        let bc = add_segment(&mut railways, &b, &c);
+        let line = metros.add_metro_line(data("AC"), vec![ab, bc], &railways);
+
+        assert_eq!(
+            metros.insert_station(line, 2, station("D", 5, 0), &mut railways),
+            Err(Error::InvalidSegmentIndex(line, 2))
+        );
+        assert_eq!(
+            metros.insert_station(line, 0, station("D", 0, 0), &mut railways),
+            Err(Error::StationAtSegmentEnd("D".to_string()))
+        );
+        assert_eq!(
+            metros.remove_station(line, &station("D", 5, 0), &mut railways),
+            Err(Error::StationNotOnLine("D".to_string(), line))
+        );
+
+        // B is on a right-angle turn, so the segments on either side can't be merged, but the
+        // station is still removed
+        metros.remove_station(line, &b, &mut railways).unwrap();
+        assert!(metros.validate(&railways).is_ok());
+        assert_eq!(names(&timetable(&metros, line, &railways)), vec!["A", "C"]);
+        assert_eq!(metros.metro_line(line).segments().len(), 2);
+    }
+}
+
+#[cfg(test)]
+mod reroute_tests {
+    use crate::metros::station_name_tests::{add_segment, data, station};
+    use crate::metros::*;
+
+    fn station_names(metros: &Metros, id: MetroLineHandle, railways: &Railways) -> Vec<String> {
+        metros
+            .metro_line(id)
+            .stations(railways)
+            .map(|station| station.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn reroute() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let a = station("A", 0, 0);
+        let b = station("B", 10, 0);
+        let c = station("C", 20, 0);
+        let d = station("D", 10, 10);
+        let ab = add_segment(&mut railways, &a, &b);
+        let bc = add_segment(&mut railways, &b, &c);
+        let ad = add_segment(&mut railways, &a, &d);
+        let dc = add_segment(&mut railways, &d, &c);
+        let line = metros.add_metro_line(data("AC"), vec![ab, bc], &railways);
+        let shuttle = metros.add_metro_line(data("BC"), vec![bc], &railways);
+        assert_eq!(metros.station_by_name("D"), None);
+
+        let diff = metros
+            .update_line_segments(line, vec![ad, dc], &railways)
+            .unwrap();
+        assert_eq!(diff.added, [ad, dc].into_iter().collect());
+        assert_eq!(diff.removed, [ab, bc].into_iter().collect());
+        assert_eq!(diff.stations_added, vec![d.clone()]);
+        assert_eq!(diff.stations_removed, vec![b.clone()]);
+
+        assert!(metros.validate(&railways).is_ok());
+        assert_eq!(station_names(&metros, line, &railways), vec!["A", "D", "C"]);
+        assert!(metros.railway_segment_metro_lines(ab).is_empty());
+        assert_eq!(
+            metros.railway_segment_metro_lines(bc),
+            &[shuttle].into_iter().collect()
+        );
+        assert_eq!(
+            metros.railway_segment_metro_lines(ad),
+            &[line].into_iter().collect()
+        );
+        assert_eq!(metros.station_by_name("D"), Some(&d));
+        // the other line still stops at B
+        assert_eq!(metros.station_by_name("B"), Some(&b));
+        // the railways are untouched
+        assert!(railways.segment(ab).change_state.is_active());
+
+        // back again, reusing one segment
+        let diff = metros
+            .update_line_segments(line, vec![ab, bc], &railways)
+            .unwrap();
+        assert_eq!(diff.added, [ab, bc].into_iter().collect());
+        assert_eq!(diff.stations_removed, vec![d]);
+        assert_eq!(metros.station_by_name("D"), None);
+        assert_eq!(station_names(&metros, line, &railways), vec!["A", "B", "C"]);
+    }
+
+    #[test]
+    fn invalid_reroute() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let a = station("A", 0, 0);
+        let b = station("B", 10, 0);
+        let c = station("C", 20, 0);
+        let d = station("D", 10, 10);
+        let ab = add_segment(&mut railways, &a, &b);
+        let bc = add_segment(&mut railways, &b, &c);
+        let dc = add_segment(&mut railways, &d, &c);
+        let line = metros.add_metro_line(data("AC"), vec![ab, bc], &railways);
+
+        assert_eq!(
+            metros.update_line_segments(line, vec![], &railways),
+            Err(Error::EmptyLine(line))
+        );
+        assert_eq!(
+            metros.update_line_segments(line, vec![ab, dc], &railways),
+            Err(Error::Disconnected(ab, dc))
+        );
+        retire_segment(&mut railways, dc);
+        assert_eq!(
+            metros.update_line_segments(line, vec![dc], &railways),
+            Err(Error::InactiveSegment(dc))
+        );
+
+        // nothing changed
+        assert_eq!(station_names(&metros, line, &railways), vec!["A", "B", "C"]);
+        assert_eq!(
+            metros.railway_segment_metro_lines(ab),
+            &[line].into_iter().collect()
+        );
+        assert!(metros.railway_segment_metro_lines(dc).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod validate_tests {
+    use crate::metros::station_name_tests::{add_segment, data, station};
+    use crate::metros::*;
+    use crate::railways::RailwaySegment;
+
+    /// Add a railway segment with the given keys between the junctions of two stations.
+    fn add_segment_with_keys(
+        railways: &mut Railways,
+        metros: &mut Metros,
+        keys: Vec<network::Key>,
+    ) -> (MetroLineHandle, network::SegmentHandle) {
+        let start = railways.add_junction(*keys.first().unwrap(), RailwayJunction::new(None));
+        let end = railways.add_junction(*keys.last().unwrap(), RailwayJunction::new(None));
+        let segment = railways.add_segment(RailwaySegment::new(None), start, end, Some(keys));
+        let line = metros.add_metro_line(data("AB"), vec![segment], railways);
+        (line, segment)
+    }
+
+    #[test]
+    fn valid_metro_line() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let a = station("A", 0, 0);
+        let b = station("B", 10, 0);
+        let c = station("C", 20, 5);
+        let ab = add_segment(&mut railways, &a, &b);
+        let bc = add_segment(&mut railways, &b, &c);
+        metros.add_metro_line(data("AC"), vec![ab, bc], &railways);
+
+        assert_eq!(metros.validate(&railways), ValidationReport::default());
+    }
+
+    #[test]
+    fn zero_length_segment() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let point = network::Key::new(3.0, 4.0);
+        let (line, segment) = add_segment_with_keys(&mut railways, &mut metros, vec![point, point]);
+
+        let report = metros.validate(&railways);
+        assert_eq!(
+            report.issues,
+            vec![ValidationIssue::Geometry {
+                metro_line: line,
+                segment,
+                issue: network::GeometryIssue::ZeroLengthSpan(point),
+            }]
+        );
+        assert_eq!(
+            report.issues[0].to_string(),
+            format!(
+                "railway segment {} on metro line {} has zero-length span at (3, 4)",
+                segment.inner(),
+                line.inner()
+            )
+        );
+    }
+
+    #[test]
+    fn backtracking_segment() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+
+        let keys = vec![
+            network::Key::new(0.0, 0.0),
+            network::Key::new(10.0, 0.0),
+            network::Key::new(5.0, 0.0),
+        ];
+        let (line, segment) = add_segment_with_keys(&mut railways, &mut metros, keys);
+        // share the segment with another line to make sure it is only reported once
+        metros.add_metro_line(data("BA"), vec![segment], &railways);
+
+        assert_eq!(
+            metros.validate(&railways).issues,
+            vec![ValidationIssue::Geometry {
+                metro_line: line,
+                segment,
+                issue: network::GeometryIssue::SharpTurn(network::Key::new(10.0, 0.0)),
+            }]
+        );
+    }
+}
+
+#[cfg(test)]
+mod orphan_station_tests {
+    use crate::metros::station_name_tests::{add_segment, data, station};
+    use crate::metros::*;
+    use crate::railways::RailwaySegment;
+
+    fn orphan(name: &str, x: f64, y: f64) -> OrphanStation {
+        OrphanStation {
+            station: station(name, x as u64, y as u64),
+            position: (x, y),
+        }
+    }
+
+    /// A railway segment with a bend in it, not used by any metro line.
+    fn add_bent_segment(railways: &mut Railways) -> network::SegmentHandle {
+        let keys = vec![
+            network::Key::new(0.0, 0.0),
+            network::Key::new(10.0, 0.0),
+            network::Key::new(20.0, 5.0),
+            network::Key::new(30.0, 5.0),
+        ];
+        let start = railways.add_junction(keys[0], RailwayJunction::new(None));
+        let end = railways.add_junction(keys[3], RailwayJunction::new(None));
+        railways.add_segment(RailwaySegment::new(None), start, end, Some(keys))
+    }
+
+    #[test]
+    fn split_at_station() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+        let segment = add_bent_segment(&mut railways);
+        let keys = railways.segment(segment).keys().to_vec();
+        let length = railways.segment(segment).length();
+
+        let b = orphan("B", 25.0, 6.0);
+        let junction = metros
+            .split_segment_at_station(segment, b.station.clone(), b.position, &mut railways)
+            .unwrap();
+        railways.validate();
+
+        let junction = railways.junction(junction);
+        assert_eq!(junction.location, network::Key::new(25.0, 5.0));
+        assert_eq!(junction.data.station, Some(b.station.clone()));
+        let (first, second) = (
+            railways.segment(junction.incoming_segments()[0]),
+            railways.segment(junction.outgoing_segments()[0]),
+        );
+        assert!((first.length() + second.length() - length).abs() < 1e-9);
+
+        // the keys are in the same order, with the station in between
+        assert_eq!(
+            first.keys(),
+            &keys[..3]
+                .iter()
+                .copied()
+                .chain([junction.location])
+                .collect::<Vec<_>>()[..]
+        );
+        assert_eq!(
+            second.keys(),
+            &[junction.location]
+                .into_iter()
+                .chain(keys[3..].iter().copied())
+                .collect::<Vec<_>>()[..]
+        );
+        assert!(!railways.segment(segment).change_state.is_active());
+
+        // no metro line uses the segment, so the station is still unserved
+        assert_eq!(metros.station_by_name("B"), None);
+    }
+
+    #[test]
+    fn adoptable() {
+        let mut railways = Railways::new();
+        let segment = add_bent_segment(&mut railways);
+
+        let orphans = [
+            orphan("far", 15.0, 8.0),
+            orphan("near", 25.0, 6.0),
+            // closest to the end of the segment, where there is already a junction
+            orphan("end", 31.0, 5.0),
+            orphan("nearer", 3.0, 0.5),
+        ];
+        let adoptable = adoptable_stations(&[segment], &orphans, 1.5, &railways);
+        let names: Vec<_> = adoptable
+            .iter()
+            .map(|adoptable| adoptable.orphan.station.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["nearer", "near"]);
+        assert!(adoptable
+            .iter()
+            .all(|adoptable| adoptable.segment == segment));
+        assert!((adoptable[1].distance - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn station_index() {
+        let mut railways = Railways::new();
+        let mut metros = Metros::new();
+        let (a, c) = (station("A", 0, 0), station("C", 20, 0));
+        let ac = add_segment(&mut railways, &a, &c);
+        metros.add_metro_line(data("AC"), vec![ac], &railways);
+
+        let b = orphan("B", 10.0, 5.0);
+        let index = metros.station_index(std::slice::from_ref(&b));
+        let entries: Vec<_> = index
+            .iter()
+            .map(|entry| (entry.station.name.as_str(), entry.unserved))
+            .collect();
+        assert_eq!(entries, vec![("A", false), ("B", true), ("C", false)]);
+
+        // once the station is on the line, it's served
+        metros
+            .split_segment_at_station(ac, b.station.clone(), b.position, &mut railways)
+            .unwrap();
+        assert_eq!(metros.station_by_name("B"), Some(&b.station));
+        assert!(metros
+            .station_index(&[])
+            .iter()
+            .all(|entry| !entry.unserved));
+    }
 }
@@ -1,12 +1,13 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Station {
     pub name: String,
     pub address: quadtree::Address,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RailwayJunction {
     pub station: Option<Station>,
 }
@@ -17,14 +18,68 @@ impl RailwayJunction {
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+/// Whether a railway runs along the ground, above it, or below it.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+pub enum RailwayAlignment {
+    #[default]
+    AtGrade,
+    Elevated,
+    Tunnel,
+}
+
+impl RailwayAlignment {
+    pub const COUNT: usize = 3;
+    pub const ALL: [RailwayAlignment; Self::COUNT] = [Self::AtGrade, Self::Elevated, Self::Tunnel];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::AtGrade => "at_grade",
+            Self::Elevated => "elevated",
+            Self::Tunnel => "tunnel",
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RailwaySegment {
     pub speed_limit: Option<u32>,
+    /// segments saved before alignments existed are at grade
+    #[serde(default)]
+    pub alignment: RailwayAlignment,
 }
 
 impl RailwaySegment {
     pub fn new(speed_limit: Option<u32>) -> Self {
-        Self { speed_limit }
+        Self {
+            speed_limit,
+            alignment: RailwayAlignment::AtGrade,
+        }
+    }
+
+    pub fn with_alignment(self, alignment: RailwayAlignment) -> Self {
+        Self { alignment, ..self }
+    }
+}
+
+impl network::SegmentData for RailwaySegment {
+    fn validate(&self) -> Result<(), String> {
+        if self.speed_limit == Some(0) {
+            return Err("railway speed limit must be positive".to_string());
+        }
+        Ok(())
     }
 }
 
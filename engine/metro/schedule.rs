@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /**
@@ -6,7 +7,7 @@ use serde::{Deserialize, Serialize};
  * For now this is very rudimentary, but in the future the intention is for this to be able to
  * support more complex schedules.
  */
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Schedule {
     /// seconds between each departure, starting from the beginning of the simulation
     fixed_frequency: u64,
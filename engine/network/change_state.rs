@@ -1,9 +1,9 @@
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 
 use serde::{Deserialize, Serialize};
 
 use crate::junction::{Junction, JunctionHandle};
-use crate::network::{Handle, ManagedMap, Network, WithHandle};
+use crate::network::{Handle, Key, ManagedMap, Network, WithHandle};
 use crate::segment::{Segment, SegmentHandle};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -17,6 +17,8 @@ pub enum ChangeState {
     /// ready to be removed, but waiting for remaining routes to finish first
     /// countdown is the number of days until removal
     Tombstone { countdown: u32 },
+    /// temporarily out of use, e.g. because of an incident, until the given simulation time
+    Closed { until: u64 },
 }
 
 impl ChangeState {
@@ -24,7 +26,7 @@ impl ChangeState {
     pub fn is_active(&self) -> bool {
         match self {
             Self::Active | Self::StagedTombstone => true,
-            Self::StagedActive | Self::Tombstone { .. } => false,
+            Self::StagedActive | Self::Tombstone { .. } | Self::Closed { .. } => false,
         }
     }
 
@@ -32,17 +34,26 @@ impl ChangeState {
     pub fn is_staged_change(&self) -> bool {
         match self {
             Self::StagedActive | Self::StagedTombstone => true,
-            Self::Active | Self::Tombstone { .. } => false,
+            Self::Active | Self::Tombstone { .. } | Self::Closed { .. } => false,
         }
     }
 
-    /// Will this item be active if the change set is applied?
+    /// Will this item be active if the change set is applied? Closed items count, since they
+    /// are only out of use for a while.
     pub fn is_staged_active(&self) -> bool {
         match self {
-            Self::Active | Self::StagedActive => true,
+            Self::Active | Self::StagedActive | Self::Closed { .. } => true,
             Self::StagedTombstone | Self::Tombstone { .. } => false,
         }
     }
+
+    /// The simulation time at which a temporarily closed item reopens, if it is closed.
+    pub fn closed_until(&self) -> Option<u64> {
+        match self {
+            Self::Closed { until } => Some(*until),
+            _ => None,
+        }
+    }
 }
 
 pub(crate) trait WithChangeState {
@@ -51,28 +62,28 @@ pub(crate) trait WithChangeState {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChangeSet<T: Eq + std::hash::Hash> {
+pub struct ChangeSet<T: Ord> {
     /// items with state StagedActive
-    created: HashSet<T>,
+    created: BTreeSet<T>,
     /// items with state StagedTombstone
-    removed: HashSet<T>,
+    removed: BTreeSet<T>,
 }
 
-impl<T: Eq + std::hash::Hash> Default for ChangeSet<T> {
+impl<T: Ord> Default for ChangeSet<T> {
     fn default() -> Self {
         Self {
-            created: HashSet::default(),
-            removed: HashSet::default(),
+            created: BTreeSet::default(),
+            removed: BTreeSet::default(),
         }
     }
 }
 
-impl<T: Eq + std::hash::Hash> ChangeSet<T> {
-    pub fn created(&self) -> &HashSet<T> {
+impl<T: Ord> ChangeSet<T> {
+    pub fn created(&self) -> &BTreeSet<T> {
         &self.created
     }
 
-    pub fn removed(&self) -> &HashSet<T> {
+    pub fn removed(&self) -> &BTreeSet<T> {
         &self.removed
     }
 }
@@ -106,14 +117,14 @@ fn apply_change_set<T: Handle, U: WithHandle<T> + WithChangeState>(
     items: &mut ManagedMap<T, U>,
 ) {
     // create all the new items
-    for created in change_set.created.drain() {
+    for created in std::mem::take(&mut change_set.created) {
         let item = items.get_mut(created);
         assert_eq!(*item.change_state(), ChangeState::StagedActive);
         *item.change_state_mut() = ChangeState::Active;
     }
 
     // mark the old items as tombstones (will be removed later as part of AdvanceNetworktombstones)
-    for removed in change_set.removed.drain() {
+    for removed in std::mem::take(&mut change_set.removed) {
         let item = items.get_mut(removed);
         assert_eq!(*item.change_state(), ChangeState::StagedTombstone);
         // Delete after 2 days; a route may cross from one day to the next, but it will never cross
@@ -127,13 +138,15 @@ fn clear_change_set<T: Handle, U: WithHandle<T> + WithChangeState>(
     items: &mut ManagedMap<T, U>,
 ) -> Vec<T> {
     // remove all the to-be-created items
-    let to_remove: Vec<T> = change_set.created.drain().collect();
+    let to_remove: Vec<T> = std::mem::take(&mut change_set.created)
+        .into_iter()
+        .collect();
     for item in &to_remove {
         assert_eq!(*items.get(*item).change_state(), ChangeState::StagedActive);
     }
 
     // revert all the to-be-removed items
-    for removed in change_set.removed.drain() {
+    for removed in std::mem::take(&mut change_set.removed) {
         let item = items.get_mut(removed);
         assert_eq!(*item.change_state(), ChangeState::StagedTombstone);
         *item.change_state_mut() = ChangeState::Active;
@@ -262,6 +275,69 @@ impl<J: Clone, S: Clone> Network<J, S> {
         ret
     }
 
+    /// Add a junction as part of the change set. It won't be used until the change set is applied.
+    pub fn add_staged_junction<K>(&mut self, location: K, data: J) -> JunctionHandle
+    where
+        K: Into<Key>,
+    {
+        let id = self.add_junction(location, data);
+        self.junction_mut(id).change_state = ChangeState::StagedActive;
+        self.change_set.junctions.created.insert(id);
+        id
+    }
+
+    /// Add a segment as part of the change set. It won't be used until the change set is applied.
+    pub fn add_staged_segment(
+        &mut self,
+        data: S,
+        start_junction: JunctionHandle,
+        end_junction: JunctionHandle,
+        keys: Option<Vec<Key>>,
+    ) -> SegmentHandle {
+        let id = self.add_segment(data, start_junction, end_junction, keys);
+        self.segment_mut(id).change_state = ChangeState::StagedActive;
+        self.change_set.segments.created.insert(id);
+        id
+    }
+
+    /// Mark an active segment for removal when the change set is applied.
+    pub fn stage_segment_removal(&mut self, id: SegmentHandle) {
+        let segment = self.segments.get_mut(id);
+        assert_eq!(segment.change_state, ChangeState::Active);
+        segment.change_state = ChangeState::StagedTombstone;
+        self.change_set.segments.removed.insert(id);
+    }
+
+    /**
+     * Undo staging a segment with add_staged_segment or stage_segment_removal. Staged junctions
+     * that are left without any segments are removed as well.
+     */
+    pub fn unstage_segment(&mut self, id: SegmentHandle) {
+        if self.change_set.segments.created.remove(&id) {
+            let segment = self.segment(id);
+            assert_eq!(segment.change_state, ChangeState::StagedActive);
+            let endpoints = [segment.start_junction(), segment.end_junction()];
+            self.remove_segment(id);
+
+            for junction in endpoints {
+                let orphaned = {
+                    let junction = self.junction(junction);
+                    junction.incoming_segments().is_empty()
+                        && junction.outgoing_segments().is_empty()
+                };
+                if orphaned && self.change_set.junctions.created.remove(&junction) {
+                    self.remove_junction(junction);
+                }
+            }
+        } else if self.change_set.segments.removed.remove(&id) {
+            let segment = self.segments.get_mut(id);
+            assert_eq!(segment.change_state, ChangeState::StagedTombstone);
+            segment.change_state = ChangeState::Active;
+        } else {
+            panic!("segment is not part of the change set: {:?}", id);
+        }
+    }
+
     pub fn apply_change_set(&mut self) {
         apply_change_set(&mut self.change_set.junctions, &mut self.junctions);
         apply_change_set(&mut self.change_set.segments, &mut self.segments);
@@ -287,3 +363,55 @@ impl<J: Clone, S: Clone> Network<J, S> {
         }
     }
 }
+
+#[cfg(test)]
+mod staged_tests {
+    use crate::{ChangeState, Network};
+
+    #[test]
+    fn unstage_added_segment() {
+        let mut network = Network::<(), ()>::new();
+        let a = network.add_junction((0.0, 0.0), ());
+        let b = network.add_staged_junction((1.0, 0.0), ());
+        let c = network.add_staged_junction((2.0, 0.0), ());
+        let ab = network.add_staged_segment((), a, b, None);
+        let bc = network.add_staged_segment((), b, c, None);
+        assert_eq!(network.segment(ab).change_state, ChangeState::StagedActive);
+        network.validate();
+
+        // b is still used by bc, but c is orphaned
+        network.unstage_segment(bc);
+        assert!(network.junctions().contains_key(&b));
+        assert!(!network.junctions().contains_key(&c));
+
+        // a was never staged, so it stays
+        network.unstage_segment(ab);
+        assert!(network.junctions().contains_key(&a));
+        assert!(!network.junctions().contains_key(&b));
+        assert!(network.segments().is_empty());
+        network.validate();
+
+        // nothing left to apply
+        network.apply_change_set();
+        assert_eq!(network.junctions().len(), 1);
+    }
+
+    #[test]
+    fn unstage_removed_segment() {
+        let mut network = Network::<(), ()>::new();
+        let a = network.add_junction((0.0, 0.0), ());
+        let b = network.add_junction((1.0, 0.0), ());
+        let ab = network.add_segment((), a, b, None);
+
+        network.stage_segment_removal(ab);
+        assert_eq!(
+            network.segment(ab).change_state,
+            ChangeState::StagedTombstone
+        );
+        network.unstage_segment(ab);
+        assert_eq!(network.segment(ab).change_state, ChangeState::Active);
+
+        network.apply_change_set();
+        assert_eq!(network.segment(ab).change_state, ChangeState::Active);
+    }
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+/**
+ * Identifies an item in a slot-based map. Slots are reused after items are removed, and the
+ * generation is incremented each time, so a handle to a removed item never matches the item that
+ * later takes its slot.
+ *
+ * This is serialized as a single integer, with the generation in the upper bits. Handles from
+ * before generations were introduced have generation zero, so they are read back unchanged.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(from = "u64", into = "u64")]
+pub struct HandleId {
+    index: u32,
+    generation: u32,
+}
+
+impl HandleId {
+    pub fn new(index: u32, generation: u32) -> Self {
+        Self { index, generation }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// The handle for the next item to use the same slot.
+    pub fn next_generation(&self) -> Self {
+        Self::new(self.index, self.generation + 1)
+    }
+
+    pub fn to_bits(&self) -> u64 {
+        (self.generation as u64) << 32 | self.index as u64
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Self::new(bits as u32, (bits >> 32) as u32)
+    }
+}
+
+impl From<u64> for HandleId {
+    fn from(bits: u64) -> Self {
+        Self::from_bits(bits)
+    }
+}
+
+impl From<HandleId> for u64 {
+    fn from(id: HandleId) -> Self {
+        id.to_bits()
+    }
+}
+
+impl std::fmt::Display for HandleId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.index, self.generation)
+    }
+}
+
+impl std::fmt::Debug for HandleId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handle::*;
+
+    #[test]
+    fn bits() {
+        let id = HandleId::new(3, 2);
+        assert_eq!(HandleId::from_bits(id.to_bits()), id);
+        assert_ne!(id, id.next_generation());
+        assert_eq!(id.next_generation().index(), 3);
+
+        // handles saved before generations were introduced
+        assert_eq!(HandleId::from_bits(7), HandleId::new(7, 0));
+
+        assert_eq!(format!("{}", id), "3@2");
+        assert_eq!(format!("{:?}", id), "3@2");
+    }
+}
@@ -1,22 +1,38 @@
 use serde::{Deserialize, Serialize};
 
 use crate::change_state::{ChangeState, WithChangeState};
+use crate::handle::HandleId;
 use crate::network::{Handle, Key, WithHandle};
 use crate::segment::SegmentHandle;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-pub struct JunctionHandle(pub(crate) u64);
+pub struct JunctionHandle(pub(crate) HandleId);
 
 impl JunctionHandle {
+    /// The index and generation packed into one integer, which is unique among all handles.
     pub fn inner(&self) -> u64 {
+        self.0.to_bits()
+    }
+
+    pub fn id(&self) -> HandleId {
         self.0
     }
 }
 
 impl Handle for JunctionHandle {
-    fn create(id: u64) -> Self {
+    fn create(id: HandleId) -> Self {
         Self(id)
     }
+
+    fn id(&self) -> HandleId {
+        self.0
+    }
+}
+
+impl std::fmt::Display for JunctionHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
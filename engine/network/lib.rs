@@ -1,11 +1,13 @@
 mod change_state;
+mod handle;
 mod junction;
 mod network;
 mod segment;
 mod timing;
 
 pub use change_state::{ChangeSet, ChangeState, NetworkChangeSet};
+pub use handle::HandleId;
 pub use junction::{Junction, JunctionHandle};
 pub use network::{Key, Network};
-pub use segment::{KeyVisitor, Segment, SegmentHandle};
-pub use timing::TimingConfig;
+pub use segment::{KeyVisitor, Segment, SegmentData, SegmentHandle};
+pub use timing::{find_sharp_turn, geometry_issues, GeometryIssue, TimingConfig};
@@ -3,13 +3,17 @@ use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 
 use crate::change_state::{NetworkChangeSet, WithChangeState};
+use crate::handle::HandleId;
 use crate::junction::{Junction, JunctionHandle};
-use crate::segment::{Segment, SegmentHandle};
+use crate::segment::{Segment, SegmentData, SegmentHandle};
 
 pub type Key = cgmath::Vector2<f64>;
 
-pub(crate) trait Handle: Copy + std::cmp::Ord + std::hash::Hash + Eq {
-    fn create(id: u64) -> Self;
+pub(crate) trait Handle:
+    Copy + std::cmp::Ord + std::hash::Hash + Eq + std::fmt::Display
+{
+    fn create(id: HandleId) -> Self;
+    fn id(&self) -> HandleId;
 }
 
 pub(crate) trait WithHandle<H: Handle> {
@@ -17,10 +21,17 @@ pub(crate) trait WithHandle<H: Handle> {
     fn clone_new_id(&self, id: H) -> Self;
 }
 
+/**
+ * Stores items by handle. The slots of removed items are reused, but with a new generation, so a
+ * handle to a removed item is detected as stale rather than silently referring to a new item.
+ */
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct ManagedMap<K: Handle, V: WithHandle<K>> {
     pub(crate) inner: BTreeMap<K, V>,
     counter: u64,
+    /// handles of removed items, whose slots can be reused
+    #[serde(default = "Vec::new")]
+    free: Vec<K>,
 }
 
 impl<K: Handle, V: WithHandle<K>> ManagedMap<K, V> {
@@ -28,6 +39,18 @@ impl<K: Handle, V: WithHandle<K>> ManagedMap<K, V> {
         Self {
             inner: BTreeMap::new(),
             counter: 0,
+            free: Vec::new(),
+        }
+    }
+
+    fn next_id(&mut self) -> K {
+        match self.free.pop() {
+            Some(removed) => Handle::create(removed.id().next_generation()),
+            None => {
+                let id = Handle::create(HandleId::new(self.counter as u32, 0));
+                self.counter += 1;
+                id
+            }
         }
     }
 
@@ -35,34 +58,60 @@ impl<K: Handle, V: WithHandle<K>> ManagedMap<K, V> {
     where
         F: FnOnce(K) -> V,
     {
-        let id = Handle::create(self.counter);
-        self.counter += 1;
+        let id = self.next_id();
         self.inner.insert(id, value_f(id));
         id
     }
 
     fn remove(&mut self, id: K) -> V {
-        // TODO: in the future, it could be useful to add some tracking here for debugging issues
-        // with removed items
         match self.inner.remove(&id) {
-            Some(removed) => removed,
-            None => panic!("attempt to remove invalid handle"),
+            Some(removed) => {
+                self.free.push(id);
+                removed
+            }
+            None => panic!("attempt to remove {}", self.describe_invalid(id)),
         }
     }
 
     pub fn clone_item(&mut self, id: K) -> K {
-        let new_id = Handle::create(self.counter);
-        self.counter += 1;
+        let new_id = self.next_id();
         self.inner.insert(new_id, self.get(id).clone_new_id(new_id));
         new_id
     }
 
+    pub fn contains(&self, id: K) -> bool {
+        self.inner.contains_key(&id)
+    }
+
+    pub fn try_get(&self, id: K) -> Option<&V> {
+        self.inner.get(&id)
+    }
+
+    pub fn try_get_mut(&mut self, id: K) -> Option<&mut V> {
+        self.inner.get_mut(&id)
+    }
+
     pub fn get(&self, id: K) -> &V {
-        self.inner.get(&id).expect("invalid handle")
+        match self.inner.get(&id) {
+            Some(value) => value,
+            None => panic!("{}", self.describe_invalid(id)),
+        }
     }
 
     pub fn get_mut(&mut self, id: K) -> &mut V {
-        self.inner.get_mut(&id).expect("invalid handle")
+        if !self.inner.contains_key(&id) {
+            panic!("{}", self.describe_invalid(id));
+        }
+        self.inner.get_mut(&id).unwrap()
+    }
+
+    /// Explain why a handle does not refer to an item, for use in panic messages.
+    fn describe_invalid(&self, id: K) -> String {
+        if (id.id().index() as u64) < self.counter {
+            format!("stale handle {}: the item was removed", id)
+        } else {
+            format!("invalid handle {}", id)
+        }
     }
 }
 
@@ -104,6 +153,45 @@ impl<J: Clone, S: Clone> Network<J, S> {
         self.segments.get_mut(id)
     }
 
+    /// Like junction(), but returns None if the handle is stale instead of panicking.
+    pub fn try_junction(&self, id: JunctionHandle) -> Option<&Junction<J>> {
+        self.junctions.try_get(id)
+    }
+
+    /// Like segment(), but returns None if the handle is stale instead of panicking.
+    pub fn try_segment(&self, id: SegmentHandle) -> Option<&Segment<S>> {
+        self.segments.try_get(id)
+    }
+
+    pub fn try_segment_mut(&mut self, id: SegmentHandle) -> Option<&mut Segment<S>> {
+        self.segments.try_get_mut(id)
+    }
+
+    /**
+     * Replace the data of a segment after checking it, returning the old data. The segment's
+     * cached timing is discarded, since it may depend on the data, e.g. on the speed limit.
+     * Anything built from the timing, like a routing graph, must be reconstructed afterwards.
+     */
+    pub fn update_segment_data(&mut self, id: SegmentHandle, data: S) -> Result<S, String>
+    where
+        S: SegmentData,
+    {
+        data.validate()?;
+        let segment = self
+            .try_segment_mut(id)
+            .ok_or_else(|| format!("segment {} does not exist", id))?;
+        segment.clear_timing();
+        Ok(std::mem::replace(&mut segment.data, data))
+    }
+
+    pub fn contains_junction(&self, id: JunctionHandle) -> bool {
+        self.junctions.contains(id)
+    }
+
+    pub fn contains_segment(&self, id: SegmentHandle) -> bool {
+        self.segments.contains(id)
+    }
+
     pub fn add_junction<K>(&mut self, location: K, data: J) -> JunctionHandle
     where
         K: Into<Key>,
@@ -170,13 +258,48 @@ impl<J: Clone, S: Clone> Network<J, S> {
         &self.segments.inner
     }
 
+    /**
+     * Find the segment closest to the given point, considering only segments within max_distance
+     * (in coordinate space) that satisfy the filter. Distances are measured against the cached
+     * spline samples of each segment. Returns the segment and its distance.
+     */
+    pub fn nearest_segment<F>(
+        &self,
+        point: Key,
+        max_distance: f64,
+        filter: F,
+    ) -> Option<(&Segment<S>, f64)>
+    where
+        F: Fn(&Segment<S>) -> bool,
+    {
+        self.segments
+            .inner
+            .values()
+            .filter(|segment| {
+                // NOTE: bounds are truncated to integer coordinates, so pad them by one
+                let bounds = &segment.bounds;
+                point.x >= bounds.min_x as f64 - max_distance
+                    && point.x <= bounds.max_x as f64 + max_distance + 1.0
+                    && point.y >= bounds.min_y as f64 - max_distance
+                    && point.y <= bounds.max_y as f64 + max_distance + 1.0
+            })
+            .filter(|segment| filter(segment))
+            .filter_map(|segment| {
+                segment
+                    .distance_to(point)
+                    .filter(|distance| *distance <= max_distance)
+                    .map(|distance| (segment, distance))
+            })
+            .min_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).unwrap())
+    }
+
     pub fn remove_junction(&mut self, id: JunctionHandle) {
         let junction = self.junctions.remove(id);
         for incoming in junction.incoming_segments() {
-            assert!(!self.segments.inner.contains_key(incoming));
+            assert!(!self.segments.contains(*incoming));
         }
         for outgoing in junction.outgoing_segments() {
-            assert!(!self.segments.inner.contains_key(outgoing));
+            assert!(!self.segments.contains(*outgoing));
         }
     }
 
@@ -324,3 +447,113 @@ impl<J: Clone, S: Clone> Network<J, S> {
         }
     }
 }
+
+#[cfg(test)]
+mod nearest_segment_tests {
+    use crate::network::Network;
+    use float_cmp::assert_approx_eq;
+
+    fn make_network() -> Network<(), ()> {
+        let mut network = Network::new();
+        let a = network.add_junction((0.0, 0.0), ());
+        let b = network.add_junction((100.0, 0.0), ());
+        let c = network.add_junction((100.0, 100.0), ());
+        network.add_segment((), a, b, Some(vec![(0.0, 0.0).into(), (100.0, 0.0).into()]));
+        network.add_segment(
+            (),
+            b,
+            c,
+            Some(vec![(100.0, 0.0).into(), (100.0, 100.0).into()]),
+        );
+        network
+    }
+
+    #[test]
+    fn nearest() {
+        let network = make_network();
+
+        let (segment, distance) = network
+            .nearest_segment((50.0, 3.0).into(), 5.0, |_| true)
+            .unwrap();
+        assert_eq!(segment.start_junction().inner(), 0);
+        assert_approx_eq!(f64, distance, 3.0);
+
+        let (segment, distance) = network
+            .nearest_segment((98.0, 50.0).into(), 5.0, |_| true)
+            .unwrap();
+        assert_eq!(segment.start_junction().inner(), 1);
+        assert_approx_eq!(f64, distance, 2.0);
+
+        // near the shared junction, but slightly closer to the second segment
+        let (segment, _) = network
+            .nearest_segment((99.0, 2.0).into(), 5.0, |_| true)
+            .unwrap();
+        assert_eq!(segment.start_junction().inner(), 1);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let network = make_network();
+        assert!(network
+            .nearest_segment((50.0, 6.0).into(), 5.0, |_| true)
+            .is_none());
+        // outside the (unpadded) bounds of the horizontal segment, but still within range
+        assert!(network
+            .nearest_segment((50.0, -4.0).into(), 5.0, |_| true)
+            .is_some());
+        assert!(network
+            .nearest_segment((-4.0, 0.0).into(), 5.0, |_| true)
+            .is_some());
+    }
+
+    #[test]
+    fn filtered() {
+        let network = make_network();
+        let (segment, distance) = network
+            .nearest_segment((98.0, 2.0).into(), 5.0, |segment| {
+                segment.start_junction().inner() == 0
+            })
+            .unwrap();
+        assert_eq!(segment.start_junction().inner(), 0);
+        assert_approx_eq!(f64, distance, 2.0);
+    }
+}
+
+#[cfg(test)]
+mod handle_tests {
+    use crate::network::Network;
+
+    #[test]
+    fn reuse_removed_slot() {
+        let mut network: Network<(), ()> = Network::new();
+        let a = network.add_junction((0.0, 0.0), ());
+        let b = network.add_junction((100.0, 0.0), ());
+        let old = network.add_segment((), a, b, None);
+        network.remove_segment(old);
+
+        let new = network.add_segment((), b, a, None);
+        assert_eq!(old.id().index(), new.id().index());
+        assert_ne!(old.id().generation(), new.id().generation());
+        assert_ne!(old, new);
+
+        assert!(network.try_segment(old).is_none());
+        assert!(!network.contains_segment(old));
+        assert_eq!(network.try_segment(new).unwrap().start_junction(), b);
+        assert_eq!(network.junction(a).incoming_segments(), &[new]);
+        assert!(network.junction(a).outgoing_segments().is_empty());
+        network.validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "stale handle 0@0")]
+    fn stale_lookup_panics() {
+        let mut network: Network<(), ()> = Network::new();
+        let a = network.add_junction((0.0, 0.0), ());
+        let b = network.add_junction((100.0, 0.0), ());
+        let old = network.add_segment((), a, b, None);
+        network.remove_segment(old);
+        network.add_segment((), a, b, None);
+
+        network.segment(old);
+    }
+}
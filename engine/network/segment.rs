@@ -1,24 +1,49 @@
-use once_cell::unsync::OnceCell;
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 
 use crate::change_state::{ChangeState, WithChangeState};
+use crate::handle::HandleId;
 use crate::junction::JunctionHandle;
 use crate::network::{Handle, Key, WithHandle};
 use crate::timing::TimingConfig;
 
+/// Maximum spacing (in coordinate space) between the cached samples of a segment's spline.
+const SAMPLE_STEP: f64 = 1.0;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-pub struct SegmentHandle(pub(crate) u64);
+pub struct SegmentHandle(pub(crate) HandleId);
 
 impl SegmentHandle {
+    /// The index and generation packed into one integer, which is unique among all handles.
     pub fn inner(&self) -> u64 {
+        self.0.to_bits()
+    }
+
+    pub fn id(&self) -> HandleId {
         self.0
     }
 }
 
 impl Handle for SegmentHandle {
-    fn create(id: u64) -> Self {
+    fn create(id: HandleId) -> Self {
         Self(id)
     }
+
+    fn id(&self) -> HandleId {
+        self.0
+    }
+}
+
+impl std::fmt::Display for SegmentHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The data attached to the segments of a network, e.g. a highway's name and speed limit.
+pub trait SegmentData {
+    /// Check that the data makes sense, e.g. that the speed limit is positive.
+    fn validate(&self) -> Result<(), String>;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +59,9 @@ pub struct Segment<T> {
     /// spline mapping time to distance (in meters) along spline
     #[serde(skip)]
     dist_spline: OnceCell<(TimingConfig, splines::Spline<f64, f64>)>,
+    /// points sampled along the spline, used for hit testing
+    #[serde(skip)]
+    samples: OnceCell<Vec<Key>>,
     pub(crate) start: JunctionHandle,
     pub(crate) end: JunctionHandle,
     pub change_state: ChangeState,
@@ -78,6 +106,7 @@ impl<T> Segment<T> {
             spline: splines::Spline::from_vec(Vec::new()),
             length: 0.0,
             dist_spline: OnceCell::new(),
+            samples: OnceCell::new(),
             start: start_junction,
             end: end_junction,
             change_state: ChangeState::Active,
@@ -108,6 +137,8 @@ impl<T> Segment<T> {
         self.keys = keys;
         self.spline = splines::Spline::from_vec(spline_keys);
         self.length = t;
+        self.samples = OnceCell::new();
+        self.clear_timing();
     }
 
     pub fn length(&self) -> f64 {
@@ -146,6 +177,22 @@ impl<T> Segment<T> {
         )
     }
 
+    /**
+     * Sample the spline as a polyline in model coordinates, with samples spaced `resolution` apart
+     * along the segment.
+     */
+    pub fn sample_polyline(&self, resolution: f64) -> Vec<(f64, f64)> {
+        assert!(resolution > 0.0, "resolution must be positive");
+        let mut visitor = spline_util::PolylineVisitor::new(|key: &Key| (key.x, key.y));
+        self.visit_spline(
+            &mut visitor,
+            resolution,
+            &spline_util::pad_bounds(&self.bounds),
+        )
+        .unwrap();
+        visitor.polylines.concat()
+    }
+
     pub fn visit_keys<V, E>(&self, visitor: &mut V, rect: &quadtree::Rect) -> Result<(), E>
     where
         V: KeyVisitor<T, E>,
@@ -167,6 +214,68 @@ impl<T> Segment<T> {
         Ok(())
     }
 
+    /**
+     * Points along the spline, including every key, spaced at most SAMPLE_STEP apart. These are
+     * computed on first use and cached until the keys change.
+     */
+    pub fn samples(&self) -> &[Key] {
+        self.samples.get_or_init(|| {
+            let keys = self.spline.keys();
+            let mut samples = Vec::new();
+            for pair in keys.windows(2) {
+                let (start, end) = (pair[0].t, pair[1].t);
+                // NOTE: sample the key itself, since repeated keys would divide by zero
+                samples.push(pair[0].value);
+                let steps = ((end - start) / SAMPLE_STEP).ceil() as usize;
+                for i in 1..steps {
+                    let t = start + (end - start) * i as f64 / steps as f64;
+                    samples.push(self.spline.clamped_sample(t).unwrap());
+                }
+            }
+            if let Some(last) = keys.last() {
+                samples.push(last.value);
+            }
+            samples
+        })
+    }
+
+    /**
+     * The shortest distance (in coordinate space) from the given point to this segment, measured
+     * against the line segments between consecutive spline samples. Returns None if the segment
+     * has no keys.
+     */
+    pub fn distance_to(&self, point: Key) -> Option<f64> {
+        use cgmath::{InnerSpace, MetricSpace};
+
+        let samples = self.samples();
+        if samples.len() == 1 {
+            return Some(point.distance(samples[0]));
+        }
+
+        samples
+            .windows(2)
+            .map(|pair| {
+                let (a, b) = (pair[0], pair[1]);
+                let ab = b - a;
+                let len2 = ab.magnitude2();
+                if len2 == 0.0 {
+                    return point.distance(a);
+                }
+                // project onto the line through a and b, clamped to the endpoints
+                let t = ((point - a).dot(ab) / len2).clamp(0.0, 1.0);
+                point.distance(a + ab * t)
+            })
+            .min_by(|d1, d2| d1.partial_cmp(d2).unwrap())
+    }
+
+    /**
+     * Discard the cached timing for this segment. This must be called whenever the timing config
+     * for the segment changes, e.g. when a station is added or removed at either end.
+     */
+    pub fn clear_timing(&mut self) {
+        self.dist_spline = OnceCell::new();
+    }
+
     fn construct_dist_spline(&self, config: &TimingConfig) -> splines::Spline<f64, f64> {
         let speed_keys = crate::timing::speed_keys(&self.keys, config);
         crate::timing::dist_spline(&speed_keys)
@@ -194,3 +303,137 @@ impl<T> Segment<T> {
 pub trait KeyVisitor<T, E> {
     fn visit(&mut self, segment: &Segment<T>, key: &Key) -> Result<(), E>;
 }
+
+#[cfg(test)]
+mod distance_tests {
+    use crate::handle::HandleId;
+    use crate::junction::JunctionHandle;
+    use crate::segment::{Segment, SegmentHandle};
+    use float_cmp::assert_approx_eq;
+
+    fn make_segment(keys: Vec<(f64, f64)>) -> Segment<()> {
+        let mut segment = Segment::new(
+            SegmentHandle(HandleId::new(0, 0)),
+            (),
+            JunctionHandle(HandleId::new(0, 0)),
+            JunctionHandle(HandleId::new(1, 0)),
+        );
+        segment.set_keys(keys.into_iter().map(|key| key.into()).collect());
+        segment
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(make_segment(vec![]).distance_to((0.0, 0.0).into()), None);
+    }
+
+    #[test]
+    fn single_key() {
+        let segment = make_segment(vec![(1.0, 1.0)]);
+        assert_approx_eq!(f64, segment.distance_to((4.0, 5.0).into()).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn straight() {
+        let segment = make_segment(vec![(0.0, 0.0), (10.0, 0.0)]);
+        // perpendicular to the middle of the segment
+        assert_approx_eq!(f64, segment.distance_to((5.0, 3.0).into()).unwrap(), 3.0);
+        assert_approx_eq!(f64, segment.distance_to((5.0, -3.0).into()).unwrap(), 3.0);
+        // on the segment
+        assert_approx_eq!(f64, segment.distance_to((2.0, 0.0).into()).unwrap(), 0.0);
+        // beyond the endpoints
+        assert_approx_eq!(f64, segment.distance_to((-3.0, 4.0).into()).unwrap(), 5.0);
+        assert_approx_eq!(f64, segment.distance_to((13.0, -4.0).into()).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn polyline() {
+        let segment = make_segment(vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]);
+        // closest to the second piece
+        assert_approx_eq!(f64, segment.distance_to((12.0, 5.0).into()).unwrap(), 2.0);
+        // inside the corner, closest to both pieces
+        assert_approx_eq!(f64, segment.distance_to((9.0, 1.0).into()).unwrap(), 1.0);
+        // outside the corner, closest to the shared key
+        assert_approx_eq!(f64, segment.distance_to((13.0, -4.0).into()).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn repeated_keys() {
+        let segment = make_segment(vec![(0.0, 0.0), (0.0, 0.0), (0.0, 10.0)]);
+        assert_approx_eq!(f64, segment.distance_to((3.0, 5.0).into()).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn samples() {
+        let segment = make_segment(vec![(0.0, 0.0), (0.0, 0.0), (0.0, 2.5), (2.0, 2.5)]);
+        let samples: Vec<(f64, f64)> = segment.samples().iter().map(|key| (key.x, key.y)).collect();
+        assert_eq!(samples.len(), 7);
+        assert_eq!(samples[0], (0.0, 0.0));
+        assert_eq!(samples[1], (0.0, 0.0));
+        assert_eq!(samples[4], (0.0, 2.5));
+        assert_eq!(samples[6], (2.0, 2.5));
+        for pair in samples.windows(2) {
+            let step = ((pair[1].0 - pair[0].0).powi(2) + (pair[1].1 - pair[0].1).powi(2)).sqrt();
+            assert!(step <= 1.0 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn samples_cleared() {
+        let mut segment = make_segment(vec![(0.0, 0.0), (10.0, 0.0)]);
+        assert_approx_eq!(f64, segment.distance_to((5.0, 3.0).into()).unwrap(), 3.0);
+        segment.set_keys(vec![(0.0, 2.0).into(), (10.0, 2.0).into()]);
+        assert_approx_eq!(f64, segment.distance_to((5.0, 3.0).into()).unwrap(), 1.0);
+    }
+}
+
+#[cfg(test)]
+mod sample_polyline_tests {
+    use crate::handle::HandleId;
+    use crate::junction::JunctionHandle;
+    use crate::segment::{Segment, SegmentHandle};
+    use float_cmp::assert_approx_eq;
+
+    fn make_segment(keys: Vec<(f64, f64)>) -> Segment<()> {
+        let mut segment = Segment::new(
+            SegmentHandle(HandleId::new(0, 0)),
+            (),
+            JunctionHandle(HandleId::new(0, 0)),
+            JunctionHandle(HandleId::new(1, 0)),
+        );
+        segment.set_keys(keys.into_iter().map(|key| key.into()).collect());
+        segment
+    }
+
+    #[test]
+    fn empty() {
+        assert!(make_segment(vec![]).sample_polyline(1.0).is_empty());
+    }
+
+    #[test]
+    fn straight_is_collinear() {
+        let segment = make_segment(vec![(1.0, 2.0), (7.0, 10.0)]);
+        let points = segment.sample_polyline(0.5);
+
+        // 10 units long, so 20 steps plus the starting point
+        assert_eq!(points.len(), 21);
+        assert_eq!(points.first(), Some(&(1.0, 2.0)));
+        assert_eq!(points.last(), Some(&(7.0, 10.0)));
+        for (x, y) in points {
+            // cross product with the direction of the segment
+            assert_approx_eq!(f64, (x - 1.0) * 8.0 - (y - 2.0) * 6.0, 0.0, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn keys_are_in_order() {
+        let segment = make_segment(vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]);
+        let points = segment.sample_polyline(1.0);
+        assert_eq!(points.len(), 21);
+        assert!(points.contains(&(10.0, 0.0)));
+        assert!(points.windows(2).all(|pair| {
+            let ((x1, y1), (x2, y2)) = (pair[0], pair[1]);
+            ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt() <= 1.0 + 1e-9
+        }));
+    }
+}
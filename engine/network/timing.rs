@@ -88,6 +88,85 @@ impl PartialOrd for SqrtPair {
     }
 }
 
+/**
+ * Find the first key at which the keys turn by 90 degrees or more, if any. Timing can't be
+ * computed for these turns, and speed_bounds will panic on some of them, so geometry should be
+ * checked with this before it is committed.
+ */
+pub fn find_sharp_turn(keys: &[Key]) -> Option<Key> {
+    sharp_turns(keys).next()
+}
+
+fn sharp_turns(keys: &[Key]) -> impl Iterator<Item = Key> + '_ {
+    use cgmath::InnerSpace;
+    use itertools::Itertools;
+
+    keys.iter()
+        .tuple_windows()
+        .filter(|(prev_key, key, next_key)| {
+            let angle_diff = (*key - *prev_key).angle(*next_key - *key);
+            angle_diff.0.is_nan() || angle_diff >= cgmath::Rad(std::f64::consts::FRAC_PI_2)
+        })
+        .map(|(_, key, _)| *key)
+}
+
+/// A problem with the keys of a segment that would make its timing meaningless.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeometryIssue {
+    /// there are fewer than two keys, so the segment has no length at all
+    TooFewKeys,
+    /// a key has a NaN or infinite coordinate
+    NonFiniteKey(Key),
+    /// the key is at the same location as the one before it, so the direction is undefined
+    ZeroLengthSpan(Key),
+    /// the keys turn by 90 degrees or more at the key, including when they double back
+    SharpTurn(Key),
+}
+
+impl std::fmt::Display for GeometryIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooFewKeys => write!(f, "fewer than two keys"),
+            Self::NonFiniteKey(key) => write!(f, "non-finite key at ({}, {})", key.x, key.y),
+            Self::ZeroLengthSpan(key) => write!(f, "zero-length span at ({}, {})", key.x, key.y),
+            Self::SharpTurn(key) => write!(f, "sharp turn at ({}, {})", key.x, key.y),
+        }
+    }
+}
+
+/**
+ * Find every problem with the keys that would cause speed_bounds to panic or produce NaN travel
+ * times. Zero-length spans are skipped when looking for sharp turns, so that each problem is only
+ * reported once.
+ */
+pub fn geometry_issues(keys: &[Key]) -> Vec<GeometryIssue> {
+    if keys.len() < 2 {
+        return vec![GeometryIssue::TooFewKeys];
+    }
+
+    let mut issues: Vec<_> = keys
+        .iter()
+        .filter(|key| !key.x.is_finite() || !key.y.is_finite())
+        .map(|key| GeometryIssue::NonFiniteKey(*key))
+        .collect();
+    if !issues.is_empty() {
+        // distances and angles are meaningless without finite keys
+        return issues;
+    }
+
+    let mut distinct = vec![keys[0]];
+    for key in &keys[1..] {
+        if distinct.last() == Some(key) {
+            issues.push(GeometryIssue::ZeroLengthSpan(*key));
+        } else {
+            distinct.push(*key);
+        }
+    }
+    issues.extend(sharp_turns(&distinct).map(GeometryIssue::SharpTurn));
+
+    issues
+}
+
 /**
  * Convert each key into a speed bound, a SqrtPair.
  */
@@ -447,3 +526,54 @@ mod dist_spline_tests {
         assert_approx_eq!(f64, keys[2].value, 2.0);
     }
 }
+
+#[cfg(test)]
+mod sharp_turn_tests {
+    use crate::timing::{find_sharp_turn, geometry_issues, GeometryIssue};
+    use crate::Key;
+
+    #[test]
+    fn find_sharp_turn_test() {
+        let straight = [Key::new(0.0, 0.0), Key::new(1.0, 0.0), Key::new(2.0, 0.0)];
+        assert_eq!(find_sharp_turn(&straight), None);
+
+        let right_angle = [Key::new(0.0, 0.0), Key::new(1.0, 0.0), Key::new(1.0, 1.0)];
+        assert_eq!(find_sharp_turn(&right_angle), Some(Key::new(1.0, 0.0)));
+
+        let hairpin = [Key::new(0.0, 0.0), Key::new(1.0, 0.0), Key::new(0.0, 0.5)];
+        assert_eq!(find_sharp_turn(&hairpin), Some(Key::new(1.0, 0.0)));
+
+        let gentle = [Key::new(0.0, 0.0), Key::new(1.0, 0.0), Key::new(2.0, 0.5)];
+        assert_eq!(find_sharp_turn(&gentle), None);
+    }
+
+    #[test]
+    fn geometry_issues_test() {
+        let gentle = [Key::new(0.0, 0.0), Key::new(1.0, 0.0), Key::new(2.0, 0.5)];
+        assert_eq!(geometry_issues(&gentle), vec![]);
+
+        assert_eq!(
+            geometry_issues(&[Key::new(0.0, 0.0)]),
+            vec![GeometryIssue::TooFewKeys]
+        );
+
+        // the repeated key isn't also reported as a sharp turn
+        let repeated = [Key::new(0.0, 0.0), Key::new(1.0, 0.0), Key::new(1.0, 0.0)];
+        assert_eq!(
+            geometry_issues(&repeated),
+            vec![GeometryIssue::ZeroLengthSpan(Key::new(1.0, 0.0))]
+        );
+
+        let backtracking = [Key::new(0.0, 0.0), Key::new(2.0, 0.0), Key::new(1.0, 0.0)];
+        assert_eq!(
+            geometry_issues(&backtracking),
+            vec![GeometryIssue::SharpTurn(Key::new(2.0, 0.0))]
+        );
+
+        let nan = [Key::new(0.0, 0.0), Key::new(f64::NAN, 0.0)];
+        assert!(matches!(
+            geometry_issues(&nan)[..],
+            [GeometryIssue::NonFiniteKey(_)]
+        ));
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+
+use crate::engine::{Engine, Error};
+
+/// The most zones along each side of the map that commutes are counted between.
+pub const MAX_OD_ZONES: u32 = 64;
+
+/**
+ * The width of a single zone of the commute flow matrix, in units of the smallest tile size. This
+ * is the same as the local traffic grid, unless that would make more than MAX_OD_ZONES zones along
+ * each side of the map.
+ */
+pub fn od_zone_size(config: &state::Config) -> u32 {
+    route::local_traffic::grid_downsample(config).max(config.tile_width() / MAX_OD_ZONES)
+}
+
+/// The number of commutes between a pair of zones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OdFlow {
+    /// where the commuters live
+    pub origin: route::ZoneId,
+    /// where the commuters work
+    pub destination: route::ZoneId,
+    pub trips: u64,
+}
+
+/**
+ * Counts of the commutes to work that were completed on a single day, between the zones that the
+ * commuters live and work in. Each commuter is counted at most once per day, on their way to work.
+ * Only pairs of zones with at least one commute are stored.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OdMatrix {
+    /// the day that the commutes were completed on, counting from the start of the simulation
+    pub day: u64,
+    /// the width of each zone, in units of the smallest tile size; see od_zone_size
+    pub zone_size: u32,
+    flows: BTreeMap<(route::ZoneId, route::ZoneId), u64>,
+}
+
+impl OdMatrix {
+    const CSV_HEADER: &'static str =
+        "day,zone_size,origin_x,origin_y,destination_x,destination_y,trips\n";
+
+    fn new(day: u64, zone_size: u32) -> Self {
+        Self {
+            day,
+            zone_size,
+            flows: BTreeMap::new(),
+        }
+    }
+
+    /// The zone containing the given address.
+    pub fn zone(&self, address: quadtree::Address) -> route::ZoneId {
+        let (x, y) = address.to_xy();
+        let zone_size = self.zone_size as u64;
+        route::ZoneId::new((x / zone_size) as u32, (y / zone_size) as u32)
+    }
+
+    /// The center of the given zone, in model coordinates.
+    pub fn zone_center(&self, zone: route::ZoneId) -> (f64, f64) {
+        let zone_size = self.zone_size as f64;
+        (
+            (zone.x as f64 + 0.5) * zone_size,
+            (zone.y as f64 + 0.5) * zone_size,
+        )
+    }
+
+    fn record(&mut self, home: quadtree::Address, work: quadtree::Address) {
+        let key = (self.zone(home), self.zone(work));
+        *self.flows.entry(key).or_insert(0) += 1;
+    }
+
+    /// The number of commutes from the origin zone to the destination zone.
+    pub fn get(&self, origin: route::ZoneId, destination: route::ZoneId) -> u64 {
+        self.flows.get(&(origin, destination)).copied().unwrap_or(0)
+    }
+
+    /// All pairs of zones with at least one commute, ordered by origin and then destination.
+    pub fn flows(&self) -> impl Iterator<Item = OdFlow> + '_ {
+        self.flows
+            .iter()
+            .map(|((origin, destination), trips)| OdFlow {
+                origin: *origin,
+                destination: *destination,
+                trips: *trips,
+            })
+    }
+
+    /// The number of pairs of zones with at least one commute.
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    pub fn total_trips(&self) -> u64 {
+        self.flows.values().sum()
+    }
+
+    /**
+     * The `n` largest flows between different zones, largest first. Ties are broken by origin and
+     * then destination, so that the selection is stable. Commutes within a single zone are left
+     * out, since they don't go anywhere at this resolution.
+     */
+    pub fn top_flows(&self, n: usize) -> Vec<OdFlow> {
+        let mut flows: Vec<_> = self
+            .flows()
+            .filter(|flow| flow.origin != flow.destination)
+            .collect();
+        // the flows are already ordered by origin and destination, and the sort is stable
+        flows.sort_by_key(|flow| std::cmp::Reverse(flow.trips));
+        flows.truncate(n);
+        flows
+    }
+
+    /// One row per pair of zones with at least one commute, with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(Self::CSV_HEADER);
+        for flow in self.flows() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                self.day,
+                self.zone_size,
+                flow.origin.x,
+                flow.origin.y,
+                flow.destination.x,
+                flow.destination.y,
+                flow.trips,
+            ));
+        }
+        csv
+    }
+}
+
+/// The commute flow matrix that is being filled in today, and the one from the day before.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OdMatrices {
+    current: Option<OdMatrix>,
+    previous: Option<OdMatrix>,
+}
+
+impl OdMatrices {
+    fn day(current_time: u64) -> u64 {
+        current_time / state::TRAFFIC_HISTORY_PERIOD
+    }
+
+    /// Count a commute to work that has just been completed.
+    pub(crate) fn record(
+        &mut self,
+        config: &state::Config,
+        current_time: u64,
+        home: quadtree::Address,
+        work: quadtree::Address,
+    ) {
+        let today = Self::day(current_time);
+        if self.current.as_ref().map(|current| current.day) != Some(today) {
+            self.previous = self.current.take();
+        }
+        self.current
+            .get_or_insert_with(|| OdMatrix::new(today, od_zone_size(config)))
+            .record(home, work);
+    }
+
+    fn on_day(&self, day: u64) -> Option<&OdMatrix> {
+        self.current
+            .iter()
+            .chain(&self.previous)
+            .find(|matrix| matrix.day == day)
+    }
+}
+
+impl Engine {
+    /**
+     * The commutes to work that were completed yesterday, by the zones that the commuters live and
+     * work in. None if no commutes were completed yesterday.
+     */
+    pub fn od_matrix(&self) -> Option<&OdMatrix> {
+        let today = OdMatrices::day(self.time_state.current_time);
+        self.od_matrices.on_day(today.checked_sub(1)?)
+    }
+
+    /// Like od_matrix, but for the commutes that have been completed so far today.
+    pub fn od_matrix_today(&self) -> Option<&OdMatrix> {
+        let today = OdMatrices::day(self.time_state.current_time);
+        self.od_matrices.on_day(today)
+    }
+
+    /// Write yesterday's commute flow matrix as CSV, with one row per pair of zones.
+    pub fn export_od_matrix_csv(&self, path: &std::path::Path) -> Result<(), Error> {
+        let csv = match self.od_matrix() {
+            Some(matrix) => matrix.to_csv(),
+            None => OdMatrix::CSV_HEADER.to_string(),
+        };
+        std::fs::write(path, csv)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::od_matrix::*;
+
+    /// the coordinates of an origin zone and a destination zone
+    type Pair = ((u32, u32), (u32, u32));
+
+    fn matrix(flows: &[(Pair, u64)]) -> OdMatrix {
+        let mut matrix = OdMatrix::new(0, 4);
+        for ((origin, destination), trips) in flows {
+            matrix.flows.insert(
+                (
+                    route::ZoneId::new(origin.0, origin.1),
+                    route::ZoneId::new(destination.0, destination.1),
+                ),
+                *trips,
+            );
+        }
+        matrix
+    }
+
+    fn pairs(flows: &[OdFlow]) -> Vec<Pair> {
+        flows
+            .iter()
+            .map(|flow| {
+                (
+                    (flow.origin.x, flow.origin.y),
+                    (flow.destination.x, flow.destination.y),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn top_flows() {
+        let matrix = matrix(&[
+            (((0, 0), (1, 0)), 3),
+            (((0, 0), (2, 2)), 10),
+            (((1, 1), (1, 1)), 50),
+            (((2, 0), (0, 1)), 3),
+            (((1, 0), (0, 0)), 1),
+        ]);
+
+        // largest first, ties broken by origin, and nothing within a single zone
+        assert_eq!(
+            pairs(&matrix.top_flows(10)),
+            vec![
+                ((0, 0), (2, 2)),
+                ((0, 0), (1, 0)),
+                ((2, 0), (0, 1)),
+                ((1, 0), (0, 0)),
+            ]
+        );
+        assert_eq!(
+            pairs(&matrix.top_flows(2)),
+            vec![((0, 0), (2, 2)), ((0, 0), (1, 0))]
+        );
+        assert!(matrix.top_flows(0).is_empty());
+        assert_eq!(matrix.total_trips(), 67);
+    }
+
+    #[test]
+    fn zones() {
+        let matrix = matrix(&[]);
+        let address = quadtree::Address::from_xy(9, 3, 4);
+        assert_eq!(matrix.zone(address), route::ZoneId::new(2, 0));
+        assert_eq!(matrix.zone_center(route::ZoneId::new(2, 0)), (10.0, 2.0));
+    }
+
+    #[test]
+    fn roll_over() {
+        let config = state::Config {
+            max_depth: 4,
+            people_per_sim: 1.0,
+            min_tile_size: 100,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        };
+        let home = quadtree::Address::from_xy(0, 0, 4);
+        let work = quadtree::Address::from_xy(15, 15, 4);
+        let day = state::TRAFFIC_HISTORY_PERIOD;
+
+        let mut matrices = OdMatrices::default();
+        matrices.record(&config, 10, home, work);
+        matrices.record(&config, day + 10, home, work);
+        matrices.record(&config, day + 20, home, work);
+        assert_eq!(matrices.on_day(0).unwrap().total_trips(), 1);
+        assert_eq!(matrices.on_day(1).unwrap().total_trips(), 2);
+
+        // only the previous day is kept
+        matrices.record(&config, 3 * day, home, work);
+        assert!(matrices.on_day(0).is_none());
+        assert!(matrices.on_day(2).is_none());
+        assert_eq!(matrices.on_day(1).unwrap().total_trips(), 2);
+    }
+}
@@ -0,0 +1,142 @@
+use state::{BranchState, LeafState};
+
+use crate::engine::{Engine, Error};
+use crate::fields::FieldsState;
+
+/// How close a metro line's path has to pass to an orphan station to offer it as a stop, in meters.
+pub const ORPHAN_STATION_ADOPTION_RADIUS: f64 = 150.0;
+
+struct CollectOrphanStationsVisitor {
+    orphans: Vec<metro::OrphanStation>,
+}
+
+impl quadtree::Visitor<BranchState<FieldsState>, LeafState<FieldsState>, Error>
+    for CollectOrphanStationsVisitor
+{
+    fn visit_branch_pre(
+        &mut self,
+        _branch: &BranchState<FieldsState>,
+        _data: &quadtree::VisitData,
+    ) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn visit_leaf(
+        &mut self,
+        leaf: &LeafState<FieldsState>,
+        data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        if let Some(orphan) = orphan_station(leaf, data.address) {
+            self.orphans.push(orphan);
+        }
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &BranchState<FieldsState>,
+        _data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+fn orphan_station(
+    leaf: &LeafState<FieldsState>,
+    address: quadtree::Address,
+) -> Option<metro::OrphanStation> {
+    match &leaf.tile {
+        tiles::Tile::MetroStationTile(tile) if tile.ids.is_empty() => Some(metro::OrphanStation {
+            station: metro::Station {
+                name: tile.name.clone(),
+                address,
+            },
+            position: (tile.x as f64, tile.y as f64),
+        }),
+        _ => None,
+    }
+}
+
+impl Engine {
+    /**
+     * The station tiles that aren't served by any metro line, e.g. because map generation placed
+     * them before the lines were drawn.
+     */
+    pub fn orphan_stations(&self) -> Result<Vec<metro::OrphanStation>, Error> {
+        let mut visitor = CollectOrphanStationsVisitor {
+            orphans: Vec::new(),
+        };
+        self.state.qtree.visit(&mut visitor)?;
+        Ok(visitor.orphans)
+    }
+
+    /// Every station, sorted by name, with orphan stations marked as unserved.
+    pub fn station_index(&self) -> Result<Vec<metro::StationIndexEntry>, Error> {
+        Ok(self.state.metros.station_index(&self.orphan_stations()?))
+    }
+
+    /**
+     * The orphan stations that are within the given distance in meters of any of the railway
+     * segments, e.g. the segments of a path being drawn for a metro line. See
+     * metro::adoptable_stations.
+     */
+    pub fn adoptable_stations(
+        &self,
+        segments: &[network::SegmentHandle],
+        radius: f64,
+    ) -> Result<Vec<metro::AdoptableStation>, Error> {
+        let radius = radius / self.state.config.min_tile_size as f64;
+        Ok(metro::adoptable_stations(
+            segments,
+            &self.orphan_stations()?,
+            radius,
+            &self.state.railways,
+        ))
+    }
+
+    /**
+     * Make the orphan station at the given address a stop partway along a railway segment. The
+     * segment is split in two at the point closest to the station's exact location, with a new
+     * junction for the station in between, and the station is served by every metro line that
+     * uses the segment. Returns the junction for the station. See
+     * metro::Metros::split_segment_at_station.
+     */
+    pub fn adopt_orphan_station(
+        &mut self,
+        segment: network::SegmentHandle,
+        address: quadtree::Address,
+    ) -> Result<network::JunctionHandle, Error> {
+        let orphan = orphan_station(self.state.qtree.get_leaf(address)?, address)
+            .ok_or(Error::NotAnOrphanStation(address))?;
+        match self.state.railways.try_segment(segment) {
+            Some(data) if data.change_state.is_active() => (),
+            _ => return Err(metro::Error::InactiveSegment(segment).into()),
+        }
+
+        let mut base_graph = self.base_graph.write().unwrap();
+        let junction = self.state.metros.split_segment_at_station(
+            segment,
+            orphan.station,
+            orphan.position,
+            &mut self.state.railways,
+        )?;
+        base_graph.clear();
+
+        // both halves are used by the same metro lines, so either will do
+        let half = self.state.railways.junction(junction).incoming_segments()[0];
+        let ids: Vec<u64> = self
+            .state
+            .metros
+            .railway_segment_metro_lines(half)
+            .iter()
+            .map(|metro_line| metro_line.inner())
+            .collect();
+        if let tiles::Tile::MetroStationTile(tile) =
+            &mut self.state.qtree.get_leaf_mut(address)?.tile
+        {
+            tile.ids = ids;
+        }
+
+        Ok(junction)
+    }
+}
@@ -0,0 +1,385 @@
+use rand::Rng;
+
+use crate::engine::{Engine, Error};
+
+/**
+ * Distributions used to generate the data for agents created by Engine::populate_housing.
+ */
+#[derive(Debug, Clone)]
+pub struct AgentDataDistribution {
+    /// range of birth years, inclusive
+    pub birth_years: (i32, i32),
+    /// range of total years of schooling, inclusive
+    pub years_of_education: (u32, u32),
+    /// fraction of agents that own a car, in [0, 1]
+    pub car_ownership_rate: f64,
+    /// range of walking speed multipliers, inclusive
+    pub walking_speed: (f64, f64),
+    /// range of multipliers for the values of each agent's archetype, inclusive; see BehaviorNoise
+    pub behavior_noise: (f32, f32),
+}
+
+impl Default for AgentDataDistribution {
+    fn default() -> Self {
+        // TODO: generate ages and education levels from some data source
+        Self {
+            birth_years: (1950, 2000),
+            years_of_education: (10, 20),
+            car_ownership_rate: 1.0,
+            walking_speed: (0.8, 1.2),
+            behavior_noise: (1.0, 1.0),
+        }
+    }
+}
+
+impl AgentDataDistribution {
+    /// Check that all of the ranges are in order and all of the rates are in [0, 1].
+    pub fn validate(&self) -> Result<(), Error> {
+        fn check_range<T: PartialOrd + std::fmt::Debug>(
+            name: &'static str,
+            (min, max): (T, T),
+        ) -> Result<(), Error> {
+            // NOTE: written this way so that NaN is rejected too
+            if min <= max {
+                Ok(())
+            } else {
+                Err(Error::InvalidArgument {
+                    name,
+                    reason: format!("range is empty: {:?}..={:?}", min, max),
+                })
+            }
+        }
+
+        check_range("birth years", self.birth_years)?;
+        check_range("years of education", self.years_of_education)?;
+        check_rate("car ownership rate", self.car_ownership_rate)?;
+        check_range("walking speed", self.walking_speed)?;
+        check_range("behavior noise", self.behavior_noise)?;
+        if !(self.walking_speed.0.is_finite() && self.walking_speed.1.is_finite()) {
+            return Err(Error::InvalidArgument {
+                name: "walking speed",
+                reason: format!("must be finite, got {:?}", self.walking_speed),
+            });
+        }
+        if !(self.behavior_noise.0.is_finite() && self.behavior_noise.1.is_finite()) {
+            return Err(Error::InvalidArgument {
+                name: "behavior noise",
+                reason: format!("must be finite, got {:?}", self.behavior_noise),
+            });
+        }
+        Ok(())
+    }
+
+    /// Sample the data for one agent. Panics if the distribution isn't valid; see validate.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> agent::AgentData {
+        let year = rng.gen_range(self.birth_years.0..=self.birth_years.1);
+        // NOTE: skip the last day of leap years, it's not important
+        let ordinal = rng.gen_range(1..=365);
+        let years_of_education =
+            rng.gen_range(self.years_of_education.0..=self.years_of_education.1);
+        // NOTE: only sample if necessary so that everyone owning a car doesn't perturb the RNG
+        let owns_car =
+            self.car_ownership_rate >= 1.0 || rng.gen_bool(self.car_ownership_rate.max(0.0));
+        let walking_speed = rng.gen_range(self.walking_speed.0..=self.walking_speed.1);
+        // NOTE: likewise, agents without noise don't perturb the RNG
+        let (min_noise, max_noise) = self.behavior_noise;
+        let mut sample_noise = || {
+            if min_noise == max_noise {
+                min_noise
+            } else {
+                rng.gen_range(min_noise..=max_noise)
+            }
+        };
+        let noise = agent::BehaviorNoise {
+            stickiness: sample_noise(),
+            commute_length_tolerance: sample_noise(),
+        };
+        agent::AgentData {
+            birthday: chrono::NaiveDate::from_yo_opt(year, ordinal).unwrap(),
+            years_of_education,
+            owns_car,
+            walking_speed,
+            archetype: 0,
+            noise,
+        }
+    }
+
+    /**
+     * Like sample, but also give the agent one of the archetypes, picked in proportion to their
+     * shares. Agents of archetypes with a lower car ownership rate keep their car only at that
+     * rate.
+     */
+    pub fn sample_with_archetype<R: Rng>(
+        &self,
+        archetypes: &state::AgentArchetypes,
+        rng: &mut R,
+    ) -> agent::AgentData {
+        let mut data = self.sample(rng);
+        data.archetype = archetypes.pick(rng);
+        let car_ownership_rate = archetypes.get(data.archetype).car_ownership_rate;
+        if data.owns_car && car_ownership_rate < 1.0 {
+            data.owns_car = rng.gen_bool(car_ownership_rate.max(0.0));
+        }
+        data
+    }
+}
+
+impl Engine {
+    /**
+     * Create new agents in all housing tiles until each tile is occupied at the given rate. Tiles
+     * that are already occupied beyond the target rate are left alone. Rounding errors are carried
+     * between tiles so that the overall occupancy matches the target rate as closely as possible.
+     *
+     * New agents are unemployed; use assign_workplaces to give them jobs. Returns the number of
+     * agents that were added.
+     */
+    pub fn populate_housing(
+        &mut self,
+        occupancy_rate: f64,
+        rng_seed: u64,
+        distribution: &AgentDataDistribution,
+    ) -> Result<usize, Error> {
+        use rand::SeedableRng;
+
+        check_rate("occupancy rate", occupancy_rate)?;
+        distribution.validate()?;
+
+        // NOTE: use a separate RNG so that map generation is deterministic given the seed
+        let mut rng = rand_chacha::ChaCha12Rng::seed_from_u64(rng_seed);
+
+        self.state.update_collect_tiles()?;
+        let housing = self.state.collect_tiles.housing.clone();
+
+        let mut added = 0;
+        let mut remainder = 0.0;
+
+        for address in housing {
+            let (density, occupied) = match &self.state.qtree.get_leaf(address)?.tile {
+                tiles::Tile::HousingTile(tiles::HousingTile { density, agents }) => {
+                    (*density, agents.len())
+                }
+                _ => return Err(Error::NotAHousingTile(address)),
+            };
+
+            let target = density as f64 * occupancy_rate + remainder;
+            let count = target.floor();
+            remainder = target - count;
+
+            let to_add = (count as usize).min(density).saturating_sub(occupied);
+            for _ in 0..to_add {
+                let data =
+                    distribution.sample_with_archetype(&self.state.config.archetypes, &mut rng);
+                self.add_agent(data, address, None)?;
+            }
+            added += to_add;
+        }
+
+        self.state.update_collect_tiles()?;
+
+        Ok(added)
+    }
+
+    /**
+     * Give jobs to unemployed agents, assigning each agent to the nearest workplace with vacancies.
+     * match_rate is the fraction of unemployed agents that should be given jobs, and
+     * max_commute_distance is the maximum straight-line distance between housing and workplace, in
+     * meters. Agents without any vacant workplace in range stay unemployed, so fewer agents may
+     * be matched than requested. Like populate_housing, the order in which agents are matched only
+     * depends on the seed.
+     *
+     * Returns the number of agents that were given jobs.
+     */
+    pub fn assign_workplaces(
+        &mut self,
+        match_rate: f64,
+        rng_seed: u64,
+        max_commute_distance: f64,
+    ) -> Result<usize, Error> {
+        use rand::seq::SliceRandom;
+        use rand::SeedableRng;
+
+        check_rate("match rate", match_rate)?;
+        if max_commute_distance.is_nan() || max_commute_distance < 0.0 {
+            return Err(Error::InvalidArgument {
+                name: "max commute distance",
+                reason: format!("must not be negative, got {}", max_commute_distance),
+            });
+        }
+
+        // NOTE: use a separate RNG so that map generation is deterministic given the seed
+        let mut rng = rand_chacha::ChaCha12Rng::seed_from_u64(rng_seed);
+
+        self.state.update_collect_tiles()?;
+
+        let mut vacancies = Vec::new();
+        for address in &self.state.collect_tiles.vacant_workplaces {
+            match &self.state.qtree.get_leaf(*address)?.tile {
+                tiles::Tile::WorkplaceTile(tiles::WorkplaceTile {
+                    density, agents, ..
+                }) => {
+                    vacancies.push((*address, density - agents.len()));
+                }
+                _ => return Err(Error::NotAWorkplaceTile(*address)),
+            }
+        }
+        let mut vacancies = VacancyGrid::new(self.state.qtree.width(), vacancies);
+
+        // NOTE: agents are kept in a BTreeMap, so this starts out sorted by ID before shuffling
+        let mut unemployed: Vec<u64> = self
+            .agents
+            .values()
+            .filter(|agent| agent.workplace.is_none())
+            .map(|agent| agent.id)
+            .collect();
+        unemployed.shuffle(&mut rng);
+
+        let target = (unemployed.len() as f64 * match_rate).round() as usize;
+        let max_distance = max_commute_distance / self.state.config.min_tile_size as f64;
+
+        let mut assigned = 0;
+        for agent_id in unemployed {
+            if assigned >= target || vacancies.is_empty() {
+                break;
+            }
+
+            let housing = self.agents[&agent_id].housing.to_xy_f64();
+            if let Some(workplace) = vacancies.take_nearest(housing, max_distance) {
+                let industry = match &mut self.state.qtree.get_leaf_mut(workplace)?.tile {
+                    tiles::Tile::WorkplaceTile(tiles::WorkplaceTile {
+                        density,
+                        agents,
+                        industry,
+                    }) => {
+                        if agents.len() >= *density {
+                            return Err(Error::WorkplaceFull(workplace));
+                        }
+                        agents.push(agent_id);
+                        *industry
+                    }
+                    _ => return Err(Error::NotAWorkplaceTile(workplace)),
+                };
+                let current_time = self.time_state.current_time;
+                self.agents
+                    .get_mut(&agent_id)
+                    .unwrap()
+                    .take_job(workplace, industry, current_time);
+
+                assigned += 1;
+            }
+        }
+
+        self.state.update_collect_tiles()?;
+
+        Ok(assigned)
+    }
+}
+
+fn check_rate(name: &'static str, rate: f64) -> Result<(), Error> {
+    if (0.0..=1.0).contains(&rate) {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument {
+            name,
+            reason: format!("must be in [0, 1], got {}", rate),
+        })
+    }
+}
+
+/**
+ * Workplaces with vacancies, bucketed into a coarse grid of cells so that the nearest one to a
+ * point can be found by only looking at the cells around it.
+ */
+struct VacancyGrid {
+    /// the width of each cell, in tiles
+    cell_width: f64,
+    /// the number of cells along each side of the map
+    cells_per_side: usize,
+    /// the workplaces in each cell, with how many vacancies they have left, row by row
+    cells: Vec<Vec<(quadtree::Address, usize)>>,
+    /// the number of workplaces that still have vacancies
+    len: usize,
+}
+
+impl VacancyGrid {
+    fn new(map_width: u64, vacancies: Vec<(quadtree::Address, usize)>) -> Self {
+        // aim for about one workplace per cell
+        let cells_per_side =
+            ((vacancies.len() as f64).sqrt().ceil() as usize).clamp(1, map_width.max(1) as usize);
+        let cell_width = map_width.max(1) as f64 / cells_per_side as f64;
+        let mut grid = Self {
+            cell_width,
+            cells_per_side,
+            cells: vec![Vec::new(); cells_per_side * cells_per_side],
+            len: 0,
+        };
+        for (address, remaining) in vacancies {
+            if remaining > 0 {
+                let (cx, cy) = grid.cell(address.to_xy_f64());
+                grid.cells[cy * cells_per_side + cx].push((address, remaining));
+                grid.len += 1;
+            }
+        }
+        grid
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn cell(&self, (x, y): (f64, f64)) -> (usize, usize) {
+        let clamp = |v: f64| ((v / self.cell_width).max(0.0) as usize).min(self.cells_per_side - 1);
+        (clamp(x), clamp(y))
+    }
+
+    /**
+     * Use up one vacancy at the nearest workplace within max_distance (in tiles) of the point,
+     * searching rings of cells outwards until no closer workplace can be left.
+     */
+    fn take_nearest(&mut self, (x, y): (f64, f64), max_distance: f64) -> Option<quadtree::Address> {
+        let (cx, cy) = self.cell((x, y));
+        let side = self.cells_per_side as i64;
+        // (cell, index in the cell, distance)
+        let mut nearest: Option<(usize, usize, f64)> = None;
+
+        for ring in 0..side {
+            // cells outside of this ring are at least this far away from the point
+            let min_distance = (ring - 1).max(0) as f64 * self.cell_width;
+            if min_distance > max_distance
+                || nearest.map_or(false, |(_, _, distance)| distance <= min_distance)
+            {
+                break;
+            }
+            for ry in -ring..=ring {
+                for rx in -ring..=ring {
+                    if rx.abs() != ring && ry.abs() != ring {
+                        continue;
+                    }
+                    let (gx, gy) = (cx as i64 + rx, cy as i64 + ry);
+                    if gx < 0 || gy < 0 || gx >= side || gy >= side {
+                        continue;
+                    }
+                    let cell = (gy * side + gx) as usize;
+                    for (i, (address, _)) in self.cells[cell].iter().enumerate() {
+                        let (wx, wy) = address.to_xy_f64();
+                        let distance = (wx - x).hypot(wy - y);
+                        if distance <= max_distance
+                            && nearest.map_or(true, |(_, _, best)| distance < best)
+                        {
+                            nearest = Some((cell, i, distance));
+                        }
+                    }
+                }
+            }
+        }
+
+        let (cell, i, _) = nearest?;
+        let (address, remaining) = &mut self.cells[cell][i];
+        let address = *address;
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.cells[cell].swap_remove(i);
+            self.len -= 1;
+        }
+        Some(address)
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{Engine, Error, ErrorContext};
+
+/**
+ * The agents of a simulation, saved separately from the map so that the same map can be run with
+ * different populations. Agents are identified by their position in the file rather than by ID,
+ * since they get new IDs when they are imported. See Engine::export_population.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Population {
+    #[serde(default)]
+    pub households: Vec<PopulationHousehold>,
+    pub agents: Vec<PopulationAgent>,
+    /**
+     * How many agents live and work at each tile, for tools that want to inspect a population
+     * without going through every agent. This is implied by the agents, so it is ignored when
+     * importing.
+     */
+    #[serde(default)]
+    pub occupancy: Vec<TileOccupancy>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PopulationHousehold {
+    pub housing: quadtree::Address,
+    pub cars: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PopulationAgent {
+    pub data: agent::AgentData,
+    pub housing: quadtree::Address,
+    #[serde(default)]
+    pub workplace: Option<quadtree::Address>,
+    /// the index of the agent's household in Population::households, if any
+    #[serde(default)]
+    pub household: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TileOccupancy {
+    pub address: quadtree::Address,
+    pub residents: usize,
+    pub workers: usize,
+}
+
+/// What Engine::set_population does with agents that don't fit on the current map.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ImportPolicy {
+    /// Don't import anything, and leave the current agents alone.
+    #[default]
+    Abort,
+    /// Import everything else, and report the agents that were skipped.
+    Skip,
+}
+
+/// households or agents that can't be imported, by their index in the file
+type Skipped = Vec<(usize, Error)>;
+
+#[derive(Debug, Default)]
+pub struct PopulationImportReport {
+    /// the IDs of the imported agents, in the order they appear in the file
+    pub agents: Vec<u64>,
+    pub households: Vec<u64>,
+    /// the households that were skipped, by their index in the file
+    pub skipped_households: Vec<(usize, Error)>,
+    /// the agents that were skipped, by their index in the file
+    pub skipped: Vec<(usize, Error)>,
+}
+
+impl Population {
+    pub fn load(data: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(data)?)
+    }
+
+    pub fn load_file(path: &std::path::Path) -> Result<Self, Error> {
+        Self::load(&std::fs::read_to_string(path)?)
+            .with_context(|| format!("failed to load population from {}", path.display()))
+    }
+
+    /**
+     * The problems that would keep each household and each agent from being imported into the
+     * given engine once its current agents are gone, by index. Agents whose household can't be
+     * imported can't be either.
+     */
+    fn validate(&self, engine: &Engine) -> (Skipped, Skipped) {
+        let household_errors: Skipped = self
+            .households
+            .iter()
+            .enumerate()
+            .filter_map(|(index, household)| {
+                check_tile(engine, household.housing, false)
+                    .err()
+                    .map(|err| (index, err))
+            })
+            .collect();
+
+        let mut residents = HashMap::new();
+        let mut workers = HashMap::new();
+        let mut agent_errors = Vec::new();
+        for (index, agent) in self.agents.iter().enumerate() {
+            let result = (|| {
+                if let Some(household) = agent.household {
+                    if self.households.get(household).map(|h| h.housing) != Some(agent.housing)
+                        || household_errors.iter().any(|(i, _)| *i == household)
+                    {
+                        return Err(Error::InvalidHousehold(household as u64));
+                    }
+                }
+                let housing_density = check_tile(engine, agent.housing, false)?;
+                let workplace_density = match agent.workplace {
+                    Some(workplace) => Some((workplace, check_tile(engine, workplace, true)?)),
+                    None => None,
+                };
+
+                // nobody lives or works anywhere yet once the current agents are gone
+                if *residents.get(&agent.housing).unwrap_or(&0) >= housing_density {
+                    return Err(Error::HousingFull(agent.housing));
+                }
+                if let Some((workplace, density)) = workplace_density {
+                    if *workers.get(&workplace).unwrap_or(&0) >= density {
+                        return Err(Error::WorkplaceFull(workplace));
+                    }
+                    *workers.entry(workplace).or_insert(0) += 1;
+                }
+                *residents.entry(agent.housing).or_insert(0) += 1;
+                Ok(())
+            })();
+            if let Err(err) = result {
+                agent_errors.push((index, err));
+            }
+        }
+        (household_errors, agent_errors)
+    }
+
+    /**
+     * Like validate, but according to the policy: when aborting, the first problem is returned as
+     * an error instead.
+     */
+    pub(crate) fn check(
+        &self,
+        engine: &Engine,
+        policy: ImportPolicy,
+    ) -> Result<(Skipped, Skipped), Error> {
+        match (policy, self.validate(engine)) {
+            (ImportPolicy::Skip, skipped) => Ok(skipped),
+            (ImportPolicy::Abort, (skipped_households, skipped)) => {
+                if let Some((index, err)) = skipped_households.into_iter().next() {
+                    return Err(err).with_context(|| format!("cannot import household {}", index));
+                }
+                if let Some((index, err)) = skipped.into_iter().next() {
+                    return Err(err).with_context(|| format!("cannot import agent {}", index));
+                }
+                Ok((Vec::new(), Vec::new()))
+            }
+        }
+    }
+}
+
+/// The density of the tile at the given address, if it is the right kind.
+fn check_tile(
+    engine: &Engine,
+    address: quadtree::Address,
+    workplace: bool,
+) -> Result<usize, Error> {
+    if address.max_depth() != engine.state.config.max_depth {
+        return Err(quadtree::Error::MaxDepthExceeded(address.max_depth()).into());
+    }
+    match (&engine.state.qtree.get_leaf(address)?.tile, workplace) {
+        (tiles::Tile::HousingTile(tiles::HousingTile { density, .. }), false)
+        | (tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { density, .. }), true) => Ok(*density),
+        (_, false) => Err(Error::NotAHousingTile(address)),
+        (_, true) => Err(Error::NotAWorkplaceTile(address)),
+    }
+}
+
+impl Engine {
+    /// The current agents and households, in a form that can be imported into another engine.
+    pub fn population(&self) -> Population {
+        let mut household_indices = HashMap::new();
+        let households = self
+            .households
+            .iter()
+            .enumerate()
+            .map(|(index, (id, household))| {
+                household_indices.insert(*id, index);
+                PopulationHousehold {
+                    housing: household.housing,
+                    cars: household.cars(),
+                }
+            })
+            .collect();
+
+        let mut occupancy: Vec<TileOccupancy> = Vec::new();
+        let mut occupancy_indices = HashMap::new();
+        let mut occupy = |address: quadtree::Address, workplace: bool| {
+            let index = *occupancy_indices.entry(address).or_insert_with(|| {
+                occupancy.push(TileOccupancy {
+                    address,
+                    residents: 0,
+                    workers: 0,
+                });
+                occupancy.len() - 1
+            });
+            if workplace {
+                occupancy[index].workers += 1;
+            } else {
+                occupancy[index].residents += 1;
+            }
+        };
+
+        let agents = self
+            .agents
+            .values()
+            .map(|agent| {
+                occupy(agent.housing, false);
+                if let Some(workplace) = agent.workplace {
+                    occupy(workplace, true);
+                }
+                PopulationAgent {
+                    data: agent.data.clone(),
+                    housing: agent.housing,
+                    workplace: agent.workplace,
+                    household: agent
+                        .household
+                        .and_then(|household| household_indices.get(&household).copied()),
+                }
+            })
+            .collect();
+
+        Population {
+            households,
+            agents,
+            occupancy,
+        }
+    }
+
+    /// Write the current agents and households to a file; see population.
+    pub fn export_population(&self, path: &std::path::Path) -> Result<(), Error> {
+        serde_json::to_string(&self.population())
+            .map_err(Error::from)
+            .and_then(|data| Ok(std::fs::write(path, data)?))
+            .with_context(|| format!("failed to save population to {}", path.display()))
+    }
+
+    /**
+     * Replace the current agents and households with the given population. Agents that don't fit
+     * on this map, e.g. because their home is water here, are handled according to the policy;
+     * when aborting, the current agents are left alone. The current agents are removed with
+     * remove_agent, so that their routes and cars are cleaned up, and the new ones are added with
+     * add_agent, so that they take their spots in their tiles, park at home, and get their
+     * triggers scheduled once the simulation is running.
+     */
+    pub fn set_population(
+        &mut self,
+        population: &Population,
+        policy: ImportPolicy,
+    ) -> Result<PopulationImportReport, Error> {
+        let (skipped_households, skipped) = population.check(self, policy)?;
+        self.clear_population()?;
+        self.add_population(population, skipped_households, skipped)
+    }
+
+    /// Like set_population, but reads the population from a file written by export_population.
+    pub fn import_population(
+        &mut self,
+        path: &std::path::Path,
+        policy: ImportPolicy,
+    ) -> Result<PopulationImportReport, Error> {
+        self.set_population(&Population::load_file(path)?, policy)
+    }
+
+    /**
+     * Add the households and agents of the population alongside the current ones, leaving out the
+     * ones that check said to skip.
+     */
+    pub(crate) fn add_population(
+        &mut self,
+        population: &Population,
+        skipped_households: Skipped,
+        skipped: Skipped,
+    ) -> Result<PopulationImportReport, Error> {
+        let mut report = PopulationImportReport::default();
+        let mut households = Vec::new();
+        let mut skipped_household_indices = skipped_households.iter().map(|(index, _)| *index);
+        let mut next_skipped_household = skipped_household_indices.next();
+        for (index, household) in population.households.iter().enumerate() {
+            if next_skipped_household == Some(index) {
+                next_skipped_household = skipped_household_indices.next();
+                households.push(None);
+                continue;
+            }
+            let id = self.add_household(household.housing, household.cars)?;
+            report.households.push(id);
+            households.push(Some(id));
+        }
+
+        let mut skipped_indices = skipped.iter().map(|(index, _)| *index);
+        let mut next_skipped = skipped_indices.next();
+        for (index, agent) in population.agents.iter().enumerate() {
+            if next_skipped == Some(index) {
+                next_skipped = skipped_indices.next();
+                continue;
+            }
+            let id = match agent.household.and_then(|household| households[household]) {
+                Some(household) => {
+                    self.add_household_agent(household, agent.data.clone(), agent.workplace)
+                }
+                None => self.add_agent(agent.data.clone(), agent.housing, agent.workplace),
+            }
+            .with_context(|| format!("failed to import agent {}", index))?;
+            report.agents.push(id);
+        }
+
+        report.skipped_households = skipped_households;
+        report.skipped = skipped;
+        Ok(report)
+    }
+
+    /// Remove every agent and household, along with their cars.
+    fn clear_population(&mut self) -> Result<(), Error> {
+        let agents: Vec<u64> = self.agents.keys().copied().collect();
+        for agent in agents {
+            self.remove_agent(agent)?;
+        }
+        // by now, all of the household cars have been returned home
+        for household in std::mem::take(&mut self.households).into_values() {
+            for _ in 0..household.cars() {
+                self.world_state.decrement_parking(household.housing)?;
+            }
+        }
+        Ok(())
+    }
+}
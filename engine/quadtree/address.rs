@@ -2,7 +2,9 @@ use crate::quadrant::Quadrant;
 
 const MAX_ADDRESS_DEPTH: usize = 16;
 
-#[derive(Hash, PartialEq, Eq, Copy, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(
+    Hash, PartialEq, Eq, Copy, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
 pub struct Address {
     /// the contents of the address; everything indexed to the right of `depth` is garbage
     data: [Quadrant; MAX_ADDRESS_DEPTH],
@@ -89,6 +91,21 @@ impl Address {
         }
     }
 
+    /// The address of the parent node, or None for the root.
+    pub fn parent(&self) -> Option<Self> {
+        if self.depth == 0 {
+            return None;
+        }
+        let mut data = self.data;
+        // reset the dropped entry so that equal addresses have equal data
+        data[self.depth() - 1] = Quadrant::NW;
+        Some(Self {
+            data,
+            depth: self.depth - 1,
+            max_depth: self.max_depth,
+        })
+    }
+
     /**
      * Returns the (x, y) coordinates of the center of the tile
      * represented by this address.
@@ -117,6 +134,70 @@ impl Address {
         (x as f64, y as f64)
     }
 
+    /// The width of the tile represented by this address, in the smallest units.
+    pub fn width(&self) -> u64 {
+        2_u64.pow(self.max_depth() - self.depth() as u32)
+    }
+
+    /// The bounds covered by the tile represented by this address.
+    pub fn bounds(&self) -> crate::rect::Rect {
+        let width = self.width();
+        let (x, y) = self.to_xy();
+        crate::rect::Rect::xywh(x - width / 2, y - width / 2, width, width)
+    }
+
+    /// Whether this address is the given address or one of its descendants.
+    pub fn starts_with(&self, ancestor: &Address) -> bool {
+        self.max_depth == ancestor.max_depth
+            && self.depth >= ancestor.depth
+            && self.data[..ancestor.depth()] == ancestor.data[..ancestor.depth()]
+    }
+
+    /**
+     * The address of this node within the subtree rooted at the given ancestor, as if the
+     * ancestor were the root of a quadtree of its own; see Quadtree::extract. Returns None if this
+     * is not a descendant of the ancestor.
+     */
+    pub fn relative_to(&self, ancestor: &Address) -> Option<Address> {
+        if !self.starts_with(ancestor) {
+            return None;
+        }
+        Some(Self::from_vec(
+            self.data[ancestor.depth()..self.depth()].to_vec(),
+            self.max_depth - ancestor.depth,
+        ))
+    }
+
+    /**
+     * The inverse of relative_to: the address of this node once the quadtree it belongs to is
+     * grafted at the given root; see Quadtree::graft. Panics if the depths don't line up.
+     */
+    pub fn rebased(&self, root: &Address) -> Address {
+        assert_eq!(
+            self.max_depth + root.depth,
+            root.max_depth,
+            "address does not fit below the root"
+        );
+        let mut data = root.data;
+        data[root.depth()..root.depth() + self.depth()].copy_from_slice(&self.data[..self.depth()]);
+        Self {
+            data,
+            depth: root.depth + self.depth,
+            max_depth: root.max_depth,
+        }
+    }
+
+    /**
+     * Returns the exact center of the tile represented by this address. Unlike to_xy, this is not
+     * rounded down, so it is not at the corner of tiles of the smallest size.
+     */
+    pub fn to_center_f64(&self) -> (f64, f64) {
+        let (x, y) = self.to_xy();
+        // to_xy only rounds for tiles of odd width, i.e. of width 1
+        let offset = (self.width() % 2) as f64 / 2.0;
+        (x as f64 + offset, y as f64 + offset)
+    }
+
     pub fn from_xy(x: u64, y: u64, max_depth: u32) -> Self {
         Self::from_xy_depth(x, y, max_depth, max_depth)
     }
@@ -229,6 +310,17 @@ mod tests {
         assert_eq!(two_vec, vec![NE, SW]);
     }
 
+    #[test]
+    fn parent() {
+        let zero = Address::from_vec(vec![], 3);
+        assert_eq!(zero.parent(), None);
+        assert_eq!(zero.child(NE).parent(), Some(zero));
+        assert_eq!(
+            Address::from_vec(vec![SE, SW], 3).parent(),
+            Some(Address::from_vec(vec![SE], 3))
+        );
+    }
+
     #[test]
     fn to_xy() {
         assert_eq!(Address::from_vec(vec![NW, NW, NW], 3).to_xy(), (0, 0));
@@ -242,6 +334,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn to_center_f64() {
+        assert_eq!(
+            Address::from_vec(vec![NW, NW, NW], 3).to_center_f64(),
+            (0.5, 0.5)
+        );
+        assert_eq!(
+            Address::from_vec(vec![SE, SE, SE], 3).to_center_f64(),
+            (7.5, 7.5)
+        );
+        assert_eq!(
+            Address::from_vec(vec![SE, SE], 3).to_center_f64(),
+            (7.0, 7.0)
+        );
+        assert_eq!(Address::from_vec(vec![], 3).to_center_f64(), (4.0, 4.0));
+        assert_eq!(Address::from_vec(vec![SE, SE], 3).width(), 2);
+    }
+
     #[test]
     fn from_xy() {
         assert_eq!(
@@ -281,4 +391,38 @@ mod tests {
             Address::from_vec(vec![SE, NW], 3)
         );
     }
+
+    #[test]
+    fn relative_to() {
+        let root = Address::from_vec(vec![SE, NW], 4);
+        let address = Address::from_vec(vec![SE, NW, NE, SW], 4);
+        let relative = address.relative_to(&root).unwrap();
+        assert_eq!(relative, Address::from_vec(vec![NE, SW], 2));
+        assert_eq!(root.relative_to(&root), Some(Address::from_vec(vec![], 2)));
+        assert_eq!(Address::from_vec(vec![SW, NW], 4).relative_to(&root), None);
+        assert_eq!(Address::from_vec(vec![SE], 4).relative_to(&root), None);
+
+        assert_eq!(relative.rebased(&root), address);
+        let other = Address::from_vec(vec![NW, NE], 4);
+        assert_eq!(
+            relative.rebased(&other),
+            Address::from_vec(vec![NW, NE, NE, SW], 4)
+        );
+    }
+
+    #[test]
+    fn bounds() {
+        assert_eq!(
+            Address::from_vec(vec![SE, NW], 3).bounds(),
+            Rect::xywh(4, 4, 2, 2)
+        );
+        assert_eq!(
+            Address::from_vec(vec![], 3).bounds(),
+            Rect::xywh(0, 0, 8, 8)
+        );
+        assert_eq!(
+            Address::from_vec(vec![NE, SW, SE], 3).bounds(),
+            Rect::xywh(5, 3, 1, 1)
+        );
+    }
 }
@@ -1,6 +1,7 @@
 mod address;
 mod direction;
 mod neighbors;
+mod paged;
 mod quadrant;
 mod quadtree;
 mod rect;
@@ -8,6 +9,7 @@ mod rect;
 pub use crate::address::Address;
 pub use crate::direction::{Direction, DIRECTIONS};
 pub use crate::neighbors::{AllNeighborsVisitor, NeighborsStore, NeighborsVisitor};
+pub use crate::paged::{PagedQuadtree, PagingError};
 pub use crate::quadrant::{QuadMap, Quadrant, QUADRANTS};
 pub use crate::quadtree::{Error, Fold, MutFold, MutVisitor, Quadtree, VisitData, Visitor};
 pub use crate::rect::Rect;
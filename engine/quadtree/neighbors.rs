@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 
+use memory_size::MemorySize;
 use ordered_float::OrderedFloat;
 
 use crate::quadrant::{QuadMap, QUADRANTS};
@@ -24,6 +25,20 @@ pub struct NeighborsStore<T> {
     entries: Vec<Entry<T>>,
 }
 
+// NOTE: entries only ever hold indices, so their data is counted as part of the entry itself
+impl<T> MemorySize for Entry<T> {
+    fn heap_bytes(&self) -> usize {
+        0
+    }
+}
+
+/// Entries are stored both in the leaves of the quadtree and in a flat list.
+impl<T> MemorySize for NeighborsStore<T> {
+    fn heap_bytes(&self) -> usize {
+        self.qtree.heap_bytes() + memory_size::vec_bytes(&self.entries)
+    }
+}
+
 pub trait NeighborsVisitor<T, E> {
     fn visit(&mut self, entry: &T, x: f64, y: f64, distance: f64) -> Result<(), E>;
 }
@@ -148,7 +163,8 @@ where
                 break visitor.nearest.into_values().take(k).collect();
             }
 
-            if radius >= self.qtree.width() {
+            // NOTE: the farthest point can be a diagonal away, which is farther than the width
+            if radius >= self.qtree.width() * 2 {
                 break visitor.nearest.into_values().collect();
             }
             radius *= 2;
@@ -388,10 +404,21 @@ mod tests {
         neighbors.insert(1, 3.0, 0.0)?;
         neighbors.insert(2, 0.0, 2.0)?;
 
-        assert_eq!(neighbors.find_nearest_k(0.0, 0.0, 0), vec![]);
+        assert_eq!(neighbors.find_nearest_k(0.0, 0.0, 0), Vec::<i32>::new());
         assert_eq!(neighbors.find_nearest_k(0.0, 0.0, 3), vec![0, 2, 1]);
         assert_eq!(neighbors.find_nearest_k(0.0, 0.0, 2), vec![0, 2]);
 
         Ok(())
     }
+
+    #[test]
+    fn nearest_opposite_corner() -> Result<(), quadtree::Error> {
+        let mut neighbors = NeighborsStore::new(1, 4);
+        neighbors.insert(0, 0.0, 0.0)?;
+
+        // farther away than the width of the quadtree
+        assert_eq!(neighbors.find_nearest(15.0, 15.0), Some(0));
+
+        Ok(())
+    }
 }
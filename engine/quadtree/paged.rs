@@ -0,0 +1,842 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::address::Address;
+use crate::quadrant::{QuadMap, Quadrant};
+use crate::quadtree::{Error, MutVisitor, Node, Quadtree, VisitData, Visitor};
+use crate::rect::Rect;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PagingError {
+    #[error("Quadtree error: {0}")]
+    QuadtreeError(#[from] Error),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Bincode error: {0}")]
+    BincodeError(#[from] bincode::Error),
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct PageEntry {
+    /// the address of the root of the subtree stored in this page
+    address: Address,
+    /// where the compressed page starts, relative to the end of the header
+    offset: u64,
+    len: u64,
+}
+
+/// Everything in a paged file except for the pages themselves.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Header<B, L> {
+    page_depth: u32,
+    /// the part of the quadtree above the page depth, with unloaded nodes in place of the pages
+    top: Quadtree<B, L>,
+    pages: Vec<PageEntry>,
+}
+
+enum PageData {
+    /// the page is unchanged, so the compressed data can be copied over from the source
+    Existing(usize),
+    Fresh(Vec<u8>),
+}
+
+fn compress<T: Serialize>(value: &T) -> Result<Vec<u8>, PagingError> {
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+    bincode::serialize_into(&mut encoder, value)?;
+    Ok(encoder.finish()?)
+}
+
+fn decompress<T: DeserializeOwned>(data: &[u8]) -> Result<T, PagingError> {
+    let decoder = flate2::read::DeflateDecoder::new(data);
+    Ok(bincode::deserialize_from(decoder)?)
+}
+
+/**
+ * Copy the part of the tree above the page depth, cutting out every subtree at the page depth as a
+ * separate page. Subtrees that `reuse` returns a page for, and subtrees that are already unloaded,
+ * are not serialized again.
+ */
+fn cut_pages<B, L, F>(
+    node: &Node<B, L>,
+    address: Address,
+    page_depth: usize,
+    reuse: &F,
+    pages: &mut Vec<(Address, PageData)>,
+) -> Result<Node<B, L>, PagingError>
+where
+    B: Clone + Serialize,
+    L: Clone + Serialize,
+    F: Fn(&Address) -> Option<usize>,
+{
+    if address.depth() == page_depth {
+        let data = match node {
+            Node::Unloaded { page, .. } => PageData::Existing(*page),
+            _ => match reuse(&address) {
+                Some(page) => PageData::Existing(page),
+                None => PageData::Fresh(compress(node)?),
+            },
+        };
+        pages.push((address, data));
+        return Ok(Node::Unloaded {
+            page: pages.len() - 1,
+            depth: page_depth,
+        });
+    }
+
+    Ok(match node {
+        Node::Branch {
+            data,
+            children,
+            depth,
+            child_count,
+            child_depth,
+        } => {
+            let mut cut = |quadrant| -> Result<_, PagingError> {
+                Ok(Box::new(cut_pages(
+                    &children[quadrant],
+                    address.child(quadrant),
+                    page_depth,
+                    reuse,
+                    pages,
+                )?))
+            };
+            Node::Branch {
+                data: data.clone(),
+                children: QuadMap::new(
+                    cut(Quadrant::NW)?,
+                    cut(Quadrant::NE)?,
+                    cut(Quadrant::SW)?,
+                    cut(Quadrant::SE)?,
+                ),
+                depth: *depth,
+                child_count: *child_count,
+                child_depth: *child_depth,
+            }
+        }
+        Node::Leaf { data, depth } => Node::Leaf {
+            data: data.clone(),
+            depth: *depth,
+        },
+        Node::Unloaded { .. } => panic!("pages must be at the page depth"),
+    })
+}
+
+fn truncate(address: &Address, depth: usize) -> Address {
+    Address::from_vec(
+        (0..depth).map(|index| address.at(index)).collect(),
+        address.max_depth(),
+    )
+}
+
+impl<B, L> Quadtree<B, L>
+where
+    B: Clone + Serialize,
+    L: Clone + Serialize,
+{
+    /**
+     * Write this quadtree in the format read by PagedQuadtree, with a page for each subtree at
+     * `page_depth`. Parts of the tree that don't reach the page depth are stored with the rest of
+     * the top of the tree.
+     */
+    pub fn write_paged<W: Write>(&self, page_depth: u32, writer: W) -> Result<(), PagingError> {
+        assert!(
+            page_depth <= self.max_depth(),
+            "page depth exceeds max depth"
+        );
+        let root = Address::from_vec(vec![], self.max_depth());
+        let mut pages = Vec::new();
+        let top = cut_pages(
+            self.get(&root)?,
+            root,
+            page_depth as usize,
+            &|_| None,
+            &mut pages,
+        )?;
+        // every page is fresh, so there is nothing to copy over
+        write_pages(
+            top,
+            self.max_depth(),
+            page_depth,
+            &pages,
+            writer,
+            |_, _| unreachable!(),
+            |_| unreachable!(),
+        )
+    }
+}
+
+/**
+ * A quadtree for maps that are too big to keep in memory all at once. Each subtree at the page
+ * depth is stored as a separately compressed page, and is only loaded once something accesses it.
+ * Clean pages are unloaded again when more than `max_loaded_pages` are loaded, least recently used
+ * first. Pages that have been modified are pinned in memory until they are saved.
+ *
+ * Accessors take &mut self because they may need to load pages. Everything above the page depth is
+ * always in memory.
+ */
+pub struct PagedQuadtree<B, L, R> {
+    qtree: Quadtree<B, L>,
+    page_depth: u32,
+    max_loaded_pages: usize,
+    source: R,
+    /// where the pages start in the source
+    base: u64,
+    pages: Vec<PageEntry>,
+    page_ids: HashMap<Address, usize>,
+    /// compressed pages that were saved after opening the source, which take precedence over it
+    saved: HashMap<usize, Vec<u8>>,
+    /// loaded pages, from least to most recently used
+    loaded: VecDeque<usize>,
+    dirty: HashSet<usize>,
+}
+
+impl<B, L, R> PagedQuadtree<B, L, R>
+where
+    B: Clone + Serialize + DeserializeOwned,
+    L: Clone + Serialize + DeserializeOwned,
+    R: Read + Seek,
+{
+    /**
+     * Open a quadtree written by Quadtree::write_paged or save. Nothing below the page depth is
+     * loaded until it is accessed.
+     */
+    pub fn open(mut source: R, max_loaded_pages: usize) -> Result<Self, PagingError> {
+        let mut len = [0; 8];
+        source.seek(SeekFrom::Start(0))?;
+        source.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        let mut header = vec![0; len as usize];
+        source.read_exact(&mut header)?;
+        let header: Header<B, L> = decompress(&header)?;
+
+        let page_ids = header
+            .pages
+            .iter()
+            .enumerate()
+            .map(|(page, entry)| (entry.address, page))
+            .collect();
+
+        Ok(Self {
+            qtree: header.top,
+            page_depth: header.page_depth,
+            max_loaded_pages,
+            source,
+            base: 8 + len,
+            pages: header.pages,
+            page_ids,
+            saved: HashMap::new(),
+            loaded: VecDeque::new(),
+            dirty: HashSet::new(),
+        })
+    }
+
+    /**
+     * Write everything, including the pages that aren't loaded, to a new paged file. Modified
+     * pages are no longer pinned afterwards.
+     */
+    pub fn save<W: Write>(&mut self, writer: W) -> Result<(), PagingError> {
+        let max_depth = self.qtree.max_depth();
+        let root = Address::from_vec(vec![], max_depth);
+        let mut pages = Vec::new();
+        let top = cut_pages(
+            self.qtree.get(&root)?,
+            root,
+            self.page_depth as usize,
+            &|address| {
+                let page = *self.page_ids.get(address)?;
+                (!self.dirty.contains(&page)).then_some(page)
+            },
+            &mut pages,
+        )?;
+
+        let (source, base, entries, saved) =
+            (&mut self.source, self.base, &self.pages, &self.saved);
+        write_pages(
+            top,
+            max_depth,
+            self.page_depth,
+            &pages,
+            writer,
+            |page, buf| read_page(source, base, entries, saved, page, buf),
+            |page| match saved.get(&page) {
+                Some(data) => data.len() as u64,
+                None => entries[page].len,
+            },
+        )?;
+
+        // keep the saved copies of modified pages so that they can be unloaded
+        for (address, data) in pages {
+            let data = match data {
+                PageData::Fresh(data) => data,
+                PageData::Existing(_) => continue,
+            };
+            let page = match self.page_ids.get(&address) {
+                Some(page) => *page,
+                None => {
+                    // a new page, split from a leaf above the page depth
+                    self.pages.push(PageEntry {
+                        address,
+                        offset: 0,
+                        len: 0,
+                    });
+                    let page = self.pages.len() - 1;
+                    self.page_ids.insert(address, page);
+                    self.loaded.push_back(page);
+                    page
+                }
+            };
+            self.saved.insert(page, data);
+        }
+        self.dirty.clear();
+        self.unload_excess(&[]);
+
+        Ok(())
+    }
+
+    pub fn max_depth(&self) -> u32 {
+        self.qtree.max_depth()
+    }
+
+    pub fn width(&self) -> u64 {
+        self.qtree.width()
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn loaded_pages(&self) -> usize {
+        self.loaded.len()
+    }
+
+    pub fn is_loaded(&self, address: &Address) -> bool {
+        match self.page_for_address(address) {
+            Some(page) => self.loaded.contains(&page),
+            None => true,
+        }
+    }
+
+    pub fn is_dirty(&self, address: &Address) -> bool {
+        match self.page_for_address(address) {
+            Some(page) => self.dirty.contains(&page),
+            None => false,
+        }
+    }
+
+    fn page_for_address(&self, address: &Address) -> Option<usize> {
+        if address.depth() < self.page_depth as usize {
+            return None;
+        }
+        self.page_ids
+            .get(&truncate(address, self.page_depth as usize))
+            .copied()
+    }
+
+    fn page_for_xy(&self, x: u64, y: u64) -> Option<usize> {
+        if x >= self.width() || y >= self.width() {
+            return None;
+        }
+        let address = Address::from_xy_depth(x, y, self.page_depth, self.max_depth());
+        self.page_ids.get(&address).copied()
+    }
+
+    fn pages_in_bounds(&self, bounds: &Rect) -> Vec<usize> {
+        self.pages
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.address.bounds().intersects(bounds))
+            .map(|(page, _)| page)
+            .collect()
+    }
+
+    fn load(&mut self, page: usize) -> Result<(), PagingError> {
+        let mut buf = Vec::new();
+        read_page(
+            &mut self.source,
+            self.base,
+            &self.pages,
+            &self.saved,
+            page,
+            &mut buf,
+        )?;
+        let node: Node<B, L> = decompress(&buf)?;
+        let address = self.pages[page].address;
+        let existing = self.qtree.get_mut(&address)?;
+        assert!(matches!(existing, Node::Unloaded { page: p, .. } if *p == page));
+        *existing = node;
+        self.loaded.push_back(page);
+        Ok(())
+    }
+
+    fn unload(&mut self, page: usize) {
+        let address = self.pages[page].address;
+        let node = self
+            .qtree
+            .get_mut(&address)
+            .expect("loaded page is missing");
+        *node = Node::Unloaded {
+            page,
+            depth: address.depth(),
+        };
+    }
+
+    /// Unload clean pages, least recently used first, until few enough pages are loaded.
+    fn unload_excess(&mut self, keep: &[usize]) {
+        let mut index = 0;
+        while self.loaded.len() > self.max_loaded_pages && index < self.loaded.len() {
+            let page = self.loaded[index];
+            if self.dirty.contains(&page) || keep.contains(&page) {
+                index += 1;
+            } else {
+                self.loaded.remove(index);
+                self.unload(page);
+            }
+        }
+    }
+
+    /// Make sure that the pages are loaded, marking them as the most recently used.
+    fn ensure_loaded(&mut self, pages: &[usize]) -> Result<(), PagingError> {
+        for page in pages {
+            match self.loaded.iter().position(|loaded| loaded == page) {
+                Some(index) => {
+                    self.loaded.remove(index);
+                    self.loaded.push_back(*page);
+                }
+                None => self.load(*page)?,
+            }
+        }
+        self.unload_excess(pages);
+        Ok(())
+    }
+
+    fn ensure_address_loaded(&mut self, address: &Address, dirty: bool) -> Result<(), PagingError> {
+        if let Some(page) = self.page_for_address(address) {
+            self.ensure_loaded(&[page])?;
+            if dirty {
+                self.dirty.insert(page);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_branch<A: Into<Address>>(&mut self, address: A) -> Result<&B, PagingError> {
+        let address = address.into();
+        self.ensure_address_loaded(&address, false)?;
+        Ok(self.qtree.get_branch(address)?)
+    }
+
+    pub fn get_branch_mut<A: Into<Address>>(&mut self, address: A) -> Result<&mut B, PagingError> {
+        let address = address.into();
+        self.ensure_address_loaded(&address, true)?;
+        Ok(self.qtree.get_branch_mut(address)?)
+    }
+
+    pub fn get_leaf<A: Into<Address>>(&mut self, address: A) -> Result<&L, PagingError> {
+        let address = address.into();
+        self.ensure_address_loaded(&address, false)?;
+        Ok(self.qtree.get_leaf(address)?)
+    }
+
+    pub fn get_leaf_mut<A: Into<Address>>(&mut self, address: A) -> Result<&mut L, PagingError> {
+        let address = address.into();
+        self.ensure_address_loaded(&address, true)?;
+        Ok(self.qtree.get_leaf_mut(address)?)
+    }
+
+    pub fn split<A: Into<Address>>(
+        &mut self,
+        address: A,
+        data: B,
+        child_data: QuadMap<L>,
+    ) -> Result<(), PagingError> {
+        let address = address.into();
+        self.ensure_address_loaded(&address, true)?;
+        Ok(self.qtree.split(address, data, child_data)?)
+    }
+
+    pub fn get_visit_data(&mut self, x: u64, y: u64) -> Result<VisitData, PagingError> {
+        if let Some(page) = self.page_for_xy(x, y) {
+            self.ensure_loaded(&[page])?;
+        }
+        Ok(self.qtree.get_visit_data(x, y)?)
+    }
+
+    pub fn get_address(&mut self, x: u64, y: u64) -> Result<Address, PagingError> {
+        Ok(self.get_visit_data(x, y)?.address)
+    }
+
+    /**
+     * Visit everything in the bounds, loading every page in the bounds first.
+     *
+     * NOTE: all of the pages in the bounds stay loaded during the visit, even if that is more than
+     * max_loaded_pages.
+     */
+    pub fn visit_rect<V, E>(&mut self, visitor: &mut V, bounds: &Rect) -> Result<(), E>
+    where
+        V: Visitor<B, L, E>,
+        E: From<PagingError>,
+    {
+        self.ensure_loaded(&self.pages_in_bounds(bounds))?;
+        self.qtree.visit_rect(visitor, bounds)
+    }
+
+    /// Like visit_rect, but every page in the bounds is treated as modified.
+    pub fn visit_rect_mut<V, E>(&mut self, visitor: &mut V, bounds: &Rect) -> Result<(), E>
+    where
+        V: MutVisitor<B, L, E>,
+        E: From<PagingError>,
+    {
+        let pages = self.pages_in_bounds(bounds);
+        self.ensure_loaded(&pages)?;
+        self.dirty.extend(pages);
+        self.qtree.visit_rect_mut(visitor, bounds)
+    }
+}
+
+fn read_page<R: Read + Seek>(
+    source: &mut R,
+    base: u64,
+    entries: &[PageEntry],
+    saved: &HashMap<usize, Vec<u8>>,
+    page: usize,
+    buf: &mut Vec<u8>,
+) -> Result<(), PagingError> {
+    match saved.get(&page) {
+        Some(data) => buf.extend_from_slice(data),
+        None => {
+            let entry = &entries[page];
+            source.seek(SeekFrom::Start(base + entry.offset))?;
+            let start = buf.len();
+            buf.resize(start + entry.len as usize, 0);
+            source.read_exact(&mut buf[start..])?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Layout: the length of the compressed header as a little-endian u64, the compressed header, and
+ * then each compressed page, one after another.
+ */
+fn write_pages<B, L, W, F, G>(
+    top: Node<B, L>,
+    max_depth: u32,
+    page_depth: u32,
+    pages: &[(Address, PageData)],
+    mut writer: W,
+    mut read_existing: F,
+    existing_len: G,
+) -> Result<(), PagingError>
+where
+    B: Serialize,
+    L: Serialize,
+    W: Write,
+    F: FnMut(usize, &mut Vec<u8>) -> Result<(), PagingError>,
+    G: Fn(usize) -> u64,
+{
+    let mut entries = Vec::with_capacity(pages.len());
+    let mut offset = 0;
+    for (address, data) in pages {
+        let len = match data {
+            PageData::Existing(page) => existing_len(*page),
+            PageData::Fresh(data) => data.len() as u64,
+        };
+        entries.push(PageEntry {
+            address: *address,
+            offset,
+            len,
+        });
+        offset += len;
+    }
+
+    let header = compress(&Header {
+        page_depth,
+        top: Quadtree::from_root(top, max_depth),
+        pages: entries,
+    })?;
+    writer.write_all(&(header.len() as u64).to_le_bytes())?;
+    writer.write_all(&header)?;
+
+    let mut buf = Vec::new();
+    for (_, data) in pages {
+        match data {
+            PageData::Existing(page) => {
+                buf.clear();
+                read_existing(*page, &mut buf)?;
+                writer.write_all(&buf)?;
+            }
+            PageData::Fresh(data) => writer.write_all(data)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::paged::*;
+    use crate::quadrant::QUADRANTS;
+
+    const MAX_DEPTH: u32 = 14;
+    /// the depth that the whole map is split to
+    const SPLIT_DEPTH: u32 = 6;
+    /// 64 pages, with 64 leaves each
+    const PAGE_DEPTH: u32 = 3;
+
+    type Paged = PagedQuadtree<u32, u64, Cursor<Vec<u8>>>;
+
+    fn split_all(qtree: &mut Quadtree<u32, u64>, address: Address, counter: &mut u64) {
+        if address.depth() == SPLIT_DEPTH as usize {
+            return;
+        }
+        let mut next = || {
+            *counter += 1;
+            *counter
+        };
+        qtree
+            .split(
+                address,
+                address.depth() as u32,
+                QuadMap::new(next(), next(), next(), next()),
+            )
+            .unwrap();
+        for quadrant in QUADRANTS {
+            split_all(qtree, address.child(quadrant), counter);
+        }
+    }
+
+    /// A map that is split evenly, except for one dense corner that goes all the way to max depth.
+    fn generate_map() -> Quadtree<u32, u64> {
+        let mut qtree = Quadtree::new(0, MAX_DEPTH);
+        let mut counter = 0;
+        split_all(
+            &mut qtree,
+            Address::from_vec(vec![], MAX_DEPTH),
+            &mut counter,
+        );
+
+        let mut address = Address::from_xy_depth(0, 0, SPLIT_DEPTH, MAX_DEPTH);
+        while address.depth() < MAX_DEPTH as usize {
+            counter += 4;
+            qtree
+                .split(
+                    address,
+                    address.depth() as u32,
+                    QuadMap::new(counter - 3, counter - 2, counter - 1, counter),
+                )
+                .unwrap();
+            address = address.child(Quadrant::NW);
+        }
+        qtree
+    }
+
+    fn open(qtree: &Quadtree<u32, u64>, max_loaded_pages: usize) -> Paged {
+        let mut data = Vec::new();
+        qtree.write_paged(PAGE_DEPTH, &mut data).unwrap();
+        PagedQuadtree::open(Cursor::new(data), max_loaded_pages).unwrap()
+    }
+
+    /// Any address in the page containing the given coordinates.
+    fn address_in_page(qtree: &Quadtree<u32, u64>, page: (u64, u64)) -> Address {
+        let page_width = 2_u64.pow(MAX_DEPTH - PAGE_DEPTH);
+        qtree
+            .get_address(page.0 * page_width, page.1 * page_width)
+            .unwrap()
+    }
+
+    #[derive(Default)]
+    struct LeafVisitor {
+        leaves: Vec<(u64, VisitData)>,
+        unloaded: usize,
+    }
+
+    impl Visitor<u32, u64, PagingError> for LeafVisitor {
+        fn visit_branch_pre(&mut self, _: &u32, _: &VisitData) -> Result<bool, PagingError> {
+            Ok(true)
+        }
+
+        fn visit_leaf(&mut self, leaf: &u64, data: &VisitData) -> Result<(), PagingError> {
+            self.leaves.push((*leaf, data.clone()));
+            Ok(())
+        }
+
+        fn visit_branch_post(&mut self, _: &u32, _: &VisitData) -> Result<(), PagingError> {
+            Ok(())
+        }
+
+        fn visit_unloaded(&mut self, _: &VisitData) -> Result<(), PagingError> {
+            self.unloaded += 1;
+            Ok(())
+        }
+    }
+
+    fn visit_rect_leaves(leaves: Vec<(u64, VisitData)>) -> Vec<u64> {
+        let mut leaves: Vec<u64> = leaves.into_iter().map(|(leaf, _)| leaf).collect();
+        leaves.sort_unstable();
+        leaves
+    }
+
+    /// The number of leaves that are currently in memory.
+    fn resident_leaves(paged: &Paged) -> usize {
+        let mut visitor = LeafVisitor::default();
+        paged.qtree.visit(&mut visitor).unwrap();
+        visitor.leaves.len()
+    }
+
+    #[test]
+    fn load_on_access() {
+        let qtree = generate_map();
+        let mut paged = open(&qtree, 4);
+        assert_eq!(paged.page_count(), 64);
+        assert_eq!(paged.loaded_pages(), 0);
+        assert_eq!(resident_leaves(&paged), 0);
+
+        let address = address_in_page(&qtree, (5, 2));
+        assert!(!paged.is_loaded(&address));
+        assert_eq!(
+            paged.get_leaf(address).unwrap(),
+            qtree.get_leaf(address).unwrap()
+        );
+        assert!(paged.is_loaded(&address));
+        assert_eq!(paged.loaded_pages(), 1);
+
+        // looking up coordinates loads the page too
+        let (x, y) = address_in_page(&qtree, (7, 7)).to_xy();
+        assert_eq!(
+            paged.get_visit_data(x, y).unwrap(),
+            qtree.get_visit_data(x, y).unwrap()
+        );
+        assert_eq!(paged.loaded_pages(), 2);
+
+        // branches above the page depth are always loaded
+        let root = Address::from_vec(vec![], MAX_DEPTH);
+        assert_eq!(paged.get_branch(root).unwrap(), &0);
+        assert_eq!(paged.loaded_pages(), 2);
+    }
+
+    #[test]
+    fn eviction() {
+        let qtree = generate_map();
+        let mut paged = open(&qtree, 2);
+        let addresses: Vec<Address> = (0..4)
+            .map(|page| address_in_page(&qtree, (page, 0)))
+            .collect();
+
+        paged.get_leaf(addresses[0]).unwrap();
+        paged.get_leaf(addresses[1]).unwrap();
+        paged.get_leaf(addresses[2]).unwrap();
+        assert_eq!(paged.loaded_pages(), 2);
+        assert!(!paged.is_loaded(&addresses[0]));
+
+        // the least recently used page is unloaded first
+        paged.get_leaf(addresses[1]).unwrap();
+        paged.get_leaf(addresses[3]).unwrap();
+        assert!(paged.is_loaded(&addresses[1]));
+        assert!(!paged.is_loaded(&addresses[2]));
+        assert!(paged.is_loaded(&addresses[3]));
+
+        // unloaded pages can be loaded again
+        assert_eq!(
+            paged.get_leaf(addresses[0]).unwrap(),
+            qtree.get_leaf(addresses[0]).unwrap()
+        );
+    }
+
+    #[test]
+    fn dirty_pages_are_pinned() {
+        let qtree = generate_map();
+        let mut paged = open(&qtree, 1);
+        let modified = address_in_page(&qtree, (1, 1));
+        *paged.get_leaf_mut(modified).unwrap() = 1000;
+        assert!(paged.is_dirty(&modified));
+
+        for page in 2..5 {
+            paged.get_leaf(address_in_page(&qtree, (page, 1))).unwrap();
+            assert!(paged.is_loaded(&modified));
+        }
+        // the modified page is allowed to go over the limit
+        assert_eq!(paged.loaded_pages(), 2);
+
+        let mut saved = Vec::new();
+        paged.save(&mut saved).unwrap();
+        assert!(!paged.is_dirty(&modified));
+        assert_eq!(paged.loaded_pages(), 1);
+        assert!(!paged.is_loaded(&modified));
+
+        // the modification survives being unloaded, in both the original and the saved copy
+        assert_eq!(paged.get_leaf(modified).unwrap(), &1000);
+        let mut reopened = Paged::open(Cursor::new(saved), 1).unwrap();
+        assert_eq!(reopened.get_leaf(modified).unwrap(), &1000);
+        let other = address_in_page(&qtree, (3, 1));
+        assert_eq!(
+            reopened.get_leaf(other).unwrap(),
+            qtree.get_leaf(other).unwrap()
+        );
+    }
+
+    #[test]
+    fn split_pins_page() {
+        let qtree = generate_map();
+        let mut paged = open(&qtree, 1);
+        let address = address_in_page(&qtree, (6, 6));
+        paged
+            .split(address, 0, QuadMap::new(1001, 1002, 1003, 1004))
+            .unwrap();
+        paged.get_leaf(address_in_page(&qtree, (0, 6))).unwrap();
+        assert!(paged.is_loaded(&address));
+        assert_eq!(paged.get_leaf(address.child(Quadrant::SE)).unwrap(), &1004);
+    }
+
+    #[test]
+    fn visit_rect_matches_eager() {
+        let qtree = generate_map();
+        let max_loaded_pages = 4;
+        let mut paged = open(&qtree, max_loaded_pages);
+
+        let width = qtree.width();
+        let mut seed: u64 = 12345;
+        let mut random = |max: u64| {
+            // xorshift, to avoid depending on rand
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % max
+        };
+
+        for _ in 0..50 {
+            // viewports up to the size of a page, so that they overlap at most four pages
+            let size = random(width / 8) + 1;
+            let bounds = Rect::xywh(random(width - size), random(width - size), size, size);
+
+            let mut eager = LeafVisitor::default();
+            qtree.visit_rect(&mut eager, &bounds).unwrap();
+            let mut lazy = LeafVisitor::default();
+            paged.visit_rect(&mut lazy, &bounds).unwrap();
+
+            assert_eq!(lazy.unloaded, 0);
+            assert!(!eager.leaves.is_empty());
+            assert_eq!(
+                visit_rect_leaves(eager.leaves),
+                visit_rect_leaves(lazy.leaves)
+            );
+        }
+
+        // the dense corner is included
+        let mut eager = LeafVisitor::default();
+        let bounds = Rect::xywh(0, 0, 4, 4);
+        qtree.visit_rect(&mut eager, &bounds).unwrap();
+        let mut lazy = LeafVisitor::default();
+        paged.visit_rect(&mut lazy, &bounds).unwrap();
+        assert_eq!(eager.leaves, lazy.leaves);
+        assert!(eager.leaves.iter().any(|(_, data)| data.depth == MAX_DEPTH));
+
+        assert!(paged.loaded_pages() <= max_loaded_pages);
+        let mut all = LeafVisitor::default();
+        qtree.visit(&mut all).unwrap();
+        assert!(resident_leaves(&paged) * 10 <= all.leaves.len());
+    }
+}
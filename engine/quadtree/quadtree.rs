@@ -3,6 +3,8 @@ use crate::direction::Direction;
 use crate::quadrant::{QuadMap, Quadrant, QUADRANTS};
 use crate::rect::Rect;
 
+use memory_size::MemorySize;
+
 #[derive(thiserror::Error, Debug, PartialEq)]
 pub enum Error {
     #[error("Expected branch, but got leaf")]
@@ -15,6 +17,10 @@ pub enum Error {
     CoordsOutOfBoundsU64(u64, u64),
     #[error("Coordinates out of bounds: {0}, {1}")]
     CoordsOutOfBoundsF64(f64, f64),
+    #[error("Subtree is not loaded: page {0}")]
+    NotLoaded(usize),
+    #[error("Subtree has max depth {actual}, but only {expected} fits at the address")]
+    IncompatibleDepth { expected: u32, actual: u32 },
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -27,11 +33,12 @@ pub struct VisitData {
 }
 
 impl VisitData {
+    /**
+     * Whether this node overlaps the given bounds. Both the node and the bounds are half-open (see
+     * Rect), so a node that only touches the edge of the bounds is not considered in bounds.
+     */
     pub fn in_bounds(&self, bounds: &Rect) -> bool {
-        self.x < bounds.max_x
-            && self.x + self.width > bounds.min_x
-            && self.y < bounds.max_y
-            && self.y + self.width > bounds.min_y
+        self.get_bounds().intersects(bounds)
     }
 
     pub fn get_bounds(&self) -> Rect {
@@ -81,17 +88,45 @@ pub trait Visitor<B, L, E> {
     fn visit_branch_pre(&mut self, branch: &B, data: &VisitData) -> Result<bool, E>;
     fn visit_leaf(&mut self, leaf: &L, data: &VisitData) -> Result<(), E>;
     fn visit_branch_post(&mut self, branch: &B, data: &VisitData) -> Result<(), E>;
+
+    /**
+     * Called in place of a subtree of a PagedQuadtree that is not loaded. Plain quadtrees never
+     * contain unloaded subtrees, and PagedQuadtree loads every subtree that a visit needs, so by
+     * default this panics.
+     */
+    fn visit_unloaded(&mut self, data: &VisitData) -> Result<(), E> {
+        panic!("subtree is not loaded: {:?}", data.address)
+    }
+
+    /**
+     * Called in place of visit_branch_pre and visit_branch_post for a branch at the cutoff depth
+     * of a depth-limited visit (see Quadtree::visit_to_depth), so that the branch's aggregate can
+     * be used as if it were a leaf. Only depth-limited visits need this, so by default this panics.
+     */
+    fn visit_cutoff_branch(&mut self, _branch: &B, data: &VisitData) -> Result<(), E> {
+        panic!("depth-limited visit is not supported: {:?}", data.address)
+    }
 }
 
 pub trait MutVisitor<B, L, E> {
     fn visit_branch_pre(&mut self, branch: &mut B, data: &VisitData) -> Result<bool, E>;
     fn visit_leaf(&mut self, leaf: &mut L, data: &VisitData) -> Result<(), E>;
     fn visit_branch_post(&mut self, branch: &mut B, data: &VisitData) -> Result<(), E>;
+
+    /// See Visitor::visit_unloaded.
+    fn visit_unloaded(&mut self, data: &VisitData) -> Result<(), E> {
+        panic!("subtree is not loaded: {:?}", data.address)
+    }
 }
 
 pub trait Fold<B, L, T, E> {
     fn fold_leaf(&mut self, leaf: &L, data: &VisitData) -> Result<T, E>;
     fn fold_branch(&mut self, branch: &B, children: &QuadMap<T>, data: &VisitData) -> Result<T, E>;
+
+    /// See Visitor::visit_cutoff_branch and Quadtree::fold_to_depth.
+    fn fold_cutoff_branch(&mut self, _branch: &B, data: &VisitData) -> Result<T, E> {
+        panic!("depth-limited fold is not supported: {:?}", data.address)
+    }
 }
 
 pub trait MutFold<B, L, T, E> {
@@ -105,7 +140,7 @@ pub trait MutFold<B, L, T, E> {
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-enum Node<B, L> {
+pub(crate) enum Node<B, L> {
     Branch {
         data: B,
         children: QuadMap<Box<Node<B, L>>>,
@@ -123,6 +158,16 @@ enum Node<B, L> {
     Leaf {
         data: L,
 
+        /** The number of branches above this node, up to and including the root. */
+        depth: usize,
+    },
+    /**
+     * A subtree that is stored in a page of a PagedQuadtree and is not currently in memory. Plain
+     * quadtrees never contain unloaded nodes.
+     */
+    Unloaded {
+        page: usize,
+
         /** The number of branches above this node, up to and including the root. */
         depth: usize,
     },
@@ -130,18 +175,18 @@ enum Node<B, L> {
 
 impl<B, L> Node<B, L> {
     fn get(&self, quadrant: Quadrant) -> Result<&Node<B, L>, Error> {
-        if let Node::Branch { children, .. } = self {
-            Ok(&children[quadrant])
-        } else {
-            Err(Error::ExpectedBranch())
+        match self {
+            Node::Branch { children, .. } => Ok(&children[quadrant]),
+            Node::Leaf { .. } => Err(Error::ExpectedBranch()),
+            Node::Unloaded { page, .. } => Err(Error::NotLoaded(*page)),
         }
     }
 
     fn get_mut(&mut self, quadrant: Quadrant) -> Result<&mut Box<Node<B, L>>, Error> {
-        if let Node::Branch { children, .. } = self {
-            Ok(&mut children[quadrant])
-        } else {
-            Err(Error::ExpectedBranch())
+        match self {
+            Node::Branch { children, .. } => Ok(&mut children[quadrant]),
+            Node::Leaf { .. } => Err(Error::ExpectedBranch()),
+            Node::Unloaded { page, .. } => Err(Error::NotLoaded(*page)),
         }
     }
 
@@ -159,6 +204,7 @@ impl<B, L> Node<B, L> {
                 visitor.visit_branch_post(data, &visit_data)?;
             }
             Node::Leaf { data, .. } => visitor.visit_leaf(data, &visit_data)?,
+            Node::Unloaded { .. } => visitor.visit_unloaded(&visit_data)?,
         }
         Ok(())
     }
@@ -177,22 +223,30 @@ impl<B, L> Node<B, L> {
                 visitor.visit_branch_post(data, &visit_data)?;
             }
             Node::Leaf { data, .. } => visitor.visit_leaf(data, &visit_data)?,
+            Node::Unloaded { .. } => visitor.visit_unloaded(&visit_data)?,
         }
         Ok(())
     }
 
-    fn fold<F, T, E>(&self, fold: &mut F, visit_data: VisitData) -> Result<T, E>
+    /// Branches at max_depth are folded with Fold::fold_cutoff_branch instead of descending.
+    fn fold<F, T, E>(&self, fold: &mut F, visit_data: VisitData, max_depth: u32) -> Result<T, E>
     where
         F: Fold<B, L, T, E>,
     {
         match self {
             Node::Leaf { data, .. } => fold.fold_leaf(data, &visit_data),
+            Node::Unloaded { .. } => panic!("subtree is not loaded: {:?}", visit_data.address),
+            Node::Branch { data, .. } if visit_data.depth >= max_depth => {
+                fold.fold_cutoff_branch(data, &visit_data)
+            }
             Node::Branch { data, children, .. } => {
                 // TODO: this is gross
-                let nw = children[Quadrant::NW].fold(fold, visit_data.child(Quadrant::NW))?;
-                let ne = children[Quadrant::NE].fold(fold, visit_data.child(Quadrant::NE))?;
-                let sw = children[Quadrant::SW].fold(fold, visit_data.child(Quadrant::SW))?;
-                let se = children[Quadrant::SE].fold(fold, visit_data.child(Quadrant::SE))?;
+                let mut fold_child =
+                    |quadrant| children[quadrant].fold(fold, visit_data.child(quadrant), max_depth);
+                let nw = fold_child(Quadrant::NW)?;
+                let ne = fold_child(Quadrant::NE)?;
+                let sw = fold_child(Quadrant::SW)?;
+                let se = fold_child(Quadrant::SE)?;
                 fold.fold_branch(data, &QuadMap::new(nw, ne, sw, se), &visit_data)
             }
         }
@@ -204,6 +258,7 @@ impl<B, L> Node<B, L> {
     {
         match self {
             Node::Leaf { data, .. } => fold.fold_leaf(data, &visit_data),
+            Node::Unloaded { .. } => panic!("subtree is not loaded: {:?}", visit_data.address),
             Node::Branch { data, children, .. } => {
                 // TODO: this is gross
                 let nw = children[Quadrant::NW].fold_mut(fold, visit_data.child(Quadrant::NW))?;
@@ -218,14 +273,129 @@ impl<B, L> Node<B, L> {
     /**
      * Adds the addresses of all contained leaves along the given side to vec.
      */
-    fn get_side(&self, direction: Direction, address: Address, vec: &mut Vec<Address>) {
+    fn get_side(
+        &self,
+        direction: Direction,
+        address: Address,
+        vec: &mut Vec<Address>,
+    ) -> Result<(), Error> {
         match self {
             Node::Leaf { .. } => vec.push(address),
             Node::Branch { children, .. } => {
                 for quadrant in direction.get_quadrants() {
-                    children[quadrant].get_side(direction, address.child(quadrant), vec);
+                    children[quadrant].get_side(direction, address.child(quadrant), vec)?;
+                }
+            }
+            Node::Unloaded { page, .. } => return Err(Error::NotLoaded(*page)),
+        }
+        Ok(())
+    }
+}
+
+impl<B: Clone, L: Clone> Node<B, L> {
+    /// A deep copy of this node, with the depths adjusted as if it were at the given depth.
+    fn clone_at_depth(&self, new_depth: usize) -> Result<Node<B, L>, Error> {
+        Ok(match self {
+            Node::Branch {
+                data,
+                children,
+                child_count,
+                child_depth,
+                ..
+            } => {
+                let clone_child = |quadrant| -> Result<_, Error> {
+                    Ok(Box::new(children[quadrant].clone_at_depth(new_depth + 1)?))
+                };
+                Node::Branch {
+                    data: data.clone(),
+                    children: QuadMap::new(
+                        clone_child(Quadrant::NW)?,
+                        clone_child(Quadrant::NE)?,
+                        clone_child(Quadrant::SW)?,
+                        clone_child(Quadrant::SE)?,
+                    ),
+                    depth: new_depth,
+                    child_count: *child_count,
+                    child_depth: *child_depth,
+                }
+            }
+            Node::Leaf { data, .. } => Node::Leaf {
+                data: data.clone(),
+                depth: new_depth,
+            },
+            Node::Unloaded { page, .. } => return Err(Error::NotLoaded(*page)),
+        })
+    }
+}
+
+impl<B, L> Node<B, L> {
+    fn leaf_count(&self) -> usize {
+        match self {
+            Node::Branch { child_count, .. } => *child_count,
+            Node::Leaf { .. } => 1,
+            Node::Unloaded { .. } => 0,
+        }
+    }
+
+    fn branch_depth(&self) -> usize {
+        match self {
+            Node::Branch { child_depth, .. } => *child_depth,
+            _ => 0,
+        }
+    }
+
+    /// Move the node to the given depth, adjusting the depths of everything below it.
+    fn set_depth(&mut self, new_depth: usize) {
+        match self {
+            Node::Branch {
+                children, depth, ..
+            } => {
+                *depth = new_depth;
+                for quadrant in QUADRANTS {
+                    children[quadrant].set_depth(new_depth + 1);
                 }
             }
+            Node::Leaf { depth, .. } | Node::Unloaded { depth, .. } => *depth = new_depth,
+        }
+    }
+
+    /// Recompute the leaf counts and branch depths along the path to the given address.
+    fn refresh_counts(&mut self, address: &Address, index: usize) {
+        if let Node::Branch {
+            children,
+            child_count,
+            child_depth,
+            ..
+        } = self
+        {
+            if index < address.depth() {
+                children[address.at(index)].refresh_counts(address, index + 1);
+            }
+            *child_count = QUADRANTS
+                .iter()
+                .map(|quadrant| children[*quadrant].leaf_count())
+                .sum();
+            *child_depth = 1 + QUADRANTS
+                .iter()
+                .map(|quadrant| children[*quadrant].branch_depth())
+                .max()
+                .unwrap();
+        }
+    }
+}
+
+impl<B: MemorySize, L: MemorySize> MemorySize for Node<B, L> {
+    fn heap_bytes(&self) -> usize {
+        match self {
+            Node::Branch { data, children, .. } => {
+                data.heap_bytes()
+                    + QUADRANTS
+                        .iter()
+                        .map(|quadrant| children[*quadrant].estimated_bytes())
+                        .sum::<usize>()
+            }
+            Node::Leaf { data, .. } => data.heap_bytes(),
+            Node::Unloaded { .. } => 0,
         }
     }
 }
@@ -243,6 +413,16 @@ pub struct Quadtree<B, L> {
     width: u64,
 }
 
+/**
+ * Every node is boxed separately, and each is as big as the biggest variant, so this counts the
+ * size of the nodes along with what the branch and leaf data own.
+ */
+impl<B: MemorySize, L: MemorySize> MemorySize for Quadtree<B, L> {
+    fn heap_bytes(&self) -> usize {
+        self.root.estimated_bytes()
+    }
+}
+
 impl<B, L> Quadtree<B, L> {
     pub fn new(data: L, max_depth: u32) -> Quadtree<B, L> {
         let base: u64 = 2;
@@ -258,6 +438,14 @@ impl<B, L> Quadtree<B, L> {
         }
     }
 
+    pub(crate) fn from_root(root: Node<B, L>, max_depth: u32) -> Quadtree<B, L> {
+        Quadtree {
+            root: Box::new(root),
+            max_depth,
+            width: 2_u64.checked_pow(max_depth).unwrap(),
+        }
+    }
+
     pub fn width(&self) -> u64 {
         self.width
     }
@@ -266,7 +454,15 @@ impl<B, L> Quadtree<B, L> {
         self.max_depth
     }
 
-    fn get(&self, address: &Address) -> Result<&Node<B, L>, Error> {
+    pub fn leaf_count(&self) -> usize {
+        match &*self.root {
+            Node::Branch { child_count, .. } => *child_count,
+            Node::Leaf { .. } => 1,
+            Node::Unloaded { .. } => 0,
+        }
+    }
+
+    pub(crate) fn get(&self, address: &Address) -> Result<&Node<B, L>, Error> {
         // NOTE: this is an associated function rather than a method to avoid borrowing the arena
         let mut node = &*self.root;
         for index in 0..address.depth() {
@@ -275,7 +471,7 @@ impl<B, L> Quadtree<B, L> {
         Ok(node)
     }
 
-    fn get_mut(&mut self, address: &Address) -> Result<&mut Node<B, L>, Error> {
+    pub(crate) fn get_mut(&mut self, address: &Address) -> Result<&mut Node<B, L>, Error> {
         // NOTE: this is an associated function rather than a method to avoid borrowing the arena
         let mut node = &mut self.root;
         for index in 0..address.depth() {
@@ -285,34 +481,34 @@ impl<B, L> Quadtree<B, L> {
     }
 
     pub fn get_branch<A: Into<Address>>(&self, address: A) -> Result<&B, Error> {
-        if let Node::Branch { data, .. } = self.get(&address.into())? {
-            Ok(data)
-        } else {
-            Err(Error::ExpectedBranch())
+        match self.get(&address.into())? {
+            Node::Branch { data, .. } => Ok(data),
+            Node::Leaf { .. } => Err(Error::ExpectedBranch()),
+            Node::Unloaded { page, .. } => Err(Error::NotLoaded(*page)),
         }
     }
 
     pub fn get_branch_mut<A: Into<Address>>(&mut self, address: A) -> Result<&mut B, Error> {
-        if let Node::Branch { data, .. } = self.get_mut(&address.into())? {
-            Ok(data)
-        } else {
-            Err(Error::ExpectedBranch())
+        match self.get_mut(&address.into())? {
+            Node::Branch { data, .. } => Ok(data),
+            Node::Leaf { .. } => Err(Error::ExpectedBranch()),
+            Node::Unloaded { page, .. } => Err(Error::NotLoaded(*page)),
         }
     }
 
     pub fn get_leaf<A: Into<Address>>(&self, address: A) -> Result<&L, Error> {
-        if let Node::Leaf { data, .. } = self.get(&address.into())? {
-            Ok(data)
-        } else {
-            Err(Error::ExpectedLeaf())
+        match self.get(&address.into())? {
+            Node::Leaf { data, .. } => Ok(data),
+            Node::Branch { .. } => Err(Error::ExpectedLeaf()),
+            Node::Unloaded { page, .. } => Err(Error::NotLoaded(*page)),
         }
     }
 
     pub fn get_leaf_mut<A: Into<Address>>(&mut self, address: A) -> Result<&mut L, Error> {
-        if let Node::Leaf { data, .. } = self.get_mut(&address.into())? {
-            Ok(data)
-        } else {
-            Err(Error::ExpectedLeaf())
+        match self.get_mut(&address.into())? {
+            Node::Leaf { data, .. } => Ok(data),
+            Node::Branch { .. } => Err(Error::ExpectedLeaf()),
+            Node::Unloaded { page, .. } => Err(Error::NotLoaded(*page)),
         }
     }
 
@@ -335,6 +531,7 @@ impl<B, L> Quadtree<B, L> {
         let existing = self.get_mut(&address)?;
         match existing {
             Node::Branch { .. } => Err(Error::ExpectedLeaf()),
+            Node::Unloaded { page, .. } => Err(Error::NotLoaded(*page)),
             Node::Leaf {
                 depth: existing_depth,
                 ..
@@ -376,6 +573,53 @@ impl<B, L> Quadtree<B, L> {
         }
     }
 
+    /**
+     * A deep copy of the subtree at the given address, as a quadtree of its own. The max depth is
+     * reduced by the depth of the address, so that the leaves are as small as they were, and
+     * coordinates in the copy are relative to the corner of the subtree; see Address::relative_to.
+     */
+    pub fn extract<A: Into<Address>>(&self, address: A) -> Result<Quadtree<B, L>, Error>
+    where
+        B: Clone,
+        L: Clone,
+    {
+        let address = address.into();
+        let root = self.get(&address)?.clone_at_depth(0)?;
+        Ok(Quadtree::from_root(
+            root,
+            self.max_depth - address.depth() as u32,
+        ))
+    }
+
+    /**
+     * Replace the node at the given address with the given subtree, e.g. one from extract. The
+     * subtree must have exactly the max depth that is left below the address, so that its leaves
+     * keep their size. Returns the subtree that was replaced.
+     */
+    pub fn graft<A: Into<Address>>(
+        &mut self,
+        address: A,
+        subtree: Quadtree<B, L>,
+    ) -> Result<Quadtree<B, L>, Error> {
+        let address = address.into();
+        let expected = self.max_depth - address.depth() as u32;
+        if subtree.max_depth != expected {
+            return Err(Error::IncompatibleDepth {
+                expected,
+                actual: subtree.max_depth,
+            });
+        }
+
+        let mut root = subtree.root;
+        root.set_depth(address.depth());
+        let existing = self.get_mut(&address)?;
+        let mut replaced = std::mem::replace(existing, *root);
+        replaced.set_depth(0);
+
+        self.root.refresh_counts(&address, 0);
+        Ok(Quadtree::from_root(replaced, expected))
+    }
+
     pub fn get_visit_data(&self, x: u64, y: u64) -> Result<VisitData, Error> {
         if x >= self.width || y >= self.width {
             return Err(Error::CoordsOutOfBoundsU64(x, y));
@@ -421,6 +665,7 @@ impl<B, L> Quadtree<B, L> {
                     address.push(quadrant);
                     node = &children[quadrant];
                 }
+                Node::Unloaded { page, .. } => return Err(Error::NotLoaded(*page)),
             }
         }
         panic!("invariant violated; nodes nested deeper than max_depth");
@@ -454,6 +699,22 @@ impl<B, L> Quadtree<B, L> {
         self.root.visit_mut(visitor, self.root_visit_data())
     }
 
+    /**
+     * Like visit, but doesn't descend below max_depth. Branches at max_depth are passed to
+     * Visitor::visit_cutoff_branch instead of being descended into, so the visitor can use their
+     * aggregates in place of the leaves below them. Leaves above max_depth are visited as usual.
+     */
+    pub fn visit_to_depth<V, E>(&self, visitor: &mut V, max_depth: u32) -> Result<(), E>
+    where
+        V: Visitor<B, L, E>,
+    {
+        self.visit(&mut DepthVisitor {
+            max_depth,
+            inner: visitor,
+            phantom: std::marker::PhantomData::default(),
+        })
+    }
+
     pub fn visit_rect<V, E>(&self, visitor: &mut V, bounds: &Rect) -> Result<(), E>
     where
         V: Visitor<B, L, E>,
@@ -476,11 +737,42 @@ impl<B, L> Quadtree<B, L> {
         })
     }
 
+    /// Combination of visit_rect and visit_to_depth.
+    pub fn visit_rect_to_depth<V, E>(
+        &self,
+        visitor: &mut V,
+        bounds: &Rect,
+        max_depth: u32,
+    ) -> Result<(), E>
+    where
+        V: Visitor<B, L, E>,
+    {
+        self.visit_to_depth(
+            &mut RectVisitor {
+                bounds,
+                inner: visitor,
+                phantom: std::marker::PhantomData::default(),
+            },
+            max_depth,
+        )
+    }
+
     pub fn fold<F, T, E>(&self, fold: &mut F) -> Result<T, E>
     where
         F: Fold<B, L, T, E>,
     {
-        self.root.fold(fold, self.root_visit_data())
+        self.root.fold(fold, self.root_visit_data(), u32::MAX)
+    }
+
+    /**
+     * Like fold, but branches at max_depth are folded with Fold::fold_cutoff_branch instead of
+     * folding their children, e.g. for cheap statistics from the branch aggregates.
+     */
+    pub fn fold_to_depth<F, T, E>(&self, fold: &mut F, max_depth: u32) -> Result<T, E>
+    where
+        F: Fold<B, L, T, E>,
+    {
+        self.root.fold(fold, self.root_visit_data(), max_depth)
     }
 
     pub fn fold_mut<F, T, E>(&mut self, fold: &mut F) -> Result<T, E>
@@ -510,8 +802,9 @@ impl<B, L> Quadtree<B, L> {
                     }
                     Ok(branch @ Node::Branch { .. }) => {
                         // too big
-                        branch.get_side(direction.opposite(), other, &mut borders);
+                        branch.get_side(direction.opposite(), other, &mut borders)?;
                     }
+                    Ok(Node::Unloaded { page, .. }) => return Err(Error::NotLoaded(*page)),
                     Err(_) => {
                         // too small
                         let (x, y) = other.to_xy();
@@ -551,6 +844,63 @@ where
     fn visit_branch_post(&mut self, branch: &B, data: &VisitData) -> Result<(), E> {
         self.inner.visit_branch_post(branch, data)
     }
+
+    fn visit_unloaded(&mut self, data: &VisitData) -> Result<(), E> {
+        if data.in_bounds(self.bounds) {
+            self.inner.visit_unloaded(data)?
+        }
+        Ok(())
+    }
+
+    fn visit_cutoff_branch(&mut self, branch: &B, data: &VisitData) -> Result<(), E> {
+        if data.in_bounds(self.bounds) {
+            self.inner.visit_cutoff_branch(branch, data)?
+        }
+        Ok(())
+    }
+}
+
+struct DepthVisitor<'a, V, B, L, E>
+where
+    V: Visitor<B, L, E>,
+{
+    max_depth: u32,
+    inner: &'a mut V,
+    phantom: std::marker::PhantomData<(B, L, E)>,
+}
+
+impl<'a, V, B, L, E> Visitor<B, L, E> for DepthVisitor<'a, V, B, L, E>
+where
+    V: Visitor<B, L, E>,
+{
+    fn visit_branch_pre(&mut self, branch: &B, data: &VisitData) -> Result<bool, E> {
+        if data.depth >= self.max_depth {
+            self.inner.visit_cutoff_branch(branch, data)?;
+            Ok(false)
+        } else {
+            self.inner.visit_branch_pre(branch, data)
+        }
+    }
+
+    fn visit_leaf(&mut self, leaf: &L, data: &VisitData) -> Result<(), E> {
+        self.inner.visit_leaf(leaf, data)
+    }
+
+    fn visit_branch_post(&mut self, branch: &B, data: &VisitData) -> Result<(), E> {
+        // cutoff branches were already handled in visit_branch_pre
+        if data.depth < self.max_depth {
+            self.inner.visit_branch_post(branch, data)?;
+        }
+        Ok(())
+    }
+
+    fn visit_unloaded(&mut self, data: &VisitData) -> Result<(), E> {
+        self.inner.visit_unloaded(data)
+    }
+
+    fn visit_cutoff_branch(&mut self, branch: &B, data: &VisitData) -> Result<(), E> {
+        self.inner.visit_cutoff_branch(branch, data)
+    }
 }
 
 struct MutRectVisitor<'a, 'b, V, B, L, E>
@@ -580,6 +930,13 @@ where
     fn visit_branch_post(&mut self, branch: &mut B, data: &VisitData) -> Result<(), E> {
         self.inner.visit_branch_post(branch, data)
     }
+
+    fn visit_unloaded(&mut self, data: &VisitData) -> Result<(), E> {
+        if data.in_bounds(self.bounds) {
+            self.inner.visit_unloaded(data)?
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -670,6 +1027,7 @@ mod tests {
     struct SeenVisitor<B: Copy, L: Copy> {
         branches: Vec<(B, VisitData)>,
         leaves: Vec<(L, VisitData)>,
+        cutoffs: Vec<(B, VisitData)>,
     }
 
     impl<B: Copy, L: Copy> SeenVisitor<B, L> {
@@ -677,6 +1035,7 @@ mod tests {
             Self {
                 branches: Vec::new(),
                 leaves: Vec::new(),
+                cutoffs: Vec::new(),
             }
         }
     }
@@ -695,6 +1054,11 @@ mod tests {
         fn visit_branch_post(&mut self, _branch: &B, _data: &VisitData) -> Result<(), ()> {
             Ok(())
         }
+
+        fn visit_cutoff_branch(&mut self, branch: &B, data: &VisitData) -> Result<(), ()> {
+            self.cutoffs.push((*branch, data.clone()));
+            Ok(())
+        }
     }
 
     fn make_visit_data(
@@ -785,6 +1149,218 @@ mod tests {
         );
     }
 
+    fn visit_rect_leaves(qtree: &Quadtree<i32, i32>, bounds: Rect) -> Vec<i32> {
+        let mut visitor = SeenVisitor::new();
+        qtree.visit_rect(&mut visitor, &bounds).unwrap();
+        let mut leaves: Vec<i32> = visitor.leaves.into_iter().map(|(leaf, _)| leaf).collect();
+        leaves.sort_unstable();
+        leaves
+    }
+
+    #[test]
+    fn visit_rect_edges() {
+        // leaves are 1 = [0, 2) x [0, 2), 2 = [2, 4) x [0, 2), 3 = [0, 2) x [2, 4), 4 = [2, 4) x [2, 4)
+        let mut qtree = Quadtree::new(0, 2);
+        qtree
+            .split((vec![], 2), 0, QuadMap::new(1, 2, 3, 4))
+            .unwrap();
+
+        // rect exactly matching the whole tree
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(0, 0, 4, 4)),
+            vec![1, 2, 3, 4]
+        );
+
+        // rect exactly matching a single leaf; neighbors touching the max edges are excluded
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(0, 0, 2, 2)),
+            vec![1]
+        );
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(2, 0, 4, 2)),
+            vec![2]
+        );
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(0, 2, 2, 4)),
+            vec![3]
+        );
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(2, 2, 4, 4)),
+            vec![4]
+        );
+
+        // rect one unit past a leaf edge includes the neighbor
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(0, 0, 3, 2)),
+            vec![1, 2]
+        );
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(0, 0, 2, 3)),
+            vec![1, 3]
+        );
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(1, 1, 3, 3)),
+            vec![1, 2, 3, 4]
+        );
+
+        // empty rects and rects outside the tree see nothing
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(2, 2, 2, 2)),
+            Vec::<i32>::new()
+        );
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(4, 0, 6, 4)),
+            Vec::<i32>::new()
+        );
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(0, 4, 4, 6)),
+            Vec::<i32>::new()
+        );
+    }
+
+    #[test]
+    fn visit_rect_nested_edges() {
+        use Quadrant::*;
+
+        let mut qtree = Quadtree::new(0, 2);
+        qtree
+            .split((vec![], 2), 0, QuadMap::new(1, 2, 3, 4))
+            .unwrap();
+        qtree
+            .split((vec![NW], 2), 1, QuadMap::new(5, 6, 7, 8))
+            .unwrap();
+
+        // the right edge of the rect coincides with the right edge of the NW branch
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(1, 1, 2, 2)),
+            vec![8]
+        );
+        // the left edge of the rect coincides with the right edge of the NW branch
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(2, 0, 3, 1)),
+            vec![2]
+        );
+        // the rect covers the last column and row of the NW branch
+        assert_eq!(
+            visit_rect_leaves(&qtree, Rect::corners(1, 1, 4, 4)),
+            vec![2, 3, 4, 8]
+        );
+    }
+
+    #[test]
+    fn in_bounds_matches_contains() {
+        let data = make_visit_data(vec![], 0, 2, 2, 2, 0);
+        for x in 0..6 {
+            for y in 0..6 {
+                let bounds = Rect::xywh(x, y, 1, 1);
+                assert_eq!(
+                    data.in_bounds(&bounds),
+                    data.get_bounds().contains(x, y),
+                    "mismatch at ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    /// Split every leaf until the tree is full, labeling each node with its depth.
+    fn split_all(qtree: &mut Quadtree<u32, u32>, address: Address) {
+        if address.depth() == qtree.max_depth() as usize {
+            return;
+        }
+        let depth = address.depth() as u32;
+        qtree
+            .split(address, depth, QuadMap::each(|| depth + 1))
+            .unwrap();
+        for quadrant in QUADRANTS {
+            split_all(qtree, address.child(quadrant));
+        }
+    }
+
+    #[test]
+    fn visit_to_depth() {
+        let mut qtree = Quadtree::new(0, 5);
+        split_all(&mut qtree, Address::from_vec(vec![], 5));
+
+        let mut visitor = SeenVisitor::new();
+        qtree.visit_to_depth(&mut visitor, 2).unwrap();
+        assert_eq!(visitor.branches.len(), 1 + 4);
+        assert_eq!(visitor.cutoffs.len(), 16);
+        assert!(visitor.leaves.is_empty());
+        for (depth, data) in &visitor.cutoffs {
+            assert_eq!(*depth, 2);
+            assert_eq!(data.depth, 2);
+            assert_eq!(data.width, 8);
+        }
+
+        // leaves above the cutoff are visited as usual
+        let mut qtree = Quadtree::new(0, 5);
+        qtree
+            .split((vec![], 5), 0, QuadMap::new(1, 1, 1, 1))
+            .unwrap();
+        split_all(&mut qtree, Address::from_vec(vec![Quadrant::SE], 5));
+        let mut visitor = SeenVisitor::new();
+        qtree.visit_to_depth(&mut visitor, 2).unwrap();
+        assert_eq!(visitor.branches.len(), 2);
+        assert_eq!(visitor.leaves.len(), 3);
+        assert_eq!(visitor.cutoffs.len(), 4);
+        for (_, data) in &visitor.cutoffs {
+            assert_eq!(data.address.at(0), Quadrant::SE);
+            assert_eq!(data.width, 8);
+        }
+    }
+
+    #[test]
+    fn visit_rect_to_depth() {
+        let mut qtree = Quadtree::new(0, 5);
+        split_all(&mut qtree, Address::from_vec(vec![], 5));
+
+        let mut visitor = SeenVisitor::new();
+        qtree
+            .visit_rect_to_depth(&mut visitor, &Rect::xywh(0, 0, 8, 16), 2)
+            .unwrap();
+        assert_equal_vec_unordered(
+            visitor
+                .cutoffs
+                .into_iter()
+                .map(|(_, data)| (data.x, data.y))
+                .collect(),
+            vec![(0, 0), (0, 8)],
+        );
+    }
+
+    struct CountFold;
+
+    impl quadtree::Fold<u32, u32, usize, ()> for CountFold {
+        fn fold_leaf(&mut self, _leaf: &u32, _data: &VisitData) -> Result<usize, ()> {
+            Ok(1)
+        }
+
+        fn fold_branch(
+            &mut self,
+            _branch: &u32,
+            children: &QuadMap<usize>,
+            _data: &VisitData,
+        ) -> Result<usize, ()> {
+            Ok(1 + children.values().iter().copied().sum::<usize>())
+        }
+
+        fn fold_cutoff_branch(&mut self, _branch: &u32, data: &VisitData) -> Result<usize, ()> {
+            assert_eq!(data.width, 8);
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn fold_to_depth() {
+        let mut qtree = Quadtree::new(0, 5);
+        split_all(&mut qtree, Address::from_vec(vec![], 5));
+
+        assert_eq!(qtree.fold_to_depth(&mut CountFold, 2), Ok(1 + 4 + 16));
+        assert_eq!(qtree.fold(&mut CountFold), Ok(1 + 4 + 16 + 64 + 256 + 1024));
+    }
+
     #[test]
     fn get_borders() {
         use Quadrant::*;
@@ -833,4 +1409,99 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn extract_graft() {
+        use Quadrant::*;
+
+        let mut qtree = Quadtree::new(0, 3);
+        qtree
+            .split((vec![], 3), 0, QuadMap::new(1, 2, 3, 4))
+            .unwrap();
+        qtree
+            .split((vec![NW], 3), 10, QuadMap::new(11, 12, 13, 14))
+            .unwrap();
+        qtree
+            .split((vec![NW, SE], 3), 20, QuadMap::new(21, 22, 23, 24))
+            .unwrap();
+
+        let subtree = qtree.extract((vec![NW], 3)).unwrap();
+        assert_eq!(subtree.max_depth(), 2);
+        assert_eq!(subtree.leaf_count(), 7);
+        assert_eq!(subtree.get_branch((vec![], 2)), Ok(&10));
+        assert_eq!(subtree.get_leaf((vec![SE, NE], 2)), Ok(&22));
+        assert_eq!(subtree.get_address(3, 2).unwrap(), (vec![SE, NE], 2).into());
+
+        // the subtree only fits one level down
+        assert_eq!(
+            qtree.clone().graft((vec![], 3), subtree.clone()).err(),
+            Some(Error::IncompatibleDepth {
+                expected: 3,
+                actual: 2
+            })
+        );
+
+        let replaced = qtree.graft((vec![SE], 3), subtree).unwrap();
+        assert_eq!(replaced.max_depth(), 2);
+        assert_eq!(replaced.get_leaf((vec![], 2)), Ok(&4));
+
+        assert_eq!(qtree.leaf_count(), 16);
+        assert_eq!(qtree.get_branch((vec![SE], 3)), Ok(&10));
+        assert_eq!(qtree.get_leaf((vec![SE, SE, NE], 3)), Ok(&22));
+        assert_eq!(
+            qtree.get_address(7, 6).unwrap(),
+            (vec![SE, SE, NE], 3).into()
+        );
+        // the original is untouched
+        assert_eq!(qtree.get_leaf((vec![NW, SE, NE], 3)), Ok(&22));
+
+        // the pasted subtree can be split further, down to the max depth
+        assert_eq!(
+            qtree.split((vec![SE, SE, NE], 3), 30, QuadMap::new(0, 0, 0, 0)),
+            Err(Error::MaxDepthExceeded(3))
+        );
+        qtree
+            .split((vec![SE, NE], 3), 30, QuadMap::new(31, 32, 33, 34))
+            .unwrap();
+        assert_eq!(qtree.leaf_count(), 19);
+
+        // grafting over a branch with a single leaf shrinks the tree again
+        qtree.graft((vec![SE], 3), Quadtree::new(5, 2)).unwrap();
+        assert_eq!(qtree.leaf_count(), 10);
+        assert_eq!(qtree.get_leaf((vec![SE], 3)), Ok(&5));
+    }
+
+    #[test]
+    fn memory_size() {
+        use memory_size::MemorySize;
+
+        const PAYLOAD: usize = 100;
+        let leaf = || String::with_capacity(PAYLOAD);
+
+        // split every leaf breadth first, one at a time, so each split replaces a leaf with a
+        // branch and four new leaves
+        let mut qtree: Quadtree<String, String> = Quadtree::new(leaf(), 4);
+        let mut queue = std::collections::VecDeque::from([Address::from((vec![], 4))]);
+        let mut estimates = vec![(qtree.leaf_count(), qtree.estimated_bytes())];
+        while let Some(address) = queue.pop_front() {
+            if address.depth() == 4 {
+                continue;
+            }
+            qtree
+                .split(address, String::new(), QuadMap::each(leaf))
+                .unwrap();
+            queue.extend(QUADRANTS.iter().map(|quadrant| address.child(*quadrant)));
+            estimates.push((qtree.leaf_count(), qtree.estimated_bytes()));
+        }
+        assert_eq!(qtree.leaf_count(), 256);
+
+        // the estimate grows by the same amount for every three leaves
+        let (first_leaves, first_bytes) = estimates[0];
+        let per_split = estimates[1].1 - first_bytes;
+        assert!(per_split > 3 * PAYLOAD, "{}", per_split);
+        for (leaves, bytes) in estimates {
+            assert_eq!((leaves - first_leaves) % 3, 0);
+            assert_eq!(bytes, first_bytes + (leaves - first_leaves) / 3 * per_split);
+        }
+    }
 }
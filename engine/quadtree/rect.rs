@@ -1,3 +1,10 @@
+/**
+ * An axis-aligned rectangle in model coordinates.
+ *
+ * Rects are half-open: a rect covers [min_x, max_x) horizontally and [min_y, max_y) vertically.
+ * This matches the way quadtree tiles are laid out, so a tile at x with width w covers exactly the
+ * rect Rect::xywh(x, y, w, w), and adjacent tiles share an edge without overlapping.
+ */
 #[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub struct Rect {
     pub min_x: u64,
@@ -25,10 +32,12 @@ impl Rect {
         }
     }
 
+    /// Whether the point lies inside the rect; points on the max edges are not contained.
     pub fn contains(&self, x: u64, y: u64) -> bool {
-        x >= self.min_x && x <= self.max_x && y >= self.min_y && y <= self.max_y
+        x >= self.min_x && x < self.max_x && y >= self.min_y && y < self.max_y
     }
 
+    /// Whether the rects overlap; rects that only share an edge do not intersect.
     pub fn intersects(&self, other: &Self) -> bool {
         self.max_x > other.min_x
             && self.min_x < other.max_x
@@ -45,3 +54,45 @@ impl Rect {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use quadtree::Rect;
+
+    #[test]
+    fn contains_half_open() {
+        let rect = Rect::xywh(2, 2, 4, 4);
+        assert!(rect.contains(2, 2));
+        assert!(rect.contains(5, 5));
+        assert!(!rect.contains(6, 2));
+        assert!(!rect.contains(2, 6));
+        assert!(!rect.contains(6, 6));
+        assert!(!rect.contains(1, 3));
+    }
+
+    #[test]
+    fn intersects_shared_edge() {
+        let rect = Rect::xywh(2, 2, 4, 4);
+        assert!(rect.intersects(&Rect::xywh(5, 5, 1, 1)));
+        assert!(rect.intersects(&Rect::xywh(0, 0, 3, 3)));
+        assert!(!rect.intersects(&Rect::xywh(6, 2, 1, 1)));
+        assert!(!rect.intersects(&Rect::xywh(2, 6, 1, 1)));
+        assert!(!rect.intersects(&Rect::xywh(0, 0, 2, 2)));
+    }
+
+    #[test]
+    fn contains_agrees_with_intersects() {
+        let rect = Rect::xywh(2, 2, 4, 4);
+        for x in 0..8 {
+            for y in 0..8 {
+                assert_eq!(
+                    rect.contains(x, y),
+                    rect.intersects(&Rect::xywh(x, y, 1, 1)),
+                    "mismatch at ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
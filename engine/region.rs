@@ -0,0 +1,165 @@
+use crate::engine::{Engine, Error, ErrorContext};
+use crate::fields::FieldsState;
+use crate::population::{
+    ImportPolicy, Population, PopulationAgent, PopulationHousehold, PopulationImportReport,
+    TileOccupancy,
+};
+
+/**
+ * Part of the map copied with Engine::copy_region, for pasting somewhere else with
+ * Engine::paste_region.
+ */
+#[derive(Debug, Clone)]
+pub struct CopiedRegion {
+    pub region: state::Region<FieldsState>,
+    /**
+     * The agents that live in the region, with addresses relative to it, if they were copied.
+     * Agents that work outside of the region are copied without a job.
+     */
+    pub population: Option<Population>,
+}
+
+#[derive(Debug)]
+pub struct PastedRegionReport {
+    pub pasted: state::PastedRegion,
+    /// the agents that were added, if the region had any
+    pub population: Option<PopulationImportReport>,
+}
+
+/**
+ * The part of the population that lives at the addresses for which the map returns a new address,
+ * moved to those addresses. Workplaces that aren't mapped are dropped.
+ */
+fn map_population(
+    population: &Population,
+    map: impl Fn(quadtree::Address) -> Option<quadtree::Address>,
+) -> Population {
+    let mut household_indices = Vec::new();
+    let mut households = Vec::new();
+    for household in &population.households {
+        household_indices.push(map(household.housing).map(|housing| {
+            households.push(PopulationHousehold {
+                housing,
+                cars: household.cars,
+            });
+            households.len() - 1
+        }));
+    }
+
+    let agents = population
+        .agents
+        .iter()
+        .filter_map(|agent| {
+            Some(PopulationAgent {
+                data: agent.data.clone(),
+                housing: map(agent.housing)?,
+                workplace: agent.workplace.and_then(&map),
+                household: agent
+                    .household
+                    .and_then(|household| household_indices[household]),
+            })
+        })
+        .collect();
+
+    let occupancy = population
+        .occupancy
+        .iter()
+        .filter_map(|tile| {
+            Some(TileOccupancy {
+                address: map(tile.address)?,
+                ..tile.clone()
+            })
+        })
+        .collect();
+
+    Population {
+        households,
+        agents,
+        occupancy,
+    }
+}
+
+impl Engine {
+    /**
+     * Copy the node at the given address, along with the networks inside it; see
+     * State::extract_region. If include_agents is set, the agents that live there are copied too,
+     * although they are not removed from here.
+     */
+    pub fn copy_region(
+        &self,
+        address: quadtree::Address,
+        policy: state::RegionPolicy,
+        include_agents: bool,
+    ) -> Result<CopiedRegion, Error> {
+        let region = self
+            .state
+            .extract_region(address, policy)
+            .with_context(|| format!("failed to copy region at {:?}", address))?;
+        let population = include_agents
+            .then(|| map_population(&self.population(), |tile| tile.relative_to(&address)));
+        Ok(CopiedRegion { region, population })
+    }
+
+    /**
+     * Paste a region from copy_region at the given address, which must be the same size as the
+     * copied one; see State::graft_region. If the address is inside a larger empty or water tile,
+     * that tile is split first. The copied agents, if any, move into the pasted tiles.
+     */
+    pub fn paste_region(
+        &mut self,
+        address: quadtree::Address,
+        region: &CopiedRegion,
+    ) -> Result<PastedRegionReport, Error> {
+        let context = || format!("failed to paste region at {:?}", address);
+
+        // check this before splitting anything
+        let expected = self.state.config.max_depth - address.depth() as u32;
+        if region.region.qtree.max_depth() != expected {
+            return Err(Error::from(quadtree::Error::IncompatibleDepth {
+                expected,
+                actual: region.region.qtree.max_depth(),
+            }))
+            .with_context(context);
+        }
+
+        let bounds = address.bounds();
+        let leaf = self
+            .state
+            .qtree
+            .get_address(bounds.min_x, bounds.min_y)
+            .with_context(context)?;
+        if leaf.depth() < address.depth() {
+            let report = self.bulk_apply(
+                &[leaf],
+                &state::BulkOp::SplitToDepth(address.depth() as u32),
+            );
+            if let Some((_, err)) = report.errors.into_iter().next() {
+                return Err(err).with_context(context);
+            }
+        }
+
+        let pasted = self
+            .state
+            .graft_region(address, &region.region)
+            .with_context(context)?;
+        self.base_graph.write().unwrap().clear();
+
+        let population = match &region.population {
+            Some(population) => {
+                let population = map_population(population, |tile| Some(tile.rebased(&address)));
+                // nothing lives in the pasted tiles yet, so this only skips agents that didn't fit
+                // in the copied tiles either
+                let (skipped_households, skipped) = population
+                    .check(self, ImportPolicy::Skip)
+                    .with_context(context)?;
+                Some(
+                    self.add_population(&population, skipped_households, skipped)
+                        .with_context(context)?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(PastedRegionReport { pasted, population })
+    }
+}
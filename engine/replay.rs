@@ -0,0 +1,500 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+use uom::si::time::minute;
+use uom::si::u64::Time;
+
+use crate::engine::{Engine, Error};
+
+/// identifies replay files, followed by the format version
+const MAGIC: &[u8; 8] = b"MSREPLAY";
+const VERSION: u32 = 1;
+/// records larger than this are assumed to be corrupt rather than read into memory
+const MAX_RECORD_SIZE: u64 = 1 << 30;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayConfig {
+    /// how often to record agent positions, in simulation time
+    pub keyframe_interval: u64,
+    /// recording stops once the replay file would grow past this size, in bytes
+    pub max_file_size: u64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            keyframe_interval: Time::new::<minute>(5).value,
+            max_file_size: 1 << 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentKeyframe {
+    pub time: u64,
+    /// positions of all agents that are currently traveling, by agent ID
+    pub positions: BTreeMap<u64, (f32, f32)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum ReplayRecord {
+    WorldState {
+        time: u64,
+        world_state: route::WorldStateImpl,
+    },
+    AgentKeyframe(AgentKeyframe),
+}
+
+/**
+ * Appends traffic snapshots and agent keyframes to a replay file as the simulation runs.
+ *
+ * Each record is serialized and compressed separately and prefixed with its length, so that a
+ * recording that is cut short can still be read up to the last complete record.
+ */
+#[derive(Debug)]
+pub struct ReplayRecorder {
+    writer: std::io::BufWriter<std::fs::File>,
+    config: ReplayConfig,
+    bytes_written: u64,
+    /// set once a record didn't fit in the file, after which nothing more is written
+    full: bool,
+}
+
+impl ReplayRecorder {
+    pub fn create(path: &std::path::Path, config: ReplayConfig) -> Result<Self, Error> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            config,
+            bytes_written: (MAGIC.len() + std::mem::size_of::<u32>()) as u64,
+            full: false,
+        })
+    }
+
+    pub fn config(&self) -> &ReplayConfig {
+        &self.config
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /**
+     * Whether recording stopped because the file reached its maximum size. The recorder stays
+     * around until stop_recording, so that this can be shown.
+     */
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    /**
+     * Returns false without writing anything if the record would exceed the maximum file size, and
+     * marks the recorder as full.
+     */
+    fn write_record(&mut self, record: &ReplayRecord) -> Result<bool, Error> {
+        if self.full {
+            return Ok(false);
+        }
+
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+        bincode::serialize_into(&mut encoder, record)?;
+        let data = encoder.finish()?;
+        let len = (std::mem::size_of::<u64>() + data.len()) as u64;
+        if data.len() as u64 > MAX_RECORD_SIZE
+            || self.bytes_written + len > self.config.max_file_size
+        {
+            self.full = true;
+            return Ok(false);
+        }
+
+        self.writer.write_all(&(data.len() as u64).to_le_bytes())?;
+        self.writer.write_all(&data)?;
+        self.bytes_written += len;
+
+        Ok(true)
+    }
+
+    pub fn record_world_state(
+        &mut self,
+        time: u64,
+        world_state: &route::WorldStateImpl,
+    ) -> Result<bool, Error> {
+        // TODO: avoid cloning the world state just to serialize it
+        self.write_record(&ReplayRecord::WorldState {
+            time,
+            world_state: world_state.clone(),
+        })
+    }
+
+    pub fn record_agent_keyframe(&mut self, keyframe: AgentKeyframe) -> Result<bool, Error> {
+        self.write_record(&ReplayRecord::AgentKeyframe(keyframe))
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/**
+ * Holds the active recorder, if any. The recorder writes to a file, so it cannot be shared with
+ * cloned engines; clones are not recording.
+ */
+#[derive(Debug, Default)]
+pub(crate) struct Recording {
+    pub(crate) recorder: Option<ReplayRecorder>,
+    /// incremented every time a recording starts, so that keyframe triggers from previous
+    /// recordings can tell that they are stale
+    pub(crate) generation: u64,
+}
+
+impl Clone for Recording {
+    fn clone(&self) -> Self {
+        Self {
+            recorder: None,
+            generation: self.generation,
+        }
+    }
+}
+
+/**
+ * A recording loaded from a replay file, which can be scrubbed through without re-simulating.
+ */
+#[derive(Debug, Default)]
+pub struct Replay {
+    /// traffic snapshots, in order of time
+    world_states: Vec<(u64, route::WorldStateImpl)>,
+    /// agent position keyframes, in order of time
+    keyframes: Vec<AgentKeyframe>,
+    /// whether the file ended with an incomplete record, which was ignored
+    truncated: bool,
+}
+
+impl Replay {
+    pub fn load_file(path: &std::path::Path) -> Result<Self, Error> {
+        Self::read(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        let mut version = [0; 4];
+        reader.read_exact(&mut version)?;
+        if &magic != MAGIC || u32::from_le_bytes(version) != VERSION {
+            return Err(Error::ReplayError(
+                "not a replay file, or unsupported version".to_string(),
+            ));
+        }
+
+        let mut replay = Self::default();
+        loop {
+            let mut len = [0; 8];
+            match reader.read_exact(&mut len) {
+                Ok(()) => (),
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+
+            let len = u64::from_le_bytes(len);
+            if len > MAX_RECORD_SIZE {
+                return Err(Error::ReplayError(format!(
+                    "record of {} bytes is too large, the replay file is probably corrupt",
+                    len
+                )));
+            }
+
+            // only allocate as much as is actually there, in case the length is garbage
+            let mut data = Vec::new();
+            reader.by_ref().take(len).read_to_end(&mut data)?;
+            if (data.len() as u64) < len {
+                tracing::warn!(
+                    len,
+                    read = data.len(),
+                    "replay ends with an incomplete record; ignoring it"
+                );
+                replay.truncated = true;
+                break;
+            }
+
+            let decoder = flate2::read::DeflateDecoder::new(data.as_slice());
+            match bincode::deserialize_from(decoder)? {
+                ReplayRecord::WorldState { time, world_state } => {
+                    replay.world_states.push((time, world_state))
+                }
+                ReplayRecord::AgentKeyframe(keyframe) => replay.keyframes.push(keyframe),
+            }
+        }
+
+        // records are written in order, but be robust to a simulation that was rewound
+        replay.world_states.sort_by_key(|(time, _)| *time);
+        replay.keyframes.sort_by_key(|keyframe| keyframe.time);
+
+        Ok(replay)
+    }
+
+    /**
+     * Whether the recording was cut short in the middle of a record, e.g. because the simulation
+     * crashed. Everything before that record was still read.
+     */
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The times of the recorded traffic snapshots.
+    pub fn timestamps(&self) -> impl Iterator<Item = u64> + '_ {
+        self.world_states.iter().map(|(time, _)| *time)
+    }
+
+    /// The times of the recorded agent keyframes.
+    pub fn keyframe_timestamps(&self) -> impl Iterator<Item = u64> + '_ {
+        self.keyframes.iter().map(|keyframe| keyframe.time)
+    }
+
+    /// The earliest and latest recorded times, if anything was recorded.
+    pub fn time_range(&self) -> Option<(u64, u64)> {
+        let first = self
+            .world_states
+            .first()
+            .map(|(time, _)| *time)
+            .into_iter()
+            .chain(self.keyframes.first().map(|keyframe| keyframe.time))
+            .min()?;
+        let last = self
+            .world_states
+            .last()
+            .map(|(time, _)| *time)
+            .into_iter()
+            .chain(self.keyframes.last().map(|keyframe| keyframe.time))
+            .max()?;
+        Some((first, last))
+    }
+
+    /**
+     * The most recent traffic snapshot at the given time. Before the first snapshot, this is the
+     * first snapshot.
+     */
+    pub fn world_state_at(&self, time: u64) -> Option<&route::WorldStateImpl> {
+        let index = self
+            .world_states
+            .partition_point(|(snapshot_time, _)| *snapshot_time <= time);
+        self.world_states
+            .get(index.saturating_sub(1))
+            .map(|(_, world_state)| world_state)
+    }
+
+    /**
+     * Positions of traveling agents at the given time, linearly interpolated between the
+     * surrounding keyframes. Agents that start or finish traveling between keyframes appear at
+     * their position in whichever keyframe is closer.
+     */
+    pub fn agent_positions_at(&self, time: u64) -> Vec<(u64, (f32, f32))> {
+        let index = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+
+        let (before, after) = match (
+            index.checked_sub(1).map(|i| &self.keyframes[i]),
+            self.keyframes.get(index),
+        ) {
+            (Some(before), Some(after)) => (before, after),
+            (Some(keyframe), None) | (None, Some(keyframe)) => {
+                return keyframe
+                    .positions
+                    .iter()
+                    .map(|(id, pos)| (*id, *pos))
+                    .collect()
+            }
+            (None, None) => return vec![],
+        };
+
+        let fraction = (time - before.time) as f32 / (after.time - before.time) as f32;
+        let mut positions = Vec::new();
+
+        for (id, (x1, y1)) in &before.positions {
+            match after.positions.get(id) {
+                Some((x2, y2)) => {
+                    positions.push((*id, (x1 + (x2 - x1) * fraction, y1 + (y2 - y1) * fraction)))
+                }
+                None if fraction < 0.5 => positions.push((*id, (*x1, *y1))),
+                None => (),
+            }
+        }
+        if fraction >= 0.5 {
+            for (id, pos) in &after.positions {
+                if !before.positions.contains_key(id) {
+                    positions.push((*id, *pos));
+                }
+            }
+        }
+
+        positions
+    }
+}
+
+impl Engine {
+    /**
+     * Start recording traffic snapshots and agent positions to a replay file. Traffic is recorded
+     * every time a snapshot is taken for the traffic history, and agent positions are recorded at
+     * the interval given in the config. Replaces any recording that is already in progress.
+     */
+    pub fn start_recording(
+        &mut self,
+        path: &std::path::Path,
+        config: ReplayConfig,
+    ) -> Result<(), Error> {
+        self.stop_recording()?;
+
+        self.recording.recorder = Some(ReplayRecorder::create(path, config)?);
+        self.recording.generation += 1;
+
+        self.trigger_queue.push(
+            crate::behavior::RecordAgentKeyframe {
+                generation: self.recording.generation,
+            },
+            self.time_state.current_time,
+        );
+
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<(), Error> {
+        if let Some(recorder) = self.recording.recorder.take() {
+            recorder.finish()?;
+        }
+        Ok(())
+    }
+
+    pub fn recorder(&self) -> Option<&ReplayRecorder> {
+        self.recording.recorder.as_ref()
+    }
+
+    /**
+     * Flush what was recorded so far once the recorder reports that the file is full. The recorder
+     * is kept, marked as full, so that recorder() reports why recording stopped.
+     */
+    fn handle_recorder_full(&mut self, written: bool) -> Result<(), Error> {
+        if let (false, Some(recorder)) = (written, &mut self.recording.recorder) {
+            tracing::warn!(
+                bytes_written = recorder.bytes_written,
+                "replay file reached its maximum size; stopping recording"
+            );
+            recorder.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn record_replay_world_state(&mut self) -> Result<(), Error> {
+        match &mut self.recording.recorder {
+            Some(recorder) if !recorder.is_full() => {
+                let written =
+                    recorder.record_world_state(self.time_state.current_time, &self.world_state)?;
+                self.handle_recorder_full(written)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn record_replay_agent_keyframe(&mut self) -> Result<(), Error> {
+        let current_time = self.time_state.current_time;
+        if let Some(recorder) = &mut self.recording.recorder {
+            if recorder.is_full() {
+                return Ok(());
+            }
+            let positions = self
+                .agents
+                .values()
+                .filter_map(|agent| match &agent.state {
+                    agent::AgentState::Route(route_state) => route_state
+                        .sample(current_time, &self.state)
+                        .map(|key| (agent.id, key.position)),
+                    _ => None,
+                })
+                .collect();
+
+            let written = recorder.record_agent_keyframe(AgentKeyframe {
+                time: current_time,
+                positions,
+            })?;
+            self.handle_recorder_full(written)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::replay::*;
+
+    fn keyframe(time: u64, positions: &[(u64, (f32, f32))]) -> AgentKeyframe {
+        AgentKeyframe {
+            time,
+            positions: positions.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn interpolate_agent_positions() {
+        let replay = Replay {
+            world_states: vec![],
+            keyframes: vec![
+                keyframe(100, &[(0, (0.0, 0.0)), (1, (10.0, 10.0))]),
+                keyframe(200, &[(0, (10.0, 20.0)), (2, (5.0, 5.0))]),
+            ],
+            truncated: false,
+        };
+
+        assert_eq!(replay.time_range(), Some((100, 200)));
+
+        let mut positions = replay.agent_positions_at(125);
+        positions.sort_by_key(|(id, _)| *id);
+        // agent 1 stopped traveling, but is shown until halfway to the next keyframe
+        assert_eq!(positions, vec![(0, (2.5, 5.0)), (1, (10.0, 10.0))]);
+
+        let mut positions = replay.agent_positions_at(175);
+        positions.sort_by_key(|(id, _)| *id);
+        // agent 2 started traveling, and is shown from halfway to the next keyframe
+        assert_eq!(positions, vec![(0, (7.5, 15.0)), (2, (5.0, 5.0))]);
+
+        // clamped to the first and last keyframes
+        assert_eq!(replay.agent_positions_at(0).len(), 2);
+        assert_eq!(replay.agent_positions_at(1000).len(), 2);
+    }
+
+    #[test]
+    fn invalid_file() {
+        assert!(matches!(
+            Replay::read(&b"NOTAREPLAYFILE"[..]),
+            Err(Error::ReplayError(_))
+        ));
+    }
+
+    fn header() -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn record_too_large() {
+        let mut data = header();
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            Replay::read(data.as_slice()),
+            Err(Error::ReplayError(_))
+        ));
+    }
+
+    #[test]
+    fn incomplete_record() {
+        let mut data = header();
+        data.extend_from_slice(&MAX_RECORD_SIZE.to_le_bytes());
+        data.extend_from_slice(&[0; 16]);
+        let replay = Replay::read(data.as_slice()).unwrap();
+        assert_eq!(replay.time_range(), None);
+    }
+}
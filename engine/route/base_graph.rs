@@ -1,5 +1,8 @@
 use std::collections::{HashMap, HashSet};
 
+use memory_size::MemorySize;
+use serde::{Deserialize, Serialize};
+
 use crate::common::{Error, Mode, ModeMap, MODES};
 use crate::edge::Edge;
 use crate::fast_graph_wrapper::FastGraphWrapper;
@@ -40,6 +43,8 @@ pub struct BaseGraphStats {
     pub node_count: usize,
     pub edge_count: usize,
     pub terminal_node_counts: HashMap<Mode, usize>,
+    /// number of parking node pairs
+    pub parking_count: usize,
 }
 
 impl Default for BaseGraphStats {
@@ -48,17 +53,49 @@ impl Default for BaseGraphStats {
             node_count: 0,
             edge_count: 0,
             terminal_node_counts: MODES.iter().map(|mode| (*mode, 0)).collect(),
+            parking_count: 0,
         }
     }
 }
 
+/**
+ * Identifies what a route or isochrone was computed against: the base graph version, which changes
+ * whenever the networks are edited, and the number of times the edge weights have been updated for
+ * predicted traffic since the graph was constructed. Both are zero for graphs that were constructed
+ * outside of the engine.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphVersion {
+    pub network: u64,
+    pub traffic: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct Graph {
     pub graph: FastGraphWrapper,
     pub terminal_nodes: Neighbors,
     pub parking: HashMap<quadtree::Address, Parking>,
+    /// addresses where cars can't be parked, so routes that end there have to park elsewhere
+    pub no_parking: HashSet<quadtree::Address>,
     pub tile_size: f64,
     pub max_depth: u32,
+    pub version: GraphVersion,
+    /// The time that the edge weights predict traffic for, or None if they are still the free-flow
+    /// weights from when the graph was constructed.
+    pub traffic_time: Option<u64>,
+}
+
+impl MemorySize for Graph {
+    fn heap_bytes(&self) -> usize {
+        use memory_size::{hash_map_bytes, hash_set_bytes};
+        self.graph.heap_bytes()
+            + MODES
+                .iter()
+                .map(|mode| self.terminal_nodes[*mode].heap_bytes())
+                .sum::<usize>()
+            + hash_map_bytes(&self.parking)
+            + hash_set_bytes(&self.no_parking)
+    }
 }
 
 impl Graph {
@@ -70,8 +107,32 @@ impl Graph {
                 .iter()
                 .map(|mode| (*mode, self.terminal_nodes[*mode].count()))
                 .collect(),
+            parking_count: self.parking.len(),
         }
     }
+
+    pub fn allows_parking(&self, address: quadtree::Address) -> bool {
+        !self.no_parking.contains(&address)
+    }
+}
+
+/**
+ * Whether cars can be parked at the station at the given address. Stations that don't offer
+ * parking only get a walking node in the base graph.
+ */
+fn station_has_parking<F: state::Fields>(
+    state: &state::State<F>,
+    address: quadtree::Address,
+) -> bool {
+    match state.qtree.get_leaf(address) {
+        Ok(leaf) => match &leaf.tile {
+            tiles::Tile::MetroStationTile(tiles::MetroStationTile { parking, .. }) => *parking,
+            _ => true,
+        },
+        // default to allowing parking, which matches the behavior from before stations could
+        // disallow parking
+        Err(_) => true,
+    }
 }
 
 pub fn dump_graph<W>(_graph: &InnerGraph, _write: &mut W) -> Result<(), std::io::Error>
@@ -147,58 +208,95 @@ mod triangulation_ext {
             }
         }
     }
+
+    /**
+     * The pairs of vertices that should be connected by inferred edges, i.e. the edges of the
+     * triangulation that are no longer than the max radius, along with their squared lengths.
+     *
+     * With fewer than three vertices there is nothing to triangulate, so all pairs within the
+     * radius are connected instead. This matters for sparse maps, e.g. with only two stations.
+     */
+    pub(crate) fn inferred_edges(
+        triangulation: &spade::DelaunayTriangulation<TriangulationVertex>,
+        max_radius_sq: f64,
+    ) -> Vec<(TriangulationVertex, TriangulationVertex, f64)> {
+        use spade::Triangulation;
+
+        if triangulation.num_vertices() < 3 {
+            let vertices: Vec<_> = triangulation.vertices().map(|v| *v.data()).collect();
+            let mut edges = Vec::new();
+            for (i, a) in vertices.iter().enumerate() {
+                for b in &vertices[i + 1..] {
+                    let length_2 = (a.x - b.x).powi(2) + (a.y - b.y).powi(2);
+                    if length_2 <= max_radius_sq {
+                        edges.push((*a, *b, length_2));
+                    }
+                }
+            }
+            return edges;
+        }
+
+        triangulation
+            .undirected_edges()
+            .filter(|edge| edge.length_2() <= max_radius_sq)
+            .map(|edge| {
+                let [a, b] = edge.vertices();
+                (*a.data(), *b.data(), edge.length_2())
+            })
+            .collect()
+    }
 }
 
 type Neighbors = ModeMap<quadtree::NeighborsStore<NodeIndex>>;
-type Triangulations = ModeMap<spade::DelaunayTriangulation<triangulation_ext::TriangulationVertex>>;
 
-pub fn construct_base_graph<F: state::Fields>(
-    input: BaseGraphInput<'_, F>,
-) -> Result<Graph, Error> {
-    use highway::timing::HighwayTiming;
-    use metro::RailwayTiming;
-    use spade::Triangulation;
-    use triangulation_ext::SafeTriangulationInsert;
+/**
+ * Part of the base graph, built independently of the other parts so that they can be built in
+ * parallel. Node indices are local to the part until it is merged into the full graph.
+ */
+#[derive(Debug, Default)]
+struct PartialGraph {
+    nodes: Vec<Node>,
+    edges: Vec<(NodeIndex, NodeIndex, Edge)>,
+    /// nodes from which routes can start and end, with their locations
+    terminal_nodes: Vec<(Mode, NodeIndex, f64, f64)>,
+    /// nodes to put into the triangulation used to infer edges, with their locations
+    inference_nodes: Vec<(Mode, NodeIndex, f64, f64)>,
+    parking: Vec<Parking>,
+    no_parking: Vec<quadtree::Address>,
+}
 
-    let tile_size = input.state.config.min_tile_size as f64;
+impl PartialGraph {
+    fn add_node(&mut self, node: Node) -> NodeIndex {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
 
-    if input.validate_highways {
-        input.state.highways.validate();
+    fn add_edge(&mut self, from: NodeIndex, to: NodeIndex, edge: Edge) {
+        self.edges.push((from, to, edge));
+    }
+
+    fn add_terminal_node(&mut self, mode: Mode, node: NodeIndex, (x, y): (f64, f64)) {
+        self.terminal_nodes.push((mode, node, x, y));
+        self.inference_nodes.push((mode, node, x, y));
     }
 
-    let mut graph = InnerGraph::new();
-    // nodes from which routes can start and end
-    let mut terminal_nodes =
-        ModeMap::new(|_| quadtree::NeighborsStore::new(4, input.state.config.max_depth));
-    // we use a Delaunay triangulation to infer edges based on proximity
-    let mut inference_triangulation = ModeMap::new(|_| spade::DelaunayTriangulation::new());
-    let mut parking = HashMap::new();
-
-    let mut add_parking = |address,
-                           graph: &mut InnerGraph,
-                           terminal_nodes: &mut Neighbors,
-                           inference_triangulation: &mut Triangulations|
-     -> Result<(NodeIndex, NodeIndex), Error> {
-        let walking_node = graph.add_node(Node::Parking { address });
-        let driving_node = graph.add_node(Node::Parking { address });
+    fn add_parking(&mut self, address: quadtree::Address) -> (NodeIndex, NodeIndex) {
+        let walking_node = self.add_node(Node::Parking { address });
+        let driving_node = self.add_node(Node::Parking { address });
         let (x, y) = address.to_xy_f64();
 
         // NOTE: Important that we don't add the driving node to the terminal nodes.
         // This would be invalid since it intentionally has no outgoing edges.
-        terminal_nodes[Mode::Walking].insert(walking_node, x, y)?;
-
-        inference_triangulation[Mode::Walking].safe_insert(walking_node, x, y)?;
-        inference_triangulation[Mode::Driving].safe_insert(driving_node, x, y)?;
+        self.add_terminal_node(Mode::Walking, walking_node, (x, y));
+        self.inference_nodes
+            .push((Mode::Driving, driving_node, x, y));
 
-        parking.insert(
+        self.parking.push(Parking {
             address,
-            Parking {
-                address,
-                walking_node,
-                driving_node,
-            },
-        );
-        graph.add_edge(
+            walking_node,
+            driving_node,
+        });
+        self.add_edge(
             driving_node,
             walking_node,
             Edge::ModeTransition {
@@ -206,11 +304,78 @@ pub fn construct_base_graph<F: state::Fields>(
                 to: Mode::Walking,
                 address,
             },
-            input.state,
         );
 
-        Ok((walking_node, driving_node))
-    };
+        (walking_node, driving_node)
+    }
+}
+
+/// The parts of the base graph that are put together from the partial graphs.
+struct MergedGraph {
+    graph: InnerGraph,
+    terminal_nodes: Neighbors,
+    inference_nodes: ModeMap<Vec<(NodeIndex, f64, f64)>>,
+    parking: HashMap<quadtree::Address, Parking>,
+    no_parking: HashSet<quadtree::Address>,
+}
+
+impl MergedGraph {
+    fn new(max_depth: u32) -> Self {
+        Self {
+            graph: InnerGraph::new(),
+            terminal_nodes: ModeMap::new(|_| quadtree::NeighborsStore::new(4, max_depth)),
+            inference_nodes: ModeMap::new(|_| Vec::new()),
+            parking: HashMap::new(),
+            no_parking: HashSet::new(),
+        }
+    }
+
+    /**
+     * Add everything in the partial graph, offsetting its node indices by the number of nodes that
+     * have been added so far. Merging the same partial graphs in the same order always produces
+     * the same node indices.
+     */
+    fn merge<F: state::Fields>(
+        &mut self,
+        partial: PartialGraph,
+        state: &state::State<F>,
+    ) -> Result<(), Error> {
+        let offset = self.graph.node_count();
+        for node in partial.nodes {
+            self.graph.add_node(node);
+        }
+        for (from, to, edge) in partial.edges {
+            self.graph.add_edge(from + offset, to + offset, edge, state);
+        }
+        for (mode, node, x, y) in partial.terminal_nodes {
+            self.terminal_nodes[mode].insert(node + offset, x, y)?;
+        }
+        for (mode, node, x, y) in partial.inference_nodes {
+            self.inference_nodes[mode].push((node + offset, x, y));
+        }
+        for parking in partial.parking {
+            self.parking.insert(
+                parking.address,
+                Parking {
+                    address: parking.address,
+                    walking_node: parking.walking_node + offset,
+                    driving_node: parking.driving_node + offset,
+                },
+            );
+        }
+        self.no_parking.extend(partial.no_parking);
+        Ok(())
+    }
+}
+
+/// Metro stations and stops, rail junctions, and metro segments, along with parking at stations.
+fn construct_metro_graph<F: state::Fields>(
+    input: &BaseGraphInput<'_, F>,
+    tile_size: f64,
+) -> PartialGraph {
+    use metro::RailwayTiming;
+
+    let mut graph = PartialGraph::default();
 
     let mut station_map = HashMap::new();
     for metro_line in input.state.metros.metro_lines().values() {
@@ -238,41 +403,42 @@ pub fn construct_base_graph<F: state::Fields>(
                             station: station.clone(),
                         });
 
-                        // for now, we assume that every station offers parking
-                        let (parking_walking, _) = add_parking(
-                            station.address,
-                            &mut graph,
-                            &mut terminal_nodes,
-                            &mut inference_triangulation,
-                        )
-                        .unwrap();
-
-                        let location = station.address.to_xy_f64();
-
-                        // NOTE: can't put this node into the inference triangulation because it
-                        // occupies the same point as the parking node.
-                        graph.add_edge(
-                            station_id,
-                            parking_walking,
-                            Edge::ModeSegment {
-                                mode: Mode::Walking,
-                                distance: 0.0,
-                                start: location,
-                                stop: location,
-                            },
-                            input.state,
-                        );
-                        graph.add_edge(
-                            parking_walking,
-                            station_id,
-                            Edge::ModeSegment {
-                                mode: Mode::Walking,
-                                distance: 0.0,
-                                start: location,
-                                stop: location,
-                            },
-                            input.state,
-                        );
+                        if station_has_parking(input.state, station.address) {
+                            let (parking_walking, _) = graph.add_parking(station.address);
+
+                            let location = station.address.to_xy_f64();
+
+                            // NOTE: can't put this node into the inference triangulation because it
+                            // occupies the same point as the parking node.
+                            graph.add_edge(
+                                station_id,
+                                parking_walking,
+                                Edge::ModeSegment {
+                                    mode: Mode::Walking,
+                                    distance: 0.0,
+                                    start: location,
+                                    stop: location,
+                                },
+                            );
+                            graph.add_edge(
+                                parking_walking,
+                                station_id,
+                                Edge::ModeSegment {
+                                    mode: Mode::Walking,
+                                    distance: 0.0,
+                                    start: location,
+                                    stop: location,
+                                },
+                            );
+                        } else {
+                            // without parking, the station node itself is the terminal node
+                            graph.add_terminal_node(
+                                Mode::Walking,
+                                station_id,
+                                station.address.to_xy_f64(),
+                            );
+                            graph.no_parking.push(station.address);
+                        }
 
                         station_id
                     });
@@ -289,7 +455,6 @@ pub fn construct_base_graph<F: state::Fields>(
                             metro_line: metro_line.id,
                             station: station.clone(),
                         },
-                        input.state,
                     );
 
                     graph.add_edge(
@@ -299,7 +464,6 @@ pub fn construct_base_graph<F: state::Fields>(
                             metro_line: metro_line.id,
                             station: station.clone(),
                         },
-                        input.state,
                     );
 
                     (stop_id, station.address)
@@ -339,11 +503,22 @@ pub fn construct_base_graph<F: state::Fields>(
                     start: start_address,
                     stop: end_address,
                 },
-                input.state,
             );
         }
     }
 
+    graph
+}
+
+/// Highway junctions and ramps, and highway segments.
+fn construct_highway_graph<F: state::Fields>(
+    input: &BaseGraphInput<'_, F>,
+    tile_size: f64,
+) -> PartialGraph {
+    use highway::timing::HighwayTiming;
+
+    let mut graph = PartialGraph::default();
+
     let mut junction_map = HashMap::new();
 
     for junction in input.state.highways.junctions().values() {
@@ -370,14 +545,8 @@ pub fn construct_base_graph<F: state::Fields>(
                 highway::RampDirection::OnRamp => (outer_id, inner_id),
                 highway::RampDirection::OffRamp => (inner_id, outer_id),
             };
-            graph.add_edge(
-                first,
-                second,
-                Edge::HighwayRamp { position: (x, y) },
-                input.state,
-            );
-            terminal_nodes[Mode::Driving].insert(outer_id, x, y)?;
-            inference_triangulation[Mode::Driving].safe_insert(outer_id, x, y)?;
+            graph.add_edge(first, second, Edge::HighwayRamp { position: (x, y) });
+            graph.add_terminal_node(Mode::Driving, outer_id, (x, y));
             inner_id
         } else {
             graph.add_node(Node::HighwayJunction {
@@ -438,49 +607,156 @@ pub fn construct_base_graph<F: state::Fields>(
                 data: segment.data.clone(),
                 time: segment.highway_travel_time(tile_size),
             },
-            input.state,
         );
     }
 
+    graph
+}
+
+/**
+ * The edges to infer for one mode, based on proximity. Builds a Delaunay triangulation of the
+ * nodes, which are inserted in order so that the result is deterministic.
+ */
+fn mode_inferred_edges(
+    nodes: &[(NodeIndex, f64, f64)],
+    max_radius_sq: f64,
+) -> Result<
+    Vec<(
+        triangulation_ext::TriangulationVertex,
+        triangulation_ext::TriangulationVertex,
+        f64,
+    )>,
+    Error,
+> {
+    use spade::Triangulation;
+    use triangulation_ext::SafeTriangulationInsert;
+
+    // TODO: use bulk_load instead of a bunch of individual insertions
+    let mut triangulation = spade::DelaunayTriangulation::new();
+    for (node, x, y) in nodes {
+        triangulation.safe_insert(*node, *x, *y)?;
+    }
+    Ok(triangulation_ext::inferred_edges(
+        &triangulation,
+        max_radius_sq,
+    ))
+}
+
+/**
+ * Construct the graph used for routing. The metro and highway parts of the graph are built in
+ * parallel and then merged in a fixed order, as are the edges inferred for each mode, so the graph
+ * is the same regardless of how many threads are available.
+ *
+ * NOTE: This runs on rayon's global thread pool, which every engine in the process shares, unless
+ * it is called from within ThreadPool::install. Since the result doesn't depend on the threads,
+ * engines only affect each other's speed.
+ */
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn construct_base_graph<F: state::Fields>(
+    input: BaseGraphInput<'_, F>,
+) -> Result<Graph, Error> {
+    use rayon::prelude::*;
+
+    let tile_size = input.state.config.min_tile_size as f64;
+
+    if input.validate_highways {
+        input.state.highways.validate();
+    }
+
+    let (metro_graph, highway_graph) = tracing::debug_span!("partial_graphs").in_scope(|| {
+        rayon::join(
+            || construct_metro_graph(&input, tile_size),
+            || construct_highway_graph(&input, tile_size),
+        )
+    });
+
+    let mut merged = MergedGraph::new(input.state.config.max_depth);
+    merged.merge(metro_graph, input.state)?;
+    merged.merge(highway_graph, input.state)?;
+    let MergedGraph {
+        mut graph,
+        terminal_nodes,
+        inference_nodes,
+        parking,
+        no_parking,
+    } = merged;
+
     if input.add_inferred_edges {
-        for mode in MODES {
-            let max_radius_sq = (mode.bridge_radius() / tile_size).powi(2);
-            // TODO: use bulk_load instead of a bunch of individual insertions
-            for edge in inference_triangulation[*mode].undirected_edges() {
-                if edge.length_2() <= max_radius_sq {
-                    let [a, b] = edge.vertices();
-                    for (start, end) in [(a, b), (b, a)] {
-                        let start = start.data();
-                        let end = end.data();
-
-                        graph.add_edge(
-                            start.index(),
-                            end.index(),
-                            Edge::ModeSegment {
-                                mode: *mode,
-                                distance: edge.length_2().sqrt() * tile_size,
-                                start: start.coords(),
-                                stop: start.coords(),
-                            },
-                            input.state,
-                        );
-                    }
+        let inferred_edges = tracing::debug_span!("inferred_edges").in_scope(|| {
+            MODES
+                .par_iter()
+                .map(|mode| {
+                    let max_radius_sq = (mode.bridge_radius() / tile_size).powi(2);
+                    mode_inferred_edges(&inference_nodes[*mode], max_radius_sq)
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        })?;
+
+        for (mode, edges) in MODES.iter().zip(inferred_edges) {
+            for (a, b, length_2) in edges {
+                for (start, end) in [(a, b), (b, a)] {
+                    graph.add_edge(
+                        start.index(),
+                        end.index(),
+                        Edge::ModeSegment {
+                            mode: *mode,
+                            distance: length_2.sqrt() * tile_size,
+                            start: start.coords(),
+                            stop: start.coords(),
+                        },
+                        input.state,
+                    );
                 }
             }
         }
     }
 
-    graph.prepare();
+    tracing::debug_span!("prepare").in_scope(|| graph.prepare());
 
     Ok(Graph {
         graph,
         terminal_nodes,
         parking,
+        no_parking,
         tile_size,
         max_depth: input.state.config.max_depth,
+        version: GraphVersion::default(),
+        traffic_time: None,
     })
 }
 
+#[cfg(test)]
+mod triangulation_tests {
+    use crate::base_graph::triangulation_ext::*;
+    use crate::base_graph::NodeIndex;
+    use spade::Triangulation;
+
+    fn inferred(points: &[(f64, f64)], max_radius: f64) -> Vec<(NodeIndex, NodeIndex)> {
+        let mut triangulation = spade::DelaunayTriangulation::new();
+        for (i, (x, y)) in points.iter().enumerate() {
+            triangulation.safe_insert(i, *x, *y).unwrap();
+        }
+        let mut edges: Vec<_> = inferred_edges(&triangulation, max_radius.powi(2))
+            .into_iter()
+            .map(|(a, b, _)| (a.index().min(b.index()), a.index().max(b.index())))
+            .collect();
+        edges.sort_unstable();
+        edges
+    }
+
+    #[test]
+    fn sparse_inferred_edges() {
+        assert!(inferred(&[], 10.0).is_empty());
+        assert!(inferred(&[(0.0, 0.0)], 10.0).is_empty());
+        assert_eq!(inferred(&[(0.0, 0.0), (3.0, 4.0)], 10.0), vec![(0, 1)]);
+        assert!(inferred(&[(0.0, 0.0), (30.0, 40.0)], 10.0).is_empty());
+        assert_eq!(
+            inferred(&[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)], 10.0),
+            vec![(0, 1), (0, 2), (1, 2)]
+        );
+    }
+}
+
 #[cfg(test)]
 mod highway_tests {
     use crate::base_graph::*;
@@ -513,8 +789,18 @@ mod highway_tests {
 
         let mut state: state::State<DummyFields> = state::State::new(state::Config {
             max_depth: 5,
-            people_per_sim: 1,
+            people_per_sim: 1.0,
             min_tile_size: 1,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
         });
 
         let mut handle_map = HashMap::new();
@@ -14,6 +14,8 @@ pub enum Error {
     EdgeCountingError(String),
     #[error("Parking error: {0}")]
     ParkingError(String),
+    #[error("Walking must be an allowed mode, since it connects the rest of the graph")]
+    WalkingNotAllowed,
 }
 
 /**
@@ -32,6 +34,99 @@ pub struct QueryInput {
     pub start: quadtree::Address,
     pub end: quadtree::Address,
     pub car_config: Option<CarConfig>,
+    #[serde(default)]
+    pub profile: MobilityProfile,
+    #[serde(default)]
+    pub allowed_modes: AllowedModes,
+}
+
+/**
+ * The modes that a route may use. Metro lines are always allowed, since they are reached on foot,
+ * so excluding driving leaves routes that only walk and take transit. The car config of a query is
+ * ignored if driving is excluded.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowedModes {
+    modes: ModeMap<bool>,
+}
+
+impl AllowedModes {
+    pub const ALL: Self = Self {
+        modes: ModeMap {
+            data: [true, true, true],
+        },
+    };
+
+    /// Walking and metro lines only.
+    pub fn transit_only() -> Self {
+        Self::ALL.with(Mode::Driving, false)
+    }
+
+    pub fn with(mut self, mode: Mode, allowed: bool) -> Self {
+        self.modes[mode] = allowed;
+        self
+    }
+
+    pub fn contains(&self, mode: Mode) -> bool {
+        self.modes[mode]
+    }
+
+    pub fn set(&mut self, mode: Mode, allowed: bool) {
+        self.modes[mode] = allowed;
+    }
+
+    /// Walking can't be excluded, because every route starts and ends on foot.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.contains(Mode::Walking) {
+            Ok(())
+        } else {
+            Err(Error::WalkingNotAllowed)
+        }
+    }
+}
+
+impl Default for AllowedModes {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/**
+ * How quickly the querying agent gets around, relative to the standard speeds given by
+ * Mode::linear_speed. This is per-query so that agents can walk at different speeds, and so that
+ * isochrones can be calculated for people with reduced mobility.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MobilityProfile {
+    /// multiplier for the walking speed, e.g. 0.5 to walk at half the standard speed
+    pub walking_speed: f64,
+    /// extra time for each transfer, i.e. boarding a metro or changing modes, in seconds
+    pub transfer_penalty: f64,
+}
+
+impl MobilityProfile {
+    pub const STANDARD: Self = Self {
+        walking_speed: 1.0,
+        transfer_penalty: 0.0,
+    };
+
+    pub fn is_standard(&self) -> bool {
+        *self == Self::STANDARD
+    }
+
+    /// Average speed for this profile, in m/s.
+    pub fn linear_speed(&self, mode: Mode) -> f64 {
+        match mode {
+            Mode::Walking => mode.linear_speed() * self.walking_speed,
+            _ => mode.linear_speed(),
+        }
+    }
+}
+
+impl Default for MobilityProfile {
+    fn default() -> Self {
+        Self::STANDARD
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
@@ -83,7 +178,7 @@ impl std::fmt::Display for Mode {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct ModeMap<T> {
     data: [T; 3],
 }
@@ -130,4 +225,16 @@ mod tests {
         assert_eq!(map[Mode::Biking], 1);
         assert_eq!(map[Mode::Driving], 2);
     }
+
+    #[test]
+    fn allowed_modes() {
+        let transit_only = AllowedModes::transit_only();
+        assert!(transit_only.contains(Mode::Walking));
+        assert!(!transit_only.contains(Mode::Driving));
+        assert!(transit_only.validate().is_ok());
+        assert_eq!(
+            AllowedModes::ALL.with(Mode::Walking, false).validate(),
+            Err(Error::WalkingNotAllowed)
+        );
+    }
 }
@@ -1,12 +1,56 @@
 use serde::{Deserialize, Serialize};
 
-use crate::common::Mode;
+use crate::common::{MobilityProfile, Mode};
 use crate::node::Node;
 use crate::traffic::WorldState;
 
 // time it takes to enter or leave a highway
 pub const RAMP_TIME: f64 = 30.0;
 
+/**
+ * The parts that make up the cost of traversing an edge, in seconds. There is no monetary cost
+ * (e.g. fares or tolls) yet, so the cost is all time.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CostComponents {
+    /// the time spent moving along the edge or waiting for a train, including congestion
+    pub travel_time: f64,
+    /// what travel_time would be without any congestion
+    pub base_time: f64,
+    /// how many times slower congestion makes the edge, before capping at MAX_CONGESTED_TIME
+    pub congestion_factor: f64,
+    /// the time spent looking for parking after driving
+    pub parking_search: f64,
+    /// the time spent at intersections on local roads
+    #[serde(default)]
+    pub intersection_delay: f64,
+}
+
+impl CostComponents {
+    fn uncongested(time: f64) -> Self {
+        Self {
+            travel_time: time,
+            base_time: time,
+            congestion_factor: 1.0,
+            parking_search: 0.0,
+            intersection_delay: 0.0,
+        }
+    }
+
+    /// The extra time spent due to congestion.
+    pub fn congestion_delay(&self) -> f64 {
+        self.travel_time - self.base_time
+    }
+
+    /// The cost of the edge, as used by the router. Every edge costs at least one second.
+    pub fn total(&self) -> f64 {
+        f64::max(
+            self.travel_time + self.parking_search + self.intersection_delay,
+            1.0,
+        )
+    }
+}
+
 #[non_exhaustive]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Edge {
@@ -58,12 +102,27 @@ impl Edge {
                 metro_line: metro_line_id,
                 ..
             } => {
-                let metro_line = state.metros.metro_line(*metro_line_id);
-                metro_line.data.schedule.expected_waiting_time() as f64
+                // a line that was removed since the route was planned has nothing to wait for
+                state
+                    .metros
+                    .try_metro_line(*metro_line_id)
+                    .map_or(0.0, |metro_line| {
+                        metro_line.data.schedule.expected_waiting_time() as f64
+                    })
             }
             MetroDisembark { .. } => 0.0,
             Highway { time, .. } => *time,
             HighwayRamp { .. } => RAMP_TIME,
+            ModeSegment {
+                mode: Mode::Driving,
+                distance,
+                start,
+                stop,
+            } => {
+                // intersections aren't congestion, so they slow down even an empty road
+                distance / Mode::Driving.linear_speed()
+                    + crate::local_traffic::intersection_delay(state, *start, *stop)
+            }
             ModeSegment { mode, distance, .. } => distance / mode.linear_speed(),
             ModeTransition { .. } => 0.0,
         };
@@ -81,15 +140,33 @@ impl Edge {
         state: &state::State<F>,
         current_time: Option<u64>,
     ) -> f64 {
+        self.cost_components(world_state, state, current_time)
+            .total()
+    }
+
+    /**
+     * The same as cost, but broken down into the parts that make it up. This is what the router
+     * uses to weight edges, so it can be used to explain why a particular route was chosen.
+     */
+    pub fn cost_components<W: WorldState, F: state::Fields>(
+        &self,
+        world_state: &W,
+        state: &state::State<F>,
+        current_time: Option<u64>,
+    ) -> CostComponents {
         use Edge::*;
-        let cost = match self {
-            MetroSegment { time, .. } => *time,
+        match self {
+            MetroSegment { time, .. } => CostComponents::uncongested(*time),
             MetroEmbark {
                 metro_line: metro_line_id,
                 ..
             } => {
-                let metro_line = state.metros.metro_line(*metro_line_id);
-                match current_time {
+                let metro_line = match state.metros.try_metro_line(*metro_line_id) {
+                    Some(metro_line) => metro_line,
+                    // removed since the route was planned, so there is nothing to wait for
+                    None => return CostComponents::uncongested(0.0),
+                };
+                let waiting_time = match current_time {
                     None => metro_line.data.schedule.expected_waiting_time() as f64,
                     Some(_current_time) => {
                         // let current_time_f64 = current_time as f64;
@@ -116,25 +193,43 @@ impl Edge {
                         // TODO: re-implement the new way
                         metro_line.data.schedule.expected_waiting_time() as f64
                     }
-                }
+                };
+                CostComponents::uncongested(waiting_time)
             }
-            MetroDisembark { .. } => 0.0,
+            MetroDisembark { .. } => CostComponents::uncongested(0.0),
             Highway {
                 segment: segment_id,
+                time,
                 ..
             } => {
                 use highway::timing::HighwayTiming;
 
                 let travelers = world_state.get_highway_segment_travelers(*segment_id);
-                let segment = state.highways.segment(*segment_id);
+                let segment = match state.highways.try_segment(*segment_id) {
+                    Some(segment) => segment,
+                    // removed since the route was planned, so keep the time it was planned with
+                    None => return CostComponents::uncongested(*time),
+                };
 
-                segment.congested_travel_time(
+                let base_time = segment.highway_travel_time(state.config.min_tile_size as f64);
+                let congestion_factor = segment.congested_travel_factor(
                     state.config.min_tile_size,
                     state.config.people_per_sim,
                     travelers,
-                )
+                );
+                CostComponents {
+                    travel_time: segment.congested_travel_time(
+                        state.config.min_tile_size,
+                        state.config.people_per_sim,
+                        travelers,
+                    ),
+                    base_time,
+                    congestion_factor,
+                    parking_search: 0.0,
+                    intersection_delay: 0.0,
+                }
             }
-            HighwayRamp { .. } => RAMP_TIME,
+            HighwayRamp { .. } => CostComponents::uncongested(RAMP_TIME),
             ModeSegment {
                 mode,
                 distance,
@@ -156,14 +251,39 @@ impl Edge {
                             "{}",
                             travel_time
                         );
-                        travel_time
+                        CostComponents {
+                            travel_time,
+                            base_time: base_travel_time,
+                            congestion_factor: crate::local_traffic::congested_travel_factor(
+                                &state.config,
+                                travelers,
+                            ),
+                            parking_search: 0.0,
+                            intersection_delay: crate::local_traffic::intersection_delay(
+                                state, *start, *stop,
+                            ),
+                        }
                     }
-                    _ => base_travel_time,
+                    _ => CostComponents::uncongested(base_travel_time),
                 }
             }
-            ModeTransition { .. } => 0.0,
-        };
-        f64::max(cost, 1.0)
+            ModeTransition {
+                from: Mode::Driving,
+                to: Mode::Walking,
+                address,
+            } => {
+                let (x, y) = address.to_xy_f64();
+                let parked = world_state.get_parking(x, y);
+                CostComponents {
+                    parking_search: crate::local_traffic::parking_search_time(
+                        &state.config,
+                        parked,
+                    ),
+                    ..CostComponents::uncongested(0.0)
+                }
+            }
+            ModeTransition { .. } => CostComponents::uncongested(0.0),
+        }
     }
 
     /**
@@ -184,8 +304,14 @@ impl Edge {
             } => {
                 use metro::RailwayTiming;
 
-                let metro_line = state.metros.metro_line(*metro_line_id);
-                let segment = state.railways.segment(oriented_segment.segment);
+                let (metro_line, segment) = match (
+                    state.metros.try_metro_line(*metro_line_id),
+                    state.railways.try_segment(oriented_segment.segment),
+                ) {
+                    (Some(metro_line), Some(segment)) => (metro_line, segment),
+                    // removed since the route was planned, so go straight across
+                    _ => return straight_position(pred, succ, fraction),
+                };
                 let dist = segment
                     .railway_dist_spline(
                         metro_line.data.speed_limit,
@@ -202,7 +328,10 @@ impl Edge {
                 (position.x as f32, position.y as f32)
             }
             Edge::Highway { segment, .. } => {
-                let segment = state.highways.segment(*segment);
+                let segment = match state.highways.try_segment(*segment) {
+                    Some(segment) => segment,
+                    None => return straight_position(pred, succ, fraction),
+                };
 
                 let position = segment
                     .spline()
@@ -210,12 +339,7 @@ impl Edge {
                     .expect("highway spline is empty");
                 (position.x as f32, position.y as f32)
             }
-            Edge::ModeSegment { .. } => {
-                use cgmath::VectorSpace;
-                cgmath::Vector2::from(pred.location_f32())
-                    .lerp(succ.location_f32().into(), fraction)
-                    .into()
-            }
+            Edge::ModeSegment { .. } => straight_position(pred, succ, fraction),
             Edge::MetroEmbark { .. }
             | Edge::MetroDisembark { .. }
             | Edge::ModeTransition { .. }
@@ -223,6 +347,23 @@ impl Edge {
         }
     }
 
+    /**
+     * Adjust a cost for this edge, calculated for the standard mobility profile, to the given
+     * profile. Only walking and transfers depend on the profile.
+     */
+    pub fn profile_cost(&self, cost: f64, profile: &MobilityProfile) -> f64 {
+        match self {
+            Edge::ModeSegment {
+                mode: Mode::Walking,
+                ..
+            } => cost / profile.walking_speed,
+            Edge::MetroEmbark { .. } | Edge::ModeTransition { .. } => {
+                cost + profile.transfer_penalty
+            }
+            _ => cost,
+        }
+    }
+
     /**
      * If this edge changes the mode of travel, the new mode. Otherwise, None.
      */
@@ -246,12 +387,17 @@ impl Edge {
                 use highway::timing::HighwayTiming;
 
                 let travelers = world_state.get_highway_segment_travelers(*segment_id);
-                let segment = state.highways.segment(*segment_id);
-                segment.is_jammed(
-                    state.config.min_tile_size,
-                    state.config.people_per_sim,
-                    travelers,
-                )
+                // a segment that was removed since the route was planned has no traffic
+                state
+                    .highways
+                    .try_segment(*segment_id)
+                    .map_or(false, |segment| {
+                        segment.is_jammed(
+                            state.config.min_tile_size,
+                            state.config.people_per_sim,
+                            travelers,
+                        )
+                    })
             }
             Edge::ModeSegment {
                 mode: Mode::Driving,
@@ -267,6 +413,14 @@ impl Edge {
     }
 }
 
+/// The position the given fraction of the way along a straight line between the nodes.
+fn straight_position(pred: &Node, succ: &Node, fraction: f32) -> (f32, f32) {
+    use cgmath::VectorSpace;
+    cgmath::Vector2::from(pred.location_f32())
+        .lerp(succ.location_f32().into(), fraction)
+        .into()
+}
+
 impl std::fmt::Display for Edge {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use Edge::*;
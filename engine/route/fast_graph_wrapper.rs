@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap};
 
 use fast_paths::{
     FastGraph, InputGraph, NodeId, Params, ParamsWithOrder, PathCalculator, ShortestPath, Weight,
@@ -17,6 +17,9 @@ pub struct FastGraphWrapper {
     edge_map: HashMap<(NodeId, NodeId), Edge>,
     fast_graph: Option<FastGraph>,
     node_ordering: Option<Vec<NodeId>>,
+    /// outgoing edges for each node with the current weights, used for one-to-many searches
+    #[derivative(Debug = "ignore")]
+    adjacency: Vec<Vec<(NodeId, Weight)>>,
     #[derivative(Debug = "ignore")]
     path_calculator: Option<PathCalculator>,
 }
@@ -29,11 +32,40 @@ impl Clone for FastGraphWrapper {
             edge_map: self.edge_map.clone(),
             fast_graph: self.fast_graph.clone(),
             node_ordering: self.node_ordering.clone(),
+            adjacency: self.adjacency.clone(),
             path_calculator: self.fast_graph.as_ref().map(fast_paths::create_calculator),
         }
     }
 }
 
+/**
+ * Counts the input graph, the maps from node and edge IDs to the nodes and edges, the adjacency
+ * lists, and the prepared contraction hierarchy, but not the working memory of the path calculator
+ * or anything owned by the nodes and edges themselves.
+ */
+impl memory_size::MemorySize for FastGraphWrapper {
+    fn heap_bytes(&self) -> usize {
+        use memory_size::{hash_map_bytes, vec_bytes};
+        use std::mem::size_of;
+
+        let input = self.input.get_num_edges() * size_of::<(NodeId, NodeId, Weight)>();
+        let adjacency =
+            vec_bytes(&self.adjacency) + self.adjacency.iter().map(vec_bytes).sum::<usize>();
+        // ranks and the first edge of each node in each direction
+        let prepared = self.fast_graph.as_ref().map_or(0, |fast_graph| {
+            (fast_graph.get_num_out_edges() + fast_graph.get_num_in_edges())
+                * size_of::<fast_paths::FastGraphEdge>()
+                + fast_graph.get_num_nodes() * 3 * size_of::<usize>()
+        });
+        input
+            + hash_map_bytes(&self.node_map)
+            + hash_map_bytes(&self.edge_map)
+            + self.node_ordering.as_ref().map_or(0, vec_bytes)
+            + adjacency
+            + prepared
+    }
+}
+
 impl Default for FastGraphWrapper {
     fn default() -> Self {
         Self::new()
@@ -48,6 +80,7 @@ impl FastGraphWrapper {
             edge_map: HashMap::new(),
             fast_graph: None,
             node_ordering: None,
+            adjacency: Vec::new(),
             path_calculator: None,
         }
     }
@@ -60,6 +93,7 @@ impl FastGraphWrapper {
         assert!(!self.is_prepared());
         let id = self.node_map.len();
         self.node_map.insert(id, node);
+        self.adjacency.push(Vec::new());
         id
     }
 
@@ -77,6 +111,7 @@ impl FastGraphWrapper {
         let weight = edge.base_cost(state) as Weight;
         assert!(weight > 0, "base weight for {} -> {} is 0", from, to);
         self.input.add_edge(from, to, weight);
+        self.adjacency[from].push((to, weight));
         self.edge_map.insert((from, to), edge);
     }
 
@@ -114,15 +149,17 @@ impl FastGraphWrapper {
         &self,
         world_state: &W,
         state: &state::State<F>,
-    ) -> InputGraph {
+    ) -> (InputGraph, Vec<Vec<(NodeId, Weight)>>) {
         let mut input_graph = InputGraph::new();
+        let mut adjacency = vec![Vec::new(); self.node_map.len()];
         for ((from, to), edge) in self.edge_map.iter() {
             let weight = edge.cost(world_state, state, None) as Weight;
             assert!(weight > 0, "weight for {} -> {} is 0", from, to);
             input_graph.add_edge(*from, *to, weight);
+            adjacency[*from].push((*to, weight));
         }
         input_graph.freeze();
-        input_graph
+        (input_graph, adjacency)
     }
 
     pub fn update_weights<W: WorldState, F: state::Fields>(
@@ -134,7 +171,7 @@ impl FastGraphWrapper {
         // TODO: Patch fast_paths to allow mutating the edge weights instead of constructing a new graph.
         // This might be nontrivial, but it seems like a worthwhile optimization.
         // At the very least, we should be able to pre-specify the number of nodes in the InputGraph.
-        let input_graph = self.create_input(world_state, state);
+        let (input_graph, adjacency) = self.create_input(world_state, state);
         assert_eq!(
             input_graph.get_num_nodes(),
             self.node_ordering.as_ref().unwrap().len()
@@ -148,6 +185,7 @@ impl FastGraphWrapper {
         .unwrap();
         let path_calculator = fast_paths::create_calculator(&fast_graph);
         self.fast_graph = Some(fast_graph);
+        self.adjacency = adjacency;
         self.path_calculator = Some(path_calculator);
     }
 
@@ -164,7 +202,7 @@ impl FastGraphWrapper {
         E: From<Error> + Send + 'static,
     {
         assert!(self.is_prepared());
-        let input_graph = self.create_input(world_state, state);
+        let (input_graph, adjacency) = self.create_input(world_state, state);
         assert_eq!(
             input_graph.get_num_nodes(),
             self.node_ordering.as_ref().unwrap().len()
@@ -179,7 +217,7 @@ impl FastGraphWrapper {
 
         thread_pool.execute(move || {
             let fast_graph = fast_paths::prepare_with_order_with_params(
-                &input,
+                &input_graph,
                 &node_ordering,
                 &Self::get_update_params(),
             )
@@ -192,6 +230,7 @@ impl FastGraphWrapper {
                     edge_map,
                     fast_graph: Some(fast_graph),
                     node_ordering: Some(node_ordering),
+                    adjacency,
                     path_calculator: Some(path_calculator),
                 },
                 version,
@@ -208,6 +247,112 @@ impl FastGraphWrapper {
         let path_calculator = self.path_calculator.as_mut().unwrap();
         path_calculator.calc_path(fast_graph, source, target)
     }
+
+    /**
+     * Like `query`, but finds the shortest path from any of `sources` to any of `targets`. Each
+     * source and target comes with an additional weight, e.g. the cost of getting to the source
+     * from the actual start of the route, which is included in the weight of the path.
+     */
+    pub fn query_multiple(
+        &mut self,
+        sources: Vec<(NodeId, Weight)>,
+        targets: Vec<(NodeId, Weight)>,
+    ) -> Option<ShortestPath> {
+        assert!(self.is_prepared());
+        let fast_graph = &self.fast_graph.as_ref().unwrap();
+        let path_calculator = self.path_calculator.as_mut().unwrap();
+        path_calculator.calc_path_multiple_sources_and_targets(fast_graph, sources, targets)
+    }
+
+    /**
+     * Find the cost of the shortest path from `source` to every node reachable from it, using the
+     * same weights as `query`. If `max_weight` is specified, the search stops once it reaches
+     * nodes that are farther away, and these nodes are omitted from the result.
+     *
+     * This is a plain Dijkstra search, so it is much slower than `query` for a single target, but
+     * much faster than calling `query` for each of many targets.
+     */
+    pub fn query_all(&self, source: NodeId, max_weight: Option<Weight>) -> HashMap<NodeId, Weight> {
+        self.query_all_with(source, max_weight, |_, _, weight| weight)
+    }
+
+    /**
+     * Like `query_all`, but the weight of each edge is first passed through `adjust`, along with
+     * the nodes at either end of the edge. This makes it possible to search with different
+     * weights, e.g. for a slower walking speed, without preparing the graph again.
+     */
+    pub fn query_all_with<A>(
+        &self,
+        source: NodeId,
+        max_weight: Option<Weight>,
+        adjust: A,
+    ) -> HashMap<NodeId, Weight>
+    where
+        A: Fn(NodeId, NodeId, Weight) -> Weight,
+    {
+        use std::cmp::Reverse;
+
+        assert!(self.is_prepared());
+        let max_weight = max_weight.unwrap_or(Weight::MAX);
+
+        let mut settled = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse((0, source)));
+
+        while let Some(Reverse((weight, node))) = queue.pop() {
+            if weight > max_weight || settled.contains_key(&node) {
+                continue;
+            }
+            settled.insert(node, weight);
+            for (next, edge_weight) in &self.adjacency[node] {
+                if !settled.contains_key(next) {
+                    let edge_weight = adjust(node, *next, *edge_weight);
+                    queue.push(Reverse((weight.saturating_add(edge_weight), *next)));
+                }
+            }
+        }
+
+        settled
+    }
+
+    /**
+     * Like `query_all`, but searches outward from all of `sources` at once, each starting with an
+     * additional weight. Each reachable node maps to its cost and the source it is reached from.
+     */
+    pub fn query_all_multiple(
+        &self,
+        sources: &[(NodeId, Weight)],
+        max_weight: Option<Weight>,
+    ) -> HashMap<NodeId, (Weight, NodeId)> {
+        use std::cmp::Reverse;
+
+        assert!(self.is_prepared());
+        let max_weight = max_weight.unwrap_or(Weight::MAX);
+
+        let mut settled = HashMap::new();
+        let mut queue: BinaryHeap<_> = sources
+            .iter()
+            .map(|(source, weight)| Reverse((*weight, *source, *source)))
+            .collect();
+
+        while let Some(Reverse((weight, node, source))) = queue.pop() {
+            if weight > max_weight || settled.contains_key(&node) {
+                continue;
+            }
+            settled.insert(node, (weight, source));
+            for (next, edge_weight) in &self.adjacency[node] {
+                if !settled.contains_key(next) {
+                    queue.push(Reverse((
+                        weight.saturating_add(*edge_weight),
+                        *next,
+                        source,
+                    )));
+                }
+            }
+        }
+
+        settled
+    }
 }
 
 // for compatibility with petgraph
@@ -224,6 +369,15 @@ impl FastGraphWrapper {
         self.edge_map.get(&edge)
     }
 
+    /// The weight of the edge that is currently used for queries.
+    pub fn edge_cost(&self, from: NodeId, to: NodeId) -> Option<Weight> {
+        self.adjacency
+            .get(from)?
+            .iter()
+            .find(|(next, _)| *next == to)
+            .map(|(_, weight)| *weight)
+    }
+
     pub fn node_count(&self) -> usize {
         self.node_map.len()
     }
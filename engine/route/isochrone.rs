@@ -1,48 +1,214 @@
 use std::collections::HashMap;
 
-use crate::base_graph::Graph;
-use crate::common::{Error, Mode};
+use fast_paths::Weight;
+
+use crate::base_graph::{Graph, GraphVersion};
+use crate::common::{Error, MobilityProfile, Mode};
 
 pub struct Isochrone {
     pub travel_times: HashMap<(u64, u64), f64>,
     pub focus: quadtree::Address,
     pub mode: Mode,
+    pub profile: MobilityProfile,
+    pub graph_version: GraphVersion,
+}
+
+impl Isochrone {
+    /**
+     * The longest travel time to any reachable terminal node, in seconds, or None if nothing is
+     * reachable. Travel times beyond this are only due to traveling away from the network.
+     */
+    pub fn max_reachable_travel_time(&self) -> Option<f64> {
+        self.travel_times
+            .values()
+            .copied()
+            .filter(|travel_time| travel_time.is_finite())
+            .max_by(|a, b| a.partial_cmp(b).unwrap())
+    }
+
+    /// The number of terminal nodes that can be reached within the travel time, in seconds.
+    pub fn reachable_within(&self, travel_time: f64) -> usize {
+        self.travel_times
+            .values()
+            .filter(|time| **time <= travel_time)
+            .count()
+    }
+}
+
+/**
+ * The outcome of an isochrone query. A focus that can't be routed from, such as a lake or a corner
+ * of the map far away from any network, is not an error, so that callers can offer to re-run the
+ * query from the nearest point that can be routed from instead.
+ */
+#[derive(Debug)]
+pub enum IsochroneResult<T> {
+    Calculated(T),
+    FocusNotRoutable {
+        focus: quadtree::Address,
+        mode: Mode,
+        /// the nearest point that an isochrone can be calculated from
+        nearest: quadtree::Address,
+        /// the distance from the focus to the nearest routable point, in meters
+        distance: f64,
+    },
+}
+
+impl<T> IsochroneResult<T> {
+    pub fn try_map<U, E, F>(self, f: F) -> Result<IsochroneResult<U>, E>
+    where
+        F: FnOnce(T) -> Result<U, E>,
+    {
+        Ok(match self {
+            Self::Calculated(value) => IsochroneResult::Calculated(f(value)?),
+            Self::FocusNotRoutable {
+                focus,
+                mode,
+                nearest,
+                distance,
+            } => IsochroneResult::FocusNotRoutable {
+                focus,
+                mode,
+                nearest,
+                distance,
+            },
+        })
+    }
+}
+
+/// how many of the nearest terminal nodes to consider when looking for one that isn't in the water
+const NEAREST_CANDIDATES: usize = 8;
+
+fn is_water<F: state::Fields>(state: &state::State<F>, address: quadtree::Address) -> bool {
+    matches!(
+        state.qtree.get_leaf(address).map(|leaf| &leaf.tile),
+        Ok(tiles::Tile::WaterTile(_))
+    )
 }
 
-pub fn calculate_isochrone(
-    mut base_graph: std::cell::RefMut<Graph>,
+#[tracing::instrument(level = "debug", skip(base_graph, state), fields(focus = ?focus.to_xy()))]
+pub fn calculate_isochrone<F: state::Fields>(
+    base_graph: std::cell::RefMut<Graph>,
+    state: &state::State<F>,
     focus: quadtree::Address,
     mode: Mode,
-) -> Result<Isochrone, Error> {
-    // TODO: This is a very naive implementation, simply calculating the shortest path for each
-    // possible destination. Fortunately the networks here are very simple, so this is tractable.
-    // A superior implementation would require modifying fast_paths.
-    // See https://github.com/easbar/fast_paths/issues/5.
+    profile: MobilityProfile,
+) -> Result<IsochroneResult<Isochrone>, Error> {
+    use cgmath::MetricSpace;
+
+    let (start_x, start_y) = focus.to_xy_f64();
+    // NOTE: terminal nodes on bridges are in the water, so they can't be used as a focus either
+    let nearest = base_graph.terminal_nodes[mode]
+        .find_nearest_k(start_x, start_y, NEAREST_CANDIDATES)
+        .into_iter()
+        .find_map(|node| {
+            let (x, y) = base_graph.graph.node_weight(node)?.location();
+            let address = state.qtree.get_address(x as u64, y as u64).ok()?;
+            (!is_water(state, address)).then_some((node, address, (x, y)))
+        });
+    let (nearest, nearest_address, location) = match nearest {
+        Some(nearest) => nearest,
+        // with no network for this mode at all, e.g. on a walking-only map, everything is reached
+        // directly from the focus, which the isochrone map fills in around it
+        None if base_graph.terminal_nodes[mode].count() == 0 && !is_water(state, focus) => {
+            return Ok(IsochroneResult::Calculated(Isochrone {
+                travel_times: HashMap::from([(focus.to_xy(), 0.0)]),
+                focus,
+                mode,
+                profile,
+                graph_version: base_graph.version,
+            }));
+        }
+        None => return Err(Error::NoTerminalNodeFound(focus)),
+    };
+
+    let distance =
+        cgmath::Vector2::from(location).distance((start_x, start_y).into()) * base_graph.tile_size;
+    if is_water(state, focus) || distance > mode.bridge_radius() {
+        return Ok(IsochroneResult::FocusNotRoutable {
+            focus,
+            mode,
+            nearest: nearest_address,
+            distance,
+        });
+    }
 
     let mut isochrone = Isochrone {
         travel_times: HashMap::new(),
         focus,
         mode,
+        profile,
+        graph_version: base_graph.version,
     };
 
-    let (start_x, start_y) = focus.to_xy_f64();
-    let nearest = match base_graph.terminal_nodes[mode].find_nearest(start_x, start_y) {
-        Some(nearest) => nearest,
-        None => return Err(Error::NoTerminalNodeFound(focus)),
+    // a single search from the focus reaches every terminal node at once
+    let graph = &base_graph.graph;
+    let node_costs = if profile.is_standard() {
+        graph.query_all(nearest, None)
+    } else {
+        graph.query_all_with(nearest, None, |from, to, weight| {
+            match graph.edge_weight((from, to)) {
+                Some(edge) => edge.profile_cost(weight as f64, &profile).ceil() as Weight,
+                None => weight,
+            }
+        })
     };
 
-    // TODO: using this clone to avoid borrowing issues, but it shouldn't be necessary
-    for entry in base_graph.terminal_nodes[mode].entries().clone() {
+    for entry in base_graph.terminal_nodes[mode].entries() {
         // f64 is unhashable
         let (x, y) = (entry.x as u64, entry.y as u64);
-        let travel_time = match base_graph.graph.query(nearest, entry.data) {
-            Some(shortest_path) => shortest_path.get_weight() as f64,
+        let travel_time = match node_costs.get(&entry.data) {
+            Some(weight) => *weight as f64,
             None => f64::INFINITY,
         };
         isochrone.travel_times.insert((x, y), travel_time);
     }
 
-    Ok(isochrone)
+    Ok(IsochroneResult::Calculated(isochrone))
+}
+
+/// The location of each reachable node and the travel time to it, in seconds.
+pub type NodeTravelTimes = Vec<((f64, f64), f64)>;
+
+/**
+ * Travel times (in seconds) from the terminal node nearest to the focus to every node that can be
+ * reached within `max_travel_time` using only `mode`, keyed by the location of each node. Unlike
+ * calculate_isochrone, this never takes a shortcut through another mode, so e.g. a walking search
+ * from a station doesn't ride the metro.
+ */
+pub fn calculate_mode_only_travel_times(
+    base_graph: &Graph,
+    focus: quadtree::Address,
+    mode: Mode,
+    max_travel_time: f64,
+) -> Result<NodeTravelTimes, Error> {
+    use crate::edge::Edge;
+
+    let (x, y) = focus.to_xy_f64();
+    let start = match base_graph.terminal_nodes[mode].find_nearest(x, y) {
+        Some(start) => start,
+        None => return Err(Error::NoTerminalNodeFound(focus)),
+    };
+
+    let graph = &base_graph.graph;
+    let node_costs = graph.query_all_with(
+        start,
+        Some(max_travel_time.floor() as Weight),
+        |from, to, weight| match graph.edge_weight((from, to)) {
+            Some(Edge::ModeSegment {
+                mode: edge_mode, ..
+            }) if *edge_mode == mode => weight,
+            // saturates, so that the edge is never taken
+            _ => Weight::MAX,
+        },
+    );
+
+    Ok(node_costs
+        .into_iter()
+        .filter_map(|(node, weight)| {
+            let node = graph.node_weight(node)?;
+            Some((node.location(), weight as f64))
+        })
+        .collect())
 }
 
 pub struct IsochroneMap {
@@ -53,6 +219,11 @@ pub struct IsochroneMap {
 }
 
 impl IsochroneMap {
+    /// See Isochrone::graph_version.
+    pub fn graph_version(&self) -> GraphVersion {
+        self.isochrone.graph_version
+    }
+
     pub fn get_travel_time_sq(&self, x: u64, y: u64) -> f64 {
         let (x, y) = ((x / self.downsample) as u32, (y / self.downsample) as u32);
         let pixel = self.map.get_pixel_checked(x, y);
@@ -67,6 +238,61 @@ impl IsochroneMap {
         // individual positions, such that pre-calculating all values will not be worthwhile.
         self.get_travel_time_sq(x, y).sqrt()
     }
+
+    /**
+     * A view of this map with travel times quantized to multiples of `step`, for drawing the map in
+     * discrete bands. Both `step` and `max` are in seconds.
+     */
+    pub fn quantized(&self, step: f64, max: f64) -> QuantizedIsochroneMap<'_> {
+        assert!(step > 0.0, "step must be positive");
+        QuantizedIsochroneMap {
+            isochrone_map: self,
+            step,
+            max,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuantizedTravelTime {
+    /// the travel time rounded down to a multiple of the step, in seconds
+    Within(f64),
+    /// reachable, but not within the max travel time
+    BeyondMax,
+    /// not reachable at all, e.g. because the network doesn't go anywhere near
+    Unreachable,
+}
+
+fn quantize(travel_time: f64, step: f64, max: f64) -> QuantizedTravelTime {
+    if !travel_time.is_finite() {
+        QuantizedTravelTime::Unreachable
+    } else if travel_time > max {
+        QuantizedTravelTime::BeyondMax
+    } else {
+        // adding the 1.0 allows including times that are at the threshold
+        QuantizedTravelTime::Within(((travel_time / step + 1.0).floor() - 1.0) * step)
+    }
+}
+
+pub struct QuantizedIsochroneMap<'a> {
+    isochrone_map: &'a IsochroneMap,
+    step: f64,
+    max: f64,
+}
+
+impl<'a> QuantizedIsochroneMap<'a> {
+    pub fn get_travel_time(&self, x: u64, y: u64) -> QuantizedTravelTime {
+        quantize(
+            self.isochrone_map.get_travel_time(x, y),
+            self.step,
+            self.max,
+        )
+    }
+
+    /// The max travel time, in seconds.
+    pub fn max(&self) -> f64 {
+        self.max
+    }
 }
 
 pub fn calculate_isochrone_map(
@@ -80,7 +306,7 @@ pub fn calculate_isochrone_map(
 
     // we need to scale up so that each pixel corresponds to one second
     let meters_per_pixel = config.min_tile_size as u64 * downsample;
-    let scale_factor = isochrone.mode.linear_speed() / meters_per_pixel as f64;
+    let scale_factor = isochrone.profile.linear_speed(isochrone.mode) / meters_per_pixel as f64;
 
     let mut raw_map: imageproc::definitions::Image<image::Luma<f64>> =
         image::ImageBuffer::new(dim, dim);
@@ -108,3 +334,28 @@ pub fn calculate_isochrone_map(
         scale_factor,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::isochrone::*;
+
+    #[test]
+    fn quantize_test() {
+        use QuantizedTravelTime::*;
+
+        let step = 20.0;
+        let max = 50.0;
+        assert_eq!(quantize(0.0, step, max), Within(0.0));
+        assert_eq!(quantize(19.9, step, max), Within(0.0));
+        // times at a threshold are included in the step that starts there
+        assert_eq!(quantize(20.0, step, max), Within(20.0));
+        assert_eq!(quantize(39.9, step, max), Within(20.0));
+        assert_eq!(quantize(40.0, step, max), Within(40.0));
+        // the max doesn't need to be a multiple of the step, and is itself included
+        assert_eq!(quantize(50.0, step, max), Within(40.0));
+        assert_eq!(quantize(50.1, step, max), BeyondMax);
+        assert_eq!(quantize(1e9, step, max), BeyondMax);
+        assert_eq!(quantize(f64::INFINITY, step, max), Unreachable);
+        assert_eq!(quantize(f64::NAN, step, max), Unreachable);
+    }
+}
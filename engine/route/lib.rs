@@ -6,22 +6,32 @@ mod isochrone;
 pub mod local_traffic;
 mod node;
 mod query;
+mod reliability;
 mod route;
+mod route_debug;
 mod route_key;
 mod traffic;
+mod zone_grid;
 
 pub use base_graph::{
-    construct_base_graph, dump_graph, BaseGraphInput, BaseGraphStats, Graph, InnerGraph, Parking,
+    construct_base_graph, dump_graph, BaseGraphInput, BaseGraphStats, Graph, GraphVersion,
+    InnerGraph, Parking,
 };
-pub use common::{CarConfig, Error, Mode, QueryInput, MODES};
-pub use edge::Edge;
+pub use common::{AllowedModes, CarConfig, Error, MobilityProfile, Mode, QueryInput, MODES};
+pub use edge::{CostComponents, Edge};
 pub use fast_graph_wrapper::FastGraphWrapper;
-pub use isochrone::{calculate_isochrone, calculate_isochrone_map, Isochrone, IsochroneMap};
+pub use isochrone::{
+    calculate_isochrone, calculate_isochrone_map, calculate_mode_only_travel_times, Isochrone,
+    IsochroneMap, IsochroneResult, NodeTravelTimes, QuantizedIsochroneMap, QuantizedTravelTime,
+};
 pub use node::Node;
-pub use query::best_route;
-pub use route::{Route, SplineVisitor};
+pub use query::{best_route, best_route_between, best_route_one_to_many};
+pub use reliability::ReliabilityStats;
+pub use route::{ProfileSample, Route, SplineVisitor};
+pub use route_debug::{find_divergences, RouteDebug, RouteDivergence};
 pub use route_key::RouteKey;
 pub use traffic::{
-    CongestionIterator, CongestionStats, WorldState, WorldStateHistory, WorldStateImpl,
+    CongestionIterator, CongestionStats, Stats, WorldState, WorldStateHistory, WorldStateImpl,
     WorldStatePredictor,
 };
+pub use zone_grid::{ZoneGrid, ZoneId};
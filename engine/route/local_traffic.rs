@@ -1,19 +1,53 @@
 // NOTE: in the future we may extend this to support walking and biking as well
 
-/// The width of a single block in the local zone grid, in meters.
-pub const LOCAL_ZONE_BLOCK_SIZE: f32 = 500.0;
-
 /// the number of cars that can pass through a 1x1 m square before congestion passes the critical
 /// threshold where a significant slowdown begins to occur
 pub const K_CRITICAL_CAPACITY: f64 = 0.05;
 
+/// the number of parked cars per square meter that a block can hold before drivers have to circle
+/// around looking for a space
+pub const K_PARKING_CAPACITY: f64 = 0.01;
+
+/// the longest that anyone will spend looking for parking, in seconds
+pub const MAX_PARKING_SEARCH_TIME: f64 = 900.0;
+
+/// The width of a single block in the local zone grid, in units of the smallest tile size.
 pub fn grid_downsample(config: &state::Config) -> u32 {
-    config.even_downsample(LOCAL_ZONE_BLOCK_SIZE)
+    config.even_downsample(config.traffic_history.grid_block_size as f32)
 }
 
+/// The area of a single block in the local zone grid, in square meters.
+fn block_area(config: &state::Config) -> f64 {
+    ((config.min_tile_size * grid_downsample(config)) as f64).powi(2)
+}
+
+/// The number of simulated travelers that can be in a single block before congestion passes the
+/// critical threshold.
 pub fn critical_capacity(config: &state::Config) -> f64 {
-    ((config.min_tile_size * grid_downsample(config)).pow(2) / config.people_per_sim) as f64
-        * K_CRITICAL_CAPACITY
+    block_area(config) / config.people_per_sim * K_CRITICAL_CAPACITY
+}
+
+/// The number of real people per square meter in a block with the given number of simulated
+/// travelers. Congestion depends only on this, not on how many people each simulated person
+/// represents.
+pub fn traveler_density(config: &state::Config, travelers: f64) -> f64 {
+    travelers * config.people_per_sim / block_area(config)
+}
+
+/// The number of simulated cars that can be parked in a single block before it is saturated.
+pub fn parking_capacity(config: &state::Config) -> f64 {
+    block_area(config) / config.people_per_sim * K_PARKING_CAPACITY
+}
+
+/**
+ * The time spent looking for parking in a block with the given number of simulated parked cars.
+ * This is zero until the block is saturated, and then grows linearly until the block is at twice
+ * its capacity. Both the router and the agents use this, so that predicted and realized commutes
+ * agree.
+ */
+pub fn parking_search_time(config: &state::Config, parked: f64) -> f64 {
+    let saturation = parked / parking_capacity(config);
+    ((saturation - 1.0) * MAX_PARKING_SEARCH_TIME).clamp(0.0, MAX_PARKING_SEARCH_TIME)
 }
 
 pub fn congested_travel_factor(config: &state::Config, travelers: f64) -> f64 {
@@ -30,3 +64,36 @@ pub fn is_jammed(config: &state::Config, travelers: f64) -> bool {
     let traffic_factor = congested_travel_factor(config, travelers);
     highway::timing::is_jammed(traffic_factor, travelers)
 }
+
+/**
+ * The time spent at intersections while driving on local roads in a straight line between two
+ * points in model coordinates, in seconds. Every crossing into another block of the local traffic
+ * grid counts as an intersection, which is slower the more built up the block being entered is.
+ * Both the router and the agents use this, so that predicted and realized commutes agree.
+ */
+pub fn intersection_delay<F: state::Fields>(
+    state: &state::State<F>,
+    start: (f64, f64),
+    stop: (f64, f64),
+) -> f64 {
+    let config = &state.config.intersection_delay;
+    // this is computed for every local road edge whenever route weights are updated
+    if config.base_delay == 0.0 && config.density_delay == 0.0 {
+        return 0.0;
+    }
+
+    let downsample = grid_downsample(&state.config);
+    // the development grid may not have been computed for the current grid yet
+    let development = (state.development.downsample() == downsample).then_some(&state.development);
+    let block = |(x, y): (f64, f64)| {
+        (
+            (x / downsample as f64).floor() as i64,
+            (y / downsample as f64).floor() as i64,
+        )
+    };
+    line_drawing::WalkGrid::new(block(start), block(stop))
+        // the block that the drive starts in isn't entered
+        .skip(1)
+        .map(|(x, y)| config.crossing_delay(development.map_or(0.0, |grid| grid.get(x, y))))
+        .sum()
+}
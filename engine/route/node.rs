@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[non_exhaustive]
 pub enum Node {
     MetroStation {
@@ -27,9 +27,15 @@ pub enum Node {
     Parking {
         address: quadtree::Address,
     },
+    /// an endpoint at the exact center of its address; never part of the base graph
     Endpoint {
         address: quadtree::Address,
     },
+    /// an endpoint at a precise position rather than at the center of its address
+    Waypoint {
+        position: (f64, f64),
+        address: quadtree::Address,
+    },
 }
 
 impl Node {
@@ -47,7 +53,8 @@ impl Node {
             | HighwayJunction { address, .. }
             | HighwayRamp { address, .. }
             | Parking { address }
-            | Endpoint { address } => *address,
+            | Endpoint { address }
+            | Waypoint { address, .. } => *address,
         }
     }
 
@@ -62,12 +69,15 @@ impl Node {
                 ..
             }
             | RailJunction { address, .. }
-            | Parking { address }
-            | Endpoint { address } => {
+            | Parking { address } => {
                 let (x, y) = address.to_xy();
                 (x as f64, y as f64)
             }
-            HighwayJunction { position, .. } | HighwayRamp { position, .. } => *position,
+            // endpoints are not part of the base graph, so they can be at the exact tile center
+            Endpoint { address } => address.to_center_f64(),
+            HighwayJunction { position, .. }
+            | HighwayRamp { position, .. }
+            | Waypoint { position, .. } => *position,
         }
     }
 
@@ -99,9 +109,12 @@ impl std::fmt::Display for Node {
                 write!(f, "parking:({:.1}, {:.1})", x, y)
             }
             Endpoint { address } => {
-                let (x, y) = address.to_xy_f64();
+                let (x, y) = address.to_center_f64();
                 write!(f, "endpoint:({:.1}, {:.1})", x, y)
             }
+            Waypoint {
+                position: (x, y), ..
+            } => write!(f, "waypoint:({:.1}, {:.1})", x, y),
         }
     }
 }
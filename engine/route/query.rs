@@ -1,90 +1,155 @@
+use std::collections::HashMap;
+
 use crate::base_graph::{Graph, InnerGraph, NodeIndex};
-use crate::common::{CarConfig, Error, Mode, QueryInput};
+use crate::common::{CarConfig, Error, MobilityProfile, Mode, QueryInput};
 use crate::edge::Edge;
 use crate::node::Node;
 use crate::route::Route;
 
-fn perform_query(
-    base_graph: &mut InnerGraph,
-    start_id: NodeIndex,
-    end_id: NodeIndex,
-) -> Result<Option<(f64, Vec<NodeIndex>)>, Error> {
-    let path = {
-        let shortest_path = base_graph.query(start_id, end_id);
-        match shortest_path {
-            // TODO: remove this clone
-            Some(p) if p.is_found() => Some((p.get_weight() as f64, p.get_nodes().clone())),
-            _ => None,
-        }
-    };
+/// The number of terminal nodes that each end of a route is connected to.
+const TERMINAL_NODE_CANDIDATES: usize = 4;
+
+/**
+ * One of the terminal nodes nearest to the start or end of a route, along with the distance and
+ * cost of the leg between the node and the exact start or end. These legs connect the route to the
+ * base graph for the duration of a single query; they are never added to the base graph itself.
+ */
+struct TerminalCandidate {
+    node: NodeIndex,
+    dist: f64,
+    cost: f64,
+}
 
-    Ok(path)
+impl TerminalCandidate {
+    /// The cost rounded to a graph weight, so that candidates can be compared in the graph search.
+    fn weight(&self) -> fast_paths::Weight {
+        self.cost.round() as fast_paths::Weight
+    }
+}
+
+fn terminal_candidates(
+    base_graph: &Graph,
+    (x, y): (f64, f64),
+    mode: Mode,
+    speed: f64,
+) -> Vec<TerminalCandidate> {
+    use cgmath::MetricSpace;
+
+    // TODO: precompute these values and store in the qtree?
+    base_graph.terminal_nodes[mode]
+        .find_nearest_k(x, y, TERMINAL_NODE_CANDIDATES)
+        .into_iter()
+        .filter_map(|node| {
+            let location = cgmath::Vector2::from(base_graph.graph.node_weight(node)?.location());
+            let dist = location.distance((x, y).into()) * base_graph.tile_size;
+            Some(TerminalCandidate {
+                node,
+                dist,
+                cost: dist / speed,
+            })
+        })
+        .enumerate()
+        // the nearest node is always used, even if it is far away, but the others only if they are
+        // close enough to go to directly
+        .filter(|(i, candidate)| *i == 0 || candidate.dist < mode.bridge_radius())
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+fn find_candidate(candidates: &[TerminalCandidate], node: NodeIndex) -> &TerminalCandidate {
+    candidates
+        .iter()
+        .find(|candidate| candidate.node == node)
+        .expect("path does not start or end at a candidate")
+}
+
+/**
+ * The cost of a path found by a graph query, adjusted to the given profile.
+ *
+ * NOTE: The graph is prepared with the standard weights, so the path itself is the fastest path
+ * for the standard profile. Only its cost is adjusted, which is enough to choose between modes.
+ */
+fn profile_path_cost(
+    base_graph: &InnerGraph,
+    cost: f64,
+    path: &[NodeIndex],
+    profile: &MobilityProfile,
+) -> f64 {
+    use itertools::Itertools;
+
+    if profile.is_standard() {
+        return cost;
+    }
+    cost + path
+        .iter()
+        .tuple_windows()
+        .filter_map(|(a, b)| {
+            let edge = base_graph.edge_weight((*a, *b))?;
+            let weight = base_graph.edge_cost(*a, *b)? as f64;
+            Some(edge.profile_cost(weight, profile) - weight)
+        })
+        .sum::<f64>()
 }
 
 fn potential_route(
     base_graph: &mut std::cell::RefMut<Graph>,
-    start: quadtree::Address,
-    end: quadtree::Address,
+    start: &RouteEndpoint,
+    end: &RouteEndpoint,
     start_mode: Mode,
     end_mode: Mode,
+    profile: &MobilityProfile,
 ) -> Result<Option<PotentialRoute>, Error> {
     use cgmath::MetricSpace;
 
-    let (start_x, start_y) = start.to_xy_f64();
-    let (end_x, end_y) = end.to_xy_f64();
+    let (start_x, start_y) = start.position;
+    let (end_x, end_y) = end.position;
 
-    // TODO: pick N nearest terminal nodes and use calc_pat_multiple_sources_and_targets
+    let sources = terminal_candidates(
+        base_graph,
+        start.position,
+        start_mode,
+        profile.linear_speed(start_mode),
+    );
+    let targets = terminal_candidates(
+        base_graph,
+        end.position,
+        end_mode,
+        profile.linear_speed(end_mode),
+    );
 
-    // TODO: precompute these values and store in the qtree?
-    let start_id = base_graph.terminal_nodes[start_mode].find_nearest(start_x, start_y);
-    let end_id = base_graph.terminal_nodes[end_mode].find_nearest(end_x, end_y);
-
-    let potential_route = if let (Some(start_id), Some(end_id)) = (start_id, end_id) {
-        let path = perform_query(&mut base_graph.graph, start_id, end_id)?;
-        if let Some((cost, nodes)) = path {
-            // add in cost for reaching the start node and end node
-            let start_vec = cgmath::Vector2::from(
-                base_graph
-                    .graph
-                    .node_weight(*nodes.first().unwrap())
-                    .unwrap()
-                    .location(),
-            );
-            let end_vec = cgmath::Vector2::from(
-                base_graph
-                    .graph
-                    .node_weight(*nodes.last().unwrap())
-                    .unwrap()
-                    .location(),
-            );
-            let start_dist = start_vec.distance((start_x, start_y).into()) * base_graph.tile_size;
-            let start_cost = start_dist / start_mode.linear_speed();
-            let end_dist = end_vec.distance((end_x, end_y).into()) * base_graph.tile_size;
-            let end_cost = end_dist / end_mode.linear_speed();
+    let path = if sources.is_empty() || targets.is_empty() {
+        None
+    } else {
+        base_graph.graph.query_multiple(
+            sources.iter().map(|c| (c.node, c.weight())).collect(),
+            targets.iter().map(|c| (c.node, c.weight())).collect(),
+        )
+    };
 
-            let total_cost = cost + start_cost + end_cost;
+    let potential_route = path.filter(|path| path.is_found()).map(|path| {
+        let source = find_candidate(&sources, path.get_source());
+        let target = find_candidate(&targets, path.get_target());
+        // the graph search includes the rounded cost of the legs, so replace them with exact costs
+        let cost = (path.get_weight() - source.weight() - target.weight()) as f64;
+        let cost = profile_path_cost(&base_graph.graph, cost, path.get_nodes(), profile);
 
-            Some(PotentialRoute {
-                cost: total_cost,
-                path: nodes,
-                start_mode,
-                end_mode,
-                start_dist,
-                end_dist,
-            })
-        } else {
-            None
+        PotentialRoute {
+            cost: cost + source.cost + target.cost,
+            // TODO: remove this clone
+            path: path.get_nodes().clone(),
+            start_mode,
+            end_mode,
+            start_dist: source.dist,
+            end_dist: target.dist,
         }
-    } else {
-        None
-    };
+    });
 
     // compare with a "direct" route, i.e. a straight line
     let direct_route = if start_mode == end_mode {
         let direct_dist = cgmath::Vector2::from((start_x, start_y)).distance((end_x, end_y).into())
             * base_graph.tile_size;
         if direct_dist < start_mode.bridge_radius() {
-            let direct_cost = direct_dist / start_mode.linear_speed();
+            let direct_cost = direct_dist / profile.linear_speed(start_mode);
             Some(PotentialRoute {
                 cost: direct_cost,
                 path: Vec::new(),
@@ -114,22 +179,26 @@ struct PotentialRoute {
     end_dist: f64,
 }
 
-fn construct_route(base_graph: &InnerGraph, input: QueryInput, route: &PotentialRoute) -> Route {
+fn construct_route(
+    base_graph: &InnerGraph,
+    input: QueryInput,
+    start: &RouteEndpoint,
+    end: &RouteEndpoint,
+    route: &PotentialRoute,
+) -> Route {
     use itertools::Itertools;
     use std::iter::once;
 
-    let start_pos = input.start.to_xy_f64();
-    let end_pos = input.end.to_xy_f64();
+    let start_pos = start.position;
+    let end_pos = end.position;
 
     let (extra_start_node, extra_start_edge, real_start_mode) = match route.start_mode {
         Mode::Driving => (
-            Some(Node::Parking {
-                address: input.start,
-            }),
+            Some(start.parking_node()),
             Some(Edge::ModeTransition {
                 from: Mode::Walking,
                 to: Mode::Driving,
-                address: input.start,
+                address: start.address,
             }),
             Mode::Walking,
         ),
@@ -138,11 +207,11 @@ fn construct_route(base_graph: &InnerGraph, input: QueryInput, route: &Potential
 
     let (extra_end_node, extra_end_edge, real_end_mode) = match route.end_mode {
         Mode::Driving => (
-            Some(Node::Parking { address: input.end }),
+            Some(end.parking_node()),
             Some(Edge::ModeTransition {
                 from: Mode::Driving,
                 to: Mode::Walking,
-                address: input.end,
+                address: end.address,
             }),
             Mode::Walking,
         ),
@@ -176,19 +245,17 @@ fn construct_route(base_graph: &InnerGraph, input: QueryInput, route: &Potential
         stop: end_pos,
     });
 
-    let nodes = once(Node::Endpoint {
-        address: input.start,
-    })
-    .chain(extra_start_node.into_iter())
-    .chain(
-        route
-            .path
-            .iter()
-            .map(|n| base_graph.node_weight(*n).unwrap().clone()),
-    )
-    .chain(extra_end_node.into_iter())
-    .chain(once(Node::Endpoint { address: input.end }))
-    .collect();
+    let nodes = once(start.endpoint_node())
+        .chain(extra_start_node.into_iter())
+        .chain(
+            route
+                .path
+                .iter()
+                .map(|n| base_graph.node_weight(*n).unwrap().clone()),
+        )
+        .chain(extra_end_node.into_iter())
+        .chain(once(end.endpoint_node()))
+        .collect();
 
     let edges = extra_start_edge
         .into_iter()
@@ -220,6 +287,65 @@ where
     potential_routes.min_by(|a, b| a.cost.partial_cmp(&b.cost).unwrap())
 }
 
+/**
+ * Where a route starts or ends. Routes between addresses start and end at the center of the
+ * address, and routes between arbitrary coordinates start and end at precise positions. Either way,
+ * the start and end are connected to several of the nearest terminal nodes, so that the route
+ * includes the legs between the base graph and the exact start and end.
+ */
+struct RouteEndpoint {
+    position: (f64, f64),
+    address: quadtree::Address,
+    precise: bool,
+}
+
+impl RouteEndpoint {
+    fn from_address(address: quadtree::Address) -> Self {
+        Self {
+            position: address.to_center_f64(),
+            address,
+            precise: false,
+        }
+    }
+
+    fn from_position(position: (f64, f64), max_depth: u32) -> Result<Self, Error> {
+        let (x, y) = position;
+        let width = 2_u64.pow(max_depth) as f64;
+        if !(0.0..width).contains(&x) || !(0.0..width).contains(&y) {
+            return Err(quadtree::Error::CoordsOutOfBoundsF64(x, y).into());
+        }
+        Ok(Self {
+            position,
+            address: quadtree::Address::from_xy(x as u64, y as u64, max_depth),
+            precise: true,
+        })
+    }
+
+    fn endpoint_node(&self) -> Node {
+        if self.precise {
+            Node::Waypoint {
+                position: self.position,
+                address: self.address,
+            }
+        } else {
+            Node::Endpoint {
+                address: self.address,
+            }
+        }
+    }
+
+    fn parking_node(&self) -> Node {
+        if self.precise {
+            // park right at the endpoint
+            self.endpoint_node()
+        } else {
+            Node::Parking {
+                address: self.address,
+            }
+        }
+    }
+}
+
 /**
  * Finds the best (lowest cost) route from `start` to `end` in
  * `base_graph`. Returns None if no route could be found.
@@ -228,81 +354,145 @@ where
  * find a route.
  */
 pub fn best_route(
+    base_graph: std::cell::RefMut<Graph>,
+    input: QueryInput,
+) -> Result<Option<Route>, Error> {
+    let start = RouteEndpoint::from_address(input.start);
+    let end = RouteEndpoint::from_address(input.end);
+    best_route_between_endpoints(base_graph, input, &start, &end)
+}
+
+/**
+ * Like best_route, but the route starts and ends at precise model coordinates instead of at
+ * addresses, e.g. for routes between points picked with the mouse. The start and end are connected
+ * to the nearest terminal nodes in the same way as addresses, so this produces the same routes as
+ * best_route, but without snapping the endpoints to the tile grid.
+ *
+ * The query input of the returned route refers to the addresses containing the coordinates.
+ */
+pub fn best_route_between(
+    base_graph: std::cell::RefMut<Graph>,
+    start: (f64, f64),
+    end: (f64, f64),
+    car_config: Option<CarConfig>,
+) -> Result<Option<Route>, Error> {
+    let start = RouteEndpoint::from_position(start, base_graph.max_depth)?;
+    let end = RouteEndpoint::from_position(end, base_graph.max_depth)?;
+    let input = QueryInput {
+        start: start.address,
+        end: end.address,
+        car_config,
+        profile: MobilityProfile::STANDARD,
+        allowed_modes: Default::default(),
+    };
+    best_route_between_endpoints(base_graph, input, &start, &end)
+}
+
+#[tracing::instrument(
+    name = "best_route",
+    level = "debug",
+    skip_all,
+    fields(car_config = ?input.car_config)
+)]
+fn best_route_between_endpoints(
     mut base_graph: std::cell::RefMut<Graph>,
     input: QueryInput,
+    start: &RouteEndpoint,
+    end: &RouteEndpoint,
 ) -> Result<Option<Route>, Error> {
-    Ok(match &input.car_config {
+    input.allowed_modes.validate()?;
+
+    // if the car can't be parked at the destination, it has to be parked somewhere else
+    let park_at_end = base_graph.allows_parking(end.address);
+
+    // without a car, routes never start at driving terminal nodes, and there are no edges from
+    // walking to driving, so they can't reach highways at all
+    let car_config = input
+        .car_config
+        .filter(|_| input.allowed_modes.contains(Mode::Driving));
+
+    let route = match &car_config {
         None => potential_route(
             &mut base_graph,
-            input.start,
-            input.end,
+            start,
+            end,
             Mode::Walking,
             Mode::Walking,
+            &input.profile,
         )?
-        .map(|route| construct_route(&base_graph.graph, input, &route)),
+        .map(|route| construct_route(&base_graph.graph, input, start, end, &route)),
         Some(CarConfig::StartWithCar) => fastest_route(
             potential_route(
                 &mut base_graph,
-                input.start,
-                input.end,
+                start,
+                end,
                 Mode::Driving,
                 Mode::Walking,
+                &input.profile,
             )?
             .into_iter()
-            .chain(
+            .chain(if park_at_end {
                 potential_route(
                     &mut base_graph,
-                    input.start,
-                    input.end,
+                    start,
+                    end,
                     Mode::Driving,
                     Mode::Driving,
+                    &input.profile,
                 )?
-                .into_iter(),
-            )
+            } else {
+                None
+            })
             .chain(potential_route(
                 &mut base_graph,
-                input.start,
-                input.end,
+                start,
+                end,
                 Mode::Walking,
                 Mode::Walking,
+                &input.profile,
             )?),
         )
-        .map(|route| construct_route(&base_graph.graph, input, &route)),
+        .map(|route| construct_route(&base_graph.graph, input, start, end, &route)),
         Some(CarConfig::CollectParkedCar { address }) => {
             // basically just merge two routes together
+            let parked_car = RouteEndpoint::from_address(*address);
             let walking_leg = potential_route(
                 &mut base_graph,
-                input.start,
-                *address,
+                start,
+                &parked_car,
                 Mode::Walking,
                 Mode::Walking,
+                &input.profile,
             )?
             .map(|route| {
                 construct_route(
                     &base_graph.graph,
                     QueryInput {
-                        start: input.start,
                         end: *address,
-                        car_config: input.car_config,
+                        ..input
                     },
+                    start,
+                    &parked_car,
                     &route,
                 )
             });
             let driving_leg = potential_route(
                 &mut base_graph,
-                *address,
-                input.end,
+                &parked_car,
+                end,
                 Mode::Driving,
                 Mode::Driving,
+                &input.profile,
             )?
             .map(|route| {
                 construct_route(
                     &base_graph.graph,
                     QueryInput {
                         start: *address,
-                        end: input.end,
-                        car_config: input.car_config,
+                        ..input
                     },
+                    &parked_car,
+                    end,
                     &route,
                 )
             });
@@ -311,5 +501,889 @@ pub fn best_route(
                 .zip(driving_leg)
                 .map(|(walking, driving)| Route::join(walking, driving))
         }
+    };
+
+    Ok(route.map(|mut route| {
+        route.graph_version = base_graph.version;
+        route
+    }))
+}
+
+/**
+ * The result of a bounded search from a single starting point: the graph costs of all nodes that
+ * can be reached from the terminal nodes nearest the start, plus the terminal nodes themselves.
+ */
+struct OneToManySearch {
+    start_mode: Mode,
+    start_candidates: Vec<TerminalCandidate>,
+    /// the cost of each node, including the rounded cost of the start leg, and the terminal node
+    /// it is reached from
+    node_costs: HashMap<NodeIndex, (fast_paths::Weight, NodeIndex)>,
+}
+
+fn one_to_many_search(
+    base_graph: &Graph,
+    start: quadtree::Address,
+    start_mode: Mode,
+    max_cost: Option<f64>,
+) -> Option<OneToManySearch> {
+    let start_candidates: Vec<_> = terminal_candidates(
+        base_graph,
+        start.to_center_f64(),
+        start_mode,
+        start_mode.linear_speed(),
+    )
+    .into_iter()
+    .filter(|candidate| max_cost.map_or(true, |max_cost| candidate.cost <= max_cost))
+    .collect();
+    if start_candidates.is_empty() {
+        return None;
+    }
+
+    // NOTE: the costs of the start legs are rounded in the search, so allow for the rounding
+    let max_weight = max_cost.map(|max_cost| (max_cost + 1.0).ceil() as fast_paths::Weight);
+    let sources: Vec<_> = start_candidates
+        .iter()
+        .map(|candidate| (candidate.node, candidate.weight()))
+        .collect();
+
+    Some(OneToManySearch {
+        start_mode,
+        node_costs: base_graph.graph.query_all_multiple(&sources, max_weight),
+        start_candidates,
     })
 }
+
+/**
+ * Equivalent to the cost of potential_route, but looks up the graph cost in an existing search
+ * instead of querying the graph.
+ */
+fn potential_route_cost(
+    base_graph: &Graph,
+    search: Option<&OneToManySearch>,
+    start: quadtree::Address,
+    end: quadtree::Address,
+    start_mode: Mode,
+    end_mode: Mode,
+) -> Option<f64> {
+    use cgmath::MetricSpace;
+
+    let (start_x, start_y) = start.to_center_f64();
+    let (end_x, end_y) = end.to_center_f64();
+
+    let graph_cost = search.and_then(|search| {
+        debug_assert_eq!(search.start_mode, start_mode);
+        let targets = terminal_candidates(
+            base_graph,
+            (end_x, end_y),
+            end_mode,
+            end_mode.linear_speed(),
+        );
+        let (weight, source, target) = targets
+            .iter()
+            .filter_map(|target| {
+                let (weight, source) = search.node_costs.get(&target.node)?;
+                Some((weight + target.weight(), *source, target))
+            })
+            .min_by_key(|(weight, _, _)| *weight)?;
+        let source = find_candidate(&search.start_candidates, source);
+        let cost = (weight - source.weight() - target.weight()) as f64;
+        Some(cost + source.cost + target.cost)
+    });
+
+    let direct_cost = if start_mode == end_mode {
+        let direct_dist = cgmath::Vector2::from((start_x, start_y)).distance((end_x, end_y).into())
+            * base_graph.tile_size;
+        (direct_dist < start_mode.bridge_radius()).then(|| direct_dist / start_mode.linear_speed())
+    } else {
+        None
+    };
+
+    graph_cost
+        .into_iter()
+        .chain(direct_cost)
+        .min_by(|a, b| a.partial_cmp(b).unwrap())
+}
+
+/**
+ * Finds the cost of the best route from `start` to each of `candidates`, as best_route would
+ * compute it, but without constructing the routes. Instead of querying the graph once per
+ * candidate, this performs a single search outward from the start (or one per starting mode),
+ * which is much cheaper when there are many candidates.
+ *
+ * If `max_cost` is specified, the search stops once routes would exceed it. The returned vector
+ * has one entry per candidate, in the same order; candidates that cannot be reached, or cannot be
+ * reached within `max_cost`, are None.
+ *
+ * NOTE: costs are always for the standard mobility profile.
+ */
+pub fn best_route_one_to_many(
+    base_graph: &Graph,
+    start: quadtree::Address,
+    candidates: &[quadtree::Address],
+    car_config: Option<CarConfig>,
+    max_cost: Option<f64>,
+) -> Result<Vec<Option<f64>>, Error> {
+    let within_max = |cost: Option<f64>| match (cost, max_cost) {
+        (Some(cost), Some(max_cost)) if cost > max_cost => None,
+        _ => cost,
+    };
+
+    let costs = match car_config {
+        None => {
+            let walking = one_to_many_search(base_graph, start, Mode::Walking, max_cost);
+            candidates
+                .iter()
+                .map(|end| {
+                    potential_route_cost(
+                        base_graph,
+                        walking.as_ref(),
+                        start,
+                        *end,
+                        Mode::Walking,
+                        Mode::Walking,
+                    )
+                })
+                .map(within_max)
+                .collect()
+        }
+        Some(CarConfig::StartWithCar) => {
+            let walking = one_to_many_search(base_graph, start, Mode::Walking, max_cost);
+            let driving = one_to_many_search(base_graph, start, Mode::Driving, max_cost);
+            candidates
+                .iter()
+                .map(|end| {
+                    [
+                        (driving.as_ref(), Mode::Driving, Mode::Walking),
+                        (driving.as_ref(), Mode::Driving, Mode::Driving),
+                        (walking.as_ref(), Mode::Walking, Mode::Walking),
+                    ]
+                    .into_iter()
+                    .filter(|(_, _, end_mode)| {
+                        *end_mode != Mode::Driving || base_graph.allows_parking(*end)
+                    })
+                    .filter_map(|(search, start_mode, end_mode)| {
+                        potential_route_cost(base_graph, search, start, *end, start_mode, end_mode)
+                    })
+                    .min_by(|a, b| a.partial_cmp(b).unwrap())
+                })
+                .map(within_max)
+                .collect()
+        }
+        Some(CarConfig::CollectParkedCar { address }) => {
+            // the walking leg is the same for every candidate, so only the driving leg needs a
+            // one-to-many search
+            let walking = one_to_many_search(base_graph, start, Mode::Walking, max_cost);
+            let walking_cost = potential_route_cost(
+                base_graph,
+                walking.as_ref(),
+                start,
+                address,
+                Mode::Walking,
+                Mode::Walking,
+            );
+            match walking_cost {
+                Some(walking_cost) => {
+                    let driving = one_to_many_search(
+                        base_graph,
+                        address,
+                        Mode::Driving,
+                        max_cost.map(|max_cost| max_cost - walking_cost),
+                    );
+                    candidates
+                        .iter()
+                        .map(|end| {
+                            potential_route_cost(
+                                base_graph,
+                                driving.as_ref(),
+                                address,
+                                *end,
+                                Mode::Driving,
+                                Mode::Driving,
+                            )
+                            .map(|driving_cost| walking_cost + driving_cost)
+                        })
+                        .map(within_max)
+                        .collect()
+                }
+                None => vec![None; candidates.len()],
+            }
+        }
+    };
+
+    Ok(costs)
+}
+
+#[cfg(test)]
+mod query_tests {
+    use std::cell::RefCell;
+
+    use crate::base_graph::{construct_base_graph, BaseGraphInput};
+    use crate::common::AllowedModes;
+    use crate::query::*;
+
+    const MAX_DEPTH: u32 = 8;
+
+    #[derive(Debug, Default, Clone)]
+    struct DummyFields {}
+
+    impl state::Fields for DummyFields {}
+
+    fn address(x: u64, y: u64) -> quadtree::Address {
+        quadtree::Address::from_xy(x, y, MAX_DEPTH)
+    }
+
+    fn add_metro_line(state: &mut state::State<DummyFields>, start: (u64, u64), end: (u64, u64)) {
+        let mut add_station = |(x, y): (u64, u64)| {
+            state.railways.add_junction(
+                (x as f64, y as f64),
+                metro::RailwayJunction::new(Some(metro::Station {
+                    name: format!("{}, {}", x, y),
+                    address: address(x, y),
+                })),
+            )
+        };
+        let start_junction = add_station(start);
+        let end_junction = add_station(end);
+
+        let segment = state.railways.add_segment(
+            metro::RailwaySegment::new(None),
+            start_junction,
+            end_junction,
+            Some(vec![
+                (start.0 as f64, start.1 as f64).into(),
+                (end.0 as f64, end.1 as f64).into(),
+            ]),
+        );
+
+        state.metros.add_metro_line(
+            metro::MetroLineData {
+                color: (255, 0, 0).into(),
+                name: format!("{:?} to {:?}", start, end),
+                schedule: metro::Schedule::fixed_frequency(300),
+                speed_limit: 20,
+            },
+            vec![segment],
+            &state.railways,
+        );
+    }
+
+    /**
+     * Two metro lines separated by 900 meters of "water", i.e. with no way to get between them
+     * other than walking, which is beyond the walking bridge radius.
+     */
+    fn setup_problem() -> Graph {
+        construct_graph(&setup_state())
+    }
+
+    fn setup_state() -> state::State<DummyFields> {
+        let mut state: state::State<DummyFields> = state::State::new(state::Config {
+            max_depth: MAX_DEPTH,
+            people_per_sim: 1.0,
+            min_tile_size: 10,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        });
+
+        add_metro_line(&mut state, (12, 10), (200, 10));
+        add_metro_line(&mut state, (10, 100), (100, 100));
+        state
+    }
+
+    fn construct_graph(state: &state::State<DummyFields>) -> Graph {
+        construct_base_graph(BaseGraphInput {
+            state,
+            filter_metro_lines: None,
+            filter_highway_segments: None,
+            add_inferred_edges: false,
+            validate_highways: true,
+        })
+        .unwrap()
+    }
+
+    const START: (u64, u64) = (10, 10);
+
+    fn candidates() -> Vec<quadtree::Address> {
+        vec![
+            // walking distance
+            address(14, 12),
+            // walking distance, but nearest to the unreachable metro line
+            address(10, 60),
+            // at the far end of the metro line
+            address(200, 12),
+            // across the water
+            address(12, 100),
+            address(100, 100),
+        ]
+    }
+
+    #[test]
+    fn matches_best_route() {
+        let graph = RefCell::new(setup_problem());
+        let start = address(START.0, START.1);
+
+        for car_config in [
+            None,
+            Some(CarConfig::StartWithCar),
+            Some(CarConfig::CollectParkedCar {
+                address: address(12, 12),
+            }),
+        ] {
+            let costs =
+                best_route_one_to_many(&graph.borrow(), start, &candidates(), car_config, None)
+                    .unwrap();
+            assert_eq!(costs.len(), candidates().len());
+
+            for (end, cost) in candidates().into_iter().zip(costs) {
+                let route = best_route(
+                    graph.borrow_mut(),
+                    QueryInput {
+                        start,
+                        end,
+                        car_config,
+                        profile: MobilityProfile::STANDARD,
+                        allowed_modes: Default::default(),
+                    },
+                )
+                .unwrap();
+                match (route, cost) {
+                    (Some(route), Some(cost)) => assert!(
+                        (route.cost as f64 - cost).abs() < 1e-3 * cost.max(1.0),
+                        "{:?} to {:?} with {:?}: expected {}, got {}",
+                        start,
+                        end,
+                        car_config,
+                        route.cost,
+                        cost
+                    ),
+                    (None, None) => (),
+                    (route, cost) => panic!(
+                        "{:?} to {:?} with {:?}: expected {:?}, got {:?}",
+                        start,
+                        end,
+                        car_config,
+                        route.map(|route| route.cost),
+                        cost
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn across_water() {
+        let graph = setup_problem();
+        let start = address(START.0, START.1);
+
+        let costs = best_route_one_to_many(&graph, start, &candidates(), None, None).unwrap();
+        assert!(costs[0].is_some());
+        assert!(costs[1].is_some());
+        // farther away than the candidates across the water, but reachable by metro
+        assert!(costs[2].is_some());
+        assert!(costs[3].is_none());
+        assert!(costs[4].is_none());
+    }
+
+    #[test]
+    fn max_cost() {
+        let graph = setup_problem();
+        let start = address(START.0, START.1);
+
+        let unbounded = best_route_one_to_many(&graph, start, &candidates(), None, None).unwrap();
+        for max_cost in [0.0, 30.0, 300.0, 400.0] {
+            let bounded =
+                best_route_one_to_many(&graph, start, &candidates(), None, Some(max_cost)).unwrap();
+            for (bounded, unbounded) in bounded.into_iter().zip(&unbounded) {
+                assert_eq!(bounded, unbounded.filter(|cost| *cost <= max_cost));
+            }
+        }
+    }
+
+    #[test]
+    fn coordinates_near_tile_boundary() {
+        let graph = RefCell::new(setup_problem());
+        // the center of the start address
+        let start = (START.0 as f64 + 0.5, START.1 as f64 + 0.5);
+
+        let route_between = |end| {
+            best_route_between(graph.borrow_mut(), start, end, None)
+                .unwrap()
+                .unwrap()
+        };
+        let route_to_address = |(x, y)| {
+            best_route(
+                graph.borrow_mut(),
+                QueryInput {
+                    start: address(START.0, START.1),
+                    end: address(x, y),
+                    car_config: None,
+                    profile: MobilityProfile::STANDARD,
+                    allowed_modes: Default::default(),
+                },
+            )
+            .unwrap()
+            .unwrap()
+        };
+
+        // coordinates on either side of the boundary between two tiles give nearly the same route,
+        // but the addresses of those tiles are a whole tile apart
+        let before = route_between((14.999, 12.0));
+        let after = route_between((15.001, 12.0));
+        assert!((before.cost - after.cost).abs() < 0.1);
+        assert_eq!(before.query_input.end, address(14, 12));
+        assert_eq!(after.query_input.end, address(15, 12));
+        let before_address = route_to_address((14, 12));
+        let after_address = route_to_address((15, 12));
+        assert!((before_address.cost - after_address.cost).abs() > 1.0);
+
+        // coordinates at the center of an address are the same as the address
+        assert!((route_between((14.5, 12.5)).cost - before_address.cost).abs() < 0.01);
+
+        // routes using the graph end at the precise position as well
+        let metro = route_between((199.5, 11.5));
+        let metro_address = route_to_address((199, 11));
+        assert!((metro.cost - metro_address.cost).abs() < 10.0);
+        assert!(metro.nodes.len() > 2);
+        assert_eq!(metro.nodes.first().unwrap().location(), start);
+        assert_eq!(metro.nodes.last().unwrap().location(), (199.5, 11.5));
+    }
+
+    #[test]
+    fn adjacent_tiles() {
+        let state = setup_state();
+        let graph = RefCell::new(construct_graph(&state));
+        // a tile next to a smaller one, so that neither center is on the grid of the smallest tiles
+        let start = quadtree::Address::from_xy_depth(40, 40, MAX_DEPTH - 1, MAX_DEPTH);
+        let end = address(42, 40);
+        let (start_center, end_center) = (start.to_center_f64(), end.to_center_f64());
+        assert_eq!(start_center, (41.0, 41.0));
+        assert_eq!(end_center, (42.5, 40.5));
+
+        let route = best_route(
+            graph.borrow_mut(),
+            QueryInput {
+                start,
+                end,
+                car_config: None,
+                profile: MobilityProfile::STANDARD,
+                allowed_modes: Default::default(),
+            },
+        )
+        .unwrap()
+        .unwrap();
+
+        // the route is nothing but the walk between the centers
+        let dist = cgmath::MetricSpace::distance(
+            cgmath::Vector2::from(start_center),
+            cgmath::Vector2::from(end_center),
+        ) * graph.borrow().tile_size;
+        let walking_cost = dist / Mode::Walking.linear_speed();
+        assert!(
+            (route.cost as f64 - walking_cost).abs() < 1e-3,
+            "expected {}, got {}",
+            walking_cost,
+            route.cost
+        );
+
+        let polyline = route.sample_polyline(0.1, &state);
+        let first = *polyline.first().unwrap();
+        let last = *polyline.last().unwrap();
+        assert!((first.0 - start_center.0).abs() < 1e-3 && (first.1 - start_center.1).abs() < 1e-3);
+        assert!((last.0 - end_center.0).abs() < 1e-3 && (last.1 - end_center.1).abs() < 1e-3);
+    }
+
+    #[test]
+    fn route_ends_at_tile_center() {
+        let state = setup_state();
+        let graph = RefCell::new(construct_graph(&state));
+        // far enough from the metro station that the route uses the graph
+        let start = address(START.0, START.1);
+        let end = address(199, 13);
+
+        let route = best_route(
+            graph.borrow_mut(),
+            QueryInput {
+                start,
+                end,
+                car_config: None,
+                profile: MobilityProfile::STANDARD,
+                allowed_modes: Default::default(),
+            },
+        )
+        .unwrap()
+        .unwrap();
+        assert!(route.nodes.len() > 2);
+        assert_eq!(route.nodes.first().unwrap().location(), (10.5, 10.5));
+        assert_eq!(route.nodes.last().unwrap().location(), (199.5, 13.5));
+
+        // the final leg walks from the station to the center of the tile
+        match route.edges.last().unwrap() {
+            Edge::ModeSegment {
+                mode: Mode::Walking,
+                distance,
+                stop,
+                ..
+            } => {
+                assert_eq!(*stop, (199.5, 13.5));
+                assert!(*distance > 0.0);
+            }
+            edge => panic!("unexpected last edge: {}", edge),
+        }
+
+        let total = route.sample_time(0.0, &state).unwrap();
+        assert_eq!(total.position, (10.5, 10.5));
+        let last = *route.sample_polyline(0.1, &state).last().unwrap();
+        assert!((last.0 - 199.5).abs() < 1e-3 && (last.1 - 13.5).abs() < 1e-3);
+        assert!(route.bounds.max_x >= 199 && route.bounds.max_y >= 13);
+    }
+
+    /**
+     * Replace the tile at the given address, splitting the quadtree as needed so that the address
+     * is a leaf.
+     */
+    fn set_tile(
+        state: &mut state::State<DummyFields>,
+        address: quadtree::Address,
+        tile: tiles::Tile,
+    ) {
+        let mut current = quadtree::Address::from_vec(vec![], MAX_DEPTH);
+        for i in 0..address.depth() {
+            if state.qtree.get_leaf(current).is_ok() {
+                state
+                    .qtree
+                    .split(
+                        current,
+                        state::BranchState::default(),
+                        quadtree::QuadMap::each(state::LeafState::default),
+                    )
+                    .unwrap();
+            }
+            current = current.child(address.at(i));
+        }
+        state.qtree.get_leaf_mut(address).unwrap().tile = tile;
+    }
+
+    #[test]
+    fn station_without_parking() {
+        let mut state: state::State<DummyFields> = state::State::new(state::Config {
+            max_depth: MAX_DEPTH,
+            people_per_sim: 1.0,
+            min_tile_size: 10,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        });
+
+        let no_parking = (40, 10);
+        let parking = (44, 10);
+        add_metro_line(&mut state, no_parking, parking);
+        set_tile(
+            &mut state,
+            address(no_parking.0, no_parking.1),
+            tiles::MetroStationTile {
+                name: "no parking".to_string(),
+                x: no_parking.0,
+                y: no_parking.1,
+                ids: vec![],
+                parking: false,
+            }
+            .into(),
+        );
+
+        // a highway from the start to past the stations, so that there is somewhere to drive
+        let on_ramp = state.highways.add_junction(
+            (10.0, 12.0),
+            highway::HighwayJunction {
+                ramp: Some(highway::RampDirection::OnRamp),
+            },
+        );
+        let off_ramp = state.highways.add_junction(
+            (50.0, 12.0),
+            highway::HighwayJunction {
+                ramp: Some(highway::RampDirection::OffRamp),
+            },
+        );
+        state.highways.add_segment(
+            highway::HighwaySegment {
+                name: None,
+                refs: vec![],
+                lanes: None,
+                speed_limit: Some(30),
+            },
+            on_ramp,
+            off_ramp,
+            Some(vec![(10.0, 12.0).into(), (50.0, 12.0).into()]),
+        );
+
+        let graph = RefCell::new(
+            construct_base_graph(BaseGraphInput {
+                state: &state,
+                filter_metro_lines: None,
+                filter_highway_segments: None,
+                add_inferred_edges: true,
+                validate_highways: true,
+            })
+            .unwrap(),
+        );
+
+        let end = address(no_parking.0, no_parking.1);
+        assert!(!graph.borrow().parking.contains_key(&end));
+        assert!(!graph.borrow().allows_parking(end));
+        assert_eq!(graph.borrow().get_stats().parking_count, 1);
+
+        let start = address(START.0, START.1);
+        let car_config = Some(CarConfig::StartWithCar);
+        let route = best_route(
+            graph.borrow_mut(),
+            QueryInput {
+                start,
+                end,
+                car_config,
+                profile: MobilityProfile::STANDARD,
+                allowed_modes: Default::default(),
+            },
+        )
+        .unwrap()
+        .unwrap();
+
+        // the car is parked at the other station, and the rest of the way is on foot
+        let parked: Vec<_> = route
+            .edges
+            .iter()
+            .filter_map(|edge| match edge {
+                Edge::ModeTransition {
+                    from: Mode::Driving,
+                    to: Mode::Walking,
+                    address,
+                } => Some(*address),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(parked, vec![address(parking.0, parking.1)]);
+        assert_eq!(route.end_mode, Mode::Walking);
+
+        let costs =
+            best_route_one_to_many(&graph.borrow(), start, &[end], car_config, None).unwrap();
+        assert!((route.cost as f64 - costs[0].unwrap()).abs() < 1e-3 * route.cost as f64);
+    }
+
+    #[test]
+    fn coordinates_out_of_bounds() {
+        let graph = RefCell::new(setup_problem());
+        let width = 2_u64.pow(MAX_DEPTH) as f64;
+        for end in [(-1.0, 0.0), (0.0, width), (f64::NAN, 0.0)] {
+            assert!(best_route_between(graph.borrow_mut(), (0.0, 0.0), end, None).is_err());
+        }
+    }
+
+    #[test]
+    fn transit_only() {
+        let mut state: state::State<DummyFields> = state::State::new(state::Config {
+            max_depth: MAX_DEPTH,
+            people_per_sim: 1.0,
+            min_tile_size: 10,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        });
+
+        // two stations too far apart to walk between, with a highway alongside the metro line
+        let (from, to) = ((20, 50), (120, 50));
+        add_metro_line(&mut state, from, to);
+        let on_ramp = state.highways.add_junction(
+            (20.0, 52.0),
+            highway::HighwayJunction {
+                ramp: Some(highway::RampDirection::OnRamp),
+            },
+        );
+        let off_ramp = state.highways.add_junction(
+            (120.0, 52.0),
+            highway::HighwayJunction {
+                ramp: Some(highway::RampDirection::OffRamp),
+            },
+        );
+        state.highways.add_segment(
+            highway::HighwaySegment {
+                name: None,
+                refs: vec![],
+                lanes: None,
+                speed_limit: Some(30),
+            },
+            on_ramp,
+            off_ramp,
+            Some(vec![(20.0, 52.0).into(), (120.0, 52.0).into()]),
+        );
+
+        let graph = RefCell::new(
+            construct_base_graph(BaseGraphInput {
+                state: &state,
+                filter_metro_lines: None,
+                filter_highway_segments: None,
+                add_inferred_edges: true,
+                validate_highways: true,
+            })
+            .unwrap(),
+        );
+
+        let query = |allowed_modes| QueryInput {
+            start: address(from.0, from.1),
+            end: address(to.0, to.1),
+            car_config: Some(CarConfig::StartWithCar),
+            profile: MobilityProfile::STANDARD,
+            allowed_modes,
+        };
+        let drives = |route: &Route| {
+            route.edges.iter().any(|edge| {
+                matches!(
+                    edge,
+                    Edge::Highway { .. }
+                        | Edge::ModeSegment {
+                            mode: Mode::Driving,
+                            ..
+                        }
+                )
+            })
+        };
+
+        // with a car, it is faster to take the highway
+        let route = best_route(graph.borrow_mut(), query(AllowedModes::ALL))
+            .unwrap()
+            .unwrap();
+        assert!(drives(&route));
+
+        let route = best_route(graph.borrow_mut(), query(AllowedModes::transit_only()))
+            .unwrap()
+            .unwrap();
+        assert!(!drives(&route));
+        assert_eq!(route.start_mode, Mode::Walking);
+        assert_eq!(route.end_mode, Mode::Walking);
+        assert!(route
+            .edges
+            .iter()
+            .any(|edge| matches!(edge, Edge::MetroSegment { .. })));
+
+        // walk from the center of the start tile to the corner where the station is, board, ride,
+        // and walk from the end station to the center of the end tile; the walking edges between
+        // each station and its parking, and getting off, take the minimum of one second each
+        use metro::RailwayTiming;
+        let tile_size = state.config.min_tile_size as f64;
+        let (_, segment) = state.railways.segments().iter().next().unwrap();
+        let ride = segment.railway_travel_time(20, tile_size, &state.railways) as u64;
+        let waiting = metro::Schedule::fixed_frequency(300).expected_waiting_time();
+        let walk = 2.0 * (0.5_f64.powi(2) * 2.0).sqrt() * tile_size / Mode::Walking.linear_speed();
+        let expected = walk + (1 + waiting + ride + 1 + 1) as f64;
+        assert!(
+            (route.cost as f64 - expected).abs() < 1e-3,
+            "{} != {}",
+            route.cost,
+            expected
+        );
+
+        assert_eq!(
+            best_route(
+                graph.borrow_mut(),
+                query(AllowedModes::ALL.with(Mode::Walking, false))
+            )
+            .unwrap_err(),
+            Error::WalkingNotAllowed
+        );
+    }
+
+    #[test]
+    fn parallel_construction_matches_serial() {
+        let mut state = setup_state();
+        // a highway as well, so that both partial graphs and every mode have something in them
+        let on_ramp = state.highways.add_junction(
+            (10.0, 12.0),
+            highway::HighwayJunction {
+                ramp: Some(highway::RampDirection::OnRamp),
+            },
+        );
+        let off_ramp = state.highways.add_junction(
+            (150.0, 12.0),
+            highway::HighwayJunction {
+                ramp: Some(highway::RampDirection::OffRamp),
+            },
+        );
+        state.highways.add_segment(
+            highway::HighwaySegment {
+                name: None,
+                refs: vec![],
+                lanes: None,
+                speed_limit: Some(30),
+            },
+            on_ramp,
+            off_ramp,
+            Some(vec![(10.0, 12.0).into(), (150.0, 12.0).into()]),
+        );
+
+        let construct = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| {
+                    construct_base_graph(BaseGraphInput {
+                        state: &state,
+                        filter_metro_lines: None,
+                        filter_highway_segments: None,
+                        add_inferred_edges: true,
+                        validate_highways: true,
+                    })
+                    .unwrap()
+                })
+        };
+        let serial = construct(1);
+        let parallel = construct(4);
+
+        assert!(serial.graph.node_count() > 0);
+        assert_eq!(serial.graph.node_count(), parallel.graph.node_count());
+        assert_eq!(serial.graph.edge_count(), parallel.graph.edge_count());
+        assert_eq!(
+            serial.graph.get_node_map(),
+            parallel.graph.get_node_map(),
+            "node indices differ"
+        );
+        let edges = |graph: &Graph| {
+            let mut edges: Vec<_> = graph
+                .graph
+                .get_edge_map()
+                .iter()
+                .map(|(key, edge)| (*key, format!("{:?}", edge)))
+                .collect();
+            edges.sort();
+            edges
+        };
+        assert_eq!(edges(&serial), edges(&parallel));
+
+        let start = address(START.0, START.1);
+        for car_config in [None, Some(CarConfig::StartWithCar)] {
+            assert_eq!(
+                best_route_one_to_many(&serial, start, &candidates(), car_config, None).unwrap(),
+                best_route_one_to_many(&parallel, start, &candidates(), car_config, None).unwrap(),
+            );
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+use crate::route::Route;
+use crate::traffic::{WorldState, WorldStateHistory, WorldStateImpl};
+
+/**
+ * How long a route takes across the stored traffic snapshots and the live state, in seconds. The
+ * planned cost is what the router expected when it chose the route; a wide spread between the
+ * median and p90 means that the route is fast on a good day but unreliable.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReliabilityStats {
+    pub planned: f64,
+    pub min: f64,
+    pub median: f64,
+    pub p90: f64,
+    pub max: f64,
+    /// the number of world states that the route was evaluated against
+    pub samples: usize,
+}
+
+impl ReliabilityStats {
+    /// Panics if there are no samples.
+    pub fn from_samples(planned: f64, mut samples: Vec<f64>) -> Self {
+        assert!(!samples.is_empty(), "no samples");
+        samples.sort_by(f64::total_cmp);
+        // nearest rank, so every statistic is one of the samples
+        let percentile = |fraction: f64| {
+            let rank = (samples.len() as f64 * fraction).ceil() as usize;
+            samples[rank.clamp(1, samples.len()) - 1]
+        };
+        Self {
+            planned,
+            min: samples[0],
+            median: percentile(0.5),
+            p90: percentile(0.9),
+            max: samples[samples.len() - 1],
+            samples: samples.len(),
+        }
+    }
+
+    /// The difference between the p90 and the median, which is how much slack to leave.
+    pub fn spread(&self) -> f64 {
+        self.p90 - self.median
+    }
+}
+
+impl Route {
+    /**
+     * The total cost of the route's edges under the given world state, weighted the same way as
+     * the router weights them. This sums over the fixed edge list rather than searching, so it is
+     * cheap enough to evaluate a route against many world states, e.g. each traffic snapshot.
+     */
+    pub fn cost_under<W: WorldState, F: state::Fields>(
+        &self,
+        world_state: &W,
+        state: &state::State<F>,
+    ) -> f64 {
+        self.edges
+            .iter()
+            .map(|edge| edge.cost(world_state, state, None))
+            .sum()
+    }
+
+    /// How long the route takes under each snapshot in the history and under the live state.
+    pub fn reliability<F: state::Fields>(
+        &self,
+        history: &WorldStateHistory,
+        live: &WorldStateImpl,
+        state: &state::State<F>,
+    ) -> ReliabilityStats {
+        let samples = history
+            .get_snapshots()
+            .iter()
+            .chain(std::iter::once(live))
+            .map(|world_state| self.cost_under(world_state, state))
+            .collect();
+        ReliabilityStats::from_samples(self.cost as f64, samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReliabilityStats;
+
+    #[test]
+    fn percentiles() {
+        let samples = vec![50.0, 10.0, 40.0, 20.0, 30.0, 90.0, 60.0, 80.0, 70.0, 100.0];
+        let stats = ReliabilityStats::from_samples(25.0, samples);
+        assert_eq!(stats.min, 10.0);
+        assert_eq!(stats.median, 50.0);
+        assert_eq!(stats.p90, 90.0);
+        assert_eq!(stats.max, 100.0);
+        assert_eq!(stats.samples, 10);
+        assert_eq!(stats.spread(), 40.0);
+
+        let single = ReliabilityStats::from_samples(5.0, vec![7.0]);
+        assert_eq!((single.min, single.median, single.p90), (7.0, 7.0, 7.0));
+    }
+}
@@ -1,12 +1,18 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use memory_size::{vec_bytes, MemorySize};
 use once_cell::unsync::OnceCell;
 use serde::{Deserialize, Serialize};
 
 pub use spline_util::SplineVisitor;
 
+use crate::base_graph::GraphVersion;
 use crate::common::{Error, Mode, QueryInput};
 use crate::edge::Edge;
 use crate::node::Node;
 use crate::route_key::RouteKey;
+use crate::traffic::WorldState;
 
 struct ConstructedSplines {
     keys: Vec<RouteKey>,
@@ -20,6 +26,89 @@ struct SplineData {
     total: f32,
 }
 
+/// the most resolutions that are cached for a route at once
+const MAX_GEOMETRY_VARIANTS: usize = 3;
+/// longer routes at higher resolutions are sampled every time rather than held in memory
+const MAX_CACHED_SAMPLES: usize = 1 << 14;
+
+/// The dist spline sampled at every multiple of a step, see Route::visit_spline.
+#[derive(Debug, Clone)]
+struct Geometry {
+    /// the step is 2^bucket
+    bucket: i32,
+    samples: Arc<[RouteKey]>,
+}
+
+/**
+ * Sampled geometry for the few resolutions that the route was drawn at most recently. Zooming
+ * changes the resolution continuously, so resolutions are rounded down to a power of two.
+ */
+#[derive(Debug, Clone, Default)]
+struct GeometryCache {
+    variants: RefCell<Vec<Geometry>>,
+}
+
+impl GeometryCache {
+    fn get_or_insert(
+        &self,
+        bucket: i32,
+        sample: impl FnOnce() -> Vec<RouteKey>,
+    ) -> Arc<[RouteKey]> {
+        let mut variants = self.variants.borrow_mut();
+        if let Some(geometry) = variants.iter().find(|geometry| geometry.bucket == bucket) {
+            return geometry.samples.clone();
+        }
+        if variants.len() >= MAX_GEOMETRY_VARIANTS {
+            variants.remove(0);
+        }
+        let samples: Arc<[RouteKey]> = sample().into();
+        variants.push(Geometry {
+            bucket,
+            samples: samples.clone(),
+        });
+        samples
+    }
+
+    fn clear(&mut self) {
+        self.variants.get_mut().clear();
+    }
+}
+
+impl MemorySize for GeometryCache {
+    fn heap_bytes(&self) -> usize {
+        self.variants
+            .borrow()
+            .iter()
+            .map(|geometry| std::mem::size_of_val(&*geometry.samples))
+            .sum()
+    }
+}
+
+/// The power of two that the step is rounded down to when drawing.
+fn step_bucket(step: f64) -> i32 {
+    step.log2().floor() as i32
+}
+
+/// A point along a route; see Route::time_profile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProfileSample {
+    /// the distance along the route, in meters
+    pub dist: f64,
+    /// the time since the start of the route, in seconds
+    pub time: f64,
+    /// how this sample was reached from the previous one, or None while riding or waiting for a
+    /// metro
+    pub mode: Option<Mode>,
+}
+
+impl ProfileSample {
+    /// The average speed between the previous sample and this one, in m/s, if any time passed.
+    pub fn speed_since(&self, prev: &Self) -> Option<f64> {
+        let dt = self.time - prev.time;
+        (dt > 0.0).then(|| (self.dist - prev.dist) / dt)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Route {
     pub nodes: Vec<Node>,
@@ -29,18 +118,40 @@ pub struct Route {
     pub bounds: quadtree::Rect,
     pub start_mode: Mode,
     pub end_mode: Mode,
+    /// see Engine::is_route_current
+    #[serde(default)]
+    pub graph_version: GraphVersion,
     // NOTE: we store time and dist splines separately because dist spline is rarely used and this
     // saves a ton of memory.
     #[serde(skip)]
     time_spline: OnceCell<SplineData>,
     #[serde(skip)]
     dist_spline: OnceCell<SplineData>,
+    #[serde(skip)]
+    geometry: GeometryCache,
 }
 
 fn f64p_f32p((x, y): (f64, f64)) -> (f32, f32) {
     (x as f32, y as f32)
 }
 
+/// Counts the splines and sampled geometry too if they have been computed, since they are cached
+/// with the route.
+impl MemorySize for Route {
+    fn heap_bytes(&self) -> usize {
+        let spline_bytes = |spline: &OnceCell<SplineData>| {
+            spline
+                .get()
+                .map_or(0, |data| std::mem::size_of_val(data.spline.keys()))
+        };
+        vec_bytes(&self.nodes)
+            + vec_bytes(&self.edges)
+            + spline_bytes(&self.time_spline)
+            + spline_bytes(&self.dist_spline)
+            + self.geometry.heap_bytes()
+    }
+}
+
 impl Route {
     pub fn new(
         nodes: Vec<Node>,
@@ -58,8 +169,10 @@ impl Route {
             query_input,
             start_mode,
             end_mode,
+            graph_version: GraphVersion::default(),
             time_spline: OnceCell::new(),
             dist_spline: OnceCell::new(),
+            geometry: GeometryCache::default(),
         }
     }
 
@@ -103,7 +216,8 @@ impl Route {
         let mut t: f32 = 0.0; // total elapsed time
 
         for ((start, end), edge) in self.iter() {
-            let dt = edge.base_cost(state) as f32;
+            // NOTE: agents move at the speed they planned for, e.g. slower if they walk slowly
+            let dt = edge.profile_cost(edge.base_cost(state), &self.query_input.profile) as f32;
             // TODO: there may be some errors in dimensional analysis, i.e. meters vs coordinates
             let start_location = f64p_f32p(start.location());
             let end_location = f64p_f32p(end.location());
@@ -117,43 +231,55 @@ impl Route {
                 } => {
                     use metro::RailwayTiming;
 
-                    let metro_line = state.metros.metro_line(*metro_line_id);
-                    let segment = state.railways.segment(oriented_segment.segment);
-                    let dist_spline = segment.railway_dist_spline(
-                        metro_line.data.speed_limit,
-                        state.config.min_tile_size as f64,
-                        &state.railways,
-                    );
-
-                    oriented_segment.maybe_reversed_iter(dist_spline.keys().iter(), |key| {
-                        let dist = key.value / state.config.min_tile_size as f64;
-                        let location = segment.spline().clamped_sample(dist).unwrap();
-                        // TODO: it is probably insufficient to describe this as walking
-                        keys.push(RouteKey::new(
-                            f64p_f32p(location.into()),
-                            d + dist as f32,
-                            t + key.t as f32,
-                            Mode::Walking,
-                        ));
-                    });
+                    // stored routes can outlive the network; anything removed since is skipped over
+                    match (
+                        state.metros.try_metro_line(*metro_line_id),
+                        state.railways.try_segment(oriented_segment.segment),
+                    ) {
+                        (Some(metro_line), Some(segment)) => {
+                            let dist_spline = segment.railway_dist_spline(
+                                metro_line.data.speed_limit,
+                                state.config.min_tile_size as f64,
+                                &state.railways,
+                            );
 
-                    dd = segment.length() as f32;
+                            oriented_segment.maybe_reversed_iter(
+                                dist_spline.keys().iter(),
+                                |key| {
+                                    let dist = key.value / state.config.min_tile_size as f64;
+                                    let location = segment.spline().clamped_sample(dist).unwrap();
+                                    // TODO: it is probably insufficient to describe this as walking
+                                    keys.push(RouteKey::new(
+                                        f64p_f32p(location.into()),
+                                        d + dist as f32,
+                                        t + key.t as f32,
+                                        Mode::Walking,
+                                    ));
+                                },
+                            );
+
+                            dd = segment.length() as f32;
+                        }
+                        _ => dd = default_dd,
+                    }
                 }
                 Edge::MetroEmbark { .. } | Edge::MetroDisembark { .. } => {
                     dd = default_dd;
                 }
-                Edge::Highway { segment, .. } => {
-                    let segment = state.highways.segment(*segment);
-                    for key in segment.spline_keys() {
-                        keys.push(RouteKey::new(
-                            f64p_f32p(key.value.into()),
-                            d + key.t as f32,
-                            t + dt * (key.t / segment.length()) as f32,
-                            Mode::Driving,
-                        ));
+                Edge::Highway { segment, .. } => match state.highways.try_segment(*segment) {
+                    Some(segment) => {
+                        for key in segment.spline_keys() {
+                            keys.push(RouteKey::new(
+                                f64p_f32p(key.value.into()),
+                                d + key.t as f32,
+                                t + dt * (key.t / segment.length()) as f32,
+                                Mode::Driving,
+                            ));
+                        }
+                        dd = segment.length() as f32;
                     }
-                    dd = segment.length() as f32;
-                }
+                    None => dd = default_dd,
+                },
                 Edge::HighwayRamp { .. } => {
                     dd = default_dd;
                 }
@@ -217,6 +343,11 @@ impl Route {
             .get_or_init(|| self.construct_dist_spline(state))
     }
 
+    /**
+     * Visit the route with vertices spaced about `step` apart, rounded down to a power of two.
+     * The vertices are sampled once per rounded step and cached on the route, so drawing the
+     * same route every frame is cheap. The cache is dropped by invalidate_geometry.
+     */
     pub fn visit_spline<V, E, F: state::Fields>(
         &self,
         visitor: &mut V,
@@ -224,6 +355,54 @@ impl Route {
         rect: &quadtree::Rect,
         state: &state::State<F>,
     ) -> Result<(), E>
+    where
+        V: SplineVisitor<Route, RouteKey, E>,
+    {
+        let bucket = step_bucket(step);
+        let step = 2_f64.powi(bucket);
+        let spline = self.get_dist_spline(state);
+        if (spline.total as f64 / step) as usize >= MAX_CACHED_SAMPLES {
+            return self.visit_dist_spline(visitor, step, rect, state);
+        }
+
+        let samples = self.geometry.get_or_insert(bucket, || {
+            spline_util::sample_spline(&spline.spline, spline.total, step)
+        });
+        spline_util::visit_sampled_spline(
+            self,
+            &samples,
+            spline.total,
+            visitor,
+            step,
+            rect,
+            |key| key.position.into(),
+        )
+    }
+
+    /**
+     * Visit the same vertices as visit_spline, but sample them from the spline every time instead
+     * of using the cache.
+     */
+    pub fn visit_spline_uncached<V, E, F: state::Fields>(
+        &self,
+        visitor: &mut V,
+        step: f64,
+        rect: &quadtree::Rect,
+        state: &state::State<F>,
+    ) -> Result<(), E>
+    where
+        V: SplineVisitor<Route, RouteKey, E>,
+    {
+        self.visit_dist_spline(visitor, 2_f64.powi(step_bucket(step)), rect, state)
+    }
+
+    fn visit_dist_spline<V, E, F: state::Fields>(
+        &self,
+        visitor: &mut V,
+        step: f64,
+        rect: &quadtree::Rect,
+        state: &state::State<F>,
+    ) -> Result<(), E>
     where
         V: SplineVisitor<Route, RouteKey, E>,
     {
@@ -239,6 +418,41 @@ impl Route {
         )
     }
 
+    /**
+     * Drop the geometry cached for drawing, so that it is rebuilt from the nodes and edges the
+     * next time it is needed. The time spline is kept, since agents that are partway along the
+     * route rely on it.
+     */
+    pub fn invalidate_geometry(&mut self) {
+        self.dist_spline = OnceCell::new();
+        self.geometry.clear();
+    }
+
+    /**
+     * Sample the route as a polyline in model coordinates, with samples spaced `resolution` apart
+     * along the route.
+     */
+    pub fn sample_polyline<F: state::Fields>(
+        &self,
+        resolution: f64,
+        state: &state::State<F>,
+    ) -> Vec<(f64, f64)> {
+        assert!(resolution > 0.0, "resolution must be positive");
+        let mut visitor = spline_util::PolylineVisitor::new(|key: &RouteKey| {
+            (key.position.0 as f64, key.position.1 as f64)
+        });
+        // NOTE: the bounds only cover the nodes, and edges can stray outside of them
+        let width = state.qtree.width();
+        self.visit_dist_spline(
+            &mut visitor,
+            resolution,
+            &spline_util::pad_bounds(&quadtree::Rect::xywh(0, 0, width, width)),
+            state,
+        )
+        .unwrap();
+        visitor.polylines.concat()
+    }
+
     /**
      * Get the route key at the given time, relative to the start of the route.
      */
@@ -256,6 +470,123 @@ impl Route {
         spline.total
     }
 
+    /// The length of the route in meters.
+    pub fn length_meters<F: state::Fields>(&self, state: &state::State<F>) -> f64 {
+        self.total_dist(state) as f64 * state.config.min_tile_size as f64
+    }
+
+    /**
+     * The distance and time at points along the route, for plotting the speed along it. Points are
+     * as far apart as the splines of the edges they are on. The time spent on each edge is its cost
+     * under the given world state, so congestion slows down driving, and the profile ends at the
+     * cost of the route if the world state is the one it was planned with. Legs that go nowhere,
+     * like waiting for a metro or looking for parking, only add time, so the speed drops to zero
+     * for them instead of being averaged into the legs around them.
+     */
+    pub fn time_profile<W: WorldState, F: state::Fields>(
+        &self,
+        state: &state::State<F>,
+        world_state: &W,
+    ) -> Vec<ProfileSample> {
+        use cgmath::MetricSpace;
+
+        let tile_size = state.config.min_tile_size as f64;
+        let mut samples = vec![ProfileSample {
+            dist: 0.0,
+            time: 0.0,
+            mode: Some(self.start_mode),
+        }];
+
+        let (mut d, mut t) = (0.0, 0.0);
+        for ((start, end), edge) in self.iter() {
+            let dt = edge.profile_cost(
+                edge.cost(world_state, state, None),
+                &self.query_input.profile,
+            );
+            let straight =
+                cgmath::Vector2::from(start.location()).distance(end.location().into()) * tile_size;
+
+            // points strictly inside the edge, as (distance, time) relative to its start
+            let mut inner = Vec::new();
+            let (dd, mode) = match edge {
+                Edge::MetroSegment {
+                    metro_line: metro_line_id,
+                    oriented_segment,
+                    ..
+                } => {
+                    use metro::RailwayTiming;
+
+                    match (
+                        state.metros.try_metro_line(*metro_line_id),
+                        state.railways.try_segment(oriented_segment.segment),
+                    ) {
+                        (Some(metro_line), Some(segment)) => {
+                            let keys = segment
+                                .railway_dist_spline(
+                                    metro_line.data.speed_limit,
+                                    tile_size,
+                                    &state.railways,
+                                )
+                                .keys();
+                            let (length, duration) = keys
+                                .last()
+                                .map_or((straight, 0.0), |key| (key.value, key.t));
+                            // the edge cost can differ a bit from the timing, e.g. since every edge
+                            // takes at least a second, so stretch the timing to match it
+                            let scale = if duration > 0.0 { dt / duration } else { 0.0 };
+                            oriented_segment.maybe_reversed_iter(keys.iter(), |key| {
+                                let (dist, time) = if oriented_segment.forward {
+                                    (key.value, key.t)
+                                } else {
+                                    (length - key.value, duration - key.t)
+                                };
+                                inner.push((dist, time * scale));
+                            });
+                            (length, None)
+                        }
+                        // removed since the route was planned, so there is no timing to follow
+                        _ => (straight, None),
+                    }
+                }
+                Edge::MetroEmbark { .. } | Edge::MetroDisembark { .. } => (straight, None),
+                Edge::Highway { segment, .. } => match state.highways.try_segment(*segment) {
+                    Some(segment) => {
+                        let length = segment.length();
+                        if length > 0.0 {
+                            for key in segment.spline_keys() {
+                                inner.push((key.t * tile_size, dt * key.t / length));
+                            }
+                        }
+                        (length * tile_size, Some(Mode::Driving))
+                    }
+                    None => (straight, Some(Mode::Driving)),
+                },
+                Edge::HighwayRamp { .. } => (straight, Some(Mode::Driving)),
+                Edge::ModeSegment { mode, distance, .. } => (*distance, Some(*mode)),
+                Edge::ModeTransition { from, .. } => (0.0, Some(*from)),
+            };
+
+            for (dist, time) in inner {
+                if time > 0.0 && time < dt {
+                    samples.push(ProfileSample {
+                        dist: d + dist,
+                        time: t + time,
+                        mode,
+                    });
+                }
+            }
+            d += dd;
+            t += dt;
+            samples.push(ProfileSample {
+                dist: d,
+                time: t,
+                mode,
+            });
+        }
+
+        samples
+    }
+
     pub fn total_cost(&self) -> f32 {
         self.cost
     }
@@ -279,8 +610,8 @@ impl Route {
             first.edges.push(Edge::ModeSegment {
                 mode: first.end_mode,
                 distance: 0.0,
-                start: first.end().to_xy_f64(),
-                stop: second.start().to_xy_f64(),
+                start: first.end().to_center_f64(),
+                stop: second.start().to_center_f64(),
             });
         } else {
             first.edges.push(Edge::ModeTransition {
@@ -300,13 +631,17 @@ impl Route {
                 start: first.query_input.start,
                 end: second.query_input.end,
                 car_config: first.query_input.car_config,
+                profile: first.query_input.profile,
+                allowed_modes: Default::default(),
             },
             cost: first.cost + second.cost,
             bounds: first.bounds.and(&second.bounds),
             start_mode: first.start_mode,
             end_mode: second.end_mode,
+            graph_version: first.graph_version,
             time_spline: OnceCell::new(),
             dist_spline: OnceCell::new(),
+            geometry: GeometryCache::default(),
         }
     }
 
@@ -324,6 +659,12 @@ impl Route {
                 Node::Endpoint { address } | Node::Parking { address } if *address == from => {
                     *address = to
                 }
+                Node::Waypoint { position, address } if *address == from => {
+                    // keep the same position relative to the tile
+                    let ((fx, fy), (tx, ty)) = (from.to_xy_f64(), to.to_xy_f64());
+                    *position = (position.0 - fx + tx, position.1 - fy + ty);
+                    *address = to;
+                }
                 _ => (),
             }
         }
@@ -346,6 +687,396 @@ impl Route {
             self.query_input.end = to;
         }
 
+        self.invalidate_geometry();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod route_tests {
+    use crate::common::MobilityProfile;
+    use crate::route::*;
+
+    const MAX_DEPTH: u32 = 8;
+    const MIN_TILE_SIZE: u32 = 10;
+
+    #[derive(Debug, Default, Clone)]
+    struct DummyFields {}
+
+    impl state::Fields for DummyFields {}
+
+    fn address(x: u64, y: u64) -> quadtree::Address {
+        quadtree::Address::from_xy(x, y, MAX_DEPTH)
+    }
+
+    fn setup_state() -> state::State<DummyFields> {
+        state::State::new(state::Config {
+            max_depth: MAX_DEPTH,
+            people_per_sim: 1.0,
+            min_tile_size: MIN_TILE_SIZE,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        })
+    }
+
+    /// A walk that zig-zags across the map.
+    fn make_route() -> Route {
+        let start = address(2, 2);
+        let end = address(120, 100);
+        let nodes = vec![
+            Node::Endpoint { address: start },
+            Node::Waypoint {
+                position: (40.5, 10.5),
+                address: address(40, 10),
+            },
+            Node::Waypoint {
+                position: (60.5, 80.5),
+                address: address(60, 80),
+            },
+            Node::Endpoint { address: end },
+        ];
+        let edges = nodes
+            .windows(2)
+            .map(|pair| {
+                let (start, stop) = (pair[0].location(), pair[1].location());
+                Edge::ModeSegment {
+                    mode: Mode::Walking,
+                    distance: ((stop.0 - start.0).powi(2) + (stop.1 - start.1).powi(2)).sqrt()
+                        * MIN_TILE_SIZE as f64,
+                    start,
+                    stop,
+                }
+            })
+            .collect();
+        Route::new(
+            nodes,
+            edges,
+            0.0,
+            QueryInput {
+                start,
+                end,
+                car_config: None,
+                profile: MobilityProfile::STANDARD,
+                allowed_modes: Default::default(),
+            },
+            Mode::Walking,
+            Mode::Walking,
+        )
+    }
+
+    /// Records each visited vertex, and whether it continues the previous one.
+    #[derive(Debug, Default)]
+    struct CollectVisitor {
+        vertices: Vec<(f64, (f32, f32), bool)>,
+    }
+
+    impl SplineVisitor<Route, RouteKey, ()> for CollectVisitor {
+        fn visit(
+            &mut self,
+            _route: &Route,
+            vertex: RouteKey,
+            t: f64,
+            prev: Option<RouteKey>,
+        ) -> Result<(), ()> {
+            self.vertices.push((t, vertex.position, prev.is_some()));
+            Ok(())
+        }
+    }
+
+    fn visit(
+        route: &Route,
+        step: f64,
+        rect: &quadtree::Rect,
+        state: &state::State<DummyFields>,
+        cached: bool,
+    ) -> Vec<(f64, (f32, f32), bool)> {
+        let mut visitor = CollectVisitor::default();
+        if cached {
+            route.visit_spline(&mut visitor, step, rect, state).unwrap();
+        } else {
+            route
+                .visit_spline_uncached(&mut visitor, step, rect, state)
+                .unwrap();
+        }
+        visitor.vertices
+    }
+
+    #[test]
+    fn cached_matches_uncached() {
+        let state = setup_state();
+        let route = make_route();
+        let rects = [
+            quadtree::Rect::xywh(0, 0, 256, 256),
+            // only part of the route is visible
+            quadtree::Rect::xywh(30, 0, 40, 50),
+        ];
+        for step in [0.2, 0.5, 0.7, 1.0, 3.0, 16.0] {
+            for rect in &rects {
+                let uncached = visit(&route, step, rect, &state, false);
+                assert!(!uncached.is_empty());
+                // the first visit fills the cache and the second one uses it
+                assert_eq!(visit(&route, step, rect, &state, true), uncached);
+                assert_eq!(visit(&route, step, rect, &state, true), uncached);
+            }
+        }
+
+        // the part of the route outside of the rectangle is skipped
+        let partial = visit(&route, 1.0, &rects[1], &state, true);
+        assert!(partial.len() < visit(&route, 1.0, &rects[0], &state, true).len());
+        assert!(partial.first().unwrap().0 > 0.0);
+    }
+
+    #[test]
+    fn steps_are_bucketed() {
+        let state = setup_state();
+        let route = make_route();
+        let rect = quadtree::Rect::xywh(0, 0, 256, 256);
+
+        // steps that round down to the same power of two share geometry
+        assert_eq!(
+            visit(&route, 0.5, &rect, &state, true),
+            visit(&route, 0.9, &rect, &state, true)
+        );
+        assert_eq!(route.geometry.variants.borrow().len(), 1);
+
+        for step in [0.25, 1.0, 2.0, 4.0] {
+            visit(&route, step, &rect, &state, true);
+        }
+        let buckets: Vec<_> = route
+            .geometry
+            .variants
+            .borrow()
+            .iter()
+            .map(|geometry| geometry.bucket)
+            .collect();
+        assert_eq!(buckets, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn invalidate_geometry() {
+        let state = setup_state();
+        let mut route = make_route();
+        let rect = quadtree::Rect::xywh(0, 0, 256, 256);
+        let before = visit(&route, 1.0, &rect, &state, true);
+        assert!(route.heap_bytes() > vec_bytes(&route.nodes) + vec_bytes(&route.edges));
+
+        // moving the start moves the drawn route along with it
+        route.patch_tile(address(2, 2), address(4, 2)).unwrap();
+        assert!(route.geometry.variants.borrow().is_empty());
+        let after = visit(&route, 1.0, &rect, &state, true);
+        assert_ne!(after, before);
+        assert_eq!(after, visit(&route, 1.0, &rect, &state, false));
+    }
+
+    /**
+     * A route along the given waypoints, with each leg traveled by the given mode. A leg with no
+     * mode switches between the modes of the legs around it in place.
+     */
+    fn make_legs_route(
+        waypoints: &[(u64, u64)],
+        modes: &[Option<Mode>],
+        world_state: &crate::traffic::WorldStateImpl,
+        state: &state::State<DummyFields>,
+    ) -> Route {
+        assert_eq!(waypoints.len(), modes.len() + 1);
+        let nodes: Vec<Node> = waypoints
+            .iter()
+            .map(|&(x, y)| Node::Waypoint {
+                position: (x as f64 + 0.5, y as f64 + 0.5),
+                address: address(x, y),
+            })
+            .collect();
+        let edges: Vec<Edge> = nodes
+            .windows(2)
+            .enumerate()
+            .map(|(i, pair)| {
+                let (start, stop) = (pair[0].location(), pair[1].location());
+                match modes[i] {
+                    Some(mode) => Edge::ModeSegment {
+                        mode,
+                        distance: ((stop.0 - start.0).powi(2) + (stop.1 - start.1).powi(2)).sqrt()
+                            * MIN_TILE_SIZE as f64,
+                        start,
+                        stop,
+                    },
+                    None => Edge::ModeTransition {
+                        from: modes[i - 1].unwrap(),
+                        to: modes[i + 1].unwrap(),
+                        address: pair[0].address(),
+                    },
+                }
+            })
+            .collect();
+        let cost: f64 = edges
+            .iter()
+            .map(|edge| edge.cost(world_state, state, None))
+            .sum();
+        let (start, end) = (nodes[0].address(), nodes[nodes.len() - 1].address());
+        Route::new(
+            nodes,
+            edges,
+            cost as f32,
+            QueryInput {
+                start,
+                end,
+                car_config: None,
+                profile: MobilityProfile::STANDARD,
+                allowed_modes: Default::default(),
+            },
+            modes[0].unwrap(),
+            modes[modes.len() - 1].unwrap(),
+        )
+    }
+
+    #[test]
+    fn time_profile_speeds() {
+        let state = setup_state();
+        let world_state = crate::traffic::WorldStateImpl::new(&state.config);
+        // walk 300 meters, then bike 800 meters
+        let route = make_legs_route(
+            &[(10, 10), (40, 10), (40, 90)],
+            &[Some(Mode::Walking), Some(Mode::Biking)],
+            &world_state,
+            &state,
+        );
+
+        let profile = route.time_profile(&state, &world_state);
+        assert_eq!(profile.first().unwrap().time, 0.0);
+        let last = profile.last().unwrap();
+        assert!((last.dist - 1100.0).abs() < 1e-6, "{:?}", last);
+        assert!(
+            (last.time - route.total_cost() as f64).abs() < 1e-2,
+            "{:?}",
+            last
+        );
+
+        for pair in profile.windows(2) {
+            let mode = pair[1].mode.unwrap();
+            let speed = pair[1].speed_since(&pair[0]).unwrap();
+            assert!(
+                (speed - mode.linear_speed()).abs() < 1e-6,
+                "{} at {}",
+                mode,
+                speed
+            );
+        }
+        assert_eq!(
+            profile.iter().filter_map(|sample| sample.mode).last(),
+            Some(Mode::Biking)
+        );
+    }
+
+    #[test]
+    fn time_profile_transitions_are_flat() {
+        let state = setup_state();
+        let world_state = crate::traffic::WorldStateImpl::new(&state.config);
+        let route = make_legs_route(
+            &[(10, 10), (40, 10), (40, 10), (40, 90)],
+            &[Some(Mode::Walking), None, Some(Mode::Biking)],
+            &world_state,
+            &state,
+        );
+
+        let profile = route.time_profile(&state, &world_state);
+        assert_eq!(profile.len(), 4);
+        // the transition takes time but goes nowhere, rather than slowing down the legs around it
+        let transition = profile[2].speed_since(&profile[1]).unwrap();
+        assert_eq!(transition, 0.0);
+        assert!(profile[2].time > profile[1].time);
+        assert!((profile[1].speed_since(&profile[0]).unwrap() - 1.5).abs() < 1e-6);
+        assert!((profile[3].speed_since(&profile[2]).unwrap() - 6.7).abs() < 1e-6);
+        assert!((profile[3].time - route.total_cost() as f64).abs() < 1e-2);
+    }
+
+    #[test]
+    fn length_meters() {
+        let state = setup_state();
+        let route = make_route();
+        let expected: f64 = route
+            .edges
+            .iter()
+            .map(|edge| match edge {
+                Edge::ModeSegment { distance, .. } => *distance,
+                _ => unreachable!(),
+            })
+            .sum();
+        assert!((route.length_meters(&state) - expected).abs() < 1e-2);
+    }
+
+    #[test]
+    fn removed_segment() {
+        let mut state = setup_state();
+        let world_state = crate::traffic::WorldStateImpl::new(&state.config);
+
+        let points = [(10.5, 10.5), (40.5, 10.5)];
+        let junctions: Vec<_> = points
+            .iter()
+            .map(|point| {
+                state
+                    .highways
+                    .add_junction(*point, highway::HighwayJunction::new(None))
+            })
+            .collect();
+        let data = highway::HighwaySegment::new(None, vec![], None, Some(40));
+        let segment = state.highways.add_segment(
+            data.clone(),
+            junctions[0],
+            junctions[1],
+            Some(vec![points[0].into(), points[1].into()]),
+        );
+        let time = 30.0 * MIN_TILE_SIZE as f64 / 40.0;
+        let route = Route::new(
+            vec![
+                Node::Waypoint {
+                    position: points[0],
+                    address: address(10, 10),
+                },
+                Node::Waypoint {
+                    position: points[1],
+                    address: address(40, 10),
+                },
+            ],
+            vec![Edge::Highway {
+                segment,
+                data,
+                time,
+            }],
+            time as f32,
+            QueryInput {
+                start: address(10, 10),
+                end: address(40, 10),
+                car_config: None,
+                profile: MobilityProfile::STANDARD,
+                allowed_modes: Default::default(),
+            },
+            Mode::Driving,
+            Mode::Driving,
+        );
+
+        // routes that agents are following can outlive the segments that they travel along
+        state.highways.remove_segment(segment);
+        assert!(state.highways.try_segment(segment).is_none());
+
+        let edge = &route.edges[0];
+        assert_eq!(edge.cost(&world_state, &state, None), time);
+        assert!(!edge.is_jammed(&world_state, &state));
+        let (x, y) = edge.interpolate_position(&state, &route.nodes[0], &route.nodes[1], 0.5);
+        assert!((x - 25.5).abs() < 1e-6 && (y - 10.5).abs() < 1e-6);
+
+        // the segment was straight, so going straight across instead is just as long
+        let length = 30.0 * MIN_TILE_SIZE as f64;
+        assert!((route.length_meters(&state) - length).abs() < 1e-2);
+        let profile = route.time_profile(&state, &world_state);
+        let last = profile.last().unwrap();
+        assert!((last.dist - length).abs() < 1e-6, "{:?}", last);
+        assert!((last.time - time).abs() < 1e-6, "{:?}", last);
+    }
+}
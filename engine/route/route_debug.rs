@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use crate::edge::CostComponents;
+use crate::route::Route;
+
+/// A node shared by two routes, where the routes leave along different edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteDivergence {
+    /// the index of the edge that the first route takes from the shared node
+    pub edge: usize,
+    /// the index of the edge that the other route takes from the shared node instead
+    pub other_edge: usize,
+}
+
+/**
+ * A route along with the cost components of each of its edges, to explain why the router chose it.
+ * If traffic made the router pick something other than the best route in free-flow conditions, the
+ * free-flow route is included so that the two can be compared.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteDebug {
+    pub route: Route,
+    /// the cost components of each edge of the route, in order
+    pub components: Vec<CostComponents>,
+    /// the time that the edge weights predicted traffic for, or None if they were free-flow weights
+    pub traffic_time: Option<u64>,
+    /// the best route without any traffic, if it is different from the chosen route
+    pub free_flow_route: Option<Route>,
+    /// the cost components of each edge of the free-flow route, under the same traffic conditions
+    /// as the chosen route
+    pub free_flow_components: Vec<CostComponents>,
+    /// where the chosen route left the free-flow route, as indices into the two routes' edges
+    pub divergences: Vec<RouteDivergence>,
+}
+
+/**
+ * Find the points where `route` leaves `other`: nodes that both routes pass through, but that the
+ * two routes leave along edges to different nodes.
+ */
+pub fn find_divergences(route: &Route, other: &Route) -> Vec<RouteDivergence> {
+    let mut divergences = Vec::new();
+    for (edge, node) in route.nodes.iter().enumerate().take(route.edges.len()) {
+        let other_edge = other
+            .nodes
+            .iter()
+            .take(other.edges.len())
+            .position(|other_node| other_node == node);
+        if let Some(other_edge) = other_edge {
+            if route.nodes[edge + 1] != other.nodes[other_edge + 1] {
+                divergences.push(RouteDivergence { edge, other_edge });
+            }
+        }
+    }
+    divergences
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{MobilityProfile, Mode, QueryInput};
+    use crate::edge::Edge;
+    use crate::node::Node;
+    use crate::route_debug::*;
+
+    fn parking(x: u64, y: u64) -> Node {
+        Node::Parking {
+            address: quadtree::Address::from_xy(x, y, 4),
+        }
+    }
+
+    fn walk(points: &[(u64, u64)]) -> Route {
+        let nodes: Vec<Node> = points.iter().map(|(x, y)| parking(*x, *y)).collect();
+        let edges = points
+            .windows(2)
+            .map(|pair| Edge::ModeSegment {
+                mode: Mode::Walking,
+                distance: 1.0,
+                start: (pair[0].0 as f64, pair[0].1 as f64),
+                stop: (pair[1].0 as f64, pair[1].1 as f64),
+            })
+            .collect();
+        let query_input = QueryInput {
+            start: nodes[0].address(),
+            end: nodes[nodes.len() - 1].address(),
+            car_config: None,
+            profile: MobilityProfile::STANDARD,
+            allowed_modes: Default::default(),
+        };
+        Route::new(nodes, edges, 0.0, query_input, Mode::Walking, Mode::Walking)
+    }
+
+    #[test]
+    fn divergences() {
+        let route = walk(&[(0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0)]);
+        let other = walk(&[(0, 0), (1, 0), (1, 1), (3, 0), (4, 0), (4, 1), (5, 0)]);
+
+        // the routes split at (1, 0) and (4, 0), and rejoin in between
+        assert_eq!(
+            find_divergences(&route, &other),
+            vec![
+                RouteDivergence {
+                    edge: 1,
+                    other_edge: 1,
+                },
+                RouteDivergence {
+                    edge: 4,
+                    other_edge: 4,
+                },
+            ]
+        );
+        assert!(find_divergences(&route, &route).is_empty());
+    }
+}
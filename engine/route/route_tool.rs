@@ -103,6 +103,8 @@ fn main() {
                     start,
                     end,
                     car_config,
+                    profile: route::MobilityProfile::STANDARD,
+                    allowed_modes: Default::default(),
                 },
             )
             .unwrap();
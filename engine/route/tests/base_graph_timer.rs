@@ -1,14 +1,32 @@
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use engine::{BaseGraph, Engine};
 
+fn time_construction(state: &state::State<engine::FieldsState>, threads: usize) -> Duration {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+    let start_time = Instant::now();
+    pool.install(|| BaseGraph::construct_base_graph(state).unwrap());
+    start_time.elapsed()
+}
+
 fn main() {
     let engine = Engine::load_file(&PathBuf::from("maps/sf.json")).unwrap();
-    let start_time = Instant::now();
-    BaseGraph::construct_base_graph(&engine.state).unwrap();
+    let threads = rayon::current_num_threads();
+    let serial = time_construction(&engine.state, 1);
+    let parallel = time_construction(&engine.state, threads);
     println!(
         "Total time to construct base graph: {:.4}",
-        start_time.elapsed().as_secs_f64(),
+        parallel.as_secs_f64(),
+    );
+    println!(
+        "Serial: {:.4}, parallel ({} threads): {:.4}, speedup: {:.2}x",
+        serial.as_secs_f64(),
+        threads,
+        parallel.as_secs_f64(),
+        serial.as_secs_f64() / parallel.as_secs_f64(),
     );
 }
@@ -9,7 +9,9 @@ fn benchmark(bench: &mut Bencher, (x, y): sf_routes::Coord, mode: route::Mode) {
     bench.iter(|| {
         let engine = &ENGINE.lock().unwrap();
         let address = engine.state.qtree.get_address(x, y).unwrap();
-        engine.query_isochrone_map(address, mode).unwrap();
+        engine
+            .query_isochrone_map(address, mode, Default::default())
+            .unwrap();
     });
 }
 
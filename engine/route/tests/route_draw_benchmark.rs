@@ -0,0 +1,71 @@
+use std::sync::Mutex;
+
+use bencher::{benchmark_group, benchmark_main, Bencher};
+use once_cell::sync::Lazy;
+
+/// how many routes are drawn each frame
+const ROUTES: usize = 100;
+/// roughly the step the app uses when zoomed in on a city
+const STEP: f64 = 0.5;
+
+struct Frame {
+    engine: engine::Engine,
+    routes: Vec<route::Route>,
+}
+
+/// The routes from the SF route tests, repeated until there are enough of them.
+static FRAME: Lazy<Mutex<Frame>> = Lazy::new(|| {
+    let (engine, graph) = sf_routes::setup();
+    let routes: Vec<_> = sf_routes::TESTS
+        .iter()
+        .filter_map(|test| sf_routes::perform_query(&engine, graph.borrow_mut(), test))
+        .collect();
+    let routes = routes.into_iter().cycle().take(ROUTES).collect();
+    Mutex::new(Frame { engine, routes })
+});
+
+struct CountVisitor {
+    vertices: usize,
+}
+
+impl route::SplineVisitor<route::Route, route::RouteKey, ()> for CountVisitor {
+    fn visit(
+        &mut self,
+        _route: &route::Route,
+        _vertex: route::RouteKey,
+        _t: f64,
+        _prev: Option<route::RouteKey>,
+    ) -> Result<(), ()> {
+        self.vertices += 1;
+        Ok(())
+    }
+}
+
+fn benchmark(bench: &mut Bencher, cached: bool) {
+    let frame = FRAME.lock().unwrap();
+    let width = frame.engine.state.qtree.width();
+    let rect = quadtree::Rect::xywh(0, 0, width, width);
+    bench.iter(|| {
+        let mut visitor = CountVisitor { vertices: 0 };
+        for route in &frame.routes {
+            let result = if cached {
+                route.visit_spline(&mut visitor, STEP, &rect, &frame.engine.state)
+            } else {
+                route.visit_spline_uncached(&mut visitor, STEP, &rect, &frame.engine.state)
+            };
+            result.unwrap();
+        }
+        visitor.vertices
+    });
+}
+
+fn cached_benchmark(bench: &mut Bencher) {
+    benchmark(bench, true);
+}
+
+fn uncached_benchmark(bench: &mut Bencher) {
+    benchmark(bench, false);
+}
+
+benchmark_group!(benches, cached_benchmark, uncached_benchmark);
+benchmark_main!(benches);
@@ -4,7 +4,7 @@ use lazy_static::lazy_static;
 
 use engine::Engine;
 use quadtree::Address;
-use route::{best_route, CarConfig, Edge, Graph, Node, QueryInput, Route};
+use route::{best_route, CarConfig, Edge, Graph, MobilityProfile, Node, QueryInput, Route};
 
 #[derive(Debug, Clone)]
 pub enum StringPredicate {
@@ -245,6 +245,8 @@ pub fn perform_query(
             start,
             end,
             car_config: test.car_config,
+            profile: MobilityProfile::STANDARD,
+            allowed_modes: Default::default(),
         },
     )
     .unwrap()
@@ -1,11 +1,11 @@
 use std::collections::HashMap;
 
+use memory_size::{hash_map_bytes, MemorySize};
 use serde::{Deserialize, Serialize};
-use uom::si::time::day;
-use uom::si::u64::Time;
 
 use crate::common::{Error, Mode};
 use crate::edge::Edge;
+use crate::zone_grid::{ZoneGrid, ZoneId};
 
 /// The weight of each new congestion observation on the running estimate.
 /// Larger values converge faster, but are less stable.
@@ -14,6 +14,31 @@ pub const OBSERVATION_WEIGHT: f64 = 0.3;
 /// threshold for driver counts (fractional because some edges are split over multiple grid tiles)
 const TOLERANCE: f64 = 0.0001;
 
+/**
+ * Guards against NaN and infinite traffic values, e.g. from a degenerate edge, which would
+ * otherwise poison route weights. These indicate a bug, so they panic in debug builds. In release
+ * builds they are returned as errors instead, so that one bad edge gets skipped rather than taking
+ * down a long-running simulation.
+ */
+fn check_finite<C>(value: f64, context: C) -> Result<(), Error>
+where
+    C: FnOnce() -> String,
+{
+    if value.is_finite() {
+        return Ok(());
+    }
+    let message = format!("degenerate edge: {} is {}", context(), value);
+    if cfg!(debug_assertions) {
+        panic!("{}", message);
+    }
+    Err(Error::EdgeCountingError(message))
+}
+
+/**
+ * NOTE: Coordinates passed to and returned from these methods are model coordinates. Local road
+ * and parking zones are keyed by the model coordinates of their corner closest to the origin.
+ * Coordinates that are off the map have no travelers and no parking.
+ */
 pub trait WorldState {
     fn get_highway_segment_travelers(&self, segment: network::SegmentHandle) -> f64;
     fn get_metro_segment_travelers(&self, segment: network::SegmentHandle) -> f64;
@@ -39,13 +64,11 @@ pub struct WorldStateImpl {
     /// travelers
     #[serde_as(as = "Vec<(_, _)>")]
     metro_segments: HashMap<network::SegmentHandle, f64>,
-    /// flattened grid of local traffic zones, row major
-    local_roads: Vec<f64>,
-    /// flattened grid of parking zones, row major
-    parking: Vec<f64>,
+    /// number of travelers on the local roads in each zone
+    local_roads: ZoneGrid<f64>,
+    /// number of cars parked in each zone
+    parking: ZoneGrid<f64>,
 
-    pub grid_downsample: u32,
-    grid_width: u32,
     min_tile_size: u32,
 
     /// map from addresses to the number of cars parked there
@@ -53,19 +76,26 @@ pub struct WorldStateImpl {
     parked_cars: HashMap<quadtree::Address, u64>,
 }
 
+impl MemorySize for WorldStateImpl {
+    fn heap_bytes(&self) -> usize {
+        hash_map_bytes(&self.highway_segments)
+            + hash_map_bytes(&self.metro_segments)
+            + self.local_roads.heap_bytes()
+            + self.parking.heap_bytes()
+            + hash_map_bytes(&self.parked_cars)
+    }
+}
+
 impl WorldStateImpl {
     pub fn new(config: &state::Config) -> Self {
         let grid_downsample = crate::local_traffic::grid_downsample(config);
         let grid_width = config.tile_width() / grid_downsample;
-        let grid_len = grid_width.pow(2) as usize;
 
         Self {
             highway_segments: HashMap::new(),
             metro_segments: HashMap::new(),
-            local_roads: vec![0.0; grid_len],
-            parking: vec![0.0; grid_len],
-            grid_downsample,
-            grid_width,
+            local_roads: ZoneGrid::new(grid_width, grid_downsample, 0.0),
+            parking: ZoneGrid::new(grid_width, grid_downsample, 0.0),
             min_tile_size: config.min_tile_size,
             parked_cars: HashMap::new(),
         }
@@ -95,14 +125,23 @@ impl WorldStateImpl {
                 start,
                 stop,
             } => {
+                check_finite(*distance, || format!("distance of {:?}", edge))?;
+                for coord in [start.0, start.1, stop.0, stop.1] {
+                    check_finite(coord, || format!("endpoint of {:?}", edge))?;
+                }
                 // avoid NaN
                 if *distance > 0.0 {
-                    let local_path: Vec<_> = self.local_path(*start, *stop).collect();
-                    for ((x, y), value) in local_path {
-                        let scaled_value = value / distance;
-                        assert!(scaled_value.is_normal());
+                    let local_path = self
+                        .local_path(*start, *stop)
+                        .map(|(coords, value)| (coords, value / distance))
+                        .collect::<Vec<_>>();
+                    // check everything up front so that a bad edge is not partially applied
+                    for (_, scaled_value) in &local_path {
+                        check_finite(*scaled_value, || format!("local traffic of {:?}", edge))?;
+                    }
+                    for (zone, scaled_value) in local_path {
                         if scaled_value > TOLERANCE {
-                            f(self.local_road_zone_mut(x, y), scaled_value)?;
+                            f(&mut self.local_roads[zone], scaled_value)?;
                         }
                     }
                 }
@@ -113,20 +152,23 @@ impl WorldStateImpl {
         Ok(())
     }
 
+    /// The local road zones along the straight line between two points in model coordinates.
     pub fn local_path(
         &self,
         start: (f64, f64),
         stop: (f64, f64),
-    ) -> impl Iterator<Item = ((u64, u64), f64)> + '_ {
-        let start = self.local_zone_downscale(start);
-        let stop = self.local_zone_downscale(stop);
-        line_drawing::XiaolinWu::<f64, i64>::new(start, stop).filter_map(|((x, y), value)| {
+    ) -> impl Iterator<Item = (ZoneId, f64)> + '_ {
+        let start = self.local_roads.downscale(start);
+        let stop = self.local_roads.downscale(stop);
+        let width = self.local_roads.width() as i64;
+        line_drawing::XiaolinWu::<f64, i64>::new(start, stop).filter_map(move |((x, y), value)| {
             // NOTE: XiaolinWu will return coordinates outside the grid; we can just ignore them
-            if x >= 0 && x < self.grid_width as i64 && y >= 0 && y < self.grid_width as i64 {
+            if x >= 0 && x < width && y >= 0 && y < width {
                 // scale number of people appropriately so that 1.0 is spread out across all
                 // blocks
-                let scaled_value = value * self.grid_downsample as f64 * self.min_tile_size as f64;
-                Some(((x as u64, y as u64), scaled_value))
+                let scaled_value =
+                    value * self.local_roads.downsample() as f64 * self.min_tile_size as f64;
+                Some((ZoneId::new(x as u32, y as u32), scaled_value))
             } else {
                 None
             }
@@ -135,8 +177,9 @@ impl WorldStateImpl {
 
     pub fn increment_edge_no_parking(&mut self, edge: &Edge) -> Result<(), Error> {
         self.apply_edge_entries(edge, |e, v| {
-            *e += v;
-            assert!(e.is_finite() && !e.is_nan());
+            let incremented = *e + v;
+            check_finite(incremented, || format!("travelers after adding {}", v))?;
+            *e = incremented;
             Ok(())
         })?;
         Ok(())
@@ -166,61 +209,55 @@ impl WorldStateImpl {
             if v - *e > TOLERANCE {
                 return Err(Error::EdgeCountingError(format!("e: {}, v: {}", e, v)));
             }
-            *e -= v;
-            if *e < -TOLERANCE {
-                *e = 0.0;
-            }
-            assert!(e.is_finite() && !e.is_nan());
+            let decremented = *e - v;
+            check_finite(decremented, || format!("travelers after removing {}", v))?;
+            *e = if decremented < -TOLERANCE {
+                0.0
+            } else {
+                decremented
+            };
             Ok(())
         })?;
 
         Ok(())
     }
 
-    fn local_zone_downscale(&self, (x, y): (f64, f64)) -> (f64, f64) {
-        (
-            (x / self.grid_downsample as f64),
-            (y / self.grid_downsample as f64),
-        )
+    pub fn local_roads(&self) -> &ZoneGrid<f64> {
+        &self.local_roads
     }
 
-    fn local_zone_upscale(&self, (x, y): (u64, u64)) -> (u64, u64) {
-        (
-            (x as f64 * self.grid_downsample as f64) as u64,
-            (y as f64 * self.grid_downsample as f64) as u64,
-        )
+    /// Whether the local road and parking grids have the block size that the config asks for.
+    pub fn grid_matches(&self, config: &state::Config) -> bool {
+        self.local_roads.downsample() == crate::local_traffic::grid_downsample(config)
     }
 
-    fn local_zone_index(&self, x: u64, y: u64) -> usize {
-        assert!(x <= self.grid_width as u64);
-        assert!(y <= self.grid_width as u64);
-        (self.grid_width as u64 * y + x) as usize
+    pub fn parking(&self) -> &ZoneGrid<f64> {
+        &self.parking
     }
 
-    fn local_zone_coords(&self, index: usize) -> (u64, u64) {
-        assert!(index < self.local_roads.len());
-        (
-            (index as u64 % self.grid_width as u64),
-            (index as u64 / self.grid_width as u64),
-        )
+    /// The number of cars parked anywhere on the map.
+    pub fn total_parked_cars(&self) -> u64 {
+        self.parked_cars.values().sum()
     }
 
-    fn local_road_zone(&self, x: u64, y: u64) -> f64 {
-        self.local_roads[self.local_zone_index(x, y)]
+    /// The number of cars parked at each address that has any.
+    pub fn parked_cars(&self) -> impl Iterator<Item = (quadtree::Address, u64)> + '_ {
+        self.parked_cars
+            .iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(address, count)| (*address, *count))
     }
 
-    fn local_road_zone_mut(&mut self, x: u64, y: u64) -> &mut f64 {
-        let index = self.local_zone_index(x, y);
-        &mut self.local_roads[index]
+    /// The number of cars parked at the given address.
+    pub fn parked_cars_at(&self, address: quadtree::Address) -> u64 {
+        *self.parked_cars.get(&address).unwrap_or(&0)
     }
 
-    fn parking_zone(&self, x: u64, y: u64) -> f64 {
-        self.parking[self.local_zone_index(x, y)]
-    }
-
-    fn parking_zone_mut(&mut self, x: u64, y: u64) -> &mut f64 {
-        let index = self.local_zone_index(x, y);
-        &mut self.parking[index]
+    /// The parking zone containing the given tile.
+    fn parking_zone_mut(&mut self, address: quadtree::Address) -> Result<&mut f64, Error> {
+        let zone = self.parking.zone_at(address.to_xy_f64());
+        zone.and_then(|zone| self.parking.get_mut(zone))
+            .ok_or_else(|| Error::ParkingError(format!("{:?} is not on the map", address)))
     }
 
     /**
@@ -228,8 +265,7 @@ impl WorldStateImpl {
      * agents so that the initial parking levels are consistent with where agents' cars are parked.
      */
     pub fn increment_parking(&mut self, address: quadtree::Address) -> Result<(), Error> {
-        let (x, y) = self.local_zone_downscale(address.to_xy_f64());
-        *self.parking_zone_mut(x as u64, y as u64) += 1.0;
+        *self.parking_zone_mut(address)? += 1.0;
 
         *self.parked_cars.entry(address).or_insert(0) += 1;
 
@@ -237,8 +273,7 @@ impl WorldStateImpl {
     }
 
     pub fn decrement_parking(&mut self, address: quadtree::Address) -> Result<(), Error> {
-        let (x, y) = self.local_zone_downscale(address.to_xy_f64());
-        let handle = self.parking_zone_mut(x as u64, y as u64);
+        let handle = self.parking_zone_mut(address)?;
         if *handle < 1.0 {
             let (x, y) = address.to_xy_f64();
             return Err(Error::ParkingError(format!(
@@ -273,6 +308,137 @@ impl WorldStateImpl {
         Ok(())
     }
 
+    /**
+     * Add travelers to a highway segment that are not associated with any agent, e.g. to seed
+     * congestion when testing how routes respond to it. The count may be negative to remove
+     * travelers that were previously added this way, but the total may not drop below zero.
+     */
+    pub fn add_highway_segment_travelers(
+        &mut self,
+        segment: network::SegmentHandle,
+        count: f64,
+    ) -> Result<(), Error> {
+        let travelers = self.highway_segments.entry(segment).or_insert(0.0);
+        if !count.is_finite() || *travelers + count < -TOLERANCE {
+            return Err(Error::EdgeCountingError(format!(
+                "e: {}, v: {}",
+                travelers, -count
+            )));
+        }
+        *travelers = f64::max(*travelers + count, 0.0);
+        Ok(())
+    }
+
+    /**
+     * Checks that all of the stored traffic and parking values are finite. A NaN or infinity that
+     * sneaks in (e.g. from a corrupted save) would otherwise silently poison route weights, so this
+     * is run as part of the consistency check. Returns a list of errors.
+     */
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        for (segment, value) in &self.highway_segments {
+            if !value.is_finite() {
+                errors.push(format!("highway segment {:?} has value {}", segment, value));
+            }
+        }
+        for (segment, value) in &self.metro_segments {
+            if !value.is_finite() {
+                errors.push(format!("metro segment {:?} has value {}", segment, value));
+            }
+        }
+        for (zone, value) in self.local_roads.iter() {
+            if !value.is_finite() {
+                let (x, y) = self.local_roads.upscale(zone);
+                errors.push(format!("local road traffic at ({}, {}) is {}", x, y, value));
+            }
+        }
+        for (zone, value) in self.parking.iter() {
+            if !value.is_finite() {
+                let (x, y) = self.parking.upscale(zone);
+                errors.push(format!("parking at ({}, {}) is {}", x, y, value));
+            }
+        }
+
+        errors
+    }
+
+    /// Resets any NaN or infinite values to zero. Returns the number of values that were reset.
+    pub fn repair(&mut self) -> usize {
+        let mut repaired = 0;
+        for value in self
+            .highway_segments
+            .values_mut()
+            .chain(self.metro_segments.values_mut())
+            .chain(self.local_roads.values_mut())
+            .chain(self.parking.values_mut())
+        {
+            if !value.is_finite() {
+                *value = 0.0;
+                repaired += 1;
+            }
+        }
+        repaired
+    }
+
+    /**
+     * Drops segment entries that no longer carry any travelers, and finds entries keyed by handles
+     * to segments that have since been removed from the networks. Stale entries that still have
+     * travelers are kept, since the agents on them will decrement them when they move on; if
+     * `drop_stale` is set, they are dropped anyway. Returns the stale handles that were found.
+     */
+    pub fn compact<HJ: Clone, HS: Clone, RJ: Clone, RS: Clone>(
+        &mut self,
+        highways: &network::Network<HJ, HS>,
+        railways: &network::Network<RJ, RS>,
+        drop_stale: bool,
+    ) -> Vec<network::SegmentHandle> {
+        let mut stale = Vec::new();
+        let mut compact_map =
+            |map: &mut HashMap<network::SegmentHandle, f64>,
+             valid: &dyn Fn(network::SegmentHandle) -> bool| {
+                map.retain(|segment, travelers| {
+                    let empty = travelers.abs() < 1e-6;
+                    if valid(*segment) {
+                        !empty
+                    } else {
+                        stale.push(*segment);
+                        !empty && !drop_stale
+                    }
+                });
+            };
+        compact_map(&mut self.highway_segments, &|segment| {
+            highways.contains_segment(segment)
+        });
+        compact_map(&mut self.metro_segments, &|segment| {
+            railways.contains_segment(segment)
+        });
+        stale.sort();
+        stale
+    }
+
+    /**
+     * Drops the entries for metro segments that a metro line no longer runs on. Entries that still
+     * have riders are kept, since the agents on them will decrement them when they move on.
+     * Returns the segments whose entries were kept.
+     */
+    pub fn drop_metro_segments(
+        &mut self,
+        segments: impl IntoIterator<Item = network::SegmentHandle>,
+    ) -> Vec<network::SegmentHandle> {
+        let mut kept = Vec::new();
+        for segment in segments {
+            match self.metro_segments.get(&segment) {
+                Some(riders) if riders.abs() >= 1e-6 => kept.push(segment),
+                Some(_) => {
+                    self.metro_segments.remove(&segment);
+                }
+                None => (),
+            }
+        }
+        kept
+    }
+
     /// Compare two HashMaps for equality, assuming a default value if either is missing a key.
     /// Invokes the callback function f for any key with unequal values.
     fn compare_hash_maps<K, V, F>(a: &HashMap<K, V>, b: &HashMap<K, V>, mut f: F)
@@ -331,12 +497,13 @@ impl WorldStateImpl {
             },
         );
 
-        for i in 0..self.local_roads.len() {
-            if (self.local_roads[i] - other.local_roads[i]).abs() > TOLERANCE {
-                let (x, y) = self.local_zone_upscale(self.local_zone_coords(i));
+        for (zone, value) in self.local_roads.iter() {
+            let other_value = other.local_roads[zone];
+            if (value - other_value).abs() > TOLERANCE {
+                let (x, y) = self.local_roads.upscale(zone);
                 errors.push(format!(
                     "mismatched local road traffic at ({}, {}): {} != {}",
-                    x, y, self.local_roads[i], other.local_roads[i],
+                    x, y, value, other_value,
                 ));
             }
         }
@@ -349,12 +516,13 @@ impl WorldStateImpl {
     pub fn check_same_parking(&self, other: &Self) -> Vec<String> {
         let mut errors = Vec::new();
 
-        for i in 0..self.parking.len() {
-            if self.parking[i] != other.parking[i] {
-                let (x, y) = self.local_zone_upscale(self.local_zone_coords(i));
+        for (zone, value) in self.parking.iter() {
+            let other_value = other.parking[zone];
+            if *value != other_value {
+                let (x, y) = self.parking.upscale(zone);
                 errors.push(format!(
                     "mismatched parking at ({}, {}): {} != {}",
-                    x, y, self.parking[i], other.parking[i]
+                    x, y, value, other_value
                 ));
             }
         }
@@ -384,15 +552,16 @@ impl WorldState for WorldStateImpl {
     }
 
     fn get_local_road_zone_travelers(&self, x: u64, y: u64) -> f64 {
-        let (x, y) = self.local_zone_downscale((x as f64, y as f64));
-        self.local_road_zone(x as u64, y as u64)
+        self.local_roads
+            .zone_at((x as f64, y as f64))
+            .map_or(0.0, |zone| self.local_roads[zone])
     }
 
     fn get_local_road_travelers(&self, start: (f64, f64), end: (f64, f64), distance: f64) -> f64 {
         // we pass in the distance to avoid having to do a sqrt. a little gross but maybe worthwhile?
         if distance > 0.0 {
             self.local_path(start, end)
-                .map(|((x, y), value)| self.local_road_zone(x, y) * value)
+                .map(|(zone, value)| self.local_roads[zone] * value)
                 .sum::<f64>()
                 / distance
         } else {
@@ -401,21 +570,20 @@ impl WorldState for WorldStateImpl {
     }
 
     fn get_parking(&self, x: f64, y: f64) -> f64 {
-        let (x, y) = self.local_zone_downscale((x, y));
-        self.parking_zone(x as u64, y as u64)
+        self.parking
+            .zone_at((x, y))
+            .map_or(0.0, |zone| self.parking[zone])
     }
 
     fn iter_highway_segments(&self) -> CongestionIterator<'_, network::SegmentHandle> {
         CongestionIterator {
             iterator: Box::new(self.highway_segments.iter().map(|(k, v)| (*k, *v))),
-            total: Some(self.highway_segments.len()),
         }
     }
 
     fn iter_metro_segments(&self) -> CongestionIterator<'_, network::SegmentHandle> {
         CongestionIterator {
             iterator: Box::new(self.metro_segments.iter().map(|(k, v)| (*k, *v))),
-            total: Some(self.metro_segments.len()),
         }
     }
 
@@ -424,10 +592,8 @@ impl WorldState for WorldStateImpl {
             iterator: Box::new(
                 self.local_roads
                     .iter()
-                    .enumerate()
-                    .map(|(i, v)| (self.local_zone_upscale(self.local_zone_coords(i)), *v)),
+                    .map(|(zone, v)| (self.local_roads.upscale(zone), *v)),
             ),
-            total: Some(self.local_roads.len()),
         }
     }
 
@@ -436,20 +602,28 @@ impl WorldState for WorldStateImpl {
             iterator: Box::new(
                 self.parking
                     .iter()
-                    .enumerate()
-                    .map(|(i, v)| (self.local_zone_upscale(self.local_zone_coords(i)), *v)),
+                    .map(|(zone, v)| (self.parking.upscale(zone), *v)),
             ),
-            total: Some(self.parking.len()),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/**
+ * Predicts congestion from traffic seen at the same time on previous days. The default history has
+ * no snapshots at all, and is only a placeholder until a real history is created or loaded.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct WorldStateHistory {
     snapshots: Vec<WorldStateImpl>,
     period: u64,
 }
 
+impl MemorySize for WorldStateHistory {
+    fn heap_bytes(&self) -> usize {
+        self.snapshots.heap_bytes()
+    }
+}
+
 impl WorldStateHistory {
     pub fn new(config: &state::Config, num_snapshots: usize) -> Self {
         let mut snapshots = Vec::with_capacity(num_snapshots);
@@ -458,7 +632,19 @@ impl WorldStateHistory {
         }
         Self {
             snapshots,
-            period: Time::new::<day>(1).value / num_snapshots as u64,
+            period: state::TRAFFIC_HISTORY_PERIOD / num_snapshots as u64,
+        }
+    }
+
+    /**
+     * A history made of the given snapshots, spread evenly over the day, e.g. to see how routes fare
+     * under hand-picked traffic. Panics if there are no snapshots.
+     */
+    pub fn from_snapshots(snapshots: Vec<WorldStateImpl>) -> Self {
+        assert!(!snapshots.is_empty(), "no snapshots");
+        Self {
+            period: state::TRAFFIC_HISTORY_PERIOD / snapshots.len() as u64,
+            snapshots,
         }
     }
 
@@ -473,6 +659,18 @@ impl WorldStateHistory {
         &self.snapshots
     }
 
+    /**
+     * Whether this history has as many snapshots as the config asks for, each with the configured
+     * grid. A history that doesn't can't be used with that config.
+     */
+    pub fn matches_config(&self, config: &state::Config) -> bool {
+        self.snapshots.len() == config.traffic_history.snapshots
+            && self
+                .snapshots
+                .iter()
+                .all(|snapshot| snapshot.grid_matches(config))
+    }
+
     /**
      * Number of seconds between each snapshot.
      */
@@ -480,11 +678,96 @@ impl WorldStateHistory {
         self.period
     }
 
+    /**
+     * A hash of everything that goes into predictions. Unlike std's hashers, this is stable across
+     * runs, platforms, and compiler versions, so it can be stored in a save file to check that a
+     * separately saved history still belongs with it.
+     */
+    pub fn content_hash(&self) -> u64 {
+        // FNV-1a, a word at a time
+        fn mix(hash: u64, value: u64) -> u64 {
+            value.to_le_bytes().iter().fold(hash, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+            })
+        }
+
+        fn mix_segments(hash: u64, segments: &HashMap<network::SegmentHandle, f64>) -> u64 {
+            let mut entries: Vec<(u64, u64)> = segments
+                .iter()
+                .map(|(segment, travelers)| (segment.inner(), travelers.to_bits()))
+                .collect();
+            entries.sort_unstable();
+            entries
+                .into_iter()
+                .fold(mix(hash, segments.len() as u64), |hash, (k, v)| {
+                    mix(mix(hash, k), v)
+                })
+        }
+
+        fn mix_zones(hash: u64, zones: &ZoneGrid<f64>) -> u64 {
+            zones
+                .iter()
+                .fold(mix(hash, zones.len() as u64), |hash, (_, v)| {
+                    mix(hash, v.to_bits())
+                })
+        }
+
+        let mut hash = mix(0xcbf29ce484222325, self.period);
+        hash = mix(hash, self.snapshots.len() as u64);
+        for snapshot in &self.snapshots {
+            hash = mix_segments(hash, &snapshot.highway_segments);
+            hash = mix_segments(hash, &snapshot.metro_segments);
+            hash = mix_zones(hash, &snapshot.local_roads);
+            hash = mix_zones(hash, &snapshot.parking);
+        }
+        hash
+    }
+
     fn update_prior(prior: &mut f64, observation: f64) {
         // TODO: use f64, store likelihood estimate, turn this into a real estimator.
+        debug_assert!(
+            prior.is_finite() && observation.is_finite(),
+            "non-finite traffic history; prior: {}, observation: {}",
+            prior,
+            observation
+        );
         *prior = *prior * (1.0 - OBSERVATION_WEIGHT) + observation * OBSERVATION_WEIGHT;
     }
 
+    /// Checks that all snapshots contain only finite values. Returns a list of errors.
+    pub fn validate(&self) -> Vec<String> {
+        self.snapshots
+            .iter()
+            .enumerate()
+            .flat_map(|(i, snapshot)| {
+                snapshot
+                    .validate()
+                    .into_iter()
+                    .map(move |err| format!("snapshot {}: {}", i, err))
+            })
+            .collect()
+    }
+
+    /// Resets any NaN or infinite values to zero. Returns the number of values that were reset.
+    pub fn repair(&mut self) -> usize {
+        self.snapshots.iter_mut().map(WorldStateImpl::repair).sum()
+    }
+
+    /**
+     * Drops entries for removed segments from all snapshots, since they would otherwise linger
+     * until overwritten by future snapshots. Returns the number of stale entries dropped.
+     */
+    pub fn compact<HJ: Clone, HS: Clone, RJ: Clone, RS: Clone>(
+        &mut self,
+        highways: &network::Network<HJ, HS>,
+        railways: &network::Network<RJ, RS>,
+    ) -> usize {
+        self.snapshots
+            .iter_mut()
+            .map(|snapshot| snapshot.compact(highways, railways, true).len())
+            .sum()
+    }
+
     /**
      * Update the history with a new snapshot. The new data will be used for future predictions.
      *
@@ -520,21 +803,21 @@ impl WorldStateHistory {
             );
         }
 
-        for (index, observation) in world_state.local_roads.iter().enumerate() {
-            Self::update_prior(
-                &mut self.snapshots[snapshot_index].local_roads[index],
-                *observation,
-            );
+        let snapshot = &mut self.snapshots[snapshot_index];
+        for (zone, observation) in world_state.local_roads.iter() {
+            Self::update_prior(&mut snapshot.local_roads[zone], *observation);
         }
 
-        for (index, observation) in world_state.parking.iter().enumerate() {
-            Self::update_prior(
-                &mut self.snapshots[snapshot_index].parking[index],
-                *observation,
-            );
+        for (zone, observation) in world_state.parking.iter() {
+            Self::update_prior(&mut snapshot.parking[zone], *observation);
         }
     }
 
+    /**
+     * The snapshot at or before the prediction time, or if round_forward is set, the one strictly
+     * after it. Rounding forward from an exact snapshot time gives the next snapshot rather than the
+     * same one, so the two always bracket the prediction time. Wraps around at the end of the day.
+     */
     pub fn get_current_snapshot_index(&self, prediction_time: u64, round_forward: bool) -> usize {
         let offset = u64::from(round_forward);
         let periods = (prediction_time + offset) as f64 / self.snapshot_period() as f64;
@@ -546,6 +829,29 @@ impl WorldStateHistory {
         rounded as usize % self.num_snapshots()
     }
 
+    /**
+     * Add travelers to a highway segment in every snapshot, so that predictions reflect them
+     * regardless of the prediction time. Unlike the world state, the history is only an estimate,
+     * so removing more travelers than a snapshot has just clears that snapshot's count.
+     */
+    pub fn add_highway_segment_travelers(
+        &mut self,
+        segment: network::SegmentHandle,
+        count: f64,
+    ) -> Result<(), Error> {
+        if !count.is_finite() {
+            return Err(Error::EdgeCountingError(format!(
+                "invalid traveler count: {}",
+                count
+            )));
+        }
+        for snapshot in &mut self.snapshots {
+            let travelers = snapshot.highway_segments.entry(segment).or_insert(0.0);
+            *travelers = f64::max(*travelers + count, 0.0);
+        }
+        Ok(())
+    }
+
     /**
      * Returns a predictor which can be used in place of WorldStateImpl to predict congestion at the
      * given prediction time.
@@ -569,7 +875,7 @@ impl WorldStateHistory {
         // simple linear interpolation function
         // in the future we could potentially do something fancier
         measure(&self.snapshots[first_snapshot]) * (1.0 - fraction)
-            + measure(&self.snapshots[second_snapshot]) * 1.0
+            + measure(&self.snapshots[second_snapshot]) * fraction
     }
 }
 
@@ -626,7 +932,6 @@ impl<'a> WorldState for WorldStatePredictor<'a> {
                     .keys()
                     .map(|segment| (*segment, self.get_highway_segment_travelers(*segment))),
             ),
-            total: Some(self.history.snapshots[snapshot].highway_segments.len()),
         }
     }
 
@@ -641,7 +946,6 @@ impl<'a> WorldState for WorldStatePredictor<'a> {
                     .keys()
                     .map(|segment| ((*segment), self.get_metro_segment_travelers(*segment))),
             ),
-            total: Some(self.history.snapshots[snapshot].metro_segments.len()),
         }
     }
 
@@ -649,14 +953,12 @@ impl<'a> WorldState for WorldStatePredictor<'a> {
         let snapshot_index = self
             .history
             .get_current_snapshot_index(self.prediction_time, true);
-        let total = self.history.snapshots[snapshot_index].local_roads.len();
+        let grid = &self.history.snapshots[snapshot_index].local_roads;
         CongestionIterator {
-            iterator: Box::new((0..total).map(move |i| {
-                let snapshot = &self.history.snapshots[snapshot_index];
-                let (x, y) = snapshot.local_zone_upscale(snapshot.local_zone_coords(i));
+            iterator: Box::new(grid.iter().map(move |(zone, _)| {
+                let (x, y) = grid.upscale(zone);
                 ((x, y), self.get_local_road_zone_travelers(x, y))
             })),
-            total: Some(total),
         }
     }
 
@@ -664,24 +966,32 @@ impl<'a> WorldState for WorldStatePredictor<'a> {
         let snapshot_index = self
             .history
             .get_current_snapshot_index(self.prediction_time, true);
-        let total = self.history.snapshots[snapshot_index].parking.len();
+        let grid = &self.history.snapshots[snapshot_index].parking;
         CongestionIterator {
-            iterator: Box::new((0..total).map(move |i| {
-                let snapshot = &self.history.snapshots[snapshot_index];
-                let (x, y) = snapshot.local_zone_upscale(snapshot.local_zone_coords(i));
+            iterator: Box::new(grid.iter().map(move |(zone, _)| {
+                let (x, y) = grid.upscale(zone);
                 ((x, y), self.get_parking(x as f64, y as f64))
             })),
-            total: Some(total),
         }
     }
 }
 
+/**
+ * Iterates over the congestion of each highway segment, metro segment, or zone. The number of items
+ * is not known up front, since the iterator may be filtered; the statistics report how many items
+ * they were calculated from instead.
+ */
 pub struct CongestionIterator<'a, K> {
     iterator: Box<dyn Iterator<Item = (K, f64)> + 'a>,
-    pub total: Option<usize>,
 }
 
 impl<'a, K: 'a> CongestionIterator<'a, K> {
+    pub fn new(iterator: impl Iterator<Item = (K, f64)> + 'a) -> Self {
+        Self {
+            iterator: Box::new(iterator),
+        }
+    }
+
     pub fn keys(self) -> Box<dyn Iterator<Item = K> + 'a> {
         Box::new(self.iterator.map(|(k, _)| k))
     }
@@ -698,66 +1008,564 @@ impl<'a, K: 'a + Copy> CongestionIterator<'a, K> {
     {
         Self {
             iterator: Box::new(self.iterator.filter(move |(k, v)| filter(*k, *v))),
-            // TODO: it's not possible to calculate total unless we clone the iterator?
-            total: None,
         }
     }
 }
 
+/// A statistic along with the number of items that contributed to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats<T = f64> {
+    pub value: T,
+    pub count: usize,
+}
+
 pub trait CongestionStats {
     /// sum of all items
-    fn sum(self) -> f64;
+    fn sum(self) -> Stats;
 
-    /// calculate the mean of all items
-    fn mean(self) -> f64;
+    /// calculate the mean of all items, which is zero if there are none
+    fn mean(self) -> Stats;
 
-    /// calculate the root mean square of all items
-    fn rms(self) -> f64;
+    /// calculate the root mean square of all items, which is zero if there are none
+    fn rms(self) -> Stats;
 
     /// constructs a histogram
-    fn histogram(self, buckets: usize, max: f64) -> Vec<u64>;
+    fn histogram(self, buckets: usize, max: f64) -> Stats<Vec<u64>>;
 }
 
 impl<'a, K> CongestionStats for CongestionIterator<'a, K> {
-    fn sum(self) -> f64 {
-        self.values().sum()
-    }
-
-    fn mean(self) -> f64 {
+    fn sum(self) -> Stats {
         let mut sum = 0.0;
-        let mut total = 0;
+        let mut count = 0;
         for (_, value) in self.iterator {
             sum += value;
-            total += 1;
+            count += 1;
         }
-        if total > 0 {
-            sum / total as f64
-        } else {
-            0.0
+        Stats { value: sum, count }
+    }
+
+    fn mean(self) -> Stats {
+        let Stats { value: sum, count } = self.sum();
+        Stats {
+            value: if count > 0 { sum / count as f64 } else { 0.0 },
+            count,
         }
     }
 
-    fn rms(self) -> f64 {
+    fn rms(self) -> Stats {
         let mut sum = 0.0;
-        let mut total = 0;
+        let mut count = 0;
         for (_, value) in self.iterator {
             sum += value.powi(2);
-            total += 1;
+            count += 1;
         }
-        if total > 0 {
-            (sum / total as f64).sqrt()
-        } else {
-            0.0
+        Stats {
+            value: if count > 0 {
+                (sum / count as f64).sqrt()
+            } else {
+                0.0
+            },
+            count,
         }
     }
 
-    fn histogram(self, buckets: usize, max: f64) -> Vec<u64> {
+    fn histogram(self, buckets: usize, max: f64) -> Stats<Vec<u64>> {
         assert!(max > 0.0);
         let mut histogram = vec![0; buckets];
+        let mut count = 0;
         for (_, value) in self.iterator {
             let bucket = ((value / max) * buckets as f64) as usize;
             histogram[bucket.min(buckets - 1)] += 1;
+            count += 1;
+        }
+        Stats {
+            value: histogram,
+            count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::traffic::*;
+
+    fn config() -> state::Config {
+        state::Config {
+            max_depth: 4,
+            people_per_sim: 1.0,
+            min_tile_size: 100,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        }
+    }
+
+    fn segment() -> network::SegmentHandle {
+        let mut network = network::Network::<(), ()>::new();
+        let start = network.add_junction((0.0, 0.0), ());
+        let end = network.add_junction((1.0, 0.0), ());
+        network.add_segment((), start, end, None)
+    }
+
+    #[test]
+    fn validate_and_repair_world_state() {
+        let segment = segment();
+        let mut world_state = WorldStateImpl::new(&config());
+        world_state
+            .add_highway_segment_travelers(segment, 2.0)
+            .unwrap();
+        assert!(world_state.validate().is_empty());
+
+        world_state.local_roads[ZoneId::new(3, 0)] = f64::NAN;
+        world_state.parking[ZoneId::new(0, 0)] = f64::INFINITY;
+        *world_state.highway_segments.get_mut(&segment).unwrap() = f64::NEG_INFINITY;
+        assert_eq!(world_state.validate().len(), 3);
+
+        assert_eq!(world_state.repair(), 3);
+        assert!(world_state.validate().is_empty());
+        assert_eq!(world_state.local_roads[ZoneId::new(3, 0)], 0.0);
+        assert_eq!(world_state.repair(), 0);
+    }
+
+    #[test]
+    fn compact_detects_stale_handles() {
+        let mut highways = network::Network::<(), ()>::new();
+        let railways = network::Network::<(), ()>::new();
+        let start = highways.add_junction((0.0, 0.0), ());
+        let end = highways.add_junction((1.0, 0.0), ());
+        let old = highways.add_segment((), start, end, None);
+        let emptied = highways.add_segment((), end, start, None);
+
+        let mut world_state = WorldStateImpl::new(&config());
+        world_state.add_highway_segment_travelers(old, 2.0).unwrap();
+        world_state
+            .add_highway_segment_travelers(emptied, 1.0)
+            .unwrap();
+        world_state
+            .add_highway_segment_travelers(emptied, -1.0)
+            .unwrap();
+
+        // the new segment reuses the slot of the removed one
+        highways.remove_segment(old);
+        let new = highways.add_segment((), start, end, None);
+        assert_eq!(new.id().index(), old.id().index());
+        world_state.add_highway_segment_travelers(new, 1.0).unwrap();
+
+        // the stale entry still has travelers, so it is reported but kept
+        assert_eq!(world_state.compact(&highways, &railways, false), vec![old]);
+        assert_eq!(world_state.get_highway_segment_travelers(old), 2.0);
+        assert_eq!(world_state.get_highway_segment_travelers(new), 1.0);
+        assert!(!world_state.highway_segments.contains_key(&emptied));
+
+        assert_eq!(world_state.compact(&highways, &railways, true), vec![old]);
+        assert_eq!(world_state.get_highway_segment_travelers(old), 0.0);
+        assert_eq!(world_state.get_highway_segment_travelers(new), 1.0);
+        assert!(world_state.compact(&highways, &railways, true).is_empty());
+    }
+
+    /// Applies a degenerate edge, which must not change the world state.
+    fn apply_degenerate_edge(edge: Edge) {
+        let mut world_state = WorldStateImpl::new(&config());
+        let before = world_state.local_roads.clone();
+
+        assert!(matches!(
+            world_state.increment_edge(&edge),
+            Err(Error::EdgeCountingError(_))
+        ));
+        assert!(matches!(
+            world_state.decrement_edge(&edge),
+            Err(Error::EdgeCountingError(_))
+        ));
+
+        assert_eq!(world_state.local_roads, before);
+        assert!(world_state.validate().is_empty());
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "degenerate edge"))]
+    fn nan_distance_edge() {
+        apply_degenerate_edge(Edge::ModeSegment {
+            mode: Mode::Driving,
+            distance: f64::NAN,
+            start: (0.0, 2.0),
+            stop: (8.0, 2.0),
+        });
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "degenerate edge"))]
+    fn rounded_distance_edge() {
+        // the distance was rounded down to almost nothing, so the traffic spread along the edge
+        // overflows to infinity
+        apply_degenerate_edge(Edge::ModeSegment {
+            mode: Mode::Driving,
+            distance: 1e-320,
+            start: (0.0, 2.0),
+            stop: (8.0, 2.0),
+        });
+    }
+
+    #[test]
+    fn people_per_sim_scales_local_density() {
+        use crate::local_traffic::{congested_travel_factor, traveler_density};
+
+        let (start, stop, distance) = ((0.0, 2.0), (8.0, 2.0), 800.0);
+        let edge = Edge::ModeSegment {
+            mode: Mode::Driving,
+            distance,
+            start,
+            stop,
+        };
+
+        let local_road = |people_per_sim| {
+            let config = state::Config {
+                people_per_sim,
+                ..config()
+            };
+            let mut world_state = WorldStateImpl::new(&config);
+            world_state.increment_edge_no_parking(&edge).unwrap();
+            let travelers = world_state.get_local_road_travelers(start, stop, distance);
+            (
+                travelers,
+                traveler_density(&config, travelers),
+                congested_travel_factor(&config, travelers),
+            )
+        };
+
+        let (travelers, density, factor) = local_road(1.0);
+        assert!(density > 0.0);
+
+        let (double_travelers, double_density, double_factor) = local_road(2.0);
+        assert_eq!(double_travelers, travelers);
+        assert_eq!(double_density, density * 2.0);
+        assert!(double_factor > factor);
+
+        let (_, half_density, _) = local_road(0.5);
+        assert_eq!(half_density, density * 0.5);
+
+        // each agent representing twice as many people is the same as having twice as many agents
+        assert_eq!(
+            congested_travel_factor(&config(), travelers * 2.0),
+            double_factor
+        );
+    }
+
+    #[test]
+    fn max_model_coordinate() {
+        let config = config();
+        let max = config.tile_width() as u64 - 1;
+        let mut world_state = WorldStateImpl::new(&config);
+
+        // the tile in the far corner belongs to the last zone
+        let corner = quadtree::Address::from_xy(max, max, config.max_depth);
+        world_state.increment_parking(corner).unwrap();
+        let last = world_state.parking().width() - 1;
+        assert_eq!(world_state.parking()[ZoneId::new(last, last)], 1.0);
+        assert_eq!(world_state.get_parking(max as f64, max as f64), 1.0);
+
+        // Just past the edge of the map is not part of any zone. This used to wrap around into the
+        // first zone of the next row.
+        world_state.parking[ZoneId::new(0, 1)] = 5.0;
+        let edge = max as f64 + 1.0;
+        assert_eq!(world_state.get_parking(edge, 0.0), 0.0);
+        assert_eq!(world_state.get_local_road_zone_travelers(max + 1, 0), 0.0);
+        assert!(world_state.parking().zone_at((edge, 0.0)).is_none());
+    }
+
+    #[test]
+    fn validate_and_repair_history() {
+        let config = config();
+        let mut history = WorldStateHistory::new(&config, 4);
+        assert!(history.validate().is_empty());
+
+        history.snapshots[2].local_roads[ZoneId::new(1, 0)] = f64::NAN;
+        let errors = history.validate();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("snapshot 2:"), "{}", errors[0]);
+
+        assert_eq!(history.repair(), 1);
+        assert!(history.validate().is_empty());
+
+        // once repaired, new snapshots can be taken normally
+        let world_state = WorldStateImpl::new(&config);
+        history.take_snapshot(&world_state, history.snapshot_period() * 2);
+        assert!(history.validate().is_empty());
+    }
+
+    #[test]
+    fn history_content_hash() {
+        let config = config();
+        let mut history = WorldStateHistory::new(&config, 4);
+        let hash = history.content_hash();
+        assert_eq!(hash, history.clone().content_hash());
+        assert_ne!(hash, WorldStateHistory::new(&config, 2).content_hash());
+
+        let mut world_state = WorldStateImpl::new(&config);
+        world_state
+            .add_highway_segment_travelers(segment(), 2.0)
+            .unwrap();
+        history.take_snapshot(&world_state, history.snapshot_period());
+        let with_travelers = history.content_hash();
+        assert_ne!(hash, with_travelers);
+
+        // zone values count too, not only segments
+        history.snapshots[1].local_roads[ZoneId::new(0, 0)] = 1.0;
+        assert_ne!(with_travelers, history.content_hash());
+    }
+
+    fn congestion(values: &[f64]) -> CongestionIterator<'_, usize> {
+        CongestionIterator::new(values.iter().copied().enumerate())
+    }
+
+    #[test]
+    fn congestion_stats() {
+        let values = [3.0, 0.0, 4.0, 1.0];
+        assert_eq!(
+            congestion(&values).sum(),
+            Stats {
+                value: 8.0,
+                count: 4
+            }
+        );
+        assert_eq!(
+            congestion(&values).mean(),
+            Stats {
+                value: 2.0,
+                count: 4
+            }
+        );
+        let rms = congestion(&values).rms();
+        assert_eq!(rms.count, 4);
+        assert!((rms.value - 6.5_f64.sqrt()).abs() < 1e-9, "{}", rms.value);
+        assert_eq!(
+            congestion(&values).histogram(2, 4.0),
+            Stats {
+                value: vec![2, 2],
+                count: 4
+            }
+        );
+
+        // the count reflects only the items that pass the filter
+        let filtered = || congestion(&values).filter(|i, v| i > 0 && v > 0.0);
+        assert_eq!(
+            filtered().sum(),
+            Stats {
+                value: 5.0,
+                count: 2
+            }
+        );
+        assert_eq!(
+            filtered().mean(),
+            Stats {
+                value: 2.5,
+                count: 2
+            }
+        );
+        assert_eq!(filtered().rms().count, 2);
+        assert_eq!(filtered().histogram(4, 4.0).value, vec![0, 1, 0, 1]);
+
+        // nothing left at all
+        let empty = || congestion(&values).filter(|_, v| v > 10.0);
+        assert_eq!(empty().sum(), Stats::default());
+        assert_eq!(empty().mean(), Stats::default());
+        assert_eq!(empty().rms(), Stats::default());
+        assert_eq!(empty().histogram(2, 4.0).count, 0);
+    }
+
+    #[test]
+    fn world_state_congestion_stats() {
+        let config = config();
+        let mut world_state = WorldStateImpl::new(&config);
+        world_state
+            .add_highway_segment_travelers(segment(), 2.0)
+            .unwrap();
+        world_state.local_roads[ZoneId::new(0, 0)] = 3.0;
+
+        assert_eq!(
+            world_state.iter_highway_segments().sum(),
+            Stats {
+                value: 2.0,
+                count: 1
+            }
+        );
+        let zones = world_state.local_roads.len();
+        assert_eq!(
+            world_state.iter_local_road_zones().sum(),
+            Stats {
+                value: 3.0,
+                count: zones
+            }
+        );
+        assert_eq!(
+            world_state
+                .iter_local_road_zones()
+                .filter(|_, v| v > 0.0)
+                .mean(),
+            Stats {
+                value: 3.0,
+                count: 1
+            }
+        );
+    }
+
+    #[test]
+    fn history_memory_size() {
+        let config = config();
+        let mut world_state = WorldStateImpl::new(&config);
+        world_state
+            .add_highway_segment_travelers(segment(), 2.0)
+            .unwrap();
+
+        let mut history = WorldStateHistory::new(&config, 6);
+        for i in 0..history.num_snapshots() as u64 {
+            history.take_snapshot(&world_state, i * history.snapshot_period());
+        }
+
+        let per_snapshot = history.get_snapshots()[0].estimated_bytes();
+        assert!(
+            per_snapshot > world_state.local_roads.heap_bytes() + world_state.parking.heap_bytes()
+        );
+        assert_eq!(history.heap_bytes(), 6 * per_snapshot);
+        assert_eq!(
+            history.estimated_bytes(),
+            std::mem::size_of::<WorldStateHistory>() + 6 * per_snapshot
+        );
+    }
+
+    /// A history with one snapshot for each of the given traveler counts on the segment.
+    fn history_of(segment: network::SegmentHandle, travelers: &[f64]) -> WorldStateHistory {
+        let snapshots = travelers
+            .iter()
+            .map(|travelers| {
+                let mut snapshot = WorldStateImpl::new(&config());
+                snapshot
+                    .add_highway_segment_travelers(segment, *travelers)
+                    .unwrap();
+                snapshot
+            })
+            .collect();
+        WorldStateHistory::from_snapshots(snapshots)
+    }
+
+    fn predict(history: &WorldStateHistory, segment: network::SegmentHandle, time: u64) -> f64 {
+        history
+            .get_predictor(time)
+            .get_highway_segment_travelers(segment)
+    }
+
+    #[test]
+    fn snapshot_index_rounding() {
+        let history = WorldStateHistory::new(&config(), 4);
+        let period = history.snapshot_period();
+        let index = |time| {
+            (
+                history.get_current_snapshot_index(time, false),
+                history.get_current_snapshot_index(time, true),
+            )
+        };
+
+        // at an exact snapshot time, rounding forward still moves on to the next snapshot
+        assert_eq!(index(0), (0, 1));
+        assert_eq!(index(1), (0, 1));
+        assert_eq!(index(period - 1), (0, 1));
+        assert_eq!(index(period), (1, 2));
+        assert_eq!(index(period + 1), (1, 2));
+
+        // the last snapshot of the day is followed by the first one
+        assert_eq!(index(3 * period), (3, 0));
+        assert_eq!(index(4 * period - 1), (3, 0));
+        assert_eq!(index(4 * period), (0, 1));
+        assert_eq!(index(state::TRAFFIC_HISTORY_PERIOD * 3 + period), (1, 2));
+    }
+
+    #[test]
+    fn interpolation() {
+        let segment = segment();
+        let history = history_of(segment, &[10.0, 20.0, 40.0, 0.0]);
+        let period = history.snapshot_period();
+        let predict = |time| predict(&history, segment, time);
+
+        // fraction 0, 0.5, and (approaching) 1
+        assert_eq!(predict(0), 10.0);
+        assert_eq!(predict(period / 2), 15.0);
+        assert_eq!(predict(period), 20.0);
+        assert!((predict(period - 1) - 20.0).abs() < 1e-3);
+
+        assert_eq!(predict(period + period / 4), 25.0);
+        assert_eq!(predict(2 * period + period / 2), 20.0);
+
+        // wraps around from the last snapshot to the first, and across days
+        assert_eq!(predict(3 * period + period / 2), 5.0);
+        assert_eq!(predict(4 * period), 10.0);
+        assert_eq!(predict(state::TRAFFIC_HISTORY_PERIOD + period / 2), 15.0);
+    }
+
+    #[test]
+    fn update_prior_convergence() {
+        let config = config();
+        let segment = segment();
+        let mut world_state = WorldStateImpl::new(&config);
+        world_state
+            .add_highway_segment_travelers(segment, 10.0)
+            .unwrap();
+
+        let mut history = WorldStateHistory::new(&config, 4);
+        let period = history.snapshot_period();
+        let mut gap = 10.0;
+        for day in 0..20 {
+            history.take_snapshot(&world_state, day * state::TRAFFIC_HISTORY_PERIOD + period);
+            gap *= 1.0 - OBSERVATION_WEIGHT;
+
+            let prior = history.get_snapshots()[1].get_highway_segment_travelers(segment);
+            assert!((10.0 - prior - gap).abs() < 1e-9, "day {}: {}", day, prior);
+        }
+        assert!(gap < 0.01);
+
+        // only the snapshot at the observation time learns anything
+        assert_eq!(
+            history.get_snapshots()[0].get_highway_segment_travelers(segment),
+            0.0
+        );
+    }
+
+    #[test]
+    fn predictions_between_bracketing_snapshots() {
+        let segment = segment();
+
+        // a small linear congruential generator, so that the test is deterministic
+        let mut seed: u64 = 12345;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            seed >> 33
+        };
+
+        for _ in 0..50 {
+            let travelers: Vec<f64> = (0..6).map(|_| (next() % 10_000) as f64 / 10.0).collect();
+            let history = history_of(segment, &travelers);
+
+            for _ in 0..50 {
+                let time = next() % (2 * state::TRAFFIC_HISTORY_PERIOD);
+                let first = travelers[history.get_current_snapshot_index(time, false)];
+                let second = travelers[history.get_current_snapshot_index(time, true)];
+                let predicted = predict(&history, segment, time);
+                assert!(
+                    predicted >= first.min(second) - 1e-9 && predicted <= first.max(second) + 1e-9,
+                    "time {}: {} not between {} and {}",
+                    time,
+                    predicted,
+                    first,
+                    second
+                );
+            }
         }
-        histogram
     }
 }
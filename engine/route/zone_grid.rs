@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+
+/**
+ * The coordinates of a zone in a ZoneGrid. These are in units of zones, not model coordinates; use
+ * ZoneGrid::zone_at and ZoneGrid::upscale to convert between the two.
+ */
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    derive_more::Constructor,
+)]
+pub struct ZoneId {
+    pub x: u32,
+    pub y: u32,
+}
+
+/**
+ * A square grid of values covering the whole map, where each zone covers `downsample` by
+ * `downsample` model units. Used for quantities that are tracked at a coarser resolution than tiles,
+ * like local road traffic and parking.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ZoneGrid<T> {
+    /// flattened grid of zones, row major
+    values: Vec<T>,
+    /// the number of zones along each side of the grid
+    width: u32,
+    /// the number of model units along each side of a zone
+    downsample: u32,
+}
+
+impl<T: Clone> ZoneGrid<T> {
+    pub fn new(width: u32, downsample: u32, value: T) -> Self {
+        Self {
+            values: vec![value; width.pow(2) as usize],
+            width,
+            downsample,
+        }
+    }
+}
+
+impl<T> memory_size::MemorySize for ZoneGrid<T> {
+    fn heap_bytes(&self) -> usize {
+        memory_size::vec_bytes(&self.values)
+    }
+}
+
+impl<T> ZoneGrid<T> {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn downsample(&self) -> u32 {
+        self.downsample
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn contains(&self, zone: ZoneId) -> bool {
+        zone.x < self.width && zone.y < self.width
+    }
+
+    fn index(&self, zone: ZoneId) -> Option<usize> {
+        self.contains(zone)
+            .then(|| (self.width as usize) * (zone.y as usize) + (zone.x as usize))
+    }
+
+    fn zone(&self, index: usize) -> ZoneId {
+        debug_assert!(index < self.values.len());
+        ZoneId {
+            x: (index % self.width as usize) as u32,
+            y: (index / self.width as usize) as u32,
+        }
+    }
+
+    pub fn get(&self, zone: ZoneId) -> Option<&T> {
+        self.index(zone).map(|index| &self.values[index])
+    }
+
+    pub fn get_mut(&mut self, zone: ZoneId) -> Option<&mut T> {
+        self.index(zone).map(move |index| &mut self.values[index])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ZoneId, &T)> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| (self.zone(index), value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (ZoneId, &mut T)> + '_ {
+        let width = self.width as usize;
+        self.values
+            .iter_mut()
+            .enumerate()
+            .map(move |(index, value)| {
+                let zone = ZoneId::new((index % width) as u32, (index / width) as u32);
+                (zone, value)
+            })
+    }
+
+    /// All of the values, in row-major order. Useful for combining grids of the same size.
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn values_mut(&mut self) -> &mut [T] {
+        &mut self.values
+    }
+
+    /**
+     * Converts model coordinates to fractional zone coordinates. The result may lie outside of the
+     * grid.
+     */
+    pub fn downscale(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        (x / self.downsample as f64, y / self.downsample as f64)
+    }
+
+    /// The model coordinates of the corner of the zone closest to the origin.
+    pub fn upscale(&self, zone: ZoneId) -> (u64, u64) {
+        (
+            zone.x as u64 * self.downsample as u64,
+            zone.y as u64 * self.downsample as u64,
+        )
+    }
+
+    /// The zone containing the given model coordinates, if they are on the map.
+    pub fn zone_at(&self, (x, y): (f64, f64)) -> Option<ZoneId> {
+        let (x, y) = self.downscale((x, y));
+        if x >= 0.0 && y >= 0.0 {
+            // NOTE: the casts saturate, so coordinates far off the map are still rejected
+            let zone = ZoneId::new(x as u32, y as u32);
+            self.contains(zone).then_some(zone)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> std::ops::Index<ZoneId> for ZoneGrid<T> {
+    type Output = T;
+
+    fn index(&self, zone: ZoneId) -> &T {
+        match self.get(zone) {
+            Some(value) => value,
+            None => panic!("{:?} is outside of grid with width {}", zone, self.width),
+        }
+    }
+}
+
+impl<T> std::ops::IndexMut<ZoneId> for ZoneGrid<T> {
+    fn index_mut(&mut self, zone: ZoneId) -> &mut T {
+        let width = self.width;
+        match self.get_mut(zone) {
+            Some(value) => value,
+            None => panic!("{:?} is outside of grid with width {}", zone, width),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::zone_grid::*;
+
+    #[test]
+    fn index_round_trip() {
+        let mut grid = ZoneGrid::new(4, 2, 0);
+        for (zone, value) in grid.iter_mut() {
+            *value = zone.x + 10 * zone.y;
+        }
+        assert_eq!(grid[ZoneId::new(3, 1)], 13);
+        assert_eq!(grid.get(ZoneId::new(1, 3)), Some(&31));
+        assert_eq!(grid.values()[4 + 3], 13);
+        for (zone, value) in grid.iter() {
+            assert_eq!(*value, zone.x + 10 * zone.y);
+        }
+    }
+
+    #[test]
+    fn bounds() {
+        let mut grid = ZoneGrid::new(4, 2, 0.0);
+        assert!(grid.get(ZoneId::new(3, 3)).is_some());
+        assert!(grid.get(ZoneId::new(4, 0)).is_none());
+        assert!(grid.get(ZoneId::new(0, 4)).is_none());
+        assert!(grid.get_mut(ZoneId::new(4, 3)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of grid")]
+    fn index_out_of_bounds() {
+        let grid = ZoneGrid::new(4, 2, 0.0);
+        let _ = grid[ZoneId::new(4, 0)];
+    }
+
+    #[test]
+    fn model_conversions() {
+        let grid = ZoneGrid::new(4, 2, 0.0);
+        assert_eq!(grid.zone_at((0.0, 0.0)), Some(ZoneId::new(0, 0)));
+        assert_eq!(grid.zone_at((3.5, 5.0)), Some(ZoneId::new(1, 2)));
+        assert_eq!(grid.upscale(ZoneId::new(1, 2)), (2, 4));
+        assert_eq!(grid.downscale((3.0, 5.0)), (1.5, 2.5));
+
+        // the map is 8 model units wide, so 8 is just off the edge
+        assert_eq!(grid.zone_at((7.9, 7.0)), Some(ZoneId::new(3, 3)));
+        assert_eq!(grid.zone_at((8.0, 0.0)), None);
+        assert_eq!(grid.zone_at((0.0, -0.5)), None);
+        assert_eq!(grid.zone_at((1e30, 0.0)), None);
+    }
+}
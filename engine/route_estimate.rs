@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use crate::engine::{Engine, Error};
+
+/// The most samples along each side of a route cost table.
+const MAX_GRID_WIDTH: u64 = 32;
+
+/// Route costs beyond this are not estimated at all, in seconds.
+pub const MAX_ESTIMATED_COST: f64 = 3.0 * 3600.0;
+
+/// How a traveler gets around, for estimating the cost of a route they might take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModeProfile {
+    /// walking in a straight line, without using any network
+    Walking,
+    /// walking and riding metro lines
+    Transit,
+    /// driving, starting with a car
+    Driving,
+}
+
+impl ModeProfile {
+    fn car_config(&self) -> Option<route::CarConfig> {
+        match self {
+            Self::Driving => Some(route::CarConfig::StartWithCar),
+            Self::Walking | Self::Transit => None,
+        }
+    }
+}
+
+/**
+ * Route costs from a single origin to a coarse grid of targets covering the whole map, computed
+ * with a single one-to-many search. Costs to anywhere else are interpolated between the nearest
+ * targets.
+ */
+#[derive(Debug, Clone)]
+pub struct RouteCostTable {
+    pub origin: quadtree::Address,
+    pub profile: ModeProfile,
+    pub graph_version: route::GraphVersion,
+    /// the cost to the center of each zone, in seconds, or None if it can't be reached
+    costs: route::ZoneGrid<Option<f64>>,
+}
+
+impl RouteCostTable {
+    /**
+     * The cost to the given point, interpolated between the targets around it, in seconds. Targets
+     * that can't be reached are left out.
+     */
+    fn interpolate(&self, (x, y): (f64, f64)) -> Option<f64> {
+        let downsample = self.costs.downsample() as f64;
+        let max = (self.costs.width() - 1) as f64;
+        // the targets are at the centers of the zones
+        let (gx, gy) = (
+            (x / downsample - 0.5).clamp(0.0, max),
+            (y / downsample - 0.5).clamp(0.0, max),
+        );
+        let (x0, y0) = (gx.floor(), gy.floor());
+        let (fx, fy) = (gx - x0, gy - y0);
+
+        let mut total = 0.0;
+        let mut total_weight = 0.0;
+        for (dx, wx) in [(0.0, 1.0 - fx), (1.0, fx)] {
+            for (dy, wy) in [(0.0, 1.0 - fy), (1.0, fy)] {
+                let zone = route::ZoneId::new((x0 + dx).min(max) as u32, (y0 + dy).min(max) as u32);
+                if let Some(Some(cost)) = self.costs.get(zone) {
+                    total += cost * wx * wy;
+                    total_weight += wx * wy;
+                }
+            }
+        }
+        (total_weight > 0.0).then(|| total / total_weight)
+    }
+}
+
+/// Everything that a route cost table depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RouteCostKey {
+    origin: quadtree::Address,
+    profile: ModeProfile,
+    graph_version: route::GraphVersion,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RouteCostCache(Option<(RouteCostKey, Arc<RouteCostTable>)>);
+
+impl Engine {
+    /**
+     * A cheap approximation of the cost of the best route from `start` to `end`, in seconds, e.g.
+     * for showing travel times while hovering over the map. Walking is estimated as a straight
+     * line. Otherwise, the first estimate from a given start computes a coarse table of route costs
+     * to the whole map, which is reused until the start, the profile, or the graph version
+     * changes. Returns None if `end` can't be reached within MAX_ESTIMATED_COST. Close to the edge
+     * of the area that can be reached at all, this may give an estimate where there is no route.
+     */
+    pub fn estimate_route_cost(
+        &mut self,
+        start: quadtree::Address,
+        end: quadtree::Address,
+        profile: ModeProfile,
+    ) -> Result<Option<f64>, Error> {
+        use cgmath::MetricSpace;
+
+        let tile_size = self.state.config.min_tile_size as f64;
+        let distance = cgmath::Vector2::from(start.to_center_f64())
+            .distance(end.to_center_f64().into())
+            * tile_size;
+        let walking = route::Mode::Walking;
+        let walking_cost = distance / walking.linear_speed();
+
+        if profile == ModeProfile::Walking {
+            return Ok((walking_cost <= MAX_ESTIMATED_COST).then_some(walking_cost));
+        }
+
+        let table = self.route_cost_table(start, profile)?;
+        // like best_route, prefer walking directly if the end is close enough
+        let direct = (distance < walking.bridge_radius()).then_some(walking_cost);
+        Ok(table
+            .interpolate(end.to_center_f64())
+            .into_iter()
+            .chain(direct)
+            .min_by(|a, b| a.total_cmp(b))
+            .filter(|cost| *cost <= MAX_ESTIMATED_COST))
+    }
+
+    /**
+     * The table of route costs that estimate_route_cost uses for the given origin. The table is
+     * cached until the origin, the profile, or the graph version changes.
+     */
+    pub fn route_cost_table(
+        &mut self,
+        origin: quadtree::Address,
+        profile: ModeProfile,
+    ) -> Result<Arc<RouteCostTable>, Error> {
+        let key = RouteCostKey {
+            origin,
+            profile,
+            graph_version: self.graph_version(),
+        };
+        if let Some((cached_key, table)) = &self.route_cost_cache.0 {
+            if *cached_key == key {
+                return Ok(table.clone());
+            }
+        }
+
+        let table = Arc::new(self.calculate_route_cost_table(key)?);
+        self.route_cost_cache.0 = Some((key, table.clone()));
+        Ok(table)
+    }
+
+    fn calculate_route_cost_table(&self, key: RouteCostKey) -> Result<RouteCostTable, Error> {
+        let _span = tracing::debug_span!("route_cost_table", profile = ?key.profile).entered();
+
+        let max_depth = self.state.config.max_depth;
+        let width = self.state.qtree.width();
+        let grid_width = width.min(MAX_GRID_WIDTH);
+        // both are powers of two
+        let downsample = width / grid_width;
+        let mut costs = route::ZoneGrid::new(grid_width as u32, downsample as u32, None);
+
+        let targets: Vec<_> = costs
+            .iter()
+            .map(|(zone, _)| {
+                let (x, y) = costs.upscale(zone);
+                quadtree::Address::from_xy(x + downsample / 2, y + downsample / 2, max_depth)
+            })
+            .collect();
+        let target_costs = self.query_route_costs(
+            key.origin,
+            &targets,
+            key.profile.car_config(),
+            Some(MAX_ESTIMATED_COST),
+        )?;
+        for ((_, cost), target_cost) in costs.iter_mut().zip(target_costs) {
+            *cost = target_cost;
+        }
+
+        Ok(RouteCostTable {
+            origin: key.origin,
+            profile: key.profile,
+            graph_version: key.graph_version,
+            costs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::route_estimate::*;
+
+    fn cost_table(
+        width: u32,
+        downsample: u32,
+        cost: impl Fn(route::ZoneId) -> Option<f64>,
+    ) -> RouteCostTable {
+        let mut costs = route::ZoneGrid::new(width, downsample, None);
+        for (zone, value) in costs.iter_mut() {
+            *value = cost(zone);
+        }
+        RouteCostTable {
+            origin: quadtree::Address::from((vec![], 4)),
+            profile: ModeProfile::Transit,
+            graph_version: Default::default(),
+            costs,
+        }
+    }
+
+    #[test]
+    fn interpolate() {
+        let table = cost_table(4, 4, |zone| Some((zone.x * 10) as f64));
+        // exactly at the centers of the zones
+        assert_eq!(table.interpolate((2.0, 2.0)), Some(0.0));
+        assert_eq!(table.interpolate((6.0, 2.0)), Some(10.0));
+        // halfway between two zones
+        assert_eq!(table.interpolate((4.0, 7.0)), Some(5.0));
+        // past the outermost centers, the edge zones are used
+        assert_eq!(table.interpolate((0.0, 0.0)), Some(0.0));
+        assert_eq!(table.interpolate((16.0, 16.0)), Some(30.0));
+    }
+
+    #[test]
+    fn interpolate_unreachable() {
+        let table = cost_table(2, 4, |zone| (zone.x == 0).then_some(100.0));
+        // unreachable zones don't drag the estimate towards anything
+        assert_eq!(table.interpolate((4.0, 2.0)), Some(100.0));
+        assert_eq!(table.interpolate((6.0, 2.0)), None);
+        assert_eq!(cost_table(2, 4, |_| None).interpolate((4.0, 4.0)), None);
+    }
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+/// upper bounds of the histogram buckets, in milliseconds; the last bucket has no upper bound
+pub const LATENCY_BUCKETS_MS: [u64; 12] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+
+/** Wall-clock durations, counted in buckets that grow roughly exponentially. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, duration: Duration) {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| duration <= Duration::from_millis(*bound))
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    /// Each bucket's upper bound, if it has one, along with how many durations fell into it.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        LATENCY_BUCKETS_MS
+            .iter()
+            .map(|bound| Some(Duration::from_millis(*bound)))
+            .chain(std::iter::once(None))
+            .zip(self.counts.iter().copied())
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count as u32)
+    }
+
+    /**
+     * An upper bound on the given fraction of the durations, i.e. the upper bound of the bucket that
+     * the percentile falls into. Durations in the last bucket are bounded by the longest one.
+     */
+    pub fn percentile(&self, fraction: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let target = ((self.count as f64 * fraction).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (bound, count) in self.buckets() {
+            seen += count;
+            if seen >= target {
+                return Some(bound.map_or(self.max, |bound| bound.min(self.max)));
+            }
+        }
+        unreachable!()
+    }
+}
+
+/**
+ * How long async route queries take, and how often agents have to wait for them. Agents query
+ * their route when they plan a trip, and only join the query route_start_deadline simulated seconds
+ * later, in AgentRouteStart. If the query isn't done by then, the whole engine blocks until it is,
+ * which is a deadline miss. These are wall-clock measurements, so they are only kept for
+ * diagnostics and are not saved.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteLatency {
+    /// from submitting each query to it finishing, including time spent waiting for a worker
+    pub queries: LatencyHistogram,
+    /// how long route starts blocked waiting for queries that missed the deadline
+    pub blocked: LatencyHistogram,
+    /// route starts that joined an async query
+    pub route_starts: u64,
+    /// route starts that had to block
+    pub deadline_misses: u64,
+}
+
+impl RouteLatency {
+    pub(crate) fn record_query(&mut self, duration: Duration) {
+        self.queries.record(duration);
+    }
+
+    /// Record a route start, along with how long it blocked if the query missed the deadline.
+    pub(crate) fn record_route_start(&mut self, blocked: Option<Duration>) {
+        self.route_starts += 1;
+        if let Some(blocked) = blocked {
+            self.deadline_misses += 1;
+            self.blocked.record(blocked);
+        }
+    }
+
+    pub fn miss_rate(&self) -> Option<f64> {
+        (self.route_starts > 0).then(|| self.deadline_misses as f64 / self.route_starts as f64)
+    }
+
+    /**
+     * The route start deadline, in simulated seconds, that would give 95% of queries enough time
+     * to finish when the simulation runs at the given playback rate, in simulated seconds per
+     * wall-clock second.
+     */
+    pub fn suggested_deadline(&self, playback_rate: u64) -> Option<u64> {
+        let p95 = self.queries.percentile(0.95)?;
+        Some(((p95.as_secs_f64() * playback_rate as f64).ceil() as u64).max(1))
+    }
+
+    /// A summary suitable for printing alongside the trigger profile.
+    pub fn to_text(&self) -> String {
+        use std::fmt::Write;
+
+        let millis = |duration: Option<Duration>| {
+            duration.map_or_else(
+                || "-".to_string(),
+                |duration| format!("{:.1} ms", duration.as_secs_f64() * 1000.0),
+            )
+        };
+
+        let mut text = String::new();
+        writeln!(
+            text,
+            "Route queries: {}, mean {}, p95 {}, max {}",
+            self.queries.count,
+            millis(self.queries.mean()),
+            millis(self.queries.percentile(0.95)),
+            millis((self.queries.count > 0).then_some(self.queries.max)),
+        )
+        .unwrap();
+        writeln!(
+            text,
+            "Deadline misses: {} of {} route starts ({:.1}%), blocked for {} in total",
+            self.deadline_misses,
+            self.route_starts,
+            self.miss_rate().unwrap_or(0.0) * 100.0,
+            millis(Some(self.blocked.total)),
+        )
+        .unwrap();
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::route_latency::*;
+
+    #[test]
+    fn histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(0.95), None);
+
+        for millis in [1, 3, 3, 4, 15, 40, 90, 90, 150, 8000] {
+            histogram.record(Duration::from_millis(millis));
+        }
+        assert_eq!(histogram.count, 10);
+        assert_eq!(histogram.mean(), Some(Duration::from_micros(839_600)));
+        assert_eq!(histogram.percentile(0.5), Some(Duration::from_millis(20)));
+        assert_eq!(histogram.percentile(0.8), Some(Duration::from_millis(100)));
+        // the last bucket is bounded by the longest duration
+        assert_eq!(histogram.percentile(1.0), Some(Duration::from_millis(8000)));
+
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(buckets.len(), LATENCY_BUCKETS_MS.len() + 1);
+        assert_eq!(buckets[0], (Some(Duration::from_millis(1)), 1));
+        assert_eq!(buckets[2], (Some(Duration::from_millis(5)), 3));
+        assert_eq!(buckets[LATENCY_BUCKETS_MS.len()], (None, 1));
+    }
+
+    #[test]
+    fn suggested_deadline() {
+        let mut latency = RouteLatency::default();
+        assert_eq!(latency.suggested_deadline(60), None);
+
+        for _ in 0..19 {
+            latency.record_query(Duration::from_millis(30));
+        }
+        latency.record_query(Duration::from_millis(400));
+        // p95 is within the 50ms bucket, which is 3 simulated seconds at 60x
+        assert_eq!(latency.suggested_deadline(60), Some(3));
+        // never suggest zero, which would always block
+        assert_eq!(latency.suggested_deadline(1), Some(1));
+
+        latency.record_route_start(None);
+        latency.record_route_start(Some(Duration::from_millis(20)));
+        assert_eq!(latency.miss_rate(), Some(0.5));
+        assert_eq!(latency.blocked.total, Duration::from_millis(20));
+    }
+}
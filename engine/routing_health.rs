@@ -0,0 +1,73 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uom::si::time::day;
+use uom::si::u64::Time;
+
+/**
+ * Counters for agents that couldn't get where they were going. When a network edit disconnects
+ * part of the map, agents quietly stay put or teleport home instead of failing loudly, so these
+ * counters are the easiest way to notice that something is broken.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RoutingHealth {
+    /// agents that could not find a route to work on the current day
+    pub failed_to_work_today: u64,
+    /// agents that could not find a route to work since the start of the simulation
+    pub failed_to_work_total: u64,
+    /// agents that could not find a route home from work, and were teleported home instead
+    pub teleported_home: u64,
+    /// routes that were aborted because the agent was still traveling when their next trip began
+    pub routes_aborted: u64,
+    /// routes that were queried again because the network changed underneath them before they began
+    #[serde(default)]
+    pub routes_requeried: u64,
+    /// agents that were found still on a route long after it should have ended, e.g. because the
+    /// trigger to advance them was lost, and were teleported home
+    #[serde(default)]
+    pub stuck_agents_recovered: u64,
+    /// the day that failed_to_work_today refers to
+    day: u64,
+}
+
+impl RoutingHealth {
+    fn day(current_time: u64) -> u64 {
+        current_time / Time::new::<day>(1).value
+    }
+
+    /// Returns a copy of the counters as of the given time, resetting the daily counters if needed.
+    pub fn at(&self, current_time: u64) -> Self {
+        let mut health = self.clone();
+        health.roll_over(current_time);
+        health
+    }
+
+    fn roll_over(&mut self, current_time: u64) {
+        let today = Self::day(current_time);
+        if today != self.day {
+            self.failed_to_work_today = 0;
+            self.day = today;
+        }
+    }
+
+    pub(crate) fn record_failed_to_work(&mut self, current_time: u64) {
+        self.roll_over(current_time);
+        self.failed_to_work_today += 1;
+        self.failed_to_work_total += 1;
+    }
+
+    pub(crate) fn record_teleported_home(&mut self) {
+        self.teleported_home += 1;
+    }
+
+    pub(crate) fn record_route_aborted(&mut self) {
+        self.routes_aborted += 1;
+    }
+
+    pub(crate) fn record_route_requeried(&mut self) {
+        self.routes_requeried += 1;
+    }
+
+    pub(crate) fn record_stuck_agent_recovered(&mut self) {
+        self.stuck_agents_recovered += 1;
+    }
+}
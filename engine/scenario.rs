@@ -0,0 +1,634 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use uom::si::time::{day, hour};
+use uom::si::u64::Time;
+
+use crate::change_set::{ChangeSetHandle, StagedChange};
+use crate::closures::NetworkSegment;
+use crate::engine::{Engine, Error, ErrorContext};
+
+/**
+ * A timeline of events that are applied to the simulation at fixed times, e.g. to open a new
+ * highway on day three and add residents to a new development on day five. Scenarios are loaded
+ * from TOML, with one [[events]] table per event.
+ *
+ * NOTE: There is no weather model yet, so scenarios can't override the weather.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub events: Vec<ScenarioEvent>,
+}
+
+impl Scenario {
+    pub fn load(data: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(data)?)
+    }
+
+    pub fn load_file(path: &std::path::Path) -> Result<Self, Error> {
+        Self::load(&std::fs::read_to_string(path)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioEvent {
+    /// days since the start of the simulation
+    pub day: u64,
+    /// hour of the day at which the event is applied, in [0, 24)
+    #[serde(default)]
+    pub hour: u64,
+    #[serde(flatten)]
+    pub action: ScenarioAction,
+}
+
+impl ScenarioEvent {
+    /// The simulation time at which the event is applied.
+    pub fn time(&self) -> u64 {
+        Time::new::<day>(self.day).value + Time::new::<hour>(self.hour).value
+    }
+}
+
+/// Tiles are identified by the (x, y) coordinates of any point inside them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ScenarioAction {
+    /// Commit the change set that is currently open, e.g. one that was prepared in the editor.
+    CommitChangeSet { change_set: ChangeSetHandle },
+    /// Remove highway segments, e.g. to simulate a bridge closure.
+    CloseHighwaySegments {
+        segments: Vec<network::SegmentHandle>,
+    },
+    /// Remove railway segments. Segments used by a metro line can't be closed.
+    CloseRailwaySegments {
+        segments: Vec<network::SegmentHandle>,
+    },
+    /// Close highway segments for a number of hours, e.g. to simulate a crash. See
+    /// Engine::close_segment.
+    TemporarilyCloseHighwaySegments {
+        segments: Vec<network::SegmentHandle>,
+        hours: u64,
+    },
+    /// Close railway segments for a number of hours, e.g. to simulate a signal failure.
+    TemporarilyCloseRailwaySegments {
+        segments: Vec<network::SegmentHandle>,
+        hours: u64,
+    },
+    /// Change the speed limit of highway segments, e.g. to simulate a lowered limit through road
+    /// works. Leaving out the speed limit removes it, so that the default speed is assumed. See
+    /// Engine::set_segment_speed_limit.
+    SetHighwaySpeedLimit {
+        segments: Vec<network::SegmentHandle>,
+        #[serde(default)]
+        speed_limit: Option<u32>,
+    },
+    /// Change the speed limit of railway segments. Leaving out the speed limit removes it, so that
+    /// trains go as fast as their line allows.
+    SetRailwaySpeedLimit {
+        segments: Vec<network::SegmentHandle>,
+        #[serde(default)]
+        speed_limit: Option<u32>,
+    },
+    /// Add agents with randomly generated data to a single housing tile.
+    AddAgents {
+        housing: (u64, u64),
+        #[serde(default)]
+        workplace: Option<(u64, u64)>,
+        count: usize,
+    },
+    /// See Engine::populate_housing.
+    PopulateHousing { occupancy_rate: f64, rng_seed: u64 },
+    /// See Engine::assign_workplaces.
+    AssignWorkplaces {
+        match_rate: f64,
+        max_commute_distance: f64,
+        #[serde(default)]
+        rng_seed: u64,
+    },
+    /// Change config tunables; anything that isn't specified is left alone.
+    UpdateConfig {
+        #[serde(default)]
+        people_per_sim: Option<f64>,
+        #[serde(default)]
+        industry_weights: Option<state::IndustryWeights>,
+        #[serde(default)]
+        scheduling: Option<state::SchedulingConfig>,
+        #[serde(default)]
+        travel_diary: Option<state::TravelDiaryConfig>,
+        /// replaces all of the archetypes; existing agents keep their index into the list
+        #[serde(default)]
+        archetypes: Option<state::AgentArchetypes>,
+        /// applies the next time each trigger re-schedules itself
+        #[serde(default)]
+        trigger_cadence: Option<state::TriggerCadenceConfig>,
+    },
+}
+
+impl ScenarioAction {
+    /// A short description, e.g. for listing upcoming events.
+    pub fn label(&self) -> String {
+        match self {
+            Self::CommitChangeSet { change_set } => format!("commit change set {:?}", change_set),
+            Self::CloseHighwaySegments { segments } => {
+                format!("close {} highway segment(s)", segments.len())
+            }
+            Self::CloseRailwaySegments { segments } => {
+                format!("close {} railway segment(s)", segments.len())
+            }
+            Self::TemporarilyCloseHighwaySegments { segments, hours } => {
+                format!(
+                    "close {} highway segment(s) for {} hour(s)",
+                    segments.len(),
+                    hours
+                )
+            }
+            Self::TemporarilyCloseRailwaySegments { segments, hours } => {
+                format!(
+                    "close {} railway segment(s) for {} hour(s)",
+                    segments.len(),
+                    hours
+                )
+            }
+            Self::SetHighwaySpeedLimit {
+                segments,
+                speed_limit,
+            } => speed_limit_label(segments, "highway", *speed_limit),
+            Self::SetRailwaySpeedLimit {
+                segments,
+                speed_limit,
+            } => speed_limit_label(segments, "railway", *speed_limit),
+            Self::AddAgents { housing, count, .. } => {
+                format!("add {} agent(s) at {:?}", count, housing)
+            }
+            Self::PopulateHousing { occupancy_rate, .. } => {
+                format!("populate housing to {:.0}%", occupancy_rate * 100.0)
+            }
+            Self::AssignWorkplaces { match_rate, .. } => {
+                format!(
+                    "assign workplaces to {:.0}% of unemployed",
+                    match_rate * 100.0
+                )
+            }
+            Self::UpdateConfig { .. } => "update config".to_string(),
+        }
+    }
+}
+
+fn speed_limit_label(
+    segments: &[network::SegmentHandle],
+    kind: &str,
+    speed_limit: Option<u32>,
+) -> String {
+    match speed_limit {
+        Some(speed_limit) => format!(
+            "set speed limit on {} {} segment(s) to {} m/s",
+            segments.len(),
+            kind,
+            speed_limit
+        ),
+        None => format!(
+            "remove speed limit on {} {} segment(s)",
+            segments.len(),
+            kind
+        ),
+    }
+}
+
+/// The scenario that is currently scheduled, if any.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct LoadedScenario {
+    /// sorted by time
+    events: Vec<ScenarioEvent>,
+    /// the index of the next event to apply
+    next: usize,
+    /// incremented each time a scenario is scheduled, so that triggers from a replaced scenario
+    /// don't do anything
+    pub(crate) generation: u64,
+}
+
+/// What the networks will look like at a point in the scenario, for validation.
+#[derive(Debug, Default)]
+struct NetworkTimeline {
+    active: HashSet<network::SegmentHandle>,
+    /// segments added by the change set that is open at this point in the scenario
+    staged: HashSet<network::SegmentHandle>,
+}
+
+impl NetworkTimeline {
+    fn new<S>(segments: &BTreeMap<network::SegmentHandle, network::Segment<S>>) -> Self {
+        let mut timeline = Self::default();
+        for (handle, segment) in segments {
+            match segment.change_state {
+                network::ChangeState::Active | network::ChangeState::Closed { .. } => {
+                    timeline.active.insert(*handle);
+                }
+                network::ChangeState::StagedActive => {
+                    timeline.staged.insert(*handle);
+                }
+                _ => (),
+            }
+        }
+        timeline
+    }
+
+    fn commit(&mut self, removed: &[network::SegmentHandle]) {
+        self.active.extend(self.staged.drain());
+        for segment in removed {
+            self.active.remove(segment);
+        }
+    }
+
+    fn close(&mut self, segments: &[network::SegmentHandle], kind: &str) -> Result<(), String> {
+        self.check(segments, kind)?;
+        for segment in segments {
+            self.active.remove(segment);
+        }
+        Ok(())
+    }
+
+    fn check(&self, segments: &[network::SegmentHandle], kind: &str) -> Result<(), String> {
+        for segment in segments {
+            if !self.active.contains(segment) {
+                return Err(format!(
+                    "{} segment {} does not exist at this point in the scenario",
+                    kind, segment
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Engine {
+    /**
+     * Load a scenario from a TOML file and schedule its events. This replaces any scenario that
+     * was scheduled before.
+     */
+    pub fn load_scenario(&mut self, path: &std::path::Path) -> Result<(), Error> {
+        let scenario = Scenario::load_file(path)
+            .with_context(|| format!("failed to load scenario from {}", path.display()))?;
+        self.schedule_scenario(scenario)
+    }
+
+    /**
+     * Schedule the events of a scenario as one-shot triggers. The whole scenario is validated
+     * first, so that mistakes are caught up front instead of partway through a long simulation;
+     * in particular, every referenced segment and tile must either exist now or be created by an
+     * earlier event. Nothing is scheduled if validation fails.
+     */
+    pub fn schedule_scenario(&mut self, scenario: Scenario) -> Result<(), Error> {
+        let mut events = scenario.events;
+        // NOTE: stable sort so that events at the same time are applied in the order written
+        events.sort_by_key(|event| event.time());
+        self.validate_scenario(&events)?;
+
+        self.scenario.generation += 1;
+        self.scenario.events = events;
+        self.scenario.next = 0;
+
+        for (index, event) in self.scenario.events.iter().enumerate() {
+            self.trigger_queue.push(
+                crate::behavior::ApplyScenarioEvent {
+                    generation: self.scenario.generation,
+                    index,
+                },
+                event.time(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The events of the scheduled scenario that haven't been applied yet, in order.
+    pub fn upcoming_scenario_events(&self) -> &[ScenarioEvent] {
+        &self.scenario.events[self.scenario.next..]
+    }
+
+    /// The event that an ApplyScenarioEvent trigger refers to, unless its scenario was replaced.
+    pub(crate) fn scenario_event(&self, generation: u64, index: usize) -> Option<&ScenarioEvent> {
+        if generation != self.scenario.generation {
+            return None;
+        }
+        self.scenario.events.get(index)
+    }
+
+    fn validate_scenario(&self, events: &[ScenarioEvent]) -> Result<(), Error> {
+        let mut highways = NetworkTimeline::new(self.state.highways.segments());
+        let mut railways = NetworkTimeline::new(self.state.railways.segments());
+        let mut open_change_set = self.open_change_set();
+        let mut config = self.state.config.clone();
+
+        for (index, event) in events.iter().enumerate() {
+            let invalid = |message: String| {
+                Error::InvalidScenario(format!(
+                    "event {} ({}, day {} hour {}): {}",
+                    index,
+                    event.action.label(),
+                    event.day,
+                    event.hour,
+                    message
+                ))
+            };
+
+            if event.hour >= 24 {
+                return Err(invalid("hour must be less than 24".to_string()));
+            }
+            if event.time() < self.time_state.current_time {
+                return Err(invalid("event is in the past".to_string()));
+            }
+
+            match &event.action {
+                ScenarioAction::CommitChangeSet { change_set } => {
+                    if open_change_set != Some(*change_set) {
+                        return Err(invalid("change set is not open".to_string()));
+                    }
+                    let (mut removed_highways, mut removed_railways) = (Vec::new(), Vec::new());
+                    for change in self.staged_changes(*change_set)? {
+                        match change {
+                            StagedChange::RemoveHighwaySegment(segment) => {
+                                removed_highways.push(*segment)
+                            }
+                            StagedChange::RemoveRailwaySegment(segment) => {
+                                removed_railways.push(*segment)
+                            }
+                            _ => (),
+                        }
+                    }
+                    highways.commit(&removed_highways);
+                    railways.commit(&removed_railways);
+                    open_change_set = None;
+                }
+                ScenarioAction::CloseHighwaySegments { segments }
+                | ScenarioAction::CloseRailwaySegments { segments } => {
+                    // NOTE: closures are applied as their own change set
+                    if open_change_set.is_some() {
+                        return Err(invalid(
+                            "can't close segments while a change set is open".to_string(),
+                        ));
+                    }
+                    if let ScenarioAction::CloseHighwaySegments { .. } = event.action {
+                        highways.close(segments, "highway").map_err(invalid)?;
+                    } else {
+                        railways.close(segments, "railway").map_err(invalid)?;
+                        for segment in segments {
+                            if !self
+                                .state
+                                .metros
+                                .railway_segment_metro_lines(*segment)
+                                .is_empty()
+                            {
+                                return Err(invalid(format!(
+                                    "railway segment {} is used by a metro line",
+                                    segment
+                                )));
+                            }
+                        }
+                    }
+                }
+                ScenarioAction::TemporarilyCloseHighwaySegments { segments, hours }
+                | ScenarioAction::TemporarilyCloseRailwaySegments { segments, hours } => {
+                    if *hours == 0 {
+                        return Err(invalid("hours must be positive".to_string()));
+                    }
+                    // NOTE: the segments are still there once the closure is over
+                    if let ScenarioAction::TemporarilyCloseHighwaySegments { .. } = event.action {
+                        highways.check(segments, "highway").map_err(invalid)?;
+                    } else {
+                        railways.check(segments, "railway").map_err(invalid)?;
+                    }
+                }
+                ScenarioAction::SetHighwaySpeedLimit {
+                    segments,
+                    speed_limit,
+                }
+                | ScenarioAction::SetRailwaySpeedLimit {
+                    segments,
+                    speed_limit,
+                } => {
+                    if *speed_limit == Some(0) {
+                        return Err(invalid("speed limit must be positive".to_string()));
+                    }
+                    if let ScenarioAction::SetHighwaySpeedLimit { .. } = event.action {
+                        highways.check(segments, "highway").map_err(invalid)?;
+                    } else {
+                        railways.check(segments, "railway").map_err(invalid)?;
+                    }
+                }
+                ScenarioAction::AddAgents {
+                    housing, workplace, ..
+                } => {
+                    match self.scenario_tile(*housing).map_err(invalid)? {
+                        tiles::Tile::HousingTile(_) => (),
+                        tile => return Err(invalid(format!("expected housing, found {:?}", tile))),
+                    }
+                    if let Some(workplace) = workplace {
+                        match self.scenario_tile(*workplace).map_err(invalid)? {
+                            tiles::Tile::WorkplaceTile(_) => (),
+                            tile => {
+                                return Err(invalid(format!(
+                                    "expected workplace, found {:?}",
+                                    tile
+                                )))
+                            }
+                        }
+                    }
+                }
+                ScenarioAction::PopulateHousing { occupancy_rate, .. } => {
+                    if !(0.0..=1.0).contains(occupancy_rate) {
+                        return Err(invalid("occupancy rate must be in [0, 1]".to_string()));
+                    }
+                }
+                ScenarioAction::AssignWorkplaces { match_rate, .. } => {
+                    if !(0.0..=1.0).contains(match_rate) {
+                        return Err(invalid("match rate must be in [0, 1]".to_string()));
+                    }
+                }
+                ScenarioAction::UpdateConfig { .. } => {
+                    apply_config_change(&mut config, &event.action);
+                    config.validate().map_err(|err| invalid(err.to_string()))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scenario_tile(&self, (x, y): (u64, u64)) -> Result<&tiles::Tile, String> {
+        let width = self.state.config.tile_width() as u64;
+        if x >= width || y >= width {
+            return Err(format!("({}, {}) is outside of the map", x, y));
+        }
+        let address = self
+            .state
+            .qtree
+            .get_address(x, y)
+            .map_err(|err| err.to_string())?;
+        self.state
+            .qtree
+            .get_leaf(address)
+            .map(|leaf| &leaf.tile)
+            .map_err(|err| err.to_string())
+    }
+
+    pub(crate) fn apply_scenario_event(
+        &mut self,
+        generation: u64,
+        index: usize,
+    ) -> Result<(), Error> {
+        // the scenario that scheduled this event has been replaced
+        let event = match self.scenario_event(generation, index) {
+            Some(event) => event.clone(),
+            None => return Ok(()),
+        };
+        self.scenario.next = index + 1;
+        let context = || format!("failed to apply scenario event {}", event.action.label());
+
+        match &event.action {
+            ScenarioAction::CommitChangeSet { change_set } => {
+                self.commit_change_set(*change_set).with_context(context)?;
+            }
+            ScenarioAction::CloseHighwaySegments { segments } => {
+                let change_set = self.begin_change_set().with_context(context)?;
+                for segment in segments {
+                    self.staged_remove_highway_segment(change_set, *segment)
+                        .with_context(context)?;
+                }
+                self.commit_change_set(change_set).with_context(context)?;
+            }
+            ScenarioAction::CloseRailwaySegments { segments } => {
+                let change_set = self.begin_change_set().with_context(context)?;
+                for segment in segments {
+                    self.staged_remove_railway_segment(change_set, *segment)
+                        .with_context(context)?;
+                }
+                self.commit_change_set(change_set).with_context(context)?;
+            }
+            ScenarioAction::TemporarilyCloseHighwaySegments { segments, hours } => {
+                for segment in segments {
+                    self.close_segment_for_hours(NetworkSegment::Highway(*segment), *hours)
+                        .with_context(context)?;
+                }
+            }
+            ScenarioAction::TemporarilyCloseRailwaySegments { segments, hours } => {
+                for segment in segments {
+                    self.close_segment_for_hours(NetworkSegment::Railway(*segment), *hours)
+                        .with_context(context)?;
+                }
+            }
+            ScenarioAction::SetHighwaySpeedLimit {
+                segments,
+                speed_limit,
+            } => {
+                for segment in segments {
+                    self.set_segment_speed_limit(NetworkSegment::Highway(*segment), *speed_limit)
+                        .with_context(context)?;
+                }
+            }
+            ScenarioAction::SetRailwaySpeedLimit {
+                segments,
+                speed_limit,
+            } => {
+                for segment in segments {
+                    self.set_segment_speed_limit(NetworkSegment::Railway(*segment), *speed_limit)
+                        .with_context(context)?;
+                }
+            }
+            ScenarioAction::AddAgents {
+                housing,
+                workplace,
+                count,
+            } => {
+                let housing = self.state.qtree.get_address(housing.0, housing.1)?;
+                let workplace = match workplace {
+                    Some((x, y)) => Some(self.state.qtree.get_address(*x, *y)?),
+                    None => None,
+                };
+                for (address, kind) in [(Some(housing), "housing"), (workplace, "workplace")] {
+                    let vacancies = match address.map(|address| self.state.qtree.get_leaf(address))
+                    {
+                        Some(Ok(state::LeafState {
+                            tile: tiles::Tile::HousingTile(tiles::HousingTile { density, agents }),
+                            ..
+                        }))
+                        | Some(Ok(state::LeafState {
+                            tile:
+                                tiles::Tile::WorkplaceTile(tiles::WorkplaceTile {
+                                    density, agents, ..
+                                }),
+                            ..
+                        })) => density.saturating_sub(agents.len()),
+                        Some(_) => 0,
+                        None => continue,
+                    };
+                    if vacancies < *count {
+                        return Err(Error::InvalidScenario(format!(
+                            "only {} vacancies in {} tile for {} agents",
+                            vacancies, kind, count
+                        )))
+                        .with_context(context);
+                    }
+                }
+                for _ in 0..*count {
+                    let data = crate::populate::AgentDataDistribution::default()
+                        .sample_with_archetype(&self.state.config.archetypes, &mut self.rng);
+                    self.add_agent(data, housing, workplace)
+                        .with_context(context)?;
+                }
+                self.state.update_collect_tiles()?;
+            }
+            ScenarioAction::PopulateHousing {
+                occupancy_rate,
+                rng_seed,
+            } => {
+                self.populate_housing(*occupancy_rate, *rng_seed, &Default::default())
+                    .with_context(context)?;
+            }
+            ScenarioAction::AssignWorkplaces {
+                match_rate,
+                max_commute_distance,
+                rng_seed,
+            } => {
+                self.assign_workplaces(*match_rate, *rng_seed, *max_commute_distance)
+                    .with_context(context)?;
+            }
+            ScenarioAction::UpdateConfig { .. } => {
+                apply_config_change(&mut self.state.config, &event.action);
+                // edge costs depend on the config
+                self.base_graph.write().unwrap().clear();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn apply_config_change(config: &mut state::Config, action: &ScenarioAction) {
+    if let ScenarioAction::UpdateConfig {
+        people_per_sim,
+        industry_weights,
+        scheduling,
+        travel_diary,
+        archetypes,
+        trigger_cadence,
+    } = action
+    {
+        if let Some(people_per_sim) = people_per_sim {
+            config.people_per_sim = *people_per_sim;
+        }
+        if let Some(industry_weights) = industry_weights {
+            config.industry_weights = industry_weights.clone();
+        }
+        if let Some(scheduling) = scheduling {
+            config.scheduling = scheduling.clone();
+        }
+        if let Some(travel_diary) = travel_diary {
+            config.travel_diary = travel_diary.clone();
+        }
+        if let Some(archetypes) = archetypes {
+            config.archetypes = archetypes.clone();
+        }
+        if let Some(trigger_cadence) = trigger_cadence {
+            config.trigger_cadence = trigger_cadence.clone();
+        }
+    }
+}
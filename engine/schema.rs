@@ -0,0 +1,128 @@
+use schemars::schema::RootSchema;
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::fields::FieldsState;
+
+/**
+ * The top-level layout of a save file, as written by Engine::dump. This is only used to generate
+ * the schema; it mirrors the fields of Engine that get serialized, which the tests check against a
+ * dump. Most of the simulation state is an implementation detail that external tools shouldn't
+ * depend on, so it is left unconstrained.
+ */
+#[derive(JsonSchema, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct SaveFile {
+    state: SavedState,
+    world_state: serde_json::Value,
+    /// the traffic history itself is saved in a separate file
+    #[serde(default)]
+    history_hash: Option<u64>,
+    #[serde(default)]
+    snapshots_since_history_save: usize,
+    #[serde(default)]
+    injected_highway_travelers: serde_json::Value,
+    time_state: serde_json::Value,
+    agents: serde_json::Value,
+    agent_counter: u64,
+    #[serde(default)]
+    households: serde_json::Value,
+    #[serde(default)]
+    household_counter: u64,
+    trigger_queue: serde_json::Value,
+    rng: serde_json::Value,
+    #[serde(default)]
+    routing_health: crate::routing_health::RoutingHealth,
+    #[serde(default)]
+    alerts: serde_json::Value,
+    #[serde(default)]
+    change_sets: serde_json::Value,
+    /// the scheduled scenario, if any; see Engine::load_scenario
+    #[serde(default)]
+    scenario: serde_json::Value,
+    #[serde(default)]
+    job_quits: serde_json::Value,
+}
+
+/** Mirrors the fields of state::State that get serialized. */
+#[derive(JsonSchema, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct SavedState {
+    config: state::Config,
+    /// the leaves are described by the leaf_state schema
+    qtree: serde_json::Value,
+    /// the junctions and segments are described by the railway_* schemas
+    railways: serde_json::Value,
+    /// the junctions and segments are described by the highway_* schemas
+    highways: serde_json::Value,
+    /// the metro lines are described by the metro_line_data schema
+    metros: serde_json::Value,
+    #[serde(default)]
+    metadata: state::MapMetadata,
+    /// the last tile id that was assigned
+    #[serde(default)]
+    tile_id_counter: u64,
+}
+
+/// The schema for leaves, as read and written by State::get_leaf_data and State::set_leaf_data.
+pub fn leaf_schema() -> RootSchema {
+    schema_for!(state::LeafState<FieldsState>)
+}
+
+/**
+ * The schemas for each of the types that external tools (map generators, analysis scripts) read
+ * or write, by name.
+ */
+pub fn all_schemas() -> Vec<(&'static str, RootSchema)> {
+    vec![
+        ("tile", schema_for!(tiles::Tile)),
+        ("leaf_state", leaf_schema()),
+        ("branch_state", schema_for!(state::BranchState<FieldsState>)),
+        ("config", schema_for!(state::Config)),
+        ("highway_segment", schema_for!(highway::HighwaySegment)),
+        ("highway_junction", schema_for!(highway::HighwayJunction)),
+        ("railway_segment", schema_for!(metro::RailwaySegment)),
+        ("railway_junction", schema_for!(metro::RailwayJunction)),
+        ("metro_line_data", schema_for!(metro::MetroLineData)),
+        ("agent_data", schema_for!(agent::AgentData)),
+        ("save_file", schema_for!(SaveFile)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::*;
+    use crate::Engine;
+
+    /// SaveFile is written by hand, so make sure that it hasn't drifted from what Engine::dump writes.
+    #[test]
+    fn save_file_round_trip() {
+        let engine = Engine::new(state::Config {
+            max_depth: 3,
+            people_per_sim: 1.0,
+            min_tile_size: 1,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        });
+        let dump = engine.dump().unwrap();
+
+        // fails on fields that SaveFile doesn't know about, or that it requires but are missing
+        let save: SaveFile = serde_json::from_str(&dump).unwrap();
+        // and what SaveFile writes back has to load as the same engine
+        let reloaded = Engine::load(&serde_json::to_string(&save).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&reloaded.state.config).unwrap(),
+            serde_json::to_value(&engine.state.config).unwrap()
+        );
+    }
+}
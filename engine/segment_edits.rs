@@ -0,0 +1,78 @@
+use std::collections::BTreeSet;
+
+use crate::closures::NetworkSegment;
+use crate::engine::{Engine, Error};
+
+impl Engine {
+    /**
+     * Replace the data of a highway segment, e.g. to change its speed limit or number of lanes.
+     * Travel times on highways are computed from the data, so the routing graph is reconstructed
+     * and the graph version changes. Routes that are already underway keep their old timing.
+     */
+    pub fn update_highway_segment(
+        &mut self,
+        segment: network::SegmentHandle,
+        data: highway::HighwaySegment,
+    ) -> Result<(), Error> {
+        let mut base_graph = self.base_graph.write().unwrap();
+        self.state
+            .highways
+            .update_segment_data(segment, data)
+            .map_err(|err| {
+                Error::InvalidSegmentData(format!("{}: {}", NetworkSegment::Highway(segment), err))
+            })?;
+        base_graph.clear();
+        Ok(())
+    }
+
+    /**
+     * Replace the data of a railway segment, like update_highway_segment. Returns the metro lines
+     * that use the segment, whose timetables have changed.
+     */
+    pub fn update_railway_segment(
+        &mut self,
+        segment: network::SegmentHandle,
+        data: metro::RailwaySegment,
+    ) -> Result<BTreeSet<metro::MetroLineHandle>, Error> {
+        let mut base_graph = self.base_graph.write().unwrap();
+        let metro_lines =
+            self.state
+                .metros
+                .update_railway_segment(segment, data, &mut self.state.railways)?;
+        base_graph.clear();
+        Ok(metro_lines)
+    }
+
+    /**
+     * Change the speed limit of a segment of either network, leaving the rest of its data alone.
+     * A speed limit of None means that the segment has no limit of its own: highways assume a
+     * default speed, and trains go as fast as their line allows. Returns the metro lines whose
+     * timetables have changed, which is always empty for highways.
+     */
+    pub fn set_segment_speed_limit(
+        &mut self,
+        segment: NetworkSegment,
+        speed_limit: Option<u32>,
+    ) -> Result<BTreeSet<metro::MetroLineHandle>, Error> {
+        let missing = || Error::InvalidSegmentData(format!("{} does not exist", segment));
+        match segment {
+            NetworkSegment::Highway(id) => {
+                let old = self.state.highways.try_segment(id).ok_or_else(missing)?;
+                let data = highway::HighwaySegment {
+                    speed_limit,
+                    ..old.data.clone()
+                };
+                self.update_highway_segment(id, data)?;
+                Ok(BTreeSet::new())
+            }
+            NetworkSegment::Railway(id) => {
+                let old = self.state.railways.try_segment(id).ok_or_else(missing)?;
+                let data = metro::RailwaySegment {
+                    speed_limit,
+                    ..old.data.clone()
+                };
+                self.update_railway_segment(id, data)
+            }
+        }
+    }
+}
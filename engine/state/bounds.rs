@@ -0,0 +1,458 @@
+use crate::state::{BranchState, Error, Fields, LeafState, State};
+
+/// How far inside the map out-of-bounds coordinates are moved, in tiles.
+pub const BOUNDS_EPSILON: f64 = 1e-6;
+
+/**
+ * What to do with a location outside of the map, i.e. outside of [0, width) in either dimension.
+ * Such locations usually come from rounding when imported maps are projected, and only cause
+ * problems much later, e.g. when they are converted to addresses.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoundsPolicy {
+    /// Fail with Error::OutOfBounds. Imports should use this, so that bad data is noticed.
+    #[default]
+    Reject,
+    /// Move the location just inside the map, with a warning. Interactive edits should use this,
+    /// since the user can't be expected to be that precise.
+    Clamp,
+}
+
+/// Something that State::repair_out_of_bounds moved back inside the map.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BoundsRepair {
+    HighwayJunction {
+        junction: network::JunctionHandle,
+        from: (f64, f64),
+        to: (f64, f64),
+    },
+    RailwayJunction {
+        junction: network::JunctionHandle,
+        from: (f64, f64),
+        to: (f64, f64),
+    },
+    /// a segment with spline keys outside of the map, not counting the keys at its junctions
+    HighwaySegment(network::SegmentHandle),
+    RailwaySegment(network::SegmentHandle),
+    /// the exact location of a station, from the station tile
+    Station {
+        address: quadtree::Address,
+        from: (u64, u64),
+        to: (u64, u64),
+    },
+}
+
+impl std::fmt::Display for BoundsRepair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HighwayJunction { junction, from, to } => write!(
+                f,
+                "moved highway junction {} from {:?} to {:?}",
+                junction, from, to
+            ),
+            Self::RailwayJunction { junction, from, to } => write!(
+                f,
+                "moved railway junction {} from {:?} to {:?}",
+                junction, from, to
+            ),
+            Self::HighwaySegment(segment) => {
+                write!(f, "moved the spline of highway segment {}", segment)
+            }
+            Self::RailwaySegment(segment) => {
+                write!(f, "moved the spline of railway segment {}", segment)
+            }
+            Self::Station { address, from, to } => write!(
+                f,
+                "moved station at {:?} from {:?} to {:?}",
+                address, from, to
+            ),
+        }
+    }
+}
+
+/// Everything that State::repair_out_of_bounds changed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BoundsRepairReport {
+    pub repairs: Vec<BoundsRepair>,
+}
+
+impl BoundsRepairReport {
+    pub fn is_empty(&self) -> bool {
+        self.repairs.is_empty()
+    }
+}
+
+/// Move the coordinate inside [0, width), or return None if it is already inside.
+fn clamp_coord(value: f64, width: f64) -> Option<f64> {
+    if value < 0.0 {
+        Some(0.0)
+    } else if value >= width {
+        Some(width - BOUNDS_EPSILON)
+    } else {
+        None
+    }
+}
+
+/// Move the location inside the map, or return None if it is already inside.
+fn clamp_location(location: network::Key, width: f64) -> Option<network::Key> {
+    let x = clamp_coord(location.x, width);
+    let y = clamp_coord(location.y, width);
+    if x.is_none() && y.is_none() {
+        return None;
+    }
+    Some(network::Key::new(
+        x.unwrap_or(location.x),
+        y.unwrap_or(location.y),
+    ))
+}
+
+/// a junction, and where it was moved from and to
+type MovedJunction = (network::JunctionHandle, (f64, f64), (f64, f64));
+
+/**
+ * Move all of the junctions and spline keys in the network inside the map. Returns the junctions
+ * that were moved, with their old and new locations, and the segments whose keys were moved.
+ */
+fn repair_network<J: Clone, S: Clone>(
+    network: &mut network::Network<J, S>,
+    width: f64,
+) -> (Vec<MovedJunction>, Vec<network::SegmentHandle>) {
+    let junctions: Vec<_> = network
+        .junctions()
+        .values()
+        .filter_map(|junction| {
+            clamp_location(junction.location, width).map(|to| (junction.id, junction.location, to))
+        })
+        .collect();
+    for (junction, _, to) in &junctions {
+        network.junction_mut(*junction).location = *to;
+    }
+
+    let mut segments = Vec::new();
+    let handles: Vec<_> = network.segments().keys().copied().collect();
+    for handle in handles {
+        let segment = network.segment(handle);
+        let keys = segment.keys();
+        if keys.iter().all(|key| clamp_location(*key, width).is_none()) {
+            continue;
+        }
+        // the keys at the ends are moved along with their junctions, so only report the segment
+        // if some key in between was out of bounds
+        let interior = keys.len() > 2
+            && keys[1..keys.len() - 1]
+                .iter()
+                .any(|key| clamp_location(*key, width).is_some());
+        let keys = keys
+            .iter()
+            .map(|key| clamp_location(*key, width).unwrap_or(*key))
+            .collect();
+        network.segment_mut(handle).set_keys(keys);
+        if interior {
+            segments.push(handle);
+        }
+    }
+
+    let junctions = junctions
+        .into_iter()
+        .map(|(junction, from, to)| (junction, from.into(), to.into()))
+        .collect();
+    (junctions, segments)
+}
+
+struct RepairStationsVisitor {
+    width: u64,
+    repairs: Vec<BoundsRepair>,
+}
+
+impl<F: Fields> quadtree::MutVisitor<BranchState<F>, LeafState<F>, Error>
+    for RepairStationsVisitor
+{
+    fn visit_branch_pre(
+        &mut self,
+        _branch: &mut BranchState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn visit_leaf(
+        &mut self,
+        leaf: &mut LeafState<F>,
+        data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        if let tiles::Tile::MetroStationTile(tile) = &mut leaf.tile {
+            let from = (tile.x, tile.y);
+            tile.x = tile.x.min(self.width - 1);
+            tile.y = tile.y.min(self.width - 1);
+            if (tile.x, tile.y) != from {
+                self.repairs.push(BoundsRepair::Station {
+                    address: data.address,
+                    from,
+                    to: (tile.x, tile.y),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &mut BranchState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<F: Fields> State<F> {
+    /**
+     * Check that the location is inside the map, i.e. inside [0, width) in both dimensions.
+     * Returns the location to use, which is clamped inside the map if it is outside and the
+     * policy allows it.
+     */
+    pub fn check_bounds(
+        &self,
+        location: (f64, f64),
+        policy: BoundsPolicy,
+    ) -> Result<(f64, f64), Error> {
+        let width = self.qtree.width();
+        match clamp_location(location.into(), width as f64) {
+            None => Ok(location),
+            Some(clamped) => match policy {
+                BoundsPolicy::Reject => Err(Error::OutOfBounds(location, width)),
+                BoundsPolicy::Clamp => {
+                    let clamped = clamped.into();
+                    eprintln!(
+                        "Moved location {:?} outside of the map to {:?}",
+                        location, clamped
+                    );
+                    Ok(clamped)
+                }
+            },
+        }
+    }
+
+    /// Add a highway junction, checking that it is inside the map; see check_bounds.
+    pub fn add_highway_junction(
+        &mut self,
+        location: (f64, f64),
+        data: highway::HighwayJunction,
+        policy: BoundsPolicy,
+    ) -> Result<network::JunctionHandle, Error> {
+        let location = self.check_bounds(location, policy)?;
+        Ok(self.highways.add_junction(location, data))
+    }
+
+    /**
+     * Add a railway junction, e.g. for a new station, checking that it is inside the map; see
+     * check_bounds.
+     */
+    pub fn add_railway_junction(
+        &mut self,
+        location: (f64, f64),
+        data: metro::RailwayJunction,
+        policy: BoundsPolicy,
+    ) -> Result<network::JunctionHandle, Error> {
+        let location = self.check_bounds(location, policy)?;
+        Ok(self.railways.add_junction(location, data))
+    }
+
+    /**
+     * Move everything that is outside of the map just inside of it: highway and railway junctions,
+     * the splines of highway and railway segments, and the exact locations of stations. This is
+     * for repairing maps that were imported before locations were checked. Returns what was
+     * changed.
+     */
+    pub fn repair_out_of_bounds(&mut self) -> BoundsRepairReport {
+        let width = self.qtree.width();
+        let mut report = BoundsRepairReport::default();
+
+        let (junctions, segments) = repair_network(&mut self.highways, width as f64);
+        report.repairs.extend(
+            junctions
+                .into_iter()
+                .map(|(junction, from, to)| BoundsRepair::HighwayJunction { junction, from, to }),
+        );
+        report
+            .repairs
+            .extend(segments.into_iter().map(BoundsRepair::HighwaySegment));
+
+        let (junctions, segments) = repair_network(&mut self.railways, width as f64);
+        report.repairs.extend(
+            junctions
+                .into_iter()
+                .map(|(junction, from, to)| BoundsRepair::RailwayJunction { junction, from, to }),
+        );
+        report
+            .repairs
+            .extend(segments.into_iter().map(BoundsRepair::RailwaySegment));
+
+        let mut visitor = RepairStationsVisitor {
+            width,
+            repairs: Vec::new(),
+        };
+        self.qtree
+            .visit_mut(&mut visitor)
+            .expect("should be impossible");
+        report.repairs.extend(visitor.repairs);
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bounds::*;
+    use crate::Config;
+
+    #[derive(Debug, Default, Clone)]
+    struct TestFields {}
+
+    impl Fields for TestFields {}
+
+    /// A map that is 4 tiles wide.
+    fn new_state() -> State<TestFields> {
+        State::new(Config {
+            max_depth: 2,
+            people_per_sim: 1.0,
+            min_tile_size: 100,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        })
+    }
+
+    fn junction() -> highway::HighwayJunction {
+        highway::HighwayJunction { ramp: None }
+    }
+
+    #[test]
+    fn reject() {
+        let mut state = new_state();
+        let width = state.qtree.width() as f64;
+        for location in [(width, 1.0), (1.0, width + 0.5), (-0.5, 1.0)] {
+            assert!(matches!(
+                state.add_highway_junction(location, junction(), BoundsPolicy::Reject),
+                Err(Error::OutOfBounds(_, 4))
+            ));
+        }
+        assert!(state.highways.junctions().is_empty());
+
+        let inside = (width - 0.5, 0.0);
+        let id = state
+            .add_highway_junction(inside, junction(), BoundsPolicy::Reject)
+            .unwrap();
+        assert_eq!(state.highways.junction(id).location, inside.into());
+    }
+
+    #[test]
+    fn clamp() {
+        let mut state = new_state();
+        let width = state.qtree.width() as f64;
+        for (location, expected) in [
+            ((width, 1.0), (width - BOUNDS_EPSILON, 1.0)),
+            ((1.0, width + 0.5), (1.0, width - BOUNDS_EPSILON)),
+            ((-0.5, -0.5), (0.0, 0.0)),
+        ] {
+            let id = state
+                .add_railway_junction(
+                    location,
+                    metro::RailwayJunction::new(None),
+                    BoundsPolicy::Clamp,
+                )
+                .unwrap();
+            let location = state.railways.junction(id).location;
+            assert_eq!(location, expected.into());
+            // the clamped location can be converted to an address
+            state.railways.junction(id).address(state.config.max_depth);
+        }
+    }
+
+    #[test]
+    fn repair() {
+        let mut state = new_state();
+        let width = state.qtree.width() as f64;
+
+        // junctions that were added before locations were checked
+        let a = state.highways.add_junction((1.0, 1.0), junction());
+        let b = state.highways.add_junction((width + 0.5, 2.0), junction());
+        let highway = state.highways.add_segment(
+            highway::HighwaySegment::new(None, vec![], None, None),
+            a,
+            b,
+            Some(vec![
+                (1.0, 1.0).into(),
+                (2.0, -0.25).into(),
+                (width + 0.5, 2.0).into(),
+            ]),
+        );
+        let c = state
+            .railways
+            .add_junction((2.0, 2.0), metro::RailwayJunction::new(None));
+        let d = state
+            .railways
+            .add_junction((2.0, width), metro::RailwayJunction::new(None));
+        let railway = state.railways.add_segment(
+            metro::RailwaySegment::new(None),
+            c,
+            d,
+            Some(vec![(2.0, 2.0).into(), (2.0, width).into()]),
+        );
+
+        // the map is a single leaf
+        let station = quadtree::Address::from((vec![], 2));
+        state.qtree.get_leaf_mut(station).unwrap().tile = tiles::MetroStationTile {
+            name: "Edge".to_string(),
+            x: 4,
+            y: 3,
+            ids: vec![],
+            parking: true,
+        }
+        .into();
+
+        let report = state.repair_out_of_bounds();
+        let edge = width - BOUNDS_EPSILON;
+        assert_eq!(
+            report.repairs,
+            vec![
+                BoundsRepair::HighwayJunction {
+                    junction: b,
+                    from: (width + 0.5, 2.0),
+                    to: (edge, 2.0),
+                },
+                BoundsRepair::HighwaySegment(highway),
+                BoundsRepair::RailwayJunction {
+                    junction: d,
+                    from: (2.0, width),
+                    to: (2.0, edge),
+                },
+                BoundsRepair::Station {
+                    address: station,
+                    from: (4, 3),
+                    to: (3, 3),
+                },
+            ]
+        );
+
+        let in_bounds =
+            |key: &network::Key| (0.0..width).contains(&key.x) && (0.0..width).contains(&key.y);
+        for junction in state.highways.junctions().values() {
+            assert!(in_bounds(&junction.location));
+        }
+        for junction in state.railways.junctions().values() {
+            assert!(in_bounds(&junction.location));
+        }
+        assert!(state.highways.segment(highway).keys().iter().all(in_bounds));
+        assert!(state.railways.segment(railway).keys().iter().all(in_bounds));
+
+        // everything is in bounds now, so there is nothing left to repair
+        assert!(state.repair_out_of_bounds().is_empty());
+    }
+}
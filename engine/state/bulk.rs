@@ -0,0 +1,226 @@
+use crate::state::{BranchState, Error, Fields, LeafState, State};
+
+/** An operation that can be applied to many leaves at once; see State::bulk_apply. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BulkOp {
+    /// Replace the tile with water.
+    SetWater,
+    /// Replace the tile with an empty tile.
+    SetEmpty,
+    /// Set the density of housing tiles. Empty tiles become housing with the given density.
+    SetHousingDensity(usize),
+    /// Split empty and water tiles until all of the resulting leaves are at the given depth. Each
+    /// of the new leaves has the same tile as the original leaf, and any point of interest ends up in
+    /// the northwest one.
+    SplitToDepth(u32),
+}
+
+/**
+ * The outcome of applying a bulk operation. Failing to apply the operation to one address doesn't
+ * stop it from being applied to the rest, so the errors are collected instead.
+ */
+#[derive(Debug)]
+pub struct BulkReport<E> {
+    /// addresses that the operation was successfully applied to
+    pub applied: Vec<quadtree::Address>,
+    /// addresses that the operation could not be applied to, and why
+    pub errors: Vec<(quadtree::Address, E)>,
+}
+
+impl<E> Default for BulkReport<E> {
+    fn default() -> Self {
+        Self {
+            applied: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl<E> BulkReport<E> {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn map_err<T, F: Fn(E) -> T>(self, f: F) -> BulkReport<T> {
+        BulkReport {
+            applied: self.applied,
+            errors: self
+                .errors
+                .into_iter()
+                .map(|(address, err)| (address, f(err)))
+                .collect(),
+        }
+    }
+}
+
+/**
+ * Returns the addresses of all leaves that intersect the given bounds, optionally only those with
+ * the given type of tile. This is State::leaves_in_rect for callers that only have the quadtree,
+ * e.g. a snapshot of it.
+ */
+pub fn leaves_in_rect<F: Fields>(
+    qtree: &quadtree::Quadtree<BranchState<F>, LeafState<F>>,
+    bounds: &quadtree::Rect,
+    tile_type: Option<std::mem::Discriminant<tiles::Tile>>,
+) -> Vec<quadtree::Address> {
+    let mut visitor = CollectLeavesVisitor {
+        leaves: Vec::new(),
+        tile_type,
+    };
+    qtree
+        .visit_rect(&mut visitor, bounds)
+        .expect("should be impossible");
+    visitor.leaves
+}
+
+impl<F: Fields> State<F> {
+    /// Returns the addresses of all leaves that intersect the given bounds.
+    pub fn leaves_in_rect(&self, bounds: &quadtree::Rect) -> Vec<quadtree::Address> {
+        leaves_in_rect(&self.qtree, bounds, None)
+    }
+
+    /**
+     * Returns the addresses of all leaves that intersect the given bounds and have the same type of
+     * tile, e.g. to select every housing tile on the map.
+     */
+    pub fn leaves_of_type_in_rect(
+        &self,
+        bounds: &quadtree::Rect,
+        tile_type: std::mem::Discriminant<tiles::Tile>,
+    ) -> Vec<quadtree::Address> {
+        leaves_in_rect(&self.qtree, bounds, Some(tile_type))
+    }
+
+    /**
+     * Apply an operation to each of the given leaves. Tiles that have agents or that are part of
+     * another structure (e.g. metro stations) are left alone and reported as errors, since
+     * modifying them here would leave the rest of the engine in an inconsistent state.
+     *
+     * This function should not be used directly, instead use Engine::bulk_apply.
+     */
+    pub fn bulk_apply(
+        &mut self,
+        addresses: &[quadtree::Address],
+        op: &BulkOp,
+        current_time: i64,
+    ) -> BulkReport<Error> {
+        let mut report = BulkReport::default();
+        for address in addresses {
+            match self.bulk_apply_one(*address, op, current_time) {
+                Ok(()) => report.applied.push(*address),
+                Err(err) => report.errors.push((*address, err)),
+            }
+        }
+        report
+    }
+
+    fn bulk_apply_one(
+        &mut self,
+        address: quadtree::Address,
+        op: &BulkOp,
+        current_time: i64,
+    ) -> Result<(), Error> {
+        use tiles::{Tile::*, TileType};
+
+        let leaf = self.qtree.get_leaf_mut(address)?;
+        match (&mut leaf.tile, op) {
+            (MetroStationTile(tile), _) => Err(Error::UnsupportedTile(address, tile.name())),
+            (HousingTile(tiles::HousingTile { density, agents }), BulkOp::SetHousingDensity(n)) => {
+                if agents.len() > *n {
+                    return Err(Error::TileHasAgents(address, agents.len()));
+                }
+                *density = *n;
+                Ok(())
+            }
+            (HousingTile(tiles::HousingTile { agents, .. }), _)
+            | (WorkplaceTile(tiles::WorkplaceTile { agents, .. }), _)
+                if !agents.is_empty() =>
+            {
+                Err(Error::TileHasAgents(address, agents.len()))
+            }
+            (_, BulkOp::SetWater) => {
+                leaf.tile = tiles::WaterTile {}.into();
+                leaf.creation_time = current_time;
+                Ok(())
+            }
+            (_, BulkOp::SetEmpty) => {
+                leaf.tile = tiles::EmptyTile {}.into();
+                leaf.creation_time = current_time;
+                Ok(())
+            }
+            (EmptyTile(_), BulkOp::SetHousingDensity(n)) => {
+                leaf.tile = tiles::HousingTile {
+                    density: *n,
+                    agents: vec![],
+                }
+                .into();
+                leaf.creation_time = current_time;
+                Ok(())
+            }
+            (EmptyTile(_), BulkOp::SplitToDepth(depth))
+            | (WaterTile(_), BulkOp::SplitToDepth(depth)) => {
+                if *depth > self.qtree.max_depth() {
+                    return Err(quadtree::Error::MaxDepthExceeded(*depth).into());
+                }
+                self.split_to_depth(address, *depth)
+            }
+            (tile, _) => Err(Error::UnsupportedTile(address, tile.name())),
+        }
+    }
+
+    fn split_to_depth(&mut self, address: quadtree::Address, depth: u32) -> Result<(), Error> {
+        if address.depth() >= depth as usize {
+            return Ok(());
+        }
+
+        let leaf = self.qtree.get_leaf(address)?;
+        let (tile, creation_time, poi) = (leaf.tile.clone(), leaf.creation_time, leaf.poi.clone());
+        let mut leaves = quadtree::QuadMap::each(|| LeafState {
+            id: 0,
+            tile: tile.clone(),
+            fields: F::default(),
+            creation_time,
+            poi: None,
+        });
+        // the point of interest stays in one place rather than being copied into each quadrant
+        leaves[quadtree::Quadrant::NW].poi = poi;
+        self.split(address, BranchState::default(), leaves)?;
+
+        for quadrant in quadtree::QUADRANTS {
+            self.split_to_depth(address.child(quadrant), depth)?;
+        }
+        Ok(())
+    }
+}
+
+struct CollectLeavesVisitor {
+    leaves: Vec<quadtree::Address>,
+    /// if set, only collect leaves with this type of tile
+    tile_type: Option<std::mem::Discriminant<tiles::Tile>>,
+}
+
+impl<F: Fields> quadtree::Visitor<BranchState<F>, LeafState<F>, Error> for CollectLeavesVisitor {
+    fn visit_branch_pre(
+        &mut self,
+        _branch: &BranchState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn visit_leaf(&mut self, leaf: &LeafState<F>, data: &quadtree::VisitData) -> Result<(), Error> {
+        match self.tile_type {
+            Some(tile_type) if std::mem::discriminant(&leaf.tile) != tile_type => (),
+            _ => self.leaves.push(data.address),
+        }
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &BranchState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -6,27 +6,710 @@ pub enum Error {
     TomlSerializingError(#[from] toml::ser::Error),
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
+    #[error(
+        "Traffic horizon ({horizon}s) plus deadline ({deadline}s) must be less than the traffic history period ({}s)",
+        TRAFFIC_HISTORY_PERIOD
+    )]
+    InvalidTrafficHorizon { horizon: u64, deadline: u64 },
+    #[error("People per sim must be positive and finite, got {0}")]
+    InvalidPeoplePerSim(f64),
+    #[error(
+        "The number of traffic history snapshots must evenly divide the traffic history period ({}s), got {0}",
+        TRAFFIC_HISTORY_PERIOD
+    )]
+    InvalidTrafficHistorySnapshots(usize),
+    #[error("The {0} construction cost multiplier must be positive and finite, got {1}")]
+    InvalidRailwayCostMultiplier(&'static str, f64),
+    #[error("The {0} land value penalty must be between zero and one, got {1}")]
+    InvalidRailwayLandValuePenalty(&'static str, f64),
+    #[error("The {0} workplace happiness weight must be non-negative and finite, got {1}")]
+    InvalidWorkplaceHappinessWeight(&'static str, f32),
+    #[error("The leisure trip probability must be between zero and one, got {0}")]
+    InvalidLeisureTripProbability(f64),
+    #[error("The {0} intersection delay must be non-negative and finite, got {1}")]
+    InvalidIntersectionDelay(&'static str, f64),
+    #[error("At least one agent archetype is needed")]
+    NoArchetypes,
+    #[error("Agent archetype {0:?} is invalid: {1}")]
+    InvalidArchetype(String, String),
+    #[error("The {0} cadence must be at least a minute, got {1} seconds")]
+    InvalidTriggerCadence(&'static str, u64),
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/** The length (in seconds) of the cycle over which traffic history is tracked, i.e. one day. */
+pub const TRAFFIC_HISTORY_PERIOD: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub struct Config {
     /** Maximum number of times a tile can be split. */
     pub max_depth: u32,
-    /** The number of real people represented by a single simulated person. */
-    pub people_per_sim: u32,
+    /**
+     * The number of real people represented by a single simulated person. This scales the capacity
+     * of roads and highways in the congestion model, and does not need to be a whole number.
+     */
+    pub people_per_sim: f64,
     /** The size (in meters) of the smallest possible tile. */
     pub min_tile_size: u32,
+    /** How likely new workplaces are to be in each industry, before accounting for nearby jobs. */
+    #[serde(default)]
+    pub industry_weights: IndustryWeights,
+    /** Deadlines for background computations, which trade off responsiveness and accuracy. */
+    #[serde(default)]
+    pub scheduling: SchedulingConfig,
+    /** Recording of each agent's trips, e.g. for comparing against real-world travel surveys. */
+    #[serde(default)]
+    pub travel_diary: TravelDiaryConfig,
+    /** How much traffic history is kept for predicting congestion, and how often it is saved. */
+    #[serde(default)]
+    pub traffic_history: TrafficHistoryConfig,
+    /** How much railways cost to build and how they affect the land around them. */
+    #[serde(default)]
+    pub railways: RailwayConfig,
+    /** What agents care about in a job, and how unhappy they need to be to quit. */
+    #[serde(default)]
+    pub workplace_happiness: WorkplaceHappinessConfig,
+    /** Whether and how often agents head out to parks in the evening. */
+    #[serde(default)]
+    pub leisure: LeisureConfig,
+    /** How long drivers spend at intersections on local roads. */
+    #[serde(default)]
+    pub intersection_delay: IntersectionDelayConfig,
+    /** The kinds of agents, each with their own behavior; see AgentArchetype. */
+    #[serde(default)]
+    pub archetypes: AgentArchetypes,
+    /** How often the periodic triggers run. */
+    #[serde(default)]
+    pub trigger_cadence: TriggerCadenceConfig,
+}
+
+/**
+ * Relative weights for each industry. These don't need to add up to one. The defaults are a rough
+ * approximation of the mix of jobs in a typical city.
+ */
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(default)]
+pub struct IndustryWeights {
+    pub office: f64,
+    pub retail: f64,
+    pub industrial: f64,
+    pub education: f64,
+    pub healthcare: f64,
+}
+
+impl Default for IndustryWeights {
+    fn default() -> Self {
+        Self {
+            office: 0.4,
+            retail: 0.2,
+            industrial: 0.1,
+            education: 0.1,
+            healthcare: 0.2,
+        }
+    }
+}
+
+impl IndustryWeights {
+    pub fn get(&self, industry: tiles::Industry) -> f64 {
+        use tiles::Industry::*;
+        match industry {
+            Office => self.office,
+            Retail => self.retail,
+            Industrial => self.industrial,
+            Education => self.education,
+            Healthcare => self.healthcare,
+        }
+    }
+}
+
+/**
+ * How long (in simulation time) background computations are given before the simulation blocks on
+ * them. Longer deadlines mean less blocking on slower hardware, but the results are based on
+ * slightly older information. All values are in seconds.
+ */
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(default)]
+pub struct SchedulingConfig {
+    /** How long we wait before joining a route query worker when an agent starts a trip. */
+    pub route_start_deadline: u64,
+    /**
+     * How far into the future we want the traffic prediction to be when it is applied. This can be
+     * arbitrarily large as long as it is less than the traffic history period minus the deadline.
+     */
+    pub traffic_horizon: u64,
+    /**
+     * How long we allow the traffic computation to run before joining it. If the value is too
+     * small, we risk blocking until the computation finishes.
+     */
+    pub traffic_deadline: u64,
+    /**
+     * Update the traffic on the simulation thread instead of in the background, ignoring the
+     * deadline. This is slower, but makes the traffic updates easier to debug and profile.
+     */
+    pub single_thread_traffic: bool,
+}
+
+impl Default for SchedulingConfig {
+    fn default() -> Self {
+        Self {
+            route_start_deadline: 5,
+            traffic_horizon: 30 * 60,
+            traffic_deadline: 60 * 60,
+            single_thread_traffic: false,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(default)]
+pub struct TravelDiaryConfig {
+    /** Whether trips are recorded at all. This is off by default because it costs memory. */
+    pub enabled: bool,
+    /** The number of days of trips to keep; older days are dropped first. */
+    pub max_days: usize,
+}
+
+impl Default for TravelDiaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_days: 7,
+        }
+    }
+}
+
+/**
+ * The traffic history is saved to a sidecar file next to the main save file, because it is much
+ * larger than the rest of the state and changes slowly. If the sidecar is missing, the simulation
+ * starts over with fresh traffic priors.
+ */
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(default)]
+pub struct TrafficHistoryConfig {
+    /**
+     * The number of snapshots taken over the traffic history period. More snapshots give finer
+     * predictions at the cost of memory. Must evenly divide the period.
+     */
+    pub snapshots: usize,
+    /**
+     * How many snapshots need to be taken before saving rewrites the sidecar file. Saving more often
+     * than this only writes the main save file, which keeps referring to the older history.
+     */
+    pub save_interval: usize,
+    /**
+     * The size (in meters) of the blocks that local road traffic and parking are counted in, in the
+     * current traffic state and in every snapshot. Rounded down to a power of two times the
+     * smallest tile size. Larger blocks use less memory but model congestion more coarsely.
+     */
+    pub grid_block_size: u32,
+}
+
+impl Default for TrafficHistoryConfig {
+    fn default() -> Self {
+        Self {
+            snapshots: 48,
+            save_interval: 12,
+            grid_block_size: 500,
+        }
+    }
+}
+
+/**
+ * The effects of a railway that depend on its alignment. Construction costs are relative to building
+ * at-grade track, so the at-grade multiplier should usually be one.
+ */
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct RailwayAlignmentConfig {
+    pub cost_multiplier: f64,
+    /** The fraction of land value lost by the tiles that the railway passes through. */
+    pub land_value_penalty: f64,
+}
+
+/**
+ * Tunnels are by far the most expensive to build, but don't disturb the land above them. Elevated
+ * railways are cheaper and only somewhat of a nuisance, and at-grade railways cut neighborhoods
+ * apart.
+ */
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(default)]
+pub struct RailwayConfig {
+    pub at_grade: RailwayAlignmentConfig,
+    pub elevated: RailwayAlignmentConfig,
+    pub tunnel: RailwayAlignmentConfig,
+}
+
+impl Default for RailwayConfig {
+    fn default() -> Self {
+        Self {
+            at_grade: RailwayAlignmentConfig {
+                cost_multiplier: 1.0,
+                land_value_penalty: 0.2,
+            },
+            elevated: RailwayAlignmentConfig {
+                cost_multiplier: 2.5,
+                land_value_penalty: 0.1,
+            },
+            tunnel: RailwayAlignmentConfig {
+                cost_multiplier: 7.0,
+                land_value_penalty: 0.0,
+            },
+        }
+    }
+}
+
+impl RailwayConfig {
+    pub fn get(&self, alignment: metro::RailwayAlignment) -> &RailwayAlignmentConfig {
+        use metro::RailwayAlignment::*;
+        match alignment {
+            AtGrade => &self.at_grade,
+            Elevated => &self.elevated,
+            Tunnel => &self.tunnel,
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for alignment in metro::RailwayAlignment::ALL {
+            let RailwayAlignmentConfig {
+                cost_multiplier,
+                land_value_penalty,
+            } = *self.get(alignment);
+            if !(cost_multiplier.is_finite() && cost_multiplier > 0.0) {
+                return Err(Error::InvalidRailwayCostMultiplier(
+                    alignment.name(),
+                    cost_multiplier,
+                ));
+            }
+            if !(0.0..=1.0).contains(&land_value_penalty) {
+                return Err(Error::InvalidRailwayLandValuePenalty(
+                    alignment.name(),
+                    land_value_penalty,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * The weights of each part of an agent's workplace happiness. These don't need to add up to one, and
+ * parts that don't apply to an agent are left out of the weighted average.
+ */
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(default)]
+pub struct WorkplaceHappinessConfig {
+    /// how much an agent's commute falls short of what they are willing to put up with
+    pub commute: f32,
+    /// how well the job pays; doesn't apply until workplaces pay wages
+    pub wage: f32,
+    /// how well the job matches the agent's education
+    pub education_fit: f32,
+    /// agents that have been at a job for a while are more attached to it
+    pub tenure: f32,
+    /** How long (in seconds) it takes for the tenure part to reach its maximum. */
+    pub tenure_horizon: u64,
+    /** Agents quit their jobs when their workplace happiness drops below this. */
+    pub quit_threshold: f32,
+}
+
+impl Default for WorkplaceHappinessConfig {
+    fn default() -> Self {
+        Self {
+            // commutes are still what matters most, as they were before the other parts existed
+            commute: 0.85,
+            wage: 0.5,
+            education_fit: 0.1,
+            tenure: 0.05,
+            tenure_horizon: 365 * 24 * 60 * 60,
+            quit_threshold: 0.15,
+        }
+    }
+}
+
+impl WorkplaceHappinessConfig {
+    fn validate(&self) -> Result<(), Error> {
+        for (name, weight) in [
+            ("commute", self.commute),
+            ("wage", self.wage),
+            ("education fit", self.education_fit),
+            ("tenure", self.tenure),
+        ] {
+            if !(weight.is_finite() && weight >= 0.0) {
+                return Err(Error::InvalidWorkplaceHappinessWeight(name, weight));
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Evening trips to parks, which add traffic outside of the commute peaks. These are off by default so
+ * that existing maps keep behaving the same.
+ */
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(default)]
+pub struct LeisureConfig {
+    pub enabled: bool,
+    /** The chance that an agent heads out on any given day. */
+    pub probability: f64,
+    /** The hour of the day at which agents leave home. */
+    pub start_hour: u64,
+    /** How many hours agents stay before heading home. */
+    pub duration_hours: u64,
+}
+
+impl Default for LeisureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            probability: 0.1,
+            start_hour: 18,
+            duration_hours: 1,
+        }
+    }
+}
+
+impl LeisureConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&self.probability) {
+            return Err(Error::InvalidLeisureTripProbability(self.probability));
+        }
+        Ok(())
+    }
+}
+
+/**
+ * A coarse stand-in for traffic signals and stop signs on local roads: drivers are delayed each time
+ * they cross from one block of the local traffic grid into the next, more so in blocks where many
+ * people live and work. Both delays are zero by default so that existing maps keep behaving the
+ * same.
+ */
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(default)]
+pub struct IntersectionDelayConfig {
+    /** The delay for entering a block where nobody lives or works, in seconds. */
+    pub base_delay: f64,
+    /**
+     * The additional delay for entering a block, in seconds per thousand residents and workers per
+     * square kilometer.
+     */
+    pub density_delay: f64,
+}
+
+impl Default for IntersectionDelayConfig {
+    fn default() -> Self {
+        Self {
+            base_delay: 0.0,
+            density_delay: 0.0,
+        }
+    }
+}
+
+impl IntersectionDelayConfig {
+    /**
+     * The delay for entering a block with the given number of residents and workers per square
+     * kilometer, in seconds.
+     */
+    pub fn crossing_delay(&self, density: f64) -> f64 {
+        self.base_delay + self.density_delay * density / 1000.0
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for (name, delay) in [("base", self.base_delay), ("density", self.density_delay)] {
+            if !(delay.is_finite() && delay >= 0.0) {
+                return Err(Error::InvalidIntersectionDelay(name, delay));
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * A named bundle of behavior shared by many agents, so that a whole population can be tuned at
+ * once, e.g. to make everyone more willing to put up with long commutes. Agents only refer to their
+ * archetype, and read its values whenever they need them, so editing an archetype changes the
+ * behavior of existing agents immediately. Each agent also has noise factors of their own; see
+ * agent::AgentData.
+ */
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(default)]
+pub struct AgentArchetype {
+    pub name: String,
+    /** How many of the agents created by map generation get this archetype, relative to the others. */
+    pub share: f64,
+    /** How much agents like to stay in the same housing, in [0, 1]. */
+    pub housing_stickiness: f32,
+    /** How much agents like to stay at the same job, in [0, 1]. */
+    pub workplace_stickiness: f32,
+    /** How long agents are willing to commute each way, in seconds. */
+    pub commute_length_tolerance: u32,
+    /**
+     * Extra time (in seconds) that agents count for each transfer, e.g. boarding a metro. Higher
+     * values make driving more attractive compared to transit.
+     */
+    pub transfer_penalty: f64,
+    /** Multiplier for the standard walking speed. */
+    pub walking_speed: f64,
+    /** The chance that map generation gives agents of this archetype a car of their own. */
+    pub car_ownership_rate: f64,
+}
+
+impl Default for AgentArchetype {
+    fn default() -> Self {
+        // these match the constants that every agent used before archetypes existed
+        Self {
+            name: "default".to_string(),
+            share: 1.0,
+            housing_stickiness: 0.7,
+            workplace_stickiness: 0.5,
+            commute_length_tolerance: 60 * 60,
+            transfer_penalty: 0.0,
+            walking_speed: 1.0,
+            car_ownership_rate: 1.0,
+        }
+    }
+}
+
+impl AgentArchetype {
+    fn validate(&self) -> Result<(), Error> {
+        let invalid =
+            |message: &str| Err(Error::InvalidArchetype(self.name.clone(), message.into()));
+        if !(self.share.is_finite() && self.share >= 0.0) {
+            return invalid("share must be non-negative and finite");
+        }
+        for stickiness in [self.housing_stickiness, self.workplace_stickiness] {
+            if !(0.0..=1.0).contains(&stickiness) {
+                return invalid("stickiness must be between zero and one");
+            }
+        }
+        if self.commute_length_tolerance == 0 {
+            return invalid("commute length tolerance must be positive");
+        }
+        if !(self.transfer_penalty.is_finite() && self.transfer_penalty >= 0.0) {
+            return invalid("transfer penalty must be non-negative and finite");
+        }
+        if !(self.walking_speed.is_finite() && self.walking_speed > 0.0) {
+            return invalid("walking speed must be positive and finite");
+        }
+        if !(0.0..=1.0).contains(&self.car_ownership_rate) {
+            return invalid("car ownership rate must be between zero and one");
+        }
+        Ok(())
+    }
+}
+
+/**
+ * The agent archetypes, which agents refer to by index. In TOML, each one is an [[archetypes]]
+ * table. Agents from before archetypes existed, and agents whose archetype has since been removed,
+ * behave like the first one.
+ */
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(transparent)]
+pub struct AgentArchetypes(Vec<AgentArchetype>);
+
+impl Default for AgentArchetypes {
+    fn default() -> Self {
+        Self(vec![AgentArchetype::default()])
+    }
+}
+
+impl AgentArchetypes {
+    pub fn new(archetypes: Vec<AgentArchetype>) -> Self {
+        Self(archetypes)
+    }
+
+    pub fn get(&self, id: u32) -> &AgentArchetype {
+        self.0
+            .get(id as usize)
+            .or_else(|| self.0.first())
+            .expect("no agent archetypes")
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut AgentArchetype> {
+        self.0.get_mut(id as usize)
+    }
+
+    /// The index of the archetype with the given name, if there is one.
+    pub fn find(&self, name: &str) -> Option<u32> {
+        self.0
+            .iter()
+            .position(|archetype| archetype.name == name)
+            .map(|id| id as u32)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AgentArchetype> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /**
+     * Pick an archetype at random, in proportion to the shares. With only one archetype, the RNG
+     * isn't used at all, so that maps generated before archetypes existed come out the same.
+     */
+    pub fn pick<R: rand::Rng>(&self, rng: &mut R) -> u32 {
+        if self.0.len() <= 1 {
+            return 0;
+        }
+        let total: f64 = self.0.iter().map(|archetype| archetype.share).sum();
+        let mut target = rng.gen_range(0.0..total);
+        for (id, archetype) in self.0.iter().enumerate() {
+            if target < archetype.share {
+                return id as u32;
+            }
+            target -= archetype.share;
+        }
+        // rounding errors can leave a sliver at the end
+        (self.0.len() - 1) as u32
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.0.is_empty() {
+            return Err(Error::NoArchetypes);
+        }
+        for (i, archetype) in self.0.iter().enumerate() {
+            archetype.validate()?;
+            if self.0[..i].iter().any(|other| other.name == archetype.name) {
+                return Err(Error::InvalidArchetype(
+                    archetype.name.clone(),
+                    "names must be unique".to_string(),
+                ));
+            }
+        }
+        if self.0.len() > 1 && self.0.iter().all(|archetype| archetype.share == 0.0) {
+            return Err(Error::InvalidArchetype(
+                self.0[0].name.clone(),
+                "at least one archetype needs a positive share".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/**
+ * The shortest allowed cadence, in seconds. Anything shorter would flood the trigger queue, since
+ * each of these triggers does work proportional to the size of the map or the population.
+ */
+pub const MIN_TRIGGER_CADENCE: u64 = 60;
+
+/**
+ * How often (in seconds of simulated time) each of the triggers that re-schedule themselves runs.
+ * The fields are named after the matching TriggerKind. A new cadence takes effect the next time
+ * the trigger re-schedules itself.
+ */
+#[derive(
+    Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(default)]
+pub struct TriggerCadenceConfig {
+    /** How often fields like land value and employment are recomputed. */
+    pub update_fields: u64,
+    /** How often the lists of housing and workplace tiles are refreshed. */
+    pub update_collect_tiles: u64,
+    /** How often each agent considers quitting their job or finding a new one. */
+    pub agent_life_decisions: u64,
+    /** How often new workplaces are created to keep up with demand. */
+    pub workplace_decisions: u64,
+    /** How often removed network segments are forgotten. */
+    pub advance_network_tombstones: u64,
+}
+
+impl Default for TriggerCadenceConfig {
+    fn default() -> Self {
+        const HOUR: u64 = 60 * 60;
+        const DAY: u64 = 24 * HOUR;
+        Self {
+            update_fields: DAY,
+            update_collect_tiles: HOUR,
+            agent_life_decisions: 2 * DAY,
+            workplace_decisions: 2 * DAY,
+            advance_network_tombstones: DAY,
+        }
+    }
+}
+
+impl TriggerCadenceConfig {
+    /// Each cadence along with the name of its field, e.g. for listing them all.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, u64)> {
+        [
+            ("update_fields", self.update_fields),
+            ("update_collect_tiles", self.update_collect_tiles),
+            ("agent_life_decisions", self.agent_life_decisions),
+            ("workplace_decisions", self.workplace_decisions),
+            (
+                "advance_network_tombstones",
+                self.advance_network_tombstones,
+            ),
+        ]
+        .into_iter()
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for (name, cadence) in self.iter() {
+            if cadence < MIN_TRIGGER_CADENCE {
+                return Err(Error::InvalidTriggerCadence(name, cadence));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Config {
     pub fn load(data: &str) -> Result<Self, Error> {
-        Ok(toml::from_str(data)?)
+        let config: Self = toml::from_str(data)?;
+        config.validate()?;
+        Ok(config)
     }
 
     pub fn load_file(path: &std::path::Path) -> Result<Self, Error> {
         Self::load(&std::fs::read_to_string(path)?)
     }
 
+    /**
+     * Check invariants that can't be expressed in the types. In particular, the traffic horizon
+     * plus deadline must fit within the traffic history period, so that if the engine is serialized
+     * while traffic is being computed, it can still be recomputed with the correct horizon.
+     */
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(self.people_per_sim.is_finite() && self.people_per_sim > 0.0) {
+            return Err(Error::InvalidPeoplePerSim(self.people_per_sim));
+        }
+
+        let SchedulingConfig {
+            traffic_horizon,
+            traffic_deadline,
+            ..
+        } = self.scheduling;
+        if traffic_horizon.saturating_add(traffic_deadline) >= TRAFFIC_HISTORY_PERIOD {
+            return Err(Error::InvalidTrafficHorizon {
+                horizon: traffic_horizon,
+                deadline: traffic_deadline,
+            });
+        }
+
+        let snapshots = self.traffic_history.snapshots;
+        if snapshots == 0 || TRAFFIC_HISTORY_PERIOD % snapshots as u64 != 0 {
+            return Err(Error::InvalidTrafficHistorySnapshots(snapshots));
+        }
+
+        self.railways.validate()?;
+        self.workplace_happiness.validate()?;
+        self.leisure.validate()?;
+        self.intersection_delay.validate()?;
+        self.archetypes.validate()?;
+        self.trigger_cadence.validate()
+    }
+
     pub fn dump(&self) -> Result<String, Error> {
         Ok(toml::to_string(self)?)
     }
@@ -50,3 +733,211 @@ impl Config {
         2_u32.pow((block_size / self.min_tile_size as f32).log2().floor() as u32)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::config::*;
+
+    fn config(scheduling: SchedulingConfig) -> Config {
+        Config {
+            max_depth: 4,
+            people_per_sim: 1.0,
+            min_tile_size: 100,
+            industry_weights: Default::default(),
+            scheduling,
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        }
+    }
+
+    #[test]
+    fn default_scheduling_is_valid() {
+        assert!(config(SchedulingConfig::default()).validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_traffic_horizon() {
+        let invalid = config(SchedulingConfig {
+            traffic_horizon: TRAFFIC_HISTORY_PERIOD - 60,
+            traffic_deadline: 60,
+            ..Default::default()
+        });
+        assert!(matches!(
+            invalid.validate(),
+            Err(Error::InvalidTrafficHorizon {
+                horizon,
+                deadline: 60,
+            }) if horizon == TRAFFIC_HISTORY_PERIOD - 60
+        ));
+
+        // also rejected when loading
+        let data = invalid.dump().unwrap();
+        assert!(matches!(
+            Config::load(&data),
+            Err(Error::InvalidTrafficHorizon { .. })
+        ));
+    }
+
+    #[test]
+    fn fractional_people_per_sim() {
+        let loaded =
+            Config::load("max_depth = 4\npeople_per_sim = 2.5\nmin_tile_size = 100\n").unwrap();
+        assert_eq!(loaded.people_per_sim, 2.5);
+
+        for people_per_sim in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let invalid = Config {
+                people_per_sim,
+                ..config(SchedulingConfig::default())
+            };
+            assert!(matches!(
+                invalid.validate(),
+                Err(Error::InvalidPeoplePerSim(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_traffic_history_snapshots() {
+        for snapshots in [0, 7] {
+            let mut invalid = config(SchedulingConfig::default());
+            invalid.traffic_history.snapshots = snapshots;
+            assert!(matches!(
+                invalid.validate(),
+                Err(Error::InvalidTrafficHistorySnapshots(n)) if n == snapshots
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_railway_config() {
+        let mut invalid = config(SchedulingConfig::default());
+        invalid.railways.tunnel.cost_multiplier = 0.0;
+        assert!(matches!(
+            invalid.validate(),
+            Err(Error::InvalidRailwayCostMultiplier("tunnel", _))
+        ));
+
+        let mut invalid = config(SchedulingConfig::default());
+        invalid.railways.elevated.land_value_penalty = 1.5;
+        assert!(matches!(
+            invalid.validate(),
+            Err(Error::InvalidRailwayLandValuePenalty("elevated", _))
+        ));
+    }
+
+    #[test]
+    fn invalid_intersection_delay() {
+        let mut invalid = config(SchedulingConfig::default());
+        invalid.intersection_delay.density_delay = -1.0;
+        assert!(matches!(
+            invalid.validate(),
+            Err(Error::InvalidIntersectionDelay("density", _))
+        ));
+    }
+
+    #[test]
+    fn archetypes() {
+        let config = Config::load(
+            "max_depth = 4\npeople_per_sim = 1\nmin_tile_size = 100\n\
+             [[archetypes]]\nname = \"commuter\"\nshare = 3\ncommute_length_tolerance = 5400\n\
+             [[archetypes]]\nname = \"homebody\"\nhousing_stickiness = 0.9\n",
+        )
+        .unwrap();
+        assert_eq!(config.archetypes.len(), 2);
+        assert_eq!(config.archetypes.find("homebody"), Some(1));
+        assert_eq!(config.archetypes.get(0).commute_length_tolerance, 5400);
+        assert_eq!(config.archetypes.get(1).housing_stickiness, 0.9);
+        // anything left out is the same as the default archetype
+        assert_eq!(
+            config.archetypes.get(1).walking_speed,
+            AgentArchetype::default().walking_speed
+        );
+        // unknown archetypes behave like the first one
+        assert_eq!(config.archetypes.get(7).name, "commuter");
+
+        // configs from before archetypes existed get the default one
+        let config =
+            Config::load("max_depth = 4\npeople_per_sim = 1\nmin_tile_size = 100\n").unwrap();
+        assert_eq!(config.archetypes, AgentArchetypes::default());
+    }
+
+    #[test]
+    fn invalid_archetypes() {
+        let mut invalid = config(SchedulingConfig::default());
+        invalid.archetypes = AgentArchetypes::new(vec![]);
+        assert!(matches!(invalid.validate(), Err(Error::NoArchetypes)));
+
+        let mut invalid = config(SchedulingConfig::default());
+        invalid.archetypes = AgentArchetypes::new(vec![AgentArchetype::default(); 2]);
+        assert!(matches!(
+            invalid.validate(),
+            Err(Error::InvalidArchetype(..))
+        ));
+
+        let mut invalid = config(SchedulingConfig::default());
+        invalid.archetypes.get_mut(0).unwrap().housing_stickiness = 1.5;
+        assert!(matches!(
+            invalid.validate(),
+            Err(Error::InvalidArchetype(..))
+        ));
+    }
+
+    #[test]
+    fn trigger_cadence() {
+        let config = Config::load(
+            "max_depth = 4\npeople_per_sim = 1\nmin_tile_size = 100\n\
+             [trigger_cadence]\nupdate_fields = 172800\n",
+        )
+        .unwrap();
+        assert_eq!(config.trigger_cadence.update_fields, 172800);
+        assert_eq!(
+            config.trigger_cadence.update_collect_tiles,
+            TriggerCadenceConfig::default().update_collect_tiles
+        );
+
+        let err = Config::load(
+            "max_depth = 4\npeople_per_sim = 1\nmin_tile_size = 100\n\
+             [trigger_cadence]\nworkplace_decisions = 0\n",
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InvalidTriggerCadence("workplace_decisions", 0)
+        ));
+    }
+
+    #[test]
+    fn railways_default_when_partial() {
+        let config = Config::load(
+            "max_depth = 4\npeople_per_sim = 1\nmin_tile_size = 100\n\
+             [railways.tunnel]\ncost_multiplier = 10\nland_value_penalty = 0\n",
+        )
+        .unwrap();
+        assert_eq!(config.railways.tunnel.cost_multiplier, 10.0);
+        assert_eq!(config.railways.elevated, RailwayConfig::default().elevated);
+    }
+
+    #[test]
+    fn scheduling_defaults_when_missing() {
+        let config =
+            Config::load("max_depth = 4\npeople_per_sim = 1\nmin_tile_size = 100\n").unwrap();
+        assert_eq!(config.scheduling, SchedulingConfig::default());
+
+        let config = Config::load(
+            "max_depth = 4\npeople_per_sim = 1\nmin_tile_size = 100\n\
+             [scheduling]\ntraffic_deadline = 120\n",
+        )
+        .unwrap();
+        assert_eq!(config.scheduling.traffic_deadline, 120);
+        assert_eq!(
+            config.scheduling.traffic_horizon,
+            SchedulingConfig::default().traffic_horizon
+        );
+    }
+}
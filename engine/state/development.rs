@@ -0,0 +1,54 @@
+/**
+ * The number of residents and workers per square kilometer in each block of the local traffic
+ * grid, which makes intersections slower in built-up areas; see IntersectionDelayConfig. This is
+ * derived from the fields, so it isn't serialized. The engine refreshes it after updating them; until
+ * then, every block is treated as empty.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct DevelopmentGrid {
+    /// the number of blocks along each side of the map
+    width: u32,
+    /// the width of each block, in units of the smallest tile size
+    downsample: u32,
+    density: Vec<f64>,
+}
+
+impl DevelopmentGrid {
+    /// A grid with the given dimensions where every block is empty.
+    pub fn new(width: u32, downsample: u32) -> Self {
+        Self {
+            width,
+            downsample,
+            density: vec![0.0; (width as usize).pow(2)],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn downsample(&self) -> u32 {
+        self.downsample
+    }
+
+    fn index(&self, x: i64, y: i64) -> Option<usize> {
+        let width = self.width as i64;
+        (x >= 0 && x < width && y >= 0 && y < width).then(|| (y * width + x) as usize)
+    }
+
+    /**
+     * The density of the block at the given block coordinates, i.e. model coordinates divided by
+     * the downsample. Blocks that are off the map are empty.
+     */
+    pub fn get(&self, x: i64, y: i64) -> f64 {
+        self.index(x, y).map_or(0.0, |index| self.density[index])
+    }
+
+    /// Panics if the block is off the map.
+    pub fn get_mut(&mut self, x: i64, y: i64) -> &mut f64 {
+        let index = self
+            .index(x, y)
+            .unwrap_or_else(|| panic!("block ({}, {}) is off the map", x, y));
+        &mut self.density[index]
+    }
+}
@@ -1,5 +1,21 @@
+mod bounds;
+mod bulk;
 mod config;
+mod development;
+mod metadata;
+mod region;
 mod state;
+mod tile_ids;
 
-pub use crate::config::{Config, Error as ConfigError};
+pub use crate::bounds::{BoundsPolicy, BoundsRepair, BoundsRepairReport, BOUNDS_EPSILON};
+pub use crate::bulk::{leaves_in_rect, BulkOp, BulkReport};
+pub use crate::config::{
+    AgentArchetype, AgentArchetypes, Config, Error as ConfigError, IndustryWeights,
+    IntersectionDelayConfig, LeisureConfig, RailwayAlignmentConfig, RailwayConfig,
+    SchedulingConfig, TrafficHistoryConfig, TravelDiaryConfig, TriggerCadenceConfig,
+    WorkplaceHappinessConfig, MIN_TRIGGER_CADENCE, TRAFFIC_HISTORY_PERIOD,
+};
+pub use crate::development::DevelopmentGrid;
+pub use crate::metadata::{Georeference, MapMetadata};
+pub use crate::region::{PastedRegion, Region, RegionPolicy};
 pub use crate::state::{BranchState, Error, Fields, LeafState, SerdeFormat, State};
@@ -0,0 +1,104 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/**
+ * Describes a map, so that saves can be told apart by more than their filenames. Everything is
+ * optional; older saves have none of it.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct MapMetadata {
+    pub name: String,
+    pub author: String,
+    pub description: String,
+    /// seconds since the Unix epoch when the map was first saved, if it has been
+    pub created: Option<u64>,
+    /// seconds since the Unix epoch when the map was last saved, if it has been
+    pub modified: Option<u64>,
+    /// where the map is in the real world, for maps made by the importer
+    pub georeference: Option<Georeference>,
+}
+
+impl MapMetadata {
+    /// Record that the map was saved at the given time, in seconds since the Unix epoch.
+    pub fn touch(&mut self, now: u64) {
+        self.created.get_or_insert(now);
+        self.modified = Some(now);
+    }
+
+    /// Record that the map was saved just now.
+    pub fn touch_now(&mut self) {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        self.touch(now);
+    }
+}
+
+/**
+ * The parameters of the importer's projection from latitude and longitude to model coordinates;
+ * see georeference::Projection.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Georeference {
+    /// the latitude at the center of the map, in degrees
+    pub origin_lat: f64,
+    /// the longitude at the center of the map, in degrees
+    pub origin_lon: f64,
+    /// the size of the smallest possible tile, in meters
+    pub meters_per_tile: f64,
+}
+
+impl Georeference {
+    pub fn projection(&self, map_width: u64) -> georeference::Projection {
+        georeference::Projection {
+            origin_lat: self.origin_lat,
+            origin_lon: self.origin_lon,
+            meters_per_tile: self.meters_per_tile,
+            map_width: map_width as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metadata::*;
+
+    fn round_trip(metadata: &MapMetadata) -> MapMetadata {
+        serde_json::from_str(&serde_json::to_string(metadata).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let mut metadata = MapMetadata {
+            name: String::from("San Francisco"),
+            author: String::from("someone"),
+            description: String::from("The Bay Area,\nroughly"),
+            ..Default::default()
+        };
+        metadata.touch(1000);
+        metadata.touch(2000);
+        assert_eq!(
+            (metadata.created, metadata.modified),
+            (Some(1000), Some(2000))
+        );
+        assert_eq!(round_trip(&metadata), metadata);
+
+        metadata.georeference = Some(Georeference {
+            origin_lat: 37.7749,
+            origin_lon: -122.4194,
+            meters_per_tile: 100.0,
+        });
+        assert_eq!(round_trip(&metadata), metadata);
+    }
+
+    #[test]
+    fn missing_fields() {
+        let metadata: MapMetadata = serde_json::from_str("{}").unwrap();
+        assert_eq!(metadata, MapMetadata::default());
+
+        let metadata: MapMetadata = serde_json::from_str(r#"{"name": "Test"}"#).unwrap();
+        assert_eq!(metadata.name, "Test");
+        assert_eq!(metadata.georeference, None);
+    }
+}
@@ -0,0 +1,536 @@
+use std::collections::BTreeMap;
+
+use crate::bounds::BOUNDS_EPSILON;
+use crate::state::{BranchState, Error, Fields, LeafState, State};
+
+/// What State::extract_region does with network segments that cross the edge of the region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RegionPolicy {
+    /// Fail with Error::SegmentCrossesRegion.
+    #[default]
+    Reject,
+    /// Keep the parts of the segments that are inside the region, ending at new junctions on its
+    /// edge.
+    Clip,
+}
+
+/**
+ * A copy of part of the map, for pasting somewhere else with State::graft_region. Besides the
+ * tiles, this has the highways and railways inside the region, and the metro lines that run
+ * entirely inside it. Everything is in coordinates relative to the corner of the region, and
+ * addresses are relative to the root of the region (see quadtree::Address::relative_to).
+ *
+ * Agents are not part of the state, so the housing and workplace tiles in the copy are vacant.
+ */
+#[derive(Debug, Clone)]
+pub struct Region<F: Fields> {
+    pub qtree: quadtree::Quadtree<BranchState<F>, LeafState<F>>,
+    pub highways: highway::Highways,
+    pub railways: metro::Railways,
+    pub metros: metro::Metros,
+}
+
+impl<F: Fields> Region<F> {
+    /// The width of the region, in the smallest tiles.
+    pub fn width(&self) -> u64 {
+        self.qtree.width()
+    }
+}
+
+/// The handles on the map of everything that State::graft_region pasted, by their handles in the
+/// region.
+#[derive(Debug, Clone, Default)]
+pub struct PastedRegion {
+    pub highway_junctions: BTreeMap<network::JunctionHandle, network::JunctionHandle>,
+    pub highway_segments: BTreeMap<network::SegmentHandle, network::SegmentHandle>,
+    pub railway_junctions: BTreeMap<network::JunctionHandle, network::JunctionHandle>,
+    pub railway_segments: BTreeMap<network::SegmentHandle, network::SegmentHandle>,
+    pub metro_lines: BTreeMap<metro::MetroLineHandle, metro::MetroLineHandle>,
+}
+
+/// new segment handles by old ones
+type SegmentMap = BTreeMap<network::SegmentHandle, network::SegmentHandle>;
+
+/// The part of a polyline that is inside a rect.
+struct ClippedRun {
+    keys: Vec<network::Key>,
+    /// whether the run starts at the first key of the polyline, rather than at the edge of the rect
+    from_start: bool,
+    /// whether the run ends at the last key of the polyline
+    to_end: bool,
+}
+
+/// Whether the point is inside the half-open rect [min, max).
+fn contains(min: network::Key, max: network::Key, point: network::Key) -> bool {
+    point.x >= min.x && point.x < max.x && point.y >= min.y && point.y < max.y
+}
+
+/**
+ * The parameters along the line from a to b at which it enters and leaves the closed rect
+ * [min, max], if it passes through it at all (Liang-Barsky).
+ */
+fn clip_line(
+    min: network::Key,
+    max: network::Key,
+    a: network::Key,
+    b: network::Key,
+) -> Option<(f64, f64)> {
+    let (mut t0, mut t1) = (0.0_f64, 1.0_f64);
+    let d = b - a;
+    for (p, q) in [
+        (-d.x, a.x - min.x),
+        (d.x, max.x - a.x),
+        (-d.y, a.y - min.y),
+        (d.y, max.y - a.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+    (t0 <= t1).then_some((t0, t1))
+}
+
+/// Split the polyline into the runs that are inside the rect [min, max].
+fn clip_polyline(min: network::Key, max: network::Key, keys: &[network::Key]) -> Vec<ClippedRun> {
+    let mut runs: Vec<ClippedRun> = Vec::new();
+    let mut open = false;
+    for (index, pair) in keys.windows(2).enumerate() {
+        let (a, b) = (pair[0], pair[1]);
+        let (t0, t1) = match clip_line(min, max, a, b) {
+            Some(interval) => interval,
+            None => {
+                open = false;
+                continue;
+            }
+        };
+        let (start, end) = (a + (b - a) * t0, a + (b - a) * t1);
+        if open && t0 == 0.0 {
+            runs.last_mut().unwrap().keys.push(end);
+        } else {
+            runs.push(ClippedRun {
+                keys: vec![start, end],
+                from_start: index == 0 && t0 == 0.0,
+                to_end: false,
+            });
+        }
+        open = t1 == 1.0;
+        if open && index == keys.len() - 2 {
+            runs.last_mut().unwrap().to_end = true;
+        }
+    }
+
+    // drop runs that only touch the rect
+    runs.retain(|run| {
+        run.keys
+            .windows(2)
+            .any(|pair| (pair[1] - pair[0]).x.abs() + (pair[1] - pair[0]).y.abs() > BOUNDS_EPSILON)
+    });
+    runs
+}
+
+/// Move a point on the far edges of the region just inside of it.
+fn clamp_inside(key: network::Key, width: f64) -> network::Key {
+    network::Key::new(
+        key.x.clamp(0.0, width - BOUNDS_EPSILON),
+        key.y.clamp(0.0, width - BOUNDS_EPSILON),
+    )
+}
+
+/**
+ * Copy the active junctions and segments of the network that are inside the rect starting at
+ * `origin` with the given width, translated so that the origin is at zero. Junctions on the edge
+ * of the region, for clipped segments, are created with `edge_junction`, and the data of copied
+ * junctions is translated with `junction_data`. Returns the copy and the segments that were copied
+ * whole, by their handles in the original.
+ */
+fn extract_network<J: Clone, S: Clone>(
+    network: &network::Network<J, S>,
+    origin: network::Key,
+    width: f64,
+    policy: RegionPolicy,
+    edge_junction: impl Fn() -> J,
+    junction_data: impl Fn(&J) -> J,
+) -> Result<(network::Network<J, S>, SegmentMap), Error> {
+    let max = origin + network::Key::new(width, width);
+    let mut copy = network::Network::new();
+
+    let mut junctions = BTreeMap::new();
+    for junction in network.junctions().values() {
+        if junction.change_state.is_active() && contains(origin, max, junction.location) {
+            let id = copy.add_junction(junction.location - origin, junction_data(&junction.data));
+            junctions.insert(junction.id, id);
+        }
+    }
+
+    let mut whole_segments = BTreeMap::new();
+    for segment in network.segments().values() {
+        if !segment.change_state.is_active() {
+            continue;
+        }
+        let keys = segment.keys();
+        let start = junctions.get(&segment.start_junction());
+        let end = junctions.get(&segment.end_junction());
+        if let (Some(start), Some(end)) = (start, end) {
+            if keys.iter().all(|key| contains(origin, max, *key)) {
+                let keys = keys.iter().map(|key| *key - origin).collect();
+                let id = copy.add_segment(segment.data.clone(), *start, *end, Some(keys));
+                whole_segments.insert(segment.id, id);
+                continue;
+            }
+        }
+
+        let runs = clip_polyline(origin, max, keys);
+        if runs.is_empty() {
+            continue;
+        }
+        if policy == RegionPolicy::Reject {
+            return Err(Error::SegmentCrossesRegion(segment.id));
+        }
+        for run in runs {
+            let keys: Vec<network::Key> = run
+                .keys
+                .iter()
+                .map(|key| clamp_inside(*key - origin, width))
+                .collect();
+            let mut run_junction = |original: Option<&network::JunctionHandle>, key| match original
+            {
+                Some(junction) => *junction,
+                None => copy.add_junction(key, edge_junction()),
+            };
+            let run_start = run_junction(start.filter(|_| run.from_start), keys[0]);
+            let run_end = run_junction(end.filter(|_| run.to_end), keys[keys.len() - 1]);
+            copy.add_segment(segment.data.clone(), run_start, run_end, Some(keys));
+        }
+    }
+
+    Ok((copy, whole_segments))
+}
+
+/**
+ * Add everything in the network to another network, translated so that zero is at `origin`.
+ * Returns the new handles of the junctions and segments, by their handles in the network.
+ */
+fn graft_network<J: Clone, S: Clone>(
+    network: &network::Network<J, S>,
+    target: &mut network::Network<J, S>,
+    origin: network::Key,
+    junction_data: impl Fn(&J) -> J,
+) -> (
+    BTreeMap<network::JunctionHandle, network::JunctionHandle>,
+    SegmentMap,
+) {
+    let junctions: BTreeMap<_, _> = network
+        .junctions()
+        .values()
+        .map(|junction| {
+            let id = target.add_junction(junction.location + origin, junction_data(&junction.data));
+            (junction.id, id)
+        })
+        .collect();
+    let segments = network
+        .segments()
+        .values()
+        .map(|segment| {
+            let keys = segment.keys().iter().map(|key| *key + origin).collect();
+            let id = target.add_segment(
+                segment.data.clone(),
+                junctions[&segment.start_junction()],
+                junctions[&segment.end_junction()],
+                Some(keys),
+            );
+            (segment.id, id)
+        })
+        .collect();
+    (junctions, segments)
+}
+
+/// The station with its address moved from one root to another, if it is inside the first one.
+fn move_station(
+    station: &Option<metro::Station>,
+    from: &quadtree::Address,
+    to: impl Fn(quadtree::Address) -> quadtree::Address,
+) -> Option<metro::Station> {
+    let station = station.as_ref()?;
+    Some(metro::Station {
+        name: station.name.clone(),
+        address: to(station.address.relative_to(from)?),
+    })
+}
+
+/**
+ * Moves the exact locations of stations by an offset, and maps the metro lines that they belong
+ * to. Lines that aren't in the map are dropped.
+ */
+struct MoveStationsVisitor<'a> {
+    offset: (i64, i64),
+    metro_lines: &'a BTreeMap<u64, u64>,
+}
+
+impl<'a, F: Fields> quadtree::MutVisitor<BranchState<F>, LeafState<F>, Error>
+    for MoveStationsVisitor<'a>
+{
+    fn visit_branch_pre(
+        &mut self,
+        _branch: &mut BranchState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn visit_leaf(
+        &mut self,
+        leaf: &mut LeafState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        match &mut leaf.tile {
+            tiles::Tile::MetroStationTile(tile) => {
+                tile.x = (tile.x as i64 + self.offset.0) as u64;
+                tile.y = (tile.y as i64 + self.offset.1) as u64;
+                tile.ids = tile
+                    .ids
+                    .iter()
+                    .filter_map(|id| self.metro_lines.get(id).copied())
+                    .collect();
+            }
+            tiles::Tile::HousingTile(tiles::HousingTile { agents, .. })
+            | tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { agents, .. }) => agents.clear(),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &mut BranchState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Finds tiles that can't be pasted over: ones with agents, and stations.
+struct CheckTargetVisitor;
+
+impl<F: Fields> quadtree::Visitor<BranchState<F>, LeafState<F>, Error> for CheckTargetVisitor {
+    fn visit_branch_pre(
+        &mut self,
+        _branch: &BranchState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn visit_leaf(&mut self, leaf: &LeafState<F>, data: &quadtree::VisitData) -> Result<(), Error> {
+        use tiles::TileType;
+
+        match &leaf.tile {
+            tiles::Tile::HousingTile(tiles::HousingTile { agents, .. })
+            | tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { agents, .. })
+                if !agents.is_empty() =>
+            {
+                Err(Error::TileHasAgents(data.address, agents.len()))
+            }
+            tile @ tiles::Tile::MetroStationTile(_) => {
+                Err(Error::UnsupportedTile(data.address, tile.name()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &BranchState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The metro lines from `metros` that only use the given railway segments, added to `target`.
+fn copy_metro_lines(
+    metros: &metro::Metros,
+    railway_segments: &SegmentMap,
+    target: &mut metro::Metros,
+    target_railways: &metro::Railways,
+) -> BTreeMap<metro::MetroLineHandle, metro::MetroLineHandle> {
+    metros
+        .metro_lines()
+        .values()
+        .filter_map(|metro_line| {
+            let segments = metro_line
+                .segments()
+                .iter()
+                .map(|segment| railway_segments.get(&segment.segment).copied())
+                .collect::<Option<Vec<_>>>()?;
+            let id = target.add_metro_line(metro_line.data.clone(), segments, target_railways);
+            Some((metro_line.id, id))
+        })
+        .collect()
+}
+
+fn metro_line_ids(
+    metro_lines: &BTreeMap<metro::MetroLineHandle, metro::MetroLineHandle>,
+) -> BTreeMap<u64, u64> {
+    metro_lines
+        .iter()
+        .map(|(from, to)| (from.inner(), to.inner()))
+        .collect()
+}
+
+impl<F: Fields> State<F> {
+    /**
+     * Copy the subtree at the given address, along with the highways, railways, and metro lines
+     * inside it; see Region. Segments that cross the edge of the region are handled according to
+     * the policy.
+     */
+    pub fn extract_region(
+        &self,
+        address: quadtree::Address,
+        policy: RegionPolicy,
+    ) -> Result<Region<F>, Error> {
+        let qtree = self.qtree.extract(address)?;
+        let bounds = address.bounds();
+        let origin = network::Key::new(bounds.min_x as f64, bounds.min_y as f64);
+        let width = address.width() as f64;
+
+        let (highways, _) = extract_network(
+            &self.highways,
+            origin,
+            width,
+            policy,
+            || highway::HighwayJunction::new(None),
+            |data| data.clone(),
+        )?;
+        let (railways, railway_segments) = extract_network(
+            &self.railways,
+            origin,
+            width,
+            policy,
+            || metro::RailwayJunction::new(None),
+            |data| metro::RailwayJunction::new(move_station(&data.station, &address, |a| a)),
+        )?;
+
+        let mut metros = metro::Metros::new();
+        let metro_lines = copy_metro_lines(&self.metros, &railway_segments, &mut metros, &railways);
+
+        let mut region = Region {
+            qtree,
+            highways,
+            railways,
+            metros,
+        };
+        region.qtree.visit_mut(&mut MoveStationsVisitor {
+            offset: (-(bounds.min_x as i64), -(bounds.min_y as i64)),
+            metro_lines: &metro_line_ids(&metro_lines),
+        })?;
+        Ok(region)
+    }
+
+    /**
+     * Paste a region from extract_region at the given address, replacing whatever is there. The
+     * region must be the same size as the node at the address. Tiles with agents and metro
+     * stations can't be replaced, since the rest of the simulation refers to them; highways and
+     * railways that already run through the target are left alone.
+     *
+     * NOTE: This does not update the routing graph, which must be reconstructed afterwards.
+     */
+    pub fn graft_region(
+        &mut self,
+        address: quadtree::Address,
+        region: &Region<F>,
+    ) -> Result<PastedRegion, Error> {
+        self.qtree
+            .visit_rect(&mut CheckTargetVisitor, &address.bounds())?;
+        // this fails without changing anything if the region doesn't fit
+        self.graft(address, region.qtree.clone())?;
+
+        let bounds = address.bounds();
+        let origin = network::Key::new(bounds.min_x as f64, bounds.min_y as f64);
+        let (highway_junctions, highway_segments) =
+            graft_network(&region.highways, &mut self.highways, origin, |data| {
+                data.clone()
+            });
+        let (railway_junctions, railway_segments) =
+            graft_network(&region.railways, &mut self.railways, origin, |data| {
+                let root = quadtree::Address::from_vec(vec![], region.qtree.max_depth());
+                metro::RailwayJunction::new(move_station(&data.station, &root, |a| {
+                    a.rebased(&address)
+                }))
+            });
+        let metro_lines = copy_metro_lines(
+            &region.metros,
+            &railway_segments,
+            &mut self.metros,
+            &self.railways,
+        );
+
+        self.qtree.visit_rect_mut(
+            &mut MoveStationsVisitor {
+                offset: (bounds.min_x as i64, bounds.min_y as i64),
+                metro_lines: &metro_line_ids(&metro_lines),
+            },
+            &bounds,
+        )?;
+
+        Ok(PastedRegion {
+            highway_junctions,
+            highway_segments,
+            railway_junctions,
+            railway_segments,
+            metro_lines,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::region::*;
+
+    fn key(x: f64, y: f64) -> network::Key {
+        network::Key::new(x, y)
+    }
+
+    #[test]
+    fn clip_polyline_runs() {
+        let (min, max) = (key(2.0, 2.0), key(4.0, 4.0));
+
+        // entirely inside
+        let runs = clip_polyline(min, max, &[key(2.5, 2.5), key(3.5, 3.5)]);
+        assert_eq!(runs.len(), 1);
+        assert!(runs[0].from_start && runs[0].to_end);
+
+        // passes through, entering and leaving partway along the edges
+        let runs = clip_polyline(min, max, &[key(0.0, 3.0), key(3.0, 3.0), key(6.0, 3.0)]);
+        assert_eq!(runs.len(), 1);
+        assert_eq!(
+            runs[0].keys,
+            vec![key(2.0, 3.0), key(3.0, 3.0), key(4.0, 3.0)]
+        );
+        assert!(!runs[0].from_start && !runs[0].to_end);
+
+        // leaves and comes back
+        let runs = clip_polyline(
+            min,
+            max,
+            &[key(3.0, 3.0), key(3.0, 6.0), key(3.5, 6.0), key(3.5, 3.0)],
+        );
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].keys, vec![key(3.0, 3.0), key(3.0, 4.0)]);
+        assert!(runs[0].from_start && !runs[0].to_end);
+        assert_eq!(runs[1].keys, vec![key(3.5, 4.0), key(3.5, 3.0)]);
+        assert!(!runs[1].from_start && runs[1].to_end);
+
+        // outside, or only touching a corner
+        assert!(clip_polyline(min, max, &[key(0.0, 0.0), key(1.0, 6.0)]).is_empty());
+        assert!(clip_polyline(min, max, &[key(0.0, 4.0), key(4.0, 8.0)]).is_empty());
+    }
+}
@@ -1,7 +1,12 @@
+use memory_size::MemorySize;
 use quadtree::Quadtree;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::config::Config;
+use crate::development::DevelopmentGrid;
+use crate::metadata::MapMetadata;
+use crate::tile_ids::TileIndex;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -17,19 +22,37 @@ pub enum Error {
     QuadtreeError(#[from] quadtree::Error),
     #[error("Config error: {0}")]
     ConfigError(#[from] crate::config::Error),
+    #[error("Tile at {0:?} has {1} agents")]
+    TileHasAgents(quadtree::Address, usize),
+    #[error("Operation not supported for {1} tile at {0:?}")]
+    UnsupportedTile(quadtree::Address, &'static str),
+    #[error("Location {0:?} is outside of the map, which is {1} tiles wide")]
+    OutOfBounds((f64, f64), u64),
+    #[error("Segment {0:?} crosses the edge of the region")]
+    SegmentCrossesRegion(network::SegmentHandle),
 }
 
-pub trait Fields: std::fmt::Debug + Default + Clone {}
+pub trait Fields: std::fmt::Debug + Default + Clone + Send + Sync {}
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+// the fields are never serialized, so they don't need a schema
+#[schemars(bound = "F: Fields", rename = "BranchState")]
 pub struct BranchState<F: Fields> {
     #[serde(skip)]
     pub fields: F,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+// the fields are never serialized, so they don't need a schema
+#[schemars(bound = "F: Fields", rename = "LeafState")]
 pub struct LeafState<F: Fields> {
+    /// assigned by State when the leaf is created, or 0 if it hasn't been yet; see State::find_tile
+    #[serde(default)]
+    pub id: u64,
     pub tile: tiles::Tile,
+    /// older maps don't have points of interest
+    #[serde(default)]
+    pub poi: Option<tiles::Poi>,
     #[serde(skip)]
     pub fields: F,
     // NOTE: i64 so that we can use i64::MIN to represent tiles that are part of the original map.
@@ -39,13 +62,39 @@ pub struct LeafState<F: Fields> {
 impl<F: Fields> Default for LeafState<F> {
     fn default() -> Self {
         Self {
+            id: 0,
             tile: tiles::EmptyTile {}.into(),
             fields: F::default(),
             creation_time: i64::MIN,
+            poi: None,
         }
     }
 }
 
+impl<F: Fields> LeafState<F> {
+    /// Serialize the leaf, in the format that State::set_leaf_data accepts.
+    pub fn data(&self, format: SerdeFormat) -> Result<String, Error> {
+        Ok(match format {
+            SerdeFormat::Json => serde_json::to_string(self)?,
+            SerdeFormat::Toml => toml::to_string(self)?,
+        })
+    }
+}
+
+// NOTE: fields are only ever plain numbers, so they are counted as part of the size of the leaf or
+// branch itself.
+impl<F: Fields> MemorySize for BranchState<F> {
+    fn heap_bytes(&self) -> usize {
+        0
+    }
+}
+
+impl<F: Fields> MemorySize for LeafState<F> {
+    fn heap_bytes(&self) -> usize {
+        self.tile.heap_bytes() + self.poi.as_ref().map_or(0, MemorySize::heap_bytes)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum SerdeFormat {
     Json,
@@ -59,21 +108,38 @@ pub struct State<F: Fields> {
     pub railways: metro::Railways,
     pub highways: highway::Highways,
     pub metros: metro::Metros,
+    /// older maps don't have metadata
+    #[serde(default)]
+    pub metadata: MapMetadata,
     #[serde(skip)]
     pub collect_tiles: CollectTilesVisitor,
+    #[serde(skip)]
+    pub development: DevelopmentGrid,
+    /// the last tile id that was assigned; ids are never reused
+    #[serde(default)]
+    pub(crate) tile_id_counter: u64,
+    #[serde(skip)]
+    pub(crate) tile_index: TileIndex,
 }
 
 impl<F: Fields> State<F> {
     pub fn new(config: Config) -> Self {
         let qtree = Quadtree::new(LeafState::default(), config.max_depth);
-        Self {
+        let mut state = Self {
             config,
             qtree,
             railways: metro::Railways::new(),
             highways: highway::Highways::new(),
             metros: metro::Metros::new(),
+            metadata: MapMetadata::default(),
             collect_tiles: CollectTilesVisitor::default(),
-        }
+            development: DevelopmentGrid::default(),
+            tile_id_counter: 0,
+            tile_index: TileIndex::default(),
+        };
+        let root = state.qtree.get_address(0, 0).unwrap();
+        state.assign_tile_id(root).unwrap();
+        state
     }
 
     pub fn update_collect_tiles(&mut self) -> Result<(), Error> {
@@ -87,11 +153,7 @@ impl<F: Fields> State<F> {
         address: A,
         format: SerdeFormat,
     ) -> Result<String, Error> {
-        let leaf = self.qtree.get_leaf(address)?;
-        Ok(match format {
-            SerdeFormat::Json => serde_json::to_string(leaf)?,
-            SerdeFormat::Toml => toml::to_string(leaf)?,
-        })
+        self.qtree.get_leaf(address)?.data(format)
     }
 
     pub fn set_leaf_data<A: Into<quadtree::Address>>(
@@ -105,7 +167,10 @@ impl<F: Fields> State<F> {
             SerdeFormat::Json => serde_json::from_str(data)?,
             SerdeFormat::Toml => toml::from_str(data)?,
         };
+        // editing a leaf doesn't change its identity, whatever id the data has
+        let id = leaf.id;
         *leaf = decoded;
+        leaf.id = id;
         Ok(())
     }
 
@@ -152,17 +217,21 @@ impl<F: Fields> State<F> {
             let empty_quadrants: [LeafState<F>; 4] = Default::default();
             let mut quad_map = quadtree::QuadMap::from(empty_quadrants);
             quad_map[*current_quadrant] = LeafState {
+                id: 0,
                 tile: current_leaf.tile,
                 fields: F::default(),
                 creation_time: current_leaf.creation_time,
+                poi: current_leaf.poi,
             };
             quad_map[*new_quadrant] = LeafState {
+                id: 0,
                 tile,
                 fields: F::default(),
                 creation_time: current_time,
+                poi: None,
             };
 
-            match self.qtree.split(
+            match self.split(
                 address,
                 BranchState {
                     fields: current_leaf.fields,
@@ -174,8 +243,10 @@ impl<F: Fields> State<F> {
                     Some(address.child(*new_quadrant)),
                 )),
                 // TODO: handle this case
-                Err(quadtree::Error::MaxDepthExceeded(_)) => Ok((Some(address), None)),
-                Err(err) => Err(err.into()),
+                Err(Error::QuadtreeError(quadtree::Error::MaxDepthExceeded(_))) => {
+                    Ok((Some(address), None))
+                }
+                Err(err) => Err(err),
             }
         }
     }
@@ -198,6 +269,7 @@ pub struct CollectTilesVisitor {
     pub workplaces: Vec<quadtree::Address>,
     pub vacant_housing: Vec<quadtree::Address>,
     pub vacant_workplaces: Vec<quadtree::Address>,
+    pub pois: Vec<(quadtree::Address, tiles::PoiKind)>,
 }
 
 impl CollectTilesVisitor {
@@ -207,6 +279,7 @@ impl CollectTilesVisitor {
         self.workplaces.clear();
         self.vacant_housing.clear();
         self.vacant_workplaces.clear();
+        self.pois.clear();
     }
 }
 
@@ -229,7 +302,9 @@ impl<F: Fields> quadtree::Visitor<BranchState<F>, LeafState<F>, Error> for Colle
                     self.vacant_housing.push(data.address);
                 }
             }
-            WorkplaceTile(tiles::WorkplaceTile { density, agents }) => {
+            WorkplaceTile(tiles::WorkplaceTile {
+                density, agents, ..
+            }) => {
                 self.workplaces.push(data.address);
                 if &agents.len() < density {
                     self.vacant_workplaces.push(data.address);
@@ -237,6 +312,9 @@ impl<F: Fields> quadtree::Visitor<BranchState<F>, LeafState<F>, Error> for Colle
             }
             _ => (),
         }
+        if let Some(poi) = &leaf.poi {
+            self.pois.push((data.address, poi.kind));
+        }
         Ok(())
     }
 
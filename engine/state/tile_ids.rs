@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use crate::state::{BranchState, Error, Fields, LeafState, State};
+
+/**
+ * Where the leaf with each tile id is. This can be derived from the ids stored in the leaves, so it
+ * isn't serialized; see State::index_tiles.
+ */
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TileIndex(HashMap<u64, quadtree::Address>);
+
+/// Collects the id and address of every leaf, including ones that don't have an id yet.
+#[derive(Default)]
+struct CollectIdsVisitor {
+    tiles: Vec<(u64, quadtree::Address)>,
+}
+
+impl<F: Fields> quadtree::Visitor<BranchState<F>, LeafState<F>, Error> for CollectIdsVisitor {
+    fn visit_branch_pre(
+        &mut self,
+        _branch: &BranchState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn visit_leaf(&mut self, leaf: &LeafState<F>, data: &quadtree::VisitData) -> Result<(), Error> {
+        self.tiles.push((leaf.id, data.address));
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &BranchState<F>,
+        _data: &quadtree::VisitData,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+fn collect_ids<F: Fields>(
+    qtree: &quadtree::Quadtree<BranchState<F>, LeafState<F>>,
+) -> Result<Vec<(u64, quadtree::Address)>, Error> {
+    let mut visitor = CollectIdsVisitor::default();
+    qtree.visit(&mut visitor)?;
+    Ok(visitor.tiles)
+}
+
+impl<F: Fields> State<F> {
+    /**
+     * The address of the leaf with the given tile id, or None if there is no such leaf, e.g.
+     * because it has since been split. Unlike addresses, ids stay the same while the rest of the
+     * map is edited, so they are suitable for referring to tiles from outside of the simulation.
+     */
+    pub fn find_tile(&self, id: u64) -> Option<quadtree::Address> {
+        let address = *self.tile_index.0.get(&id)?;
+        // the qtree may have been edited directly since the index was updated
+        match self.qtree.get_leaf(address) {
+            Ok(leaf) if leaf.id == id => Some(address),
+            _ => None,
+        }
+    }
+
+    /// Give the leaf at the given address a new id, retiring the one it had before.
+    pub(crate) fn assign_tile_id(&mut self, address: quadtree::Address) -> Result<u64, Error> {
+        let leaf = self.qtree.get_leaf_mut(address)?;
+        self.tile_index.0.remove(&leaf.id);
+        self.tile_id_counter += 1;
+        leaf.id = self.tile_id_counter;
+        self.tile_index.0.insert(leaf.id, address);
+        Ok(leaf.id)
+    }
+
+    /**
+     * Rebuild the index of tile ids from the leaves, giving new ids to leaves that don't have one,
+     * such as leaves in maps from before tile ids were introduced or leaves that were created by
+     * editing the qtree directly. This needs to be called after loading a map.
+     */
+    pub fn index_tiles(&mut self) -> Result<(), Error> {
+        let tiles = collect_ids(&self.qtree)?;
+        if let Some(max_id) = tiles.iter().map(|(id, _)| *id).max() {
+            self.tile_id_counter = self.tile_id_counter.max(max_id);
+        }
+
+        self.tile_index.0.clear();
+        let mut unassigned = Vec::new();
+        for (id, address) in tiles {
+            // a leaf that was copied with its id needs a new one, since ids must be unique
+            if id == 0 || self.tile_index.0.insert(id, address).is_some() {
+                unassigned.push(address);
+            }
+        }
+        for address in unassigned {
+            self.tile_id_counter += 1;
+            let leaf = self.qtree.get_leaf_mut(address)?;
+            leaf.id = self.tile_id_counter;
+            self.tile_index.0.insert(leaf.id, address);
+        }
+        Ok(())
+    }
+
+    /**
+     * Split the leaf at the given address into four leaves. The id of the leaf is retired, and
+     * the new leaves get new ids.
+     */
+    pub fn split(
+        &mut self,
+        address: quadtree::Address,
+        branch: BranchState<F>,
+        leaves: quadtree::QuadMap<LeafState<F>>,
+    ) -> Result<(), Error> {
+        let id = self.qtree.get_leaf(address)?.id;
+        self.qtree.split(address, branch, leaves)?;
+        self.tile_index.0.remove(&id);
+        for quadrant in quadtree::QUADRANTS {
+            self.assign_tile_id(address.child(quadrant))?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Replace the node at the given address with the given subtree; see Quadtree::graft. The ids
+     * of the replaced leaves are retired, and the grafted leaves get new ids.
+     */
+    pub(crate) fn graft(
+        &mut self,
+        address: quadtree::Address,
+        subtree: quadtree::Quadtree<BranchState<F>, LeafState<F>>,
+    ) -> Result<(), Error> {
+        let grafted = collect_ids(&subtree)?;
+        let replaced = self.qtree.graft(address, subtree)?;
+        for (id, _) in collect_ids(&replaced)? {
+            self.tile_index.0.remove(&id);
+        }
+        for (_, leaf) in grafted {
+            self.assign_tile_id(leaf.rebased(&address))?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Returns a description of each way that the ids stored in the leaves and the index of tile
+     * ids disagree. Leaves without ids are not reported, since leaves created by editing the qtree
+     * directly don't get ids until the next call to index_tiles.
+     */
+    pub fn tile_id_issues(&self) -> Result<Vec<String>, Error> {
+        let mut issues = Vec::new();
+        let mut seen = HashMap::new();
+        for (id, address) in collect_ids(&self.qtree)? {
+            if id == 0 {
+                continue;
+            }
+            if let Some(other) = seen.insert(id, address) {
+                issues.push(format!(
+                    "Tile id {} is used at both {:?} and {:?}",
+                    id, other, address
+                ));
+            }
+            if id > self.tile_id_counter {
+                issues.push(format!(
+                    "Tile id {} at {:?} is greater than the last assigned id {}",
+                    id, address, self.tile_id_counter
+                ));
+            }
+            if self.tile_index.0.get(&id) != Some(&address) {
+                issues.push(format!(
+                    "Tile id {} at {:?} is indexed at {:?}",
+                    id,
+                    address,
+                    self.tile_index.0.get(&id)
+                ));
+            }
+        }
+        for (id, address) in &self.tile_index.0 {
+            if seen.get(id) != Some(address) {
+                issues.push(format!(
+                    "Retired tile id {} is still indexed at {:?}",
+                    id, address
+                ));
+            }
+        }
+        Ok(issues)
+    }
+}
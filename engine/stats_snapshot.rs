@@ -0,0 +1,92 @@
+use crate::engine::{Engine, Error};
+use crate::fields::FieldsState;
+
+/**
+ * Summary statistics that are all assembled at once, so that they describe the same moment in the
+ * simulation. The totals from the fields are only as fresh as the last field update, while the
+ * agent counts are always current, so the difference between the two is kept as drift rather than
+ * hidden. The snapshot is reassembled after every field update, and on request with
+ * Engine::refresh_stats_snapshot.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsSnapshot {
+    /// the simulation time that the snapshot was assembled at
+    pub time: u64,
+    /// people living on the map, from the root fields
+    pub population: usize,
+    /// people working on the map, from the root fields
+    pub employment: usize,
+    /// people living on the map that have jobs, from the root fields
+    pub employed_population: usize,
+    pub agents: usize,
+    /// agents with a workplace
+    pub employed_agents: usize,
+    /// agents currently traveling
+    pub active_routes: usize,
+    pub parked_cars: u64,
+}
+
+impl StatsSnapshot {
+    /// Agents that the fields don't know about yet, or negative if the fields count agents that
+    /// have since been removed. Zero right after the fields are updated.
+    pub fn population_drift(&self) -> i64 {
+        self.agents as i64 - self.population as i64
+    }
+
+    /// Like population_drift, for employed agents.
+    pub fn employment_drift(&self) -> i64 {
+        self.employed_agents as i64 - self.employed_population as i64
+    }
+
+    /// the fraction of people that have jobs, according to the fields
+    pub fn employment_rate(&self) -> f64 {
+        if self.population > 0 {
+            self.employed_population as f64 / self.population as f64
+        } else {
+            1.0
+        }
+    }
+}
+
+impl Engine {
+    /// The fields of the whole map.
+    pub(crate) fn root_fields(&self) -> Result<&FieldsState, Error> {
+        Ok(match self.state.qtree.get_root_branch() {
+            Ok(branch) => &branch.fields,
+            // the root is a leaf on maps that consist of a single tile
+            Err(_) => {
+                let root = quadtree::Address::from((vec![], self.state.config.max_depth));
+                &self.state.qtree.get_leaf(root)?.fields
+            }
+        })
+    }
+
+    /// The stats as of the last field update, or the last refresh if that was more recent.
+    pub fn stats_snapshot(&self) -> &StatsSnapshot {
+        &self.stats_snapshot
+    }
+
+    /// Reassemble the stats snapshot from the current state.
+    pub fn refresh_stats_snapshot(&mut self) -> Result<(), Error> {
+        let fields = self.root_fields()?;
+        let mut snapshot = StatsSnapshot {
+            time: self.time_state.current_time,
+            population: fields.population.people.total,
+            employment: fields.employment.workers.total,
+            employed_population: fields.population.employed_people,
+            agents: self.agents.len(),
+            parked_cars: self.world_state.total_parked_cars(),
+            ..Default::default()
+        };
+        for agent in self.agents.values() {
+            if agent.workplace.is_some() {
+                snapshot.employed_agents += 1;
+            }
+            if matches!(agent.state, agent::AgentState::Route(_)) {
+                snapshot.active_routes += 1;
+            }
+        }
+        self.stats_snapshot = snapshot;
+        Ok(())
+    }
+}
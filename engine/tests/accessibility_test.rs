@@ -0,0 +1,183 @@
+use engine::Engine;
+use route::{IsochroneResult, MobilityProfile};
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 5;
+const MIN_TILE_SIZE: u32 = 100;
+
+const STATION_A: (u64, u64) = (4, 4);
+const STATION_B: (u64, u64) = (28, 4);
+/// a short walk from station A
+const HOUSING: (u64, u64) = (2, 6);
+/// a short walk from station B, but too far to walk to from the housing
+const WORKPLACE: (u64, u64) = (30, 6);
+
+const REDUCED: MobilityProfile = MobilityProfile {
+    walking_speed: 0.6,
+    transfer_penalty: 120.0,
+};
+
+fn address(engine: &Engine, (x, y): (u64, u64)) -> quadtree::Address {
+    engine.state.qtree.get_address(x, y).unwrap()
+}
+
+/// Generate a map with a single metro line between two stations.
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    let mut add_station = |(x, y): (u64, u64)| {
+        let address = address(&engine, (x, y));
+        engine.state.railways.add_junction(
+            (x as f64, y as f64),
+            metro::RailwayJunction::new(Some(metro::Station {
+                name: format!("{}, {}", x, y),
+                address,
+            })),
+        )
+    };
+    let start = add_station(STATION_A);
+    let end = add_station(STATION_B);
+
+    let segment = engine.state.railways.add_segment(
+        metro::RailwaySegment::new(None),
+        start,
+        end,
+        Some(vec![
+            (STATION_A.0 as f64, STATION_A.1 as f64).into(),
+            (STATION_B.0 as f64, STATION_B.1 as f64).into(),
+        ]),
+    );
+    engine.state.metros.add_metro_line(
+        metro::MetroLineData {
+            color: (255, 0, 0).into(),
+            name: "Red".to_string(),
+            schedule: metro::Schedule::fixed_frequency(300),
+            speed_limit: 20,
+        },
+        vec![segment],
+        &engine.state.railways,
+    );
+
+    engine
+}
+
+fn query_route(engine: &Engine, profile: MobilityProfile) -> route::Route {
+    engine
+        .query_route(route::QueryInput {
+            start: address(engine, HOUSING),
+            end: address(engine, WORKPLACE),
+            car_config: None,
+            profile,
+            allowed_modes: Default::default(),
+        })
+        .unwrap()
+        .expect("expected a route")
+}
+
+/// The time spent walking along the route with the standard profile, in seconds.
+fn standard_walking_time(engine: &Engine, route: &route::Route) -> f64 {
+    route
+        .edges
+        .iter()
+        .filter(|edge| {
+            matches!(
+                edge,
+                route::Edge::ModeSegment {
+                    mode: route::Mode::Walking,
+                    ..
+                }
+            )
+        })
+        .map(|edge| edge.base_cost(&engine.state))
+        .sum()
+}
+
+#[test]
+fn slow_walking_test() {
+    let engine = generate_map();
+
+    let standard = query_route(&engine, MobilityProfile::STANDARD);
+    let slow = query_route(
+        &engine,
+        MobilityProfile {
+            walking_speed: 0.5,
+            ..MobilityProfile::STANDARD
+        },
+    );
+
+    // the walk is too long, so both routes take the metro
+    for route in [&standard, &slow] {
+        assert!(route
+            .edges
+            .iter()
+            .any(|edge| matches!(edge, route::Edge::MetroSegment { .. })));
+    }
+    assert_eq!(standard.edges.len(), slow.edges.len());
+    let walking_time = standard_walking_time(&engine, &standard);
+    assert!(walking_time > 0.0);
+
+    // walking at half the speed doubles the walking legs, and the metro takes just as long
+    let extra = (slow.total_cost() - standard.total_cost()) as f64;
+    assert!(
+        (extra - walking_time).abs() < 1.0,
+        "extra: {}, walking: {}",
+        extra,
+        walking_time
+    );
+
+    // the agent moves along the route at the same slower pace, e.g. halfway to the first station
+    let first_leg = standard.edges[0].base_cost(&engine.state) as f32;
+    let standard_key = standard
+        .sample_time(first_leg / 2.0, &engine.state)
+        .unwrap();
+    let slow_key = slow.sample_time(first_leg, &engine.state).unwrap();
+    assert_eq!(standard_key.position, slow_key.position);
+}
+
+fn query_isochrone_map(engine: &Engine, profile: MobilityProfile) -> route::IsochroneMap {
+    match engine
+        .query_isochrone_map(address(engine, STATION_A), route::Mode::Walking, profile)
+        .unwrap()
+    {
+        IsochroneResult::Calculated(isochrone_map) => isochrone_map,
+        IsochroneResult::FocusNotRoutable { .. } => panic!("expected the station to be routable"),
+    }
+}
+
+fn reachable_tiles(engine: &Engine, isochrone_map: &route::IsochroneMap, max: f64) -> Vec<bool> {
+    let width = engine.state.config.tile_width() as u64;
+    (0..width)
+        .flat_map(|x| (0..width).map(move |y| (x, y)))
+        .map(|(x, y)| isochrone_map.get_travel_time(x, y) <= max)
+        .collect()
+}
+
+#[test]
+fn reduced_mobility_isochrone_test() {
+    let engine = generate_map();
+
+    let standard = query_isochrone_map(&engine, MobilityProfile::STANDARD);
+    let reduced = query_isochrone_map(&engine, REDUCED);
+
+    let to_b = standard.isochrone.travel_times[&STATION_B];
+    let reduced_to_b = reduced.isochrone.travel_times[&STATION_B];
+    assert!(to_b.is_finite());
+    assert!(reduced_to_b >= to_b + REDUCED.transfer_penalty);
+
+    // long enough to take the metro to station B, but not with the transfer penalty
+    let max = to_b + REDUCED.transfer_penalty / 2.0;
+    assert_eq!(standard.isochrone.reachable_within(max), 2);
+    assert_eq!(reduced.isochrone.reachable_within(max), 1);
+
+    let standard_tiles = reachable_tiles(&engine, &standard, max);
+    let reduced_tiles = reachable_tiles(&engine, &reduced, max);
+    for (standard, reduced) in standard_tiles.iter().zip(&reduced_tiles) {
+        assert!(
+            standard >= reduced,
+            "reduced mobility reached an extra tile"
+        );
+    }
+    let count = |tiles: &[bool]| tiles.iter().filter(|reachable| **reachable).count();
+    assert!(count(&reduced_tiles) < count(&standard_tiles));
+}
@@ -0,0 +1,278 @@
+use engine::{AgentDataDistribution, Engine, Error, FieldsState};
+use rand::SeedableRng;
+use route::WorldState;
+use state::{BranchState, LeafState};
+use test_support::test_config;
+
+struct Map {
+    engine: Engine,
+    housing: quadtree::Address,
+    workplace: quadtree::Address,
+    empty: quadtree::Address,
+}
+
+/// Generate a map of four tiles: housing and a workplace with room for one agent each, and an
+/// empty tile.
+fn generate_map() -> Map {
+    generate_map_with_depth(3)
+}
+
+fn generate_map_with_depth(max_depth: u32) -> Map {
+    let width = 2_u64.pow(max_depth);
+    let mut engine = Engine::new(test_config(max_depth, 100));
+    engine
+        .state
+        .qtree
+        .split(
+            quadtree::Address::from((vec![], max_depth)),
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+
+    let housing = engine.state.qtree.get_address(0, 0).unwrap();
+    let workplace = engine
+        .state
+        .qtree
+        .get_address(width - 1, width - 1)
+        .unwrap();
+    let empty = engine.state.qtree.get_address(width - 1, 0).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 1,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 1,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    Map {
+        engine,
+        housing,
+        workplace,
+        empty,
+    }
+}
+
+fn agent_data(owns_car: bool) -> agent::AgentData {
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut data = AgentDataDistribution::default().sample(&mut rng);
+    data.owns_car = owns_car;
+    data
+}
+
+fn tile_agents(engine: &Engine, address: quadtree::Address) -> Vec<u64> {
+    use tiles::TileType;
+    engine
+        .state
+        .qtree
+        .get_leaf(address)
+        .unwrap()
+        .tile
+        .query_agents()
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn parking(engine: &Engine, address: quadtree::Address) -> f64 {
+    let (x, y) = address.to_xy_f64();
+    engine.world_state.get_parking(x, y)
+}
+
+/// The kinds of the pending triggers for the given agent, in order.
+fn agent_triggers(engine: &Engine, agent: u64) -> Vec<engine::TriggerKind> {
+    engine
+        .peek_triggers(100)
+        .into_iter()
+        .filter(|(_, trigger)| trigger.agent() == Some(agent))
+        .map(|(_, trigger)| engine::TriggerKind::from(trigger))
+        .collect()
+}
+
+/// Nothing changed since the map was generated.
+fn assert_unchanged(map: &Map) {
+    let engine = &map.engine;
+    assert!(engine.agents.is_empty());
+    assert!(tile_agents(engine, map.housing).is_empty());
+    assert!(tile_agents(engine, map.workplace).is_empty());
+    assert_eq!(parking(engine, map.housing), 0.0);
+}
+
+#[test]
+fn add_agent_test() {
+    let Map {
+        mut engine,
+        housing,
+        workplace,
+        ..
+    } = generate_map();
+    engine.init_trigger_queue();
+
+    let id = engine
+        .add_agent(agent_data(true), housing, Some(workplace))
+        .unwrap();
+
+    let agent = &engine.agents[&id];
+    assert_eq!(agent.housing, housing);
+    assert_eq!(agent.workplace, Some(workplace));
+    assert_eq!(agent.parked_car(), Some(housing));
+    assert_eq!(tile_agents(&engine, housing), vec![id]);
+    assert_eq!(tile_agents(&engine, workplace), vec![id]);
+    assert_eq!(parking(&engine, housing), 1.0);
+    assert_eq!(
+        agent_triggers(&engine, id),
+        vec![
+            engine::TriggerKind::AgentLifeDecisions,
+            engine::TriggerKind::AgentPlanCommuteToWork
+        ]
+    );
+    engine.consistency_check().unwrap();
+}
+
+#[test]
+fn add_agent_before_start_test() {
+    let Map {
+        mut engine,
+        housing,
+        workplace,
+        ..
+    } = generate_map();
+
+    // nothing is scheduled until the simulation starts, and then only once
+    let id = engine
+        .add_agent(agent_data(false), housing, Some(workplace))
+        .unwrap();
+    assert!(agent_triggers(&engine, id).is_empty());
+    assert_eq!(parking(&engine, housing), 0.0);
+
+    engine.init_trigger_queue();
+    assert_eq!(
+        agent_triggers(&engine, id),
+        vec![
+            engine::TriggerKind::AgentLifeDecisions,
+            engine::TriggerKind::AgentPlanCommuteToWork
+        ]
+    );
+}
+
+#[test]
+fn add_agent_after_load_test() {
+    let Map {
+        mut engine,
+        housing,
+        workplace,
+        ..
+    } = generate_map();
+    engine.init_trigger_queue();
+
+    // the loaded trigger queue isn't empty, but it still has to be initialized before agents are
+    // scheduled, and then they are only scheduled once
+    let mut engine = Engine::load(&engine.dump().unwrap()).unwrap();
+    let id = engine
+        .add_agent(agent_data(false), housing, Some(workplace))
+        .unwrap();
+    assert!(agent_triggers(&engine, id).is_empty());
+
+    engine.init_trigger_queue();
+    assert_eq!(
+        agent_triggers(&engine, id),
+        vec![
+            engine::TriggerKind::AgentLifeDecisions,
+            engine::TriggerKind::AgentPlanCommuteToWork
+        ]
+    );
+}
+
+#[test]
+fn wrong_tiles_test() {
+    let mut map = generate_map();
+    let (housing, workplace, empty) = (map.housing, map.workplace, map.empty);
+
+    assert!(matches!(
+        map.engine.add_agent(agent_data(true), empty, None),
+        Err(Error::NotAHousingTile(address)) if address == empty
+    ));
+    assert!(matches!(
+        map.engine.add_agent(agent_data(true), workplace, None),
+        Err(Error::NotAHousingTile(_))
+    ));
+    assert!(matches!(
+        map.engine.add_agent(agent_data(true), housing, Some(empty)),
+        Err(Error::NotAWorkplaceTile(address)) if address == empty
+    ));
+    assert!(matches!(
+        map.engine
+            .add_agent(agent_data(true), housing, Some(housing)),
+        Err(Error::NotAWorkplaceTile(_))
+    ));
+    assert_unchanged(&map);
+
+    // ids aren't used up by failed attempts
+    assert_eq!(
+        map.engine
+            .add_agent(agent_data(true), housing, None)
+            .unwrap(),
+        0
+    );
+}
+
+#[test]
+fn full_tiles_test() {
+    let mut map = generate_map();
+    let (housing, workplace, empty) = (map.housing, map.workplace, map.empty);
+    let first = map
+        .engine
+        .add_agent(agent_data(true), housing, Some(workplace))
+        .unwrap();
+
+    assert!(matches!(
+        map.engine.add_agent(agent_data(true), housing, None),
+        Err(Error::HousingFull(address)) if address == housing
+    ));
+
+    // the workplace is full, so the second tile fails after the first was claimed
+    map.engine.state.qtree.get_leaf_mut(empty).unwrap().tile = tiles::HousingTile {
+        density: 1,
+        agents: vec![],
+    }
+    .into();
+    let parking_before = parking(&map.engine, empty);
+    assert!(matches!(
+        map.engine.add_agent(agent_data(true), empty, Some(workplace)),
+        Err(Error::WorkplaceFull(address)) if address == workplace
+    ));
+    assert!(tile_agents(&map.engine, empty).is_empty());
+    assert_eq!(parking(&map.engine, empty), parking_before);
+    assert_eq!(map.engine.agents.len(), 1);
+    assert_eq!(tile_agents(&map.engine, workplace), vec![first]);
+
+    let second = map
+        .engine
+        .add_agent(agent_data(false), empty, None)
+        .unwrap();
+    assert_eq!(second, first + 1);
+    map.engine.consistency_check().unwrap();
+}
+
+#[test]
+fn parking_failure_test() {
+    // the map is smaller than a single parking zone, so there is nowhere to park
+    let mut map = generate_map_with_depth(1);
+    let (housing, workplace) = (map.housing, map.workplace);
+
+    assert!(matches!(
+        map.engine
+            .add_agent(agent_data(true), housing, Some(workplace)),
+        Err(Error::RouteError(_))
+    ));
+    assert!(map.engine.agents.is_empty());
+    assert!(tile_agents(&map.engine, housing).is_empty());
+    assert!(tile_agents(&map.engine, workplace).is_empty());
+
+    map.engine
+        .add_agent(agent_data(false), housing, Some(workplace))
+        .unwrap();
+}
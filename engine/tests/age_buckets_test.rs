@@ -0,0 +1,161 @@
+use agent::AgeBucket;
+use engine::{Engine, FieldsState};
+use quadtree::VisitData;
+use state::{BranchState, LeafState};
+use test_support::test_config;
+
+const MAX_DEPTH: u32 = 3;
+
+fn date(year: i32, month: u32, day: u32) -> chrono::NaiveDate {
+    chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+fn split(engine: &mut Engine, address: quadtree::Address) {
+    engine
+        .state
+        .qtree
+        .split(
+            address,
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+}
+
+fn add_housing(engine: &mut Engine, (x, y): (u64, u64)) -> quadtree::Address {
+    let address = engine.state.qtree.get_address(x, y).unwrap();
+    engine.state.qtree.get_leaf_mut(address).unwrap().tile = tiles::HousingTile {
+        density: 10,
+        agents: vec![],
+    }
+    .into();
+    address
+}
+
+fn add_agent(engine: &mut Engine, housing: quadtree::Address, birthday: chrono::NaiveDate) {
+    let data = agent::AgentData {
+        birthday,
+        years_of_education: 12,
+        owns_car: false,
+        walking_speed: 1.0,
+        archetype: 0,
+        noise: Default::default(),
+    };
+    engine.add_agent(data, housing, None).unwrap();
+}
+
+/**
+ * Generate a map with leaves at different depths, and people of all ages living in them. The
+ * simulation starts on January 1, 2020.
+ */
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    let root = quadtree::Address::from((vec![], MAX_DEPTH));
+    split(&mut engine, root);
+    split(&mut engine, root.child(quadtree::Quadrant::NW));
+    split(
+        &mut engine,
+        root.child(quadtree::Quadrant::NW)
+            .child(quadtree::Quadrant::SE),
+    );
+
+    let small = add_housing(&mut engine, (3, 3));
+    let medium = add_housing(&mut engine, (0, 0));
+    let large = add_housing(&mut engine, (6, 6));
+
+    // turns 18 on March 1, 2020
+    add_agent(&mut engine, small, date(2002, 3, 1));
+    add_agent(&mut engine, small, date(1980, 7, 4));
+    add_agent(&mut engine, medium, date(2012, 5, 20));
+    add_agent(&mut engine, medium, date(1950, 1, 1));
+    // turns 65 on February 1, 2020
+    add_agent(&mut engine, large, date(1955, 2, 1));
+    add_agent(&mut engine, large, date(1995, 11, 30));
+    add_agent(&mut engine, large, date(2019, 8, 15));
+
+    engine
+}
+
+#[derive(Default)]
+struct CollectBucketsVisitor {
+    nodes: usize,
+}
+
+impl CollectBucketsVisitor {
+    fn check(&mut self, fields: &FieldsState, data: &VisitData) {
+        let population = &fields.population;
+        let sum: usize = population.people_by_age.iter().map(|(_, n)| n).sum();
+        assert_eq!(sum, population.people.total, "at {:?}", data.address);
+        self.nodes += 1;
+    }
+}
+
+impl quadtree::Visitor<BranchState<FieldsState>, LeafState<FieldsState>, anyhow::Error>
+    for CollectBucketsVisitor
+{
+    fn visit_branch_pre(
+        &mut self,
+        branch: &BranchState<FieldsState>,
+        data: &VisitData,
+    ) -> Result<bool, anyhow::Error> {
+        self.check(&branch.fields, data);
+        Ok(true)
+    }
+
+    fn visit_leaf(
+        &mut self,
+        leaf: &LeafState<FieldsState>,
+        data: &VisitData,
+    ) -> Result<(), anyhow::Error> {
+        self.check(&leaf.fields, data);
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &BranchState<FieldsState>,
+        _data: &VisitData,
+    ) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+fn root_buckets(engine: &Engine) -> [usize; AgeBucket::COUNT] {
+    let root = &engine.state.qtree.get_root_branch().unwrap().fields;
+    AgeBucket::ALL.map(|bucket| root.population.people_by_age[bucket])
+}
+
+#[test]
+fn bucket_sums_test() {
+    let mut engine = generate_map();
+    engine.update_fields().unwrap();
+
+    let mut visitor = CollectBucketsVisitor::default();
+    engine.state.qtree.visit(&mut visitor).unwrap();
+    // the root, two branches, and ten leaves
+    assert_eq!(visitor.nodes, 13);
+
+    assert_eq!(root_buckets(&engine), [3, 3, 1]);
+    let root = &engine.state.qtree.get_root_branch().unwrap().fields;
+    assert_eq!(root.population.people.total, 7);
+    assert!((root.population.age_share(AgeBucket::Senior) - 1.0 / 7.0).abs() < 1e-9);
+}
+
+#[test]
+fn birthday_test() {
+    let mut engine = generate_map();
+    engine.update_fields().unwrap();
+    assert_eq!(root_buckets(&engine), [3, 3, 1]);
+
+    // a 65th birthday on February 1
+    engine.time_state.current_time = 32 * 24 * 60 * 60;
+    assert_eq!(engine.time_state.current_date(), date(2020, 2, 2));
+    engine.update_fields().unwrap();
+    assert_eq!(root_buckets(&engine), [3, 2, 2]);
+
+    // an 18th birthday on March 1
+    engine.time_state.current_time = (31 + 29) * 24 * 60 * 60;
+    assert_eq!(engine.time_state.current_date(), date(2020, 3, 1));
+    engine.update_fields().unwrap();
+    assert_eq!(root_buckets(&engine), [2, 3, 2]);
+}
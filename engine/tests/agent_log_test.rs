@@ -0,0 +1,23 @@
+use test_support::commuter_map;
+use uom::si::time::hour;
+use uom::si::u64::Time;
+
+#[test]
+fn routing_worker_test() {
+    let (mut engine, id) = commuter_map();
+    engine.watch_agent(id, None).unwrap();
+
+    // the commute is queried on a worker thread, which logs the outcome of the query
+    let commute_time = Time::new::<hour>(engine.work_schedule(id).start_hour).value;
+    let deadline = engine.state.config.scheduling.route_start_deadline;
+    engine.tick(commute_time + deadline + 1).unwrap();
+    assert_eq!(engine.route_latency().route_starts, 1);
+
+    let log = engine.agent_log(id);
+    assert!(
+        log.iter()
+            .any(|line| line.starts_with("route query finished in")),
+        "{:#?}",
+        log
+    );
+}
@@ -0,0 +1,80 @@
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_all, test_config};
+use uom::si::time::{hour, minute};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 3;
+const MIN_TILE_SIZE: u32 = 3000;
+
+fn set_tile(engine: &mut Engine, (x, y): (u64, u64), tile: tiles::Tile) -> quadtree::Address {
+    let address = engine.state.qtree.get_address(x, y).unwrap();
+    engine.state.qtree.get_leaf_mut(address).unwrap().tile = tile;
+    address
+}
+
+fn center(address: quadtree::Address) -> (f32, f32) {
+    let (x, y) = address.to_xy();
+    (x as f32, y as f32)
+}
+
+#[test]
+fn live_agent_positions_test() {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    let housing = set_tile(
+        &mut engine,
+        (0, 0),
+        tiles::HousingTile {
+            density: 2,
+            agents: vec![],
+        }
+        .into(),
+    );
+    // close enough to drive to directly
+    let workplace = set_tile(
+        &mut engine,
+        (1, 0),
+        tiles::WorkplaceTile {
+            density: 2,
+            agents: vec![],
+            industry: tiles::Industry::Office,
+        }
+        .into(),
+    );
+
+    for _ in 0..2 {
+        let data = AgentDataDistribution::default().sample(&mut engine.rng);
+        engine.add_agent(data, housing, Some(workplace)).unwrap();
+    }
+    engine.init_trigger_queue();
+
+    // everyone starts at home
+    let positions: Vec<_> = engine.live_agent_positions().collect();
+    assert_eq!(positions.len(), 2);
+    assert!(positions.iter().all(|(_, pos)| *pos == center(housing)));
+
+    // the simulation starts on a Wednesday, so everyone commutes to work in the morning
+    let (home_x, home_y) = center(housing);
+    let (work_x, work_y) = center(workplace);
+    let mut seen_traveling = false;
+    engine.tick(Time::new::<hour>(6).value).unwrap();
+    for _ in 0..(6 * 60) {
+        engine.tick(Time::new::<minute>(1).value).unwrap();
+        for (id, (x, y)) in engine.live_agent_positions() {
+            assert!(engine.agents.contains_key(&id));
+            if let agent::AgentState::Route(_) = engine.agents[&id].state {
+                seen_traveling = true;
+                // somewhere along the road between home and work
+                assert!(x >= home_x - 1.0 && x <= work_x + 1.0, "x: {}", x);
+                assert!(y >= home_y - 1.0 && y <= work_y + 1.0, "y: {}", y);
+            }
+        }
+    }
+    assert!(seen_traveling);
+
+    // by noon, everyone is at work
+    let positions: Vec<_> = engine.live_agent_positions().collect();
+    assert_eq!(positions.len(), 2);
+    assert!(positions.iter().all(|(_, pos)| *pos == center(workplace)));
+}
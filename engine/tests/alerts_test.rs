@@ -0,0 +1,116 @@
+use engine::{AgentDataDistribution, Engine, Severity, Watcher};
+use test_support::{split_all, test_config};
+use uom::si::time::day;
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 3;
+// large enough that the far corner of the map is out of reach without any networks
+const MIN_TILE_SIZE: u32 = 3000;
+
+fn set_tile(engine: &mut Engine, (x, y): (u64, u64), tile: tiles::Tile) -> quadtree::Address {
+    let address = engine.state.qtree.get_address(x, y).unwrap();
+    engine.state.qtree.get_leaf_mut(address).unwrap().tile = tile;
+    address
+}
+
+/// Generate a map with two agents who live together and work at the given coordinates.
+fn generate_map(workplace: (u64, u64)) -> (Engine, quadtree::Address) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    let housing = set_tile(
+        &mut engine,
+        (0, 0),
+        tiles::HousingTile {
+            density: 2,
+            agents: vec![],
+        }
+        .into(),
+    );
+    let workplace = set_tile(
+        &mut engine,
+        workplace,
+        tiles::WorkplaceTile {
+            density: 2,
+            agents: vec![],
+            industry: tiles::Industry::Office,
+        }
+        .into(),
+    );
+
+    for _ in 0..2 {
+        let data = AgentDataDistribution::default().sample(&mut engine.rng);
+        engine.add_agent(data, housing, Some(workplace)).unwrap();
+    }
+
+    engine.init_trigger_queue();
+    (engine, housing)
+}
+
+#[test]
+fn all_routes_failing_pauses() {
+    // no way to get to work
+    let (mut engine, _) = generate_map((7, 7));
+    engine.alerts.thresholds.min_route_queries = 2;
+    engine.alerts.auto_pause = true;
+
+    engine.time_state.paused = false;
+    engine.time_state.skip_by(Time::new::<day>(1).value);
+    engine.update(0.0, f64::INFINITY).unwrap();
+
+    let alerts: Vec<_> = engine.alerts.iter().cloned().collect();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].watcher, Watcher::AllRoutesFailing);
+    assert_eq!(alerts[0].severity, Severity::Critical);
+
+    // the simulation stopped as soon as the alert was raised, and the skip was cancelled
+    assert!(engine.time_state.paused);
+    assert_eq!(engine.time_state.current_time, alerts[0].time);
+    assert!(engine.time_state.is_caught_up());
+    assert!(alerts[0].time < Time::new::<day>(1).value);
+
+    // alerts survive a round trip through a save file
+    let loaded = Engine::load(&engine.dump().unwrap()).unwrap();
+    assert_eq!(loaded.alerts.iter().cloned().collect::<Vec<_>>(), alerts);
+    assert!(loaded.alerts.auto_pause);
+}
+
+#[test]
+fn no_pause_without_auto_pause() {
+    let (mut engine, _) = generate_map((7, 7));
+    engine.alerts.thresholds.min_route_queries = 2;
+
+    engine.time_state.paused = false;
+    engine.time_state.skip_by(Time::new::<day>(1).value);
+    engine.update(0.0, f64::INFINITY).unwrap();
+
+    // once for the commute to work, and once for the commute home
+    assert_eq!(engine.alerts.unacknowledged().count(), 2);
+    assert!(engine
+        .alerts
+        .iter()
+        .all(|alert| alert.watcher == Watcher::AllRoutesFailing));
+    assert!(!engine.time_state.paused);
+    assert_eq!(engine.time_state.current_time, Time::new::<day>(1).value);
+}
+
+#[test]
+fn parking_errors_are_counted() {
+    // close enough to drive to directly
+    let (mut engine, housing) = generate_map((1, 0));
+    engine.alerts.thresholds.errors_per_hour = 0;
+
+    // take away the agents' cars behind the engine's back, so that leaving home fails
+    for _ in 0..2 {
+        engine.world_state.decrement_parking(housing).unwrap();
+    }
+
+    // the errors don't stop the simulation
+    engine.tick(Time::new::<day>(1).value).unwrap();
+
+    assert!(engine.alerts.totals().parking_errors > 0);
+    let alerts: Vec<_> = engine.alerts.iter().collect();
+    assert!(!alerts.is_empty());
+    assert_eq!(alerts[0].watcher, Watcher::ErrorRate);
+    assert_eq!(alerts[0].severity, Severity::Warning);
+}
@@ -0,0 +1,92 @@
+use engine::{AgentDataDistribution, Engine};
+use state::{AgentArchetype, AgentArchetypes};
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 4;
+const MIN_TILE_SIZE: u32 = 100;
+
+/// Generate a map that is all housing, with the given archetypes.
+fn generate_map(archetypes: AgentArchetypes) -> Engine {
+    let mut engine = Engine::new(state::Config {
+        archetypes,
+        ..test_config(MAX_DEPTH, MIN_TILE_SIZE)
+    });
+    engine.state.config.validate().unwrap();
+
+    split_all(&mut engine);
+
+    let width = engine.state.qtree.width();
+    for x in 0..width {
+        for y in 0..width {
+            let address = engine.state.qtree.get_address(x, y).unwrap();
+            engine.state.qtree.get_leaf_mut(address).unwrap().tile = tiles::HousingTile {
+                density: 10,
+                agents: vec![],
+            }
+            .into();
+        }
+    }
+
+    engine
+}
+
+#[test]
+fn runtime_archetype_change_test() {
+    let mut engine = generate_map(Default::default());
+    engine
+        .populate_housing(0.1, 0, &AgentDataDistribution::default())
+        .unwrap();
+    let id = *engine.agents.keys().next().unwrap();
+
+    let tolerance = |engine: &Engine| {
+        engine.agents[&id]
+            .data
+            .commute_length_tolerance(&engine.state.config.archetypes)
+    };
+    assert_eq!(tolerance(&engine), 3600);
+
+    // existing agents pick up the new value without being regenerated
+    engine
+        .state
+        .config
+        .archetypes
+        .get_mut(0)
+        .unwrap()
+        .commute_length_tolerance = 7200;
+    assert_eq!(tolerance(&engine), 7200);
+}
+
+#[test]
+fn archetype_shares_test() {
+    let mut engine = generate_map(AgentArchetypes::new(vec![
+        AgentArchetype {
+            name: "driver".to_string(),
+            share: 3.0,
+            ..Default::default()
+        },
+        AgentArchetype {
+            name: "walker".to_string(),
+            share: 1.0,
+            car_ownership_rate: 0.0,
+            ..Default::default()
+        },
+    ]));
+    let added = engine
+        .populate_housing(1.0, 0, &AgentDataDistribution::default())
+        .unwrap();
+    assert_eq!(added, engine.agents.len());
+
+    let archetypes = &engine.state.config.archetypes;
+    let drivers = engine
+        .agents
+        .values()
+        .filter(|agent| agent.data.archetype(archetypes).name == "driver")
+        .count();
+    let fraction = drivers as f64 / added as f64;
+    assert!((fraction - 0.75).abs() < 0.05, "{}", fraction);
+
+    // walkers never get cars of their own, while drivers all do
+    for agent in engine.agents.values() {
+        assert_eq!(agent.data.owns_car, agent.data.archetype == 0);
+    }
+}
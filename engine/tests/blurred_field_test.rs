@@ -0,0 +1,127 @@
+use engine::{BlurredFieldSpec, Engine, FieldsState};
+use rand::SeedableRng;
+use state::LeafState;
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 5;
+/// the same as the blur block size, so that each tile is its own block
+const MIN_TILE_SIZE: u32 = 200;
+
+/// a block of housing in the middle of the map, far enough from the edges to not be clipped
+const HOUSING: std::ops::Range<u64> = 14..18;
+const HOUSING_DENSITY: usize = 20;
+
+fn housing_spec() -> BlurredFieldSpec {
+    BlurredFieldSpec {
+        getter: |f| f.population.housing.total as f64,
+        setter: None,
+        radius: 400.0,
+        scale: 10.0,
+    }
+}
+
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    for x in HOUSING {
+        for y in HOUSING {
+            let address = engine.state.qtree.get_address(x, y).unwrap();
+            engine.state.qtree.get_leaf_mut(address).unwrap().tile = tiles::HousingTile {
+                density: HOUSING_DENSITY,
+                agents: vec![],
+            }
+            .into();
+        }
+    }
+    // water right next to the housing, which should never be sampled
+    let lake = engine
+        .state
+        .qtree
+        .get_address(HOUSING.end, HOUSING.start)
+        .unwrap();
+    engine.state.qtree.get_leaf_mut(lake).unwrap().tile = tiles::WaterTile {}.into();
+
+    engine
+        .register_blurred_field("housing", housing_spec())
+        .unwrap();
+    engine.update_fields().unwrap();
+    engine
+}
+
+#[test]
+fn registry_test() {
+    let mut engine = generate_map();
+
+    let names = engine.blurred_field_names();
+    assert_eq!(
+        names,
+        vec![
+            engine::LAND_VALUE,
+            engine::CONSTRUCTION_COST,
+            engine::WORKPLACE_DEMAND,
+            "housing"
+        ]
+    );
+    assert!(engine.blurred_field("missing").is_none());
+
+    match engine.register_blurred_field("housing", housing_spec()) {
+        Err(engine::Error::DuplicateBlurredField(name)) => assert_eq!(name, "housing"),
+        other => panic!("expected a duplicate field error, got {:?}", other),
+    }
+}
+
+#[test]
+fn conserves_mass_test() {
+    let engine = generate_map();
+    let field = engine.blurred_field("housing").unwrap();
+
+    let expected = (HOUSING.count().pow(2) * HOUSING_DENSITY) as f64;
+    let total = field.total();
+    assert!(
+        (total - expected).abs() / expected < 0.1,
+        "expected total of about {}, got {}",
+        expected,
+        total
+    );
+
+    // blurring spreads the housing out, so the peak is lower and nearby tiles are non-zero
+    let center = field.value_at(16, 16).unwrap();
+    assert!(center > 0.0 && center < HOUSING_DENSITY as f64);
+    assert!(field.value_at(HOUSING.end + 1, 16).unwrap() > 0.0);
+    assert_eq!(field.value_at(0, 0), Some(0.0));
+    assert!(field.max_value() >= center);
+
+    let width = engine.state.qtree.width();
+    assert_eq!(field.value_at(width, 0), None);
+}
+
+#[test]
+fn sample_where_test() {
+    let engine = generate_map();
+    let field = engine.blurred_field("housing").unwrap();
+    let mut rng = rand_chacha::ChaCha12Rng::seed_from_u64(0);
+
+    let is_housing =
+        |leaf: &LeafState<FieldsState>| matches!(leaf.tile, tiles::Tile::HousingTile(_));
+    let mut sampled = 0;
+    for _ in 0..100 {
+        if let Some(address) = field.sample_where(&mut rng, &engine.state.qtree, 20, is_housing) {
+            let leaf = engine.state.qtree.get_leaf(address).unwrap();
+            assert!(is_housing(leaf));
+            sampled += 1;
+        }
+    }
+    // nearly all of the weight is on or right next to the housing
+    assert!(sampled > 90);
+
+    // rejecting everything gives up rather than looping forever
+    assert_eq!(
+        field.sample_where(&mut rng, &engine.state.qtree, 20, |_| false),
+        None
+    );
+
+    // fields that are all zero can't be sampled at all
+    let empty = engine.blurred_field(engine::WORKPLACE_DEMAND).unwrap();
+    assert_eq!(empty.sample(&mut rng, &engine.state.qtree), None);
+}
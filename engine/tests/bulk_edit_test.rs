@@ -0,0 +1,219 @@
+use engine::{AgentDataDistribution, Engine};
+use state::BulkOp;
+use test_support::{split_to_depth, test_config};
+
+const MAX_DEPTH: u32 = 4;
+
+/// Generate a map split down to depth 2, with one housing tile with a resident who works at a
+/// workplace tile.
+fn generate_map() -> (Engine, quadtree::Address, quadtree::Address, u64) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    split_to_depth(&mut engine, 2);
+
+    let housing = engine.state.qtree.get_address(1, 1).unwrap();
+    let workplace = engine.state.qtree.get_address(14, 14).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 2,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 2,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    let agent = engine.add_agent(data, housing, Some(workplace)).unwrap();
+
+    (engine, housing, workplace, agent)
+}
+
+fn is_water(engine: &Engine, address: quadtree::Address) -> bool {
+    matches!(
+        engine.state.qtree.get_leaf(address).unwrap().tile,
+        tiles::Tile::WaterTile(_)
+    )
+}
+
+#[test]
+fn paint_lake_test() {
+    let (mut engine, housing, _, _) = generate_map();
+
+    // a rectangle covering the whole west half of the map, including the housing tile
+    let selected = engine
+        .state
+        .leaves_in_rect(&quadtree::Rect::corners(0, 0, 8, 16));
+    assert_eq!(selected.len(), 8);
+    assert!(selected.contains(&housing));
+
+    let report = engine.bulk_apply(&selected, &BulkOp::SetWater);
+    // the housing tile still has a resident, so it is skipped but everything else is applied
+    assert_eq!(report.applied.len(), 7);
+    assert_eq!(report.errors.len(), 1);
+    assert!(matches!(
+        report.errors[0],
+        (address, engine::Error::StateError(state::Error::TileHasAgents(_, 1))) if address == housing
+    ));
+    for address in &report.applied {
+        assert!(is_water(&engine, *address));
+    }
+    assert!(!is_water(&engine, housing));
+
+    // once the agents are cleared, the rest of the lake can be filled in
+    let report = engine.clear_agents(&selected);
+    assert!(report.is_ok());
+    assert!(engine.agents.is_empty());
+    assert!(engine.bulk_apply(&[housing], &BulkOp::SetWater).is_ok());
+    assert!(is_water(&engine, housing));
+
+    assert!(engine.consistency_check().is_ok());
+}
+
+#[test]
+fn error_aggregation_test() {
+    let (mut engine, housing, workplace, agent) = generate_map();
+
+    let empty = engine.state.qtree.get_address(14, 1).unwrap();
+    let water = engine.state.qtree.get_address(1, 14).unwrap();
+    engine.state.qtree.get_leaf_mut(water).unwrap().tile = tiles::WaterTile {}.into();
+    // the root is a branch, not a leaf
+    let branch = quadtree::Address::from((vec![], MAX_DEPTH));
+
+    let addresses = [empty, housing, branch, water, workplace];
+
+    let report = engine.bulk_apply(&addresses, &BulkOp::SetHousingDensity(3));
+    // empty tiles become housing and existing housing is updated
+    assert_eq!(report.applied, vec![empty, housing]);
+    let errors: Vec<_> = report.errors.iter().map(|(address, _)| *address).collect();
+    assert_eq!(errors, vec![branch, water, workplace]);
+    assert!(matches!(
+        report.errors[0].1,
+        engine::Error::StateError(state::Error::QuadtreeError(quadtree::Error::ExpectedLeaf()))
+    ));
+    assert!(matches!(
+        report.errors[1].1,
+        engine::Error::StateError(state::Error::UnsupportedTile(_, "water"))
+    ));
+    assert!(matches!(
+        report.errors[2].1,
+        engine::Error::StateError(state::Error::TileHasAgents(_, 1))
+    ));
+
+    // can't lower the density below the number of residents
+    let report = engine.bulk_apply(&[housing], &BulkOp::SetHousingDensity(0));
+    assert!(!report.is_ok());
+    assert!(matches!(
+        engine.state.qtree.get_leaf(housing).unwrap().tile,
+        tiles::Tile::HousingTile(tiles::HousingTile { density: 3, .. })
+    ));
+
+    // clearing agents from the workplace only fires the agent
+    assert!(engine.clear_agents(&[workplace]).is_ok());
+    assert_eq!(engine.agents[&agent].workplace, None);
+
+    let report = engine.bulk_apply(&addresses, &BulkOp::SplitToDepth(MAX_DEPTH + 1));
+    assert!(report.applied.is_empty());
+    assert_eq!(report.errors.len(), addresses.len());
+
+    let report = engine.bulk_apply(&addresses, &BulkOp::SplitToDepth(3));
+    // only empty and water tiles can be split
+    assert_eq!(report.applied, vec![water]);
+    for quadrant in quadtree::QUADRANTS {
+        assert!(is_water(&engine, water.child(quadrant)));
+    }
+
+    assert!(engine.consistency_check().is_ok());
+}
+
+#[test]
+fn select_by_type_test() {
+    let (mut engine, housing, _workplace, _agent) = generate_map();
+
+    let other_housing = engine.state.qtree.get_address(13, 1).unwrap();
+    engine.state.qtree.get_leaf_mut(other_housing).unwrap().tile = tiles::HousingTile {
+        density: 1,
+        agents: vec![],
+    }
+    .into();
+    let housing_type = std::mem::discriminant(&engine.state.qtree.get_leaf(housing).unwrap().tile);
+
+    // only the housing tiles in the left half
+    let left = quadtree::Rect::xywh(0, 0, 8, 16);
+    assert_eq!(
+        engine.state.leaves_of_type_in_rect(&left, housing_type),
+        vec![housing]
+    );
+
+    let whole_map = quadtree::Rect::xywh(0, 0, 16, 16);
+    let selected = engine
+        .state
+        .leaves_of_type_in_rect(&whole_map, housing_type);
+    assert_eq!(selected, vec![housing, other_housing]);
+
+    let report = engine.bulk_apply(&selected, &BulkOp::SetHousingDensity(5));
+    assert!(report.is_ok());
+    for address in selected {
+        assert!(matches!(
+            engine.state.qtree.get_leaf(address).unwrap().tile,
+            tiles::Tile::HousingTile(tiles::HousingTile { density: 5, .. })
+        ));
+    }
+
+    assert!(engine.consistency_check().is_ok());
+}
+
+fn water_fraction(engine: &Engine, address: quadtree::Address) -> f64 {
+    let qtree = &engine.state.qtree;
+    let fields = match qtree.get_branch(address) {
+        Ok(branch) => &branch.fields,
+        Err(_) => &qtree.get_leaf(address).unwrap().fields,
+    };
+    fields.terrain.water_fraction()
+}
+
+#[test]
+fn water_fraction_test() {
+    let (mut engine, _, _, _) = generate_map();
+    let root = quadtree::Address::from((vec![], MAX_DEPTH));
+    let ne = root.child(quadtree::Quadrant::NE);
+    let sw = root.child(quadtree::Quadrant::SW);
+
+    // the northeast quadrant is entirely water, and half of the southwest quadrant is
+    for quadrant in quadtree::QUADRANTS {
+        engine
+            .state
+            .qtree
+            .get_leaf_mut(ne.child(quadrant))
+            .unwrap()
+            .tile = tiles::WaterTile {}.into();
+    }
+    for quadrant in [quadtree::Quadrant::NW, quadtree::Quadrant::SW] {
+        engine
+            .state
+            .qtree
+            .get_leaf_mut(sw.child(quadrant))
+            .unwrap()
+            .tile = tiles::WaterTile {}.into();
+    }
+    engine.update_fields().unwrap();
+
+    assert_eq!(water_fraction(&engine, ne), 1.0);
+    assert!((water_fraction(&engine, sw) - 0.5).abs() < 1e-9);
+    assert_eq!(
+        water_fraction(&engine, ne.child(quadtree::Quadrant::NW)),
+        1.0
+    );
+    assert!((water_fraction(&engine, root) - 6.0 / 16.0).abs() < 1e-9);
+
+    // paint a lake over the rest of the southwest quadrant
+    let selected = engine
+        .state
+        .leaves_in_rect(&quadtree::Rect::corners(4, 8, 8, 16));
+    assert!(engine.bulk_apply(&selected, &BulkOp::SetWater).is_ok());
+    engine.update_fields().unwrap();
+
+    assert_eq!(water_fraction(&engine, sw), 1.0);
+    assert!((water_fraction(&engine, root) - 8.0 / 16.0).abs() < 1e-9);
+}
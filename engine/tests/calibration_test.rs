@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, HashMap};
+
+use engine::{Engine, Observations, OsmIdMap, SegmentObservation};
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 3;
+
+/// Generate a map with a highway of two segments, with traffic on it that varies over the day.
+fn generate_map() -> (Engine, Vec<network::SegmentHandle>) {
+    let mut engine = Engine::new(state::Config {
+        people_per_sim: 2.0,
+        ..test_config(MAX_DEPTH, 100)
+    });
+    split_all(&mut engine);
+
+    let data = highway::HighwaySegment::new(None, vec![], None, Some(30));
+    let points = [(1.0, 1.0), (4.0, 1.0), (7.0, 6.0)];
+    let junctions: Vec<_> = points
+        .iter()
+        .map(|point| {
+            engine
+                .state
+                .highways
+                .add_junction(*point, highway::HighwayJunction::new(None))
+        })
+        .collect();
+    let segments: Vec<_> = (0..points.len() - 1)
+        .map(|i| {
+            engine.state.highways.add_segment(
+                data.clone(),
+                junctions[i],
+                junctions[i + 1],
+                Some(vec![points[i].into(), points[i + 1].into()]),
+            )
+        })
+        .collect();
+
+    let period = engine.world_state_history.snapshot_period();
+    for i in 0..engine.world_state_history.num_snapshots() as u64 {
+        for (j, segment) in segments.iter().enumerate() {
+            engine
+                .world_state
+                .add_highway_segment_travelers(*segment, ((i + j as u64 * 7) % 12) as f64)
+                .unwrap();
+        }
+        engine.time_state.current_time = i * period;
+        engine.record_traffic_snapshot();
+    }
+
+    (engine, segments)
+}
+
+/// Observations of the given segment that are the simulated counts, scaled by a factor.
+fn scaled_observation(
+    engine: &Engine,
+    segment: network::SegmentHandle,
+    factor: f64,
+) -> SegmentObservation {
+    SegmentObservation {
+        segment: Some(segment.inner()),
+        osm_way: None,
+        hourly_counts: (0..24)
+            .map(|hour| {
+                let simulated = engine.simulated_hourly_flow(segment, hour).unwrap();
+                (hour, simulated * factor)
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn matching_observations_test() {
+    let (engine, segments) = generate_map();
+    assert!((0..24).any(|hour| engine.simulated_hourly_flow(segments[0], hour).unwrap() > 0.0));
+
+    let observations = Observations {
+        segments: segments
+            .iter()
+            .map(|segment| scaled_observation(&engine, *segment, 1.0))
+            .collect(),
+        id_map: None,
+    };
+    let report = engine.calibration_report(&observations).unwrap();
+    assert_eq!(report.samples.len(), 48);
+    assert_eq!(report.segments.len(), 2);
+    assert!(report.unresolved.is_empty());
+    assert_eq!(report.aggregate.mape, Some(0.0));
+    assert_eq!(report.aggregate.rmse, 0.0);
+    for segment in &segments {
+        let calibration = report.segment(*segment).unwrap();
+        assert_eq!(calibration.metrics.mape, Some(0.0));
+        assert_eq!(calibration.ratio(), Some(1.0));
+    }
+
+    let csv = report.to_csv();
+    assert_eq!(csv.lines().count(), 49);
+    assert!(csv.starts_with("segment,hour,observed,simulated\n"));
+}
+
+#[test]
+fn scaled_observations_test() {
+    let (engine, segments) = generate_map();
+
+    // the simulation sees half of the observed traffic on one segment, and double on the other
+    let observations = Observations {
+        segments: vec![
+            scaled_observation(&engine, segments[0], 2.0),
+            scaled_observation(&engine, segments[1], 0.5),
+        ],
+        id_map: None,
+    };
+    let report = engine.calibration_report(&observations).unwrap();
+
+    let first = report.segment(segments[0]).unwrap();
+    assert!((first.metrics.mape.unwrap() - 50.0).abs() < 1e-9);
+    assert!((first.ratio().unwrap() - 0.5).abs() < 1e-9);
+    let second = report.segment(segments[1]).unwrap();
+    assert!((second.metrics.mape.unwrap() - 100.0).abs() < 1e-9);
+    assert!((second.ratio().unwrap() - 2.0).abs() < 1e-9);
+    assert!(report.aggregate.rmse > 0.0);
+}
+
+#[test]
+fn osm_way_observations_test() {
+    let (engine, segments) = generate_map();
+
+    let id_map = OsmIdMap::load(&format!(
+        r#"{{"highway_segments": {{"100": [{}, {}]}}}}"#,
+        segments[0].inner(),
+        segments[1].inner()
+    ))
+    .unwrap();
+    let observe_way = |way| SegmentObservation {
+        segment: None,
+        osm_way: Some(way),
+        hourly_counts: BTreeMap::from([(8, 100.0)]),
+    };
+    let observations = Observations {
+        segments: vec![
+            observe_way(100),
+            observe_way(200),
+            SegmentObservation {
+                segment: Some(u64::MAX),
+                osm_way: None,
+                hourly_counts: BTreeMap::from([(8, 100.0)]),
+            },
+        ],
+        id_map: None,
+    };
+
+    // without the id map, none of the ways can be found
+    let report = engine.calibration_report(&observations).unwrap();
+    assert!(report.samples.is_empty());
+    assert_eq!(report.unresolved.len(), 3);
+
+    // the counts for a way apply to every segment the way was baked into
+    let report = engine
+        .calibration_report(&observations.with_id_map(id_map))
+        .unwrap();
+    let observed: HashMap<_, _> = report
+        .samples
+        .iter()
+        .map(|sample| (sample.segment, sample.observed))
+        .collect();
+    assert_eq!(
+        observed,
+        HashMap::from([(segments[0], 100.0), (segments[1], 100.0)])
+    );
+    assert_eq!(report.unresolved.len(), 2);
+}
+
+#[test]
+fn invalid_observations_test() {
+    let (engine, segments) = generate_map();
+    let observations = Observations {
+        segments: vec![SegmentObservation {
+            segment: Some(segments[0].inner()),
+            osm_way: None,
+            hourly_counts: BTreeMap::from([(25, 100.0)]),
+        }],
+        id_map: None,
+    };
+    assert!(engine.calibration_report(&observations).is_err());
+}
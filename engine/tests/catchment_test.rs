@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 5;
+/// at the standard walking speed of 1.5 m/s, 10 minutes is 900m, or 9 tiles
+const MIN_TILE_SIZE: u32 = 100;
+
+const STATION_A: (u64, u64) = (4, 4);
+/// too far from station A to walk between them, so the walking network is disconnected
+const STATION_C: (u64, u64) = (12, 8);
+const STATION_B: (u64, u64) = (28, 4);
+
+/// 400m from station A and 800m from station C
+const HOUSING_1: (u64, u64) = (4, 8);
+/// 800m from station A and 400m from station C
+const HOUSING_2: (u64, u64) = (12, 4);
+/// 1000m from both stations A and C
+const HOUSING_3: (u64, u64) = (4, 14);
+/// 200m from station B
+const WORKPLACE: (u64, u64) = (28, 6);
+
+fn address(engine: &Engine, (x, y): (u64, u64)) -> quadtree::Address {
+    engine.state.qtree.get_address(x, y).unwrap()
+}
+
+fn add_housing(engine: &mut Engine, location: (u64, u64), people: usize) {
+    let housing = address(engine, location);
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: people,
+        agents: vec![],
+    }
+    .into();
+    for _ in 0..people {
+        let data = AgentDataDistribution::default().sample(&mut engine.rng);
+        engine.add_agent(data, housing, None).unwrap();
+    }
+}
+
+/// Generate a map with a single metro line through three stations, with people and jobs nearby.
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    add_housing(&mut engine, HOUSING_1, 2);
+    add_housing(&mut engine, HOUSING_2, 1);
+    add_housing(&mut engine, HOUSING_3, 3);
+    let workplace = address(&engine, WORKPLACE);
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 5,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let mut add_station = |(x, y): (u64, u64)| {
+        let address = address(&engine, (x, y));
+        engine.state.railways.add_junction(
+            (x as f64, y as f64),
+            metro::RailwayJunction::new(Some(metro::Station {
+                name: format!("{}, {}", x, y),
+                address,
+            })),
+        )
+    };
+    let a = add_station(STATION_A);
+    let c = add_station(STATION_C);
+    let b = add_station(STATION_B);
+
+    let mut add_segment = |start, end, (x1, y1): (u64, u64), (x2, y2): (u64, u64)| {
+        engine.state.railways.add_segment(
+            metro::RailwaySegment::new(None),
+            start,
+            end,
+            Some(vec![
+                (x1 as f64, y1 as f64).into(),
+                (x2 as f64, y2 as f64).into(),
+            ]),
+        )
+    };
+    let segments = vec![
+        add_segment(a, c, STATION_A, STATION_C),
+        add_segment(c, b, STATION_C, STATION_B),
+    ];
+    engine.state.metros.add_metro_line(
+        metro::MetroLineData {
+            color: (255, 0, 0).into(),
+            name: "Red".to_string(),
+            schedule: metro::Schedule::fixed_frequency(300),
+            speed_limit: 20,
+        },
+        segments,
+        &engine.state.railways,
+    );
+
+    engine.update_fields().unwrap();
+    engine
+}
+
+/// (people, jobs, unique people, unique jobs)
+fn catchment(
+    engine: &Engine,
+    catchments: &engine::StationCatchments,
+    station: (u64, u64),
+) -> (usize, usize, usize, usize) {
+    let catchment = catchments.get(address(engine, station)).unwrap();
+    (
+        catchment.people,
+        catchment.jobs,
+        catchment.unique_people,
+        catchment.unique_jobs,
+    )
+}
+
+#[test]
+fn catchment_test() {
+    let mut engine = generate_map();
+
+    let catchments = engine.station_catchments(10.0).unwrap();
+    assert_eq!(catchments.stations.len(), 3);
+    // both stations reach the first two houses, but each house only counts once for the closest
+    assert_eq!(catchment(&engine, &catchments, STATION_A), (3, 0, 2, 0));
+    assert_eq!(catchment(&engine, &catchments, STATION_C), (3, 0, 1, 0));
+    assert_eq!(catchment(&engine, &catchments, STATION_B), (0, 5, 0, 5));
+    assert_eq!(catchments.max_people(), 3);
+
+    // 5 minutes is 450m, so each station only reaches the closer house
+    let catchments = engine.station_catchments(5.0).unwrap();
+    assert_eq!(catchment(&engine, &catchments, STATION_A), (2, 0, 2, 0));
+    assert_eq!(catchment(&engine, &catchments, STATION_C), (1, 0, 1, 0));
+    assert_eq!(catchment(&engine, &catchments, STATION_B), (0, 5, 0, 5));
+
+    // 15 minutes is 1350m, which reaches the last house as well
+    let catchments = engine.station_catchments(15.0).unwrap();
+    assert_eq!(catchment(&engine, &catchments, STATION_A), (6, 0, 5, 0));
+    assert_eq!(catchment(&engine, &catchments, STATION_C), (6, 0, 1, 0));
+
+    let csv = catchments.to_csv();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "station,x,y,people,jobs,unique_people,unique_jobs"
+    );
+    assert_eq!(lines[1], "\"4, 4\",4,4,6,0,5,0");
+    assert_eq!(lines.len(), 4);
+}
+
+#[test]
+fn catchment_cache_test() {
+    let mut engine = generate_map();
+
+    let first = engine.station_catchments(10.0).unwrap();
+    let second = engine.station_catchments(10.0).unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+
+    // a new house 200m from station A only shows up once the fields are recomputed
+    add_housing(&mut engine, (4, 6), 1);
+    let stale = engine.station_catchments(10.0).unwrap();
+    assert!(Arc::ptr_eq(&first, &stale));
+    engine.update_fields().unwrap();
+    let updated = engine.station_catchments(10.0).unwrap();
+    assert!(!Arc::ptr_eq(&first, &updated));
+    assert_eq!(catchment(&engine, &updated, STATION_A), (4, 0, 3, 0));
+
+    // so are changes to the network
+    engine.base_graph.write().unwrap().clear();
+    let rebuilt = engine.station_catchments(10.0).unwrap();
+    assert!(!Arc::ptr_eq(&updated, &rebuilt));
+    assert_eq!(rebuilt.stations, updated.stations);
+}
@@ -0,0 +1,201 @@
+use engine::{ChangeKind, ChangeSetHandle, Engine};
+use test_support::{split_all, test_config, uses_segments};
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 50;
+
+fn ramp(i: usize, len: usize) -> Option<highway::RampDirection> {
+    if i == 0 {
+        Some(highway::RampDirection::OnRamp)
+    } else if i == len - 1 {
+        Some(highway::RampDirection::OffRamp)
+    } else {
+        None
+    }
+}
+
+fn segment_data() -> highway::HighwaySegment {
+    highway::HighwaySegment::new(None, vec![], None, Some(40))
+}
+
+/// Generate a map with housing and a workplace at opposite ends, connected by a long detour.
+fn generate_map() -> (Engine, route::QueryInput) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+
+    split_all(&mut engine);
+
+    let housing = engine.state.qtree.get_address(2, 2).unwrap();
+    let workplace = engine.state.qtree.get_address(61, 2).unwrap();
+
+    let points = [(3.0, 5.0), (32.0, 40.0), (60.0, 5.0)];
+    let junctions: Vec<_> = points
+        .iter()
+        .enumerate()
+        .map(|(i, point)| {
+            engine
+                .state
+                .highways
+                .add_junction(*point, highway::HighwayJunction::new(ramp(i, points.len())))
+        })
+        .collect();
+    for i in 0..points.len() - 1 {
+        engine.state.highways.add_segment(
+            segment_data(),
+            junctions[i],
+            junctions[i + 1],
+            Some(vec![points[i].into(), points[i + 1].into()]),
+        );
+    }
+
+    let commute = route::QueryInput {
+        start: housing,
+        end: workplace,
+        car_config: Some(route::CarConfig::StartWithCar),
+        profile: route::MobilityProfile::STANDARD,
+        allowed_modes: Default::default(),
+    };
+
+    (engine, commute)
+}
+
+/// Stage a direct highway between housing and the workplace, returning the staged segments.
+fn stage_direct_highway(
+    engine: &mut Engine,
+    handle: ChangeSetHandle,
+) -> Vec<network::SegmentHandle> {
+    let points = [(3.0, 3.0), (32.0, 3.0), (60.0, 3.0)];
+    let junctions: Vec<_> = points
+        .iter()
+        .enumerate()
+        .map(|(i, point)| {
+            engine
+                .staged_add_highway_junction(
+                    handle,
+                    *point,
+                    highway::HighwayJunction::new(ramp(i, points.len())),
+                )
+                .unwrap()
+        })
+        .collect();
+    (0..points.len() - 1)
+        .map(|i| {
+            engine
+                .staged_add_highway_segment(
+                    handle,
+                    segment_data(),
+                    junctions[i],
+                    junctions[i + 1],
+                    Some(vec![points[i].into(), points[i + 1].into()]),
+                )
+                .unwrap()
+        })
+        .collect()
+}
+
+fn graph_version(engine: &Engine) -> u64 {
+    engine.base_graph.read().unwrap().version()
+}
+
+#[test]
+fn discard_change_set_test() {
+    let (mut engine, commute) = generate_map();
+    let before = engine.query_route(commute).unwrap().unwrap();
+    let junctions = engine.state.highways.junctions().len();
+    let segments = engine.state.highways.segments().len();
+    let version = graph_version(&engine);
+
+    let handle = engine.begin_change_set().unwrap();
+    let staged = stage_direct_highway(&mut engine, handle);
+    assert_eq!(staged.len(), 2);
+
+    // staged segments are visible in the preview, but not to routing
+    let preview = engine.preview(handle).unwrap();
+    assert_eq!(preview.changes.len(), 2);
+    assert!(preview
+        .highway_segments
+        .iter()
+        .all(|(_, kind)| *kind == ChangeKind::Add));
+    let during = engine.query_route(commute).unwrap().unwrap();
+    assert!(!uses_segments(&during, &staged));
+    assert_eq!(graph_version(&engine), version);
+
+    engine.discard_change_set(handle).unwrap();
+    assert_eq!(engine.open_change_set(), None);
+    assert!(engine.preview(handle).is_err());
+
+    assert_eq!(engine.state.highways.junctions().len(), junctions);
+    assert_eq!(engine.state.highways.segments().len(), segments);
+    assert!(engine
+        .state
+        .highways
+        .segments()
+        .values()
+        .all(|segment| segment.change_state == network::ChangeState::Active));
+    assert_eq!(graph_version(&engine), version);
+
+    let after = engine.query_route(commute).unwrap().unwrap();
+    assert_eq!(after.edges.len(), before.edges.len());
+    assert_eq!(after.cost, before.cost);
+}
+
+#[test]
+fn commit_change_set_test() {
+    let (mut engine, commute) = generate_map();
+    let before = engine.query_route(commute).unwrap().unwrap();
+    let version = graph_version(&engine);
+
+    let handle = engine.begin_change_set().unwrap();
+    assert!(matches!(
+        engine.begin_change_set(),
+        Err(engine::Error::ChangeSetAlreadyOpen(open)) if open == handle
+    ));
+    let staged = stage_direct_highway(&mut engine, handle);
+    engine.commit_change_set(handle).unwrap();
+    assert_eq!(engine.open_change_set(), None);
+
+    for segment in &staged {
+        assert_eq!(
+            engine.state.highways.segment(*segment).change_state,
+            network::ChangeState::Active
+        );
+    }
+    // the whole change set only invalidates the base graph once
+    assert_eq!(graph_version(&engine), version + 1);
+
+    let after = engine.query_route(commute).unwrap().unwrap();
+    assert!(uses_segments(&after, &staged));
+    assert!(after.cost < before.cost);
+    assert_eq!(graph_version(&engine), version + 1);
+}
+
+#[test]
+fn unstage_test() {
+    let (mut engine, _) = generate_map();
+    let segments = engine.state.highways.segments().len();
+
+    let handle = engine.begin_change_set().unwrap();
+    let staged = stage_direct_highway(&mut engine, handle);
+    let existing = *engine.state.highways.segments().keys().next().unwrap();
+    engine
+        .staged_remove_highway_segment(handle, existing)
+        .unwrap();
+
+    // removing the first staged segment leaves the others alone
+    engine.unstage(handle, 0).unwrap();
+    assert!(engine.state.highways.segments().get(&staged[0]).is_none());
+    assert!(engine.state.highways.segments().get(&staged[1]).is_some());
+
+    let preview = engine.preview(handle).unwrap();
+    assert_eq!(preview.changes.len(), 2);
+    assert_eq!(preview.highway_segments[1].1, ChangeKind::Remove);
+
+    // unstaging a removal restores the segment
+    engine.unstage(handle, 1).unwrap();
+    assert_eq!(
+        engine.state.highways.segment(existing).change_state,
+        network::ChangeState::Active
+    );
+
+    engine.commit_change_set(handle).unwrap();
+    assert_eq!(engine.state.highways.segments().len(), segments + 1);
+}
@@ -0,0 +1,91 @@
+use engine::Engine;
+use test_support::employed_map;
+
+const SEED: u64 = 0;
+/// until 3am on the second day, when everyone should be back home
+const HOURS: u64 = 27;
+
+fn coarse_agents(engine: &Engine) -> usize {
+    engine
+        .agents
+        .values()
+        .filter(|agent| {
+            matches!(
+                &agent.state,
+                agent::AgentState::Route(agent::AgentRouteState {
+                    phase: agent::AgentRoutePhase::InProgress {
+                        chunk_end: Some(_),
+                        ..
+                    },
+                    ..
+                })
+            )
+        })
+        .count()
+}
+
+/// Run a day with the given route detail, checking consistency along the way. Returns the engine
+/// and the most agents that were following coarse routes at once.
+fn run(detail: agent::RouteDetail) -> (Engine, usize) {
+    let mut engine = employed_map(SEED);
+    engine.set_route_detail(Some(detail));
+
+    let mut max_coarse = 0;
+    for _ in 0..HOURS {
+        engine.time_state.skip_by(60 * 60);
+        engine.update(0.0, f64::INFINITY).unwrap();
+        engine.consistency_check().unwrap();
+        max_coarse = max_coarse.max(coarse_agents(&engine));
+    }
+    (engine, max_coarse)
+}
+
+#[test]
+fn coarse_matches_fine_test() {
+    let (fine, fine_coarse) = run(agent::RouteDetail::Fine);
+    let (coarse, coarse_coarse) = run(agent::RouteDetail::Coarse);
+
+    // make sure that each run actually used the detail that it asked for
+    assert_eq!(fine_coarse, 0);
+    assert!(coarse_coarse > 0);
+
+    // coarse routes may put congestion in slightly different places along the way, but once
+    // everyone is home, the world state should be exactly the same
+    assert_eq!(
+        fine.world_state.check_same_traffic(&coarse.world_state),
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        fine.world_state.check_same_parking(&coarse.world_state),
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        fine.world_state.total_parked_cars(),
+        coarse.world_state.total_parked_cars()
+    );
+
+    assert_eq!(fine.agents.len(), coarse.agents.len());
+    for (id, fine_agent) in &fine.agents {
+        let coarse_agent = &coarse.agents[id];
+        match (&fine_agent.state, &coarse_agent.state) {
+            (agent::AgentState::Tile(fine_tile), agent::AgentState::Tile(coarse_tile)) => {
+                assert_eq!(fine_tile, coarse_tile, "agent {} is somewhere else", id);
+                assert_eq!(*fine_tile, fine_agent.housing, "agent {} isn't home", id);
+            }
+            states => panic!("agent {} isn't at a tile: {:?}", id, states),
+        }
+        assert_eq!(fine_agent.parked_car(), coarse_agent.parked_car());
+    }
+}
+
+#[test]
+fn route_detail_follows_playback_rate_test() {
+    let mut engine = employed_map(SEED);
+    assert_eq!(engine.route_detail(), agent::RouteDetail::Fine);
+
+    engine.time_state.playback_rate = 86400;
+    assert_eq!(engine.route_detail(), agent::RouteDetail::Coarse);
+
+    engine.set_route_detail(Some(agent::RouteDetail::Fine));
+    assert_eq!(engine.route_detail(), agent::RouteDetail::Fine);
+}
@@ -0,0 +1,72 @@
+use engine::{AgentDataDistribution, Engine};
+use test_support::{two_highway_map, uses_segments};
+
+/// far more travelers than any of the test highways can handle
+const JAM: f64 = 1_000_000.0;
+
+/// Generate the two highway map, with an agent who commutes between the ends by car.
+fn generate_map() -> (Engine, route::QueryInput, Vec<network::SegmentHandle>) {
+    let (mut engine, commute, direct) = two_highway_map();
+
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    let agent = engine
+        .add_agent(data, commute.start, Some(commute.end))
+        .unwrap();
+    assert!(engine.agents[&agent].owns_car());
+
+    (engine, commute, direct)
+}
+
+#[test]
+fn injected_congestion_reroutes_test() {
+    let (mut engine, commute, direct) = generate_map();
+
+    let route = engine.query_route(commute).unwrap().unwrap();
+    assert!(
+        uses_segments(&route, &direct),
+        "expected the uncongested commute to take the direct highway"
+    );
+
+    for segment in &direct {
+        engine.inject_highway_travelers(*segment, JAM).unwrap();
+    }
+    engine.update_route_weights(0);
+
+    let jammed_route = engine.query_route(commute).unwrap().unwrap();
+    assert!(
+        !uses_segments(&jammed_route, &direct),
+        "expected the commute to avoid the jammed highway"
+    );
+    assert!(jammed_route.cost > route.cost);
+
+    // injected travelers aren't associated with any agent, but are still consistent
+    assert!(engine.consistency_check().is_ok());
+
+    // removing the injected travelers restores the original route
+    for segment in &direct {
+        engine.inject_highway_travelers(*segment, -JAM).unwrap();
+    }
+    engine.update_route_weights(0);
+    assert!(engine.injected_highway_travelers().is_empty());
+
+    let restored_route = engine.query_route(commute).unwrap().unwrap();
+    assert!(uses_segments(&restored_route, &direct));
+}
+
+#[test]
+fn invalid_injection_test() {
+    let (mut engine, _, direct) = generate_map();
+
+    assert!(engine
+        .inject_highway_travelers(direct[0], f64::NAN)
+        .is_err());
+    // can't remove more travelers than there are
+    assert!(engine.inject_highway_travelers(direct[0], -1.0).is_err());
+    assert!(engine.injected_highway_travelers().is_empty());
+
+    engine.state.highways.remove_segment(direct[1]);
+    assert!(matches!(
+        engine.inject_highway_travelers(direct[1], 1.0),
+        Err(engine::Error::InvalidHighwaySegment(segment)) if segment == direct[1]
+    ));
+}
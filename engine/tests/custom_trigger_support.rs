@@ -0,0 +1,55 @@
+//! An example of a trigger defined outside of the engine crate, for use in tests.
+
+use engine::{DynTriggerType, Engine, Error};
+use serde::{Deserialize, Serialize};
+
+pub const COUNTDOWN_TAG: &str = "countdown";
+
+/// Queues itself again `remaining` more times, once every `period` seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Countdown {
+    pub remaining: u32,
+    pub period: u64,
+}
+
+impl Countdown {
+    /// Must be called before loading an engine with countdowns in its trigger queue.
+    pub fn register() {
+        Engine::register_trigger_factory(COUNTDOWN_TAG, countdown_factory);
+    }
+}
+
+fn countdown_factory(payload: &str) -> Result<Box<dyn DynTriggerType>, Error> {
+    Ok(Box::new(serde_json::from_str::<Countdown>(payload)?))
+}
+
+impl DynTriggerType for Countdown {
+    fn tag(&self) -> &'static str {
+        COUNTDOWN_TAG
+    }
+
+    fn execute(self: Box<Self>, engine: &mut Engine, time: u64) -> Result<(), Error> {
+        if self.remaining > 0 {
+            engine.trigger_queue.push_custom(
+                Countdown {
+                    remaining: self.remaining - 1,
+                    period: self.period,
+                },
+                time + self.period,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn payload(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    fn clone_box(&self) -> Box<dyn DynTriggerType> {
+        Box::new(self.clone())
+    }
+
+    fn label(&self) -> String {
+        format!("countdown ({} left)", self.remaining)
+    }
+}
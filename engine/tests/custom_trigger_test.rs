@@ -0,0 +1,114 @@
+use custom_trigger_support::Countdown;
+use engine::{DynTriggerType, Engine, Error, Trigger, TriggerKind};
+use test_support::test_config;
+
+fn new_engine() -> Engine {
+    Engine::new(test_config(3, 100))
+}
+
+fn labels(engine: &Engine) -> Vec<(u64, String)> {
+    engine
+        .peek_triggers(10)
+        .into_iter()
+        .map(|(time, trigger)| match trigger {
+            Trigger::CustomTrigger(custom) => (time, custom.label()),
+            other => (time, format!("{:?}", TriggerKind::from(other))),
+        })
+        .collect()
+}
+
+/// A custom trigger whose factory is never registered.
+#[derive(Debug, Clone)]
+struct NeverRegistered {}
+
+impl DynTriggerType for NeverRegistered {
+    fn tag(&self) -> &'static str {
+        "never_registered"
+    }
+
+    fn execute(self: Box<Self>, _engine: &mut Engine, _time: u64) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn payload(&self) -> Result<String, Error> {
+        Ok(String::new())
+    }
+
+    fn clone_box(&self) -> Box<dyn DynTriggerType> {
+        Box::new(self.clone())
+    }
+}
+
+#[test]
+fn round_trip_test() {
+    Countdown::register();
+
+    let mut engine = new_engine();
+    engine
+        .trigger_queue
+        .push_custom(
+            Countdown {
+                remaining: 2,
+                period: 10,
+            },
+            5,
+        )
+        .unwrap();
+
+    let mut loaded = Engine::load(&engine.dump().unwrap()).unwrap();
+    assert_eq!(loaded.peek_triggers(10), engine.peek_triggers(10));
+    assert_eq!(labels(&loaded), vec![(5, "countdown (2 left)".to_string())]);
+
+    // the loaded trigger still behaves like the original
+    loaded.tick(20).unwrap();
+    assert_eq!(
+        labels(&loaded),
+        vec![(25, "countdown (0 left)".to_string())]
+    );
+    loaded.tick(20).unwrap();
+    assert!(loaded.peek_triggers(10).is_empty());
+}
+
+#[test]
+fn ordering_test() {
+    let mut engine = new_engine();
+    for remaining in [2, 1] {
+        engine
+            .trigger_queue
+            .push_custom(
+                Countdown {
+                    remaining,
+                    period: 10,
+                },
+                5,
+            )
+            .unwrap();
+    }
+
+    // custom triggers at the same time are ordered by tag and then payload
+    assert_eq!(
+        labels(&engine),
+        vec![
+            (5, "countdown (1 left)".to_string()),
+            (5, "countdown (2 left)".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn unregistered_tag_test() {
+    let mut engine = new_engine();
+    engine
+        .trigger_queue
+        .push_custom(NeverRegistered {}, 5)
+        .unwrap();
+
+    let dump = engine.dump().unwrap();
+    let err = Engine::load(&dump).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("no factory registered for custom trigger 'never_registered'"),
+        "unexpected error: {}",
+        err
+    );
+}
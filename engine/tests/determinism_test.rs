@@ -0,0 +1,52 @@
+use std::hash::{Hash, Hasher};
+
+use engine::Engine;
+use test_support::employed_map;
+
+const SEED: u64 = 0;
+const HOURS: u64 = 30;
+
+/// A digest of everything that the simulation changes as it runs.
+fn digest(engine: &Engine) -> u64 {
+    // NOTE: DefaultHasher::new always uses the same keys, unlike the hashers in HashMaps
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    engine.time_state.current_time.hash(&mut hasher);
+    serde_json::to_string(&engine.agents)
+        .unwrap()
+        .hash(&mut hasher);
+    serde_json::to_string(&engine.households)
+        .unwrap()
+        .hash(&mut hasher);
+    engine.world_state_history.content_hash().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn run() -> (u64, usize) {
+    let mut engine = employed_map(SEED);
+    for _ in 0..HOURS {
+        engine.time_state.skip_by(60 * 60);
+        engine.update(0.0, f64::INFINITY).unwrap();
+    }
+    let commuted = engine
+        .agents
+        .values()
+        .filter(|agent| agent.route_lengths.values().any(|length| *length > 0.0))
+        .count();
+    (digest(&engine), commuted)
+}
+
+#[test]
+fn digest_test() {
+    // Each thread picks new random keys for the HashMaps that it creates, so running each
+    // simulation on its own thread makes sure that nothing depends on the hash order.
+    let runs: Vec<(u64, usize)> = (0..3)
+        .map(|_| std::thread::spawn(run).join().unwrap())
+        .collect();
+
+    let (first, commuted) = runs[0];
+    // make sure that the simulation actually did something
+    assert!(commuted > 0);
+    for (digest, _) in &runs[1..] {
+        assert_eq!(*digest, first);
+    }
+}
@@ -0,0 +1,68 @@
+use engine::Engine;
+use test_support::test_config;
+
+/// scheduled by Engine::start_recording, so it only exists with the replay feature
+const REPLAY_TRIGGER: &str = r#"{"type": "RecordAgentKeyframe", "generation": 1}"#;
+
+fn new_engine() -> Engine {
+    Engine::new(test_config(3, 100))
+}
+
+/// A save from a build with the replay feature, made while a recording was in progress.
+fn save_with_replay_trigger() -> String {
+    let mut save: serde_json::Value = serde_json::from_str(&new_engine().dump().unwrap()).unwrap();
+    save["trigger_queue"]["heap"]
+        .as_array_mut()
+        .unwrap()
+        .push(serde_json::json!({
+            "trigger": serde_json::from_str::<serde_json::Value>(REPLAY_TRIGGER).unwrap(),
+            "time": 600,
+        }));
+    save.to_string()
+}
+
+fn replay_enabled() -> bool {
+    serde_json::from_str::<engine::Trigger>(REPLAY_TRIGGER).is_ok()
+}
+
+#[test]
+fn disabled_trigger_test() {
+    let result = Engine::load(&save_with_replay_trigger());
+    if replay_enabled() {
+        let engine = result.unwrap();
+        assert_eq!(engine.trigger_queue.len(), 1);
+    } else {
+        let message = match result {
+            Ok(_) => panic!("loaded a save with a disabled trigger"),
+            Err(err) => err.to_string(),
+        };
+        assert!(
+            message.contains("trigger RecordAgentKeyframe needs the \"replay\" feature"),
+            "{}",
+            message
+        );
+    }
+}
+
+#[test]
+fn unknown_trigger_test() {
+    // triggers that don't exist in any build are still reported as unknown
+    let save = save_with_replay_trigger().replace("RecordAgentKeyframe", "NoSuchTrigger");
+    let message = Engine::load(&save).err().unwrap().to_string();
+    assert!(
+        message.contains("unknown variant `NoSuchTrigger`"),
+        "{}",
+        message
+    );
+}
+
+#[test]
+fn enabled_triggers_test() {
+    // saves without any disabled triggers load the same in every build
+    let mut engine = new_engine();
+    engine.init_trigger_queue();
+    let len = engine.trigger_queue.len();
+    assert!(len > 0);
+    let loaded = Engine::load(&engine.dump().unwrap()).unwrap();
+    assert_eq!(loaded.trigger_queue.len(), len);
+}
@@ -0,0 +1,73 @@
+use engine::{AgentDataDistribution, Engine, Error};
+use rand::SeedableRng;
+use test_support::{split_all, test_config};
+use uom::si::time::{day, hour};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 3;
+
+/// Generate a map where every tile is empty.
+fn generate_map(split: bool) -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    if split {
+        split_all(&mut engine);
+    }
+    engine
+}
+
+#[test]
+fn empty_map_test() {
+    for split in [false, true] {
+        let mut engine = generate_map(split);
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+        engine.init_trigger_queue();
+
+        // nowhere to go, and nobody to go there
+        assert!(matches!(
+            engine.random_commute(&mut rng),
+            Err(Error::NoTiles("housing"))
+        ));
+        let data = AgentDataDistribution::default().sample(&mut rng);
+        let address = engine.state.qtree.get_address(0, 0).unwrap();
+        assert!(matches!(
+            engine.add_agent(data.clone(), address, None),
+            Err(Error::NotAHousingTile(_))
+        ));
+        assert_eq!(
+            engine
+                .populate_housing(1.0, 0, &Default::default())
+                .unwrap(),
+            0
+        );
+        assert_eq!(engine.assign_workplaces(1.0, 0, 1000.0).unwrap(), 0);
+
+        // a week goes by without anything happening
+        let steps = Time::new::<day>(7).value / Time::new::<hour>(1).value;
+        for _ in 0..steps {
+            engine.tick(Time::new::<hour>(1).value).unwrap();
+        }
+        assert!(engine.agents.is_empty());
+        assert!(engine.state.collect_tiles.housing.is_empty());
+        assert!(engine.state.collect_tiles.workplaces.is_empty());
+        assert!(matches!(
+            engine.random_commute(&mut rng),
+            Err(Error::NoTiles("housing"))
+        ));
+
+        // once there is housing but still no workplaces, that is the problem instead
+        engine.state.qtree.get_leaf_mut(address).unwrap().tile = tiles::HousingTile {
+            density: 1,
+            agents: vec![],
+        }
+        .into();
+        assert!(matches!(
+            engine.random_commute(&mut rng),
+            Err(Error::NoTiles("workplace"))
+        ));
+        let agent = engine.add_agent(data, address, None).unwrap();
+        for _ in 0..steps {
+            engine.tick(Time::new::<hour>(1).value).unwrap();
+        }
+        assert!(engine.agents[&agent].workplace.is_none());
+    }
+}
@@ -0,0 +1,101 @@
+use engine::{Engine, FieldsState};
+use state::{BranchState, LeafState};
+use test_support::test_config;
+
+const MAX_DEPTH: u32 = 2;
+
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    engine
+        .split_tile(
+            quadtree::Address::from((vec![], MAX_DEPTH)),
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+    engine
+}
+
+fn split(engine: &mut Engine, address: quadtree::Address) -> Result<(), engine::Error> {
+    engine.split_tile(
+        address,
+        BranchState::default(),
+        quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+    )
+}
+
+#[test]
+fn split_error_test() {
+    let mut engine = generate_map();
+
+    let address = engine.state.qtree.get_address(2, 0).unwrap();
+    split(&mut engine, address).unwrap();
+    let leaf = engine.state.qtree.get_address(3, 1).unwrap();
+    let err = split(&mut engine, leaf).unwrap_err();
+
+    let message = err.to_string();
+    assert!(
+        message.starts_with("failed to split tile at (3, 1): "),
+        "{}",
+        message
+    );
+    assert!(message.contains("Max depth exceeded"), "{}", message);
+    assert!(matches!(
+        err.without_context(),
+        engine::Error::QuadtreeError(quadtree::Error::MaxDepthExceeded(_))
+    ));
+}
+
+#[test]
+fn leaf_error_test() {
+    let mut engine = generate_map();
+
+    let root = quadtree::Address::from((vec![], MAX_DEPTH));
+    let message = engine.get_leaf(root).unwrap_err().to_string();
+    assert!(message.contains("failed to get tile at"), "{}", message);
+    assert!(message.contains("Expected leaf"), "{}", message);
+
+    // the messages use the center of the tile, which spans (0, 0) to (2, 2)
+    let address = engine.state.qtree.get_address(0, 0).unwrap();
+    let err = engine
+        .set_leaf_data(address, "not a leaf", state::SerdeFormat::Toml)
+        .unwrap_err();
+    let message = err.to_string();
+    assert!(
+        message.starts_with("failed to edit tile at (1, 1): "),
+        "{}",
+        message
+    );
+    assert!(message.contains("TOML"), "{}", message);
+    // the leaf is left untouched
+    assert!(matches!(
+        engine.get_leaf(address).unwrap().tile,
+        tiles::Tile::EmptyTile(_)
+    ));
+}
+
+#[test]
+fn load_error_test() {
+    let path = std::path::Path::new("does/not/exist.json");
+    let message = Engine::load_file(path).unwrap_err().to_string();
+    assert!(
+        message.contains("failed to load map from does/not/exist.json"),
+        "{}",
+        message
+    );
+    assert!(message.contains("IO error"), "{}", message);
+}
+
+#[test]
+fn nested_context_test() {
+    let result: Result<(), quadtree::Error> = Err(quadtree::Error::ExpectedBranch());
+    let err = result.context("inner").context("outer").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "outer: inner: Quadtree error: Expected branch, but got leaf"
+    );
+    assert!(matches!(
+        err.without_context(),
+        engine::Error::QuadtreeError(quadtree::Error::ExpectedBranch())
+    ));
+}
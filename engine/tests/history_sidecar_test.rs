@@ -0,0 +1,171 @@
+use engine::Engine;
+use route::WorldState;
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 3;
+
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(state::Config {
+        traffic_history: state::TrafficHistoryConfig {
+            snapshots: 48,
+            save_interval: 4,
+            ..Default::default()
+        },
+        ..test_config(MAX_DEPTH, 100)
+    });
+    split_all(&mut engine);
+    engine
+}
+
+/// Park cars around the map and take a snapshot of the traffic each period.
+fn record_history(engine: &mut Engine, start: u64, count: u64) {
+    let period = engine.world_state_history.snapshot_period();
+    for i in start..start + count {
+        let address = engine.state.qtree.get_address(i % 8, (i * 3) % 8).unwrap();
+        engine.world_state.increment_parking(address).unwrap();
+        engine.time_state.current_time = i * period;
+        engine.record_traffic_snapshot();
+    }
+}
+
+/// Predicted parking across the whole map, at times both on and between snapshots.
+fn predictions(engine: &Engine) -> Vec<f64> {
+    let period = engine.world_state_history.snapshot_period();
+    let mut predictions = Vec::new();
+    for i in 0..engine.world_state_history.num_snapshots() as u64 * 2 {
+        let predictor = engine.world_state_history.get_predictor(i * period / 2);
+        for x in 0..8 {
+            for y in 0..8 {
+                predictions.push(predictor.get_parking(x as f64, y as f64));
+            }
+        }
+    }
+    predictions
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("{}.json", name))
+}
+
+fn cleanup(path: &std::path::Path) {
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(engine::history_path(path));
+}
+
+#[test]
+fn restore_from_sidecar_test() {
+    let path = temp_path("restore_from_sidecar_test");
+    let mut engine = generate_map();
+    record_history(&mut engine, 0, 48);
+    let expected = predictions(&engine);
+    assert!(expected.iter().any(|parking| *parking > 0.0));
+
+    engine.dump_file(&path).unwrap();
+    assert!(engine::history_path(&path).exists());
+
+    let loaded = Engine::load_file(&path).unwrap();
+    cleanup(&path);
+    assert_eq!(loaded.world_state_history.num_snapshots(), 48);
+    assert_eq!(predictions(&loaded), expected);
+}
+
+#[test]
+fn missing_sidecar_test() {
+    let path = temp_path("missing_sidecar_test");
+    let mut engine = generate_map();
+    record_history(&mut engine, 0, 48);
+
+    engine.dump_file(&path).unwrap();
+    std::fs::remove_file(engine::history_path(&path)).unwrap();
+
+    // the rest of the save is still usable, but traffic predictions start over
+    let loaded = Engine::load_file(&path).unwrap();
+    cleanup(&path);
+    assert!(loaded
+        .world_state
+        .check_same_parking(&engine.world_state)
+        .is_empty());
+    assert_eq!(
+        loaded.world_state_history.num_snapshots(),
+        engine.state.config.traffic_history.snapshots
+    );
+    assert!(predictions(&loaded).iter().all(|parking| *parking == 0.0));
+
+    // same when loading without a file at all
+    let loaded = Engine::load(&engine.dump().unwrap()).unwrap();
+    assert!(predictions(&loaded).iter().all(|parking| *parking == 0.0));
+}
+
+#[test]
+fn sidecar_save_interval_test() {
+    let path = temp_path("sidecar_save_interval_test");
+    let mut engine = generate_map();
+    record_history(&mut engine, 0, 8);
+    engine.dump_file(&path).unwrap();
+    let saved = predictions(&engine);
+
+    // not enough new snapshots to rewrite the sidecar, so the save refers to the older history
+    record_history(&mut engine, 8, 3);
+    engine.dump_file(&path).unwrap();
+    assert_eq!(predictions(&Engine::load_file(&path).unwrap()), saved);
+
+    // once enough snapshots have been taken, the sidecar is rewritten
+    record_history(&mut engine, 11, 1);
+    engine.dump_file(&path).unwrap();
+    assert_eq!(
+        predictions(&Engine::load_file(&path).unwrap()),
+        predictions(&engine)
+    );
+
+    // or when asked to explicitly
+    record_history(&mut engine, 12, 1);
+    engine.dump_history_file(&path).unwrap();
+    engine.dump_file(&path).unwrap();
+    assert_eq!(
+        predictions(&Engine::load_file(&path).unwrap()),
+        predictions(&engine)
+    );
+
+    // a sidecar that belongs to a different save is ignored
+    let other_path = temp_path("sidecar_save_interval_test_other");
+    let mut other = generate_map();
+    other.dump_file(&other_path).unwrap();
+    std::fs::copy(
+        engine::history_path(&other_path),
+        engine::history_path(&path),
+    )
+    .unwrap();
+    let loaded = Engine::load_file(&path).unwrap();
+    cleanup(&path);
+    cleanup(&other_path);
+    assert!(predictions(&loaded).iter().all(|parking| *parking == 0.0));
+}
+
+fn save_size(engine: &mut Engine, name: &str) -> usize {
+    let path = temp_path(name);
+    engine.dump_file(&path).unwrap();
+    let save_size = std::fs::metadata(&path).unwrap().len() as usize;
+    cleanup(&path);
+    save_size
+}
+
+#[test]
+fn save_size_test() {
+    let empty_size = save_size(&mut generate_map(), "save_size_test_empty");
+    let mut engine = generate_map();
+    record_history(&mut engine, 0, 48);
+    let save_size = save_size(&mut engine, "save_size_test");
+
+    // compared to keeping the history inline, as saves used to, the save barely grows; the rest of
+    // the save is mostly the map, which doesn't depend on the history
+    let history_size = serde_json::to_string(&engine.world_state_history)
+        .unwrap()
+        .len();
+    assert!(
+        (save_size - empty_size) * 4 < history_size,
+        "empty save: {}, save: {}, history: {}",
+        empty_size,
+        save_size,
+        history_size
+    );
+}
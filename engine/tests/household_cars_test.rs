@@ -0,0 +1,145 @@
+use std::collections::{HashMap, HashSet};
+
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_all, test_config};
+use uom::si::time::{day, minute};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 50;
+
+/// Generate a map with housing and a workplace within walking distance, connected by a highway.
+fn generate_map(density: usize) -> (Engine, quadtree::Address, quadtree::Address) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+
+    split_all(&mut engine);
+
+    let housing = engine.state.qtree.get_address(2, 2).unwrap();
+    let workplace = engine.state.qtree.get_address(14, 2).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let points = [(3.0, 3.0), (13.0, 3.0)];
+    let on_ramp = engine.state.highways.add_junction(
+        points[0],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OnRamp)),
+    );
+    let off_ramp = engine.state.highways.add_junction(
+        points[1],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OffRamp)),
+    );
+    engine.state.highways.add_segment(
+        highway::HighwaySegment::new(None, vec![], None, Some(40)),
+        on_ramp,
+        off_ramp,
+        Some(vec![points[0].into(), points[1].into()]),
+    );
+
+    (engine, housing, workplace)
+}
+
+fn is_driving(edge: &route::Edge) -> bool {
+    match edge {
+        route::Edge::Highway { .. } | route::Edge::HighwayRamp { .. } => true,
+        route::Edge::ModeSegment { mode, .. } => *mode == route::Mode::Driving,
+        _ => false,
+    }
+}
+
+/**
+ * Simulate the given number of days one minute at a time, returning whether each agent drove on
+ * each of their commutes to work, keyed by (agent, day).
+ */
+fn simulate(engine: &mut Engine, days: u64) -> HashMap<(u64, u64), bool> {
+    let mut commutes = HashMap::new();
+    let day_length = Time::new::<day>(1).value;
+    let steps = days * day_length / Time::new::<minute>(1).value;
+    for _ in 0..steps {
+        engine.tick(Time::new::<minute>(1).value).unwrap();
+        let today = engine.time_state.current_time / day_length;
+        for agent in engine.agents.values() {
+            if let agent::AgentState::Route(agent::AgentRouteState {
+                route,
+                route_type: agent::RouteType::CommuteToWork,
+                ..
+            }) = &agent.state
+            {
+                commutes.insert((agent.id, today), route.edges.iter().any(is_driving));
+            }
+        }
+    }
+    commutes
+}
+
+#[test]
+fn car_less_agent_never_drives_test() {
+    let (mut engine, housing, workplace) = generate_map(1);
+
+    let mut data = AgentDataDistribution::default().sample(&mut engine.rng);
+    data.owns_car = false;
+    let agent = engine.add_agent(data, housing, Some(workplace)).unwrap();
+    assert!(!engine.agents[&agent].owns_car());
+    assert!(!engine.has_car_access(agent));
+    engine.init_trigger_queue();
+
+    let commutes = simulate(&mut engine, 2);
+    assert_eq!(commutes.len(), 2, "expected a commute on each day");
+    assert!(commutes.values().all(|drove| !drove));
+    assert_eq!(engine.agents[&agent].parked_car(), None);
+}
+
+#[test]
+fn household_shares_cars_test() {
+    let (mut engine, housing, workplace) = generate_map(3);
+
+    let household = engine.add_household(housing, 2).unwrap();
+    let members: HashSet<_> = (0..3)
+        .map(|_| {
+            let mut data = AgentDataDistribution::default().sample(&mut engine.rng);
+            data.owns_car = false;
+            engine
+                .add_household_agent(household, data, Some(workplace))
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(engine.households[&household].members.len(), 3);
+    assert!(members.iter().all(|member| engine.has_car_access(*member)));
+    engine.init_trigger_queue();
+
+    let commutes = simulate(&mut engine, 2);
+    for today in 0..2 {
+        let drove: Vec<_> = members
+            .iter()
+            .map(|member| commutes[&(*member, today)])
+            .collect();
+        // two members take the household's cars, and the third has to find another way
+        assert_eq!(
+            drove.iter().filter(|drove| **drove).count(),
+            2,
+            "day {}: {:?}",
+            today,
+            drove
+        );
+    }
+
+    // everyone is home by midnight, so the cars are back with the household
+    assert_eq!(engine.households[&household].available_cars(), 2);
+    assert!(members
+        .iter()
+        .all(|member| engine.agents[member].parked_car().is_none()));
+
+    // removing a member leaves the household's cars alone
+    let member = *members.iter().next().unwrap();
+    engine.remove_agent(member).unwrap();
+    assert_eq!(engine.households[&household].members.len(), 2);
+    assert_eq!(engine.households[&household].cars(), 2);
+}
@@ -0,0 +1,127 @@
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_all, test_config};
+use tiles::Industry;
+use uom::si::time::{day, hour};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 3;
+const MIN_TILE_SIZE: u32 = 100;
+
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+    engine
+}
+
+fn set_tile(engine: &mut Engine, (x, y): (u64, u64), tile: tiles::Tile) -> quadtree::Address {
+    let address = engine.state.qtree.get_address(x, y).unwrap();
+    engine.state.qtree.get_leaf_mut(address).unwrap().tile = tile;
+    address
+}
+
+fn workplace(density: usize, industry: Industry) -> tiles::Tile {
+    tiles::WorkplaceTile {
+        density,
+        agents: vec![],
+        industry,
+    }
+    .into()
+}
+
+#[test]
+fn jobs_by_industry_test() {
+    let mut engine = generate_map();
+
+    // two workplaces in the NW quadrant and one in the SE quadrant
+    set_tile(&mut engine, (0, 0), workplace(3, Industry::Retail));
+    set_tile(&mut engine, (1, 1), workplace(2, Industry::Retail));
+    set_tile(&mut engine, (7, 7), workplace(4, Industry::Healthcare));
+
+    engine.update_fields().unwrap();
+
+    let root = &engine
+        .state
+        .qtree
+        .get_root_branch()
+        .unwrap()
+        .fields
+        .employment;
+    assert_eq!(root.jobs.total, 9);
+    assert_eq!(root.jobs_by_industry[Industry::Retail].total, 5);
+    assert_eq!(root.jobs_by_industry[Industry::Healthcare].total, 4);
+    assert_eq!(root.jobs_by_industry[Industry::Office].total, 0);
+    for (_, jobs) in root.jobs_by_industry.iter() {
+        // each industry covers the whole map, so densities are comparable with the total
+        assert_eq!(jobs.area, root.jobs.area);
+    }
+    assert_eq!(
+        root.jobs_by_industry
+            .iter()
+            .map(|(_, jobs)| jobs.total)
+            .sum::<usize>(),
+        root.jobs.total
+    );
+
+    let nw = quadtree::Address::from((vec![quadtree::Quadrant::NW], MAX_DEPTH));
+    let nw = &engine.state.qtree.get_branch(nw).unwrap().fields.employment;
+    assert_eq!(nw.jobs_by_industry[Industry::Retail].total, 5);
+    assert_eq!(nw.jobs_by_industry[Industry::Healthcare].total, 0);
+}
+
+/// Run the simulation until the given time, returning whether each agent is at their workplace.
+fn at_work(engine: &mut Engine, time: u64, agents: &[u64]) -> Vec<bool> {
+    engine
+        .time_state
+        .skip_by(time - engine.time_state.current_time);
+    engine.update(0.0, f64::INFINITY).unwrap();
+    assert_eq!(engine.time_state.current_time, time);
+
+    agents
+        .iter()
+        .map(|id| {
+            let agent = &engine.agents[id];
+            matches!(agent.state, agent::AgentState::Tile(address) if Some(address) == agent.workplace)
+        })
+        .collect()
+}
+
+#[test]
+fn weekend_commute_test() {
+    let mut engine = generate_map();
+
+    let housing = set_tile(
+        &mut engine,
+        (0, 0),
+        tiles::HousingTile {
+            density: 2,
+            agents: vec![],
+        }
+        .into(),
+    );
+    let retail = set_tile(&mut engine, (2, 0), workplace(1, Industry::Retail));
+    let office = set_tile(&mut engine, (0, 2), workplace(1, Industry::Office));
+
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    let retail_worker = engine
+        .add_agent(data.clone(), housing, Some(retail))
+        .unwrap();
+    let office_worker = engine.add_agent(data, housing, Some(office)).unwrap();
+    let agents = [retail_worker, office_worker];
+
+    engine.init_trigger_queue();
+
+    // the simulation starts on Wednesday, January 1st
+    let one_day = Time::new::<day>(1).value;
+    let noon = Time::new::<hour>(12).value;
+
+    // both go to work on Friday
+    assert_eq!(
+        at_work(&mut engine, 2 * one_day + noon, &agents),
+        vec![true, true]
+    );
+    // but only the retail worker goes to work on Saturday
+    assert_eq!(
+        at_work(&mut engine, 3 * one_day + noon, &agents),
+        vec![true, false]
+    );
+}
@@ -0,0 +1,199 @@
+use engine::{AgentDataDistribution, Engine, InsertPolicy};
+use test_support::{split_to_depth, test_config};
+
+const MAX_DEPTH: u32 = 3;
+
+const WATER: (u64, u64) = (0, 0);
+const STATION: (u64, u64) = (2, 0);
+const HOUSING: (u64, u64) = (0, 2);
+const WORKPLACE: (u64, u64) = (2, 2);
+const VACANT_HOUSING: (u64, u64) = (6, 6);
+
+fn address(engine: &Engine, (x, y): (u64, u64)) -> quadtree::Address {
+    engine.state.qtree.get_address(x, y).unwrap()
+}
+
+fn set_tile(engine: &mut Engine, xy: (u64, u64), tile: tiles::Tile) -> quadtree::Address {
+    let address = address(engine, xy);
+    engine.state.qtree.get_leaf_mut(address).unwrap().tile = tile;
+    address
+}
+
+fn tile(engine: &Engine, address: quadtree::Address) -> &tiles::Tile {
+    &engine.state.qtree.get_leaf(address).unwrap().tile
+}
+
+fn workplace() -> tiles::Tile {
+    tiles::WorkplaceTile {
+        density: 1,
+        agents: vec![],
+        industry: Default::default(),
+    }
+    .into()
+}
+
+/**
+ * Generate a map with water, a metro station, an agent that lives and works on the map, and some
+ * vacant housing. Leaves are one level above the maximum depth, so that there is room to split.
+ */
+fn generate_map() -> (Engine, u64) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    split_to_depth(&mut engine, 2);
+
+    set_tile(&mut engine, WATER, tiles::WaterTile {}.into());
+    let station = set_tile(
+        &mut engine,
+        STATION,
+        tiles::MetroStationTile {
+            name: "Station".to_string(),
+            x: STATION.0,
+            y: STATION.1,
+            ids: vec![],
+            parking: true,
+        }
+        .into(),
+    );
+    engine.state.railways.add_junction(
+        (STATION.0 as f64, STATION.1 as f64),
+        metro::RailwayJunction::new(Some(metro::Station {
+            name: "Station".to_string(),
+            address: station,
+        })),
+    );
+    let housing = set_tile(
+        &mut engine,
+        HOUSING,
+        tiles::HousingTile {
+            density: 1,
+            agents: vec![],
+        }
+        .into(),
+    );
+    let workplace = set_tile(&mut engine, WORKPLACE, workplace());
+    set_tile(
+        &mut engine,
+        VACANT_HOUSING,
+        tiles::HousingTile {
+            density: 1,
+            agents: vec![],
+        }
+        .into(),
+    );
+
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    let id = engine.add_agent(data, housing, Some(workplace)).unwrap();
+
+    (engine, id)
+}
+
+#[test]
+fn reject_test() {
+    let (mut engine, _) = generate_map();
+    let before = engine.state.qtree.clone();
+
+    let water = address(&engine, WATER);
+    let result = engine.insert_tile(water, workplace(), InsertPolicy::Preserve);
+    assert!(matches!(result, Err(engine::Error::TargetIsWater(a)) if a == water));
+
+    let station = address(&engine, STATION);
+    let result = engine.insert_tile(station, workplace(), InsertPolicy::Preserve);
+    assert!(matches!(result, Err(engine::Error::TargetIsStation(a)) if a == station));
+
+    let housing = address(&engine, HOUSING);
+    let result = engine.insert_tile(housing, workplace(), InsertPolicy::Preserve);
+    assert!(matches!(
+        result,
+        Err(engine::Error::TargetOccupied { address, kind: "housing" }) if address == housing
+    ));
+
+    let workplace_address = address(&engine, WORKPLACE);
+    let result = engine.insert_tile(workplace_address, workplace(), InsertPolicy::Preserve);
+    assert!(matches!(
+        result,
+        Err(engine::Error::TargetOccupied { address, kind: "workplace" })
+            if address == workplace_address
+    ));
+    assert!(result.unwrap_err().is_insert_rejection());
+
+    // nothing was touched
+    assert_eq!(format!("{:?}", engine.state.qtree), format!("{:?}", before));
+}
+
+#[test]
+fn station_overwrite_test() {
+    let (mut engine, _) = generate_map();
+
+    // not even when asked to overwrite
+    let station = address(&engine, STATION);
+    let result = engine.insert_tile(station, workplace(), InsertPolicy::Overwrite);
+    assert!(matches!(result, Err(engine::Error::TargetIsStation(_))));
+    assert!(matches!(
+        tile(&engine, station),
+        tiles::Tile::MetroStationTile(_)
+    ));
+
+    // once the station is gone, the tile can be built on
+    let empty = engine
+        .get_leaf_data(address(&engine, (4, 0)), state::SerdeFormat::Json)
+        .unwrap();
+    engine
+        .set_leaf_data(station, &empty, state::SerdeFormat::Json)
+        .unwrap();
+    let (existing, new) = engine
+        .insert_tile(station, workplace(), InsertPolicy::Preserve)
+        .unwrap();
+    assert_eq!((existing, new), (None, Some(station)));
+}
+
+#[test]
+fn overwrite_test() {
+    let (mut engine, id) = generate_map();
+
+    // water is replaced outright
+    let water = address(&engine, WATER);
+    let (existing, new) = engine
+        .insert_tile(water, workplace(), InsertPolicy::Overwrite)
+        .unwrap();
+    assert_eq!((existing, new), (None, Some(water)));
+    assert!(matches!(
+        tile(&engine, water),
+        tiles::Tile::WorkplaceTile(_)
+    ));
+
+    // occupied housing is split, and the agent moves along with it
+    let housing = address(&engine, HOUSING);
+    let (existing, new) = engine
+        .insert_tile(housing, workplace(), InsertPolicy::Overwrite)
+        .unwrap();
+    let existing = existing.unwrap();
+    assert_ne!(existing, housing);
+    assert!(new.is_some());
+    assert_eq!(engine.agents[&id].housing, existing);
+    assert!(matches!(
+        tile(&engine, existing),
+        tiles::Tile::HousingTile(_)
+    ));
+
+    engine.consistency_check().unwrap();
+}
+
+#[test]
+fn preserve_test() {
+    let (mut engine, _) = generate_map();
+
+    // vacant housing is split rather than replaced
+    let housing = address(&engine, VACANT_HOUSING);
+    let (existing, new) = engine
+        .insert_tile(housing, workplace(), InsertPolicy::Preserve)
+        .unwrap();
+    assert!(matches!(
+        tile(&engine, existing.unwrap()),
+        tiles::Tile::HousingTile(_)
+    ));
+    assert!(matches!(
+        tile(&engine, new.unwrap()),
+        tiles::Tile::WorkplaceTile(_)
+    ));
+
+    engine.consistency_check().unwrap();
+}
@@ -0,0 +1,116 @@
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_to_depth, test_config};
+
+const MAX_DEPTH: u32 = 7;
+const MIN_TILE_SIZE: u32 = 50;
+const BASE_DELAY: f64 = 5.0;
+const DENSITY_DELAY: f64 = 2.0;
+/// the default block size of the local traffic grid is 500 meters, which rounds down to 8 tiles
+const BLOCK_WIDTH: u64 = 8;
+
+fn new_engine(intersection_delay: state::IntersectionDelayConfig) -> Engine {
+    let mut engine = Engine::new(state::Config {
+        intersection_delay,
+        ..test_config(MAX_DEPTH, MIN_TILE_SIZE)
+    });
+    // one leaf per block of the local traffic grid
+    let block_depth = MAX_DEPTH - BLOCK_WIDTH.trailing_zeros();
+    split_to_depth(&mut engine, block_depth);
+    engine
+}
+
+fn delays() -> state::IntersectionDelayConfig {
+    state::IntersectionDelayConfig {
+        base_delay: BASE_DELAY,
+        density_delay: DENSITY_DELAY,
+    }
+}
+
+fn drive(start: (f64, f64), stop: (f64, f64)) -> route::Edge {
+    use cgmath::MetricSpace;
+    route::Edge::ModeSegment {
+        mode: route::Mode::Driving,
+        distance: cgmath::Vector2::from(start).distance(stop.into()) * MIN_TILE_SIZE as f64,
+        start,
+        stop,
+    }
+}
+
+fn components(engine: &Engine, edge: &route::Edge) -> route::CostComponents {
+    edge.cost_components(&engine.world_state, &engine.state, None)
+}
+
+#[test]
+fn crossings_test() {
+    let engine = new_engine(delays());
+    assert_eq!(
+        route::local_traffic::grid_downsample(&engine.state.config) as u64,
+        BLOCK_WIDTH
+    );
+
+    // across ten blocks, compared to the same drive without any delays, and within a single block
+    let across = drive((4.0, 4.0), (84.0, 4.0));
+    let without_delays = components(&new_engine(Default::default()), &across);
+    let across = components(&engine, &across);
+    assert_eq!(across.intersection_delay, 10.0 * BASE_DELAY);
+    assert!((across.total() - without_delays.total() - 10.0 * BASE_DELAY).abs() < 1e-6);
+    let within = components(&engine, &drive((1.0, 4.0), (7.0, 4.0)));
+    assert_eq!(within.intersection_delay, 0.0);
+
+    // the router and the agents see the same delay, including without any traffic
+    let across = drive((4.0, 4.0), (84.0, 4.0));
+    assert_eq!(
+        across.cost(&engine.world_state, &engine.state, Some(0)),
+        across.cost(&engine.world_state, &engine.state, None)
+    );
+    let free_flow = across.base_cost(&engine.state);
+    assert!((free_flow - across.cost(&engine.world_state, &engine.state, None)).abs() < 1e-6);
+
+    // diagonal drives cross blocks in both directions
+    let diagonal = components(&engine, &drive((4.0, 4.0), (28.0, 28.0)));
+    assert_eq!(diagonal.intersection_delay, 6.0 * BASE_DELAY);
+}
+
+#[test]
+fn disabled_test() {
+    let engine = new_engine(Default::default());
+    let across = components(&engine, &drive((4.0, 4.0), (84.0, 4.0)));
+    assert_eq!(across.intersection_delay, 0.0);
+}
+
+#[test]
+fn density_test() {
+    let mut engine = new_engine(delays());
+
+    // fill the third block along the drive with residents
+    let block = engine.state.qtree.get_address(20, 4).unwrap();
+    assert_eq!(block.depth() as u32, 4);
+    engine.state.qtree.get_leaf_mut(block).unwrap().tile = tiles::HousingTile {
+        density: 100,
+        agents: vec![],
+    }
+    .into();
+    const RESIDENTS: usize = 40;
+    for _ in 0..RESIDENTS {
+        let data = AgentDataDistribution::default().sample(&mut engine.rng);
+        engine.add_agent(data, block, None).unwrap();
+    }
+
+    let edge = drive((4.0, 4.0), (84.0, 4.0));
+    let before = components(&engine, &edge).intersection_delay;
+    assert_eq!(before, 10.0 * BASE_DELAY);
+
+    engine.update_fields().unwrap();
+    // 40 residents in a block of 400 by 400 meters
+    let density = RESIDENTS as f64 / 0.16;
+    assert!((engine.state.development.get(2, 0) - density).abs() < 1e-6);
+    assert_eq!(engine.state.development.get(3, 0), 0.0);
+
+    let after = components(&engine, &edge).intersection_delay;
+    assert!(after > before);
+    assert!((after - before - DENSITY_DELAY * density / 1000.0).abs() < 1e-6);
+
+    // leaving the dense block back into the first one doesn't pay for it
+    let back = components(&engine, &drive((20.0, 4.0), (4.0, 4.0)));
+    assert_eq!(back.intersection_delay, 2.0 * BASE_DELAY);
+}
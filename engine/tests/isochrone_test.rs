@@ -0,0 +1,107 @@
+use engine::Engine;
+use route::IsochroneResult;
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 500;
+
+/// the on-ramp is the terminal node closest to the lake
+const ON_RAMP: (u64, u64) = (3, 3);
+const LAKE: (u64, u64) = (6, 7);
+
+/// Generate a map with a single highway from an on-ramp to an off-ramp, and a lake nearby.
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    let lake = engine.state.qtree.get_address(LAKE.0, LAKE.1).unwrap();
+    engine.state.qtree.get_leaf_mut(lake).unwrap().tile = tiles::WaterTile {}.into();
+
+    let points = [(ON_RAMP.0 as f64, ON_RAMP.1 as f64), (20.0, 3.0)];
+    let on_ramp = engine.state.highways.add_junction(
+        points[0],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OnRamp)),
+    );
+    let off_ramp = engine.state.highways.add_junction(
+        points[1],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OffRamp)),
+    );
+    engine.state.highways.add_segment(
+        highway::HighwaySegment::new(None, vec![], None, Some(40)),
+        on_ramp,
+        off_ramp,
+        Some(vec![points[0].into(), points[1].into()]),
+    );
+
+    engine
+}
+
+fn query(engine: &Engine, (x, y): (u64, u64)) -> IsochroneResult<route::IsochroneMap> {
+    let address = engine.state.qtree.get_address(x, y).unwrap();
+    engine
+        .query_isochrone_map(address, route::Mode::Driving, Default::default())
+        .unwrap()
+}
+
+#[test]
+fn focus_on_lake_test() {
+    let engine = generate_map();
+
+    let (nearest, distance) = match query(&engine, LAKE) {
+        IsochroneResult::FocusNotRoutable {
+            nearest, distance, ..
+        } => (nearest, distance),
+        IsochroneResult::Calculated(_) => panic!("expected the lake to be unroutable"),
+    };
+    assert_eq!(nearest.to_xy(), ON_RAMP);
+    // 3 tiles across and 4 tiles down
+    assert_eq!(distance, 5.0 * MIN_TILE_SIZE as f64);
+
+    // falling back to the nearest point is the same as picking it in the first place
+    let fallback = match engine
+        .query_isochrone_map(nearest, route::Mode::Driving, Default::default())
+        .unwrap()
+    {
+        IsochroneResult::Calculated(isochrone_map) => isochrone_map,
+        IsochroneResult::FocusNotRoutable { .. } => panic!("expected the fallback to be routable"),
+    };
+    let direct = match query(&engine, ON_RAMP) {
+        IsochroneResult::Calculated(isochrone_map) => isochrone_map,
+        IsochroneResult::FocusNotRoutable { .. } => panic!("expected the on-ramp to be routable"),
+    };
+
+    assert_eq!(fallback.isochrone.focus, direct.isochrone.focus);
+    assert_eq!(
+        fallback.isochrone.travel_times,
+        direct.isochrone.travel_times
+    );
+    let width = engine.state.config.tile_width() as u64;
+    for x in 0..width {
+        for y in 0..width {
+            assert_eq!(
+                fallback.get_travel_time_sq(x, y),
+                direct.get_travel_time_sq(x, y)
+            );
+        }
+    }
+
+    // the off-ramp is reachable, and is the farthest point on the network
+    let max = direct.isochrone.max_reachable_travel_time().unwrap();
+    assert!(max > 0.0 && max.is_finite());
+}
+
+#[test]
+fn focus_far_from_network_test() {
+    let engine = generate_map();
+
+    // well beyond the distance anyone would drive on local roads
+    match query(&engine, (63, 63)) {
+        IsochroneResult::FocusNotRoutable {
+            nearest, distance, ..
+        } => {
+            assert_eq!(nearest.to_xy(), (20, 3));
+            assert!(distance > route::Mode::Driving.bridge_radius());
+        }
+        IsochroneResult::Calculated(_) => panic!("expected the corner to be unroutable"),
+    }
+}
@@ -0,0 +1,94 @@
+use engine::{Engine, LoadOverrides};
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 3;
+
+/// Generate a map with housing in two opposite corners and a few agents with cars parked at home.
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    split_all(&mut engine);
+
+    for (x, y, agents) in [(0, 0, 2), (7, 7, 1)] {
+        let housing = engine.state.qtree.get_address(x, y).unwrap();
+        engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+            density: 10,
+            agents: vec![],
+        }
+        .into();
+        for _ in 0..agents {
+            let data = agent::AgentData {
+                birthday: chrono::NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+                years_of_education: 12,
+                owns_car: true,
+                walking_speed: 1.0,
+                archetype: 0,
+                noise: Default::default(),
+            };
+            engine.add_agent(data, housing, None).unwrap();
+        }
+    }
+    engine
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("{}.json", name))
+}
+
+fn cleanup(path: &std::path::Path) {
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(engine::history_path(path));
+}
+
+#[test]
+fn coarser_traffic_model_test() {
+    let path = temp_path("coarser_traffic_model_test");
+    let mut engine = generate_map();
+    engine.dump_file(&path).unwrap();
+
+    let overrides = LoadOverrides {
+        max_traffic_history_snapshots: Some(12),
+        min_traffic_grid_block_size: Some(1000),
+    };
+    let loaded = Engine::load_file_with_overrides(&path, &overrides).unwrap();
+    cleanup(&path);
+
+    let config = &loaded.state.config.traffic_history;
+    assert_eq!(config.snapshots, 12);
+    assert_eq!(config.grid_block_size, 1000);
+    assert_eq!(loaded.world_state_history.num_snapshots(), 12);
+    assert!(loaded
+        .world_state_history
+        .matches_config(&loaded.state.config));
+
+    // the parked cars are counted again in the coarser grid
+    assert_eq!(loaded.world_state.local_roads().downsample(), 8);
+    assert_eq!(loaded.world_state.parking().len(), 1);
+    assert_eq!(loaded.world_state.total_parked_cars(), 3);
+    loaded.consistency_check().unwrap();
+}
+
+#[test]
+fn overrides_are_limits_test() {
+    let path = temp_path("overrides_are_limits_test");
+    let mut engine = generate_map();
+    engine.dump_file(&path).unwrap();
+
+    // the map already asks for a coarser model than this
+    let overrides = LoadOverrides {
+        max_traffic_history_snapshots: Some(96),
+        min_traffic_grid_block_size: Some(100),
+    };
+    let loaded = Engine::load_file_with_overrides(&path, &overrides).unwrap();
+    cleanup(&path);
+
+    assert_eq!(
+        loaded.state.config.traffic_history,
+        engine.state.config.traffic_history
+    );
+    assert_eq!(loaded.world_state.parking(), engine.world_state.parking(),);
+    // the saved history still applies, so it is kept
+    assert_eq!(
+        loaded.world_state_history.content_hash(),
+        engine.world_state_history.content_hash()
+    );
+}
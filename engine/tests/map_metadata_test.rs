@@ -0,0 +1,66 @@
+use engine::Engine;
+use test_support::test_config;
+
+fn new_engine() -> Engine {
+    Engine::new(test_config(3, 100))
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("{}.json", name))
+}
+
+fn cleanup(path: &std::path::Path) {
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(engine::history_path(path));
+}
+
+#[test]
+fn round_trip_test() {
+    let mut engine = new_engine();
+    engine.state.metadata = state::MapMetadata {
+        name: String::from("Test map"),
+        author: String::from("Tester"),
+        description: String::from("Just for testing"),
+        georeference: Some(state::Georeference {
+            origin_lat: 40.7128,
+            origin_lon: -74.006,
+            meters_per_tile: 100.0,
+        }),
+        ..Default::default()
+    };
+
+    let loaded = Engine::load(&engine.dump().unwrap()).unwrap();
+    assert_eq!(loaded.state.metadata, engine.state.metadata);
+}
+
+#[test]
+fn legacy_save_test() {
+    let engine = new_engine();
+    let mut save: serde_json::Value = serde_json::from_str(&engine.dump().unwrap()).unwrap();
+    let state = save["state"].as_object_mut().unwrap();
+    assert!(state.remove("metadata").is_some());
+
+    let loaded = Engine::load(&save.to_string()).unwrap();
+    assert_eq!(loaded.state.metadata, state::MapMetadata::default());
+}
+
+#[test]
+fn modified_on_save_test() {
+    let path = temp_path("map_metadata_test_modified");
+    let mut engine = new_engine();
+    assert_eq!(engine.state.metadata.modified, None);
+
+    engine.dump_file(&path).unwrap();
+    let created = engine.state.metadata.created.unwrap();
+    let modified = engine.state.metadata.modified.unwrap();
+    assert!(modified >= created);
+
+    // the saved map knows when it was saved, and saving again keeps the creation time
+    let mut loaded = Engine::load_file(&path).unwrap();
+    assert_eq!(loaded.state.metadata.modified, Some(modified));
+    loaded.dump_file(&path).unwrap();
+    assert_eq!(loaded.state.metadata.created, Some(created));
+    assert!(loaded.state.metadata.modified.unwrap() >= modified);
+
+    cleanup(&path);
+}
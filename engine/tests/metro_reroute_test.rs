@@ -0,0 +1,311 @@
+use engine::Engine;
+use route::WorldState;
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 5;
+const MIN_TILE_SIZE: u32 = 100;
+
+const STATION_A: (u64, u64) = (4, 4);
+const STATION_C: (u64, u64) = (12, 8);
+const STATION_B: (u64, u64) = (28, 4);
+/// not on the line until it is rerouted
+const STATION_D: (u64, u64) = (16, 0);
+
+struct Map {
+    engine: Engine,
+    line: metro::MetroLineHandle,
+    /// A to C, then C to B
+    old_segments: Vec<network::SegmentHandle>,
+    /// A to D, then D to B
+    new_segments: Vec<network::SegmentHandle>,
+}
+
+fn address(engine: &Engine, (x, y): (u64, u64)) -> quadtree::Address {
+    engine.state.qtree.get_address(x, y).unwrap()
+}
+
+/// Generate a map with a metro line from A to B through C, and an unused station D.
+fn generate_map() -> Map {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    let mut add_station = |(x, y): (u64, u64)| {
+        let address = address(&engine, (x, y));
+        engine.state.qtree.get_leaf_mut(address).unwrap().tile = tiles::MetroStationTile {
+            name: format!("{}, {}", x, y),
+            x,
+            y,
+            ids: vec![],
+            parking: true,
+        }
+        .into();
+        engine.state.railways.add_junction(
+            (x as f64, y as f64),
+            metro::RailwayJunction::new(Some(metro::Station {
+                name: format!("{}, {}", x, y),
+                address,
+            })),
+        )
+    };
+    let a = add_station(STATION_A);
+    let c = add_station(STATION_C);
+    let b = add_station(STATION_B);
+    let d = add_station(STATION_D);
+
+    let mut add_segment = |start, end, (x1, y1): (u64, u64), (x2, y2): (u64, u64)| {
+        engine.state.railways.add_segment(
+            metro::RailwaySegment::new(None),
+            start,
+            end,
+            Some(vec![
+                (x1 as f64, y1 as f64).into(),
+                (x2 as f64, y2 as f64).into(),
+            ]),
+        )
+    };
+    let old_segments = vec![
+        add_segment(a, c, STATION_A, STATION_C),
+        add_segment(c, b, STATION_C, STATION_B),
+    ];
+    let new_segments = vec![
+        add_segment(a, d, STATION_A, STATION_D),
+        add_segment(d, b, STATION_D, STATION_B),
+    ];
+    let line = engine.state.metros.add_metro_line(
+        metro::MetroLineData {
+            color: (255, 0, 0).into(),
+            name: "Red".to_string(),
+            schedule: metro::Schedule::fixed_frequency(300),
+            speed_limit: 20,
+        },
+        old_segments.clone(),
+        &engine.state.railways,
+    );
+    for station in [STATION_A, STATION_C, STATION_B] {
+        station_ids_mut(&mut engine, station).push(line.inner());
+    }
+
+    Map {
+        engine,
+        line,
+        old_segments,
+        new_segments,
+    }
+}
+
+fn station_ids_mut(engine: &mut Engine, station: (u64, u64)) -> &mut Vec<u64> {
+    let address = address(engine, station);
+    match &mut engine.state.qtree.get_leaf_mut(address).unwrap().tile {
+        tiles::Tile::MetroStationTile(tile) => &mut tile.ids,
+        tile => panic!("expected a station, found {:?}", tile),
+    }
+}
+
+fn station_ids(engine: &mut Engine, station: (u64, u64)) -> Vec<u64> {
+    station_ids_mut(engine, station).clone()
+}
+
+/// The railway segments that the base graph has metro edges for, sorted.
+fn graph_segments(engine: &Engine) -> Vec<network::SegmentHandle> {
+    let base_graph = engine.base_graph.read().unwrap();
+    let mut segments: Vec<_> = base_graph
+        .get_base_graph(&engine.state)
+        .graph
+        .get_edge_map()
+        .values()
+        .filter_map(|edge| match edge {
+            route::Edge::MetroSegment {
+                oriented_segment, ..
+            } => Some(oriented_segment.segment),
+            _ => None,
+        })
+        .collect();
+    segments.sort();
+    segments.dedup();
+    segments
+}
+
+/// An edge of the base graph that rides along the given railway segment.
+fn metro_edge(engine: &Engine, segment: network::SegmentHandle) -> route::Edge {
+    let base_graph = engine.base_graph.read().unwrap();
+    let edge = base_graph
+        .get_base_graph(&engine.state)
+        .graph
+        .get_edge_map()
+        .values()
+        .find(|edge| {
+            matches!(edge, route::Edge::MetroSegment { oriented_segment, .. }
+                if oriented_segment.segment == segment)
+        })
+        .unwrap()
+        .clone();
+    edge
+}
+
+fn world_state_segments(engine: &Engine) -> Vec<network::SegmentHandle> {
+    let mut segments: Vec<_> = engine.world_state.iter_metro_segments().keys().collect();
+    segments.sort();
+    segments
+}
+
+fn metro_route(engine: &Engine) -> route::Route {
+    let route = engine
+        .query_route(route::QueryInput {
+            start: address(engine, STATION_A),
+            end: address(engine, STATION_B),
+            car_config: None,
+            profile: Default::default(),
+            allowed_modes: route::AllowedModes::transit_only(),
+        })
+        .unwrap()
+        .expect("expected a route");
+    assert!(route
+        .edges
+        .iter()
+        .any(|edge| matches!(edge, route::Edge::MetroSegment { .. })));
+    route
+}
+
+#[test]
+fn reroute_test() {
+    let Map {
+        mut engine,
+        line,
+        old_segments,
+        new_segments,
+    } = generate_map();
+    let (ac, cb) = (old_segments[0], old_segments[1]);
+
+    let mut expected = old_segments.clone();
+    expected.sort();
+    assert_eq!(graph_segments(&engine), expected);
+    let old_route = metro_route(&engine);
+
+    // one rider is still on the way from A to C, and the rider from C to B has gotten off
+    engine
+        .world_state
+        .increment_edge(&metro_edge(&engine, ac))
+        .unwrap();
+    let edge = metro_edge(&engine, cb);
+    engine.world_state.increment_edge(&edge).unwrap();
+    engine.world_state.decrement_edge(&edge).unwrap();
+    assert_eq!(world_state_segments(&engine), expected);
+
+    let diff = engine
+        .reroute_metro_line(line, new_segments.clone())
+        .unwrap();
+    assert_eq!(diff.added, new_segments.iter().copied().collect());
+    assert_eq!(diff.removed, old_segments.iter().copied().collect());
+
+    let mut expected = new_segments.clone();
+    expected.sort();
+    assert_eq!(graph_segments(&engine), expected);
+
+    assert_eq!(station_ids(&mut engine, STATION_A), vec![line.inner()]);
+    assert_eq!(station_ids(&mut engine, STATION_B), vec![line.inner()]);
+    assert_eq!(station_ids(&mut engine, STATION_D), vec![line.inner()]);
+    assert!(station_ids(&mut engine, STATION_C).is_empty());
+
+    // the rider can still get off, but nothing else is kept for the old path
+    assert_eq!(world_state_segments(&engine), vec![ac]);
+    assert_eq!(engine.world_state.get_metro_segment_travelers(ac), 1.0);
+    engine
+        .world_state
+        .decrement_edge(&metro_edge_on(&old_route, ac))
+        .unwrap();
+
+    assert!(engine.route_uses_removed_segments(&old_route));
+    let new_route = metro_route(&engine);
+    assert!(!engine.route_uses_removed_segments(&new_route));
+}
+
+/// The edge of the route that rides along the given railway segment.
+fn metro_edge_on(route: &route::Route, segment: network::SegmentHandle) -> route::Edge {
+    route
+        .edges
+        .iter()
+        .find(|edge| {
+            matches!(edge, route::Edge::MetroSegment { oriented_segment, .. }
+                if oriented_segment.segment == segment)
+        })
+        .unwrap()
+        .clone()
+}
+
+#[test]
+fn invalid_reroute_test() {
+    let Map {
+        mut engine,
+        line,
+        old_segments,
+        new_segments,
+    } = generate_map();
+
+    // A to C doesn't connect to D to B
+    let result = engine.reroute_metro_line(line, vec![old_segments[0], new_segments[1]]);
+    assert!(matches!(
+        result,
+        Err(engine::Error::MetroError(metro::Error::Disconnected(..)))
+    ));
+
+    let mut expected = old_segments.clone();
+    expected.sort();
+    assert_eq!(graph_segments(&engine), expected);
+    assert_eq!(station_ids(&mut engine, STATION_C), vec![line.inner()]);
+    assert!(station_ids(&mut engine, STATION_D).is_empty());
+}
+
+#[test]
+fn staged_reroute_test() {
+    let Map {
+        mut engine,
+        line,
+        old_segments,
+        new_segments,
+    } = generate_map();
+    let old_route = metro_route(&engine);
+
+    let handle = engine.begin_change_set().unwrap();
+    assert!(engine
+        .staged_reroute_metro_line(handle, line, vec![old_segments[0], new_segments[1]])
+        .is_err());
+
+    // a staged shortcut from C straight to D
+    let c = engine
+        .state
+        .railways
+        .segment(old_segments[0])
+        .end_junction();
+    let d = engine
+        .state
+        .railways
+        .segment(new_segments[0])
+        .end_junction();
+    let cd = engine
+        .staged_add_railway_segment(handle, metro::RailwaySegment::new(None), c, d, None)
+        .unwrap();
+    let segments = vec![old_segments[0], cd, new_segments[1]];
+    engine
+        .staged_reroute_metro_line(handle, line, segments.clone())
+        .unwrap();
+    assert_eq!(
+        engine.preview(handle).unwrap().rerouted_metro_lines.len(),
+        1
+    );
+    // the staged segment is in use
+    assert!(engine.unstage(handle, 0).is_err());
+
+    // nothing changes until the change set is committed
+    let mut expected = old_segments.clone();
+    expected.sort();
+    assert_eq!(graph_segments(&engine), expected);
+    assert!(!engine.route_uses_removed_segments(&old_route));
+
+    engine.commit_change_set(handle).unwrap();
+    let mut expected = segments;
+    expected.sort();
+    assert_eq!(graph_segments(&engine), expected);
+    assert_eq!(station_ids(&mut engine, STATION_C), vec![line.inner()]);
+    assert_eq!(station_ids(&mut engine, STATION_D), vec![line.inner()]);
+    assert!(engine.route_uses_removed_segments(&old_route));
+}
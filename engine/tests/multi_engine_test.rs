@@ -0,0 +1,105 @@
+use engine::{AgentDataDistribution, Engine, FieldsState, TriggerKind};
+use state::{BranchState, LeafState};
+use test_support::test_config;
+
+const MAX_DEPTH: u32 = 2;
+
+/// A small map with a single agent, which updates traffic on the simulation thread if requested.
+fn generate_engine(single_thread_traffic: bool) -> (Engine, u64) {
+    let mut engine = Engine::new(state::Config {
+        scheduling: state::SchedulingConfig {
+            single_thread_traffic,
+            ..Default::default()
+        },
+        ..test_config(MAX_DEPTH, 100)
+    });
+
+    let root = quadtree::Address::from((vec![], MAX_DEPTH));
+    engine
+        .state
+        .qtree
+        .split(
+            root,
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+    let housing = root.child(quadtree::Quadrant::NW);
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 1,
+        agents: vec![],
+    }
+    .into();
+
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    let id = engine.add_agent(data, housing, None).unwrap();
+
+    engine.init_trigger_queue();
+    (engine, id)
+}
+
+fn awaiting_traffic(engine: &Engine) -> bool {
+    engine
+        .peek_triggers(engine.trigger_queue.len())
+        .into_iter()
+        .any(|(_, trigger)| TriggerKind::from(trigger) == TriggerKind::UpdateTrafficReceiver)
+}
+
+#[test]
+fn independent_settings_test() {
+    let (mut single, _) = generate_engine(true);
+    let (mut background, _) = generate_engine(false);
+
+    // step the engines in turn, as an experiment harness would
+    single.tick(1).unwrap();
+    background.tick(1).unwrap();
+
+    // the single-threaded engine has its new traffic already, while the other is still waiting
+    assert_eq!(single.graph_version().traffic, 1);
+    assert!(!awaiting_traffic(&single));
+    assert_eq!(background.graph_version().traffic, 0);
+    assert!(awaiting_traffic(&background));
+
+    let deadline = background.state.config.scheduling.traffic_deadline;
+    single.tick(deadline).unwrap();
+    background.tick(deadline).unwrap();
+    assert_eq!(single.graph_version().traffic, 1);
+    assert_eq!(background.graph_version().traffic, 1);
+    assert!(!awaiting_traffic(&background));
+}
+
+#[test]
+fn independent_agent_logs_test() {
+    let (mut first, id) = generate_engine(false);
+    let (mut second, second_id) = generate_engine(false);
+    // agent ids are only unique within an engine
+    assert_eq!(id, second_id);
+
+    first.watch_agent(id, None).unwrap();
+    assert!(first.is_agent_watched(id));
+    assert!(!second.is_agent_watched(id));
+
+    first.tick(1).unwrap();
+    second.tick(1).unwrap();
+    assert!(second.agent_log(id).is_empty());
+
+    // a copy of the engine starts out watching nothing, and doesn't affect the original
+    let copy = first.clone();
+    assert!(!copy.is_agent_watched(id));
+    copy.watch_agent(id, None).unwrap();
+    first.unwatch_agent(id);
+    assert!(copy.is_agent_watched(id));
+    assert!(!first.is_agent_watched(id));
+}
+
+#[test]
+fn independent_base_graphs_test() {
+    let (engine, _) = generate_engine(true);
+    let version = engine.graph_version();
+
+    // clearing the copy's graph, as a change to its networks would, leaves the original's alone
+    let copy = engine.clone();
+    copy.base_graph.write().unwrap().clear();
+    assert_ne!(copy.graph_version().network, version.network);
+    assert_eq!(engine.graph_version(), version);
+}
@@ -0,0 +1,120 @@
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_all, test_config};
+use uom::si::time::{day, minute};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 50;
+
+/// where each of the two agents lives and works
+const COMMUTES: [((u64, u64), (u64, u64)); 2] = [((2, 2), (40, 2)), ((2, 10), (40, 10))];
+
+/// Generate a map with two agents who live at one end of a highway and work at the other end, in
+/// different zones.
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+
+    split_all(&mut engine);
+
+    for (housing, workplace) in COMMUTES {
+        let housing = engine
+            .state
+            .qtree
+            .get_address(housing.0, housing.1)
+            .unwrap();
+        let workplace = engine
+            .state
+            .qtree
+            .get_address(workplace.0, workplace.1)
+            .unwrap();
+        engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+            density: 1,
+            agents: vec![],
+        }
+        .into();
+        engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+            density: 1,
+            agents: vec![],
+            industry: tiles::Industry::Office,
+        }
+        .into();
+
+        let data = AgentDataDistribution::default().sample(&mut engine.rng);
+        engine.add_agent(data, housing, Some(workplace)).unwrap();
+    }
+
+    let points = [(3.0, 6.0), (39.0, 6.0)];
+    let on_ramp = engine.state.highways.add_junction(
+        points[0],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OnRamp)),
+    );
+    let off_ramp = engine.state.highways.add_junction(
+        points[1],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OffRamp)),
+    );
+    engine.state.highways.add_segment(
+        highway::HighwaySegment::new(None, vec![], None, Some(40)),
+        on_ramp,
+        off_ramp,
+        Some(vec![points[0].into(), points[1].into()]),
+    );
+
+    engine.init_trigger_queue();
+    engine
+}
+
+fn simulate(engine: &mut Engine, days: u64) {
+    let steps = days * Time::new::<day>(1).value / Time::new::<minute>(1).value;
+    for _ in 0..steps {
+        engine.tick(Time::new::<minute>(1).value).unwrap();
+    }
+}
+
+#[test]
+fn two_commutes_test() {
+    let mut engine = generate_map();
+    assert!(engine.od_matrix().is_none());
+    simulate(&mut engine, 1);
+
+    let matrix = engine.od_matrix().expect("no commutes yesterday");
+    assert_eq!(matrix.day, 0);
+    assert_eq!(matrix.zone_size, engine::od_zone_size(&engine.state.config));
+
+    // each agent lives and works in a different zone from the other
+    assert_eq!(matrix.len(), 2, "{:?}", matrix);
+    assert_eq!(matrix.total_trips(), 2);
+    for (housing, workplace) in COMMUTES {
+        let housing = engine
+            .state
+            .qtree
+            .get_address(housing.0, housing.1)
+            .unwrap();
+        let workplace = engine
+            .state
+            .qtree
+            .get_address(workplace.0, workplace.1)
+            .unwrap();
+        let (origin, destination) = (matrix.zone(housing), matrix.zone(workplace));
+        assert_ne!(origin, destination);
+        assert_eq!(matrix.get(origin, destination), 1);
+        // commutes home aren't counted separately
+        assert_eq!(matrix.get(destination, origin), 0);
+    }
+    assert_eq!(matrix.top_flows(10).len(), 2);
+
+    // nobody has made it to work yet today
+    assert!(engine.od_matrix_today().is_none());
+
+    // one row per pair of zones, plus the header
+    let path = std::env::temp_dir().join(format!("od_matrix_{}.csv", std::process::id()));
+    engine.export_od_matrix_csv(&path).unwrap();
+    let csv = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(csv.lines().count(), 3);
+
+    // the next day starts from scratch
+    simulate(&mut engine, 1);
+    let matrix = engine.od_matrix().expect("no commutes yesterday");
+    assert_eq!(matrix.day, 1);
+    assert_eq!(matrix.total_trips(), 2);
+}
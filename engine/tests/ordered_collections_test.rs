@@ -0,0 +1,59 @@
+/**
+ * Modules that drive the simulation, and so shouldn't use hashed collections at all, since their
+ * iteration order isn't deterministic. See the note on Engine::agents.
+ */
+const ORDERED_MODULES: [(&str, &str); 5] = [
+    ("behavior.rs", include_str!("../behavior.rs")),
+    ("populate.rs", include_str!("../populate.rs")),
+    ("agent/agent.rs", include_str!("../agent/agent.rs")),
+    (
+        "network/change_state.rs",
+        include_str!("../network/change_state.rs"),
+    ),
+    ("network/network.rs", include_str!("../network/network.rs")),
+];
+
+/// Declarations in modules that still use hashed collections for lookups.
+const ORDERED_DECLARATIONS: [(&str, &str, &str); 3] = [
+    (
+        "engine.rs",
+        include_str!("../engine.rs"),
+        "pub agents: BTreeMap<u64, agent::Agent>,",
+    ),
+    (
+        "engine.rs",
+        include_str!("../engine.rs"),
+        "pub households: BTreeMap<u64, agent::Household>,",
+    ),
+    (
+        "metro/metros.rs",
+        include_str!("../metro/metros.rs"),
+        "railway_segment_metro_lines: BTreeMap<network::SegmentHandle, BTreeSet<MetroLineHandle>>,",
+    ),
+];
+
+#[test]
+fn ordered_modules_test() {
+    for (name, source) in ORDERED_MODULES {
+        for hashed in ["HashMap", "HashSet"] {
+            assert!(
+                !source.contains(hashed),
+                "{} uses a {}, but iteration order in it can affect the simulation",
+                name,
+                hashed
+            );
+        }
+    }
+}
+
+#[test]
+fn ordered_declarations_test() {
+    for (name, source, declaration) in ORDERED_DECLARATIONS {
+        assert!(
+            source.contains(declaration),
+            "expected {} to contain `{}`",
+            name,
+            declaration
+        );
+    }
+}
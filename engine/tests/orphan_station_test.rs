@@ -0,0 +1,168 @@
+use engine::{Engine, ORPHAN_STATION_ADOPTION_RADIUS};
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 3;
+
+fn add_station_tile(engine: &mut Engine, name: &str, (x, y): (u64, u64)) -> metro::Station {
+    let address = engine.state.qtree.get_address(x, y).unwrap();
+    engine.state.qtree.get_leaf_mut(address).unwrap().tile = tiles::MetroStationTile {
+        name: name.to_string(),
+        x,
+        y,
+        ids: vec![],
+        parking: true,
+    }
+    .into();
+    metro::Station {
+        name: name.to_string(),
+        address,
+    }
+}
+
+fn station_ids(engine: &Engine, station: &metro::Station) -> Vec<u64> {
+    match &engine.state.qtree.get_leaf(station.address).unwrap().tile {
+        tiles::Tile::MetroStationTile(tile) => tile.ids.clone(),
+        tile => panic!("not a station: {:?}", tile),
+    }
+}
+
+/**
+ * A metro line running from A to C, with orphan station B just beside it and orphan station D
+ * far away from it.
+ */
+fn generate_map() -> (Engine, metro::MetroLineHandle, [metro::Station; 4]) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    split_all(&mut engine);
+
+    let a = add_station_tile(&mut engine, "A", (0, 4));
+    let b = add_station_tile(&mut engine, "B", (3, 5));
+    let c = add_station_tile(&mut engine, "C", (7, 4));
+    let d = add_station_tile(&mut engine, "D", (3, 0));
+
+    let railways = &mut engine.state.railways;
+    let start = railways.add_junction((0.0, 4.0), metro::RailwayJunction::new(Some(a.clone())));
+    let end = railways.add_junction((7.0, 4.0), metro::RailwayJunction::new(Some(c.clone())));
+    let segment = railways.add_segment(
+        metro::RailwaySegment::new(None),
+        start,
+        end,
+        Some(vec![(0.0, 4.0).into(), (7.0, 4.0).into()]),
+    );
+    let line = engine.state.metros.add_metro_line(
+        metro::MetroLineData {
+            color: (255, 0, 0).into(),
+            name: String::from("AC"),
+            schedule: metro::Schedule::fixed_frequency(300),
+            speed_limit: 20,
+        },
+        vec![segment],
+        &engine.state.railways,
+    );
+    for station in [&a, &c] {
+        if let tiles::Tile::MetroStationTile(tile) = &mut engine
+            .state
+            .qtree
+            .get_leaf_mut(station.address)
+            .unwrap()
+            .tile
+        {
+            tile.ids.push(line.inner());
+        }
+    }
+
+    (engine, line, [a, b, c, d])
+}
+
+fn orphan_names(engine: &Engine) -> Vec<String> {
+    let mut names: Vec<_> = engine
+        .orphan_stations()
+        .unwrap()
+        .into_iter()
+        .map(|orphan| orphan.station.name)
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn station_index_test() {
+    let (engine, _, _) = generate_map();
+    assert_eq!(orphan_names(&engine), vec!["B", "D"]);
+
+    let index: Vec<_> = engine
+        .station_index()
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.station.name, entry.unserved))
+        .collect();
+    assert_eq!(
+        index,
+        vec![
+            ("A".to_string(), false),
+            ("B".to_string(), true),
+            ("C".to_string(), false),
+            ("D".to_string(), true),
+        ]
+    );
+}
+
+#[test]
+fn adopt_test() {
+    let (mut engine, line, [a, b, c, _]) = generate_map();
+    let segments: Vec<_> = engine
+        .state
+        .metros
+        .metro_line(line)
+        .segments()
+        .iter()
+        .map(|oriented| oriented.segment)
+        .collect();
+
+    // B is 100 meters from the line, and D is too far away
+    let adoptable = engine
+        .adoptable_stations(&segments, ORPHAN_STATION_ADOPTION_RADIUS)
+        .unwrap();
+    assert_eq!(adoptable.len(), 1);
+    assert_eq!(adoptable[0].orphan.station, b);
+    assert_eq!(adoptable[0].segment, segments[0]);
+    assert!((adoptable[0].distance - 1.0).abs() < 1e-9);
+
+    let version = engine.graph_version();
+    let junction = engine
+        .adopt_orphan_station(adoptable[0].segment, b.address)
+        .unwrap();
+    assert_ne!(engine.graph_version(), version);
+
+    let metro_line = engine.state.metros.metro_line(line);
+    assert!(metro_line
+        .junctions(&engine.state.railways)
+        .any(|other| other == junction));
+    let stations: Vec<_> = metro_line.stations(&engine.state.railways).collect();
+    assert_eq!(stations, vec![&a, &b, &c]);
+    assert_eq!(station_ids(&engine, &b), vec![line.inner()]);
+    assert_eq!(engine.state.metros.station_by_name("B"), Some(&b));
+    assert_eq!(orphan_names(&engine), vec!["D"]);
+
+    // the station sits on the line, right next to the tile
+    let location = engine.state.railways.junction(junction).location;
+    assert_eq!((location.x, location.y), (3.0, 4.0));
+}
+
+#[test]
+fn adopt_invalid_test() {
+    let (mut engine, line, [a, _, _, d]) = generate_map();
+    let segment = engine.state.metros.metro_line(line).segments()[0].segment;
+
+    // A is already served
+    assert!(matches!(
+        engine.adopt_orphan_station(segment, a.address),
+        Err(engine::Error::NotAnOrphanStation(address)) if address == a.address
+    ));
+
+    // stations beyond the radius can still be adopted on purpose
+    engine.adopt_orphan_station(segment, d.address).unwrap();
+    assert_eq!(station_ids(&engine, &d), vec![line.inner()]);
+
+    // the old segment has been replaced by the halves on either side of D
+    assert!(engine.adopt_orphan_station(segment, d.address).is_err());
+}
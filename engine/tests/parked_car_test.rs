@@ -0,0 +1,258 @@
+use engine::{AgentDataDistribution, Engine};
+use test_support::{highway_commute_map, parking_address, test_config};
+use uom::si::time::hour;
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 50;
+
+/// Where along a drive-park-walk commute the route is aborted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AbortPoint {
+    /// just after taking the car out
+    Unparked,
+    /// on the highway
+    Driving,
+    /// just after parking
+    Parked,
+    /// partway along the walk from the car to work
+    Walking,
+}
+
+const ABORT_POINTS: [AbortPoint; 4] = [
+    AbortPoint::Unparked,
+    AbortPoint::Driving,
+    AbortPoint::Parked,
+    AbortPoint::Walking,
+];
+
+/// How the route is aborted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AbortMethod {
+    /// as when the agent needs to be somewhere else before finishing the route
+    Abort,
+    /// as when the agent has been on the route for too long
+    RecoverStuck,
+}
+
+/// Add an agent with their own car, and have them start commuting by car.
+fn start_commute(
+    engine: &mut Engine,
+    housing: quadtree::Address,
+    workplace: quadtree::Address,
+) -> (u64, route::Route) {
+    let mut data = AgentDataDistribution::default().sample(&mut engine.rng);
+    data.owns_car = true;
+    let id = engine.add_agent(data, housing, Some(workplace)).unwrap();
+    engine.consistency_check().unwrap();
+
+    let route = engine
+        .query_route(route::QueryInput {
+            start: housing,
+            end: workplace,
+            car_config: Some(route::CarConfig::StartWithCar),
+            profile: Default::default(),
+            allowed_modes: Default::default(),
+        })
+        .unwrap()
+        .expect("expected a route");
+
+    engine
+        .agents
+        .get_mut(&id)
+        .unwrap()
+        .begin_route(
+            route.clone(),
+            0,
+            agent::RouteType::CommuteToWork,
+            agent::RouteDetail::Fine,
+            &mut engine.world_state,
+            &engine.state,
+        )
+        .unwrap();
+    (id, route)
+}
+
+/// Advance the agent's route until it reaches the abort point.
+fn advance_to(engine: &mut Engine, id: u64, point: AbortPoint) {
+    let agent = engine.agents.get_mut(&id).unwrap();
+    loop {
+        let route_state = match &mut agent.state {
+            agent::AgentState::Route(route_state) => route_state,
+            _ => panic!("expected the agent to be following a route"),
+        };
+        let next_trigger = route_state.next_trigger().expect("never reached the point");
+        let (current_edge, current_mode) = match route_state.phase {
+            agent::AgentRoutePhase::InProgress {
+                current_edge,
+                current_mode,
+                ..
+            } => (current_edge as usize, current_mode),
+            agent::AgentRoutePhase::Finished { .. } => panic!("never reached {:?}", point),
+        };
+        let reached = match point {
+            AbortPoint::Unparked => true,
+            AbortPoint::Driving => {
+                matches!(
+                    route_state.route.edges[current_edge],
+                    route::Edge::Highway { .. }
+                ) && current_mode == route::Mode::Driving
+            }
+            AbortPoint::Parked => route_state.parked_car.is_some(),
+            AbortPoint::Walking => {
+                route_state.parked_car.is_some()
+                    && current_edge + 1 == route_state.route.edges.len()
+            }
+        };
+        if reached {
+            engine.time_state.current_time = next_trigger.saturating_sub(10);
+            break;
+        }
+        engine.time_state.current_time = next_trigger;
+        route_state
+            .advance(&mut engine.world_state, &engine.state)
+            .unwrap();
+    }
+    engine.consistency_check().unwrap();
+}
+
+fn abort(engine: &mut Engine, id: u64, method: AbortMethod) {
+    match method {
+        AbortMethod::Abort => {
+            engine
+                .agents
+                .get_mut(&id)
+                .unwrap()
+                .abort_route(&mut engine.world_state)
+                .unwrap();
+        }
+        AbortMethod::RecoverStuck => {
+            engine.time_state.current_time += Time::new::<hour>(24).value;
+            assert!(engine.recover_stuck_agent(id).unwrap());
+        }
+    }
+}
+
+#[test]
+fn abort_matrix_test() {
+    for point in ABORT_POINTS {
+        for method in [AbortMethod::Abort, AbortMethod::RecoverStuck] {
+            let (mut engine, _, housing, workplace) =
+                highway_commute_map(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+            let (id, route) = start_commute(&mut engine, housing, workplace);
+            let parking = parking_address(&route);
+            assert_ne!(parking, housing);
+
+            advance_to(&mut engine, id, point);
+            abort(&mut engine, id, method);
+
+            let expected = match (point, method) {
+                // teleporting home brings the car along
+                (_, AbortMethod::RecoverStuck) => housing,
+                // the car was out on the road, so it is parked back at home
+                (AbortPoint::Unparked | AbortPoint::Driving, AbortMethod::Abort) => housing,
+                // the car stays where it was parked
+                (AbortPoint::Parked | AbortPoint::Walking, AbortMethod::Abort) => parking,
+            };
+            let context = format!("{:?}, {:?}", point, method);
+            assert_eq!(
+                engine.agents[&id].parked_car(),
+                Some(expected),
+                "{}",
+                context
+            );
+            assert_eq!(
+                engine.world_state.parked_cars_at(expected),
+                1,
+                "{}",
+                context
+            );
+            assert_eq!(engine.world_state.total_parked_cars(), 1, "{}", context);
+            if let Err(err) = engine.consistency_check() {
+                panic!("{}: {}", context, err);
+            }
+            assert_eq!(engine.repair_parked_cars().unwrap(), 0, "{}", context);
+        }
+    }
+}
+
+#[test]
+fn abort_without_car_test() {
+    let (mut engine, _, housing, workplace) =
+        highway_commute_map(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+
+    let mut data = AgentDataDistribution::default().sample(&mut engine.rng);
+    data.owns_car = false;
+    let id = engine.add_agent(data, housing, Some(workplace)).unwrap();
+    let route = engine
+        .query_route(route::QueryInput {
+            start: housing,
+            end: workplace,
+            car_config: None,
+            profile: Default::default(),
+            allowed_modes: Default::default(),
+        })
+        .unwrap()
+        .expect("expected a route");
+
+    engine
+        .agents
+        .get_mut(&id)
+        .unwrap()
+        .begin_route(
+            route,
+            0,
+            agent::RouteType::CommuteToWork,
+            agent::RouteDetail::Fine,
+            &mut engine.world_state,
+            &engine.state,
+        )
+        .unwrap();
+    engine.consistency_check().unwrap();
+
+    // agents without a car don't get one from aborting
+    abort(&mut engine, id, AbortMethod::Abort);
+    assert_eq!(engine.agents[&id].parked_car(), None);
+    assert_eq!(engine.world_state.total_parked_cars(), 0);
+    engine.consistency_check().unwrap();
+}
+
+#[test]
+fn repair_parked_cars_test() {
+    let (mut engine, _, housing, workplace) =
+        highway_commute_map(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    let (id, route) = start_commute(&mut engine, housing, workplace);
+    advance_to(&mut engine, id, AbortPoint::Walking);
+    let parking = parking_address(&route);
+
+    // a car that nobody holds
+    let elsewhere = engine.state.qtree.get_address(30, 30).unwrap();
+    engine.world_state.increment_parking(elsewhere).unwrap();
+    match engine.consistency_check() {
+        Err(engine::ConsistencyError::ParkingErrors(errors)) => {
+            assert_eq!(errors.len(), 1, "{:?}", errors);
+            assert!(errors[0].contains("unclaimed"), "{:?}", errors);
+        }
+        res => panic!("expected parking errors, got {:?}", res),
+    }
+    assert_eq!(engine.repair_parked_cars().unwrap(), 1);
+    engine.consistency_check().unwrap();
+    assert_eq!(engine.world_state.parked_cars_at(elsewhere), 0);
+
+    // a car that the world state has lost track of is put back, taking the place of an unclaimed
+    // one if there is one
+    engine.world_state.decrement_parking(parking).unwrap();
+    match engine.consistency_check() {
+        Err(engine::ConsistencyError::ParkingErrors(errors)) => {
+            assert!(errors[0].contains("missing"), "{:?}", errors);
+        }
+        res => panic!("expected parking errors, got {:?}", res),
+    }
+    engine.world_state.increment_parking(housing).unwrap();
+    assert_eq!(engine.repair_parked_cars().unwrap(), 1);
+    engine.consistency_check().unwrap();
+    assert_eq!(engine.world_state.parked_cars_at(parking), 1);
+    assert_eq!(engine.world_state.total_parked_cars(), 1);
+
+    assert_eq!(engine.repair_parked_cars().unwrap(), 0);
+}
@@ -0,0 +1,175 @@
+use engine::{AgentDataDistribution, Engine};
+use route::WorldState;
+use test_support::{highway_commute_map, parking_address, test_config};
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 50;
+/// makes the parking capacity of each block small, so that it is easy to saturate
+const PEOPLE_PER_SIM: f64 = 100.0;
+
+/// The highway commute map, with parking that is easy to saturate.
+fn generate_map() -> (Engine, quadtree::Address, quadtree::Address) {
+    let (engine, _, housing, workplace) = highway_commute_map(state::Config {
+        people_per_sim: PEOPLE_PER_SIM,
+        ..test_config(MAX_DEPTH, MIN_TILE_SIZE)
+    });
+    (engine, housing, workplace)
+}
+
+fn query_driving_route(
+    engine: &Engine,
+    housing: quadtree::Address,
+    workplace: quadtree::Address,
+) -> route::Route {
+    engine
+        .query_route(route::QueryInput {
+            start: housing,
+            end: workplace,
+            car_config: Some(route::CarConfig::StartWithCar),
+            profile: Default::default(),
+            allowed_modes: Default::default(),
+        })
+        .unwrap()
+        .expect("expected a route")
+}
+
+fn new_agent(engine: &mut Engine, housing: quadtree::Address) -> agent::Agent {
+    let mut data = AgentDataDistribution::default().sample(&mut engine.rng);
+    data.owns_car = true;
+    let agent = agent::Agent::new(0, data, housing, None);
+    engine.world_state.increment_parking(housing).unwrap();
+    agent
+}
+
+/// Follow the route to the end, returning the total time taken.
+fn follow_route(engine: &mut Engine, housing: quadtree::Address, route: route::Route) -> f32 {
+    let mut agent = new_agent(engine, housing);
+    agent
+        .begin_route(
+            route,
+            0,
+            agent::RouteType::CommuteToWork,
+            agent::RouteDetail::Fine,
+            &mut engine.world_state,
+            &engine.state,
+        )
+        .unwrap();
+
+    loop {
+        match &mut agent.state {
+            agent::AgentState::Route(route_state) => match route_state.phase {
+                agent::AgentRoutePhase::InProgress { .. } => {
+                    route_state
+                        .advance(&mut engine.world_state, &engine.state)
+                        .unwrap();
+                }
+                agent::AgentRoutePhase::Finished { total_time } => {
+                    agent.finish_route().unwrap();
+                    return total_time;
+                }
+            },
+            _ => panic!("expected the agent to be following a route"),
+        }
+    }
+}
+
+/// Park enough cars near the address to make drivers search for parking, returning the penalty.
+fn saturate(engine: &mut Engine, address: quadtree::Address) -> f64 {
+    let capacity = route::local_traffic::parking_capacity(&engine.state.config);
+    for _ in 0..(capacity * 1.5).ceil() as usize {
+        engine.world_state.increment_parking(address).unwrap();
+    }
+    let (x, y) = address.to_xy_f64();
+    let penalty = route::local_traffic::parking_search_time(
+        &engine.state.config,
+        engine.world_state.get_parking(x, y),
+    );
+    assert!(
+        penalty > 0.0 && penalty < route::local_traffic::MAX_PARKING_SEARCH_TIME,
+        "penalty: {}",
+        penalty
+    );
+    penalty
+}
+
+#[test]
+fn commute_includes_parking_search_test() {
+    let (mut control, housing, workplace) = generate_map();
+    let route = query_driving_route(&control, housing, workplace);
+    let parking = parking_address(&route);
+
+    let (mut saturated, _, _) = generate_map();
+    let penalty = saturate(&mut saturated, parking);
+
+    let control_time = follow_route(&mut control, housing, route.clone());
+    let saturated_time = follow_route(&mut saturated, housing, route);
+
+    // every edge costs at least one second, including the parking edge in the control
+    let difference = (saturated_time - control_time) as f64;
+    assert!(
+        (difference - penalty).abs() <= 1.0 + 1e-3,
+        "control: {}, saturated: {}, penalty: {}",
+        control_time,
+        saturated_time,
+        penalty
+    );
+}
+
+#[test]
+fn abort_during_parking_search_test() {
+    let (mut engine, housing, workplace) = generate_map();
+    let route = query_driving_route(&engine, housing, workplace);
+    let parking = parking_address(&route);
+    saturate(&mut engine, parking);
+
+    let mut agent = new_agent(&mut engine, housing);
+    agent
+        .begin_route(
+            route,
+            0,
+            agent::RouteType::CommuteToWork,
+            agent::RouteDetail::Fine,
+            &mut engine.world_state,
+            &engine.state,
+        )
+        .unwrap();
+
+    // advance until the agent is looking for parking
+    loop {
+        match &mut agent.state {
+            agent::AgentState::Route(route_state) => {
+                if route_state.is_looking_for_parking() {
+                    assert_eq!(route_state.phase_description(), "looking for parking");
+                    break;
+                }
+                assert!(!route_state.finished(), "never looked for parking");
+                route_state
+                    .advance(&mut engine.world_state, &engine.state)
+                    .unwrap();
+            }
+            _ => panic!("expected the agent to be following a route"),
+        }
+    }
+    // the car isn't parked until the search is over
+    assert_eq!(agent.parked_car(), None);
+
+    agent.abort_route(&mut engine.world_state).unwrap();
+
+    // the only parked car that the agent is responsible for is their own
+    let parked_car = agent.parked_car().expect("expected the car to be parked");
+    let mut expected = route::WorldStateImpl::new(&engine.state.config);
+    let capacity = route::local_traffic::parking_capacity(&engine.state.config);
+    for _ in 0..(capacity * 1.5).ceil() as usize {
+        expected.increment_parking(parking).unwrap();
+    }
+    expected.increment_parking(parked_car).unwrap();
+
+    assert_eq!(
+        engine.world_state.check_same_parking(&expected),
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        engine.world_state.check_same_traffic(&expected),
+        Vec::<String>::new()
+    );
+}
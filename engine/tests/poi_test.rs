@@ -0,0 +1,210 @@
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_all, test_config};
+use uom::si::time::{day, minute};
+use uom::si::u64::Time;
+
+fn new_engine(
+    max_depth: u32,
+    min_tile_size: u32,
+    config: impl FnOnce(&mut state::Config),
+) -> Engine {
+    let mut state_config = test_config(max_depth, min_tile_size);
+    config(&mut state_config);
+    let mut engine = Engine::new(state_config);
+    split_all(&mut engine);
+    engine
+}
+
+fn poi(kind: tiles::PoiKind, name: &str) -> tiles::Poi {
+    tiles::Poi {
+        kind,
+        name: name.to_string(),
+    }
+}
+
+/// the land value of the tile at the given position
+fn land_value(engine: &Engine, (x, y): (u64, u64)) -> f64 {
+    let address = engine.state.qtree.get_address(x, y).unwrap();
+    let leaf = engine.state.qtree.get_leaf(address).unwrap();
+    leaf.fields.land_value.land_value.value
+}
+
+#[test]
+fn land_value_test() {
+    // the same as the blur block size, so that each tile is its own block
+    const MIN_TILE_SIZE: u32 = 200;
+    /// a block of tiles in the middle of the map, far enough from the edges to not be clipped
+    const BLOCK: std::ops::Range<u64> = 14..18;
+    const POI: (u64, u64) = (16, 16);
+    const NEARBY: (u64, u64) = (19, 16);
+    const FAR: (u64, u64) = (2, 2);
+
+    let with_poi = |kind: Option<tiles::PoiKind>| {
+        let mut engine = new_engine(5, MIN_TILE_SIZE, |_| ());
+        if let Some(kind) = kind {
+            for x in BLOCK {
+                for y in BLOCK {
+                    let address = engine.state.qtree.get_address(x, y).unwrap();
+                    engine.set_poi(address, Some(poi(kind, "test"))).unwrap();
+                }
+            }
+        }
+        engine.update_fields().unwrap();
+        engine
+    };
+
+    let control = with_poi(None);
+    let park = with_poi(Some(tiles::PoiKind::Park));
+    let school = with_poi(Some(tiles::PoiKind::School));
+    let hospital = with_poi(Some(tiles::PoiKind::Hospital));
+
+    // the uplift spreads to the surroundings of the park, but not across the map
+    assert!(land_value(&park, POI) > land_value(&control, POI));
+    assert!(land_value(&park, NEARBY) > land_value(&control, NEARBY));
+    assert_eq!(land_value(&park, FAR), land_value(&control, FAR));
+
+    // schools help, but not as much as parks
+    assert!(land_value(&school, NEARBY) > land_value(&control, NEARBY));
+    assert!(land_value(&school, NEARBY) < land_value(&park, NEARBY));
+
+    assert_eq!(land_value(&hospital, NEARBY), land_value(&control, NEARBY));
+}
+
+#[test]
+fn serde_test() {
+    let mut engine = new_engine(2, 100, |_| ());
+    let address = engine.state.qtree.get_address(1, 1).unwrap();
+    let park = poi(tiles::PoiKind::Park, "Central Park");
+    engine.set_poi(address, Some(park.clone())).unwrap();
+
+    let data = engine
+        .get_leaf_data(address, state::SerdeFormat::Json)
+        .unwrap();
+    engine.set_poi(address, None).unwrap();
+    engine
+        .set_leaf_data(address, &data, state::SerdeFormat::Json)
+        .unwrap();
+    let leaf = engine.get_leaf(address).unwrap();
+    assert_eq!(leaf.poi, Some(park.clone()));
+    assert_eq!(leaf.tile, tiles::EmptyTile {}.into());
+
+    // the point of interest survives saving and loading the whole map
+    let loaded = Engine::load(&engine.dump().unwrap()).unwrap();
+    assert_eq!(loaded.get_leaf(address).unwrap().poi, Some(park));
+
+    // leaves from older maps don't have points of interest
+    let old = r#"{"tile": {"type": "EmptyTile"}, "creation_time": 0}"#;
+    engine
+        .set_leaf_data(address, old, state::SerdeFormat::Json)
+        .unwrap();
+    assert_eq!(engine.get_leaf(address).unwrap().poi, None);
+}
+
+const HOUSING: (u64, u64) = (2, 2);
+const PARK: (u64, u64) = (40, 2);
+
+/// Generate a map with two agents who live at one end of a highway, with a park at the other end.
+fn generate_leisure_map(leisure: state::LeisureConfig) -> Engine {
+    let mut engine = new_engine(6, 50, |config| {
+        config.leisure = leisure;
+        config.travel_diary.enabled = true;
+    });
+
+    let housing = engine
+        .state
+        .qtree
+        .get_address(HOUSING.0, HOUSING.1)
+        .unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 2,
+        agents: vec![],
+    }
+    .into();
+    let park = engine.state.qtree.get_address(PARK.0, PARK.1).unwrap();
+    engine
+        .set_poi(park, Some(poi(tiles::PoiKind::Park, "Riverside Park")))
+        .unwrap();
+
+    let points = [(3.0, 3.0), (39.0, 3.0)];
+    let on_ramp = engine.state.highways.add_junction(
+        points[0],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OnRamp)),
+    );
+    let off_ramp = engine.state.highways.add_junction(
+        points[1],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OffRamp)),
+    );
+    engine.state.highways.add_segment(
+        highway::HighwaySegment::new(None, vec![], None, Some(40)),
+        on_ramp,
+        off_ramp,
+        Some(vec![points[0].into(), points[1].into()]),
+    );
+
+    for _ in 0..2 {
+        let data = AgentDataDistribution::default().sample(&mut engine.rng);
+        engine.add_agent(data, housing, None).unwrap();
+    }
+    engine.init_trigger_queue();
+
+    engine
+}
+
+fn simulate_day(engine: &mut Engine) {
+    let steps = Time::new::<day>(1).value / Time::new::<minute>(1).value;
+    for _ in 0..steps {
+        engine.tick(Time::new::<minute>(1).value).unwrap();
+    }
+}
+
+#[test]
+fn leisure_trip_test() {
+    let mut engine = generate_leisure_map(state::LeisureConfig {
+        enabled: true,
+        probability: 1.0,
+        ..Default::default()
+    });
+    simulate_day(&mut engine);
+
+    let park = engine.state.qtree.get_address(PARK.0, PARK.1).unwrap();
+    let housing = engine
+        .state
+        .qtree
+        .get_address(HOUSING.0, HOUSING.1)
+        .unwrap();
+    let records = engine.travel_diary(0);
+    for (route_type, destination) in [
+        (agent::RouteType::ToLeisure, park),
+        (agent::RouteType::FromLeisure, housing),
+    ] {
+        let trips: Vec<_> = records
+            .iter()
+            .filter(|record| record.route_type == route_type)
+            .collect();
+        assert_eq!(trips.len(), 2, "{:#?}", records);
+        for trip in trips {
+            assert_eq!(trip.destination, destination.to_xy());
+            assert!(!trip.aborted);
+            assert!(!trip.legs.is_empty());
+        }
+    }
+
+    // everyone made it back home
+    for agent in engine.agents.values() {
+        assert!(matches!(agent.state, agent::AgentState::Tile(address) if address == housing));
+        assert!(agent.route_lengths[&agent::RouteType::ToLeisure] > 0.0);
+    }
+    engine.consistency_check().unwrap();
+}
+
+#[test]
+fn leisure_disabled_test() {
+    let mut engine = generate_leisure_map(state::LeisureConfig {
+        enabled: false,
+        probability: 1.0,
+        ..Default::default()
+    });
+    simulate_day(&mut engine);
+
+    assert!(engine.travel_diary(0).is_empty());
+}
@@ -0,0 +1,165 @@
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 3;
+const MIN_TILE_SIZE: u32 = 100;
+
+/// Generate a small map with housing on the left half and workplaces on the right half.
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+
+    split_all(&mut engine);
+
+    let width = engine.state.qtree.width();
+    for x in 0..width {
+        for y in 0..width {
+            let address = engine.state.qtree.get_address(x, y).unwrap();
+            let tile = if x < width / 2 {
+                tiles::HousingTile {
+                    density: 10,
+                    agents: vec![],
+                }
+                .into()
+            } else {
+                tiles::WorkplaceTile {
+                    density: 5,
+                    agents: vec![],
+                    industry: tiles::Industry::Office,
+                }
+                .into()
+            };
+            engine.state.qtree.get_leaf_mut(address).unwrap().tile = tile;
+        }
+    }
+
+    engine
+}
+
+fn distance(a: quadtree::Address, b: quadtree::Address) -> f64 {
+    let (ax, ay) = a.to_xy_f64();
+    let (bx, by) = b.to_xy_f64();
+    ((ax - bx).powi(2) + (ay - by).powi(2)).sqrt() * MIN_TILE_SIZE as f64
+}
+
+#[test]
+fn populate_housing_test() {
+    let mut engine = generate_map();
+
+    let added = engine
+        .populate_housing(0.8, 0, &AgentDataDistribution::default())
+        .unwrap();
+    assert_eq!(added, engine.agents.len());
+    assert!(engine.consistency_check().is_ok());
+
+    let housing: usize = engine
+        .state
+        .collect_tiles
+        .housing
+        .iter()
+        .map(
+            |address| match &engine.state.qtree.get_leaf(*address).unwrap().tile {
+                tiles::Tile::HousingTile(tiles::HousingTile { density, .. }) => *density,
+                _ => panic!("expected housing"),
+            },
+        )
+        .sum();
+    let occupancy = engine.agents.len() as f64 / housing as f64;
+    assert!(
+        (occupancy - 0.8).abs() < 0.01,
+        "occupancy {:.3} is not close to 0.8",
+        occupancy
+    );
+
+    // populating again should not add anyone
+    assert_eq!(
+        engine
+            .populate_housing(0.8, 0, &AgentDataDistribution::default())
+            .unwrap(),
+        0
+    );
+}
+
+#[test]
+fn assign_workplaces_test() {
+    const MAX_COMMUTE_DISTANCE: f64 = 300.0;
+
+    let mut engine = generate_map();
+    engine
+        .populate_housing(1.0, 0, &AgentDataDistribution::default())
+        .unwrap();
+
+    let assigned = engine
+        .assign_workplaces(1.0, 0, MAX_COMMUTE_DISTANCE)
+        .unwrap();
+    assert!(assigned > 0);
+    assert!(engine.consistency_check().is_ok());
+
+    let employed: Vec<_> = engine
+        .agents
+        .values()
+        .filter_map(|agent| agent.workplace.map(|w| (agent.housing, w)))
+        .collect();
+    assert_eq!(employed.len(), assigned);
+
+    for (housing, workplace) in employed {
+        assert!(
+            distance(housing, workplace) <= MAX_COMMUTE_DISTANCE,
+            "commute from {:?} to {:?} exceeds maximum distance",
+            housing,
+            workplace
+        );
+    }
+}
+
+#[test]
+fn assign_workplaces_seed_test() {
+    let assign = |rng_seed| {
+        let mut engine = generate_map();
+        engine
+            .populate_housing(1.0, 0, &AgentDataDistribution::default())
+            .unwrap();
+        // fewer jobs than agents, so the order that agents are matched in matters
+        engine
+            .assign_workplaces(0.5, rng_seed, f64::INFINITY)
+            .unwrap();
+        engine
+            .agents
+            .values()
+            .map(|agent| (agent.id, agent.workplace))
+            .collect::<Vec<_>>()
+    };
+    // the engine RNG is seeded differently every time, but that shouldn't matter
+    assert_eq!(assign(7), assign(7));
+}
+
+#[test]
+fn invalid_arguments_test() {
+    let mut engine = generate_map();
+    let invalid = |result: Result<usize, engine::Error>| {
+        matches!(result, Err(engine::Error::InvalidArgument { .. }))
+    };
+
+    let distribution = AgentDataDistribution::default();
+    assert!(invalid(engine.populate_housing(1.5, 0, &distribution)));
+    assert!(invalid(engine.populate_housing(f64::NAN, 0, &distribution)));
+
+    let reversed = AgentDataDistribution {
+        birth_years: (2000, 1950),
+        ..Default::default()
+    };
+    assert!(invalid(engine.populate_housing(1.0, 0, &reversed)));
+    let not_a_number = AgentDataDistribution {
+        walking_speed: (f64::NAN, 1.2),
+        ..Default::default()
+    };
+    assert!(invalid(engine.populate_housing(1.0, 0, &not_a_number)));
+    assert!(engine.agents.is_empty());
+
+    engine.populate_housing(1.0, 0, &distribution).unwrap();
+    assert!(invalid(engine.assign_workplaces(-0.5, 0, 300.0)));
+    assert!(invalid(engine.assign_workplaces(1.0, 0, f64::NAN)));
+    assert!(engine
+        .agents
+        .values()
+        .all(|agent| agent.workplace.is_none()));
+}
@@ -0,0 +1,171 @@
+use engine::{Engine, FieldsState, ImportPolicy};
+use state::{BranchState, LeafState};
+use test_support::test_config;
+
+const MAX_DEPTH: u32 = 2;
+
+/// Generate a map with two housing tiles and a workplace. If `flooded` is set, the second housing
+/// tile is water instead.
+fn generate_map(flooded: bool) -> (Engine, quadtree::Address, quadtree::Address) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    engine
+        .state
+        .qtree
+        .split(
+            quadtree::Address::from((vec![], MAX_DEPTH)),
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+
+    for (x, y) in [(0, 0), (0, 2)] {
+        let address = engine.state.qtree.get_address(x, y).unwrap();
+        engine.state.qtree.get_leaf_mut(address).unwrap().tile = if flooded && y == 2 {
+            tiles::WaterTile {}.into()
+        } else {
+            tiles::HousingTile {
+                density: 5,
+                agents: vec![],
+            }
+            .into()
+        };
+    }
+    let workplace = engine.state.qtree.get_address(2, 0).unwrap();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 5,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let housing = engine.state.qtree.get_address(0, 0).unwrap();
+    (engine, housing, workplace)
+}
+
+fn agent_data(owns_car: bool) -> agent::AgentData {
+    agent::AgentData {
+        birthday: chrono::NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+        years_of_education: 16,
+        owns_car,
+        walking_speed: 1.0,
+        archetype: 0,
+        noise: Default::default(),
+    }
+}
+
+/// Add agents to both housing tiles, including a household that shares a car, and let them go
+/// about their day.
+fn populate(engine: &mut Engine, housing: quadtree::Address, workplace: quadtree::Address) {
+    let other_housing = engine.state.qtree.get_address(0, 2).unwrap();
+    engine
+        .add_agent(agent_data(true), housing, Some(workplace))
+        .unwrap();
+    engine.add_agent(agent_data(false), housing, None).unwrap();
+    let household = engine.add_household(other_housing, 1).unwrap();
+    engine
+        .add_household_agent(household, agent_data(false), Some(workplace))
+        .unwrap();
+    engine
+        .add_household_agent(household, agent_data(true), Some(workplace))
+        .unwrap();
+    engine.init_trigger_queue();
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("{}.json", name))
+}
+
+#[test]
+fn round_trip_test() {
+    let (mut engine, housing, workplace) = generate_map(false);
+    populate(&mut engine, housing, workplace);
+    // partway through the morning commute
+    engine.tick(9 * 3600).unwrap();
+    engine.consistency_check().unwrap();
+
+    let path = temp_path("population_round_trip_test");
+    engine.export_population(&path).unwrap();
+    let before = engine.population();
+    assert_eq!(before.agents.len(), 4);
+    assert_eq!(before.households.len(), 1);
+    assert_eq!(before.households[0].cars, 2);
+
+    let report = engine
+        .import_population(&path, ImportPolicy::Abort)
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(report.agents.len(), 4);
+    assert_eq!(report.households.len(), 1);
+    assert!(report.skipped.is_empty());
+
+    // everyone is back home with new IDs, but otherwise nothing has changed
+    assert_eq!(engine.agents.len(), 4);
+    assert_eq!(engine.households.len(), 1);
+    assert_eq!(engine.population(), before);
+    assert!(engine
+        .agents
+        .values()
+        .all(|agent| matches!(agent.state, agent::AgentState::Tile(_))));
+    engine.consistency_check().unwrap();
+
+    // the new agents have their triggers scheduled, and the old agents' triggers are harmless
+    engine.tick(2 * 24 * 3600).unwrap();
+    engine.consistency_check().unwrap();
+    assert_eq!(engine.agents.len(), 4);
+}
+
+#[test]
+fn mismatch_test() {
+    let (mut source, housing, workplace) = generate_map(false);
+    populate(&mut source, housing, workplace);
+    let population = source.population();
+
+    let (mut target, housing, workplace) = generate_map(true);
+    let existing = target
+        .add_agent(agent_data(true), housing, Some(workplace))
+        .unwrap();
+    target.init_trigger_queue();
+
+    // the household lives in the water on the target map
+    let err = target
+        .set_population(&population, ImportPolicy::Abort)
+        .unwrap_err();
+    assert!(matches!(
+        err.without_context(),
+        engine::Error::NotAHousingTile(_)
+    ));
+    assert_eq!(
+        target.agents.keys().copied().collect::<Vec<_>>(),
+        vec![existing]
+    );
+    target.consistency_check().unwrap();
+
+    let report = target
+        .set_population(&population, ImportPolicy::Skip)
+        .unwrap();
+    assert_eq!(report.agents.len(), 2);
+    assert!(report.households.is_empty());
+    assert_eq!(
+        report
+            .skipped_households
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>(),
+        vec![0]
+    );
+    assert_eq!(
+        report
+            .skipped
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>(),
+        vec![2, 3]
+    );
+    assert!(!target.agents.contains_key(&existing));
+    assert_eq!(target.agents.len(), 2);
+    assert!(target.households.is_empty());
+    target.consistency_check().unwrap();
+
+    target.tick(24 * 3600).unwrap();
+    target.consistency_check().unwrap();
+}
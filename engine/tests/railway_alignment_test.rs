@@ -0,0 +1,127 @@
+use engine::Engine;
+use metro::RailwayAlignment;
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 4;
+const MIN_TILE_SIZE: u32 = 100;
+
+/// the row that each railway runs along, and a row far away from all of them
+const AT_GRADE_ROW: f64 = 2.5;
+const ELEVATED_ROW: f64 = 7.5;
+const TUNNEL_ROW: f64 = 12.5;
+const CONTROL_ROW: u64 = 15;
+
+fn add_railway(
+    engine: &mut Engine,
+    row: f64,
+    alignment: RailwayAlignment,
+) -> network::SegmentHandle {
+    let railways = &mut engine.state.railways;
+    let start = railways.add_junction((0.5, row), metro::RailwayJunction::new(None));
+    let end = railways.add_junction((15.5, row), metro::RailwayJunction::new(None));
+    railways.add_segment(
+        metro::RailwaySegment::new(None).with_alignment(alignment),
+        start,
+        end,
+        Some(vec![(0.5, row).into(), (15.5, row).into()]),
+    )
+}
+
+/// Generate an empty map with a railway of each alignment running across it.
+fn generate_map() -> (Engine, [network::SegmentHandle; 3]) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    let segments = [
+        add_railway(&mut engine, AT_GRADE_ROW, RailwayAlignment::AtGrade),
+        add_railway(&mut engine, ELEVATED_ROW, RailwayAlignment::Elevated),
+        add_railway(&mut engine, TUNNEL_ROW, RailwayAlignment::Tunnel),
+    ];
+    (engine, segments)
+}
+
+fn raw_land_value(engine: &Engine, x: u64, y: u64) -> f64 {
+    let address = engine.state.qtree.get_address(x, y).unwrap();
+    let leaf = engine.state.qtree.get_leaf(address).unwrap();
+    leaf.fields.raw_land_value.raw_land_value.value
+}
+
+#[test]
+fn cost_multiplier_test() {
+    let (mut engine, segments) = generate_map();
+    let railways = &engine.state.config.railways;
+    let multipliers = [
+        railways.at_grade.cost_multiplier,
+        railways.elevated.cost_multiplier,
+        railways.tunnel.cost_multiplier,
+    ];
+
+    // before the fields are computed, construction is equally expensive everywhere
+    let meters = 15.0 * MIN_TILE_SIZE as f64;
+    for (segment, multiplier) in segments.iter().zip(multipliers) {
+        let segment = engine.state.railways.segment(*segment);
+        let cost = engine.railway_construction_cost(segment);
+        assert!((cost - meters * multiplier).abs() < 1e-6, "{}", cost);
+    }
+
+    // the multipliers follow the config
+    engine.state.config.railways.tunnel.cost_multiplier = 10.0;
+    let tunnel = engine.state.railways.segment(segments[2]);
+    assert!((engine.railway_construction_cost(tunnel) - meters * 10.0).abs() < 1e-6);
+
+    // the empty map has the same construction cost everywhere, so only the alignment matters
+    engine.update_fields().unwrap();
+    let at_grade = engine.railway_construction_cost(engine.state.railways.segment(segments[0]));
+    let tunnel = engine.railway_construction_cost(engine.state.railways.segment(segments[2]));
+    assert!(at_grade >= meters);
+    assert!((tunnel / at_grade - 10.0).abs() < 1e-6);
+}
+
+#[test]
+fn land_value_test() {
+    let (mut engine, _) = generate_map();
+    engine.update_fields().unwrap();
+
+    let control = raw_land_value(&engine, 8, CONTROL_ROW);
+    let config = engine.state.config.railways.clone();
+    for (row, alignment) in [
+        (AT_GRADE_ROW, &config.at_grade),
+        (ELEVATED_ROW, &config.elevated),
+        (TUNNEL_ROW, &config.tunnel),
+    ] {
+        for x in 0..16 {
+            let value = raw_land_value(&engine, x, row as u64);
+            let expected = control * (1.0 - alignment.land_value_penalty);
+            assert!(
+                (value - expected).abs() < 1e-6,
+                "{} at {}, {}",
+                value,
+                x,
+                row
+            );
+        }
+    }
+
+    // tunnels don't bother anyone, and elevated railways bother people less than at-grade ones
+    assert_eq!(raw_land_value(&engine, 8, TUNNEL_ROW as u64), control);
+    assert!(
+        raw_land_value(&engine, 8, AT_GRADE_ROW as u64)
+            < raw_land_value(&engine, 8, ELEVATED_ROW as u64)
+    );
+    assert!(raw_land_value(&engine, 8, ELEVATED_ROW as u64) < control);
+}
+
+#[test]
+fn legacy_save_test() {
+    // saved before railways had alignments
+    let segment: metro::RailwaySegment = serde_json::from_str(r#"{"speed_limit": 20}"#).unwrap();
+    assert_eq!(segment, metro::RailwaySegment::new(Some(20)));
+    assert_eq!(segment.alignment, RailwayAlignment::AtGrade);
+
+    let segment = metro::RailwaySegment::new(None).with_alignment(RailwayAlignment::Tunnel);
+    let data = serde_json::to_string(&segment).unwrap();
+    assert_eq!(
+        serde_json::from_str::<metro::RailwaySegment>(&data).unwrap(),
+        segment
+    );
+}
@@ -0,0 +1,301 @@
+use engine::Engine;
+use quadtree::Quadrant::*;
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 5;
+const MIN_TILE_SIZE: u32 = 100;
+
+/// the copied region is the northwest quadrant, which is pasted over the southeast quadrant
+const OFFSET: u64 = 16;
+
+const STATION_A: (u64, u64) = (4, 4);
+const STATION_C: (u64, u64) = (12, 8);
+const HOUSING: (u64, u64) = (4, 8);
+const WORKPLACE: (u64, u64) = (12, 6);
+/// a highway that stays inside the region
+const HIGHWAY: ((u64, u64), (u64, u64)) = ((2, 12), (14, 12));
+/// a highway that leaves the region to the east
+const CROSSING_HIGHWAY: ((u64, u64), (u64, u64)) = ((8, 14), (20, 14));
+
+fn address(engine: &Engine, (x, y): (u64, u64)) -> quadtree::Address {
+    engine.state.qtree.get_address(x, y).unwrap()
+}
+
+fn quadrant(quadrant: quadtree::Quadrant) -> quadtree::Address {
+    quadtree::Address::from_vec(vec![quadrant], MAX_DEPTH)
+}
+
+fn moved((x, y): (u64, u64)) -> (u64, u64) {
+    (x + OFFSET, y + OFFSET)
+}
+
+fn add_highway(engine: &mut Engine, ((x1, y1), (x2, y2)): ((u64, u64), (u64, u64))) {
+    let highways = &mut engine.state.highways;
+    let start = highways.add_junction((x1 as f64, y1 as f64), highway::HighwayJunction::new(None));
+    let end = highways.add_junction((x2 as f64, y2 as f64), highway::HighwayJunction::new(None));
+    highways.add_segment(
+        highway::HighwaySegment {
+            name: None,
+            refs: vec![],
+            lanes: Some(2),
+            speed_limit: Some(30),
+        },
+        start,
+        end,
+        Some(vec![
+            (x1 as f64, y1 as f64).into(),
+            (x2 as f64, y2 as f64).into(),
+        ]),
+    );
+}
+
+/**
+ * Generate a map with a metro line from A to C, a house with two people next to A, one of whom
+ * works next to C, and a couple of highways, all in the northwest quadrant.
+ */
+fn generate_map() -> (Engine, metro::MetroLineHandle) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    let mut add_station = |(x, y): (u64, u64)| {
+        let address = address(&engine, (x, y));
+        engine.state.qtree.get_leaf_mut(address).unwrap().tile = tiles::MetroStationTile {
+            name: format!("{}, {}", x, y),
+            x,
+            y,
+            ids: vec![],
+            parking: true,
+        }
+        .into();
+        engine.state.railways.add_junction(
+            (x as f64, y as f64),
+            metro::RailwayJunction::new(Some(metro::Station {
+                name: format!("{}, {}", x, y),
+                address,
+            })),
+        )
+    };
+    let a = add_station(STATION_A);
+    let c = add_station(STATION_C);
+
+    let segment = engine.state.railways.add_segment(
+        metro::RailwaySegment::new(None),
+        a,
+        c,
+        Some(vec![
+            (STATION_A.0 as f64, STATION_A.1 as f64).into(),
+            (STATION_C.0 as f64, STATION_C.1 as f64).into(),
+        ]),
+    );
+    let line = engine.state.metros.add_metro_line(
+        metro::MetroLineData {
+            color: (255, 0, 0).into(),
+            name: "Red".to_string(),
+            schedule: metro::Schedule::fixed_frequency(300),
+            speed_limit: 20,
+        },
+        vec![segment],
+        &engine.state.railways,
+    );
+    for station in [STATION_A, STATION_C] {
+        *station_ids_mut(&mut engine, station) = vec![line.inner()];
+    }
+
+    add_highway(&mut engine, HIGHWAY);
+    add_highway(&mut engine, CROSSING_HIGHWAY);
+
+    let housing = address(&engine, HOUSING);
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 2,
+        agents: vec![],
+    }
+    .into();
+    let workplace = address(&engine, WORKPLACE);
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 1,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+    for workplace in [Some(workplace), None] {
+        let data = agent::AgentData {
+            birthday: chrono::NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+            years_of_education: 16,
+            owns_car: true,
+            walking_speed: 1.0,
+            archetype: 0,
+            noise: Default::default(),
+        };
+        engine.add_agent(data, housing, workplace).unwrap();
+    }
+
+    engine.update_fields().unwrap();
+    (engine, line)
+}
+
+fn station_ids_mut(engine: &mut Engine, station: (u64, u64)) -> &mut Vec<u64> {
+    let address = address(engine, station);
+    match &mut engine.state.qtree.get_leaf_mut(address).unwrap().tile {
+        tiles::Tile::MetroStationTile(tile) => &mut tile.ids,
+        tile => panic!("expected a station, found {:?}", tile),
+    }
+}
+
+fn station_tile(engine: &Engine, station: (u64, u64)) -> tiles::MetroStationTile {
+    match &engine
+        .state
+        .qtree
+        .get_leaf(address(engine, station))
+        .unwrap()
+        .tile
+    {
+        tiles::Tile::MetroStationTile(tile) => tile.clone(),
+        tile => panic!("expected a station, found {:?}", tile),
+    }
+}
+
+fn tile_agents(engine: &Engine, location: (u64, u64)) -> Vec<u64> {
+    use tiles::TileType;
+
+    let leaf = engine
+        .state
+        .qtree
+        .get_leaf(address(engine, location))
+        .unwrap();
+    leaf.tile.query_agents().unwrap().clone()
+}
+
+#[test]
+fn copy_paste_test() {
+    let (mut engine, line) = generate_map();
+
+    // the crossing highway can't be copied whole
+    let err = engine
+        .copy_region(quadrant(NW), state::RegionPolicy::Reject, true)
+        .unwrap_err();
+    assert!(matches!(
+        err.without_context(),
+        engine::Error::StateError(state::Error::SegmentCrossesRegion(_))
+    ));
+
+    let copied = engine
+        .copy_region(quadrant(NW), state::RegionPolicy::Clip, true)
+        .unwrap();
+    assert_eq!(copied.region.width(), 16);
+    assert_eq!(copied.region.highways.segments().len(), 2);
+    assert_eq!(copied.region.metros.metro_lines().len(), 1);
+    let population = copied.population.as_ref().unwrap();
+    assert_eq!(population.agents.len(), 2);
+    // addresses are relative to the region
+    assert_eq!(population.agents[0].housing.max_depth(), MAX_DEPTH - 1);
+
+    // the clipped highway ends just inside the edge of the region
+    let clipped = copied
+        .region
+        .highways
+        .segments()
+        .values()
+        .find(|segment| segment.keys()[0].y == 14.0)
+        .unwrap();
+    let end = copied.region.highways.segment_end(clipped).location;
+    assert!(end.x < 16.0 && end.x > 15.99);
+
+    let report = engine.paste_region(quadrant(SE), &copied).unwrap();
+    assert_eq!(report.pasted.metro_lines.len(), 1);
+    let new_line = *report.pasted.metro_lines.values().next().unwrap();
+    assert_ne!(new_line, line);
+    assert_eq!(report.population.unwrap().agents.len(), 2);
+
+    // the stations are at their new locations, on the new line
+    for station in [STATION_A, STATION_C] {
+        let tile = station_tile(&engine, moved(station));
+        assert_eq!((tile.x, tile.y), moved(station));
+        assert_eq!(tile.ids, vec![new_line.inner()]);
+        assert_eq!(station_tile(&engine, station).ids, vec![line.inner()]);
+    }
+    let junction = engine.state.railways.junction(
+        report
+            .pasted
+            .railway_junctions
+            .values()
+            .copied()
+            .next()
+            .unwrap(),
+    );
+    let station = junction.data.station.as_ref().unwrap();
+    assert_eq!(station.address, address(&engine, moved(STATION_A)));
+    assert_eq!(
+        (junction.location.x, junction.location.y),
+        (moved(STATION_A).0 as f64, moved(STATION_A).1 as f64)
+    );
+
+    // the copied agents moved in, and the original ones stayed
+    assert_eq!(engine.agents.len(), 4);
+    assert_eq!(tile_agents(&engine, moved(HOUSING)).len(), 2);
+    assert_eq!(tile_agents(&engine, moved(WORKPLACE)).len(), 1);
+    assert_eq!(tile_agents(&engine, HOUSING).len(), 2);
+    engine.consistency_check().unwrap();
+
+    // the pasted metro line can be ridden
+    let route = engine
+        .query_route(route::QueryInput {
+            start: address(&engine, moved(STATION_A)),
+            end: address(&engine, moved(STATION_C)),
+            car_config: None,
+            profile: Default::default(),
+            allowed_modes: route::AllowedModes::transit_only(),
+        })
+        .unwrap()
+        .expect("expected a route");
+    assert!(route.edges.iter().any(|edge| matches!(
+        edge,
+        route::Edge::MetroSegment { oriented_segment, .. }
+            if Some(&oriented_segment.segment) == report.pasted.railway_segments.values().next()
+    )));
+}
+
+#[test]
+fn invalid_paste_test() {
+    let (mut engine, _) = generate_map();
+    let copied = engine
+        .copy_region(quadrant(NW), state::RegionPolicy::Clip, false)
+        .unwrap();
+    assert!(copied.population.is_none());
+
+    let junctions = engine.state.railways.junctions().len();
+    let highways = engine.state.highways.segments().len();
+    let metro_lines = engine.state.metros.metro_lines().len();
+
+    // stations and agents can't be pasted over
+    let err = engine.paste_region(quadrant(NW), &copied).unwrap_err();
+    assert!(matches!(
+        err.without_context(),
+        engine::Error::StateError(
+            state::Error::TileHasAgents(..) | state::Error::UnsupportedTile(..)
+        )
+    ));
+
+    // the region has to be the same size
+    let smaller = quadtree::Address::from_vec(vec![SE, NW], MAX_DEPTH);
+    let err = engine.paste_region(smaller, &copied).unwrap_err();
+    assert!(matches!(
+        err.without_context(),
+        engine::Error::QuadtreeError(quadtree::Error::IncompatibleDepth {
+            expected: 3,
+            actual: 4
+        })
+    ));
+
+    // nothing changed
+    assert_eq!(engine.state.railways.junctions().len(), junctions);
+    assert_eq!(engine.state.highways.segments().len(), highways);
+    assert_eq!(engine.state.metros.metro_lines().len(), metro_lines);
+
+    // pasting into a single empty tile splits it first
+    let mut engine = Engine::new(engine.state.config.clone());
+    engine.paste_region(quadrant(NE), &copied).unwrap();
+    assert_eq!(engine.state.metros.metro_lines().len(), 1);
+    let station = (STATION_A.0 + OFFSET, STATION_A.1);
+    assert_eq!(station_tile(&engine, station).ids.len(), 1);
+    assert!(engine.agents.is_empty());
+}
@@ -0,0 +1,127 @@
+use engine::{Engine, Replay, ReplayConfig};
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 3;
+const KEYFRAME_INTERVAL: u64 = 600;
+
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    split_all(&mut engine);
+    engine.init_trigger_queue();
+    engine
+}
+
+fn assert_same_world_state(a: &route::WorldStateImpl, b: &route::WorldStateImpl) {
+    let traffic_errs = a.check_same_traffic(b);
+    assert!(traffic_errs.is_empty(), "{:?}", traffic_errs);
+    let parking_errs = a.check_same_parking(b);
+    assert!(parking_errs.is_empty(), "{:?}", parking_errs);
+}
+
+#[test]
+fn record_and_replay_test() {
+    let path = std::env::temp_dir().join("record_and_replay_test.replay");
+
+    let mut engine = generate_map();
+    let period = engine.world_state_history.snapshot_period();
+
+    engine
+        .start_recording(
+            &path,
+            ReplayConfig {
+                keyframe_interval: KEYFRAME_INTERVAL,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    // first snapshot, at time 0
+    let first = engine.world_state.clone();
+    engine.time_state.skip_by(1);
+    engine.update(0.0, f64::INFINITY).unwrap();
+
+    // change the world state so that the second snapshot is different from the first
+    let address = engine.state.qtree.get_address(3, 5).unwrap();
+    engine.world_state.increment_parking(address).unwrap();
+    engine.world_state.increment_parking(address).unwrap();
+    let second = engine.world_state.clone();
+    assert!(!first.check_same_parking(&second).is_empty());
+
+    engine.time_state.skip_by(period);
+    engine.update(0.0, f64::INFINITY).unwrap();
+    engine.stop_recording().unwrap();
+    assert!(engine.recorder().is_none());
+
+    let replay = Replay::load_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(replay.timestamps().collect::<Vec<_>>(), vec![0, period]);
+    assert_eq!(
+        replay.keyframe_timestamps().collect::<Vec<_>>(),
+        (0..=period)
+            .step_by(KEYFRAME_INTERVAL as usize)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(replay.time_range(), Some((0, period)));
+
+    assert_same_world_state(replay.world_state_at(0).unwrap(), &first);
+    assert_same_world_state(replay.world_state_at(period - 1).unwrap(), &first);
+    assert_same_world_state(replay.world_state_at(period).unwrap(), &second);
+    assert_same_world_state(replay.world_state_at(period * 2).unwrap(), &second);
+}
+
+#[test]
+fn max_file_size_test() {
+    let path = std::env::temp_dir().join("max_file_size_test.replay");
+
+    let mut engine = generate_map();
+
+    // only enough room for the header and a few small records
+    engine
+        .start_recording(
+            &path,
+            ReplayConfig {
+                keyframe_interval: 1,
+                max_file_size: 100,
+            },
+        )
+        .unwrap();
+
+    engine.time_state.skip_by(1000);
+    engine.update(0.0, f64::INFINITY).unwrap();
+
+    // recording stops by itself once the file is full, and the recorder says so until it is
+    // stopped
+    assert!(engine.recorder().unwrap().is_full());
+    assert!(std::fs::metadata(&path).unwrap().len() <= 100);
+    engine.stop_recording().unwrap();
+    assert!(engine.recorder().is_none());
+
+    // and whatever was recorded before then can still be read
+    assert!(!Replay::load_file(&path).unwrap().is_truncated());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn truncated_replay_test() {
+    let path = std::env::temp_dir().join("truncated_replay_test.replay");
+
+    let mut engine = generate_map();
+    engine
+        .start_recording(&path, ReplayConfig::default())
+        .unwrap();
+    engine.time_state.skip_by(1);
+    engine.update(0.0, f64::INFINITY).unwrap();
+    engine.stop_recording().unwrap();
+
+    let mut data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let complete = Replay::read(data.as_slice()).unwrap();
+    assert!(!complete.is_truncated());
+
+    // cut the last record short, as if the simulation crashed while writing it
+    data.pop();
+    let truncated = Replay::read(data.as_slice()).unwrap();
+    assert!(truncated.is_truncated());
+    assert!(truncated.time_range().is_some());
+}
@@ -0,0 +1,96 @@
+use engine::Engine;
+use test_support::{two_highway_map, uses_segments};
+
+/// far more travelers than any of the test highways can handle
+const JAM: f64 = 1_000_000.0;
+
+fn jam(engine: &mut Engine, segments: &[network::SegmentHandle]) {
+    for segment in segments {
+        engine.inject_highway_travelers(*segment, JAM).unwrap();
+    }
+    engine.update_route_weights(0);
+}
+
+#[test]
+fn free_flow_debug_test() {
+    let (engine, commute, direct) = two_highway_map();
+
+    let debug = engine.query_route_debug(commute).unwrap().unwrap();
+    assert!(uses_segments(&debug.route, &direct));
+    assert_eq!(debug.traffic_time, None);
+    assert_eq!(debug.components.len(), debug.route.edges.len());
+
+    // the weights haven't been updated, so there is nothing to compare against
+    assert!(debug.free_flow_route.is_none());
+    assert!(debug.divergences.is_empty());
+    for components in &debug.components {
+        assert_eq!(components.congestion_delay(), 0.0);
+        assert_eq!(components.congestion_factor, 1.0);
+    }
+}
+
+#[test]
+fn congested_debug_test() {
+    let (mut engine, commute, direct) = two_highway_map();
+    jam(&mut engine, &direct);
+
+    let debug = engine.query_route_debug(commute).unwrap().unwrap();
+    assert!(!uses_segments(&debug.route, &direct));
+    assert_eq!(debug.traffic_time, Some(0));
+
+    // the free-flow route takes the jammed highway, which is much slower under the same traffic
+    let free_flow_route = debug.free_flow_route.as_ref().unwrap();
+    assert!(uses_segments(free_flow_route, &direct));
+    assert_eq!(
+        debug.free_flow_components.len(),
+        free_flow_route.edges.len()
+    );
+    let total = |components: &[route::CostComponents]| -> f64 {
+        components.iter().map(|components| components.total()).sum()
+    };
+    assert!(total(&debug.free_flow_components) > total(&debug.components));
+    assert!(debug.free_flow_components.iter().any(
+        |components| components.congestion_factor > 1.0 && components.congestion_delay() > 0.0
+    ));
+
+    // the routes start at the same place and go separate ways
+    assert!(!debug.divergences.is_empty());
+    for divergence in &debug.divergences {
+        assert_eq!(
+            debug.route.nodes[divergence.edge],
+            free_flow_route.nodes[divergence.other_edge]
+        );
+    }
+}
+
+#[test]
+fn components_match_weights_test() {
+    let (mut engine, commute, direct) = two_highway_map();
+    jam(&mut engine, &direct);
+
+    let debug = engine.query_route_debug(commute).unwrap().unwrap();
+    let predictor = engine
+        .world_state_history
+        .get_predictor(debug.traffic_time.unwrap());
+
+    // every edge's components add up to the weight that the router uses for it
+    let base_graph = engine.base_graph.read().unwrap();
+    let graph = base_graph.get_base_graph(&engine.state);
+    for ((from, to), edge) in graph.graph.get_edge_map() {
+        let components = edge.cost_components(&predictor, &engine.state, None);
+        assert_eq!(
+            Some(components.total() as usize),
+            graph.graph.edge_cost(*from, *to),
+            "{:?}",
+            edge
+        );
+    }
+
+    // including the edges of the route, some of which aren't in the base graph
+    for (edge, components) in debug.route.edges.iter().zip(&debug.components) {
+        assert_eq!(
+            components.total(),
+            edge.cost(&predictor, &engine.state, None)
+        );
+    }
+}
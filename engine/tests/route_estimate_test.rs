@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use engine::{Engine, ModeProfile};
+use test_support::{split_all, test_config};
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 50;
+
+/// Estimates must be within this fraction of the real route cost...
+const RELATIVE_TOLERANCE: f64 = 0.1;
+/// ...or within this many seconds, since short routes are dominated by where exactly they start.
+const ABSOLUTE_TOLERANCE: f64 = 30.0;
+
+fn add_highway(engine: &mut Engine, points: &[(f64, f64)]) {
+    let data = highway::HighwaySegment::new(None, vec![], None, Some(40));
+    let on_ramp = engine.state.highways.add_junction(
+        points[0],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OnRamp)),
+    );
+    let off_ramp = engine.state.highways.add_junction(
+        points[1],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OffRamp)),
+    );
+    engine.state.highways.add_segment(
+        data,
+        on_ramp,
+        off_ramp,
+        Some(vec![points[0].into(), points[1].into()]),
+    );
+}
+
+/// Generate a map with highways in both directions along two of its edges.
+fn generate_map() -> Engine {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    add_highway(&mut engine, &[(4.0, 4.0), (60.0, 4.0)]);
+    add_highway(&mut engine, &[(60.0, 6.0), (4.0, 6.0)]);
+    add_highway(&mut engine, &[(4.0, 8.0), (4.0, 60.0)]);
+    add_highway(&mut engine, &[(6.0, 60.0), (6.0, 8.0)]);
+
+    engine
+}
+
+fn address(engine: &Engine, (x, y): (u64, u64)) -> quadtree::Address {
+    engine.state.qtree.get_address(x, y).unwrap()
+}
+
+fn best_route_cost(
+    engine: &Engine,
+    start: quadtree::Address,
+    end: quadtree::Address,
+    profile: ModeProfile,
+) -> Option<f64> {
+    let (car_config, allowed_modes) = match profile {
+        ModeProfile::Driving => (
+            Some(route::CarConfig::StartWithCar),
+            route::AllowedModes::ALL,
+        ),
+        _ => (None, route::AllowedModes::transit_only()),
+    };
+    engine
+        .query_route(route::QueryInput {
+            start,
+            end,
+            car_config,
+            profile: route::MobilityProfile::STANDARD,
+            allowed_modes,
+        })
+        .unwrap()
+        .map(|route| route.cost as f64)
+}
+
+#[test]
+fn estimate_accuracy_test() {
+    let mut engine = generate_map();
+
+    let starts = [(2, 2), (5, 30), (33, 5), (50, 50)];
+    let ends = [
+        (1, 1),
+        (3, 7),
+        (10, 40),
+        (20, 20),
+        (40, 6),
+        (58, 9),
+        (61, 61),
+        (7, 61),
+    ];
+
+    for profile in [ModeProfile::Transit, ModeProfile::Driving] {
+        for start in starts {
+            for end in ends {
+                let (start, end) = (address(&engine, start), address(&engine, end));
+                let estimate = engine.estimate_route_cost(start, end, profile).unwrap();
+                let actual = best_route_cost(&engine, start, end, profile);
+                match (estimate, actual) {
+                    (Some(estimate), Some(actual)) => {
+                        let tolerance = f64::max(actual * RELATIVE_TOLERANCE, ABSOLUTE_TOLERANCE);
+                        assert!(
+                            (estimate - actual).abs() <= tolerance,
+                            "{:?} from {:?} to {:?}: estimated {}, actually {}",
+                            profile,
+                            start.to_xy(),
+                            end.to_xy(),
+                            estimate,
+                            actual,
+                        );
+                    }
+                    // without a walking network, far away places can't be reached on foot
+                    (None, None) => (),
+                    (estimate, actual) => panic!(
+                        "{:?} from {:?} to {:?}: estimated {:?}, actually {:?}",
+                        profile,
+                        start.to_xy(),
+                        end.to_xy(),
+                        estimate,
+                        actual,
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn walking_estimate_test() {
+    let mut engine = generate_map();
+    let start = address(&engine, (0, 0));
+    let end = address(&engine, (30, 40));
+
+    // 50 tiles of 50 meters each
+    let expected = 2500.0 / route::Mode::Walking.linear_speed();
+    let estimate = engine
+        .estimate_route_cost(start, end, ModeProfile::Walking)
+        .unwrap()
+        .unwrap();
+    assert!((estimate - expected).abs() < 1e-6);
+}
+
+#[test]
+fn cache_test() {
+    let mut engine = generate_map();
+    let origin = address(&engine, (2, 2));
+    let other = address(&engine, (50, 50));
+
+    let table = engine
+        .route_cost_table(origin, ModeProfile::Driving)
+        .unwrap();
+    assert_eq!(table.origin, origin);
+    assert_eq!(table.graph_version, engine.graph_version());
+
+    // hovering around doesn't recompute the table
+    for end in [(10, 10), (40, 6), (62, 62)] {
+        let end = address(&engine, end);
+        engine
+            .estimate_route_cost(origin, end, ModeProfile::Driving)
+            .unwrap();
+    }
+    let cached = engine
+        .route_cost_table(origin, ModeProfile::Driving)
+        .unwrap();
+    assert!(Arc::ptr_eq(&table, &cached));
+
+    // changing the origin or the profile does
+    let moved = engine
+        .route_cost_table(other, ModeProfile::Driving)
+        .unwrap();
+    assert!(!Arc::ptr_eq(&table, &moved));
+    assert_eq!(moved.origin, other);
+    let transit = engine
+        .route_cost_table(other, ModeProfile::Transit)
+        .unwrap();
+    assert!(!Arc::ptr_eq(&moved, &transit));
+
+    // so do new traffic predictions
+    engine.update_route_weights(0);
+    let rebuilt = engine
+        .route_cost_table(other, ModeProfile::Transit)
+        .unwrap();
+    assert!(!Arc::ptr_eq(&transit, &rebuilt));
+    assert_eq!(rebuilt.graph_version, engine.graph_version());
+    assert_ne!(rebuilt.graph_version, transit.graph_version);
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use engine::Engine;
+use test_support::commuter_map;
+use uom::si::time::hour;
+use uom::si::u64::Time;
+
+/// The time at which the agent plans their first commute to work, which is a weekday.
+fn commute_time(engine: &Engine, id: u64) -> u64 {
+    Time::new::<hour>(engine.work_schedule(id).start_hour).value
+}
+
+#[test]
+fn deadline_miss_test() {
+    let (mut engine, id) = commuter_map();
+    let delay = Duration::from_millis(200);
+    engine.set_route_query_delay(Some(delay));
+
+    // plan the commute and start it in the same tick, so the query can't possibly be done in time
+    let deadline = engine.state.config.scheduling.route_start_deadline;
+    engine
+        .tick(commute_time(&engine, id) + deadline + 1)
+        .unwrap();
+
+    let latency = engine.route_latency();
+    assert_eq!(latency.route_starts, 1);
+    assert_eq!(latency.deadline_misses, 1);
+    assert_eq!(latency.miss_rate(), Some(1.0));
+    assert_eq!(latency.blocked.count, 1);
+    assert!(latency.blocked.total >= delay / 2, "{:?}", latency);
+    assert_eq!(latency.queries.count, 1);
+    assert!(latency.queries.max >= delay, "{:?}", latency);
+
+    let totals = engine.alerts.totals();
+    assert_eq!(totals.route_deadline_misses, 1);
+    assert!(totals.route_blocked_ms >= delay.as_millis() as u64 / 2);
+
+    assert!(engine
+        .route_latency()
+        .to_text()
+        .contains("Deadline misses: 1 of 1 route starts"));
+}
+
+#[test]
+fn deadline_met_test() {
+    let (mut engine, id) = commuter_map();
+
+    // plan the commute, then give the query plenty of wall-clock time before the route starts
+    engine.tick(commute_time(&engine, id) + 1).unwrap();
+    assert_eq!(engine.route_latency().route_starts, 0);
+    std::thread::sleep(Duration::from_millis(200));
+
+    let deadline = engine.state.config.scheduling.route_start_deadline;
+    engine.tick(deadline).unwrap();
+
+    let latency = engine.route_latency();
+    assert_eq!(latency.route_starts, 1);
+    assert_eq!(latency.deadline_misses, 0);
+    assert_eq!(latency.blocked.count, 0);
+    assert_eq!(latency.queries.count, 1);
+    assert_eq!(engine.alerts.totals().route_deadline_misses, 0);
+
+    // the suggestion always leaves at least a second
+    assert!(latency.suggested_deadline(1).unwrap() >= 1);
+}
@@ -0,0 +1,130 @@
+use engine::Engine;
+use highway::timing::HighwayTiming;
+use test_support::{two_highway_map, uses_segments};
+
+// the direct highway of two_highway_map is the variable corridor, the alternative is the steady one
+
+/// far more travelers than any of the test highways can handle
+const JAM: f64 = 1_000_000.0;
+/// travelers on the direct highway in each traffic snapshot
+const SNAPSHOT_TRAVELERS: [f64; 4] = [0.0, 1000.0, 3000.0, 10000.0];
+
+/// A history where only the direct highway is busy, by a different amount in each snapshot.
+fn history(engine: &Engine, direct: &[network::SegmentHandle]) -> route::WorldStateHistory {
+    let snapshots = SNAPSHOT_TRAVELERS
+        .iter()
+        .map(|travelers| {
+            let mut snapshot = route::WorldStateImpl::new(&engine.state.config);
+            for segment in direct {
+                snapshot
+                    .add_highway_segment_travelers(*segment, *travelers)
+                    .unwrap();
+            }
+            snapshot
+        })
+        .collect();
+    route::WorldStateHistory::from_snapshots(snapshots)
+}
+
+/// How long the route takes with the given number of travelers on each segment of the direct highway.
+fn manual_time(
+    engine: &Engine,
+    route: &route::Route,
+    direct: &[network::SegmentHandle],
+    travelers: f64,
+) -> f64 {
+    let config = &engine.state.config;
+    let empty = route::WorldStateImpl::new(config);
+    let delay: f64 = route
+        .edges
+        .iter()
+        .filter_map(|edge| match edge {
+            route::Edge::Highway { segment, .. } if direct.contains(segment) => {
+                let segment = engine.state.highways.segment(*segment);
+                let time = |travelers| {
+                    segment.congested_travel_time(
+                        config.min_tile_size,
+                        config.people_per_sim,
+                        travelers,
+                    )
+                };
+                Some(time(travelers) - time(0.0))
+            }
+            _ => None,
+        })
+        .sum();
+    route.cost_under(&empty, &engine.state) + delay
+}
+
+#[test]
+fn percentiles_test() {
+    let (engine, commute, direct) = two_highway_map();
+    let route = engine.query_route(commute).unwrap().unwrap();
+    assert!(uses_segments(&route, &direct));
+
+    let live = route::WorldStateImpl::new(&engine.state.config);
+    let stats = route.reliability(&history(&engine, &direct), &live, &engine.state);
+    assert_eq!(stats.samples, SNAPSHOT_TRAVELERS.len() + 1);
+    assert_eq!(stats.planned, route.cost as f64);
+
+    // sorted, the samples are the live state and the four snapshots: 0, 0, 1000, 3000, 10000
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+    let free_flow = manual_time(&engine, &route, &direct, 0.0);
+    assert!(close(stats.min, free_flow), "{:?}", stats);
+    assert!(
+        close(stats.median, manual_time(&engine, &route, &direct, 1000.0)),
+        "{:?}",
+        stats
+    );
+    assert!(
+        close(stats.p90, manual_time(&engine, &route, &direct, 10000.0)),
+        "{:?}",
+        stats
+    );
+    assert!(close(stats.max, stats.p90));
+    assert!(
+        stats.median > stats.min && stats.p90 > stats.median,
+        "{:?}",
+        stats
+    );
+
+    // the live state counts as a sample too
+    let mut busy = live.clone();
+    for segment in &direct {
+        busy.add_highway_segment_travelers(*segment, 3000.0)
+            .unwrap();
+    }
+    let busy_stats = route.reliability(&history(&engine, &direct), &busy, &engine.state);
+    assert!(close(
+        busy_stats.median,
+        manual_time(&engine, &route, &direct, 3000.0)
+    ));
+}
+
+#[test]
+fn steady_route_test() {
+    let (mut engine, commute, direct) = two_highway_map();
+    let variable = engine.query_route(commute).unwrap().unwrap();
+
+    // find the route along the other highway by jamming the direct one
+    for segment in &direct {
+        engine.inject_highway_travelers(*segment, JAM).unwrap();
+    }
+    engine.update_route_weights(0);
+    let steady = engine.query_route(commute).unwrap().unwrap();
+    assert!(!uses_segments(&steady, &direct));
+
+    let history = history(&engine, &direct);
+    let live = route::WorldStateImpl::new(&engine.state.config);
+    let variable = variable.reliability(&history, &live, &engine.state);
+    let steady = steady.reliability(&history, &live, &engine.state);
+    assert!(
+        steady.spread() < variable.spread(),
+        "{:?} {:?}",
+        steady,
+        variable
+    );
+    assert_eq!(steady.min, steady.p90);
+    // the direct highway is still faster when it is empty
+    assert!(variable.min < steady.min);
+}
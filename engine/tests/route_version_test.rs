@@ -0,0 +1,132 @@
+use engine::{AgentDataDistribution, Engine, TriggerKind};
+use test_support::{split_all, test_config};
+use uom::si::time::second;
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 50;
+
+/// Generate a map with one agent who drives to work along a highway.
+fn generate_map() -> (Engine, route::QueryInput, network::SegmentHandle) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+
+    split_all(&mut engine);
+
+    let housing = engine.state.qtree.get_address(2, 2).unwrap();
+    let workplace = engine.state.qtree.get_address(40, 2).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 1,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 1,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let points = [(3.0, 3.0), (39.0, 3.0)];
+    let on_ramp = engine.state.highways.add_junction(
+        points[0],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OnRamp)),
+    );
+    let off_ramp = engine.state.highways.add_junction(
+        points[1],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OffRamp)),
+    );
+    let segment = engine.state.highways.add_segment(
+        highway::HighwaySegment::new(None, vec![], None, Some(40)),
+        on_ramp,
+        off_ramp,
+        Some(vec![points[0].into(), points[1].into()]),
+    );
+
+    // the household's car makes sure that the agent drives
+    let household = engine.add_household(housing, 1).unwrap();
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    engine
+        .add_household_agent(household, data, Some(workplace))
+        .unwrap();
+    engine.init_trigger_queue();
+
+    let query_input = route::QueryInput {
+        start: housing,
+        end: workplace,
+        car_config: Some(route::CarConfig::StartWithCar),
+        profile: route::MobilityProfile::STANDARD,
+        allowed_modes: Default::default(),
+    };
+
+    (engine, query_input, segment)
+}
+
+fn uses_segment(route: &route::Route, segment: network::SegmentHandle) -> bool {
+    route
+        .edges
+        .iter()
+        .any(|edge| matches!(edge, route::Edge::Highway { segment: s, .. } if *s == segment))
+}
+
+fn remove_segment(engine: &mut Engine, segment: network::SegmentHandle) {
+    let change_set = engine.begin_change_set().unwrap();
+    engine
+        .staged_remove_highway_segment(change_set, segment)
+        .unwrap();
+    engine.commit_change_set(change_set).unwrap();
+}
+
+#[test]
+fn stale_route_test() {
+    let (mut engine, query_input, segment) = generate_map();
+
+    let route = engine.query_route(query_input).unwrap().unwrap();
+    assert!(uses_segment(&route, segment));
+    assert!(engine.is_route_current(&route));
+    assert!(!engine.route_uses_removed_segments(&route));
+
+    // new traffic predictions make the route stale, but it can still be used
+    engine.update_route_weights(0);
+    assert!(!engine.is_route_current(&route));
+    assert_eq!(route.graph_version.network, engine.graph_version().network);
+    assert!(!engine.route_uses_removed_segments(&route));
+
+    remove_segment(&mut engine, segment);
+    assert!(!engine.is_route_current(&route));
+    assert!(engine.route_uses_removed_segments(&route));
+
+    // a fresh route is current again, and avoids the removed segment
+    if let Some(new_route) = engine.query_route(query_input).unwrap() {
+        assert!(engine.is_route_current(&new_route));
+        assert!(!uses_segment(&new_route, segment));
+        assert!(!engine.route_uses_removed_segments(&new_route));
+    }
+}
+
+#[test]
+fn requery_stale_route_test() {
+    let (mut engine, _, segment) = generate_map();
+    engine.trigger_stats.enable_profiling();
+    let count = |engine: &Engine, kind| engine.trigger_stats.stats[kind].count;
+
+    // the agent queries their route when planning the commute, but leaves a few seconds later
+    while count(&engine, TriggerKind::AgentPlanCommuteToWork) == 0 {
+        engine.tick(Time::new::<second>(1).value).unwrap();
+    }
+    assert_eq!(count(&engine, TriggerKind::AgentRouteStart), 0);
+
+    remove_segment(&mut engine, segment);
+
+    while count(&engine, TriggerKind::AgentRouteStart) == 0 {
+        engine.tick(Time::new::<second>(1).value).unwrap();
+    }
+
+    let health = engine.routing_health();
+    assert_eq!(health.routes_requeried, 1);
+
+    // the agent is on the way along the new route, which doesn't use the removed segment
+    let agent = engine.agents.values().next().unwrap();
+    if let agent::AgentState::Route(route_state) = &agent.state {
+        assert!(!uses_segment(&route_state.route, segment));
+    }
+}
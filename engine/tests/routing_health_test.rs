@@ -0,0 +1,73 @@
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_all, test_config};
+use uom::si::time::{day, hour};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 3;
+// large enough that the far corner of the map is out of reach without any networks
+const MIN_TILE_SIZE: u32 = 3000;
+
+fn set_tile(engine: &mut Engine, (x, y): (u64, u64), tile: tiles::Tile) -> quadtree::Address {
+    let address = engine.state.qtree.get_address(x, y).unwrap();
+    engine.state.qtree.get_leaf_mut(address).unwrap().tile = tile;
+    address
+}
+
+fn workplace() -> tiles::Tile {
+    tiles::WorkplaceTile {
+        density: 1,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into()
+}
+
+#[test]
+fn disconnected_workplace_test() {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    let housing = set_tile(
+        &mut engine,
+        (0, 0),
+        tiles::HousingTile {
+            density: 2,
+            agents: vec![],
+        }
+        .into(),
+    );
+    // close enough to drive to directly
+    let connected = set_tile(&mut engine, (1, 0), workplace());
+    // no way to get here
+    let disconnected = set_tile(&mut engine, (7, 7), workplace());
+
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    engine
+        .add_agent(data.clone(), housing, Some(connected))
+        .unwrap();
+    engine.add_agent(data, housing, Some(disconnected)).unwrap();
+
+    engine.init_trigger_queue();
+    assert_eq!(engine.routing_health(), Default::default());
+
+    // the simulation starts on a Wednesday, so both agents try to go to work
+    let noon = Time::new::<hour>(12).value;
+    engine.tick(noon).unwrap();
+    let health = engine.routing_health();
+    assert_eq!(health.failed_to_work_today, 1);
+    assert_eq!(health.failed_to_work_total, 1);
+    assert_eq!(health.teleported_home, 0);
+
+    engine.tick(Time::new::<day>(1).value).unwrap();
+    let health = engine.routing_health();
+    assert_eq!(health.failed_to_work_today, 1);
+    assert_eq!(health.failed_to_work_total, 2);
+    // there is no route home from the disconnected workplace either
+    assert_eq!(health.teleported_home, 1);
+    assert_eq!(health.routes_aborted, 0);
+
+    // the daily counter starts over the next day
+    engine.tick(noon).unwrap();
+    assert_eq!(engine.routing_health().failed_to_work_today, 0);
+    assert_eq!(engine.routing_health().failed_to_work_total, 2);
+}
@@ -0,0 +1,160 @@
+use engine::{AgentDataDistribution, Engine, Scenario};
+use test_support::{split_all, test_config};
+use uom::si::time::{hour, minute};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 50;
+
+/// Generate a map with one agent who lives and works at opposite ends of a highway.
+fn generate_map() -> (Engine, network::SegmentHandle) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+
+    split_all(&mut engine);
+
+    let housing = engine.state.qtree.get_address(2, 2).unwrap();
+    let workplace = engine.state.qtree.get_address(40, 2).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 4,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 4,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let points = [(3.0, 3.0), (39.0, 3.0)];
+    let on_ramp = engine.state.highways.add_junction(
+        points[0],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OnRamp)),
+    );
+    let off_ramp = engine.state.highways.add_junction(
+        points[1],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OffRamp)),
+    );
+    let segment = engine.state.highways.add_segment(
+        highway::HighwaySegment::new(None, vec![], None, Some(40)),
+        on_ramp,
+        off_ramp,
+        Some(vec![points[0].into(), points[1].into()]),
+    );
+
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    engine.add_agent(data, housing, Some(workplace)).unwrap();
+    engine.init_trigger_queue();
+
+    (engine, segment)
+}
+
+fn simulate_until(engine: &mut Engine, time: u64) {
+    while engine.time_state.current_time < time {
+        engine.tick(Time::new::<minute>(1).value).unwrap();
+    }
+}
+
+fn segment_active(engine: &Engine, segment: network::SegmentHandle) -> bool {
+    matches!(
+        engine.state.highways.try_segment(segment),
+        Some(segment) if segment.change_state.is_active()
+    )
+}
+
+#[test]
+fn two_event_scenario_test() {
+    let (mut engine, segment) = generate_map();
+    let scenario = Scenario::load(&format!(
+        r#"
+        [[events]]
+        day = 1
+        hour = 2
+        type = "AddAgents"
+        housing = [2, 2]
+        workplace = [40, 2]
+        count = 2
+
+        [[events]]
+        day = 0
+        hour = 12
+        type = "CloseHighwaySegments"
+        segments = [{}]
+        "#,
+        segment.inner()
+    ))
+    .unwrap();
+    engine.schedule_scenario(scenario).unwrap();
+
+    // events are applied in order of time, not in the order they are written
+    let upcoming = engine.upcoming_scenario_events();
+    assert_eq!(upcoming.len(), 2);
+    assert_eq!(upcoming[0].time(), Time::new::<hour>(12).value);
+
+    simulate_until(&mut engine, Time::new::<hour>(12).value - 1);
+    assert!(segment_active(&engine, segment));
+    assert_eq!(engine.agents.len(), 1);
+
+    simulate_until(&mut engine, Time::new::<hour>(12).value + 1);
+    assert!(!segment_active(&engine, segment));
+    assert_eq!(engine.agents.len(), 1);
+    assert_eq!(engine.upcoming_scenario_events().len(), 1);
+
+    simulate_until(&mut engine, Time::new::<hour>(26).value - 1);
+    assert_eq!(engine.agents.len(), 1);
+
+    simulate_until(&mut engine, Time::new::<hour>(26).value + 1);
+    assert_eq!(engine.agents.len(), 3);
+    assert!(engine.upcoming_scenario_events().is_empty());
+
+    // the new agents go about their day like everyone else
+    simulate_until(&mut engine, Time::new::<hour>(48).value);
+    for agent in engine.agents.values() {
+        assert!(
+            agent
+                .route_lengths
+                .contains_key(&agent::RouteType::CommuteToWork),
+            "{:?}",
+            agent
+        );
+    }
+}
+
+#[test]
+fn invalid_scenario_test() {
+    let (mut engine, segment) = generate_map();
+
+    let close = |day: u64| {
+        format!(
+            "[[events]]\nday = {}\ntype = \"CloseHighwaySegments\"\nsegments = [{}]\n",
+            day,
+            segment.inner()
+        )
+    };
+
+    // the second closure refers to a segment that was already closed by the first
+    let scenario = Scenario::load(&format!("{}{}", close(1), close(2))).unwrap();
+    let err = engine.schedule_scenario(scenario).unwrap_err();
+    assert!(
+        matches!(err.without_context(), engine::Error::InvalidScenario(_)),
+        "{}",
+        err
+    );
+    assert!(engine.upcoming_scenario_events().is_empty());
+
+    let scenario = Scenario::load(
+        r#"
+        [[events]]
+        day = 1
+        type = "AddAgents"
+        housing = [40, 2]
+        count = 1
+        "#,
+    )
+    .unwrap();
+    assert!(engine.schedule_scenario(scenario).is_err());
+
+    let scenario = Scenario::load(&close(1)).unwrap();
+    engine.schedule_scenario(scenario).unwrap();
+    assert_eq!(engine.upcoming_scenario_events().len(), 1);
+}
@@ -0,0 +1,172 @@
+use engine::{AgentDataDistribution, Engine, FieldsState};
+use state::{BranchState, LeafState};
+use test_support::test_config;
+
+const MAX_DEPTH: u32 = 2;
+
+fn schema(name: &str) -> serde_json::Value {
+    let (_, schema) = engine::all_schemas()
+        .into_iter()
+        .find(|(schema_name, _)| *schema_name == name)
+        .unwrap_or_else(|| panic!("missing schema: {}", name));
+    serde_json::to_value(schema).unwrap()
+}
+
+fn validation_errors(name: &str, instance: &serde_json::Value) -> Vec<String> {
+    let schema = schema(name);
+    let compiled = jsonschema::JSONSchema::compile(&schema).expect("invalid schema");
+    let result = compiled.validate(instance);
+    match result {
+        Ok(()) => vec![],
+        Err(errors) => errors.map(|err| err.to_string()).collect(),
+    }
+}
+
+fn assert_valid<T: serde::Serialize>(name: &str, instance: &T) {
+    let instance = serde_json::to_value(instance).unwrap();
+    let errors = validation_errors(name, &instance);
+    assert!(
+        errors.is_empty(),
+        "{} does not match its schema: {:#?}\n{:#}",
+        name,
+        errors,
+        instance
+    );
+}
+
+fn tiles() -> Vec<tiles::Tile> {
+    vec![
+        tiles::EmptyTile {}.into(),
+        tiles::WaterTile {}.into(),
+        tiles::HousingTile {
+            density: 3,
+            agents: vec![1, 2],
+        }
+        .into(),
+        tiles::WorkplaceTile {
+            density: 2,
+            agents: vec![3],
+            industry: tiles::Industry::Healthcare,
+        }
+        .into(),
+        tiles::MetroStationTile {
+            name: "Civic Center".to_string(),
+            x: 1,
+            y: 2,
+            ids: vec![0],
+            parking: false,
+        }
+        .into(),
+    ]
+}
+
+fn config() -> state::Config {
+    test_config(MAX_DEPTH, 100)
+}
+
+#[test]
+fn tiles_match_schema() {
+    for tile in tiles() {
+        assert_valid("tile", &tile);
+
+        let leaf = LeafState::<FieldsState> {
+            tile,
+            creation_time: 0,
+            ..Default::default()
+        };
+        assert_valid("leaf_state", &leaf);
+    }
+    assert_valid("leaf_state", &LeafState::<FieldsState>::default());
+    assert_valid("branch_state", &BranchState::<FieldsState>::default());
+}
+
+#[test]
+fn invalid_leaf_does_not_match_schema() {
+    let leaf = serde_json::json!({
+        "tile": {"type": "LavaTile"},
+        "creation_time": 0,
+    });
+    assert!(!validation_errors("leaf_state", &leaf).is_empty());
+
+    let leaf = serde_json::json!({
+        "tile": {"type": "HousingTile", "density": -1, "agents": []},
+        "creation_time": 0,
+    });
+    assert!(!validation_errors("leaf_state", &leaf).is_empty());
+}
+
+#[test]
+fn networks_match_schema() {
+    assert_valid("config", &config());
+
+    assert_valid(
+        "highway_segment",
+        &highway::HighwaySegment::new(
+            Some("US 101".to_string()),
+            vec!["101".to_string()],
+            Some(4),
+            None,
+        ),
+    );
+    assert_valid(
+        "highway_junction",
+        &highway::HighwayJunction::new(Some(highway::RampDirection::OffRamp)),
+    );
+    assert_valid("highway_junction", &highway::HighwayJunction::new(None));
+
+    assert_valid("railway_segment", &metro::RailwaySegment::new(Some(20)));
+    assert_valid(
+        "railway_junction",
+        &metro::RailwayJunction::new(Some(metro::Station {
+            name: "Civic Center".to_string(),
+            address: quadtree::Address::from_xy(1, 2, MAX_DEPTH),
+        })),
+    );
+    assert_valid("railway_junction", &metro::RailwayJunction::new(None));
+    assert_valid(
+        "metro_line_data",
+        &metro::MetroLineData {
+            color: (255, 0, 0).into(),
+            name: "Red".to_string(),
+            schedule: metro::Schedule::fixed_frequency(300),
+            speed_limit: 20,
+        },
+    );
+}
+
+#[test]
+fn save_file_matches_schema() {
+    let mut engine = Engine::new(config());
+    engine
+        .state
+        .qtree
+        .split(
+            quadtree::Address::from((vec![], MAX_DEPTH)),
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+
+    let housing = engine.state.qtree.get_address(0, 0).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 1,
+        agents: vec![],
+    }
+    .into();
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    assert_valid("agent_data", &data);
+    engine.add_agent(data, housing, None).unwrap();
+    engine.init_trigger_queue();
+
+    let dump: serde_json::Value = serde_json::from_str(&engine.dump().unwrap()).unwrap();
+    let errors = validation_errors("save_file", &dump);
+    assert!(errors.is_empty(), "{:#?}", errors);
+
+    // the leaves in the save file can be validated individually too
+    let leaf = engine
+        .state
+        .get_leaf_data(housing, state::SerdeFormat::Json)
+        .unwrap();
+    let leaf: serde_json::Value = serde_json::from_str(&leaf).unwrap();
+    assert!(validation_errors("leaf_state", &leaf).is_empty());
+}
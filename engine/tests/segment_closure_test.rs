@@ -0,0 +1,217 @@
+use engine::{AgentDataDistribution, Engine, NetworkSegment, Scenario, Watcher};
+use test_support::{highway_commute_map, test_config};
+use uom::si::time::{hour, minute};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 50;
+
+fn query_driving_route(
+    engine: &Engine,
+    start: quadtree::Address,
+    end: quadtree::Address,
+) -> route::Route {
+    engine
+        .query_route(route::QueryInput {
+            start,
+            end,
+            car_config: Some(route::CarConfig::StartWithCar),
+            profile: Default::default(),
+            allowed_modes: Default::default(),
+        })
+        .unwrap()
+        .expect("expected a route")
+}
+
+fn uses_edge(edge: &route::Edge, segment: network::SegmentHandle) -> bool {
+    NetworkSegment::Highway(segment).is_used_by(edge)
+}
+
+fn uses(route: &route::Route, segment: network::SegmentHandle) -> bool {
+    route.edges.iter().any(|edge| uses_edge(edge, segment))
+}
+
+fn closed_until(engine: &Engine, segment: network::SegmentHandle) -> Option<u64> {
+    engine
+        .segment_change_state(NetworkSegment::Highway(segment))
+        .and_then(|change_state| change_state.closed_until())
+}
+
+fn simulate_until(engine: &mut Engine, time: u64) {
+    while engine.time_state.current_time < time {
+        engine.time_state.skip_by(Time::new::<minute>(1).value);
+        engine.update(0.0, f64::INFINITY).unwrap();
+        engine.consistency_check().unwrap();
+    }
+}
+
+#[test]
+fn closure_reroutes_agents_test() {
+    let (mut engine, segment, housing, workplace) =
+        highway_commute_map(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+
+    let mut data = AgentDataDistribution::default().sample(&mut engine.rng);
+    data.owns_car = true;
+    let id = engine.add_agent(data, housing, Some(workplace)).unwrap();
+
+    let route = query_driving_route(&engine, housing, workplace);
+    assert!(uses(&route, segment));
+
+    // put the agent partway along the highway
+    let agent = engine.agents.get_mut(&id).unwrap();
+    agent
+        .begin_route(
+            route,
+            0,
+            agent::RouteType::CommuteToWork,
+            agent::RouteDetail::Fine,
+            &mut engine.world_state,
+            &engine.state,
+        )
+        .unwrap();
+    loop {
+        let route_state = match &mut agent.state {
+            agent::AgentState::Route(route_state) => route_state,
+            _ => panic!("expected the agent to be following a route"),
+        };
+        let next_trigger = route_state.next_trigger().unwrap();
+        if let agent::AgentRoutePhase::InProgress { current_edge, .. } = route_state.phase {
+            if uses_edge(&route_state.route.edges[current_edge as usize], segment) {
+                engine.time_state.current_time = next_trigger - 10;
+                break;
+            }
+        }
+        engine.time_state.current_time = next_trigger;
+        route_state
+            .advance(&mut engine.world_state, &engine.state)
+            .unwrap();
+    }
+    engine.consistency_check().unwrap();
+
+    let version = engine.graph_version().network;
+    let now = engine.time_state.current_time;
+    engine
+        .close_segment_for_hours(NetworkSegment::Highway(segment), 2)
+        .unwrap();
+    let until = now + Time::new::<hour>(2).value;
+    assert_eq!(closed_until(&engine, segment), Some(until));
+    assert_ne!(engine.graph_version().network, version);
+
+    // the agent stopped where they were, and parked the car there
+    let agent = &engine.agents[&id];
+    let address = match agent.state {
+        agent::AgentState::Tile(address) => address,
+        ref state => panic!("expected the agent to be interrupted: {:?}", state),
+    };
+    assert_ne!(address, housing);
+    assert_eq!(agent.parked_car(), Some(address));
+    engine.consistency_check().unwrap();
+
+    let alert = engine
+        .alerts
+        .iter()
+        .find(|alert| alert.watcher == Watcher::SegmentClosure)
+        .expect("expected an alert");
+    assert!(alert.message.contains("1 agent(s) rerouted"), "{:?}", alert);
+
+    // closing the segment again doesn't shorten the closure
+    engine
+        .close_segment(NetworkSegment::Highway(segment), 60)
+        .unwrap();
+    assert_eq!(closed_until(&engine, segment), Some(until));
+
+    // new routes avoid the segment, and the agent still makes it to work
+    assert!(!uses(
+        &query_driving_route(&engine, housing, workplace),
+        segment
+    ));
+    let mut traveled = false;
+    while engine.time_state.current_time < now + Time::new::<hour>(1).value {
+        let next = engine.time_state.current_time + 1;
+        simulate_until(&mut engine, next);
+        if let agent::AgentState::Route(route_state) = &engine.agents[&id].state {
+            traveled = true;
+            assert!(!route_state
+                .remaining_edges()
+                .iter()
+                .any(|edge| uses_edge(edge, segment)));
+        }
+    }
+    assert!(traveled);
+    assert!(matches!(
+        engine.agents[&id].state,
+        agent::AgentState::Tile(address) if address == workplace
+    ));
+
+    // the segment reopens on its own, and routes use it again
+    let version = engine.graph_version().network;
+    simulate_until(&mut engine, until + 1);
+    assert_eq!(closed_until(&engine, segment), None);
+    assert!(engine
+        .segment_change_state(NetworkSegment::Highway(segment))
+        .unwrap()
+        .is_active());
+    assert_ne!(engine.graph_version().network, version);
+    assert!(uses(
+        &query_driving_route(&engine, housing, workplace),
+        segment
+    ));
+    assert_eq!(
+        engine
+            .alerts
+            .iter()
+            .filter(|alert| alert.watcher == Watcher::SegmentClosure)
+            .count(),
+        3
+    );
+}
+
+#[test]
+fn invalid_closure_test() {
+    let (mut engine, segment, _, _) = highway_commute_map(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+
+    let err = engine
+        .close_segment(NetworkSegment::Highway(segment), 0)
+        .unwrap_err();
+    assert!(matches!(err, engine::Error::InvalidClosure(_)), "{}", err);
+
+    // there are no railways
+    assert!(engine
+        .close_segment(NetworkSegment::Railway(segment), 60)
+        .is_err());
+    assert_eq!(closed_until(&engine, segment), None);
+}
+
+#[test]
+fn scenario_closure_test() {
+    let (mut engine, segment, _, _) = highway_commute_map(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    engine.init_trigger_queue();
+
+    let close = |hours: u64| {
+        format!(
+            "[[events]]\nday = 0\nhour = 1\ntype = \"TemporarilyCloseHighwaySegments\"\n\
+             segments = [{}]\nhours = {}\n",
+            segment.inner(),
+            hours
+        )
+    };
+
+    let scenario = Scenario::load(&close(0)).unwrap();
+    assert!(engine.schedule_scenario(scenario).is_err());
+
+    // unlike a permanent closure, the segment can be closed again later
+    let scenario = Scenario::load(&format!("{}{}", close(2), close(3))).unwrap();
+    engine.schedule_scenario(scenario).unwrap();
+
+    simulate_until(&mut engine, Time::new::<hour>(1).value + 1);
+    assert_eq!(
+        closed_until(&engine, segment),
+        Some(Time::new::<hour>(4).value)
+    );
+
+    simulate_until(&mut engine, Time::new::<hour>(3).value + 1);
+    assert!(closed_until(&engine, segment).is_some());
+
+    simulate_until(&mut engine, Time::new::<hour>(4).value + 1);
+    assert_eq!(closed_until(&engine, segment), None);
+}
@@ -7,6 +7,12 @@ use uom::si::u64::Time;
 #[derive(clap::Parser, Debug)]
 struct Args {
     days: Option<u64>,
+    /// TOML file with a timeline of events to apply during the simulation
+    #[clap(long)]
+    scenario: Option<PathBuf>,
+    /// print estimated memory use at the end of each simulated day
+    #[clap(long)]
+    memory_report: bool,
 }
 
 fn main() {
@@ -15,8 +21,18 @@ fn main() {
 
     let days = args.days.unwrap_or(7);
 
+    // e.g. RUST_LOG=engine=debug,route=debug logs how long each trigger and route query takes
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .init();
+
     let mut engine = engine::Engine::load_file(&PathBuf::from("maps/sf.json")).unwrap();
     engine.init_trigger_queue();
+    if let Some(scenario) = &args.scenario {
+        engine.load_scenario(scenario).unwrap();
+    }
     let start_time = Instant::now();
 
     let total = Time::new::<day>(days).value;
@@ -25,14 +41,50 @@ fn main() {
     assert_eq!(total % step_size, 0);
 
     let steps = total / step_size;
+    let steps_per_day = Time::new::<day>(1).value / step_size;
 
     // TODO: Printing will mess up the progress bar. indicatif provides a function for printing
     // above the bar in a pretty way, but then we have to use that everywhere.
     let progress = indicatif::ProgressBar::new(steps);
 
-    for _ in 0..steps {
-        engine.time_state.skip_by(step_size);
-        engine.update(0.0, f64::INFINITY).unwrap();
+    let mut next_alert = 0;
+    for step in 0..steps {
+        engine.tick(step_size).unwrap();
+
+        let alerts: Vec<_> = engine
+            .alerts
+            .iter()
+            .filter(|alert| alert.id >= next_alert)
+            .cloned()
+            .collect();
+        for alert in alerts {
+            let line = format!(
+                "[{}] {}: {}",
+                alert.severity,
+                engine.time_state.pretty_date_time(alert.time),
+                alert.message
+            );
+            // the progress bar draws to stderr, but it is hidden if stderr is not a terminal
+            if progress.is_hidden() {
+                eprintln!("{}", line);
+            } else {
+                progress.println(line);
+            }
+            next_alert = alert.id + 1;
+        }
+
+        if args.memory_report && (step + 1) % steps_per_day == 0 {
+            let report = format!(
+                "Memory after day {}:\n{}",
+                (step + 1) / steps_per_day,
+                engine.memory_report()
+            );
+            if progress.is_hidden() {
+                eprintln!("{}", report);
+            } else {
+                progress.println(report);
+            }
+        }
 
         progress.inc(1);
     }
@@ -46,6 +98,7 @@ fn main() {
     if engine.trigger_stats.profiling_enabled {
         engine.trigger_stats.print();
     }
+    print!("{}", engine.route_latency().to_text());
 
     println!();
 }
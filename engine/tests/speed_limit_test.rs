@@ -0,0 +1,258 @@
+use engine::{Engine, NetworkSegment, Scenario};
+use metro::RailwayTiming;
+use test_support::{split_all, test_config};
+use uom::si::time::{hour, minute};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 5;
+const MIN_TILE_SIZE: u32 = 100;
+
+const STATIONS: [(u64, u64); 3] = [(4, 4), (12, 4), (28, 4)];
+
+struct Map {
+    engine: Engine,
+    line: metro::MetroLineHandle,
+    /// from the first station to the second, then from the second to the third
+    railway_segments: Vec<network::SegmentHandle>,
+    highway_segment: network::SegmentHandle,
+}
+
+/// Generate a map with a metro line through three stations, and a highway that runs alongside.
+fn generate_map() -> Map {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    let mut junctions = Vec::new();
+    for (x, y) in STATIONS {
+        let address = engine.state.qtree.get_address(x, y).unwrap();
+        engine.state.qtree.get_leaf_mut(address).unwrap().tile = tiles::MetroStationTile {
+            name: format!("{}, {}", x, y),
+            x,
+            y,
+            ids: vec![],
+            parking: true,
+        }
+        .into();
+        junctions.push(engine.state.railways.add_junction(
+            (x as f64, y as f64),
+            metro::RailwayJunction::new(Some(metro::Station {
+                name: format!("{}, {}", x, y),
+                address,
+            })),
+        ));
+    }
+    let railway_segments: Vec<_> = (0..2)
+        .map(|i| {
+            let ((x1, y1), (x2, y2)) = (STATIONS[i], STATIONS[i + 1]);
+            engine.state.railways.add_segment(
+                metro::RailwaySegment::new(None),
+                junctions[i],
+                junctions[i + 1],
+                Some(vec![
+                    (x1 as f64, y1 as f64).into(),
+                    (x2 as f64, y2 as f64).into(),
+                ]),
+            )
+        })
+        .collect();
+    let line = engine.state.metros.add_metro_line(
+        metro::MetroLineData {
+            color: (255, 0, 0).into(),
+            name: "Red".to_string(),
+            schedule: metro::Schedule::fixed_frequency(300),
+            speed_limit: 20,
+        },
+        railway_segments.clone(),
+        &engine.state.railways,
+    );
+    for (x, y) in STATIONS {
+        let address = engine.state.qtree.get_address(x, y).unwrap();
+        match &mut engine.state.qtree.get_leaf_mut(address).unwrap().tile {
+            tiles::Tile::MetroStationTile(tile) => tile.ids.push(line.inner()),
+            tile => panic!("expected a station, found {:?}", tile),
+        }
+    }
+
+    let points = [(4.0, 20.0), (28.0, 20.0)];
+    let on_ramp = engine.state.highways.add_junction(
+        points[0],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OnRamp)),
+    );
+    let off_ramp = engine.state.highways.add_junction(
+        points[1],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OffRamp)),
+    );
+    let highway_segment = engine.state.highways.add_segment(
+        highway::HighwaySegment::new(None, vec![], None, Some(30)),
+        on_ramp,
+        off_ramp,
+        Some(vec![points[0].into(), points[1].into()]),
+    );
+
+    Map {
+        engine,
+        line,
+        railway_segments,
+        highway_segment,
+    }
+}
+
+/// The time of the base graph edge along the given segment.
+fn edge_time(engine: &Engine, segment: NetworkSegment) -> f64 {
+    let base_graph = engine.base_graph.read().unwrap();
+    let time = base_graph
+        .get_base_graph(&engine.state)
+        .graph
+        .get_edge_map()
+        .values()
+        .find_map(|edge| match edge {
+            route::Edge::MetroSegment { time, .. } | route::Edge::Highway { time, .. }
+                if segment.is_used_by(edge) =>
+            {
+                Some(*time)
+            }
+            _ => None,
+        })
+        .expect("missing edge");
+    time
+}
+
+/// How long the metro line takes from the first station to the last.
+fn end_to_end_time(engine: &Engine, line: metro::MetroLineHandle) -> f64 {
+    let railways = &engine.state.railways;
+    let metro_line = engine.state.metros.metro_line(line);
+    metro_line
+        .segments()
+        .iter()
+        .map(|oriented| {
+            railways.segment(oriented.segment).railway_travel_time(
+                metro_line.data.speed_limit,
+                MIN_TILE_SIZE as f64,
+                railways,
+            )
+        })
+        .sum()
+}
+
+#[test]
+fn railway_speed_limit_test() {
+    let Map {
+        mut engine,
+        line,
+        railway_segments,
+        highway_segment,
+    } = generate_map();
+    let lowered = NetworkSegment::Railway(railway_segments[0]);
+    let unrelated = NetworkSegment::Railway(railway_segments[1]);
+    let highway = NetworkSegment::Highway(highway_segment);
+
+    let before_time = end_to_end_time(&engine, line);
+    let before = [lowered, unrelated, highway].map(|segment| edge_time(&engine, segment));
+    let version = engine.graph_version();
+
+    let affected = engine.set_segment_speed_limit(lowered, Some(5)).unwrap();
+    assert_eq!(affected.into_iter().collect::<Vec<_>>(), vec![line]);
+    assert_ne!(engine.graph_version().network, version.network);
+
+    assert!(end_to_end_time(&engine, line) > before_time);
+    let after = [lowered, unrelated, highway].map(|segment| edge_time(&engine, segment));
+    assert!(after[0] > before[0], "{:?} {:?}", before, after);
+    assert_eq!(after[1], before[1]);
+    assert_eq!(after[2], before[2]);
+
+    // removing the limit again restores the original timing
+    engine.set_segment_speed_limit(lowered, None).unwrap();
+    assert!((end_to_end_time(&engine, line) - before_time).abs() < 1e-6);
+    assert!((edge_time(&engine, lowered) - before[0]).abs() < 1e-6);
+}
+
+#[test]
+fn highway_speed_limit_test() {
+    let Map {
+        mut engine,
+        railway_segments,
+        highway_segment,
+        ..
+    } = generate_map();
+    let highway = NetworkSegment::Highway(highway_segment);
+    let railway = NetworkSegment::Railway(railway_segments[0]);
+
+    let before = [highway, railway].map(|segment| edge_time(&engine, segment));
+    let affected = engine.set_segment_speed_limit(highway, Some(10)).unwrap();
+    assert!(affected.is_empty());
+    assert_eq!(
+        engine
+            .state
+            .highways
+            .segment(highway_segment)
+            .data
+            .speed_limit,
+        Some(10)
+    );
+
+    let after = [highway, railway].map(|segment| edge_time(&engine, segment));
+    assert!((after[0] - before[0] * 3.0).abs() < 1e-6, "{:?}", after);
+    assert_eq!(after[1], before[1]);
+
+    // invalid limits are rejected, leaving the segment and the graph alone
+    let version = engine.graph_version();
+    let err = engine
+        .set_segment_speed_limit(highway, Some(0))
+        .unwrap_err();
+    assert!(
+        matches!(err, engine::Error::InvalidSegmentData(_)),
+        "{}",
+        err
+    );
+    assert!(engine.set_segment_speed_limit(railway, Some(0)).is_err());
+    assert_eq!(engine.graph_version(), version);
+    assert_eq!(
+        engine
+            .state
+            .highways
+            .segment(highway_segment)
+            .data
+            .speed_limit,
+        Some(10)
+    );
+}
+
+#[test]
+fn scenario_speed_limit_test() {
+    let Map {
+        mut engine,
+        line,
+        railway_segments,
+        ..
+    } = generate_map();
+    engine.init_trigger_queue();
+    let before_time = end_to_end_time(&engine, line);
+
+    let lower = |speed_limit: u32| {
+        format!(
+            "[[events]]\nday = 0\nhour = 1\ntype = \"SetRailwaySpeedLimit\"\n\
+             segments = [{}]\nspeed_limit = {}\n",
+            railway_segments[1].inner(),
+            speed_limit
+        )
+    };
+    let scenario = Scenario::load(&lower(0)).unwrap();
+    assert!(engine.schedule_scenario(scenario).is_err());
+    let scenario = Scenario::load(&lower(5)).unwrap();
+    engine.schedule_scenario(scenario).unwrap();
+
+    while engine.time_state.current_time < Time::new::<hour>(1).value + 1 {
+        engine.time_state.skip_by(Time::new::<minute>(1).value);
+        engine.update(0.0, f64::INFINITY).unwrap();
+    }
+    assert_eq!(
+        engine
+            .state
+            .railways
+            .segment(railway_segments[1])
+            .data
+            .speed_limit,
+        Some(5)
+    );
+    assert!(end_to_end_time(&engine, line) > before_time);
+}
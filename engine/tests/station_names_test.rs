@@ -0,0 +1,98 @@
+use engine::Engine;
+use test_support::test_config;
+
+const MAX_DEPTH: u32 = 3;
+
+fn station(name: &str, (x, y): (u64, u64)) -> metro::Station {
+    metro::Station {
+        name: name.to_string(),
+        address: quadtree::Address::from_xy(x, y, MAX_DEPTH),
+    }
+}
+
+fn add_metro_line(engine: &mut Engine, name: &str, from: &metro::Station, to: &metro::Station) {
+    let position = |station: &metro::Station| {
+        let (x, y) = station.address.to_xy();
+        (x as f64, y as f64)
+    };
+
+    let railways = &mut engine.state.railways;
+    let start = railways.add_junction(
+        position(from),
+        metro::RailwayJunction::new(Some(from.clone())),
+    );
+    let end = railways.add_junction(position(to), metro::RailwayJunction::new(Some(to.clone())));
+    let segment = railways.add_segment(
+        metro::RailwaySegment::new(None),
+        start,
+        end,
+        Some(vec![position(from).into(), position(to).into()]),
+    );
+    engine.state.metros.add_metro_line(
+        metro::MetroLineData {
+            color: (255, 0, 0).into(),
+            name: name.to_string(),
+            schedule: metro::Schedule::fixed_frequency(300),
+            speed_limit: 20,
+        },
+        vec![segment],
+        &engine.state.railways,
+    );
+}
+
+/// Two metro lines, where station A shares its name with another station.
+fn generate_map() -> (Engine, [metro::Station; 3]) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    let a = station("A", (0, 4));
+    let b = station("B", (7, 4));
+    let other_a = station("A", (7, 0));
+    add_metro_line(&mut engine, "AB", &a, &b);
+    add_metro_line(&mut engine, "BA", &b, &other_a);
+    (engine, [a, b, other_a])
+}
+
+fn assert_indexed(engine: &Engine, a: &metro::Station, b: &metro::Station) {
+    let metros = &engine.state.metros;
+    assert_eq!(metros.station_by_name("A"), Some(a));
+    assert_eq!(metros.station_by_name("B"), Some(b));
+    assert_eq!(metros.station_by_name("C"), None);
+    assert_eq!(
+        metros.duplicate_station_names().collect::<Vec<_>>(),
+        vec!["A"]
+    );
+}
+
+#[test]
+fn round_trip_test() {
+    let (engine, [a, b, _]) = generate_map();
+    assert_indexed(&engine, &a, &b);
+
+    // the index isn't saved, but is rebuilt when loading
+    let save: serde_json::Value = serde_json::from_str(&engine.dump().unwrap()).unwrap();
+    assert!(save["state"]["metros"].get("station_names").is_none());
+
+    let loaded = Engine::load(&engine.dump().unwrap()).unwrap();
+    assert_indexed(&loaded, &a, &b);
+}
+
+#[test]
+fn old_save_test() {
+    let (engine, [a, b, other_a]) = generate_map();
+
+    // maps from before the index was introduced don't have it, and older versions may have saved
+    // a stale one
+    let mut save: serde_json::Value = serde_json::from_str(&engine.dump().unwrap()).unwrap();
+    let metros = save["state"]["metros"].as_object_mut().unwrap();
+    metros.remove("station_names");
+    let old_save = save.to_string();
+    save["state"]["metros"].as_object_mut().unwrap().insert(
+        String::from("station_names"),
+        serde_json::json!({ "A": [other_a], "D": [b] }),
+    );
+    let stale_save = save.to_string();
+
+    for save in [old_save, stale_save] {
+        let loaded = Engine::load(&save).unwrap();
+        assert_indexed(&loaded, &a, &b);
+    }
+}
@@ -0,0 +1,96 @@
+use engine::{Engine, FieldsState};
+use state::{BranchState, LeafState};
+use test_support::test_config;
+
+const MAX_DEPTH: u32 = 2;
+
+/// Generate a map with one housing tile and one workplace tile, and one person living there.
+fn generate_map() -> (Engine, quadtree::Address, quadtree::Address) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    engine
+        .state
+        .qtree
+        .split(
+            quadtree::Address::from((vec![], MAX_DEPTH)),
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+
+    let housing = engine.state.qtree.get_address(0, 0).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 10,
+        agents: vec![],
+    }
+    .into();
+    let workplace = engine.state.qtree.get_address(3, 3).unwrap();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 10,
+        agents: vec![],
+        industry: Default::default(),
+    }
+    .into();
+
+    add_agent(&mut engine, housing, None);
+    (engine, housing, workplace)
+}
+
+fn add_agent(
+    engine: &mut Engine,
+    housing: quadtree::Address,
+    workplace: Option<quadtree::Address>,
+) {
+    let data = agent::AgentData {
+        birthday: chrono::NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+        years_of_education: 12,
+        owns_car: true,
+        walking_speed: 1.0,
+        archetype: 0,
+        noise: Default::default(),
+    };
+    engine.add_agent(data, housing, workplace).unwrap();
+}
+
+#[test]
+fn drift_test() {
+    let (mut engine, housing, workplace) = generate_map();
+
+    // nothing has been assembled yet
+    assert_eq!(*engine.stats_snapshot(), Default::default());
+
+    engine.update_fields().unwrap();
+    let stats = engine.stats_snapshot().clone();
+    assert_eq!(stats.population, 1);
+    assert_eq!(stats.agents, 1);
+    assert_eq!(stats.employed_agents, 0);
+    assert_eq!(stats.parked_cars, 1);
+    assert_eq!(stats.population_drift(), 0);
+    assert_eq!(stats.employment_drift(), 0);
+
+    add_agent(&mut engine, housing, Some(workplace));
+    add_agent(&mut engine, housing, None);
+
+    // the snapshot doesn't change by itself
+    assert_eq!(*engine.stats_snapshot(), stats);
+
+    // the fields haven't caught up with the new agents yet
+    engine.time_state.current_time = 60;
+    engine.refresh_stats_snapshot().unwrap();
+    let stats = engine.stats_snapshot().clone();
+    assert_eq!(stats.time, 60);
+    assert_eq!(stats.population, 1);
+    assert_eq!(stats.employment, 0);
+    assert_eq!(stats.agents, 3);
+    assert_eq!(stats.employed_agents, 1);
+    assert_eq!(stats.parked_cars, 3);
+    assert_eq!(stats.population_drift(), 2);
+    assert_eq!(stats.employment_drift(), 1);
+
+    engine.update_fields().unwrap();
+    let stats = engine.stats_snapshot();
+    assert_eq!(stats.population, 3);
+    assert_eq!(stats.employment, 1);
+    assert_eq!(stats.population_drift(), 0);
+    assert_eq!(stats.employment_drift(), 0);
+    assert!((stats.employment_rate() - 1.0 / 3.0).abs() < 1e-9);
+}
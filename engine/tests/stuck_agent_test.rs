@@ -0,0 +1,160 @@
+use engine::{AgentDataDistribution, Engine, FieldsState};
+use route::WorldState;
+use state::{BranchState, LeafState};
+use test_support::test_config;
+use uom::si::time::hour;
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 3;
+
+/// Generate a map with an agent that owns a car, and lives and works on opposite corners.
+fn generate_map() -> (Engine, u64) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, 100));
+    engine
+        .state
+        .qtree
+        .split(
+            quadtree::Address::from((vec![], MAX_DEPTH)),
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+
+    let housing = engine.state.qtree.get_address(0, 0).unwrap();
+    let workplace = engine.state.qtree.get_address(7, 7).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 1,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 1,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let mut data = AgentDataDistribution::default().sample(&mut engine.rng);
+    data.owns_car = true;
+    let id = engine.add_agent(data, housing, Some(workplace)).unwrap();
+
+    (engine, id)
+}
+
+/// Put the agent on their commute to work without queueing the trigger that advances them, as
+/// if the trigger had been lost.
+fn strand_on_commute(engine: &mut Engine, id: u64) {
+    let agent = &engine.agents[&id];
+    let route = engine
+        .query_route(route::QueryInput {
+            start: agent.housing,
+            end: agent.workplace.unwrap(),
+            car_config: Some(route::CarConfig::StartWithCar),
+            profile: Default::default(),
+            allowed_modes: Default::default(),
+        })
+        .unwrap()
+        .expect("expected a route to work");
+    assert!(!route.edges.is_empty());
+
+    let next_trigger = engine
+        .agents
+        .get_mut(&id)
+        .unwrap()
+        .begin_route(
+            route,
+            engine.time_state.current_time,
+            agent::RouteType::CommuteToWork,
+            agent::RouteDetail::Fine,
+            &mut engine.world_state,
+            &engine.state,
+        )
+        .unwrap();
+    assert!(next_trigger.is_some());
+}
+
+fn assert_recovered(engine: &Engine, id: u64) {
+    let agent = &engine.agents[&id];
+    assert!(matches!(agent.state, agent::AgentState::Tile(address) if address == agent.housing));
+    assert_eq!(agent.parked_car(), Some(agent.housing));
+
+    let (x, y) = agent.housing.to_xy_f64();
+    assert_eq!(engine.world_state.get_parking(x, y), 1.0);
+    // the agent may have quit their job since, so look up where it was
+    let (x, y) = engine.state.qtree.get_address(7, 7).unwrap().to_xy_f64();
+    assert_eq!(engine.world_state.get_parking(x, y), 0.0);
+
+    engine.consistency_check().unwrap();
+}
+
+#[test]
+fn stuck_in_progress_test() {
+    let (mut engine, id) = generate_map();
+    engine.watch_agent(id, None).unwrap();
+    strand_on_commute(&mut engine, id);
+
+    // not stuck yet, since the agent could still be on their way
+    engine.tick(Time::new::<hour>(1).value).unwrap();
+    assert!(!engine.recover_stuck_agent(id).unwrap());
+    assert!(matches!(
+        engine.agents[&id].state,
+        agent::AgentState::Route(_)
+    ));
+
+    engine.tick(Time::new::<hour>(12).value).unwrap();
+    assert!(engine.recover_stuck_agent(id).unwrap());
+    assert_recovered(&engine, id);
+    assert_eq!(engine.routing_health().stuck_agents_recovered, 1);
+    // logged even though this isn't called from a trigger
+    assert!(engine
+        .agent_log(id)
+        .iter()
+        .any(|line| line.ends_with("stuck on route; teleporting home")));
+
+    // recovering is only done once
+    assert!(!engine.recover_stuck_agent(id).unwrap());
+    assert_eq!(engine.routing_health().stuck_agents_recovered, 1);
+}
+
+#[test]
+fn stuck_finished_test() {
+    let (mut engine, id) = generate_map();
+    strand_on_commute(&mut engine, id);
+
+    // the agent reaches the end of the route, but the route is never finished
+    loop {
+        let agent = engine.agents.get_mut(&id).unwrap();
+        let route_state = match &mut agent.state {
+            agent::AgentState::Route(route_state) => route_state,
+            _ => panic!("expected the agent to be on a route"),
+        };
+        if route_state.finished() {
+            break;
+        }
+        route_state
+            .advance(&mut engine.world_state, &engine.state)
+            .unwrap();
+    }
+
+    engine.tick(Time::new::<hour>(12).value).unwrap();
+    assert!(engine.recover_stuck_agent(id).unwrap());
+    assert_recovered(&engine, id);
+}
+
+#[test]
+fn life_decisions_recover_test() {
+    let (mut engine, id) = generate_map();
+    strand_on_commute(&mut engine, id);
+    engine.tick(Time::new::<hour>(12).value).unwrap();
+
+    // the agent's life decisions run right away, and check on the agent first
+    engine.schedule_agent(id);
+    engine.tick(1).unwrap();
+    assert_recovered(&engine, id);
+    assert_eq!(engine.routing_health().stuck_agents_recovered, 1);
+
+    // the agent goes back to commuting as usual
+    engine.tick(Time::new::<hour>(48).value).unwrap();
+    assert_eq!(engine.routing_health().stuck_agents_recovered, 1);
+    engine.consistency_check().unwrap();
+}
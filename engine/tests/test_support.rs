@@ -0,0 +1,256 @@
+//! Fixtures shared by the engine integration tests.
+
+use engine::{AgentDataDistribution, Engine, FieldsState};
+use state::{BranchState, LeafState};
+
+/**
+ * A config for a map with the given size, with one simulated person per person and the defaults
+ * for everything else. Tests that need to change more can use struct update syntax, e.g.
+ * `state::Config { people_per_sim: 2.0, ..test_config(4, 100) }`.
+ */
+pub fn test_config(max_depth: u32, min_tile_size: u32) -> state::Config {
+    state::Config {
+        max_depth,
+        people_per_sim: 1.0,
+        min_tile_size,
+        industry_weights: Default::default(),
+        scheduling: Default::default(),
+        travel_diary: Default::default(),
+        traffic_history: Default::default(),
+        railways: Default::default(),
+        workplace_happiness: Default::default(),
+        leisure: Default::default(),
+        intersection_delay: Default::default(),
+        archetypes: Default::default(),
+        trigger_cadence: Default::default(),
+    }
+}
+
+/// Split the whole map into empty leaves of the smallest size.
+pub fn split_all(engine: &mut Engine) {
+    let max_depth = engine.state.config.max_depth;
+    split_to_depth(engine, max_depth);
+}
+
+/// Split the whole map into empty leaves at the given depth.
+pub fn split_to_depth(engine: &mut Engine, depth: u32) {
+    let root = quadtree::Address::from((vec![], engine.state.config.max_depth));
+    split_below(engine, root, depth);
+}
+
+fn split_below(engine: &mut Engine, address: quadtree::Address, depth: u32) {
+    if address.depth() == depth as usize {
+        return;
+    }
+    engine
+        .state
+        .qtree
+        .split(
+            address,
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+    for quadrant in quadtree::QUADRANTS {
+        split_below(engine, address.child(quadrant), depth);
+    }
+}
+
+/// Add a highway from an on-ramp to an off-ramp through the given points, returning the segments.
+pub fn add_highway(engine: &mut Engine, points: &[(f64, f64)]) -> Vec<network::SegmentHandle> {
+    let data = highway::HighwaySegment::new(None, vec![], None, Some(40));
+
+    let junctions: Vec<_> = points
+        .iter()
+        .enumerate()
+        .map(|(i, point)| {
+            let ramp = if i == 0 {
+                Some(highway::RampDirection::OnRamp)
+            } else if i == points.len() - 1 {
+                Some(highway::RampDirection::OffRamp)
+            } else {
+                None
+            };
+            engine
+                .state
+                .highways
+                .add_junction(*point, highway::HighwayJunction::new(ramp))
+        })
+        .collect();
+
+    (0..points.len() - 1)
+        .map(|i| {
+            engine.state.highways.add_segment(
+                data.clone(),
+                junctions[i],
+                junctions[i + 1],
+                Some(vec![points[i].into(), points[i + 1].into()]),
+            )
+        })
+        .collect()
+}
+
+/// Whether the route drives on any of the given highway segments.
+pub fn uses_segments(route: &route::Route, segments: &[network::SegmentHandle]) -> bool {
+    route.edges.iter().any(|edge| match edge {
+        route::Edge::Highway { segment, .. } => segments.contains(segment),
+        _ => false,
+    })
+}
+
+/**
+ * Generate a map with housing and a workplace at opposite ends, connected by a direct highway and
+ * a slightly longer alternative highway. Returns the commute between them and the segments of the
+ * direct highway.
+ */
+pub fn two_highway_map() -> (Engine, route::QueryInput, Vec<network::SegmentHandle>) {
+    let mut engine = Engine::new(test_config(6, 50));
+
+    split_all(&mut engine);
+
+    let housing = engine.state.qtree.get_address(2, 2).unwrap();
+    let workplace = engine.state.qtree.get_address(61, 2).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 1,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 1,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let direct = add_highway(&mut engine, &[(3.0, 3.0), (32.0, 3.0), (60.0, 3.0)]);
+    add_highway(&mut engine, &[(3.0, 5.0), (32.0, 12.0), (60.0, 5.0)]);
+
+    let commute = route::QueryInput {
+        start: housing,
+        end: workplace,
+        car_config: Some(route::CarConfig::StartWithCar),
+        profile: route::MobilityProfile::STANDARD,
+        allowed_modes: Default::default(),
+    };
+
+    (engine, commute, direct)
+}
+
+/**
+ * Generate a small map with housing on the left half and workplaces on the right half, with
+ * everyone employed. The engine RNG is seeded too, so that the whole simulation is deterministic.
+ */
+pub fn employed_map(seed: u64) -> Engine {
+    use rand::SeedableRng;
+
+    let mut engine = Engine::new(test_config(3, 100));
+    engine.rng = rand_chacha::ChaCha12Rng::seed_from_u64(seed);
+    split_all(&mut engine);
+
+    let width = engine.state.qtree.width();
+    for x in 0..width {
+        for y in 0..width {
+            let address = engine.state.qtree.get_address(x, y).unwrap();
+            let tile = if x < width / 2 {
+                tiles::HousingTile {
+                    density: 4,
+                    agents: vec![],
+                }
+                .into()
+            } else {
+                tiles::WorkplaceTile {
+                    density: 4,
+                    agents: vec![],
+                    industry: tiles::Industry::Office,
+                }
+                .into()
+            };
+            engine.state.qtree.get_leaf_mut(address).unwrap().tile = tile;
+        }
+    }
+
+    engine
+        .populate_housing(0.5, seed, &AgentDataDistribution::default())
+        .unwrap();
+    engine.assign_workplaces(1.0, 0, f64::INFINITY).unwrap();
+    engine.init_trigger_queue();
+    engine
+}
+
+/// Generate a map with an agent who lives and works on opposite corners, returning their id.
+pub fn commuter_map() -> (Engine, u64) {
+    let mut engine = Engine::new(test_config(3, 100));
+    split_to_depth(&mut engine, 1);
+
+    let housing = engine.state.qtree.get_address(0, 0).unwrap();
+    let workplace = engine.state.qtree.get_address(7, 7).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 1,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 1,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    let id = engine.add_agent(data, housing, Some(workplace)).unwrap();
+    engine.init_trigger_queue();
+
+    (engine, id)
+}
+
+/**
+ * Generate a map with housing and a workplace on opposite sides, connected by a highway. Returns the
+ * highway segment, the housing, and the workplace.
+ */
+pub fn highway_commute_map(
+    config: state::Config,
+) -> (
+    Engine,
+    network::SegmentHandle,
+    quadtree::Address,
+    quadtree::Address,
+) {
+    let mut engine = Engine::new(config);
+
+    split_all(&mut engine);
+
+    let width = engine.state.qtree.width();
+    let housing = engine.state.qtree.get_address(2, 2).unwrap();
+    let workplace = engine.state.qtree.get_address(width - 4, 2).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 4,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 4,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let segment = add_highway(&mut engine, &[(3.0, 3.0), ((width - 5) as f64, 3.0)])[0];
+
+    (engine, segment, housing, workplace)
+}
+
+/// The address where the route parks. Panics if it doesn't drive.
+pub fn parking_address(route: &route::Route) -> quadtree::Address {
+    route
+        .edges
+        .iter()
+        .find_map(|edge| match edge {
+            route::Edge::ModeTransition {
+                from: route::Mode::Driving,
+                to: route::Mode::Walking,
+                address,
+            } => Some(*address),
+            _ => None,
+        })
+        .expect("expected the route to park")
+}
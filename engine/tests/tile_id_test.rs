@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+
+use engine::{Engine, FieldsState, InsertPolicy};
+use rand::{Rng, SeedableRng};
+use state::{BranchState, LeafState};
+use test_support::test_config;
+
+const MAX_DEPTH: u32 = 5;
+const EDITS: usize = 500;
+
+fn new_engine() -> Engine {
+    Engine::new(test_config(MAX_DEPTH, 100))
+}
+
+fn leaves(engine: &Engine) -> Vec<quadtree::Address> {
+    let root = quadtree::Address::from((vec![], MAX_DEPTH));
+    engine.state.leaves_in_rect(&root.bounds())
+}
+
+fn id(engine: &Engine, address: quadtree::Address) -> u64 {
+    engine.get_leaf(address).unwrap().id
+}
+
+fn split(engine: &mut Engine, address: quadtree::Address) {
+    engine
+        .split_tile(
+            address,
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+}
+
+/// Every leaf can be found by its id, and nothing else can be.
+fn assert_consistent(engine: &Engine, retired: &HashSet<u64>) {
+    let issues = engine.state.tile_id_issues().unwrap();
+    assert!(issues.is_empty(), "{:#?}", issues);
+
+    for address in leaves(engine) {
+        let id = id(engine, address);
+        assert_ne!(id, 0, "no id for {:?}", address);
+        assert_eq!(engine.state.find_tile(id), Some(address));
+    }
+    for id in retired {
+        assert_eq!(engine.state.find_tile(*id), None, "retired id {}", id);
+    }
+}
+
+#[test]
+fn split_test() {
+    let mut engine = new_engine();
+    let root = quadtree::Address::from((vec![], MAX_DEPTH));
+    let root_id = id(&engine, root);
+    assert_ne!(root_id, 0);
+    assert_eq!(engine.state.find_tile(root_id), Some(root));
+
+    split(&mut engine, root);
+    assert_eq!(engine.state.find_tile(root_id), None);
+
+    let ids: HashSet<_> = quadtree::QUADRANTS
+        .iter()
+        .map(|quadrant| id(&engine, root.child(*quadrant)))
+        .collect();
+    assert_eq!(ids.len(), 4);
+    assert!(!ids.contains(&root_id));
+    assert_consistent(&engine, &HashSet::from([root_id]));
+}
+
+#[test]
+fn edit_keeps_id_test() {
+    let mut engine = new_engine();
+    let root = quadtree::Address::from((vec![], MAX_DEPTH));
+    split(&mut engine, root);
+    let address = root.child(quadtree::Quadrant::NE);
+    let before = id(&engine, address);
+
+    // whatever id the data has is ignored
+    engine
+        .set_leaf_data(
+            address,
+            r#"{"id": 1000, "tile": {"type": "WaterTile"}, "creation_time": 0}"#,
+            state::SerdeFormat::Json,
+        )
+        .unwrap();
+    assert_eq!(id(&engine, address), before);
+    assert_eq!(engine.state.find_tile(1000), None);
+
+    let data = engine
+        .get_leaf_data(address, state::SerdeFormat::Json)
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_str(&data).unwrap();
+    assert_eq!(json["id"], before);
+}
+
+#[test]
+fn save_load_test() {
+    let mut engine = new_engine();
+    let root = quadtree::Address::from((vec![], MAX_DEPTH));
+    split(&mut engine, root);
+    split(&mut engine, root.child(quadtree::Quadrant::SW));
+
+    let loaded = Engine::load(&engine.dump().unwrap()).unwrap();
+    assert_eq!(leaves(&loaded), leaves(&engine));
+    for address in leaves(&engine) {
+        assert_eq!(id(&loaded, address), id(&engine, address));
+    }
+    assert_consistent(&loaded, &HashSet::new());
+
+    // ids aren't reused after loading either
+    let mut loaded = loaded;
+    let max_id = leaves(&engine)
+        .into_iter()
+        .map(|address| id(&engine, address))
+        .max()
+        .unwrap();
+    split(&mut loaded, root.child(quadtree::Quadrant::NW));
+    for quadrant in quadtree::QUADRANTS {
+        assert!(id(&loaded, root.child(quadtree::Quadrant::NW).child(quadrant)) > max_id);
+    }
+}
+
+#[test]
+fn old_save_test() {
+    let mut engine = new_engine();
+    let root = quadtree::Address::from((vec![], MAX_DEPTH));
+    split(&mut engine, root);
+
+    // maps from before tile ids were introduced don't have any
+    let mut save: serde_json::Value = serde_json::from_str(&engine.dump().unwrap()).unwrap();
+    save["state"]
+        .as_object_mut()
+        .unwrap()
+        .remove("tile_id_counter");
+    let save = save.to_string().replace("\"id\":", "\"old_id\":");
+
+    let loaded = Engine::load(&save).unwrap();
+    assert_consistent(&loaded, &HashSet::new());
+}
+
+#[test]
+fn randomized_edits_test() {
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let mut engine = new_engine();
+    let mut retired = HashSet::new();
+
+    for _ in 0..EDITS {
+        let leaves = leaves(&engine);
+        let address = leaves[rng.gen_range(0..leaves.len())];
+        let before = id(&engine, address);
+
+        match rng.gen_range(0..4) {
+            0 if address.depth() < MAX_DEPTH as usize => {
+                split(&mut engine, address);
+                retired.insert(before);
+            }
+            1 => {
+                let tile = tiles::HousingTile {
+                    density: 1,
+                    agents: vec![],
+                };
+                match engine
+                    .insert_tile(address, tile.into(), InsertPolicy::Overwrite)
+                    .unwrap()
+                {
+                    // the existing tile was split
+                    (Some(_), _) if engine.get_leaf(address).is_err() => {
+                        retired.insert(before);
+                    }
+                    _ => assert_eq!(id(&engine, address), before),
+                }
+            }
+            2 => {
+                engine
+                    .set_leaf_data(
+                        address,
+                        r#"{"tile": {"type": "EmptyTile"}, "creation_time": 0}"#,
+                        state::SerdeFormat::Json,
+                    )
+                    .unwrap();
+                assert_eq!(id(&engine, address), before);
+            }
+            _ if address.depth() < MAX_DEPTH as usize - 1 => {
+                let depth = address.depth() as u32 + 2;
+                engine.bulk_apply(&[address], &state::BulkOp::SetEmpty);
+                let report = engine.bulk_apply(&[address], &state::BulkOp::SplitToDepth(depth));
+                assert!(report.is_ok(), "{:?}", report);
+                retired.insert(before);
+            }
+            _ => continue,
+        }
+        assert_consistent(&engine, &retired);
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::{Arc, Mutex};
+
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_all, test_config};
+use tracing_subscriber::layer::SubscriberExt;
+use uom::si::time::hour;
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 3;
+const MIN_TILE_SIZE: u32 = 3000;
+
+/// Records the names and fields of every span that is created.
+#[derive(Default, Clone)]
+struct SpanRecorder {
+    spans: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+struct FieldRecorder<'a>(&'a mut String);
+
+impl<'a> tracing::field::Visit for FieldRecorder<'a> {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0.push_str(&format!("{}={:?} ", field.name(), value));
+    }
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanRecorder {
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        _id: &tracing::span::Id,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut fields = String::new();
+        attrs.record(&mut FieldRecorder(&mut fields));
+        self.spans
+            .lock()
+            .unwrap()
+            .push((attrs.metadata().name().to_string(), fields));
+    }
+}
+
+impl SpanRecorder {
+    fn names(&self) -> Vec<String> {
+        let spans = self.spans.lock().unwrap();
+        spans.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    fn fields(&self, name: &str) -> Vec<String> {
+        let spans = self.spans.lock().unwrap();
+        spans
+            .iter()
+            .filter(|(span, _)| span == name)
+            .map(|(_, fields)| fields.clone())
+            .collect()
+    }
+}
+
+fn set_tile(engine: &mut Engine, (x, y): (u64, u64), tile: tiles::Tile) -> quadtree::Address {
+    let address = engine.state.qtree.get_address(x, y).unwrap();
+    engine.state.qtree.get_leaf_mut(address).unwrap().tile = tile;
+    address
+}
+
+fn generate_map() -> (Engine, quadtree::Address, quadtree::Address) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, MIN_TILE_SIZE));
+    split_all(&mut engine);
+
+    let housing = set_tile(
+        &mut engine,
+        (0, 0),
+        tiles::HousingTile {
+            density: 1,
+            agents: vec![],
+        }
+        .into(),
+    );
+    let workplace = set_tile(
+        &mut engine,
+        (1, 0),
+        tiles::WorkplaceTile {
+            density: 1,
+            agents: vec![],
+            industry: tiles::Industry::Office,
+        }
+        .into(),
+    );
+
+    (engine, housing, workplace)
+}
+
+#[test]
+fn route_query_spans_test() {
+    let (engine, housing, workplace) = generate_map();
+
+    let recorder = SpanRecorder::default();
+    let subscriber = tracing_subscriber::registry().with(recorder.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        engine
+            .query_route(route::QueryInput {
+                start: housing,
+                end: workplace,
+                car_config: Some(route::CarConfig::StartWithCar),
+                profile: route::MobilityProfile::STANDARD,
+                allowed_modes: Default::default(),
+            })
+            .unwrap()
+            .unwrap();
+    });
+
+    let names = recorder.names();
+    // the base graph is constructed lazily by the first query
+    assert!(names.contains(&"construct_base_graph".to_string()));
+    assert!(names.contains(&"prepare".to_string()));
+    assert!(names.contains(&"best_route".to_string()));
+}
+
+#[test]
+fn trigger_spans_test() {
+    let (mut engine, housing, workplace) = generate_map();
+    let data = AgentDataDistribution::default().sample(&mut engine.rng);
+    engine.add_agent(data, housing, Some(workplace)).unwrap();
+    engine.init_trigger_queue();
+
+    let recorder = SpanRecorder::default();
+    let subscriber = tracing_subscriber::registry().with(recorder.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        engine.tick(Time::new::<hour>(12).value).unwrap();
+    });
+
+    let triggers = recorder.fields("trigger");
+    assert!(!triggers.is_empty());
+    // the simulation starts on a Wednesday, so the agent commutes to work in the morning
+    assert!(triggers
+        .iter()
+        .any(|fields| fields.contains("kind=AgentPlanCommuteToWork")));
+    assert!(triggers
+        .iter()
+        .any(|fields| fields.contains("kind=UpdateFields")));
+    assert!(recorder.names().contains(&"update_fields".to_string()));
+}
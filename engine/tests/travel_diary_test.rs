@@ -0,0 +1,131 @@
+use engine::{AgentDataDistribution, Engine};
+use test_support::{split_all, test_config};
+use uom::si::time::{day, minute};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 6;
+const MIN_TILE_SIZE: u32 = 50;
+
+/// Generate a map with two agents who live and work at opposite ends of a highway.
+fn generate_map(travel_diary: state::TravelDiaryConfig) -> (Engine, Vec<u64>) {
+    let mut engine = Engine::new(state::Config {
+        travel_diary,
+        ..test_config(MAX_DEPTH, MIN_TILE_SIZE)
+    });
+
+    split_all(&mut engine);
+
+    let housing = engine.state.qtree.get_address(2, 2).unwrap();
+    let workplace = engine.state.qtree.get_address(40, 2).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 2,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 2,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    let points = [(3.0, 3.0), (39.0, 3.0)];
+    let on_ramp = engine.state.highways.add_junction(
+        points[0],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OnRamp)),
+    );
+    let off_ramp = engine.state.highways.add_junction(
+        points[1],
+        highway::HighwayJunction::new(Some(highway::RampDirection::OffRamp)),
+    );
+    engine.state.highways.add_segment(
+        highway::HighwaySegment::new(None, vec![], None, Some(40)),
+        on_ramp,
+        off_ramp,
+        Some(vec![points[0].into(), points[1].into()]),
+    );
+
+    let agents = (0..2)
+        .map(|_| {
+            let data = AgentDataDistribution::default().sample(&mut engine.rng);
+            engine.add_agent(data, housing, Some(workplace)).unwrap()
+        })
+        .collect();
+    engine.init_trigger_queue();
+
+    (engine, agents)
+}
+
+fn simulate(engine: &mut Engine, days: u64) {
+    let steps = days * Time::new::<day>(1).value / Time::new::<minute>(1).value;
+    for _ in 0..steps {
+        engine.tick(Time::new::<minute>(1).value).unwrap();
+    }
+}
+
+#[test]
+fn two_agent_day_test() {
+    let (mut engine, agents) = generate_map(state::TravelDiaryConfig {
+        enabled: true,
+        max_days: 7,
+    });
+    simulate(&mut engine, 1);
+
+    // each agent commutes to work and back
+    let records = engine.travel_diary(0);
+    assert_eq!(records.len(), 4, "{:#?}", records);
+    for agent in &agents {
+        for route_type in [
+            agent::RouteType::CommuteToWork,
+            agent::RouteType::CommuteFromWork,
+        ] {
+            let record = records
+                .iter()
+                .find(|record| record.agent == *agent && record.route_type == route_type)
+                .expect("missing trip");
+            assert!(!record.aborted);
+            assert!(!record.legs.is_empty());
+
+            // the legs add up to the commute length that the agent remembers
+            let recorded = engine.agents[agent].route_lengths[&route_type];
+            assert!(
+                (record.duration() - recorded).abs() < 0.1,
+                "legs: {:?}, recorded: {}",
+                record.legs,
+                recorded
+            );
+            assert_eq!(
+                record.arrival,
+                record.departure + record.duration().ceil() as u64
+            );
+        }
+    }
+
+    // one row per leg, plus the header
+    let path = std::env::temp_dir().join(format!("travel_diary_{}.csv", std::process::id()));
+    engine.export_travel_diary_csv(&path, 0).unwrap();
+    let csv = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let legs: usize = records.iter().map(|record| record.legs.len()).sum();
+    assert_eq!(csv.lines().count(), legs + 1);
+}
+
+#[test]
+fn max_days_test() {
+    let (mut engine, _) = generate_map(state::TravelDiaryConfig {
+        enabled: true,
+        max_days: 1,
+    });
+    simulate(&mut engine, 2);
+
+    assert!(engine.travel_diary(0).is_empty());
+    assert_eq!(engine.travel_diary(1).len(), 4);
+}
+
+#[test]
+fn disabled_test() {
+    let (mut engine, _) = generate_map(Default::default());
+    simulate(&mut engine, 1);
+
+    assert!(engine.travel_diary(0).is_empty());
+}
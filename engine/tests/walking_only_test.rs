@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use engine::{AgentDataDistribution, Engine, FieldsState};
+use route::IsochroneResult;
+use state::{BranchState, LeafState};
+use test_support::test_config;
+use uom::si::time::{day, minute};
+use uom::si::u64::Time;
+
+const MAX_DEPTH: u32 = 1;
+const MIN_TILE_SIZE: u32 = 200;
+
+/// Generate a map of four tiles, with housing and workplaces but no highways or metros.
+fn generate_map() -> (Engine, quadtree::Address, quadtree::Address) {
+    generate_map_with_tile_size(MIN_TILE_SIZE)
+}
+
+fn generate_map_with_tile_size(
+    min_tile_size: u32,
+) -> (Engine, quadtree::Address, quadtree::Address) {
+    let mut engine = Engine::new(test_config(MAX_DEPTH, min_tile_size));
+
+    engine
+        .state
+        .qtree
+        .split(
+            quadtree::Address::from((vec![], MAX_DEPTH)),
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+
+    let housing = engine.state.qtree.get_address(0, 0).unwrap();
+    let workplace = engine.state.qtree.get_address(1, 1).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 2,
+        agents: vec![],
+    }
+    .into();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 2,
+        agents: vec![],
+        industry: tiles::Industry::Office,
+    }
+    .into();
+
+    (engine, housing, workplace)
+}
+
+fn is_walking_only(route: &route::Route) -> bool {
+    route.edges.iter().all(|edge| {
+        matches!(
+            edge,
+            route::Edge::ModeSegment {
+                mode: route::Mode::Walking,
+                ..
+            }
+        )
+    })
+}
+
+#[test]
+fn walking_route_test() {
+    let (engine, housing, workplace) = generate_map();
+
+    let stats = engine
+        .base_graph
+        .read()
+        .unwrap()
+        .get_base_graph(&engine.state)
+        .get_stats();
+    assert_eq!(stats.node_count, 0);
+    assert!(stats.terminal_node_counts.values().all(|count| *count == 0));
+
+    let route = engine
+        .query_route(route::QueryInput {
+            start: housing,
+            end: workplace,
+            car_config: None,
+            profile: Default::default(),
+            allowed_modes: Default::default(),
+        })
+        .unwrap()
+        .expect("expected a direct walking route");
+    assert!(is_walking_only(&route));
+    assert_eq!(route.start(), housing);
+    assert_eq!(route.end(), workplace);
+    // diagonally across two tiles at walking speed
+    let expected = (2.0 * (MIN_TILE_SIZE as f64).powi(2)).sqrt() / 1.5;
+    assert!((route.cost as f64 - expected).abs() < 1.0, "{}", route.cost);
+
+    // too far to walk, and there is nothing else to take
+    let (engine, housing, workplace) = generate_map_with_tile_size(2000);
+    let route = engine
+        .query_route(route::QueryInput {
+            start: housing,
+            end: workplace,
+            car_config: None,
+            profile: Default::default(),
+            allowed_modes: Default::default(),
+        })
+        .unwrap();
+    assert!(route.is_none());
+}
+
+#[test]
+fn walking_isochrone_test() {
+    let (engine, housing, workplace) = generate_map();
+
+    let isochrone = match engine
+        .query_isochrone_map(housing, route::Mode::Walking, Default::default())
+        .unwrap()
+    {
+        IsochroneResult::Calculated(isochrone) => isochrone,
+        IsochroneResult::FocusNotRoutable { .. } => panic!("expected an isochrone"),
+    };
+    let (x, y) = workplace.to_xy();
+    let travel_time = isochrone.get_travel_time(x, y);
+    assert!(
+        travel_time.is_finite() && travel_time > 0.0,
+        "{}",
+        travel_time
+    );
+    let (x, y) = housing.to_xy();
+    assert_eq!(isochrone.get_travel_time(x, y), 0.0);
+}
+
+#[test]
+fn walking_commute_test() {
+    let (mut engine, housing, workplace) = generate_map();
+
+    let agents: Vec<u64> = (0..2)
+        .map(|_| {
+            let mut data = AgentDataDistribution::default().sample(&mut engine.rng);
+            data.owns_car = false;
+            engine.add_agent(data, housing, Some(workplace)).unwrap()
+        })
+        .collect();
+    engine.init_trigger_queue();
+
+    let mut commutes = HashMap::new();
+    let steps = Time::new::<day>(1).value / Time::new::<minute>(1).value;
+    for _ in 0..steps {
+        engine.tick(Time::new::<minute>(1).value).unwrap();
+        for agent in engine.agents.values() {
+            if let agent::AgentState::Route(agent::AgentRouteState { route, .. }) = &agent.state {
+                commutes
+                    .entry((agent.id, route.query_input.end))
+                    .or_insert_with(|| is_walking_only(route));
+            }
+        }
+    }
+
+    // both agents went to work and back, on foot the whole way
+    for agent in agents {
+        assert_eq!(commutes.get(&(agent, workplace)), Some(&true));
+        assert_eq!(commutes.get(&(agent, housing)), Some(&true));
+    }
+}
@@ -0,0 +1,137 @@
+use engine::{Engine, FieldsState};
+use state::{BranchState, LeafState};
+use test_support::test_config;
+
+const MAX_DEPTH: u32 = 2;
+
+/// Generate a map with a retail job next door to the home of an agent who is far too educated for
+/// it, so that education fit is the only thing wrong with the job.
+fn generate_map(workplace_happiness: state::WorkplaceHappinessConfig) -> (Engine, u64) {
+    let mut engine = Engine::new(state::Config {
+        workplace_happiness,
+        ..test_config(MAX_DEPTH, 100)
+    });
+    engine
+        .state
+        .qtree
+        .split(
+            quadtree::Address::from((vec![], MAX_DEPTH)),
+            BranchState::default(),
+            quadtree::QuadMap::each(LeafState::<FieldsState>::default),
+        )
+        .unwrap();
+
+    let housing = engine.state.qtree.get_address(0, 0).unwrap();
+    engine.state.qtree.get_leaf_mut(housing).unwrap().tile = tiles::HousingTile {
+        density: 1,
+        agents: vec![],
+    }
+    .into();
+    let workplace = engine.state.qtree.get_address(2, 0).unwrap();
+    engine.state.qtree.get_leaf_mut(workplace).unwrap().tile = tiles::WorkplaceTile {
+        density: 1,
+        agents: vec![],
+        industry: tiles::Industry::Retail,
+    }
+    .into();
+
+    let data = agent::AgentData {
+        birthday: chrono::NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+        years_of_education: 30,
+        owns_car: false,
+        walking_speed: 1.0,
+        archetype: 0,
+        noise: Default::default(),
+    };
+    let id = engine.add_agent(data, housing, Some(workplace)).unwrap();
+    engine.init_trigger_queue();
+    // no vacancies, so that the agent can't take the same job again right away
+    engine.state.update_collect_tiles().unwrap();
+    (engine, id)
+}
+
+#[test]
+fn single_component_quit_test() {
+    let (mut engine, id) = generate_map(state::WorkplaceHappinessConfig {
+        commute: 1.0,
+        education_fit: 10.0,
+        tenure: 0.0,
+        ..Default::default()
+    });
+    let happiness = engine.agents[&id]
+        .workplace_happiness(&engine.state.config, 0)
+        .unwrap();
+    assert!((happiness.score - 1.0 / 11.0).abs() < 1e-6);
+
+    // the first life decisions are made right away
+    engine.tick(1).unwrap();
+    assert_eq!(engine.agents[&id].workplace, None);
+    assert_eq!(engine.agents[&id].workplace_industry, None);
+    let quits: Vec<_> = engine.job_quits.recent().collect();
+    assert_eq!(quits.len(), 1);
+    assert_eq!(quits[0].agent, id);
+    assert_eq!(
+        quits[0].reason,
+        Some(agent::HappinessComponent::EducationFit)
+    );
+    assert_eq!(
+        engine.job_quits.totals()[&agent::HappinessComponent::EducationFit],
+        1
+    );
+    engine.consistency_check().unwrap();
+}
+
+#[test]
+fn tolerable_component_test() {
+    // the same job is bearable when education matters less
+    let (mut engine, id) = generate_map(state::WorkplaceHappinessConfig {
+        commute: 1.0,
+        education_fit: 1.0,
+        tenure: 0.0,
+        ..Default::default()
+    });
+    engine.tick(1).unwrap();
+    assert!(engine.agents[&id].workplace.is_some());
+    assert_eq!(engine.job_quits.recent().count(), 0);
+}
+
+#[test]
+fn serialization_test() {
+    let (mut engine, id) = generate_map(Default::default());
+    engine.tick(60).unwrap();
+
+    let agent = &engine.agents[&id];
+    assert_eq!(agent.workplace_industry, Some(tiles::Industry::Retail));
+    assert_eq!(agent.hired_at, Some(0));
+    let value = serde_json::to_value(agent).unwrap();
+    let loaded: agent::Agent = serde_json::from_value(value.clone()).unwrap();
+    assert_eq!(loaded.workplace_industry, agent.workplace_industry);
+    assert_eq!(loaded.hired_at, agent.hired_at);
+
+    // agents saved before these were recorded still load, and are judged on their commute alone
+    let mut old = value;
+    let fields = old.as_object_mut().unwrap();
+    fields.remove("workplace_industry");
+    fields.remove("hired_at");
+    let loaded: agent::Agent = serde_json::from_value(old).unwrap();
+    assert_eq!(loaded.workplace_industry, None);
+    assert_eq!(loaded.hired_at, None);
+    let happiness = loaded
+        .workplace_happiness(&engine.state.config, 60)
+        .unwrap();
+    assert_eq!(
+        happiness
+            .components
+            .iter()
+            .map(|component| component.component)
+            .collect::<Vec<_>>(),
+        vec![agent::HappinessComponent::Commute]
+    );
+
+    // the quit log survives a save
+    let reloaded = Engine::load(&engine.dump().unwrap()).unwrap();
+    assert_eq!(
+        reloaded.job_quits.recent().count(),
+        engine.job_quits.recent().count()
+    );
+}
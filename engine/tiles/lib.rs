@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[enum_dispatch::enum_dispatch]
@@ -7,7 +8,7 @@ pub trait TileType {
 }
 
 #[enum_dispatch::enum_dispatch(TileType)]
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 #[non_exhaustive]
 pub enum Tile {
@@ -18,7 +19,19 @@ pub enum Tile {
     MetroStationTile,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+impl memory_size::MemorySize for Tile {
+    fn heap_bytes(&self) -> usize {
+        use memory_size::vec_bytes;
+        match self {
+            Tile::EmptyTile(_) | Tile::WaterTile(_) => 0,
+            Tile::HousingTile(tile) => vec_bytes(&tile.agents),
+            Tile::WorkplaceTile(tile) => vec_bytes(&tile.agents),
+            Tile::MetroStationTile(tile) => tile.name.capacity() + vec_bytes(&tile.ids),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct EmptyTile {}
 
 impl TileType for EmptyTile {
@@ -31,7 +44,7 @@ impl TileType for EmptyTile {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct WaterTile {}
 
 impl TileType for WaterTile {
@@ -44,7 +57,7 @@ impl TileType for WaterTile {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct HousingTile {
     pub density: usize,
     pub agents: Vec<u64>,
@@ -60,10 +73,109 @@ impl TileType for HousingTile {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// The kind of work done at a workplace, which determines when its employees work.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+pub enum Industry {
+    #[default]
+    Office,
+    Retail,
+    Industrial,
+    Education,
+    Healthcare,
+}
+
+impl Industry {
+    pub const COUNT: usize = 5;
+    pub const ALL: [Industry; Self::COUNT] = [
+        Self::Office,
+        Self::Retail,
+        Self::Industrial,
+        Self::Education,
+        Self::Healthcare,
+    ];
+
+    /// A unique index in [0, COUNT), for storing per-industry data in arrays.
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Office => "office",
+            Self::Retail => "retail",
+            Self::Industrial => "industrial",
+            Self::Education => "education",
+            Self::Healthcare => "healthcare",
+        }
+    }
+
+    pub fn schedule(&self) -> WorkSchedule {
+        match self {
+            Self::Office => WorkSchedule {
+                start_hour: 8,
+                shift_hours: 8,
+                works_weekends: false,
+            },
+            Self::Retail => WorkSchedule {
+                start_hour: 10,
+                shift_hours: 8,
+                works_weekends: true,
+            },
+            Self::Industrial => WorkSchedule {
+                start_hour: 6,
+                shift_hours: 8,
+                works_weekends: false,
+            },
+            Self::Education => WorkSchedule {
+                start_hour: 7,
+                shift_hours: 8,
+                works_weekends: false,
+            },
+            Self::Healthcare => WorkSchedule {
+                start_hour: 7,
+                shift_hours: 12,
+                works_weekends: true,
+            },
+        }
+    }
+}
+
+/// When the employees of a workplace work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkSchedule {
+    /// the hour of the day at which employees leave home for work
+    pub start_hour: u64,
+    /// the number of hours after leaving for work that employees leave for home
+    pub shift_hours: u64,
+    pub works_weekends: bool,
+}
+
+impl Default for WorkSchedule {
+    fn default() -> Self {
+        Industry::default().schedule()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct WorkplaceTile {
     pub density: usize,
     pub agents: Vec<u64>,
+    /// older maps don't have industries, so their workplaces are all offices
+    #[serde(default)]
+    pub industry: Industry,
 }
 
 impl TileType for WorkplaceTile {
@@ -76,7 +188,7 @@ impl TileType for WorkplaceTile {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct MetroStationTile {
     pub name: String,
     // Exact location of station within the tile, in absolute coordinates.
@@ -85,6 +197,16 @@ pub struct MetroStationTile {
     // IDs to lookup metro lines. If there is more than one, then this is a transfer station.
     // May be empty because orphan stations are allowed, especially when constructing new lines.
     pub ids: Vec<u64>,
+    // Whether the station offers parking. Stations downtown usually don't, so drivers have to park
+    // somewhere else and walk. Existing maps predate this field, so it defaults to true.
+    #[serde(default = "MetroStationTile::default_parking")]
+    pub parking: bool,
+}
+
+impl MetroStationTile {
+    fn default_parking() -> bool {
+        true
+    }
 }
 
 impl TileType for MetroStationTile {
@@ -97,6 +219,51 @@ impl TileType for MetroStationTile {
     }
 }
 
+/// A kind of destination that shapes land value and trips without being occupied by agents.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+pub enum PoiKind {
+    School,
+    Park,
+    Hospital,
+    Civic,
+}
+
+impl PoiKind {
+    pub const COUNT: usize = 4;
+    pub const ALL: [PoiKind; Self::COUNT] = [Self::School, Self::Park, Self::Hospital, Self::Civic];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::School => "school",
+            Self::Park => "park",
+            Self::Hospital => "hospital",
+            Self::Civic => "civic",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/**
+ * A point of interest, which is stored alongside the tile of a leaf rather than being a tile
+ * itself, so that e.g. a park can be an otherwise empty tile.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Poi {
+    pub kind: PoiKind,
+    pub name: String,
+}
+
+impl memory_size::MemorySize for Poi {
+    fn heap_bytes(&self) -> usize {
+        self.name.capacity()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use tiles::*;
@@ -106,4 +273,19 @@ mod tests {
         let tile = Tile::from(EmptyTile {});
         assert_eq!(tile.name(), "empty");
     }
+
+    #[test]
+    fn poi_kind_names() {
+        for kind in PoiKind::ALL {
+            assert_eq!(PoiKind::from_name(kind.name()), Some(kind));
+        }
+        assert_eq!(PoiKind::from_name("lava"), None);
+    }
+
+    #[test]
+    fn industry_index() {
+        for (i, industry) in Industry::ALL.iter().enumerate() {
+            assert_eq!(industry.index(), i);
+        }
+    }
 }
@@ -38,20 +38,27 @@ impl TimeState {
         }
     }
 
-    pub fn current_date_time(&self) -> chrono::NaiveDateTime {
-        chrono::NaiveDateTime::from_timestamp_opt(
-            (self.engine_start_time + self.current_time) as i64,
-            0,
-        )
-        .unwrap()
+    /// The date and time corresponding to the given number of seconds since the beginning of the
+    /// simulation.
+    pub fn date_time(&self, time: u64) -> chrono::NaiveDateTime {
+        chrono::NaiveDateTime::from_timestamp_opt((self.engine_start_time + time) as i64, 0)
+            .unwrap()
     }
 
-    pub fn pretty_current_date_time(&self) -> String {
-        self.current_date_time()
+    pub fn pretty_date_time(&self, time: u64) -> String {
+        self.date_time(time)
             .format("%a, %b %d, %Y %l:%M %P")
             .to_string()
     }
 
+    pub fn current_date_time(&self) -> chrono::NaiveDateTime {
+        self.date_time(self.current_time)
+    }
+
+    pub fn pretty_current_date_time(&self) -> String {
+        self.pretty_date_time(self.current_time)
+    }
+
     pub fn current_date(&self) -> chrono::NaiveDate {
         self.current_date_time().date()
     }
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use crate::engine::{Engine, Error};
+
+/**
+ * A single trip taken by an agent. Agents are only identified by their id, and places only by tile
+ * coordinates.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TravelRecord {
+    pub agent: u64,
+    pub route_type: agent::RouteType,
+    /// simulation time at which the trip started
+    pub departure: u64,
+    /// simulation time at which the trip ended, or was aborted
+    pub arrival: u64,
+    pub origin: (u64, u64),
+    pub destination: (u64, u64),
+    pub legs: Vec<agent::TravelLeg>,
+    /// whether the trip was cut short, e.g. because it took so long that the next one started
+    pub aborted: bool,
+}
+
+impl TravelRecord {
+    fn new(route_state: &agent::AgentRouteState) -> Self {
+        let legs = route_state.travel_legs();
+        let total: f32 = legs.iter().map(|leg| leg.duration).sum();
+        Self {
+            agent: route_state.id,
+            route_type: route_state.route_type,
+            departure: route_state.start_time,
+            arrival: route_state.start_time + total.ceil() as u64,
+            origin: route_state.route.start().to_xy(),
+            destination: route_state.route.end().to_xy(),
+            legs,
+            aborted: !route_state.finished(),
+        }
+    }
+
+    /// The total time spent on the trip, which is the sum of the legs; subsecond precision.
+    pub fn duration(&self) -> f32 {
+        self.legs.iter().map(|leg| leg.duration).sum()
+    }
+}
+
+/**
+ * The trips taken by all agents, grouped by the day they started on. Only the most recent days are
+ * kept; see state::TravelDiaryConfig.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct TravelDiary {
+    days: BTreeMap<u64, Vec<TravelRecord>>,
+}
+
+impl TravelDiary {
+    /// Record a trip that has just finished or been aborted, if the travel diary is enabled.
+    pub(crate) fn record(
+        &mut self,
+        config: &state::TravelDiaryConfig,
+        route_state: &agent::AgentRouteState,
+    ) {
+        if !config.enabled {
+            return;
+        }
+        let record = TravelRecord::new(route_state);
+        let day = record.departure / state::TRAFFIC_HISTORY_PERIOD;
+        self.days.entry(day).or_default().push(record);
+
+        while self.days.len() > config.max_days {
+            let oldest = *self.days.keys().next().unwrap();
+            self.days.remove(&oldest);
+        }
+    }
+
+    /// The trips that started on the given day, in the order they ended.
+    pub fn day(&self, day: u64) -> &[TravelRecord] {
+        self.days
+            .get(&day)
+            .map_or(&[], |records| records.as_slice())
+    }
+
+    /// The days that are still being kept, in order.
+    pub fn days(&self) -> impl Iterator<Item = u64> + '_ {
+        self.days.keys().copied()
+    }
+
+    /// One row per leg of each trip that started on the given day, with a header row.
+    pub fn to_csv(&self, day: u64) -> String {
+        let mut csv = String::from(
+            "agent,route_type,departure,arrival,origin_x,origin_y,destination_x,destination_y,\
+             aborted,leg,mode,duration\n",
+        );
+        for record in self.day(day) {
+            for (i, leg) in record.legs.iter().enumerate() {
+                csv.push_str(&format!(
+                    "{},{:?},{},{},{},{},{},{},{},{},{},{}\n",
+                    record.agent,
+                    record.route_type,
+                    record.departure,
+                    record.arrival,
+                    record.origin.0,
+                    record.origin.1,
+                    record.destination.0,
+                    record.destination.1,
+                    record.aborted,
+                    i,
+                    leg.mode,
+                    leg.duration,
+                ));
+            }
+        }
+        csv
+    }
+}
+
+impl Engine {
+    /// The trips that started on the given day, counting from the start of the simulation.
+    pub fn travel_diary(&self, day: u64) -> &[TravelRecord] {
+        self.travel_diary.day(day)
+    }
+
+    /// Write the trips that started on the given day as CSV, with one row per leg.
+    pub fn export_travel_diary_csv(&self, path: &std::path::Path, day: u64) -> Result<(), Error> {
+        std::fs::write(path, self.travel_diary.to_csv(day))?;
+        Ok(())
+    }
+}
@@ -6,15 +6,46 @@ use serde::{Deserialize, Serialize};
 use tabled::Tabled;
 
 // NOTE: Trigger, and all implementations, are defined in behavior.rs
-use crate::behavior::{Trigger, TriggerKind, TriggerType};
+use crate::alerts::Alerts;
+use crate::behavior::{Trigger, TriggerKind, TriggerType, DISABLED_TRIGGERS};
+use crate::custom_trigger::{CustomTrigger, DynTriggerType};
 use crate::engine::Error;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 struct TriggerEntry {
+    #[serde(deserialize_with = "deserialize_trigger")]
     trigger: Trigger,
     time: u64,
 }
 
+/**
+ * Like Trigger::deserialize, but a trigger that is compiled out of this build is reported by name
+ * rather than as an unknown variant, since it means that the save needs a different build.
+ */
+fn deserialize_trigger<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Trigger, D::Error> {
+    use serde::de::Error as _;
+
+    if DISABLED_TRIGGERS.is_empty() {
+        return Trigger::deserialize(deserializer);
+    }
+    let value = serde_json::Value::deserialize(deserializer)?;
+    let trigger_type = value
+        .get("type")
+        .and_then(|trigger_type| trigger_type.as_str());
+    if let Some((name, feature)) = DISABLED_TRIGGERS
+        .iter()
+        .find(|(name, _)| Some(*name) == trigger_type)
+    {
+        return Err(D::Error::custom(format!(
+            "trigger {} needs the {:?} feature, which is disabled in this build",
+            name, feature
+        )));
+    }
+    Trigger::deserialize(value).map_err(D::Error::custom)
+}
+
 impl Ord for TriggerEntry {
     fn cmp(&self, other: &Self) -> Ordering {
         // always order by time first
@@ -65,6 +96,16 @@ impl TriggerQueue {
         self.heap.push(TriggerEntry { trigger, time });
     }
 
+    /// Add a trigger defined outside of this crate to the queue; see DynTriggerType.
+    pub fn push_custom<T: DynTriggerType + 'static>(
+        &mut self,
+        trigger: T,
+        time: u64,
+    ) -> Result<(), Error> {
+        self.push(CustomTrigger::new(trigger)?, time);
+        Ok(())
+    }
+
     pub fn push_rel<T: Into<Trigger>>(&mut self, trigger: T, rel_time: u64) {
         self.heap.push(TriggerEntry {
             trigger: trigger.into(),
@@ -77,6 +118,12 @@ impl TriggerQueue {
     }
 }
 
+impl memory_size::MemorySize for TriggerQueue {
+    fn heap_bytes(&self) -> usize {
+        self.heap.capacity() * std::mem::size_of::<TriggerEntry>()
+    }
+}
+
 impl crate::engine::Engine {
     /**
      * Advance time forward to the current time, executing triggers in order until the given time.
@@ -84,8 +131,13 @@ impl crate::engine::Engine {
     pub fn advance_trigger_queue(&mut self, time_step: u64, time_budget: f64) -> Result<(), Error> {
         let target_time = self.time_state.current_time + time_step;
         let budget_start = std::time::Instant::now();
+        let was_paused = self.time_state.paused;
 
         while budget_start.elapsed().as_secs_f64() < time_budget {
+            if self.time_state.paused && !was_paused {
+                // paused by a trigger, e.g. because of an alert
+                break;
+            }
             if self
                 .trigger_queue
                 .heap
@@ -103,6 +155,32 @@ impl crate::engine::Engine {
         Ok(())
     }
 
+    /**
+     * Advance time forward by exactly [time_step] simulated seconds, executing every trigger that
+     * is due before the new current time. Unlike Engine::update, this ignores the playback rate,
+     * pausing, and the time budget, so the outcome does not depend on the frame rate or on how fast
+     * the host machine is. Triggers scheduled for exactly the new current time are left for the
+     * next tick, so that consecutive ticks execute each trigger exactly once.
+     */
+    pub fn tick(&mut self, time_step: u64) -> Result<(), Error> {
+        let target_time = self.time_state.current_time + time_step;
+
+        while self
+            .trigger_queue
+            .heap
+            .peek()
+            .map(|t| t.time < target_time)
+            .unwrap_or(false)
+        {
+            self.single_step()?;
+        }
+
+        self.trigger_queue.current_time = target_time;
+        self.time_state.current_time = target_time;
+
+        Ok(())
+    }
+
     pub fn single_step(&mut self) -> Result<(), Error> {
         let entry = self.trigger_queue.heap.pop().unwrap();
         assert!(entry.time >= self.trigger_queue.current_time);
@@ -115,10 +193,15 @@ impl crate::engine::Engine {
             .then(|| cpu_time::ThreadTime::try_now().ok())
             .flatten();
         let kind = TriggerKind::from(&entry.trigger);
-
-        entry
-            .trigger
-            .execute(self, self.trigger_queue.current_time)?;
+        let _span = tracing::debug_span!("trigger", ?kind, time = entry.time).entered();
+        let _logs = self.agent_logs.enter();
+
+        match entry.trigger.execute(self, self.trigger_queue.current_time) {
+            Ok(()) => (),
+            // count errors that only affect one agent so that the simulation can keep going
+            Err(err) if Alerts::is_recoverable(&err) => self.alerts.record_error(&err),
+            Err(err) => return Err(err),
+        }
 
         if let Some(start) = start {
             self.trigger_stats.record_trigger(kind, start.elapsed());
@@ -129,6 +212,29 @@ impl crate::engine::Engine {
     pub fn peek_trigger(&self) -> Option<&Trigger> {
         self.trigger_queue.heap.peek().map(|entry| &entry.trigger)
     }
+
+    /**
+     * The next `n` triggers that will be executed, in order, along with the time that each is
+     * scheduled for. This does not modify the queue, so it is cheap enough to call every frame.
+     */
+    pub fn peek_triggers(&self, n: usize) -> Vec<(u64, &Trigger)> {
+        use std::cmp::Reverse;
+
+        // keep the n earliest entries, with the latest of those on top so that it can be evicted
+        let mut earliest: BinaryHeap<Reverse<&TriggerEntry>> = BinaryHeap::with_capacity(n + 1);
+        for entry in self.trigger_queue.heap.iter() {
+            earliest.push(Reverse(entry));
+            if earliest.len() > n {
+                earliest.pop();
+            }
+        }
+
+        earliest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(entry)| (entry.time, &entry.trigger))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,56 @@
+import json
+import unittest
+
+import engine
+
+MAX_DEPTH = 1
+
+
+class AddAgentTest(unittest.TestCase):
+    def setUp(self):
+        config = engine.Config.from_json(
+            json.dumps({"max_depth": MAX_DEPTH, "people_per_sim": 1.0, "min_tile_size": 100})
+        )
+        self.engine = engine.Engine(config)
+        self.engine.split(
+            engine.Address([], MAX_DEPTH),
+            engine.BranchState(),
+            *[engine.LeafState() for _ in range(4)],
+        )
+
+        self.housing = self.engine.get_address(0, 0)
+        self.set_tile(self.housing, {"type": "HousingTile", "density": 1, "agents": []})
+        self.workplace = self.engine.get_address(1, 1)
+        self.set_tile(
+            self.workplace,
+            {"type": "WorkplaceTile", "density": 1, "agents": [], "industry": "Office"},
+        )
+        self.data = engine.AgentData(engine.Date.from_ymd(1990, 1, 1), 16, False, 1.4)
+
+    def set_tile(self, address, tile):
+        self.engine.set_leaf_json(address, json.dumps({"tile": tile, "creation_time": 0}))
+
+    def test_wrong_tiles(self):
+        with self.assertRaises(engine.NotAHousingTileError):
+            self.engine.add_agent(self.data, self.workplace, None)
+        with self.assertRaises(engine.NotAWorkplaceTileError):
+            self.engine.add_agent(self.data, self.housing, self.housing)
+
+    def test_full_tiles(self):
+        self.engine.add_agent(self.data, self.housing, self.workplace)
+        with self.assertRaises(engine.HousingFullError):
+            self.engine.add_agent(self.data, self.housing, None)
+
+        other_housing = self.engine.get_address(1, 0)
+        self.set_tile(other_housing, {"type": "HousingTile", "density": 1, "agents": []})
+        with self.assertRaises(engine.WorkplaceFullError):
+            self.engine.add_agent(self.data, other_housing, self.workplace)
+        # the housing tile was left as it was, so there is still room
+        self.engine.add_agent(self.data, other_housing, None)
+
+    def test_engine_error_base_class(self):
+        self.assertTrue(issubclass(engine.HousingFullError, engine.EngineError))
+
+
+if __name__ == "__main__":
+    unittest.main()
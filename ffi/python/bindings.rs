@@ -1,6 +1,16 @@
+use std::collections::HashMap;
+
 use pyo3::prelude::*;
 
 pyo3::create_exception!(engine, PyEngineError, pyo3::exceptions::PyException);
+// reasons that an agent can't be added, so that scripts can tell them apart
+pyo3::create_exception!(engine, NotAHousingTileError, PyEngineError);
+pyo3::create_exception!(engine, HousingFullError, PyEngineError);
+pyo3::create_exception!(engine, NotAWorkplaceTileError, PyEngineError);
+pyo3::create_exception!(engine, WorkplaceFullError, PyEngineError);
+
+/// a home zone, a work zone, and the number of commutes between them
+type OdFlowTuple = ((u32, u32), (u32, u32), u64);
 
 #[derive(thiserror::Error, Debug)]
 pub enum EngineError {
@@ -18,7 +28,22 @@ pub enum EngineError {
 
 impl std::convert::From<EngineError> for PyErr {
     fn from(err: EngineError) -> PyErr {
-        return PyEngineError::new_err(err.to_string());
+        let message = err.to_string();
+        return match err {
+            EngineError::EngineError(engine::Error::NotAHousingTile(_)) => {
+                NotAHousingTileError::new_err(message)
+            }
+            EngineError::EngineError(engine::Error::HousingFull(_)) => {
+                HousingFullError::new_err(message)
+            }
+            EngineError::EngineError(engine::Error::NotAWorkplaceTile(_)) => {
+                NotAWorkplaceTileError::new_err(message)
+            }
+            EngineError::EngineError(engine::Error::WorkplaceFull(_)) => {
+                WorkplaceFullError::new_err(message)
+            }
+            _ => PyEngineError::new_err(message),
+        };
     }
 }
 
@@ -29,6 +54,50 @@ fn wrap_err<T, I: Into<EngineError>>(result: Result<T, I>) -> PyResult<T> {
     };
 }
 
+/// Imports are strict by default, so that bad data is noticed.
+fn bounds_policy(clamp: Option<bool>) -> state::BoundsPolicy {
+    if clamp.unwrap_or(false) {
+        state::BoundsPolicy::Clamp
+    } else {
+        state::BoundsPolicy::Reject
+    }
+}
+
+fn check_resolution(resolution: f64) -> PyResult<()> {
+    if resolution > 0.0 {
+        Ok(())
+    } else {
+        Err(pyo3::exceptions::PyValueError::new_err(
+            "resolution must be positive",
+        ))
+    }
+}
+
+/**
+ * Parses a list of mode names, e.g. ["walking"] for routes that only walk and take transit. All
+ * modes are allowed if no list is given.
+ */
+fn parse_allowed_modes(names: Option<Vec<String>>) -> PyResult<route::AllowedModes> {
+    let names = match names {
+        Some(names) => names,
+        None => return Ok(route::AllowedModes::ALL),
+    };
+    let mut allowed_modes = route::AllowedModes::ALL;
+    for mode in route::MODES {
+        allowed_modes.set(*mode, false);
+    }
+    for name in names {
+        let mode = route::MODES
+            .iter()
+            .find(|mode| mode.to_string() == name)
+            .ok_or_else(|| {
+                pyo3::exceptions::PyValueError::new_err(format!("unknown mode: {}", name))
+            })?;
+        allowed_modes.set(*mode, true);
+    }
+    Ok(allowed_modes)
+}
+
 #[pyclass]
 #[derive(derive_more::From, derive_more::Into)]
 struct Address {
@@ -70,6 +139,7 @@ impl Config {
     #[staticmethod]
     fn from_json(json: String) -> PyResult<Self> {
         let config: state::Config = wrap_err(serde_json::from_str(&json))?;
+        wrap_err(config.validate())?;
         Ok(config.into())
     }
 }
@@ -112,6 +182,93 @@ impl LeafState {
         use tiles::TileType;
         self.leaf.tile.name()
     }
+
+    /// stays the same until the tile is split; see Engine.find_tile
+    #[getter]
+    fn id(&self) -> u64 {
+        self.leaf.id
+    }
+
+    /// number of people living on this tile
+    #[getter]
+    fn population(&self) -> PyResult<usize> {
+        Ok(self.fields()?.population.people.total)
+    }
+
+    /// people living on this tile per square tile unit
+    #[getter]
+    fn population_density(&self) -> PyResult<f64> {
+        Ok(self.fields()?.population.people.density())
+    }
+
+    /// number of people living on this tile in each age bucket, keyed by "children", "adults", and
+    /// "seniors"
+    #[getter]
+    fn population_by_age(&self) -> PyResult<HashMap<&'static str, usize>> {
+        Ok(self
+            .fields()?
+            .population
+            .people_by_age
+            .iter()
+            .map(|(bucket, people)| (bucket.name(), *people))
+            .collect())
+    }
+
+    /// fraction of the people living on this tile that are under 18
+    #[getter]
+    fn children_share(&self) -> PyResult<f64> {
+        Ok(self.fields()?.population.age_share(agent::AgeBucket::Child))
+    }
+
+    /// fraction of the people living on this tile that are 65 or older
+    #[getter]
+    fn seniors_share(&self) -> PyResult<f64> {
+        Ok(self
+            .fields()?
+            .population
+            .age_share(agent::AgeBucket::Senior))
+    }
+
+    /// number of people working on this tile
+    #[getter]
+    fn employment(&self) -> PyResult<usize> {
+        Ok(self.fields()?.employment.workers.total)
+    }
+
+    #[getter]
+    fn land_value(&self) -> PyResult<f64> {
+        Ok(self.fields()?.land_value.land_value.value)
+    }
+
+    /// demand for new workplaces
+    #[getter]
+    fn demand(&self) -> PyResult<f64> {
+        Ok(self.fields()?.demand.workplace_demand.value)
+    }
+
+    /// the kind of point of interest on this tile, e.g. "park", if there is one
+    #[getter]
+    fn poi_kind(&self) -> Option<&'static str> {
+        self.leaf.poi.as_ref().map(|poi| poi.kind.name())
+    }
+
+    #[getter]
+    fn poi_name(&self) -> Option<String> {
+        self.leaf.poi.as_ref().map(|poi| poi.name.clone())
+    }
+}
+
+impl LeafState {
+    /// The fields are only computed for leaves in an engine, after Engine.update_fields is called.
+    fn fields(&self) -> PyResult<&engine::FieldsState> {
+        if self.leaf.fields == engine::FieldsState::default() {
+            Err(PyEngineError::new_err(
+                "fields have not been computed, call update_fields first",
+            ))
+        } else {
+            Ok(&self.leaf.fields)
+        }
+    }
 }
 
 #[pyclass]
@@ -136,15 +293,47 @@ impl Engine {
         })
     }
 
-    fn save(&self, path: std::path::PathBuf) -> PyResult<()> {
+    fn save(&mut self, path: std::path::PathBuf) -> PyResult<()> {
         wrap_err(self.engine.dump_file(&path))
     }
 
+    /// Save the traffic history for the save at the given path, even if it hasn't changed much.
+    fn save_history(&mut self, path: std::path::PathBuf) -> PyResult<()> {
+        wrap_err(self.engine.dump_history_file(&path))
+    }
+
     #[getter]
     fn width(&self) -> u64 {
         self.engine.state.qtree.width()
     }
 
+    /// Describe the map, e.g. for the app to show instead of the filename.
+    fn set_metadata(&mut self, name: String, author: String, description: String) {
+        let metadata = &mut self.engine.state.metadata;
+        metadata.name = name;
+        metadata.author = author;
+        metadata.description = description;
+    }
+
+    /// Record where the map is in the real world, using the same projection as the importer.
+    fn set_georeference(&mut self, origin_lat: f64, origin_lon: f64, meters_per_tile: f64) {
+        self.engine.state.metadata.georeference = Some(state::Georeference {
+            origin_lat,
+            origin_lon,
+            meters_per_tile,
+        });
+    }
+
+    /// The latitude and longitude of a point in model coordinates, if the map is georeferenced.
+    fn to_lat_lon(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let state = &self.engine.state;
+        state.metadata.georeference.map(|georeference| {
+            georeference
+                .projection(state.qtree.width())
+                .to_lat_lon((x, y))
+        })
+    }
+
     #[getter]
     fn max_depth(&self) -> u32 {
         self.engine.state.qtree.max_depth()
@@ -192,19 +381,22 @@ impl Engine {
         )
     }
 
+    /// Fails if the location is outside of the map, unless clamp is set, in which case the
+    /// location is moved just inside of it.
     fn add_railway_junction(
         &mut self,
         x: f64,
         y: f64,
         data: &RailwayJunctionData,
-    ) -> RailwayJunctionHandle {
-        RailwayJunctionHandle {
-            handle: self
-                .engine
-                .state
-                .railways
-                .add_junction((x, y), data.data.clone()),
-        }
+        clamp: Option<bool>,
+    ) -> PyResult<RailwayJunctionHandle> {
+        Ok(RailwayJunctionHandle {
+            handle: wrap_err(self.engine.state.add_railway_junction(
+                (x, y),
+                data.data.clone(),
+                bounds_policy(clamp),
+            ))?,
+        })
     }
 
     fn add_railway_segment(
@@ -242,19 +434,72 @@ impl Engine {
         }
     }
 
+    /// Returns the railway segments that the metro line gained and lost.
+    fn reroute_metro_line(
+        &mut self,
+        metro_line: &MetroLineHandle,
+        segments: Vec<pyo3::PyRef<RailwaySegmentHandle>>,
+    ) -> PyResult<(Vec<RailwaySegmentHandle>, Vec<RailwaySegmentHandle>)> {
+        let diff = wrap_err(self.engine.reroute_metro_line(
+            metro_line.handle,
+            segments.iter().map(|segment| segment.handle).collect(),
+        ))?;
+        Ok((
+            diff.added.into_iter().map(|handle| handle.into()).collect(),
+            diff.removed
+                .into_iter()
+                .map(|handle| handle.into())
+                .collect(),
+        ))
+    }
+
+    /// Stations that aren't served by any metro line.
+    fn orphan_stations(&self) -> PyResult<Vec<Station>> {
+        Ok(wrap_err(self.engine.orphan_stations())?
+            .into_iter()
+            .map(|orphan| orphan.station.into())
+            .collect())
+    }
+
+    /// Make an orphan station a stop partway along the railway segment.
+    fn adopt_orphan_station(
+        &mut self,
+        segment: &RailwaySegmentHandle,
+        station: &Address,
+    ) -> PyResult<RailwayJunctionHandle> {
+        Ok(wrap_err(
+            self.engine
+                .adopt_orphan_station(segment.handle, station.address.clone()),
+        )?
+        .into())
+    }
+
+    /// Like add_railway_junction.
     fn add_highway_junction(
         &mut self,
         x: f64,
         y: f64,
         data: &HighwayJunctionData,
-    ) -> HighwayJunctionHandle {
-        HighwayJunctionHandle {
-            handle: self
-                .engine
-                .state
-                .highways
-                .add_junction((x, y), data.data.clone()),
-        }
+        clamp: Option<bool>,
+    ) -> PyResult<HighwayJunctionHandle> {
+        Ok(HighwayJunctionHandle {
+            handle: wrap_err(self.engine.state.add_highway_junction(
+                (x, y),
+                data.data.clone(),
+                bounds_policy(clamp),
+            ))?,
+        })
+    }
+
+    /// Move everything outside of the map just inside of it, returning a description of each
+    /// change.
+    fn repair_out_of_bounds(&mut self) -> Vec<String> {
+        self.engine
+            .repair_out_of_bounds()
+            .repairs
+            .iter()
+            .map(|repair| repair.to_string())
+            .collect()
     }
 
     fn add_highway_segment(
@@ -278,34 +523,197 @@ impl Engine {
         }
     }
 
+    fn inject_highway_travelers(
+        &mut self,
+        segment: &HighwaySegmentHandle,
+        count: f64,
+    ) -> PyResult<()> {
+        wrap_err(self.engine.inject_highway_travelers(segment.handle, count))
+    }
+
+    fn injected_highway_travelers(&self, segment: &HighwaySegmentHandle) -> f64 {
+        *self
+            .engine
+            .injected_highway_travelers()
+            .get(&segment.handle)
+            .unwrap_or(&0.0)
+    }
+
+    /// Change the speed limit of a highway segment, in m/s; None means the default speed.
+    fn set_highway_speed_limit(
+        &mut self,
+        segment: &HighwaySegmentHandle,
+        speed_limit: Option<u32>,
+    ) -> PyResult<()> {
+        wrap_err(self.engine.set_segment_speed_limit(
+            engine::NetworkSegment::Highway(segment.handle),
+            speed_limit,
+        ))?;
+        Ok(())
+    }
+
+    /// Change the speed limit of a railway segment, in m/s; None means no limit of its own.
+    /// Returns the metro lines whose timetables changed.
+    fn set_railway_speed_limit(
+        &mut self,
+        segment: &RailwaySegmentHandle,
+        speed_limit: Option<u32>,
+    ) -> PyResult<Vec<MetroLineHandle>> {
+        Ok(wrap_err(self.engine.set_segment_speed_limit(
+            engine::NetworkSegment::Railway(segment.handle),
+            speed_limit,
+        ))?
+        .into_iter()
+        .map(|handle| handle.into())
+        .collect())
+    }
+
     fn add_agent(
         &mut self,
         data: &AgentData,
         housing: &Address,
         workplace: Option<&Address>,
-    ) -> u64 {
-        self.engine.add_agent(
+    ) -> PyResult<u64> {
+        wrap_err(self.engine.add_agent(
             data.data.clone(),
             housing.address,
             workplace.map(|a| a.address),
+        ))
+    }
+
+    /// Save the agents and households to a file, separately from the map.
+    fn export_population(&self, path: std::path::PathBuf) -> PyResult<()> {
+        wrap_err(self.engine.export_population(&path))
+    }
+
+    /// Replace the agents and households with the ones in a file written by export_population.
+    /// Unless skip_invalid is set, nothing is imported if any of them don't fit on this map;
+    /// otherwise they are skipped. Returns a description of each one that was skipped.
+    fn import_population(
+        &mut self,
+        path: std::path::PathBuf,
+        skip_invalid: Option<bool>,
+    ) -> PyResult<Vec<String>> {
+        let policy = if skip_invalid.unwrap_or(false) {
+            engine::ImportPolicy::Skip
+        } else {
+            engine::ImportPolicy::Abort
+        };
+        let report = wrap_err(self.engine.import_population(&path, policy))?;
+        Ok(report
+            .skipped_households
+            .iter()
+            .map(|(index, err)| format!("household {}: {}", index, err))
+            .chain(
+                report
+                    .skipped
+                    .iter()
+                    .map(|(index, err)| format!("agent {}: {}", index, err)),
+            )
+            .collect())
+    }
+
+    fn populate_housing(
+        &mut self,
+        occupancy_rate: f64,
+        rng_seed: u64,
+        distribution: Option<&AgentDataDistribution>,
+    ) -> PyResult<usize> {
+        wrap_err(
+            self.engine.populate_housing(
+                occupancy_rate,
+                rng_seed,
+                &distribution
+                    .map(|d| d.distribution.clone())
+                    .unwrap_or_default(),
+            ),
         )
     }
 
+    fn assign_workplaces(
+        &mut self,
+        match_rate: f64,
+        rng_seed: u64,
+        max_commute_distance: f64,
+    ) -> PyResult<usize> {
+        wrap_err(
+            self.engine
+                .assign_workplaces(match_rate, rng_seed, max_commute_distance),
+        )
+    }
+
+    fn query_route(
+        &self,
+        start: &Address,
+        end: &Address,
+        has_car: bool,
+        allowed_modes: Option<Vec<String>>,
+    ) -> PyResult<Option<Route>> {
+        let route = wrap_err(self.engine.query_route(route::QueryInput {
+            start: start.address,
+            end: end.address,
+            car_config: has_car.then_some(route::CarConfig::StartWithCar),
+            profile: route::MobilityProfile::STANDARD,
+            allowed_modes: parse_allowed_modes(allowed_modes)?,
+        }))?;
+        Ok(route.map(|route| route.into()))
+    }
+
+    /// Like query_route, but with the cost of each leg broken down. See Engine::query_route_debug.
+    fn query_route_debug(
+        &self,
+        start: &Address,
+        end: &Address,
+        has_car: bool,
+        allowed_modes: Option<Vec<String>>,
+    ) -> PyResult<Option<RouteDebug>> {
+        let debug = wrap_err(self.engine.query_route_debug(route::QueryInput {
+            start: start.address,
+            end: end.address,
+            car_config: has_car.then_some(route::CarConfig::StartWithCar),
+            profile: route::MobilityProfile::STANDARD,
+            allowed_modes: parse_allowed_modes(allowed_modes)?,
+        }))?;
+        Ok(debug.map(|debug| debug.into()))
+    }
+
+    /// Polylines for each highway segment, in model coordinates, with samples `resolution` apart.
+    fn highway_geometry(&self, resolution: f64) -> PyResult<Vec<Vec<(f64, f64)>>> {
+        check_resolution(resolution)?;
+        Ok(self.engine.highway_geometry(resolution))
+    }
+
+    /// Polylines for each railway segment that is part of a metro line.
+    fn metro_geometry(&self, resolution: f64) -> PyResult<Vec<Vec<(f64, f64)>>> {
+        check_resolution(resolution)?;
+        Ok(self.engine.metro_geometry(resolution))
+    }
+
     fn validate_highways(&self) {
         self.engine.state.highways.validate();
     }
 
-    fn validate_metro_lines(&self) {
+    /// Descriptions of any discontinuities or impossible geometry in the metro lines.
+    fn validate_metro_lines(&self) -> Vec<String> {
         self.engine
             .state
             .metros
-            .validate(&self.engine.state.railways);
+            .validate(&self.engine.state.railways)
+            .issues
+            .iter()
+            .map(|issue| issue.to_string())
+            .collect()
     }
 
     fn get_address(&self, x: u64, y: u64) -> PyResult<Address> {
         Ok(wrap_err(self.engine.state.qtree.get_address(x, y))?.into())
     }
 
+    /// The address of the tile with the given id, or None if it has since been split.
+    fn find_tile(&self, id: u64) -> Option<Address> {
+        self.engine.state.find_tile(id).map(Address::from)
+    }
+
     fn split(
         &mut self,
         address: &Address,
@@ -315,7 +723,7 @@ impl Engine {
         sw: &LeafState,
         se: &LeafState,
     ) -> PyResult<()> {
-        wrap_err(self.engine.state.qtree.split(
+        wrap_err(self.engine.split_tile(
             address.address.clone(),
             data.branch.clone(),
             quadtree::QuadMap::new(
@@ -327,20 +735,127 @@ impl Engine {
         ))
     }
 
+    fn get_leaf(&self, address: &Address) -> PyResult<LeafState> {
+        Ok(wrap_err(self.engine.get_leaf(address.address))?
+            .clone()
+            .into())
+    }
+
+    fn update_fields(&mut self) -> PyResult<()> {
+        wrap_err(self.engine.update_fields())
+    }
+
+    /// The people and jobs within the given walking time of each station. Uses the computed
+    /// fields, so call update_fields first.
+    fn station_catchments(&mut self, max_walk_minutes: f64) -> PyResult<Vec<StationCatchment>> {
+        let catchments = wrap_err(self.engine.station_catchments(max_walk_minutes))?;
+        Ok(catchments
+            .stations
+            .iter()
+            .cloned()
+            .map(StationCatchment::from)
+            .collect())
+    }
+
+    fn station_catchments_csv(&mut self, max_walk_minutes: f64) -> PyResult<String> {
+        Ok(wrap_err(self.engine.station_catchments(max_walk_minutes))?.to_csv())
+    }
+
+    /// The trips that started on the given day. Only recorded if enabled in the config.
+    fn travel_diary(&self, day: u64) -> Vec<TravelRecord> {
+        self.engine
+            .travel_diary(day)
+            .iter()
+            .cloned()
+            .map(TravelRecord::from)
+            .collect()
+    }
+
+    fn export_travel_diary_csv(&self, path: &str, day: u64) -> PyResult<()> {
+        wrap_err(
+            self.engine
+                .export_travel_diary_csv(&std::path::PathBuf::from(path), day),
+        )
+    }
+
+    /// Yesterday's commutes to work as (home zone, work zone, trips), or None if there were none.
+    fn od_matrix(&self) -> Option<Vec<OdFlowTuple>> {
+        self.engine.od_matrix().map(|matrix| {
+            matrix
+                .flows()
+                .map(|flow| {
+                    (
+                        (flow.origin.x, flow.origin.y),
+                        (flow.destination.x, flow.destination.y),
+                        flow.trips,
+                    )
+                })
+                .collect()
+        })
+    }
+
+    fn export_od_matrix_csv(&self, path: &str) -> PyResult<()> {
+        wrap_err(
+            self.engine
+                .export_od_matrix_csv(&std::path::PathBuf::from(path)),
+        )
+    }
+
+    /// Compare observed traffic counts from a JSON file against the traffic history. The id map
+    /// written by the importer is needed for observations that are given by OSM way.
+    fn calibration_report(
+        &self,
+        observations_path: &str,
+        id_map_path: Option<&str>,
+    ) -> PyResult<CalibrationReport> {
+        let mut observations = wrap_err(engine::Observations::load_file(
+            &std::path::PathBuf::from(observations_path),
+        ))?;
+        if let Some(id_map_path) = id_map_path {
+            observations = observations.with_id_map(wrap_err(engine::OsmIdMap::load_file(
+                &std::path::PathBuf::from(id_map_path),
+            ))?);
+        }
+        Ok(wrap_err(self.engine.calibration_report(&observations))?.into())
+    }
+
+    /// Set the point of interest at the given leaf, e.g. a "park", or clear it if no kind is given.
+    fn set_poi(
+        &mut self,
+        address: &Address,
+        kind: Option<&str>,
+        name: Option<String>,
+    ) -> PyResult<()> {
+        let poi = match kind {
+            Some(kind) => Some(tiles::Poi {
+                kind: tiles::PoiKind::from_name(kind).ok_or_else(|| {
+                    pyo3::exceptions::PyValueError::new_err(format!("unknown POI kind: {}", kind))
+                })?,
+                name: name.unwrap_or_default(),
+            }),
+            None => None,
+        };
+        wrap_err(self.engine.set_poi(address.address, poi))
+    }
+
     fn get_leaf_json(&self, address: &Address) -> PyResult<String> {
         wrap_err(
             self.engine
-                .state
                 .get_leaf_data(address.address.clone(), state::SerdeFormat::Json),
         )
     }
 
+    /// The JSON Schema for the leaf JSON used by get_leaf_json and set_leaf_json, so that scripts
+    /// can validate leaves before setting them.
+    fn leaf_schema(&self) -> PyResult<String> {
+        wrap_err(serde_json::to_string(&engine::leaf_schema()))
+    }
+
     fn set_leaf_json(&mut self, address: &Address, json: &str) -> PyResult<()> {
-        wrap_err(self.engine.state.set_leaf_data(
-            address.address.clone(),
-            json,
-            state::SerdeFormat::Json,
-        ))
+        wrap_err(
+            self.engine
+                .set_leaf_data(address.address.clone(), json, state::SerdeFormat::Json),
+        )
     }
 }
 
@@ -442,12 +957,152 @@ impl Station {
         }
     }
 
+    #[getter]
+    fn name(&self) -> String {
+        self.station.name.clone()
+    }
+
     #[getter]
     fn address(&self) -> Address {
         self.station.address.clone().into()
     }
 }
 
+#[pyclass]
+#[derive(derive_more::From, derive_more::Into)]
+struct StationCatchment {
+    catchment: engine::StationCatchment,
+}
+
+#[pymethods]
+impl StationCatchment {
+    #[getter]
+    fn station(&self) -> Station {
+        self.catchment.station.clone().into()
+    }
+
+    #[getter]
+    fn people(&self) -> usize {
+        self.catchment.people
+    }
+
+    #[getter]
+    fn jobs(&self) -> usize {
+        self.catchment.jobs
+    }
+
+    #[getter]
+    fn unique_people(&self) -> usize {
+        self.catchment.unique_people
+    }
+
+    #[getter]
+    fn unique_jobs(&self) -> usize {
+        self.catchment.unique_jobs
+    }
+}
+
+#[pyclass]
+#[derive(derive_more::From, derive_more::Into)]
+struct CalibrationReport {
+    report: engine::CalibrationReport,
+}
+
+#[pymethods]
+impl CalibrationReport {
+    /// mean absolute percentage error over all observed hours, or None if nothing was observed
+    #[getter]
+    fn mape(&self) -> Option<f64> {
+        self.report.aggregate.mape
+    }
+
+    #[getter]
+    fn rmse(&self) -> f64 {
+        self.report.aggregate.rmse
+    }
+
+    /// (segment id, MAPE, RMSE, simulated/observed ratio) for each observed segment
+    #[getter]
+    fn segments(&self) -> Vec<(u64, Option<f64>, f64, Option<f64>)> {
+        self.report
+            .segments
+            .iter()
+            .map(|segment| {
+                (
+                    segment.segment.inner(),
+                    segment.metrics.mape,
+                    segment.metrics.rmse,
+                    segment.ratio(),
+                )
+            })
+            .collect()
+    }
+
+    /// the number of observations that could not be matched to a highway segment
+    #[getter]
+    fn unresolved(&self) -> usize {
+        self.report.unresolved.len()
+    }
+
+    fn to_csv(&self) -> String {
+        self.report.to_csv()
+    }
+}
+
+#[pyclass]
+#[derive(derive_more::From, derive_more::Into)]
+struct TravelRecord {
+    record: engine::TravelRecord,
+}
+
+#[pymethods]
+impl TravelRecord {
+    #[getter]
+    fn agent(&self) -> u64 {
+        self.record.agent
+    }
+
+    #[getter]
+    fn route_type(&self) -> String {
+        format!("{:?}", self.record.route_type)
+    }
+
+    #[getter]
+    fn departure(&self) -> u64 {
+        self.record.departure
+    }
+
+    #[getter]
+    fn arrival(&self) -> u64 {
+        self.record.arrival
+    }
+
+    #[getter]
+    fn origin(&self) -> (u64, u64) {
+        self.record.origin
+    }
+
+    #[getter]
+    fn destination(&self) -> (u64, u64) {
+        self.record.destination
+    }
+
+    /// (mode, duration in seconds) for each leg, in order
+    #[getter]
+    fn legs(&self) -> Vec<(String, f32)> {
+        self.record
+            .legs
+            .iter()
+            .map(|leg| (leg.mode.to_string(), leg.duration))
+            .collect()
+    }
+
+    #[getter]
+    fn aborted(&self) -> bool {
+        self.record.aborted
+    }
+}
+
 #[pyclass]
 #[derive(derive_more::From, derive_more::Into)]
 struct RailwaySegmentData {
@@ -456,11 +1111,21 @@ struct RailwaySegmentData {
 
 #[pymethods]
 impl RailwaySegmentData {
+    /// The alignment is one of "at_grade", "elevated", or "tunnel", and defaults to at grade.
     #[new]
-    fn new(speed_limit: Option<u32>) -> Self {
-        Self {
-            data: metro::RailwaySegment::new(speed_limit),
-        }
+    fn new(speed_limit: Option<u32>, alignment: Option<String>) -> PyResult<Self> {
+        let alignment = match alignment {
+            Some(name) => *metro::RailwayAlignment::ALL
+                .iter()
+                .find(|alignment| alignment.name() == name)
+                .ok_or_else(|| {
+                    pyo3::exceptions::PyValueError::new_err(format!("unknown alignment: {}", name))
+                })?,
+            None => metro::RailwayAlignment::AtGrade,
+        };
+        Ok(Self {
+            data: metro::RailwaySegment::new(speed_limit).with_alignment(alignment),
+        })
     }
 }
 
@@ -535,6 +1200,125 @@ struct MetroLineHandle {
     handle: metro::MetroLineHandle,
 }
 
+#[pyclass]
+#[derive(derive_more::From, derive_more::Into)]
+struct Route {
+    route: route::Route,
+}
+
+#[pymethods]
+impl Route {
+    #[getter]
+    fn cost(&self) -> f32 {
+        self.route.total_cost()
+    }
+
+    /// The route as a polyline, in model coordinates, with samples `resolution` apart.
+    fn geometry(&self, engine: &Engine, resolution: f64) -> PyResult<Vec<(f64, f64)>> {
+        check_resolution(resolution)?;
+        Ok(self.route.sample_polyline(resolution, &engine.engine.state))
+    }
+}
+
+#[pyclass]
+#[derive(Clone, Copy, derive_more::From, derive_more::Into)]
+struct CostComponents {
+    components: route::CostComponents,
+}
+
+#[pymethods]
+impl CostComponents {
+    #[getter]
+    fn travel_time(&self) -> f64 {
+        self.components.travel_time
+    }
+
+    #[getter]
+    fn base_time(&self) -> f64 {
+        self.components.base_time
+    }
+
+    #[getter]
+    fn congestion_factor(&self) -> f64 {
+        self.components.congestion_factor
+    }
+
+    #[getter]
+    fn congestion_delay(&self) -> f64 {
+        self.components.congestion_delay()
+    }
+
+    #[getter]
+    fn parking_search(&self) -> f64 {
+        self.components.parking_search
+    }
+
+    #[getter]
+    fn intersection_delay(&self) -> f64 {
+        self.components.intersection_delay
+    }
+
+    #[getter]
+    fn total(&self) -> f64 {
+        self.components.total()
+    }
+}
+
+#[pyclass]
+#[derive(derive_more::From, derive_more::Into)]
+struct RouteDebug {
+    debug: route::RouteDebug,
+}
+
+#[pymethods]
+impl RouteDebug {
+    #[getter]
+    fn route(&self) -> Route {
+        self.debug.route.clone().into()
+    }
+
+    /// The cost components of each leg of the route, in order.
+    #[getter]
+    fn components(&self) -> Vec<CostComponents> {
+        self.debug
+            .components
+            .iter()
+            .copied()
+            .map(CostComponents::from)
+            .collect()
+    }
+
+    #[getter]
+    fn traffic_time(&self) -> Option<u64> {
+        self.debug.traffic_time
+    }
+
+    #[getter]
+    fn free_flow_route(&self) -> Option<Route> {
+        self.debug.free_flow_route.clone().map(Route::from)
+    }
+
+    #[getter]
+    fn free_flow_components(&self) -> Vec<CostComponents> {
+        self.debug
+            .free_flow_components
+            .iter()
+            .copied()
+            .map(CostComponents::from)
+            .collect()
+    }
+
+    /// Pairs of leg indices in the route and the free-flow route where the two go separate ways.
+    #[getter]
+    fn divergences(&self) -> Vec<(usize, usize)> {
+        self.debug
+            .divergences
+            .iter()
+            .map(|divergence| (divergence.edge, divergence.other_edge))
+            .collect()
+    }
+}
+
 #[pyclass]
 #[derive(derive_more::From, derive_more::Into)]
 struct HighwaySegmentData {
@@ -607,6 +1391,15 @@ struct HighwaySegmentHandle {
     handle: network::SegmentHandle,
 }
 
+#[pymethods]
+impl HighwaySegmentHandle {
+    /// identifies the segment in calibration observations and the importer's id map
+    #[getter]
+    fn id(&self) -> u64 {
+        self.handle.inner()
+    }
+}
+
 #[pyclass]
 #[derive(Clone, Copy)]
 struct Date {
@@ -632,11 +1425,49 @@ struct AgentData {
 #[pymethods]
 impl AgentData {
     #[new]
-    fn new(birthday: Date, years_of_education: u32) -> Self {
+    fn new(
+        birthday: Date,
+        years_of_education: u32,
+        owns_car: bool,
+        walking_speed: f64,
+        archetype: Option<u32>,
+    ) -> Self {
         Self {
             data: agent::AgentData {
                 birthday: birthday.date,
                 years_of_education,
+                owns_car,
+                walking_speed,
+                archetype: archetype.unwrap_or_default(),
+                noise: Default::default(),
+            },
+        }
+    }
+}
+
+#[pyclass]
+#[derive(Clone)]
+struct AgentDataDistribution {
+    distribution: engine::AgentDataDistribution,
+}
+
+#[pymethods]
+impl AgentDataDistribution {
+    #[new]
+    fn new(
+        birth_years: (i32, i32),
+        years_of_education: (u32, u32),
+        car_ownership_rate: f64,
+        walking_speed: (f64, f64),
+        behavior_noise: Option<(f32, f32)>,
+    ) -> Self {
+        Self {
+            distribution: engine::AgentDataDistribution {
+                birth_years,
+                years_of_education,
+                car_ownership_rate,
+                walking_speed,
+                behavior_noise: behavior_noise.unwrap_or((1.0, 1.0)),
             },
         }
     }
@@ -648,7 +1479,18 @@ fn min_creation_time() -> i64 {
 }
 
 #[pymodule]
-fn engine(_py: Python, m: &PyModule) -> PyResult<()> {
+fn engine(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("EngineError", py.get_type::<PyEngineError>())?;
+    m.add(
+        "NotAHousingTileError",
+        py.get_type::<NotAHousingTileError>(),
+    )?;
+    m.add("HousingFullError", py.get_type::<HousingFullError>())?;
+    m.add(
+        "NotAWorkplaceTileError",
+        py.get_type::<NotAWorkplaceTileError>(),
+    )?;
+    m.add("WorkplaceFullError", py.get_type::<WorkplaceFullError>())?;
     m.add_class::<Config>()?;
     m.add_class::<Address>()?;
     m.add_class::<BranchState>()?;
@@ -659,6 +1501,9 @@ fn engine(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<RailwaySegmentData>()?;
     m.add_class::<RailwayJunctionData>()?;
     m.add_class::<Station>()?;
+    m.add_class::<StationCatchment>()?;
+    m.add_class::<TravelRecord>()?;
+    m.add_class::<CalibrationReport>()?;
     m.add_class::<RailwayJunctionHandle>()?;
     m.add_class::<RailwaySegmentHandle>()?;
     m.add_class::<MetroLineData>()?;
@@ -671,8 +1516,13 @@ fn engine(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<HighwayJunctionHandle>()?;
     m.add_class::<HighwaySegmentHandle>()?;
 
+    m.add_class::<Route>()?;
+    m.add_class::<RouteDebug>()?;
+    m.add_class::<CostComponents>()?;
+
     m.add_class::<Date>()?;
     m.add_class::<AgentData>()?;
+    m.add_class::<AgentDataDistribution>()?;
 
     m.add_function(wrap_pyfunction!(min_creation_time, m)?)?;
 
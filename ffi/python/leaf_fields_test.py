@@ -0,0 +1,88 @@
+import json
+import unittest
+
+import engine
+
+MAX_DEPTH = 1
+
+
+class LeafFieldsTest(unittest.TestCase):
+    def setUp(self):
+        config = engine.Config.from_json(
+            json.dumps({"max_depth": MAX_DEPTH, "people_per_sim": 1.0, "min_tile_size": 100})
+        )
+        self.engine = engine.Engine(config)
+        self.engine.split(
+            engine.Address([], MAX_DEPTH),
+            engine.BranchState(),
+            *[engine.LeafState() for _ in range(4)],
+        )
+
+        self.housing = self.engine.get_address(0, 0)
+        self.engine.set_leaf_json(
+            self.housing,
+            json.dumps(
+                {
+                    "tile": {"type": "HousingTile", "density": 4, "agents": []},
+                    "creation_time": 0,
+                }
+            ),
+        )
+        data = engine.AgentData(engine.Date.from_ymd(1990, 1, 1), 16, False, 1.4)
+        for _ in range(2):
+            self.engine.add_agent(data, self.housing, None)
+
+    def test_uncomputed_fields(self):
+        leaf = self.engine.get_leaf(self.housing)
+        with self.assertRaises(Exception):
+            leaf.population
+
+    def test_populated_leaf(self):
+        self.engine.update_fields()
+        leaf = self.engine.get_leaf(self.housing)
+
+        self.assertEqual(leaf.name, "housing")
+        self.assertEqual(leaf.population, 2)
+        self.assertEqual(leaf.employment, 0)
+        # the map is 2x2, so each quadrant has an area of one
+        self.assertAlmostEqual(leaf.population_density, 2.0)
+        # both agents were born in 1990, and the simulation starts in 2020
+        self.assertEqual(
+            leaf.population_by_age, {"children": 0, "adults": 2, "seniors": 0}
+        )
+        self.assertEqual(leaf.children_share, 0.0)
+        self.assertEqual(leaf.seniors_share, 0.0)
+        self.assertIsInstance(leaf.land_value, float)
+        self.assertIsInstance(leaf.demand, float)
+
+    def test_poi(self):
+        park = self.engine.get_address(1, 1)
+        self.assertIsNone(self.engine.get_leaf(park).poi_kind)
+
+        self.engine.set_poi(park, "park", "Central Park")
+        leaf = self.engine.get_leaf(park)
+        self.assertEqual(leaf.name, "empty")
+        self.assertEqual(leaf.poi_kind, "park")
+        self.assertEqual(leaf.poi_name, "Central Park")
+        self.assertEqual(
+            json.loads(self.engine.get_leaf_json(park))["poi"],
+            {"kind": "Park", "name": "Central Park"},
+        )
+
+        with self.assertRaises(ValueError):
+            self.engine.set_poi(park, "lava", None)
+        self.engine.set_poi(park, None, None)
+        self.assertIsNone(self.engine.get_leaf(park).poi_kind)
+
+    def test_tile_ids(self):
+        leaf = self.engine.get_leaf(self.housing)
+        self.assertEqual(self.engine.find_tile(leaf.id).get(), self.housing.get())
+        self.assertEqual(json.loads(self.engine.get_leaf_json(self.housing))["id"], leaf.id)
+
+        # the root was split when setting up, so its id is gone
+        self.assertIsNone(self.engine.find_tile(0))
+        self.assertIsNone(self.engine.find_tile(1))
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -491,7 +491,9 @@ def generate(
     report_timestamp("done")
 
 
-def bake(baker_data_path: str, output_path: str) -> None:
+def bake(
+    baker_data_path: str, output_path: str, id_map_path: T.Optional[str] = None
+) -> None:
     import engine
 
     with open(baker_data_path, "rb") as f:
@@ -501,6 +503,11 @@ def bake(baker_data_path: str, output_path: str) -> None:
         engine.Config.from_json(json.dumps(baker_data.map_config.engine_config))
     )
 
+    map_config = baker_data.map_config
+    state.set_metadata(map_config.name, "", "")
+    (lat, lon) = parse_lat_lon(map_config.latitude, map_config.longitude)
+    state.set_georeference(lat, lon, map_config.engine_config["min_tile_size"])
+
     report_timestamp("write qtree")
     write_qtree(state, baker_data.qtree)
 
@@ -511,6 +518,16 @@ def bake(baker_data_path: str, output_path: str) -> None:
     report_timestamp("save")
     state.save(output_path)
 
+    if id_map_path is not None:
+        # lets observations keyed by OSM way be matched up with the baked highway segments
+        report_timestamp("save id map")
+        highway_segments: T.Dict[int, T.List[int]] = {}
+        for layer in baker_data.layers:
+            if isinstance(layer, highways.Highways):
+                highway_segments.update(layer.way_id_map())
+        with open(id_map_path, "w") as f:
+            json.dump({"highway_segments": highway_segments}, f)
+
 
 if __name__ == "__main__":
     try:
@@ -98,6 +98,25 @@ class JunctionData:
 @dataclass
 class SegmentData:
     speed_limit: T.Optional[int]
+    alignment: str = "at_grade"
+
+
+def parse_alignment(tags: T.Dict[str, str]) -> str:
+    """
+    Parse OSM's "tunnel" and "bridge" tags; returns the engine's name for the alignment.
+    """
+    if (
+        tags.get("tunnel", "no").lower() != "no"
+        or tags.get("location") == "underground"
+    ):
+        return "tunnel"
+    if tags.get("bridge", "no").lower() != "no":
+        return "elevated"
+    return "at_grade"
+
+
+def parse_segment_data(tags: T.Dict[str, str]) -> SegmentData:
+    return SegmentData(parse_speed_limit(tags), parse_alignment(tags))
 
 
 @dataclass
@@ -170,7 +189,7 @@ class Metros(Network):
             yield InputWay(
                 subway,
                 False,
-                SegmentData(parse_speed_limit(subway.tags)),
+                parse_segment_data(subway.tags),
             )
 
     def get_nodes(self, osm: osm.OsmData) -> T.Generator[InputNode, None, None]:
@@ -219,7 +238,7 @@ class Metros(Network):
 
         assert data is not None
 
-        data = engine.RailwaySegmentData(data.speed_limit)
+        data = engine.RailwaySegmentData(data.speed_limit, data.alignment)
         return state.add_railway_segment(data, start_id, end_id, points)
 
     def post_init_route(self, route_index: int, route: osm.Relation) -> None:
@@ -492,4 +511,6 @@ class Metros(Network):
                 segment_handles,
             )
 
-        state.validate_metro_lines()
+        issues = state.validate_metro_lines()
+        if issues:
+            raise Exception("Invalid metro lines:\n{}".format("\n".join(issues)))
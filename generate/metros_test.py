@@ -0,0 +1,38 @@
+import os
+import unittest
+
+from generate import osm
+from generate.data import Coords
+from generate.metros import parse_alignment, parse_segment_data
+
+EXTRACT = os.path.join(os.path.dirname(__file__), "testdata", "alignment_extract.json")
+
+
+class AlignmentTest(unittest.TestCase):
+    def setUp(self):
+        dataset = {"tiles": [EXTRACT]}
+        self.osm = osm.read_osm(dataset, Coords(42.36, -71.0575, 1000), 64)
+
+    def test_extract(self):
+        alignments = {
+            subway.id: parse_alignment(subway.tags) for subway in self.osm.subways
+        }
+        self.assertEqual(
+            alignments,
+            {
+                101: "tunnel",
+                102: "elevated",
+                103: "at_grade",
+                104: "at_grade",
+                105: "tunnel",
+            },
+        )
+
+    def test_segment_data(self):
+        data = parse_segment_data(self.osm.subway_map[101].tags)
+        self.assertEqual(data.speed_limit, 17)
+        self.assertEqual(data.alignment, "tunnel")
+
+
+if __name__ == "__main__":
+    unittest.main()
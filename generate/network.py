@@ -438,3 +438,16 @@ class Network(Layer):
             segment.handle = self.bake_segment(
                 segment.data, state, start_id, end_id, segment.points
             )
+
+    def way_id_map(self) -> T.Dict[int, T.List[int]]:
+        """
+        Map from OSM way IDs to the IDs of the baked segments that they ended up in. Only valid
+        after modify_state, and only for networks whose segment handles have IDs.
+        """
+        way_id_map: T.Dict[int, T.List[int]] = defaultdict(list)
+        for segment in self.segments:
+            if segment.handle is None:
+                continue
+            for way_id in segment.way_ids:
+                way_id_map[way_id].append(segment.handle.id)
+        return dict(way_id_map)
@@ -1,6 +1,7 @@
 import typing as T
 
 from generate.data import MapConfig
+from generate.layer import Tile
 from generate.simple_density import SimpleDensity
 
 
@@ -10,3 +11,10 @@ class Workplaces(SimpleDensity):
 
     def get_dataset(self) -> T.Optional[T.Dict[str, T.Any]]:
         return self.map_config.datasets["employment"]
+
+    def finalize(self, data: float) -> Tile:
+        tile = super().finalize(data)
+        if tile.kind == self.tile_name:
+            # TODO: the employment dataset doesn't distinguish between industries
+            tile.fields["industry"] = "Office"
+        return tile
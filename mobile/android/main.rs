@@ -28,7 +28,9 @@ fn main() {
 
     // TODO: don't hard-code map
     // eventually we will want a menu and the ability to select a map
-    let app = app::App::load_str(&load_map("sf.json"));
+    // phones vary too much to pick a fixed quality
+    let app = app::App::load_str(&load_map("sf.json"), app::QualityPreset::Auto)
+        .expect("failed to load map");
 
     app::bootstrap(app, true);
 }
@@ -0,0 +1,21 @@
+//! Writes a JSON Schema file for each of the serialized types that external tools read or write,
+//! e.g. leaves, tiles, network data, and the top-level layout of save files.
+
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// Directory to write the schema files to. Created if it doesn't exist.
+    output: std::path::PathBuf,
+}
+
+fn main() {
+    use clap::Parser;
+    let args = Args::parse();
+
+    std::fs::create_dir_all(&args.output).unwrap();
+
+    for (name, schema) in engine::all_schemas() {
+        let path = args.output.join(format!("{}.schema.json", name));
+        std::fs::write(&path, serde_json::to_string_pretty(&schema).unwrap()).unwrap();
+        println!("Wrote {}", path.display());
+    }
+}
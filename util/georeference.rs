@@ -0,0 +1,134 @@
+//! Conversion between real-world latitude/longitude and model coordinates, shared between the map
+//! importer and the viewers so that they agree on where things are.
+//!
+//! The importer (see generate/osm.py) uses a simple equirectangular projection centered on the
+//! map's origin: degrees of latitude are a fixed distance apart, and degrees of longitude shrink
+//! with the cosine of the origin's latitude. Model x increases to the east and model y increases to
+//! the south, with the origin at the center of the map. This is only accurate for city-sized maps,
+//! which is all the importer produces.
+
+/// Meters per degree of latitude, or of longitude at the equator. Matches EQ_KM_PER_DEG in the
+/// importer.
+pub const METERS_PER_DEGREE: f64 = 111_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Projection {
+    /// the latitude at the center of the map, in degrees
+    pub origin_lat: f64,
+    /// the longitude at the center of the map, in degrees
+    pub origin_lon: f64,
+    /// the size of a model unit (the smallest possible tile), in meters
+    pub meters_per_tile: f64,
+    /// the width of the map, in model units
+    pub map_width: f64,
+}
+
+impl Projection {
+    fn meters_per_degree_lon(&self) -> f64 {
+        METERS_PER_DEGREE * self.origin_lat.to_radians().cos()
+    }
+
+    /// The model coordinates of the given latitude and longitude, as the importer places them.
+    pub fn to_model(&self, (lat, lon): (f64, f64)) -> (f64, f64) {
+        let center = self.map_width / 2.0;
+        (
+            center + (lon - self.origin_lon) * self.meters_per_degree_lon() / self.meters_per_tile,
+            center - (lat - self.origin_lat) * METERS_PER_DEGREE / self.meters_per_tile,
+        )
+    }
+
+    /// The latitude and longitude of the given model coordinates; the inverse of to_model.
+    pub fn to_lat_lon(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let center = self.map_width / 2.0;
+        (
+            self.origin_lat - (y - center) * self.meters_per_tile / METERS_PER_DEGREE,
+            self.origin_lon + (x - center) * self.meters_per_tile / self.meters_per_degree_lon(),
+        )
+    }
+}
+
+/// e.g. "37.77490°N, 122.41940°W"
+pub fn format_lat_lon((lat, lon): (f64, f64)) -> String {
+    format!(
+        "{:.5}°{}, {:.5}°{}",
+        lat.abs(),
+        if lat < 0.0 { "S" } else { "N" },
+        lon.abs(),
+        if lon < 0.0 { "W" } else { "E" },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// San Francisco, 128 tiles of 100 meters on each side
+    const SF: Projection = Projection {
+        origin_lat: 37.7749,
+        origin_lon: -122.4194,
+        meters_per_tile: 100.0,
+        map_width: 128.0,
+    };
+
+    /**
+     * The projection as the importer computes it: an affine transform from the bounding box of the
+     * map in degrees to [0, width], followed by flipping y.
+     */
+    fn importer_forward(projection: &Projection, (lat, lon): (f64, f64)) -> (f64, f64) {
+        let radius = projection.meters_per_tile * projection.map_width / 2.0;
+        let lat_radius = radius / 1000.0 / (METERS_PER_DEGREE / 1000.0);
+        let lon_radius = lat_radius / projection.origin_lat.to_radians().cos();
+        let (min_lat, max_lat) = (
+            projection.origin_lat - lat_radius,
+            projection.origin_lat + lat_radius,
+        );
+        let (min_lon, max_lon) = (
+            projection.origin_lon - lon_radius,
+            projection.origin_lon + lon_radius,
+        );
+        let x_scale = projection.map_width / (max_lon - min_lon);
+        let y_scale = projection.map_width / (max_lat - min_lat);
+        (
+            lon * x_scale - min_lon * x_scale,
+            projection.map_width - (lat * y_scale - min_lat * y_scale),
+        )
+    }
+
+    fn assert_close((a1, a2): (f64, f64), (b1, b2): (f64, f64), tolerance: f64) {
+        assert!(
+            (a1 - b1).abs() < tolerance && (a2 - b2).abs() < tolerance,
+            "{:?} != {:?}",
+            (a1, a2),
+            (b1, b2)
+        );
+    }
+
+    #[test]
+    fn inverts_importer() {
+        for (x, y) in [(0.0, 0.0), (64.0, 64.0), (128.0, 0.0), (10.5, 117.25)] {
+            let lat_lon = SF.to_lat_lon((x, y));
+            assert_close(importer_forward(&SF, lat_lon), (x, y), 1e-6);
+            assert_close(SF.to_model(lat_lon), (x, y), 1e-9);
+        }
+
+        // the origin is at the center, north is up, and east is to the right
+        assert_close(SF.to_lat_lon((64.0, 64.0)), (37.7749, -122.4194), 1e-12);
+        let (lat, lon) = SF.to_lat_lon((100.0, 10.0));
+        assert!(lat > SF.origin_lat && lon > SF.origin_lon);
+    }
+
+    #[test]
+    fn southern_hemisphere() {
+        let sydney = Projection {
+            origin_lat: -33.8688,
+            origin_lon: 151.2093,
+            ..SF
+        };
+        let lat_lon = sydney.to_lat_lon((3.0, 90.0));
+        assert_close(importer_forward(&sydney, lat_lon), (3.0, 90.0), 1e-6);
+        assert_eq!(
+            format_lat_lon(sydney.to_lat_lon((64.0, 64.0))),
+            "33.86880°S, 151.20930°E"
+        );
+    }
+}
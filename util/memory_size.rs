@@ -0,0 +1,100 @@
+//! Explicit accounting of how much memory data structures use, for diagnostics. The estimates are
+//! computed from lengths, capacities, and element sizes instead of by a heap profiler, so that they
+//! work on every platform, but they don't include allocator overhead or anything that isn't
+//! accounted for by hand.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem::size_of;
+
+pub trait MemorySize {
+    /// Estimated bytes allocated on the heap and owned by this value, not counting the value itself.
+    fn heap_bytes(&self) -> usize;
+
+    /// Estimated bytes for the value itself and everything it owns.
+    fn estimated_bytes(&self) -> usize {
+        std::mem::size_of_val(self) + self.heap_bytes()
+    }
+}
+
+/// The allocation backing a Vec, not counting anything owned by its elements.
+pub fn vec_bytes<T>(vec: &Vec<T>) -> usize {
+    vec.capacity() * size_of::<T>()
+}
+
+/**
+ * The allocation backing a HashMap, not counting anything owned by its keys and values. The map
+ * stores keys and values inline, with an extra control byte for each.
+ */
+pub fn hash_map_bytes<K, V, S>(map: &HashMap<K, V, S>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+/// Like hash_map_bytes, for a HashSet.
+pub fn hash_set_bytes<T, S>(set: &HashSet<T, S>) -> usize {
+    set.capacity() * (size_of::<T>() + 1)
+}
+
+/**
+ * The nodes backing a BTreeMap, not counting anything owned by its keys and values. BTreeMap
+ * doesn't expose its node layout, so this assumes that the nodes are about two-thirds full.
+ */
+pub fn btree_map_bytes<K, V>(map: &BTreeMap<K, V>) -> usize {
+    map.len() * size_of::<(K, V)>() * 3 / 2
+}
+
+impl<T: MemorySize> MemorySize for Vec<T> {
+    fn heap_bytes(&self) -> usize {
+        vec_bytes(self) + self.iter().map(T::heap_bytes).sum::<usize>()
+    }
+}
+
+impl MemorySize for () {
+    fn heap_bytes(&self) -> usize {
+        0
+    }
+}
+
+impl MemorySize for String {
+    fn heap_bytes(&self) -> usize {
+        self.capacity()
+    }
+}
+
+/// A human-readable size, e.g. "12.3 MiB".
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn vec_size() {
+        let vec: Vec<u64> = Vec::with_capacity(10);
+        assert_eq!(vec_bytes(&vec), 80);
+
+        let strings = vec![String::with_capacity(5), String::with_capacity(7)];
+        assert_eq!(
+            strings.estimated_bytes(),
+            size_of::<Vec<String>>() + strings.capacity() * size_of::<String>() + 12
+        );
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+}
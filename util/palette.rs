@@ -0,0 +1,196 @@
+/**
+ * Color palettes for drawing overlays, shared between the viewers.
+ *
+ * Palettes map a scale value in [0, 1] to an RGB color. By convention, 0 is "bad" (e.g. heavy
+ * traffic, low values) and 1 is "good" (e.g. free-flowing traffic, high values). Colors are
+ * returned as plain RGB tuples so that each viewer can convert them to its own color type.
+ */
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, enum_iterator::IntoEnumIterator)]
+pub enum Palette {
+    /// the hue ramps the overlays were originally drawn with; see HueRamp
+    #[default]
+    Hue,
+    /// perceptually uniform ramp from purple to yellow
+    Viridis,
+    /// perceptually uniform ramp from blue to yellow, designed for color vision deficiency
+    Cividis,
+    /// colorblind-safe diverging ramp from orange to purple through white
+    PurpleOrange,
+}
+
+/**
+ * Before there were palettes, each overlay picked its own range of hues and its own saturation.
+ * The hue palette keeps those ramps so that the default look doesn't change; the other palettes
+ * ignore this.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HueRamp {
+    /// muted red to cyan, used by the field and isochrone overlays
+    Field,
+    /// muted red to green, used by the traffic field overlay
+    TrafficField,
+    /// vivid red to green, used for traffic on highways
+    Traffic,
+}
+
+// sampled at evenly-spaced intervals from the matplotlib colormaps
+const VIRIDIS: [(u8, u8, u8); 9] = [
+    (68, 1, 84),
+    (71, 44, 122),
+    (59, 81, 139),
+    (44, 113, 142),
+    (33, 144, 141),
+    (39, 173, 129),
+    (92, 200, 99),
+    (170, 220, 50),
+    (253, 231, 37),
+];
+
+const CIVIDIS: [(u8, u8, u8); 9] = [
+    (0, 34, 78),
+    (18, 53, 112),
+    (59, 73, 108),
+    (87, 92, 109),
+    (112, 113, 115),
+    (138, 134, 120),
+    (165, 156, 116),
+    (195, 179, 105),
+    (254, 232, 56),
+];
+
+// ColorBrewer PuOr, reversed so that orange is "bad" and purple is "good"
+const PURPLE_ORANGE: [(u8, u8, u8); 9] = [
+    (179, 88, 6),
+    (224, 130, 20),
+    (253, 184, 99),
+    (254, 224, 182),
+    (247, 247, 247),
+    (216, 218, 235),
+    (178, 171, 210),
+    (128, 115, 172),
+    (84, 39, 136),
+];
+
+impl Palette {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Hue => "Hue",
+            Self::Viridis => "Viridis",
+            Self::Cividis => "Cividis",
+            Self::PurpleOrange => "Purple-orange",
+        }
+    }
+
+    /// Look up the color for the given scale value, which is clamped to [0, 1].
+    pub fn color(&self, scale: f32, ramp: HueRamp) -> (u8, u8, u8) {
+        match self {
+            Self::Hue => {
+                let (r, g, b) = self.linear_color(scale, ramp);
+                (
+                    gamma_u8_from_linear(r),
+                    gamma_u8_from_linear(g),
+                    gamma_u8_from_linear(b),
+                )
+            }
+            Self::Viridis => interpolate(&VIRIDIS, clamp_scale(scale)),
+            Self::Cividis => interpolate(&CIVIDIS, clamp_scale(scale)),
+            Self::PurpleOrange => interpolate(&PURPLE_ORANGE, clamp_scale(scale)),
+        }
+    }
+
+    /**
+     * Like color, but in linear RGB. Viewers that blend in linear space should use this, since the
+     * hue ramps are defined in linear space and would otherwise be rounded twice.
+     */
+    pub fn linear_color(&self, scale: f32, ramp: HueRamp) -> (f32, f32, f32) {
+        let scale = clamp_scale(scale);
+        match self {
+            Self::Hue => match ramp {
+                // ranges from 0.0 (red) to 0.5 (cyan)
+                HueRamp::Field => hsv_to_linear(scale * 0.5, 0.8, 0.8),
+                // ranges from 0.0 (red) to 1/3 (green)
+                HueRamp::TrafficField => hsv_to_linear(scale / 3.0, 0.8, 0.8),
+                HueRamp::Traffic => hsv_to_linear(scale / 3.0, 1.0, 1.0),
+            },
+            _ => {
+                let (r, g, b) = self.color(scale, ramp);
+                (
+                    linear_from_gamma_u8(r),
+                    linear_from_gamma_u8(g),
+                    linear_from_gamma_u8(b),
+                )
+            }
+        }
+    }
+}
+
+fn clamp_scale(scale: f32) -> f32 {
+    if scale.is_nan() {
+        0.0
+    } else {
+        scale.clamp(0.0, 1.0)
+    }
+}
+
+/// Normalize a value to [0, 1] given the range it is expected to fall in.
+pub fn scale(val: f32, min: f32, max: f32) -> f32 {
+    if max > min {
+        (f32::min(f32::max(val, min), max) - min) / (max - min)
+    } else {
+        0.0
+    }
+}
+
+fn interpolate(stops: &[(u8, u8, u8)], scale: f32) -> (u8, u8, u8) {
+    let pos = scale * (stops.len() - 1) as f32;
+    let index = (pos.floor() as usize).min(stops.len() - 2);
+    let t = pos - index as f32;
+
+    let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    let (r1, g1, b1) = stops[index];
+    let (r2, g2, b2) = stops[index + 1];
+    (lerp(r1, r2), lerp(g1, g2), lerp(b1, b2))
+}
+
+/**
+ * Convert HSV to linear RGB the same way egui does for `Hsva` colors, which treats the HSV
+ * components as linear. Written the same way as egui so that rounding matches exactly.
+ */
+fn hsv_to_linear(h: f32, s: f32, v: f32) -> (f32, f32, f32) {
+    let h = (h.fract() + 1.0).fract();
+    let s = s.clamp(0.0, 1.0);
+
+    let f = h * 6.0 - (h * 6.0).floor();
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - f * s);
+    let t = v * (1.0 - (1.0 - f) * s);
+
+    match (h * 6.0).floor() as i32 % 6 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    }
+}
+
+fn gamma_u8_from_linear(l: f32) -> u8 {
+    if l <= 0.0 {
+        0
+    } else if l <= 0.0031308 {
+        (3294.6 * l + 0.5) as u8
+    } else if l <= 1.0 {
+        (269.025 * l.powf(1.0 / 2.4) - 14.025 + 0.5) as u8
+    } else {
+        255
+    }
+}
+
+fn linear_from_gamma_u8(s: u8) -> f32 {
+    if s <= 10 {
+        s as f32 / 3294.6
+    } else {
+        ((s as f32 + 14.025) / 269.025).powf(2.4)
+    }
+}
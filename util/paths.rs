@@ -0,0 +1,263 @@
+//! Where the viewers read and write files. Each directory defaults to the platform's conventions
+//! (e.g. ~/.config and ~/.local/share on Linux, ~/Library on macOS, and AppData on Windows), and
+//! can be overridden with an environment variable, e.g. to point at a checkout during development.
+//! Directories are only created once something is written to them.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// where configs are looked for if the config directory doesn't have them, relative to the
+/// working directory; this is where they live in a checkout
+const CHECKOUT_CONFIG_DIR: &str = "configs";
+
+/// where older versions of the editor saved maps
+const LEGACY_SAVE_DIR: &str = "/tmp";
+/// the prefix of the files that older versions of the editor saved
+const LEGACY_SAVE_PREFIX: &str = "metro_simulator_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dir {
+    /// simulation configs, e.g. debug.toml
+    Config,
+    /// maps to load
+    Maps,
+    /// maps saved by the viewers
+    Saves,
+    /// replays, traces, and other output that can be thrown away
+    Output,
+}
+
+impl Dir {
+    pub const ALL: [Self; 4] = [Self::Config, Self::Maps, Self::Saves, Self::Output];
+
+    /// The environment variable that overrides this directory.
+    pub fn env_var(self) -> &'static str {
+        match self {
+            Self::Config => "METRO_SIMULATOR_CONFIG_DIR",
+            Self::Maps => "METRO_SIMULATOR_MAPS_DIR",
+            Self::Saves => "METRO_SIMULATOR_SAVE_DIR",
+            Self::Output => "METRO_SIMULATOR_OUTPUT_DIR",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    config: PathBuf,
+    maps: PathBuf,
+    saves: PathBuf,
+    output: PathBuf,
+}
+
+impl Paths {
+    /// The platform's directories, with any overrides from the environment.
+    pub fn resolve() -> Self {
+        Self::platform_defaults().with_overrides(|var| std::env::var_os(var))
+    }
+
+    /// The platform's directories, ignoring the environment.
+    pub fn platform_defaults() -> Self {
+        match directories::ProjectDirs::from("com", "calsignlabs", "metro_simulator") {
+            Some(project) => Self {
+                config: project.config_dir().to_path_buf(),
+                maps: project.data_dir().join("maps"),
+                saves: project.data_dir().join("saves"),
+                output: project.cache_dir().to_path_buf(),
+            },
+            // e.g. there is no home directory, as on Android
+            None => Self::under(&std::env::temp_dir().join("metro_simulator")),
+        }
+    }
+
+    /// Every directory in its own subdirectory of the root.
+    pub fn under(root: &Path) -> Self {
+        Self {
+            config: root.join("config"),
+            maps: root.join("maps"),
+            saves: root.join("saves"),
+            output: root.join("output"),
+        }
+    }
+
+    /// Replace each directory whose environment variable is set to something other than "".
+    pub fn with_overrides<F>(mut self, env: F) -> Self
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        for dir in Dir::ALL {
+            if let Some(value) = env(dir.env_var()).filter(|value| !value.is_empty()) {
+                *self.dir_mut(dir) = PathBuf::from(value);
+            }
+        }
+        self
+    }
+
+    pub fn dir(&self, dir: Dir) -> &Path {
+        match dir {
+            Dir::Config => &self.config,
+            Dir::Maps => &self.maps,
+            Dir::Saves => &self.saves,
+            Dir::Output => &self.output,
+        }
+    }
+
+    fn dir_mut(&mut self, dir: Dir) -> &mut PathBuf {
+        match dir {
+            Dir::Config => &mut self.config,
+            Dir::Maps => &mut self.maps,
+            Dir::Saves => &mut self.saves,
+            Dir::Output => &mut self.output,
+        }
+    }
+
+    /// The directory, creating it first if it doesn't exist yet.
+    pub fn create(&self, dir: Dir) -> std::io::Result<&Path> {
+        let path = self.dir(dir);
+        std::fs::create_dir_all(path)?;
+        Ok(path)
+    }
+
+    /// A path for writing the named file to the directory, which is created if needed.
+    pub fn file(&self, dir: Dir, name: &str) -> std::io::Result<PathBuf> {
+        Ok(self.create(dir)?.join(name))
+    }
+
+    /**
+     * The named config. If the config directory doesn't have it, but the configs directory of a
+     * checkout does, that one is used instead.
+     */
+    pub fn find_config(&self, name: &str) -> PathBuf {
+        let path = self.config.join(name);
+        let checkout = Path::new(CHECKOUT_CONFIG_DIR).join(name);
+        if !path.exists() && checkout.exists() {
+            checkout
+        } else {
+            path
+        }
+    }
+
+    /**
+     * Copy the maps that older versions of the editor saved to /tmp into the save directory. The
+     * originals are left where they are, and maps that were copied before are skipped. Returns the
+     * paths of the new copies.
+     */
+    pub fn migrate_legacy_saves(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.migrate_saves_from(Path::new(LEGACY_SAVE_DIR))
+    }
+
+    fn migrate_saves_from(&self, legacy_dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let entries = match std::fs::read_dir(legacy_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+
+        let mut copied = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let is_save = name.to_str().map_or(false, |name| {
+                name.starts_with(LEGACY_SAVE_PREFIX) && name.ends_with(".json")
+            });
+            if !is_save || !entry.file_type()?.is_file() {
+                continue;
+            }
+            let target = self.create(Dir::Saves)?.join(&name);
+            if target.exists() {
+                continue;
+            }
+            std::fs::copy(entry.path(), &target)?;
+            copied.push(target);
+        }
+        copied.sort();
+        Ok(copied)
+    }
+}
+
+/// Create the directory that a file is about to be written to, if needed.
+pub fn create_parent(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => std::fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// An empty directory for the test to use.
+    fn test_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("paths_test_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn env_overrides_defaults() {
+        let root = test_dir("overrides");
+        let defaults = Paths::under(&root);
+
+        let env = |var: &str| match var {
+            "METRO_SIMULATOR_SAVE_DIR" => Some(OsString::from(root.join("elsewhere"))),
+            // set but empty, as with `METRO_SIMULATOR_OUTPUT_DIR= cmd`
+            "METRO_SIMULATOR_OUTPUT_DIR" => Some(OsString::new()),
+            _ => None,
+        };
+        let paths = defaults.clone().with_overrides(env);
+
+        assert_eq!(paths.dir(Dir::Saves), root.join("elsewhere"));
+        for dir in [Dir::Config, Dir::Maps, Dir::Output] {
+            assert_eq!(paths.dir(dir), defaults.dir(dir));
+        }
+
+        // nothing is created until it is written to
+        assert!(!root.exists());
+        let save = paths.file(Dir::Saves, "map.json").unwrap();
+        assert_eq!(save, root.join("elsewhere").join("map.json"));
+        assert!(root.join("elsewhere").is_dir());
+        assert!(!defaults.dir(Dir::Saves).exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn migrate_copies_once() {
+        let root = test_dir("migrate");
+        let legacy = root.join("legacy");
+        std::fs::create_dir_all(&legacy).unwrap();
+        std::fs::write(legacy.join("metro_simulator_1.json"), "{}").unwrap();
+        std::fs::write(legacy.join("unrelated.json"), "{}").unwrap();
+        std::fs::create_dir(legacy.join("metro_simulator_dir.json")).unwrap();
+
+        let paths = Paths::under(&root);
+        let copied = paths.migrate_saves_from(&legacy).unwrap();
+        assert_eq!(
+            copied,
+            vec![paths.dir(Dir::Saves).join("metro_simulator_1.json")]
+        );
+        // copied, not moved
+        assert!(legacy.join("metro_simulator_1.json").exists());
+        assert_eq!(std::fs::read_to_string(&copied[0]).unwrap(), "{}");
+
+        assert!(paths.migrate_saves_from(&legacy).unwrap().is_empty());
+        assert!(paths
+            .migrate_saves_from(&root.join("missing"))
+            .unwrap()
+            .is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn platform_dirs_are_creatable() {
+        let paths = Paths::platform_defaults();
+        for dir in Dir::ALL {
+            assert!(paths.dir(dir).is_absolute(), "{:?}", paths.dir(dir));
+        }
+        for dir in [Dir::Saves, Dir::Output] {
+            let path = paths.create(dir).unwrap();
+            assert!(path.is_dir());
+        }
+    }
+}
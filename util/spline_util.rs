@@ -23,6 +23,75 @@ where
         return Ok(());
     }
 
+    visit_samples(owner, length, visitor, step, rect, get_pos, |_, t| {
+        spline.clamped_sample(t).unwrap()
+    })
+}
+
+/**
+ * Sample the spline at every multiple of the step, which are exactly the vertices that
+ * visit_spline may visit. Pass them to visit_sampled_spline to visit them again without
+ * re-sampling the spline.
+ */
+pub fn sample_spline<F, P>(spline: &splines::Spline<F, P>, length: F, step: f64) -> Vec<P>
+where
+    F: num::Float + num::ToPrimitive + splines::interpolate::Interpolator + std::fmt::Debug,
+    P: splines::Interpolate<F>,
+{
+    if spline.is_empty() {
+        return Vec::new();
+    }
+    let step = F::from(step).unwrap();
+    let total = (length / step).ceil().to_u64().unwrap();
+    (0..=total)
+        .map(|i| spline.clamped_sample(F::from(i).unwrap() * step).unwrap())
+        .collect()
+}
+
+/**
+ * Like visit_spline, but for samples from sample_spline with the same length and step. Visits the
+ * same vertices that visit_spline would.
+ */
+pub fn visit_sampled_spline<F, T, V, E, P, G>(
+    owner: &T,
+    samples: &[P],
+    length: F,
+    visitor: &mut V,
+    step: f64,
+    rect: &quadtree::Rect,
+    get_pos: G,
+) -> Result<(), E>
+where
+    F: num::Float + num::ToPrimitive + std::fmt::Debug,
+    V: SplineVisitor<T, P, E>,
+    P: Copy,
+    G: Fn(P) -> cg::Vector2<F>,
+{
+    if samples.is_empty() {
+        return Ok(());
+    }
+
+    visit_samples(owner, length, visitor, step, rect, get_pos, |i, _| {
+        samples[i as usize]
+    })
+}
+
+fn visit_samples<F, T, V, E, P, G, S>(
+    owner: &T,
+    length: F,
+    visitor: &mut V,
+    step: f64,
+    rect: &quadtree::Rect,
+    get_pos: G,
+    sample: S,
+) -> Result<(), E>
+where
+    F: num::Float + num::ToPrimitive + std::fmt::Debug,
+    V: SplineVisitor<T, P, E>,
+    P: Copy,
+    G: Fn(P) -> cg::Vector2<F>,
+    S: Fn(u64, F) -> P,
+{
     let two = F::from(2.0).unwrap();
 
     // how close together we want the samples to be
@@ -49,7 +118,7 @@ where
     // probe for points in the rectangle
     loop {
         let t = F::from(i).unwrap() * step;
-        let data = spline.clamped_sample(t).unwrap();
+        let data = sample(i, t);
         let point = get_pos(data);
 
         // compute Manhatten distance between point and rectangle
@@ -73,6 +142,59 @@ where
     Ok(())
 }
 
+/**
+ * Collects the vertices sampled by visit_spline into polylines, starting a new polyline wherever
+ * the spline leaves the visited rectangle. Useful for exporting geometry rather than drawing it.
+ */
+pub struct PolylineVisitor<G> {
+    get_pos: G,
+    pub polylines: Vec<Vec<(f64, f64)>>,
+}
+
+impl<G> PolylineVisitor<G> {
+    pub fn new(get_pos: G) -> Self {
+        Self {
+            get_pos,
+            polylines: Vec::new(),
+        }
+    }
+}
+
+impl<T, P, G> SplineVisitor<T, P, std::convert::Infallible> for PolylineVisitor<G>
+where
+    G: Fn(&P) -> (f64, f64),
+{
+    fn visit(
+        &mut self,
+        _line: &T,
+        vertex: P,
+        _t: f64,
+        prev: Option<P>,
+    ) -> Result<(), std::convert::Infallible> {
+        if prev.is_none() || self.polylines.is_empty() {
+            self.polylines.push(Vec::new());
+        }
+        self.polylines
+            .last_mut()
+            .unwrap()
+            .push((self.get_pos)(&vertex));
+        Ok(())
+    }
+}
+
+/**
+ * Grow the bounds from compute_bounds by one in each direction. The bounds are rounded down, so
+ * this is needed to make sure that every point of the spline falls inside of them.
+ */
+pub fn pad_bounds(bounds: &quadtree::Rect) -> quadtree::Rect {
+    quadtree::Rect {
+        min_x: bounds.min_x.saturating_sub(1),
+        max_x: bounds.max_x.saturating_add(1),
+        min_y: bounds.min_y.saturating_sub(1),
+        max_y: bounds.max_y.saturating_add(1),
+    }
+}
+
 pub fn compute_bounds<T, F>(nodes: &[T], f: F) -> quadtree::Rect
 where
     F: Fn(&T) -> (f64, f64),
@@ -0,0 +1,181 @@
+//! Pan and zoom math for drawing the map into a rectangular region of the screen, shared between
+//! the viewers.
+//!
+//! A view maps model coordinates to screen coordinates as `screen = model * scale + translation`.
+//! The region the map is drawn in is only known once the viewer has been laid out, and changes when
+//! the window is resized or side panels change size, so views are always computed relative to the
+//! current region rather than assuming a particular window size.
+
+/// A rectangular region of the screen, in screen coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Region {
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn center(&self) -> (f64, f64) {
+        (self.x + self.width / 2.0, self.y + self.height / 2.0)
+    }
+
+    /**
+     * The smallest scale that makes sense for a map of the given width in this region, i.e. the
+     * scale at which the map takes up half of the shorter side of the region. This works for both
+     * landscape and portrait regions.
+     */
+    pub fn min_scale(&self, model_width: f64) -> f64 {
+        f64::min(self.width, self.height) / model_width / 2.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+    pub scale: f64,
+    pub tx: f64,
+    pub ty: f64,
+}
+
+impl View {
+    /// Fit a map of the given width into the region at the minimum scale, centered.
+    pub fn fit(model_width: f64, region: &Region) -> Self {
+        let scale = region.min_scale(model_width);
+        let (cx, cy) = region.center();
+        Self {
+            scale,
+            tx: cx - model_width * scale / 2.0,
+            ty: cy - model_width * scale / 2.0,
+        }
+    }
+
+    /// Change the scale, keeping the model point under the given screen position fixed.
+    pub fn zoom_about(&self, new_scale: f64, (mx, my): (f64, f64)) -> Self {
+        Self {
+            scale: new_scale,
+            tx: (mx * self.scale - mx * new_scale + self.tx * new_scale) / self.scale,
+            ty: (my * self.scale - my * new_scale + self.ty * new_scale) / self.scale,
+        }
+    }
+
+    /// Zoom about the given screen position, clamping the resulting scale to the given range.
+    pub fn clamped_zoom_about(
+        &self,
+        scale: f64,
+        (min_scale, max_scale): (f64, f64),
+        pos: (f64, f64),
+    ) -> Self {
+        self.zoom_about(f64::max(f64::min(scale, max_scale), min_scale), pos)
+    }
+
+    pub fn to_screen(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        (x * self.scale + self.tx, y * self.scale + self.ty)
+    }
+
+    pub fn to_model(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        ((x - self.tx) / self.scale, (y - self.ty) / self.scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    const MODEL_WIDTH: f64 = 4096.0;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    fn check_fit(region: Region) {
+        let view = View::fit(MODEL_WIDTH, &region);
+
+        // the map is centered in the region
+        let (x1, y1) = view.to_screen((0.0, 0.0));
+        let (x2, y2) = view.to_screen((MODEL_WIDTH, MODEL_WIDTH));
+        let (cx, cy) = region.center();
+        assert_close((x1 + x2) / 2.0, cx);
+        assert_close((y1 + y2) / 2.0, cy);
+
+        // and fits in the region along both axes
+        assert!(x1 >= region.x && x2 <= region.x + region.width);
+        assert!(y1 >= region.y && y2 <= region.y + region.height);
+
+        // and takes up half of the shorter side
+        let shorter = f64::min(region.width, region.height);
+        assert_close(f64::max(x2 - x1, y2 - y1), shorter / 2.0);
+    }
+
+    #[test]
+    fn fit_landscape() {
+        check_fit(Region::new(0.0, 0.0, 1920.0, 1080.0));
+        check_fit(Region::new(0.0, 0.0, 3840.0, 2160.0));
+    }
+
+    #[test]
+    fn fit_portrait() {
+        check_fit(Region::new(0.0, 0.0, 1080.0, 2340.0));
+        check_fit(Region::new(0.0, 0.0, 390.0, 844.0));
+    }
+
+    #[test]
+    fn fit_offset_region() {
+        // e.g. the region to the right of a side panel
+        check_fit(Region::new(250.0, 0.0, 1670.0, 1080.0));
+        check_fit(Region::new(300.0, 40.0, 1320.0, 1040.0));
+    }
+
+    #[test]
+    fn min_scale() {
+        assert_close(
+            Region::new(0.0, 0.0, 1920.0, 1080.0).min_scale(MODEL_WIDTH),
+            1080.0 / MODEL_WIDTH / 2.0,
+        );
+        assert_close(
+            Region::new(0.0, 0.0, 390.0, 844.0).min_scale(MODEL_WIDTH),
+            390.0 / MODEL_WIDTH / 2.0,
+        );
+        // a larger surface allows zooming out less far
+        assert!(
+            Region::new(0.0, 0.0, 3840.0, 2160.0).min_scale(MODEL_WIDTH)
+                > Region::new(0.0, 0.0, 1920.0, 1080.0).min_scale(MODEL_WIDTH)
+        );
+    }
+
+    #[test]
+    fn zoom_about_fixed_point() {
+        let view = View::fit(MODEL_WIDTH, &Region::new(0.0, 0.0, 1920.0, 1080.0));
+        let pos = (700.0, 300.0);
+        let model = view.to_model(pos);
+
+        let zoomed = view.zoom_about(view.scale * 3.0, pos);
+        let (x, y) = zoomed.to_screen(model);
+        assert_close(x, pos.0);
+        assert_close(y, pos.1);
+    }
+
+    #[test]
+    fn clamped_zoom() {
+        let region = Region::new(0.0, 0.0, 390.0, 844.0);
+        let view = View::fit(MODEL_WIDTH, &region);
+        let limits = (region.min_scale(MODEL_WIDTH), 100.0);
+
+        let zoomed_out = view.clamped_zoom_about(view.scale / 10.0, limits, region.center());
+        assert_close(zoomed_out.scale, limits.0);
+        // zooming out past the limit about the center leaves the fitted view unchanged
+        assert_close(zoomed_out.tx, view.tx);
+        assert_close(zoomed_out.ty, view.ty);
+
+        let zoomed_in = view.clamped_zoom_about(1000.0, limits, region.center());
+        assert_close(zoomed_in.scale, limits.1);
+    }
+}
@@ -2,116 +2,850 @@ use uom::si::time::{day, hour, minute};
 use uom::si::u64::Time;
 
 lazy_static::lazy_static! {
+    /// how far each of the skip buttons skips, and the key of its label
     static ref TIME_SKIPS: [(u64, &'static str); 4] = [
-        (Time::new::<minute>(1).value, "+1min"),
-        (Time::new::<hour>(1).value, "+1hr"),
-        (Time::new::<hour>(6).value, "+6hrs"),
-        (Time::new::<day>(1).value, "+1day"),
+        (Time::new::<minute>(1).value, "skip-minute"),
+        (Time::new::<hour>(1).value, "skip-hour"),
+        (Time::new::<hour>(6).value, "skip-six-hours"),
+        (Time::new::<day>(1).value, "skip-day"),
     ];
 }
 
+/// the number of upcoming scenario events listed in the time panel
+const MAX_SCENARIO_EVENTS: usize = 5;
+
+/// the most desire lines that can be drawn at once
+const MAX_DESIRE_LINES: usize = 500;
+
+/// how long each frame spends running a benchmark, so that the UI stays responsive
+const BENCHMARK_CHUNK: std::time::Duration = std::time::Duration::from_millis(50);
+
 pub struct App {
     pub(crate) engine: engine::Engine,
     pub(crate) overlay: Overlay,
     pub(crate) display_options: DisplayOptions,
+    /// the colors to draw the map with, which follow the time of day
+    pub(crate) theme_state: crate::theme::ThemeState,
     pub(crate) diagnostics: Diagnostics,
+    pub(crate) profiler: crate::profiling::Profiler,
+    pub(crate) benchmark: BenchmarkControls,
     pub(crate) pan: PanState,
-    pub(crate) route_query: RouteQuery,
-    pub(crate) isochrone_query: IsochroneQuery,
-    pub(crate) congestion_analysis: CongestionAnalysis,
-    pub(crate) agent_detail: AgentDetail,
+    pub(crate) transient: TransientState,
+    pub(crate) timeline: Timeline,
+    #[cfg(feature = "replay")]
+    pub(crate) replay: crate::replay::ReplayControls,
+    /// the most recent error, shown in the status line until it is dismissed
+    pub(crate) status: Option<String>,
+    /// whether the "About this map" window is open
+    pub(crate) show_about: bool,
+    /// where files are written
+    pub(crate) paths: paths::Paths,
+    /// the UI text, which may come from a translated bundle in the config directory
+    pub(crate) strings: crate::strings::Strings,
 }
 
 impl App {
     fn new(mut engine: engine::Engine) -> Self {
         engine.init_trigger_queue();
+        let paths = paths::Paths::resolve();
+        let (strings, strings_err) = match crate::strings::Strings::load(&paths) {
+            Ok(strings) => (strings, None),
+            Err(err) => (Default::default(), Some(err)),
+        };
 
-        Self {
+        let mut app = Self {
             pan: PanState::new(&engine),
             overlay: Overlay::new(),
             engine,
             display_options: DisplayOptions::new(),
+            theme_state: Default::default(),
             diagnostics: Diagnostics::default(),
-            route_query: RouteQuery::new(),
-            isochrone_query: IsochroneQuery::new(),
-            congestion_analysis: CongestionAnalysis::new(),
-            agent_detail: AgentDetail::new(),
+            profiler: crate::profiling::Profiler::new(&paths),
+            benchmark: BenchmarkControls::new(),
+            transient: TransientState::new(),
+            timeline: Timeline::new(),
+            #[cfg(feature = "replay")]
+            replay: crate::replay::ReplayControls::new(&paths),
+            status: None,
+            show_about: false,
+            paths,
+            strings,
+        };
+        if let Some(err) = strings_err {
+            tracing::error!(%err, "failed to load strings");
+            app.status = Some(format!("Failed to load strings: {}", err));
+        }
+        // these are otherwise only collected once the simulation starts running
+        if let Err(err) = app.engine.state.update_collect_tiles() {
+            app.report_error(err);
+        }
+        if let Err(err) = app.engine.refresh_stats_snapshot() {
+            app.report_error(err);
+        }
+        app
+    }
+
+    /**
+     * Load a map at the given quality. The quality also limits how much memory the traffic model
+     * uses, which can only be changed by loading the map again.
+     */
+    pub fn load_file(
+        map: std::path::PathBuf,
+        quality: crate::quality::QualityPreset,
+    ) -> Result<Self, engine::Error> {
+        let overrides = quality.initial_level().load_overrides();
+        let mut app = Self::new(engine::Engine::load_file_with_overrides(&map, &overrides)?);
+        app.display_options.set_quality(quality);
+        Ok(app)
+    }
+
+    pub fn load_str(
+        map: &str,
+        quality: crate::quality::QualityPreset,
+    ) -> Result<Self, engine::Error> {
+        let overrides = quality.initial_level().load_overrides();
+        let mut app = Self::new(engine::Engine::load_with_overrides(map, &overrides)?);
+        app.display_options.set_quality(quality);
+        Ok(app)
+    }
+
+    /**
+     * Swap in a different engine, e.g. after loading another map. Anything that refers to the old
+     * map is cleared; see reset_transient_state.
+     */
+    pub fn replace_engine(&mut self, mut engine: engine::Engine) {
+        engine.init_trigger_queue();
+        self.engine = engine;
+        // a replay or benchmark of the old map makes no sense on the new one
+        #[cfg(feature = "replay")]
+        self.close_replay();
+        self.benchmark.running = None;
+        self.reset_transient_state();
+        if let Err(err) = self.engine.state.update_collect_tiles() {
+            self.report_error(err);
+        }
+        if let Err(err) = self.engine.refresh_stats_snapshot() {
+            self.report_error(err);
         }
     }
 
-    pub fn load_file(map: std::path::PathBuf) -> Self {
-        Self::new(engine::Engine::load_file(&map).unwrap())
+    /**
+     * Clear everything derived from the current engine that would be stale after replacing it:
+     * query results, selections, and cached overlay data. The view is fit to the map again if
+     * the map is a different size.
+     */
+    pub(crate) fn reset_transient_state(&mut self) {
+        self.transient.reset();
+        self.overlay.reset_caches(&self.engine);
+        self.diagnostics.memory_report = None;
+        if self.pan.model_width != self.engine.state.qtree.width() as f32 {
+            self.pan = PanState::new(&self.engine);
+        }
     }
 
-    pub fn load_str(map: &str) -> Self {
-        Self::new(engine::Engine::load(map).unwrap())
+    /// Show an error in the status line instead of crashing the app.
+    pub fn report_error<E: Into<engine::Error>>(&mut self, err: E) {
+        let err = err.into();
+        tracing::error!(%err, "reported error");
+        self.status = Some(err.to_string());
     }
 
     pub fn update(&mut self, elapsed: f64) {
+        // benchmarks are slow on purpose
+        if self.benchmark.running.is_none() {
+            self.display_options.update_quality(elapsed);
+        }
+
+        // the engine stays paused while a replay is open
+        #[cfg(feature = "replay")]
+        if self.advance_replay(elapsed) {
+            return;
+        }
+
+        if let Some(benchmark) = &mut self.benchmark.running {
+            match benchmark.run_chunk(&mut self.engine, BENCHMARK_CHUNK) {
+                Ok(false) => (),
+                Ok(true) => self.finish_benchmark(),
+                Err(err) => {
+                    self.finish_benchmark();
+                    self.report_error(err);
+                }
+            }
+            return;
+        }
+
         // target 60 fps
-        self.engine.update(elapsed, 1.0 / 60.0).unwrap();
+        if let Err(err) = self.engine.update(elapsed, 1.0 / 60.0) {
+            // pause so that the same error isn't reported every frame
+            self.engine.time_state.paused = true;
+            self.report_error(err);
+        }
+    }
+
+    /// Start running the simulation forward as fast as possible; see engine::Benchmark.
+    fn start_benchmark(&mut self) {
+        let duration = Time::new::<hour>(self.benchmark.hours).value;
+        self.benchmark.report = None;
+        self.benchmark.running = Some(engine::Benchmark::start(&mut self.engine, duration));
+    }
+
+    fn finish_benchmark(&mut self) {
+        if let Some(benchmark) = self.benchmark.running.take() {
+            self.benchmark.report = Some(benchmark.finish(&mut self.engine));
+        }
+    }
+
+    // replays are compiled out, so everything is drawn from the engine; see crate::replay
+
+    #[cfg(not(feature = "replay"))]
+    pub(crate) fn replay_open(&self) -> bool {
+        false
+    }
+
+    #[cfg(not(feature = "replay"))]
+    pub(crate) fn replay_time(&self) -> Option<u64> {
+        None
+    }
+
+    #[cfg(not(feature = "replay"))]
+    pub(crate) fn replay_agent_positions(&self) -> Vec<(u64, (f32, f32))> {
+        vec![]
+    }
+
+    #[cfg(not(feature = "replay"))]
+    pub(crate) fn world_state(&self) -> &route::WorldStateImpl {
+        &self.engine.world_state
+    }
+
+    /// The colors to draw the map with for the current frame.
+    pub(crate) fn theme(&self) -> &crate::theme::Theme {
+        &self.theme_state.theme
     }
 
     pub fn draw(&mut self, ctx: &egui::Context) {
+        let _span = tracing::debug_span!("App::draw").entered();
+        // cloning is cheap, and lets panels borrow the app mutably while their titles are shown
+        let strings = self.strings.clone();
+
+        if self.engine.alerts.unacknowledged().next().is_some() {
+            egui::TopBottomPanel::top("alert_banner").show(ctx, |ui| self.draw_alert_banner(ui));
+        }
+
         egui::SidePanel::left("controls")
             .resizable(false)
             .min_width(200.0)
             .show(ctx, |ui| {
                 egui::ScrollArea::vertical().show(ui, |ui| {
-                    ui.collapsing("Time", |ui| self.draw_time_state(ui));
-                    ui.collapsing("Overlay", |ui| self.overlay.draw(ui));
-                    ui.collapsing("Stats", |ui| self.draw_stats(ui));
-                    ui.collapsing("Display options", |ui| self.display_options.draw(ui));
-                    ui.collapsing("Diagnostics", |ui| self.diagnostics.draw(self, ui));
-                    ui.collapsing("Query routes", |ui| self.draw_route_query(ui));
-                    ui.collapsing("Isochrone", |ui| self.draw_isochrone_query(ui));
-                    ui.collapsing("Congestion analysis", |ui| {
+                    ui.collapsing(strings.get("panel-time"), |ui| self.draw_time_state(ui));
+                    ui.collapsing(strings.get("panel-overlay"), |ui| {
+                        let blurred_fields = self.engine.blurred_field_names();
+                        self.overlay.draw(ui, &blurred_fields, &strings)
+                    });
+                    ui.collapsing(strings.get("panel-stats"), |ui| self.draw_stats(ui));
+                    ui.collapsing(strings.get("panel-display-options"), |ui| {
+                        if ui.button(strings.get("reset-view")).clicked() {
+                            self.pan.reset();
+                        }
+                        if ui.button(strings.get("about-map")).clicked() {
+                            self.show_about = true;
+                        }
+                        self.display_options.draw(ui, &strings)
+                    });
+                    ui.collapsing(strings.get("panel-diagnostics"), |ui| {
+                        self.diagnostics.draw(self, ui);
+                        ui.separator();
+                        self.draw_coordinate_readout(ui);
+                        ui.separator();
+                        self.draw_memory_report(ui);
+                        ui.separator();
+                        self.draw_trigger_cadences(ui);
+                        ui.separator();
+                        self.draw_bounds_repair(ui);
+                        ui.separator();
+                        self.profiler.draw(ui);
+                        ui.separator();
+                        self.draw_benchmark(ui);
+                    });
+                    ui.collapsing(strings.get("panel-alerts"), |ui| self.draw_alerts(ui));
+                    ui.collapsing(strings.get("panel-route-query"), |ui| {
+                        self.draw_route_query(ui)
+                    });
+                    ui.collapsing(strings.get("panel-isochrone"), |ui| {
+                        self.draw_isochrone_query(ui)
+                    });
+                    ui.collapsing(strings.get("panel-congestion"), |ui| {
                         self.draw_congestion_analysis(ui)
                     });
-                    ui.collapsing("Agent detail", |ui| self.draw_agent_detail(ui));
+                    ui.collapsing(strings.get("panel-catchments"), |ui| {
+                        self.draw_station_catchments(ui)
+                    });
+                    ui.collapsing(strings.get("panel-commute-flows"), |ui| {
+                        self.draw_commute_flows(ui)
+                    });
+                    ui.collapsing(strings.get("panel-calibration"), |ui| {
+                        self.draw_calibration(ui)
+                    });
+                    ui.collapsing(strings.get("panel-timeline"), |ui| self.draw_timeline(ui));
+                    ui.collapsing(strings.get("panel-agent-detail"), |ui| {
+                        self.draw_agent_detail(ui)
+                    });
+                    #[cfg(feature = "replay")]
+                    ui.collapsing(strings.get("panel-replay"), |ui| self.draw_replay(ui));
+                    ui.collapsing(strings.get("panel-planned-changes"), |ui| {
+                        self.draw_planned_changes(ui)
+                    });
                 });
             });
 
+        if self.status.is_some() {
+            egui::TopBottomPanel::bottom("status_line").show(ctx, |ui| self.draw_status_line(ui));
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
-            self.draw_content(ui).unwrap();
+            if self.benchmark.running.is_some() {
+                // the map would only slow the benchmark down
+                ui.centered_and_justified(|ui| ui.label(strings.get("benchmark-running")));
+            } else if let Err(err) = self.draw_content(ui) {
+                self.report_error(err);
+            }
         });
+
+        if let Some(selection) = self.transient.segment_detail {
+            let mut open = true;
+            egui::Window::new(strings.get("window-segment-detail"))
+                .open(&mut open)
+                .resizable(false)
+                .show(ctx, |ui| self.draw_segment_detail(ui, selection));
+            if !open {
+                self.transient.segment_detail = None;
+            }
+        }
+
+        if self.show_about {
+            let mut open = true;
+            egui::Window::new(strings.get("about-map"))
+                .open(&mut open)
+                .resizable(false)
+                .show(ctx, |ui| self.draw_about(ui));
+            self.show_about = open;
+        }
     }
 
     fn draw_time_state(&mut self, ui: &mut egui::Ui) {
+        let strings = &self.strings;
         let time = &mut self.engine.time_state;
-        ui.label(format!("Current time: {}", time.current_time));
+        ui.label(strings.format("current-time", &[("time", &time.current_time)]));
         ui.label(time.pretty_current_date_time());
-        ui.label("Playback rate:");
+        ui.label(strings.format("time-of-day", &[("band", &self.theme_state.band.label())]));
+        ui.label(strings.get("playback-rate"));
         ui.add(egui::Slider::new(&mut time.playback_rate, 60..=86400));
         if ui
-            .button(if time.paused { "Resume" } else { "Pause" })
+            .button(strings.get(if time.paused { "resume" } else { "pause" }))
             .clicked()
         {
             time.paused = !time.paused;
         }
         ui.horizontal(|ui| {
-            for (skip, label) in *TIME_SKIPS {
-                if ui.button(label).clicked() {
+            for (skip, key) in *TIME_SKIPS {
+                if ui.button(strings.get(key)).clicked() {
                     time.skip_by(skip);
                 }
             }
         });
+
+        let upcoming = self.engine.upcoming_scenario_events();
+        if !upcoming.is_empty() {
+            ui.separator();
+            ui.label(
+                self.strings
+                    .format("upcoming-scenario-events", &[("count", &upcoming.len())]),
+            );
+            for event in upcoming.iter().take(MAX_SCENARIO_EVENTS) {
+                ui.label(format!(
+                    "{}: {}",
+                    self.engine.time_state.pretty_date_time(event.time()),
+                    event.action.label()
+                ));
+            }
+        }
+    }
+
+    fn draw_alert_banner(&mut self, ui: &mut egui::Ui) {
+        let alerts = &self.engine.alerts;
+        let count = alerts.unacknowledged().count();
+        let latest = alerts
+            .unacknowledged()
+            .max_by_key(|alert| (alert.severity, alert.id))
+            .expect("banner is only shown for unacknowledged alerts");
+        let (severity, message) = (latest.severity, latest.message.clone());
+
+        ui.horizontal(|ui| {
+            ui.colored_label(alert_color(severity), format!("{}: {}", severity, message));
+            if count > 1 {
+                ui.label(
+                    self.strings
+                        .format("more-alerts", &[("count", &(count - 1))]),
+                );
+            }
+            if ui.button(self.strings.get("dismiss")).clicked() {
+                self.engine.alerts.acknowledge_all();
+            }
+        });
+    }
+
+    fn draw_status_line(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(status) = &self.status {
+                ui.colored_label(egui::Color32::RED, status);
+            }
+            if ui.button(self.strings.get("dismiss")).clicked() {
+                self.status = None;
+            }
+        });
+    }
+
+    fn draw_alerts(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(
+            &mut self.engine.alerts.auto_pause,
+            self.strings.get("pause-on-critical-alerts"),
+        );
+        ui.horizontal(|ui| {
+            if ui.button(self.strings.get("dismiss-all")).clicked() {
+                self.engine.alerts.acknowledge_all();
+            }
+            if ui.button(self.strings.get("clear")).clicked() {
+                self.engine.alerts.clear();
+            }
+        });
+
+        ui.separator();
+
+        let totals = self.engine.alerts.totals();
+        ui.label(
+            self.strings
+                .format("parking-errors", &[("count", &totals.parking_errors)]),
+        );
+        ui.label(self.strings.format(
+            "edge-counting-errors",
+            &[("count", &totals.edge_counting_errors)],
+        ));
+
+        ui.separator();
+
+        if self.engine.alerts.iter().next().is_none() {
+            ui.label(self.strings.get("no-alerts"));
+        }
+        // newest first
+        for alert in self.engine.alerts.iter().rev() {
+            ui.label(self.engine.time_state.pretty_date_time(alert.time));
+            ui.colored_label(
+                alert_color(alert.severity),
+                format!("{}: {}", alert.severity, alert.message),
+            );
+        }
+    }
+
+    fn draw_benchmark(&mut self, ui: &mut egui::Ui) {
+        match &self.benchmark.running {
+            Some(benchmark) => {
+                ui.add(egui::ProgressBar::new(benchmark.progress(&self.engine)).show_percentage());
+                if ui.button(self.strings.get("stop-benchmark")).clicked() {
+                    self.finish_benchmark();
+                }
+            }
+            None => {
+                ui.label(self.strings.get("benchmark-hours"));
+                ui.add(egui::Slider::new(&mut self.benchmark.hours, 1..=7 * 24));
+                if ui
+                    .add_enabled(
+                        !self.replay_open(),
+                        egui::Button::new(self.strings.get("benchmark")),
+                    )
+                    .clicked()
+                {
+                    self.start_benchmark();
+                }
+            }
+        }
+
+        if let Some(report) = &self.benchmark.report {
+            let text = report.to_text();
+            ui.label(&text);
+            if ui.button(self.strings.get("copy-report")).clicked() {
+                ui.output().copied_text = text;
+            }
+        }
+    }
+
+    /// The map's metadata, which can only be edited in the editor.
+    fn draw_about(&mut self, ui: &mut egui::Ui) {
+        let strings = &self.strings;
+        let metadata = &self.engine.state.metadata;
+        ui.heading(if metadata.name.is_empty() {
+            strings.get("untitled-map")
+        } else {
+            metadata.name.as_str()
+        });
+        if !metadata.author.is_empty() {
+            ui.label(strings.format("map-author", &[("author", &metadata.author)]));
+        }
+        if !metadata.description.is_empty() {
+            ui.label(metadata.description.as_str());
+        }
+        ui.separator();
+        ui.label(strings.format(
+            "map-created",
+            &[(
+                "timestamp",
+                &crate::format::timestamp(metadata.created, strings),
+            )],
+        ));
+        ui.label(strings.format(
+            "map-modified",
+            &[(
+                "timestamp",
+                &crate::format::timestamp(metadata.modified, strings),
+            )],
+        ));
+        match &metadata.georeference {
+            Some(georeference) => {
+                ui.label(strings.format(
+                    "map-origin",
+                    &[(
+                        "origin",
+                        &georeference::format_lat_lon((
+                            georeference.origin_lat,
+                            georeference.origin_lon,
+                        )),
+                    )],
+                ));
+                ui.label(strings.format(
+                    "map-tile-size",
+                    &[(
+                        "distance",
+                        &crate::format::distance(
+                            georeference.meters_per_tile,
+                            self.display_options.units,
+                            strings,
+                        ),
+                    )],
+                ));
+            }
+            None => {
+                ui.label(strings.get("not-georeferenced"));
+            }
+        }
+    }
+
+    /// The latitude and longitude under the cursor, for maps that know where they are.
+    fn draw_coordinate_readout(&mut self, ui: &mut egui::Ui) {
+        let georeference = match self.engine.state.metadata.georeference {
+            Some(georeference) => georeference,
+            None => {
+                ui.label(self.strings.get("not-georeferenced"));
+                return;
+            }
+        };
+        ui.checkbox(
+            &mut self.diagnostics.show_lat_lon,
+            self.strings.get("show-lat-lon"),
+        );
+        if !self.diagnostics.show_lat_lon {
+            return;
+        }
+        let projection = georeference.projection(self.engine.state.qtree.width());
+        match self.diagnostics.cursor {
+            Some((x, y)) => ui.label(georeference::format_lat_lon(
+                projection.to_lat_lon((x as f64, y as f64)),
+            )),
+            None => ui.label(self.strings.get("cursor-not-over-map")),
+        };
+    }
+
+    /// Estimated memory use of each part of the engine, largest first.
+    fn draw_memory_report(&mut self, ui: &mut egui::Ui) {
+        // walking the whole quadtree and all the agents is too slow to do every frame
+        if ui.button(self.strings.get("estimate-memory")).clicked() {
+            self.diagnostics.memory_report = Some(self.engine.memory_report());
+        }
+        let report = match &self.diagnostics.memory_report {
+            Some(report) => report,
+            None => return,
+        };
+
+        egui::Grid::new("memory_report")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label(self.strings.get("memory-subsystem"));
+                ui.label(self.strings.get("memory-items"));
+                ui.label(self.strings.get("memory-size"));
+                ui.end_row();
+
+                for entry in &report.entries {
+                    ui.label(entry.name);
+                    ui.label(
+                        entry
+                            .items
+                            .map(|items| items.to_string())
+                            .unwrap_or_default(),
+                    );
+                    ui.label(memory_size::format_bytes(entry.bytes));
+                    ui.end_row();
+                }
+
+                ui.label(self.strings.get("memory-total"));
+                ui.label("");
+                ui.label(memory_size::format_bytes(report.total()));
+                ui.end_row();
+            });
+
+        if ui.button(self.strings.get("copy-report")).clicked() {
+            ui.output().copied_text = report.to_string();
+        }
+    }
+
+    /// How often each of the periodic triggers runs, including changes made since the map loaded.
+    fn draw_trigger_cadences(&self, ui: &mut egui::Ui) {
+        use enum_iterator::IntoEnumIterator;
+
+        ui.label(self.strings.get("trigger-cadences"));
+        egui::Grid::new("trigger_cadences")
+            .striped(true)
+            .show(ui, |ui| {
+                for kind in engine::TriggerKind::into_enum_iter() {
+                    if let Some(cadence) = self.engine.trigger_cadence(kind) {
+                        ui.label(format!("{:?}", kind));
+                        ui.label(crate::format::offset(cadence));
+                        ui.end_row();
+                    }
+                }
+            });
+    }
+
+    fn draw_bounds_repair(&mut self, ui: &mut egui::Ui) {
+        if ui.button(self.strings.get("repair-bounds")).clicked() {
+            self.diagnostics.bounds_repair = Some(self.engine.repair_out_of_bounds());
+        }
+        match &self.diagnostics.bounds_repair {
+            Some(report) if report.is_empty() => {
+                ui.label(self.strings.get("bounds-ok"));
+            }
+            Some(report) => {
+                ui.label(
+                    self.strings
+                        .format("bounds-repaired", &[("count", &report.repairs.len())]),
+                );
+                for repair in &report.repairs {
+                    ui.label(repair.to_string());
+                }
+            }
+            None => (),
+        }
+    }
+
+    fn draw_planned_changes(&mut self, ui: &mut egui::Ui) {
+        match self.engine.open_change_set() {
+            Some(handle) => {
+                let descriptions: Vec<_> = match self.engine.preview(handle) {
+                    Ok(preview) => preview
+                        .changes
+                        .iter()
+                        .map(|change| describe_staged_change(change, &self.strings))
+                        .collect(),
+                    Err(err) => {
+                        ui.colored_label(egui::Color32::RED, err.to_string());
+                        return;
+                    }
+                };
+
+                if descriptions.is_empty() {
+                    ui.label(self.strings.get("nothing-planned"));
+                }
+                let mut unstage = None;
+                for (i, description) in descriptions.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(description);
+                        if ui.small_button(self.strings.get("unstage")).clicked() {
+                            unstage = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = unstage {
+                    self.transient.planned_changes.error =
+                        self.engine.unstage(handle, i).err().map(|e| e.to_string());
+                }
+
+                ui.separator();
+
+                // the line goes away if the change set that added it is discarded
+                let metros = &self.engine.state.metros;
+                if let Some(path_edit) = &self.transient.planned_changes.path_edit {
+                    if metros.try_metro_line(path_edit.metro_line).is_none() {
+                        self.transient.planned_changes.path_edit = None;
+                    }
+                }
+
+                if let Some(path_edit) = &mut self.transient.planned_changes.path_edit {
+                    let metro_line = self.engine.state.metros.metro_line(path_edit.metro_line);
+                    ui.label(self.strings.format(
+                        "editing-path",
+                        &[
+                            ("line", &metro_line.data.name),
+                            ("count", &path_edit.junctions.len()),
+                        ],
+                    ));
+                    let mut apply = false;
+                    let mut cancel = false;
+                    ui.horizontal(|ui| {
+                        if ui.button(self.strings.get("undo-last")).clicked() {
+                            path_edit.junctions.pop();
+                        }
+                        apply = ui.button(self.strings.get("apply")).clicked();
+                        cancel = ui.button(self.strings.get("cancel")).clicked();
+                    });
+
+                    // offer to stop at unserved stations that the path passes by
+                    let tile_size = self.engine.state.config.min_tile_size as f64;
+                    let mut adopt = None;
+                    for adoptable in path_edit.adoptable_stations(&self.engine) {
+                        ui.horizontal(|ui| {
+                            ui.label(self.strings.format(
+                                "unserved-station",
+                                &[
+                                    ("station", &adoptable.orphan.station.name),
+                                    (
+                                        "distance",
+                                        &crate::format::distance(
+                                            adoptable.distance * tile_size,
+                                            self.display_options.units,
+                                            &self.strings,
+                                        ),
+                                    ),
+                                ],
+                            ));
+                            if ui.small_button(self.strings.get("add-as-stop")).clicked() {
+                                adopt = Some(adoptable.clone());
+                            }
+                        });
+                    }
+                    if let Some(adoptable) = adopt {
+                        self.transient.planned_changes.error =
+                            path_edit.adopt(&mut self.engine, &adoptable).err();
+                    }
+                    if apply {
+                        let metro_line = path_edit.metro_line;
+                        let result =
+                            path_edit
+                                .segments(&self.engine.state.railways)
+                                .and_then(|segments| {
+                                    self.engine
+                                        .staged_reroute_metro_line(handle, metro_line, segments)
+                                        .map_err(|e| e.to_string())
+                                });
+                        self.transient.planned_changes.error = result.err();
+                        if self.transient.planned_changes.error.is_none() {
+                            self.transient.planned_changes.path_edit = None;
+                        }
+                    } else if cancel {
+                        self.transient.planned_changes.path_edit = None;
+                    }
+                    ui.separator();
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button(self.strings.get("commit")).clicked() {
+                        self.transient.planned_changes.error = self
+                            .engine
+                            .commit_change_set(handle)
+                            .err()
+                            .map(|e| e.to_string());
+                    }
+                    if ui.button(self.strings.get("discard")).clicked() {
+                        self.transient.planned_changes.error = self
+                            .engine
+                            .discard_change_set(handle)
+                            .err()
+                            .map(|e| e.to_string());
+                    }
+                });
+            }
+            None => {
+                self.transient.planned_changes.path_edit = None;
+                if ui.button(self.strings.get("start-planning")).clicked() {
+                    self.transient.planned_changes.error =
+                        self.engine.begin_change_set().err().map(|e| e.to_string());
+                }
+            }
+        }
+
+        if let Some(error) = &self.transient.planned_changes.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
     }
 
     fn draw_stats(&mut self, ui: &mut egui::Ui) {
-        if let Ok(root) = self.engine.state.qtree.get_root_branch() {
-            ui.label(format!(
-                "Population: {}",
-                root.fields.population.people.total
-            ));
-            ui.label(format!(
-                "Employment: {}",
-                root.fields.employment.workers.total
-            ));
-            ui.label(format!(
-                "Employment rate: {:.1}%",
-                root.fields.population.employment_rate() * 100.0,
-            ));
+        // everything comes from the same snapshot so that the numbers agree with each other
+        if ui.button(self.strings.get("refresh")).clicked() {
+            if let Err(err) = self.engine.refresh_stats_snapshot() {
+                self.report_error(err);
+            }
+        }
+        let stats = self.engine.stats_snapshot();
+        let strings = &self.strings;
+        ui.label(strings.format(
+            "stats-as-of",
+            &[("time", &self.engine.time_state.pretty_date_time(stats.time))],
+        ));
+        ui.separator();
+
+        ui.label(strings.format("stats-population", &[("count", &stats.population)]));
+        ui.label(strings.format("stats-employment", &[("count", &stats.employment)]));
+        ui.label(strings.format(
+            "stats-employment-rate",
+            &[(
+                "percent",
+                &format!("{:.1}", stats.employment_rate() * 100.0),
+            )],
+        ));
+        ui.label(strings.format("stats-agents", &[("count", &stats.agents)]));
+        ui.label(strings.format(
+            "stats-employed-agents",
+            &[("count", &stats.employed_agents)],
+        ));
+        ui.label(strings.format("stats-traveling", &[("count", &stats.active_routes)]));
+        ui.label(strings.format("stats-parked-cars", &[("count", &stats.parked_cars)]));
+
+        // the fields lag behind the agents until they are next updated
+        if stats.population_drift() != 0 || stats.employment_drift() != 0 {
+            ui.separator();
+            ui.label(strings.format(
+                "stats-drift",
+                &[
+                    ("people", &format!("{:+}", stats.population_drift())),
+                    ("employed", &format!("{:+}", stats.employment_drift())),
+                ],
+            ))
+            .on_hover_text(strings.get("stats-drift-hover"));
+        }
+
+        // unlike the rest, these are always current
+        let job_quits = &self.engine.job_quits;
+        let totals = job_quits.totals();
+        if !totals.is_empty() || job_quits.unexplained() > 0 {
+            ui.separator();
+            ui.label(strings.get("job-quits"));
+            for (reason, count) in totals {
+                ui.label(format!("  {}: {}", reason, count));
+            }
+            if job_quits.unexplained() > 0 {
+                ui.label(format!(
+                    "  {}",
+                    strings.format(
+                        "job-quits-unexplained",
+                        &[("count", &job_quits.unexplained())]
+                    )
+                ));
+            }
         }
     }
 
@@ -125,57 +859,85 @@ impl App {
     fn draw_route_query(&mut self, ui: &mut egui::Ui) {
         let mut changed = false;
 
-        if ui.button("Clear").clicked() {
-            self.route_query.start_address = None;
-            self.route_query.stop_address = None;
+        if ui.button(self.strings.get("clear")).clicked() {
+            self.transient.route_query.start_address = None;
+            self.transient.route_query.stop_address = None;
             changed = true;
         }
         ui.separator();
 
-        match self.route_query.start_address {
+        match self.transient.route_query.start_address {
             Some(start) => {
                 let (x, y) = start.to_xy();
-                ui.label(format!("[a] Start: {}, {}", x, y));
+                ui.label(self.strings.format("route-start", &[("x", &x), ("y", &y)]));
             }
             None => {
-                ui.label("[a] No start selected");
+                ui.label(self.strings.get("route-no-start"));
             }
         }
-        match self.route_query.stop_address {
+        match self.transient.route_query.stop_address {
             Some(stop) => {
                 let (x, y) = stop.to_xy();
-                ui.label(format!("[z] Stop: {}, {}", x, y));
+                ui.label(self.strings.format("route-stop", &[("x", &x), ("y", &y)]));
             }
             None => {
-                ui.label("[z] No stop selected");
+                ui.label(self.strings.get("route-no-stop"));
             }
         }
+        ui.horizontal(|ui| {
+            ui.label(self.strings.get("route-modes"));
+            for mode in route::MODES {
+                let mut allowed = self.transient.route_query.allowed_modes.contains(*mode);
+                // walking connects everything else, so it can't be turned off
+                if ui
+                    .add_enabled(
+                        *mode != route::Mode::Walking,
+                        egui::Checkbox::new(&mut allowed, mode.to_string()),
+                    )
+                    .clicked()
+                {
+                    self.transient.route_query.allowed_modes.set(*mode, allowed);
+                    changed = true;
+                }
+            }
+        });
         if ui
-            .checkbox(&mut self.route_query.has_car, "Has car")
+            .checkbox(
+                &mut self.transient.route_query.debug,
+                self.strings.get("route-debug"),
+            )
+            .on_hover_text(self.strings.get("route-debug-hover"))
             .clicked()
         {
             changed = true;
         }
 
-        if ui.button("Pick random").clicked() {
-            use rand::seq::SliceRandom;
-            let mut rng = rand::thread_rng();
-
-            // make sure these lists are up-to-date
-            self.engine.state.update_collect_tiles().unwrap();
-
+        let collect_tiles = &self.engine.state.collect_tiles;
+        let can_pick_random =
+            !collect_tiles.housing.is_empty() && !collect_tiles.workplaces.is_empty();
+        if ui
+            .add_enabled(
+                can_pick_random,
+                egui::Button::new(self.strings.get("pick-random")),
+            )
+            .on_disabled_hover_text(self.strings.get("pick-random-disabled"))
+            .clicked()
+        {
             // for now, go from home to work
-            let start = self.engine.state.collect_tiles.housing.choose(&mut rng);
-            let stop = self.engine.state.collect_tiles.workplaces.choose(&mut rng);
-            self.route_query.start_address = start.copied();
-            self.route_query.stop_address = stop.copied();
-            changed = true;
+            match self.engine.random_commute(&mut rand::thread_rng()) {
+                Ok((start, stop)) => {
+                    self.transient.route_query.start_address = Some(start);
+                    self.transient.route_query.stop_address = Some(stop);
+                    changed = true;
+                }
+                Err(err) => self.report_error(err),
+            }
         }
 
         if ui.input().keys_down.contains(&egui::Key::A) {
             if let Some((x, y)) = self.get_hovered_pos(ui) {
                 if let Ok(start) = self.engine.state.qtree.get_address(x, y) {
-                    self.route_query.start_address = Some(start);
+                    self.transient.route_query.start_address = Some(start);
                     changed = true;
                 }
             }
@@ -183,21 +945,82 @@ impl App {
         if ui.input().keys_down.contains(&egui::Key::Z) {
             if let Some((x, y)) = self.get_hovered_pos(ui) {
                 if let Ok(stop) = self.engine.state.qtree.get_address(x, y) {
-                    self.route_query.stop_address = Some(stop);
+                    self.transient.route_query.stop_address = Some(stop);
                     changed = true;
                 }
             }
         }
 
-        if !self.route_query.current_routes.is_empty() {
+        if !self.transient.route_query.current_routes.is_empty() {
+            let strings = &self.strings;
+            let units = self.display_options.units;
             ui.separator();
-            ui.label("Current routes:");
-
-            for (i, route) in self.route_query.current_routes.iter().enumerate() {
-                if let Some(duration) = format_duration(route.cost) {
-                    ui.label(format!("Route #{} duration: {}", i + 1, duration));
+            ui.label(strings.get("current-routes"));
+
+            let mut stale = false;
+            let mut export = None;
+            let route_query = &self.transient.route_query;
+            for (i, route) in route_query.current_routes.iter().enumerate() {
+                let number = i + 1;
+                ui.label(strings.format(
+                    "route-duration",
+                    &[
+                        ("route", &number),
+                        (
+                            "duration",
+                            &crate::format::duration(route.cost as f64, strings),
+                        ),
+                    ],
+                ));
+                if let Some(reliability) = route_query.current_reliability.get(i) {
+                    ui.label(strings.format(
+                        "route-reliability",
+                        &[
+                            ("route", &number),
+                            (
+                                "reliability",
+                                &crate::format::reliability(reliability, strings),
+                            ),
+                        ],
+                    ))
+                    .on_hover_text(strings.get("route-reliability-hover"));
                 }
+                ui.label(strings.format(
+                    "route-length",
+                    &[
+                        ("route", &number),
+                        (
+                            "distance",
+                            &crate::format::distance(
+                                route.length_meters(&self.engine.state),
+                                units,
+                                strings,
+                            ),
+                        ),
+                    ],
+                ));
+                if let Some(reason) = self.stale_reason(route.graph_version) {
+                    ui.label(strings.get(reason));
+                    stale = true;
+                }
+                if let Some(profile) = route_query.current_profiles.get(i) {
+                    ui.label(strings.get("route-speed"));
+                    ui.add(speed_chart(profile, units));
+                    if ui.button(strings.get("export-csv")).clicked() {
+                        export = Some(i);
+                    }
+                }
+            }
+            if stale && ui.button(strings.get("recompute")).clicked() {
+                changed = true;
             }
+            if let Some(i) = export {
+                self.export_route_profile(i);
+            }
+        }
+
+        if let Some(debug) = &self.transient.route_query.current_debug {
+            draw_route_debug(ui, debug, &self.strings);
         }
 
         if changed {
@@ -205,85 +1028,320 @@ impl App {
         }
     }
 
+    /// Write the time profile of one of the current routes to a CSV file in the output directory.
+    fn export_route_profile(&mut self, index: usize) {
+        let profile = &self.transient.route_query.current_profiles[index];
+        let name = format!("route_profile_{}.csv", index + 1);
+        let result = self
+            .paths
+            .file(paths::Dir::Output, &name)
+            .and_then(|path| std::fs::write(&path, profile_csv(profile)).map(|()| path));
+        self.status = Some(match result {
+            Ok(path) => self
+                .strings
+                .format("route-profile-saved", &[("path", &path.display())]),
+            Err(err) => self
+                .strings
+                .format("route-profile-failed", &[("error", &err)]),
+        });
+    }
+
+    /**
+     * The key of the reason that a result computed against the given graph version is out of date,
+     * if it is; see Strings.
+     */
+    fn stale_reason(&self, version: route::GraphVersion) -> Option<&'static str> {
+        let current = self.engine.graph_version();
+        if version.network != current.network {
+            Some("stale-network")
+        } else if version.traffic != current.traffic {
+            Some("stale-traffic")
+        } else {
+            None
+        }
+    }
+
     fn update_route_query(&mut self) {
-        self.route_query.current_routes.clear();
+        self.transient.route_query.current_routes.clear();
+        self.transient.route_query.current_reliability.clear();
+        self.transient.route_query.current_profiles.clear();
+        self.transient.route_query.current_debug = None;
 
         if let (Some(start), Some(stop)) = (
-            self.route_query.start_address,
-            self.route_query.stop_address,
+            self.transient.route_query.start_address,
+            self.transient.route_query.stop_address,
         ) {
-            let car_config = if self.route_query.has_car {
-                Some(route::CarConfig::StartWithCar)
-            } else {
-                None
-            };
+            let allowed_modes = self.transient.route_query.allowed_modes;
             let query_input = route::QueryInput {
                 start,
                 end: stop,
-                car_config,
+                car_config: allowed_modes
+                    .contains(route::Mode::Driving)
+                    .then_some(route::CarConfig::StartWithCar),
+                profile: route::MobilityProfile::STANDARD,
+                allowed_modes,
             };
-            match self.engine.query_route(query_input) {
-                Ok(Some(route)) => self.route_query.current_routes = vec![route],
-                Ok(None) => eprintln!("No route found"),
-                Err(err) => eprintln!("Error querying route: {}", err),
+            if self.transient.route_query.debug {
+                match self.engine.query_route_debug(query_input) {
+                    Ok(Some(debug)) => {
+                        self.transient.route_query.current_routes = vec![debug.route.clone()];
+                        self.transient.route_query.current_debug = Some(debug);
+                    }
+                    Ok(None) => eprintln!("No route found"),
+                    Err(err) => eprintln!("Error querying route: {}", err),
+                }
+            } else {
+                match self.engine.query_route(query_input) {
+                    Ok(Some(route)) => self.transient.route_query.current_routes = vec![route],
+                    Ok(None) => eprintln!("No route found"),
+                    Err(err) => eprintln!("Error querying route: {}", err),
+                }
             }
+
+            let route_query = &mut self.transient.route_query;
+            route_query.current_reliability = route_query
+                .current_routes
+                .iter()
+                .map(|route| {
+                    route.reliability(
+                        &self.engine.world_state_history,
+                        &self.engine.world_state,
+                        &self.engine.state,
+                    )
+                })
+                .collect();
+            route_query.current_profiles = route_query
+                .current_routes
+                .iter()
+                .map(|route| route.time_profile(&self.engine.state, &self.engine.world_state))
+                .collect();
         }
     }
 
+    pub fn query_isochrone(&mut self, focus: quadtree::Address) {
+        let mode = self.transient.isochrone_query.mode;
+        // TODO: perform asynchronously, and use intermediary "calculating" state
+        self.transient.isochrone_query.state =
+            match self
+                .engine
+                .query_isochrone_map(focus, mode, route::MobilityProfile::STANDARD)
+            {
+                Ok(route::IsochroneResult::Calculated(isochrone_map)) => {
+                    let reduced = if self.transient.isochrone_query.accessibility {
+                        match self.engine.query_isochrone_map(
+                            focus,
+                            mode,
+                            self.transient.isochrone_query.reduced_mobility,
+                        ) {
+                            Ok(route::IsochroneResult::Calculated(reduced)) => {
+                                Some(Box::new(reduced))
+                            }
+                            // the focus is the same as for the standard isochrone, so it is routable
+                            Ok(route::IsochroneResult::FocusNotRoutable { .. }) => None,
+                            Err(err) => {
+                                eprintln!(
+                                    "Error calculating reduced mobility isochrone map: {}",
+                                    err
+                                );
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    IsochroneQueryState::Calculated {
+                        isochrone_map,
+                        reduced,
+                    }
+                }
+                Ok(route::IsochroneResult::FocusNotRoutable {
+                    focus,
+                    nearest,
+                    distance,
+                    ..
+                }) => IsochroneQueryState::NotRoutable {
+                    focus,
+                    nearest,
+                    distance,
+                },
+                Err(err) => {
+                    eprintln!("Error calculating isochrone map: {}", err);
+                    IsochroneQueryState::Empty
+                }
+            };
+    }
+
     pub fn draw_isochrone_query(&mut self, ui: &mut egui::Ui) {
-        match &self.isochrone_query.state {
+        match &self.transient.isochrone_query.state {
             IsochroneQueryState::Empty => {
-                if ui.button("Pick tile").clicked() {
-                    self.isochrone_query.state = IsochroneQueryState::Querying;
+                if ui.button(self.strings.get("pick-tile")).clicked() {
+                    self.transient.isochrone_query.state = IsochroneQueryState::Querying;
                 }
                 ui.separator();
 
                 for mode in route::MODES {
-                    ui.radio_value(&mut self.isochrone_query.mode, *mode, format!("{}", mode));
+                    ui.radio_value(
+                        &mut self.transient.isochrone_query.mode,
+                        *mode,
+                        format!("{}", mode),
+                    );
+                }
+                ui.separator();
+
+                ui.checkbox(
+                    &mut self.transient.isochrone_query.accessibility,
+                    self.strings.get("compare-reduced-mobility"),
+                );
+                if self.transient.isochrone_query.accessibility {
+                    let profile = &mut self.transient.isochrone_query.reduced_mobility;
+                    ui.label(self.strings.get("walking-speed-multiplier"));
+                    ui.add(egui::Slider::new(&mut profile.walking_speed, 0.1..=1.0));
+                    ui.label(self.strings.get("transfer-penalty"));
+                    ui.add(
+                        egui::Slider::new(&mut profile.transfer_penalty, 0.0..=600.0).step_by(30.0),
+                    );
                 }
             }
             IsochroneQueryState::Querying => {
-                if ui.button("Clear").clicked() {
-                    self.isochrone_query.state = IsochroneQueryState::Empty;
+                if ui.button(self.strings.get("clear")).clicked() {
+                    self.transient.isochrone_query.state = IsochroneQueryState::Empty;
                 }
                 ui.separator();
 
-                ui.label("<waiting for tile selection>");
+                ui.label(self.strings.get("waiting-for-tile"));
             }
             IsochroneQueryState::Calculating => {
-                if ui.button("Clear").clicked() {
-                    self.isochrone_query.state = IsochroneQueryState::Empty;
+                if ui.button(self.strings.get("clear")).clicked() {
+                    self.transient.isochrone_query.state = IsochroneQueryState::Empty;
+                }
+                ui.separator();
+
+                ui.label(self.strings.get("calculating"));
+            }
+            IsochroneQueryState::NotRoutable {
+                focus,
+                nearest,
+                distance,
+            } => {
+                let (x, y) = focus.to_xy();
+                let (nearest, distance) = (*nearest, *distance);
+
+                if ui.button(self.strings.get("clear")).clicked() {
+                    self.transient.isochrone_query.state = IsochroneQueryState::Empty;
                 }
                 ui.separator();
 
-                ui.label("Calculating...");
+                ui.label(
+                    self.strings
+                        .format("cannot-route-from", &[("x", &x), ("y", &y)]),
+                );
+                if ui
+                    .button(self.strings.format(
+                        "use-nearest-routable",
+                        &[(
+                            "distance",
+                            &crate::format::distance(
+                                distance,
+                                self.display_options.units,
+                                &self.strings,
+                            ),
+                        )],
+                    ))
+                    .clicked()
+                {
+                    self.query_isochrone(nearest);
+                }
             }
-            IsochroneQueryState::Calculated { isochrone_map } => {
-                let (x, y) = isochrone_map.isochrone.focus.to_xy();
+            IsochroneQueryState::Calculated {
+                isochrone_map,
+                reduced,
+            } => {
+                let focus = isochrone_map.isochrone.focus;
+                let (x, y) = focus.to_xy();
                 let mode = isochrone_map.isochrone.mode;
+                let stale = self.stale_reason(isochrone_map.graph_version());
+                let max_travel_time = self.transient.isochrone_query.max_travel_time * 60.0;
+                let reachable = reduced.as_ref().map(|reduced| {
+                    (
+                        isochrone_map.isochrone.reachable_within(max_travel_time),
+                        reduced.isochrone.reachable_within(max_travel_time),
+                    )
+                });
 
-                if ui.button("Clear").clicked() {
-                    self.isochrone_query.state = IsochroneQueryState::Empty;
+                if ui.button(self.strings.get("clear")).clicked() {
+                    self.transient.isochrone_query.state = IsochroneQueryState::Empty;
                 }
                 ui.separator();
 
-                ui.label(format!("Focus: ({}, {})", x, y));
-                ui.label(format!("Mode: {}", mode));
+                ui.label(
+                    self.strings
+                        .format("isochrone-focus", &[("x", &x), ("y", &y)]),
+                );
+                ui.label(self.strings.format("isochrone-mode", &[("mode", &mode)]));
+
+                if let Some(reason) = stale {
+                    let recompute = ui
+                        .horizontal(|ui| {
+                            ui.label(self.strings.get(reason));
+                            ui.button(self.strings.get("recompute")).clicked()
+                        })
+                        .inner;
+                    if recompute {
+                        self.transient.isochrone_query.mode = mode;
+                        self.query_isochrone(focus);
+                        return;
+                    }
+                }
+
+                if let Some((standard, reduced)) = reachable {
+                    ui.horizontal(|ui| {
+                        ui.radio_value(
+                            &mut self.transient.isochrone_query.show_reduced,
+                            false,
+                            self.strings.get("mobility-standard"),
+                        );
+                        ui.radio_value(
+                            &mut self.transient.isochrone_query.show_reduced,
+                            true,
+                            self.strings.get("mobility-reduced"),
+                        );
+                    });
+                    ui.label(self.strings.format(
+                        "reachable-stops",
+                        &[("standard", &standard), ("reduced", &reduced)],
+                    ));
+                }
 
-                ui.label("Max travel time (minutes):");
+                ui.label(self.strings.get("max-travel-time"));
                 ui.add(
                     egui::Slider::new(
-                        &mut self.isochrone_query.max_travel_time,
+                        &mut self.transient.isochrone_query.max_travel_time,
                         0.0..=6.0 * 60.0, // six hours, in minutes
                     )
                     .step_by(5.0),
                 );
 
-                ui.label("Quantization step (minutes):");
+                ui.label(self.strings.get("quantization-step"));
                 ui.add(
-                    egui::Slider::new(&mut self.isochrone_query.quantization_step, 0.0..=60.0)
-                        .step_by(5.0),
+                    egui::Slider::new(
+                        &mut self.transient.isochrone_query.quantization_step,
+                        0.0..=60.0,
+                    )
+                    .step_by(5.0),
                 );
+
+                if let Some(isochrone_map) = self.transient.isochrone_query.displayed_map() {
+                    let display_max = self
+                        .transient
+                        .isochrone_query
+                        .display_max_travel_time(isochrone_map);
+                    if display_max < self.transient.isochrone_query.max_travel_time {
+                        ui.label(self.strings.format(
+                            "everything-reachable",
+                            &[("minutes", &format!("{:.0}", display_max))],
+                        ));
+                    }
+                }
             }
         }
     }
@@ -294,25 +1352,24 @@ impl App {
 
         let bounding_box = self.get_bounding_box(ui);
         let highway_segment_in_bounds = |highway_segment_id| {
-            if self.congestion_analysis.filter_visible {
+            // the world state can still have entries for segments that have since been removed
+            if self.transient.congestion_analysis.filter_visible {
                 self.engine
                     .state
                     .highways
-                    .segment(highway_segment_id)
-                    .bounds
-                    .intersects(&bounding_box)
+                    .try_segment(highway_segment_id)
+                    .map_or(false, |segment| segment.bounds.intersects(&bounding_box))
             } else {
                 true
             }
         };
         let railway_segment_in_bounds = |railway_segment_id| {
-            if self.congestion_analysis.filter_visible {
+            if self.transient.congestion_analysis.filter_visible {
                 self.engine
                     .state
                     .railways
-                    .segment(railway_segment_id)
-                    .bounds
-                    .intersects(&bounding_box)
+                    .try_segment(railway_segment_id)
+                    .map_or(false, |segment| segment.bounds.intersects(&bounding_box))
             } else {
                 true
             }
@@ -320,7 +1377,7 @@ impl App {
 
         let local_zone_in_bounds = |(x, y)| {
             // TODO: it would be better to include the bottom-right corner as well
-            if self.congestion_analysis.filter_visible {
+            if self.transient.congestion_analysis.filter_visible {
                 bounding_box.contains(x, y)
             } else {
                 true
@@ -336,6 +1393,30 @@ impl App {
         // TODO: there is some duplication in here, but it's hard to pull it out because the
         // highway/metro stats types are different, and the snapshot types are different as well.
 
+        let historical_quantity = self.transient.congestion_analysis.historical_quantity;
+        let current = match self.transient.congestion_analysis.congestion_type {
+            CongestionType::HighwaySegments => historical_quantity.get(
+                self.world_state()
+                    .iter_highway_segments()
+                    .filter(|k, _| highway_segment_in_bounds(k)),
+            ),
+            CongestionType::MetroSegments => historical_quantity.get(
+                self.world_state()
+                    .iter_metro_segments()
+                    .filter(|k, _| railway_segment_in_bounds(k)),
+            ),
+            CongestionType::LocalRoads => historical_quantity.get(
+                self.world_state()
+                    .iter_local_road_zones()
+                    .filter(|k, _| local_zone_in_bounds(k)),
+            ),
+            CongestionType::Parking => historical_quantity.get(
+                self.world_state()
+                    .iter_parking_zones()
+                    .filter(|k, _| local_zone_in_bounds(k)),
+            ),
+        };
+
         let mut history_chart = crate::chart::Chart::new(
             self.engine
                 .world_state_history
@@ -343,105 +1424,63 @@ impl App {
                 .iter()
                 .enumerate()
                 .map(|(i, snapshot)| {
-                    let history_value = match self.congestion_analysis.congestion_type {
-                        CongestionType::HighwaySegments => {
-                            let data = snapshot
+                    let history = match self.transient.congestion_analysis.congestion_type {
+                        CongestionType::HighwaySegments => historical_quantity.get(
+                            snapshot
                                 .iter_highway_segments()
-                                .filter(|k, _| highway_segment_in_bounds(k));
-                            self.congestion_analysis.historical_quantity.get(data)
-                        }
-                        CongestionType::MetroSegments => {
-                            let data = snapshot
+                                .filter(|k, _| highway_segment_in_bounds(k)),
+                        ),
+                        CongestionType::MetroSegments => historical_quantity.get(
+                            snapshot
                                 .iter_metro_segments()
-                                .filter(|k, _| railway_segment_in_bounds(k));
-                            self.congestion_analysis.historical_quantity.get(data)
-                        }
-                        CongestionType::LocalRoads => {
-                            let data = snapshot
+                                .filter(|k, _| railway_segment_in_bounds(k)),
+                        ),
+                        CongestionType::LocalRoads => historical_quantity.get(
+                            snapshot
                                 .iter_local_road_zones()
-                                .filter(|k, _| local_zone_in_bounds(k));
-                            self.congestion_analysis.historical_quantity.get(data)
-                        }
-                        CongestionType::Parking => {
-                            let data = snapshot
+                                .filter(|k, _| local_zone_in_bounds(k)),
+                        ),
+                        CongestionType::Parking => historical_quantity.get(
+                            snapshot
                                 .iter_parking_zones()
-                                .filter(|k, _| local_zone_in_bounds(k));
-                            self.congestion_analysis.historical_quantity.get(data)
-                        }
+                                .filter(|k, _| local_zone_in_bounds(k)),
+                        ),
                     };
+                    let history_value = history.value as f32;
                     // if this snapshot is the current snapshot, display the current value as well
-                    let extra = (i == current_snapshot_index).then(|| {
-                        let current_value = match self.congestion_analysis.congestion_type {
-                            CongestionType::HighwaySegments => {
-                                let data = self
-                                    .engine
-                                    .world_state
-                                    .iter_highway_segments()
-                                    .filter(|k, _| highway_segment_in_bounds(k));
-                                self.congestion_analysis.historical_quantity.get(data)
-                            }
-                            CongestionType::MetroSegments => {
-                                let data = self
-                                    .engine
-                                    .world_state
-                                    .iter_metro_segments()
-                                    .filter(|k, _| railway_segment_in_bounds(k));
-                                self.congestion_analysis.historical_quantity.get(data)
-                            }
-                            CongestionType::LocalRoads => {
-                                let data = self
-                                    .engine
-                                    .world_state
-                                    .iter_local_road_zones()
-                                    .filter(|k, _| local_zone_in_bounds(k));
-                                self.congestion_analysis.historical_quantity.get(data)
-                            }
-                            CongestionType::Parking => {
-                                let data = self
-                                    .engine
-                                    .world_state
-                                    .iter_parking_zones()
-                                    .filter(|k, _| local_zone_in_bounds(k));
-                                self.congestion_analysis.historical_quantity.get(data)
-                            }
-                        };
-                        current_value - history_value
-                    });
+                    let extra = (i == current_snapshot_index)
+                        .then_some(current.value as f32 - history_value);
                     (history_value, extra)
                 })
                 .collect(),
         );
         history_chart.with_labels(|_, (entry, _)| format!("{:.1}", entry));
 
-        let histogram = match self.congestion_analysis.congestion_type {
+        let histogram = match self.transient.congestion_analysis.congestion_type {
             CongestionType::HighwaySegments => {
                 let data = self
-                    .engine
-                    .world_state
+                    .world_state()
                     .iter_highway_segments()
                     .filter(|k, v| v > 0.0 && highway_segment_in_bounds(k));
                 data.histogram(48, 200.0)
             }
             CongestionType::MetroSegments => {
                 let data = self
-                    .engine
-                    .world_state
+                    .world_state()
                     .iter_metro_segments()
                     .filter(|k, v| v > 0.0 && railway_segment_in_bounds(k));
                 data.histogram(48, 200.0)
             }
             CongestionType::LocalRoads => {
                 let data = self
-                    .engine
-                    .world_state
+                    .world_state()
                     .iter_local_road_zones()
                     .filter(|k, v| v > 0.0 && local_zone_in_bounds(k));
                 data.histogram(48, 200.0)
             }
             CongestionType::Parking => {
                 let data = self
-                    .engine
-                    .world_state
+                    .world_state()
                     .iter_parking_zones()
                     .filter(|k, v| v > 0.0 && local_zone_in_bounds(k));
                 data.histogram(48, 200.0)
@@ -449,71 +1488,374 @@ impl App {
         };
 
         let mut histogram_chart =
-            crate::chart::Chart::new(histogram.iter().map(|total| *total as f32).collect());
+            crate::chart::Chart::new(histogram.value.iter().map(|total| *total as f32).collect());
         histogram_chart.with_labels(|_, entry| format!("{}", entry as f64));
 
         egui::ComboBox::from_id_source("congestion_analysis_type")
-            .selected_text(self.congestion_analysis.congestion_type.label())
+            .selected_text(
+                self.strings
+                    .get(self.transient.congestion_analysis.congestion_type.key()),
+            )
             .show_ui(ui, |ui| {
                 for congestion_type in CongestionType::into_enum_iter() {
                     ui.selectable_value(
-                        &mut self.congestion_analysis.congestion_type,
+                        &mut self.transient.congestion_analysis.congestion_type,
                         congestion_type,
-                        congestion_type.label(),
+                        self.strings.get(congestion_type.key()),
                     );
                 }
             });
 
         ui.checkbox(
-            &mut self.congestion_analysis.filter_visible,
-            "Filter visible",
+            &mut self.transient.congestion_analysis.filter_visible,
+            self.strings.get("filter-visible"),
         );
 
-        ui.label("Historical congestion");
-        ui.label(format!("Scale {:.1}", history_chart.rounded_max_entry,));
+        let items = self
+            .transient
+            .congestion_analysis
+            .congestion_type
+            .items_key();
+        ui.label(self.strings.get("historical-congestion"));
+        ui.label(self.strings.format(
+            "chart-scale",
+            &[("scale", &format!("{:.1}", history_chart.rounded_max_entry))],
+        ));
+        ui.label(self.strings.format(
+            "congestion-current",
+            &[
+                ("value", &format!("{:.1}", current.value)),
+                ("count", &current.count),
+                ("items", &self.strings.get(items)),
+            ],
+        ));
         ui.add(history_chart);
 
         egui::ComboBox::from_id_source("congestion_analysis_historical_quantity")
-            .selected_text(self.congestion_analysis.historical_quantity.label())
+            .selected_text(
+                self.strings
+                    .get(self.transient.congestion_analysis.historical_quantity.key()),
+            )
             .show_ui(ui, |ui| {
                 for quantity in CongestionHistoricalQuantity::into_enum_iter() {
                     ui.selectable_value(
-                        &mut self.congestion_analysis.historical_quantity,
+                        &mut self.transient.congestion_analysis.historical_quantity,
                         quantity,
-                        quantity.label(),
+                        self.strings.get(quantity.key()),
                     );
                 }
             });
 
-        ui.label("Current histogram");
-        ui.label(format!("Scale: {:.1}", histogram_chart.rounded_max_entry));
+        ui.label(self.strings.get("current-histogram"));
+        ui.label(self.strings.format(
+            "chart-scale",
+            &[(
+                "scale",
+                &format!("{:.1}", histogram_chart.rounded_max_entry),
+            )],
+        ));
+        ui.label(self.strings.format(
+            "congestion-with-traffic",
+            &[
+                ("count", &histogram.count),
+                ("items", &self.strings.get(items)),
+            ],
+        ));
         ui.add(histogram_chart);
     }
 
+    /**
+     * Recalculate the station catchments if the map or the fields have changed. The engine caches
+     * the result, so this is cheap to call every frame.
+     */
+    pub(crate) fn update_station_catchments(&mut self) {
+        let max_walk_minutes = self.transient.station_catchments.max_walk_minutes;
+        match self.engine.station_catchments(max_walk_minutes) {
+            Ok(catchments) => self.transient.station_catchments.catchments = Some(catchments),
+            Err(err) => {
+                self.transient.station_catchments.catchments = None;
+                self.report_error(err);
+            }
+        }
+    }
+
+    fn draw_station_catchments(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(
+            &mut self.transient.station_catchments.show,
+            self.strings.get("color-stations-by-catchment"),
+        );
+        ui.label(self.strings.get("max-walking-time"));
+        ui.add(egui::Slider::new(
+            &mut self.transient.station_catchments.max_walk_minutes,
+            1.0..=30.0,
+        ));
+
+        self.update_station_catchments();
+        let catchments = match &self.transient.station_catchments.catchments {
+            Some(catchments) => catchments.clone(),
+            None => return,
+        };
+
+        if catchments.stations.is_empty() {
+            ui.label(self.strings.get("no-served-stations"));
+            return;
+        }
+
+        egui::Grid::new("station_catchments")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label(self.strings.get("catchment-station"));
+                ui.label(self.strings.get("catchment-people"));
+                ui.label(self.strings.get("catchment-jobs"));
+                ui.label(self.strings.get("catchment-unique-people"));
+                ui.label(self.strings.get("catchment-unique-jobs"));
+                ui.end_row();
+
+                for catchment in &catchments.stations {
+                    ui.label(&catchment.station.name);
+                    ui.label(format!("{}", catchment.people));
+                    ui.label(format!("{}", catchment.jobs));
+                    ui.label(format!("{}", catchment.unique_people));
+                    ui.label(format!("{}", catchment.unique_jobs));
+                    ui.end_row();
+                }
+            });
+
+        if ui.button(self.strings.get("copy-csv")).clicked() {
+            ui.output().copied_text = catchments.to_csv();
+        }
+    }
+
+    fn draw_commute_flows(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(
+            &mut self.transient.commute_flows.show,
+            self.strings.get("show-desire-lines"),
+        )
+        .on_hover_text(self.strings.get("show-desire-lines-hover"));
+        ui.label(self.strings.get("number-of-lines"));
+        ui.add(egui::Slider::new(
+            &mut self.transient.commute_flows.top_n,
+            1..=MAX_DESIRE_LINES,
+        ));
+
+        let matrix = match self.engine.od_matrix() {
+            Some(matrix) => matrix,
+            None => {
+                ui.label(self.strings.get("no-commutes"));
+                return;
+            }
+        };
+        ui.label(self.strings.format(
+            "commutes-yesterday",
+            &[("trips", &matrix.total_trips()), ("pairs", &matrix.len())],
+        ));
+        if ui.button(self.strings.get("copy-csv")).clicked() {
+            ui.output().copied_text = matrix.to_csv();
+        }
+    }
+
+    /// Compare the traffic history against real-world counts, as loaded from an observations file.
+    fn draw_calibration(&mut self, ui: &mut egui::Ui) {
+        ui.label(self.strings.get("observations-file"));
+        ui.text_edit_singleline(&mut self.transient.calibration.observations_path);
+        ui.label(self.strings.get("importer-id-map"));
+        ui.text_edit_singleline(&mut self.transient.calibration.id_map_path);
+
+        if ui.button(self.strings.get("compare")).clicked() {
+            match self.calibration_report() {
+                Ok(report) => {
+                    self.transient.calibration.report = Some(report);
+                    self.transient.calibration.error = None;
+                }
+                Err(err) => {
+                    self.transient.calibration.report = None;
+                    self.transient.calibration.error = Some(err.to_string());
+                }
+            }
+        }
+        if let Some(error) = &self.transient.calibration.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        let report = match &self.transient.calibration.report {
+            Some(report) => report,
+            None => return,
+        };
+
+        ui.checkbox(
+            &mut self.transient.calibration.show,
+            self.strings.get("color-segments-by-ratio"),
+        );
+        let format_mape = |mape: Option<f64>| match mape {
+            Some(mape) => format!("{:.1}%", mape),
+            None => String::from("-"),
+        };
+        ui.label(self.strings.format(
+            "calibration-aggregate",
+            &[
+                ("mape", &format_mape(report.aggregate.mape)),
+                ("rmse", &format!("{:.0}", report.aggregate.rmse)),
+                ("samples", &report.aggregate.samples),
+            ],
+        ));
+        if !report.unresolved.is_empty() {
+            ui.label(self.strings.format(
+                "calibration-unresolved",
+                &[("count", &report.unresolved.len())],
+            ));
+        }
+
+        egui::Grid::new("calibration_segments")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label(self.strings.get("calibration-segment"));
+                ui.label(self.strings.get("calibration-mape"));
+                ui.label(self.strings.get("calibration-rmse"));
+                ui.label(self.strings.get("calibration-ratio"));
+                ui.end_row();
+
+                for segment in &report.segments {
+                    ui.label(format!("{}", segment.segment));
+                    ui.label(format_mape(segment.metrics.mape));
+                    ui.label(format!("{:.0}", segment.metrics.rmse));
+                    ui.label(match segment.ratio() {
+                        Some(ratio) => format!("{:.2}", ratio),
+                        None => String::from("-"),
+                    });
+                    ui.end_row();
+                }
+            });
+
+        if ui.button(self.strings.get("copy-csv")).clicked() {
+            ui.output().copied_text = report.to_csv();
+        }
+    }
+
+    fn calibration_report(&self) -> Result<engine::CalibrationReport, engine::Error> {
+        let mut observations = engine::Observations::load_file(&std::path::PathBuf::from(
+            &self.transient.calibration.observations_path,
+        ))?;
+        if !self.transient.calibration.id_map_path.is_empty() {
+            observations = observations.with_id_map(engine::OsmIdMap::load_file(
+                &std::path::PathBuf::from(&self.transient.calibration.id_map_path),
+            )?);
+        }
+        self.engine.calibration_report(&observations)
+    }
+
+    /// The next few triggers on a time axis, so that it's clear what the simulation will do next.
+    fn draw_timeline(&mut self, ui: &mut egui::Ui) {
+        ui.label(self.strings.get("triggers-to-show"));
+        ui.add(egui::Slider::new(&mut self.timeline.count, 1..=100));
+
+        let current_time = self.engine.time_state.current_time;
+        let triggers: Vec<_> = self
+            .engine
+            .peek_triggers(self.timeline.count)
+            .into_iter()
+            .map(|(time, trigger)| {
+                (
+                    time.saturating_sub(current_time),
+                    engine::TriggerKind::from(trigger),
+                    trigger.agent(),
+                )
+            })
+            .collect();
+
+        let horizon = match triggers.last() {
+            Some((offset, _, _)) => (*offset).max(1),
+            None => {
+                ui.label(self.strings.get("no-upcoming-triggers"));
+                return;
+            }
+        };
+
+        let (response, painter) =
+            ui.allocate_painter((ui.available_width(), 24.0).into(), egui::Sense::hover());
+        let rect = response.rect;
+        painter.line_segment(
+            [rect.left_center(), rect.right_center()],
+            (1.0, egui::Color32::from_gray(150)),
+        );
+        for (offset, _, agent) in &triggers {
+            let x = rect.left() + rect.width() * *offset as f32 / horizon as f32;
+            // agent triggers are by far the most common, so distinguish them from the rest
+            let color = match agent {
+                Some(_) => egui::Color32::from_rgb(100, 100, 255),
+                None => egui::Color32::from_gray(255),
+            };
+            painter.line_segment(
+                [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
+                (1.0, color),
+            );
+        }
+        ui.label(self.strings.format(
+            "timeline-horizon",
+            &[("offset", &crate::format::offset(horizon))],
+        ));
+
+        egui::Grid::new("timeline").striped(true).show(ui, |ui| {
+            for (offset, kind, agent) in triggers {
+                ui.label(format!("+{}", crate::format::offset(offset)));
+                ui.label(format!("{:?}", kind));
+                match agent {
+                    // the agent may have been removed since the trigger was queued
+                    Some(id) if self.engine.agents.contains_key(&id) => {
+                        if ui
+                            .small_button(self.strings.format("agent-button", &[("id", &id)]))
+                            .clicked()
+                        {
+                            self.transient.agent_detail = AgentDetail::Selected { id };
+                        }
+                    }
+                    _ => {
+                        ui.label("");
+                    }
+                }
+                ui.end_row();
+            }
+        });
+    }
+
     fn draw_agent_detail(&mut self, ui: &mut egui::Ui) {
-        match self.agent_detail {
+        match self.transient.agent_detail {
             AgentDetail::Empty => {
-                if ui.button("Pick tile").clicked() {
-                    self.agent_detail = AgentDetail::Querying;
+                if ui.button(self.strings.get("pick-tile")).clicked() {
+                    self.transient.agent_detail = AgentDetail::Querying;
                 }
             }
             AgentDetail::Querying => {
-                if ui.button("Clear").clicked() {
-                    self.agent_detail = AgentDetail::Empty;
+                if ui.button(self.strings.get("clear")).clicked() {
+                    self.transient.agent_detail = AgentDetail::Empty;
                 }
                 ui.separator();
 
-                ui.label("<waiting for tile selection>");
+                ui.label(self.strings.get("waiting-for-tile"));
             }
             AgentDetail::Query { address } => {
                 use tiles::TileType;
 
-                if ui.button("Clear").clicked() {
-                    self.agent_detail = AgentDetail::Empty;
+                if ui.button(self.strings.get("clear")).clicked() {
+                    self.transient.agent_detail = AgentDetail::Empty;
                 }
                 ui.separator();
 
-                let leaf = self.engine.state.qtree.get_leaf(address).unwrap();
+                let leaf = match self.engine.get_leaf(address) {
+                    Ok(leaf) => leaf,
+                    Err(err) => {
+                        ui.colored_label(egui::Color32::RED, err.to_string());
+                        return;
+                    }
+                };
+                if let tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { industry, .. }) =
+                    &leaf.tile
+                {
+                    ui.label(
+                        self.strings
+                            .format("tile-industry", &[("industry", &industry.name())]),
+                    );
+                }
+
                 // TODO: replace with if-let chain once stabilized
                 let agents = leaf.tile.query_agents().and_then(|agents| {
                     if !agents.is_empty() {
@@ -523,20 +1865,26 @@ impl App {
                     }
                 });
                 if let Some(agents) = agents {
-                    ui.label(format!("Selected tile has {} agent(s):", agents.len()));
+                    ui.label(
+                        self.strings
+                            .format("tile-agents", &[("count", &agents.len())]),
+                    );
 
                     for id in agents {
-                        if ui.button(format!("Agent #{}", id)).clicked() {
-                            self.agent_detail = AgentDetail::Selected { id: *id };
+                        if ui
+                            .button(self.strings.format("agent-button", &[("id", id)]))
+                            .clicked()
+                        {
+                            self.transient.agent_detail = AgentDetail::Selected { id: *id };
                         }
                     }
                 } else {
-                    ui.label("Selected tile has no agents");
+                    ui.label(self.strings.get("tile-no-agents"));
                 }
             }
             AgentDetail::Selected { id } => {
-                if ui.button("Clear").clicked() {
-                    self.agent_detail = AgentDetail::Empty;
+                if ui.button(self.strings.get("clear")).clicked() {
+                    self.transient.agent_detail = AgentDetail::Empty;
                 }
                 ui.separator();
 
@@ -545,124 +1893,821 @@ impl App {
         }
     }
 
+    fn draw_segment_detail(&mut self, ui: &mut egui::Ui, selection: SegmentSelection) {
+        use route::WorldState;
+
+        let strings = &self.strings;
+        let units = self.display_options.units;
+        let config = &self.engine.state.config;
+        let tile_size = config.min_tile_size as f64;
+        let predictor = self
+            .engine
+            .world_state_history
+            .get_predictor(self.engine.time_state.current_time);
+
+        match selection {
+            SegmentSelection::Highway(id) => {
+                use highway::timing::HighwayTiming;
+
+                let segment = match self.engine.state.highways.segments().get(&id) {
+                    Some(segment) => segment,
+                    None => {
+                        ui.label(strings.format("highway-segment-removed", &[("id", &id.inner())]));
+                        return;
+                    }
+                };
+                let data = &segment.data;
+
+                ui.label(strings.format("highway-segment", &[("id", &id.inner())]));
+                ui.label(
+                    strings.format(
+                        "segment-name",
+                        &[(
+                            "name",
+                            &data
+                                .name
+                                .as_deref()
+                                .unwrap_or_else(|| strings.get("not-available")),
+                        )],
+                    ),
+                );
+                if !data.refs.is_empty() {
+                    ui.label(strings.format("segment-refs", &[("refs", &data.refs.join(", "))]));
+                }
+                match data.lanes {
+                    Some(lanes) => ui.label(strings.format("segment-lanes", &[("lanes", &lanes)])),
+                    None => ui.label(strings.format(
+                        "segment-lanes-default",
+                        &[
+                            ("none", &strings.get("not-available")),
+                            ("lanes", &highway::timing::DEFAULT_LANES),
+                        ],
+                    )),
+                };
+                match data.speed_limit {
+                    Some(speed_limit) => ui.label(strings.format(
+                        "speed-limit",
+                        &[(
+                            "speed",
+                            &crate::format::speed(speed_limit as f64, units, strings),
+                        )],
+                    )),
+                    None => ui.label(strings.format(
+                        "speed-limit-default",
+                        &[
+                            ("none", &strings.get("not-available")),
+                            (
+                                "speed",
+                                &crate::format::speed(
+                                    highway::timing::DEFAULT_SPEED as f64,
+                                    units,
+                                    strings,
+                                ),
+                            ),
+                        ],
+                    )),
+                };
+                ui.label(strings.format(
+                    "segment-length",
+                    &[(
+                        "distance",
+                        &crate::format::distance(segment.length() * tile_size, units, strings),
+                    )],
+                ));
+                ui.label(strings.format(
+                    "segment-state",
+                    &[("state", &format!("{:?}", segment.change_state))],
+                ));
+
+                ui.separator();
+
+                ui.label(strings.format(
+                    "free-flow-time",
+                    &[(
+                        "duration",
+                        &crate::format::duration(segment.highway_travel_time(tile_size), strings),
+                    )],
+                ));
+                let travelers = self.world_state().get_highway_segment_travelers(id);
+                ui.label(strings.format(
+                    "current-travelers",
+                    &[("travelers", &format!("{:.1}", travelers))],
+                ));
+                ui.label(strings.format(
+                    "predicted-travelers",
+                    &[(
+                        "travelers",
+                        &format!("{:.1}", predictor.get_highway_segment_travelers(id)),
+                    )],
+                ));
+                ui.label(strings.format(
+                    "congested-factor",
+                    &[(
+                        "factor",
+                        &format!(
+                            "{:.2}",
+                            segment.congested_travel_factor(
+                                config.min_tile_size,
+                                config.people_per_sim,
+                                travelers
+                            )
+                        ),
+                    )],
+                ));
+            }
+            SegmentSelection::Railway(id) => {
+                use metro::RailwayTiming;
+
+                let railways = &self.engine.state.railways;
+                let segment = match railways.segments().get(&id) {
+                    Some(segment) => segment,
+                    None => {
+                        ui.label(strings.format("railway-segment-removed", &[("id", &id.inner())]));
+                        return;
+                    }
+                };
+
+                ui.label(strings.format("railway-segment", &[("id", &id.inner())]));
+                match segment.data.speed_limit {
+                    Some(speed_limit) => ui.label(strings.format(
+                        "speed-limit",
+                        &[(
+                            "speed",
+                            &crate::format::speed(speed_limit as f64, units, strings),
+                        )],
+                    )),
+                    None => ui.label(strings.format(
+                        "speed-limit-none",
+                        &[("none", &strings.get("not-available"))],
+                    )),
+                };
+                ui.label(strings.format(
+                    "segment-length",
+                    &[(
+                        "distance",
+                        &crate::format::distance(segment.length() * tile_size, units, strings),
+                    )],
+                ));
+                ui.label(strings.format(
+                    "segment-alignment",
+                    &[("alignment", &segment.data.alignment.name())],
+                ));
+                ui.label(strings.format(
+                    "construction-cost",
+                    &[(
+                        "cost",
+                        &format!("{:.0}", self.engine.railway_construction_cost(segment)),
+                    )],
+                ));
+                ui.label(strings.format(
+                    "segment-state",
+                    &[("state", &format!("{:?}", segment.change_state))],
+                ));
+
+                ui.separator();
+
+                ui.label(strings.format(
+                    "current-travelers",
+                    &[(
+                        "travelers",
+                        &format!("{:.1}", self.world_state().get_metro_segment_travelers(id)),
+                    )],
+                ));
+                ui.label(strings.format(
+                    "predicted-travelers",
+                    &[(
+                        "travelers",
+                        &format!("{:.1}", predictor.get_metro_segment_travelers(id)),
+                    )],
+                ));
+
+                ui.separator();
+
+                let metros = &self.engine.state.metros;
+                let mut metro_lines: Vec<_> =
+                    metros.railway_segment_metro_lines(id).iter().collect();
+                metro_lines.sort();
+                if metro_lines.is_empty() {
+                    ui.label(strings.get("no-metro-lines"));
+                } else {
+                    ui.label(strings.get("metro-lines"));
+                    for metro_line_id in metro_lines {
+                        let metro_line = metros.metro_line(*metro_line_id);
+                        let travel_time = segment.railway_travel_time(
+                            metro_line.data.speed_limit,
+                            tile_size,
+                            railways,
+                        );
+                        ui.horizontal(|ui| {
+                            ui.label(strings.format(
+                                "metro-line-time",
+                                &[
+                                    ("line", &metro_line.data.name),
+                                    ("duration", &crate::format::duration(travel_time, strings)),
+                                ],
+                            ));
+                            if self.engine.open_change_set().is_some()
+                                && ui.small_button(strings.get("edit-path")).clicked()
+                            {
+                                // start from the line's first junction
+                                let first = metro_line
+                                    .segments()
+                                    .first()
+                                    .map(|oriented| oriented.start_junction(railways));
+                                self.transient.planned_changes.path_edit = Some(PathEdit::new(
+                                    *metro_line_id,
+                                    first.into_iter().collect(),
+                                ));
+                            }
+                        });
+                    }
+                }
+            }
+        }
+
+        ui.separator();
+
+        if ui.button(self.strings.get("copy-handle-id")).clicked() {
+            ui.output().copied_text = selection.to_string();
+        }
+
+        let segment = selection.network_segment();
+        if let Some(until) = self
+            .engine
+            .segment_change_state(segment)
+            .and_then(|change_state| change_state.closed_until())
+        {
+            ui.label(self.strings.format(
+                "closed-until",
+                &[("time", &self.engine.time_state.pretty_date_time(until))],
+            ));
+        }
+        ui.horizontal(|ui| {
+            ui.label(self.strings.get("close-for-hours"));
+            ui.add(egui::Slider::new(
+                &mut self.transient.closure_hours,
+                1..=7 * 24,
+            ));
+        });
+        if ui.button(self.strings.get("close-segment")).clicked() {
+            let hours = self.transient.closure_hours;
+            if let Err(err) = self.engine.close_segment_for_hours(segment, hours) {
+                self.report_error(err);
+            }
+        }
+
+        self.draw_speed_limit_edit(ui, selection);
+
+        if let Some(handle) = self.engine.open_change_set() {
+            if ui.button(self.strings.get("plan-removal")).clicked() {
+                let result = match selection {
+                    SegmentSelection::Highway(id) => {
+                        self.engine.staged_remove_highway_segment(handle, id)
+                    }
+                    SegmentSelection::Railway(id) => {
+                        self.engine.staged_remove_railway_segment(handle, id)
+                    }
+                };
+                self.transient.planned_changes.error = result.err().map(|e| e.to_string());
+            }
+        }
+    }
+
+    fn draw_speed_limit_edit(&mut self, ui: &mut egui::Ui, selection: SegmentSelection) {
+        let strings = self.strings.clone();
+        let units = self.display_options.units;
+        let state = &self.engine.state;
+        // the current limit, and the speed assumed without one
+        let (speed_limit, assumed) = match selection {
+            SegmentSelection::Highway(id) => match state.highways.try_segment(id) {
+                Some(segment) => (segment.data.speed_limit, highway::timing::DEFAULT_SPEED),
+                None => return,
+            },
+            SegmentSelection::Railway(id) => match state.railways.try_segment(id) {
+                Some(segment) => {
+                    // trains go as fast as the fastest line allows
+                    let fastest = state
+                        .metros
+                        .railway_segment_metro_lines(id)
+                        .iter()
+                        .map(|line| state.metros.metro_line(*line).data.speed_limit)
+                        .max();
+                    (
+                        segment.data.speed_limit,
+                        fastest.unwrap_or(highway::timing::DEFAULT_SPEED),
+                    )
+                }
+                None => return,
+            },
+        };
+
+        let current = matches!(
+            self.transient.speed_limit_edit,
+            Some((edited, edited_units, _)) if edited == selection && edited_units == units
+        );
+        if !current {
+            let value = units
+                .speed_value(speed_limit.unwrap_or(assumed) as f64)
+                .round();
+            self.transient.speed_limit_edit = Some((selection, units, value));
+        }
+
+        let mut new_speed_limit = None;
+        ui.horizontal(|ui| {
+            ui.label(strings.get("new-speed-limit"));
+            if let Some((_, _, value)) = &mut self.transient.speed_limit_edit {
+                ui.add(
+                    egui::DragValue::new(value)
+                        .clamp_range(1.0..=300.0)
+                        .max_decimals(0)
+                        .suffix(format!(" {}", units.speed_unit())),
+                );
+                if ui.button(strings.get("set-speed-limit")).clicked() {
+                    // speed limits are kept in whole meters per second
+                    let meters_per_second = units.meters_per_second(*value).round().max(1.0);
+                    new_speed_limit = Some(Some(meters_per_second as u32));
+                }
+            }
+            if speed_limit.is_some() && ui.button(strings.get("remove-speed-limit")).clicked() {
+                new_speed_limit = Some(None);
+            }
+        });
+
+        if let Some(new_speed_limit) = new_speed_limit {
+            match self
+                .engine
+                .set_segment_speed_limit(selection.network_segment(), new_speed_limit)
+            {
+                Ok(metro_lines) => {
+                    self.transient.speed_limit_edit = None;
+                    if !metro_lines.is_empty() {
+                        self.status = Some(
+                            strings.format("timetables-changed", &[("count", &metro_lines.len())]),
+                        );
+                    }
+                }
+                Err(err) => self.report_error(err),
+            }
+        }
+    }
+
     fn draw_agent_info(&mut self, ui: &mut egui::Ui, id: u64) {
         let agent = self.engine.agents.get(&id).expect("missing agent");
 
-        ui.label(format!("Agent #{}:", id));
-        ui.label(format!(
-            "Age: {}",
-            agent.data.age(self.engine.time_state.current_date())
+        ui.label(self.strings.format("agent-heading", &[("id", &id)]));
+        ui.label(self.strings.format(
+            "agent-age",
+            &[(
+                "age",
+                &agent.data.age(self.engine.time_state.current_date()),
+            )],
         ));
-        ui.label(format!(
-            "Education: {} ({} years)",
-            agent.data.education_degree(),
-            agent.data.years_of_education
+        ui.label(self.strings.format(
+            "agent-education",
+            &[
+                ("degree", &agent.data.education_degree()),
+                ("years", &agent.data.years_of_education),
+            ],
         ));
 
+        match &agent.state {
+            agent::AgentState::Tile(address) => {
+                let (x, y) = address.to_xy();
+                ui.label(
+                    self.strings
+                        .format("agent-at-tile", &[("x", &x), ("y", &y)]),
+                );
+            }
+            agent::AgentState::Route(route_state) => {
+                ui.label(self.strings.format(
+                    "agent-status",
+                    &[("status", &route_state.phase_description())],
+                ));
+            }
+            agent::AgentState::Unknown => {
+                ui.label(self.strings.get("agent-status-unknown"));
+            }
+        }
+
         let (home_x, home_y) = agent.housing.to_xy();
-        ui.label(format!("Home: ({}, {})", home_x, home_y));
+        ui.label(
+            self.strings
+                .format("agent-home", &[("x", &home_x), ("y", &home_y)]),
+        );
 
         match agent.workplace {
             Some(workplace) => {
                 let (work_x, work_y) = workplace.to_xy();
-                ui.label(format!("Work: ({}, {})", work_x, work_y));
+                ui.label(
+                    self.strings
+                        .format("agent-work", &[("x", &work_x), ("y", &work_y)]),
+                );
             }
             None => {
-                ui.label("Work: n/a");
+                ui.label(self.strings.format(
+                    "agent-no-work",
+                    &[("none", &self.strings.get("not-available"))],
+                ));
             }
         }
 
-        ui.label(format!(
-            "Housing stickiness: {:.2}/1.00",
-            agent.data.housing_stickiness()
+        let archetypes = &self.engine.state.config.archetypes;
+        ui.label(self.strings.format(
+            "agent-archetype",
+            &[("name", &agent.data.archetype(archetypes).name)],
         ));
-        ui.label(format!(
-            "Workplace stickiness: {:.2}/1.00",
-            agent.data.workplace_stickiness()
+        ui.label(self.strings.format(
+            "housing-stickiness",
+            &[(
+                "score",
+                &format!("{:.2}", agent.data.housing_stickiness(archetypes)),
+            )],
         ));
-        ui.label(format!(
-            "Commute length tolerance: {} minutes",
-            agent.data.commute_length_tolerance() / 60,
+        ui.label(self.strings.format(
+            "workplace-stickiness",
+            &[(
+                "score",
+                &format!("{:.2}", agent.data.workplace_stickiness(archetypes)),
+            )],
+        ));
+        ui.label(self.strings.format(
+            "commute-tolerance",
+            &[(
+                "minutes",
+                &(agent.data.commute_length_tolerance(archetypes) / 60),
+            )],
         ));
 
-        // TODO: use some better way to format durations. chrono::Duration itself is not
-        // formattable.
-        if let Some(average_commute) = format_duration(agent.average_commute_length()) {
-            ui.label(format!("Average commute: {}", average_commute,));
+        ui.label(self.strings.format(
+            "average-commute",
+            &[(
+                "duration",
+                &crate::format::duration(agent.average_commute_length() as f64, &self.strings),
+            )],
+        ));
+        if let agent::AgentState::Route(route_state) = &agent.state {
+            if matches!(
+                route_state.route_type,
+                agent::RouteType::CommuteToWork | agent::RouteType::CommuteFromWork
+            ) {
+                let reliability = route_state.route.reliability(
+                    &self.engine.world_state_history,
+                    &self.engine.world_state,
+                    &self.engine.state,
+                );
+                ui.label(self.strings.format(
+                    "commute-reliability",
+                    &[(
+                        "reliability",
+                        &crate::format::reliability(&reliability, &self.strings),
+                    )],
+                ));
+            }
         }
 
-        if let Some(workplace_happiness_score) = agent.workplace_happiness_score() {
-            ui.label(format!(
-                "Workplace happiness score: {:.2}/1.00",
-                workplace_happiness_score
+        let happiness_config = &self.engine.state.config.workplace_happiness;
+        if let Some(happiness) = agent.workplace_happiness(
+            &self.engine.state.config,
+            self.engine.time_state.current_time,
+        ) {
+            ui.label(self.strings.format(
+                "workplace-happiness",
+                &[
+                    ("score", &format!("{:.2}", happiness.score)),
+                    (
+                        "threshold",
+                        &format!("{:.2}", happiness_config.quit_threshold),
+                    ),
+                ],
             ));
+            for component in &happiness.components {
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::ProgressBar::new(component.value)
+                            .desired_width(80.0)
+                            .text(format!("{:.2}", component.value)),
+                    );
+                    ui.label(self.strings.format(
+                        "happiness-component",
+                        &[
+                            ("component", &component.component),
+                            ("percent", &format!("{:.0}", component.weight * 100.0)),
+                        ],
+                    ));
+                });
+            }
         }
 
         if let Some(workplace) = agent.workplace {
-            if ui.button("Show commute").clicked() {
-                self.route_query.start_address = Some(agent.housing);
-                self.route_query.stop_address = Some(workplace);
+            if ui.button(self.strings.get("show-commute")).clicked() {
+                self.transient.route_query.start_address = Some(agent.housing);
+                self.transient.route_query.stop_address = Some(workplace);
                 self.update_route_query();
             }
         }
+
+        ui.separator();
+        if self.engine.is_agent_watched(id) {
+            if ui.button(self.strings.get("stop-watching-log")).clicked() {
+                self.engine.unwatch_agent(id);
+            }
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .stick_to_bottom()
+                .show(ui, |ui| {
+                    for line in self.engine.agent_log(id) {
+                        ui.monospace(line);
+                    }
+                });
+        } else if ui.button(self.strings.get("watch-log")).clicked() {
+            if let Err(err) = self.engine.watch_agent(id, None) {
+                self.status = Some(err.to_string());
+            }
+        }
     }
 }
 
 #[derive(Debug)]
 pub(crate) struct Overlay {
     pub field: Option<crate::field_overlay::FieldType>,
+    /// the name of a registered blurred field to draw instead of a field type
+    pub blurred_field: Option<String>,
+    /// only populated while the agent density overlay is selected
+    pub agent_counts: crate::field_overlay::AgentCounts,
+    /// only populated while a field that can be sampled ahead of time is selected
+    pub field_samples: crate::field_overlay::FieldSamples,
+    /// vacancy markers are drawn on top of whichever field is selected
+    pub vacancy_markers: std::collections::HashSet<crate::vacancy_markers::VacancyKind>,
+    /// the vacancy markers drawn in the last frame, for picking them
+    pub placed_markers: crate::vacancy_markers::PlacedMarkers,
 }
 
 impl Overlay {
     fn new() -> Self {
-        Self { field: None }
+        Self {
+            field: None,
+            blurred_field: None,
+            agent_counts: Default::default(),
+            field_samples: Default::default(),
+            vacancy_markers: Default::default(),
+            placed_markers: Default::default(),
+        }
+    }
+
+    /// Refresh any overlay data that is not stored in the fields, sampling down to the given depth.
+    pub fn update(&mut self, engine: &engine::Engine, sampled_depth: u32) {
+        match self.field {
+            Some(crate::field_overlay::FieldType::AgentDensity) => {
+                self.agent_counts = crate::field_overlay::AgentCounts::new(engine);
+            }
+            _ => self.agent_counts.clear(),
+        }
+        self.field_samples.update(engine, self.field, sampled_depth);
     }
 
-    fn draw(&mut self, ui: &mut egui::Ui) {
+    pub fn is_active(&self) -> bool {
+        self.field.is_some() || self.blurred_field.is_some()
+    }
+
+    /**
+     * Drop cached overlay data so that it is recomputed for the given engine, along with the
+     * blurred field if the engine doesn't have it.
+     */
+    fn reset_caches(&mut self, engine: &engine::Engine) {
+        self.agent_counts.clear();
+        self.field_samples = Default::default();
+        self.placed_markers = Default::default();
+        if let Some(name) = &self.blurred_field {
+            if engine.blurred_field(name).is_none() {
+                self.blurred_field = None;
+            }
+        }
+    }
+
+    fn draw(
+        &mut self,
+        ui: &mut egui::Ui,
+        blurred_fields: &[String],
+        strings: &crate::strings::Strings,
+    ) {
         use enum_iterator::IntoEnumIterator;
 
-        ui.radio_value(&mut self.field, None, "None");
-        for field_type in crate::field_overlay::FieldType::into_enum_iter() {
-            ui.radio_value(&mut self.field, Some(field_type), field_type.label());
+        // only one overlay is drawn at a time, so selecting any field clears the blurred field
+        if ui
+            .radio(!self.is_active(), strings.get("overlay-none"))
+            .clicked()
+        {
+            self.field = None;
+            self.blurred_field = None;
+        }
+        for field_type in crate::field_overlay::FieldType::into_enum_iter() {
+            if ui
+                .radio_value(&mut self.field, Some(field_type), field_type.label())
+                .clicked()
+            {
+                self.blurred_field = None;
+            }
+        }
+
+        ui.separator();
+        ui.label(strings.get("markers"));
+        for kind in crate::vacancy_markers::VacancyKind::into_enum_iter() {
+            let mut enabled = self.vacancy_markers.contains(&kind);
+            if ui.checkbox(&mut enabled, kind.label()).changed() {
+                if enabled {
+                    self.vacancy_markers.insert(kind);
+                } else {
+                    self.vacancy_markers.remove(&kind);
+                }
+            }
+        }
+        if self.placed_markers.dropped > 0 {
+            ui.label(strings.format(
+                "markers-dropped",
+                &[("count", &self.placed_markers.dropped)],
+            ));
+        }
+
+        ui.separator();
+        ui.label(strings.get("blurred-fields"));
+        for name in blurred_fields {
+            let selected = self.blurred_field.as_ref() == Some(name);
+            if ui.radio(selected, name).clicked() {
+                self.field = None;
+                self.blurred_field = Some(name.clone());
+            }
         }
     }
 }
 
 #[derive(Debug)]
 pub(crate) struct DisplayOptions {
+    pub quality: crate::quality::QualityPreset,
+    /// only used while the quality is Auto
+    pub auto_quality: crate::quality::AutoQuality,
     pub min_tile_size: u32,
     pub spline_resolution: u32,
     pub field_resolution: u32,
+    /// see QualitySettings::overlay_depth
+    pub overlay_depth: u32,
+    pub show_agents: bool,
     pub show_all_railways: bool,
     pub show_railway_junctions: bool,
     pub show_highway_junctions: bool,
+    pub show_labels: bool,
+    pub palette: palette::Palette,
+    /// draw the map as if it were this time of day instead of following the clock
+    pub time_of_day: Option<crate::theme::DayBand>,
+    /// for distances and speeds
+    pub units: crate::format::Units,
 }
 
 impl DisplayOptions {
     fn new() -> Self {
+        use crate::quality::{AutoQuality, QualityLevel, QualityPreset};
+
+        let settings = QualityLevel::High.settings();
         Self {
-            min_tile_size: 2,
-            spline_resolution: 5,
-            field_resolution: 10,
+            quality: QualityPreset::High,
+            auto_quality: AutoQuality::new(QualityLevel::High),
+            min_tile_size: settings.min_tile_size,
+            spline_resolution: settings.spline_resolution,
+            field_resolution: settings.field_resolution,
+            overlay_depth: settings.overlay_depth,
+            show_agents: settings.show_agents,
             show_all_railways: false,
             show_railway_junctions: false,
             show_highway_junctions: false,
+            show_labels: true,
+            palette: palette::Palette::default(),
+            time_of_day: None,
+            units: Default::default(),
+        }
+    }
+
+    /**
+     * Switch to a quality preset, which overwrites the options that it controls. The limits on the
+     * traffic model don't change until the map is loaded again.
+     */
+    pub fn set_quality(&mut self, quality: crate::quality::QualityPreset) {
+        let level = quality.initial_level();
+        self.quality = quality;
+        self.auto_quality = crate::quality::AutoQuality::new(level);
+        self.apply_quality_level(level);
+    }
+
+    fn apply_quality_level(&mut self, level: crate::quality::QualityLevel) {
+        let settings = level.settings();
+        self.min_tile_size = settings.min_tile_size;
+        self.spline_resolution = settings.spline_resolution;
+        self.field_resolution = settings.field_resolution;
+        self.overlay_depth = settings.overlay_depth;
+        self.show_agents = settings.show_agents;
+    }
+
+    /// Record how long the last frame took, and step the quality up or down if it is Auto.
+    pub fn update_quality(&mut self, elapsed: f64) {
+        if self.quality == crate::quality::QualityPreset::Auto {
+            if let Some(level) = self.auto_quality.update(elapsed) {
+                tracing::info!(?level, "automatically changed quality");
+                self.apply_quality_level(level);
+            }
         }
     }
 
-    fn draw(&mut self, ui: &mut egui::Ui) {
-        ui.label("Min tile size:");
+    fn draw(&mut self, ui: &mut egui::Ui, strings: &crate::strings::Strings) {
+        ui.label(strings.get("quality"));
+        let mut quality = self.quality;
+        egui::ComboBox::from_id_source("display_options_quality")
+            .selected_text(quality.label())
+            .show_ui(ui, |ui| {
+                use enum_iterator::IntoEnumIterator;
+
+                for preset in crate::quality::QualityPreset::into_enum_iter() {
+                    ui.selectable_value(&mut quality, preset, preset.label());
+                }
+            });
+        if quality != self.quality {
+            self.set_quality(quality);
+        }
+        if self.quality == crate::quality::QualityPreset::Auto {
+            ui.label(strings.format(
+                "current-quality-level",
+                &[("level", &format!("{:?}", self.auto_quality.level()))],
+            ));
+        }
+        ui.label(strings.get("quality-reload-note"));
+
+        ui.separator();
+
+        ui.label(strings.get("min-tile-size"));
         ui.add(egui::Slider::new(&mut self.min_tile_size, 1..=100));
-        ui.label("Spline resolution:");
+        ui.label(strings.get("spline-resolution"));
         ui.add(egui::Slider::new(&mut self.spline_resolution, 1..=100));
-        ui.label("Field resolution:");
+        ui.label(strings.get("field-resolution"));
         ui.add(egui::Slider::new(&mut self.field_resolution, 3..=100));
+        ui.label(strings.get("overlay-depth"));
+        ui.add(egui::Slider::new(
+            &mut self.overlay_depth,
+            0..=crate::field_overlay::MAX_SAMPLED_DEPTH,
+        ));
+
+        ui.separator();
+
+        ui.checkbox(&mut self.show_agents, strings.get("show-agents"));
+        ui.checkbox(
+            &mut self.show_all_railways,
+            strings.get("show-all-railways"),
+        );
+        ui.checkbox(
+            &mut self.show_railway_junctions,
+            strings.get("show-railway-junctions"),
+        );
+        ui.checkbox(
+            &mut self.show_highway_junctions,
+            strings.get("show-highway-junctions"),
+        );
+        ui.checkbox(&mut self.show_labels, strings.get("show-labels"));
 
         ui.separator();
 
-        ui.checkbox(&mut self.show_all_railways, "Show all railways");
-        ui.checkbox(&mut self.show_railway_junctions, "Show railway junctions");
-        ui.checkbox(&mut self.show_highway_junctions, "Show highway junctions");
+        ui.label(strings.get("palette"));
+        egui::ComboBox::from_id_source("display_options_palette")
+            .selected_text(self.palette.label())
+            .show_ui(ui, |ui| {
+                use enum_iterator::IntoEnumIterator;
+
+                for palette in palette::Palette::into_enum_iter() {
+                    ui.selectable_value(&mut self.palette, palette, palette.label());
+                }
+            });
+
+        ui.label(strings.get("display-time-of-day"));
+        egui::ComboBox::from_id_source("display_options_time_of_day")
+            .selected_text(
+                self.time_of_day
+                    .map_or(strings.get("follow-the-clock"), |band| band.label()),
+            )
+            .show_ui(ui, |ui| {
+                use enum_iterator::IntoEnumIterator;
+
+                ui.selectable_value(&mut self.time_of_day, None, strings.get("follow-the-clock"));
+                for band in crate::theme::DayBand::into_enum_iter() {
+                    ui.selectable_value(&mut self.time_of_day, Some(band), band.label());
+                }
+            });
+
+        // every panel formats its quantities as it is drawn, so this takes effect right away
+        ui.label(strings.get("units"));
+        egui::ComboBox::from_id_source("display_options_units")
+            .selected_text(strings.get(self.units.key()))
+            .show_ui(ui, |ui| {
+                use enum_iterator::IntoEnumIterator;
+
+                for units in crate::format::Units::into_enum_iter() {
+                    ui.selectable_value(&mut self.units, units, strings.get(units.key()));
+                }
+            });
     }
 }
 
@@ -673,15 +2718,28 @@ pub(crate) struct Diagnostics {
     pub metro_vertices: u64,
     pub highway_vertices: u64,
     pub agents: u64,
+    /// only estimated on request, since it takes a while
+    pub memory_report: Option<engine::MemoryReport>,
+    /// the result of the last repair of out-of-bounds locations, if any
+    pub bounds_repair: Option<state::BoundsRepairReport>,
+    /// the model coordinates under the cursor, if it is over the map
+    pub cursor: Option<(f32, f32)>,
+    /// whether to show the latitude and longitude under the cursor, for georeferenced maps
+    pub show_lat_lon: bool,
 }
 
 impl Diagnostics {
     fn draw(&self, app: &App, ui: &mut egui::Ui) {
-        ui.label(format!("Frame rate: {:.1}", self.frame_rate));
-        ui.label(format!("Tiles: {}", self.tiles));
-        ui.label(format!("Metro vertices: {}", self.metro_vertices));
-        ui.label(format!("Highway vertices: {}", self.highway_vertices));
-        ui.label(format!("Agents: {}", self.agents));
+        let strings = &app.strings;
+        ui.label(strings.format(
+            "frame-rate",
+            &[("rate", &format!("{:.1}", self.frame_rate))],
+        ));
+        ui.label(strings.format("drawn-tiles", &[("count", &self.tiles)]));
+        ui.label(strings.format("metro-vertices", &[("count", &self.metro_vertices)]));
+        ui.label(strings.format("highway-vertices", &[("count", &self.highway_vertices)]));
+        ui.label(strings.format("drawn-agents", &[("count", &self.agents)]));
+        ui.label(strings.format("total-agents", &[("count", &app.engine.agents.len())]));
 
         ui.separator();
 
@@ -692,20 +2750,92 @@ impl Diagnostics {
             .unwrap()
             .get_stats()
             .unwrap_or_default();
-        ui.label(format!("Graph nodes: {}", graph_stats.node_count));
-        ui.label(format!("Graph edges: {}", graph_stats.edge_count));
+        ui.label(strings.format("graph-nodes", &[("count", &graph_stats.node_count)]));
+        ui.label(strings.format("graph-edges", &[("count", &graph_stats.edge_count)]));
         for mode in route::MODES {
-            ui.label(format!(
-                "Terminal nodes ({}): {}",
-                mode, graph_stats.terminal_node_counts[mode],
+            ui.label(strings.format(
+                "terminal-nodes",
+                &[
+                    ("mode", mode),
+                    ("count", &graph_stats.terminal_node_counts[mode]),
+                ],
             ));
         }
+        ui.label(strings.format("parking-areas", &[("count", &graph_stats.parking_count)]));
+
+        ui.separator();
+
+        let routing_health = app.engine.routing_health();
+        ui.label(strings.format(
+            "failed-to-work-today",
+            &[("count", &routing_health.failed_to_work_today)],
+        ));
+        ui.label(strings.format(
+            "failed-to-work-total",
+            &[("count", &routing_health.failed_to_work_total)],
+        ));
+        ui.label(strings.format(
+            "teleported-home",
+            &[("count", &routing_health.teleported_home)],
+        ));
+        ui.label(strings.format(
+            "routes-aborted",
+            &[("count", &routing_health.routes_aborted)],
+        ));
+        ui.label(strings.format(
+            "routes-requeried",
+            &[("count", &routing_health.routes_requeried)],
+        ));
+        ui.label(strings.format(
+            "stuck-agents-recovered",
+            &[("count", &routing_health.stuck_agents_recovered)],
+        ));
+
+        ui.separator();
+
+        let route_latency = app.engine.route_latency();
+        let millis = |duration: Option<std::time::Duration>| {
+            duration.map_or_else(
+                || strings.get("not-available").to_string(),
+                |duration| format!("{:.1} ms", duration.as_secs_f64() * 1000.0),
+            )
+        };
+        ui.label(strings.format("route-queries", &[("count", &route_latency.queries.count)]));
+        ui.label(strings.format(
+            "route-query-p95",
+            &[("duration", &millis(route_latency.queries.percentile(0.95)))],
+        ));
+        ui.label(strings.format(
+            "route-deadline-misses",
+            &[
+                ("count", &route_latency.deadline_misses),
+                (
+                    "percent",
+                    &format!("{:.1}", route_latency.miss_rate().unwrap_or(0.0) * 100.0),
+                ),
+            ],
+        ));
+        ui.label(strings.format(
+            "blocked-on-route-queries",
+            &[("duration", &millis(Some(route_latency.blocked.total)))],
+        ));
+        let deadline = app.engine.state.config.scheduling.route_start_deadline;
+        match route_latency.suggested_deadline(app.engine.time_state.playback_rate) {
+            Some(suggested) => ui.label(strings.format(
+                "route-start-deadline-suggested",
+                &[("deadline", &deadline), ("suggested", &suggested)],
+            )),
+            None => ui.label(strings.format("route-start-deadline", &[("deadline", &deadline)])),
+        };
 
         ui.separator();
 
         match app.get_hovered_pos(ui) {
-            Some((x, y)) => ui.label(format!("Coords: {}, {}", x, y)),
-            None => ui.label("Coords: n/a"),
+            Some((x, y)) => ui.label(strings.format("cursor-coords", &[("x", &x), ("y", &y)])),
+            None => ui.label(strings.format(
+                "cursor-coords-none",
+                &[("none", &strings.get("not-available"))],
+            )),
         };
     }
 }
@@ -717,33 +2847,98 @@ pub(crate) struct PanState {
 
     pub min_scale: f32,
     pub max_scale: f32,
+
+    model_width: f32,
+    /// the region of the screen that the map is drawn in, which isn't known until the first frame
+    region: Option<viewport::Region>,
+    /// whether the user has panned or zoomed since the map was last fit to the region
+    interacted: bool,
 }
 
 impl PanState {
     fn new(engine: &engine::Engine) -> Self {
-        // TODO: pass through actual screen dimensions?
-        let (width, height) = (1920.0, 1080.0);
+        // NOTE: the real values are computed once we know the region to draw in; see set_region
+        Self {
+            scale: 1.0,
+            tx: 0.0,
+            ty: 0.0,
+            min_scale: 0.0,
+            max_scale: 100.0,
+            model_width: engine.state.qtree.width() as f32,
+            region: None,
+            interacted: false,
+        }
+    }
 
-        let min_dim = f32::min(width, height);
-        let model_width = engine.state.qtree.width() as f32;
+    fn view(&self) -> viewport::View {
+        viewport::View {
+            scale: self.scale as f64,
+            tx: self.tx as f64,
+            ty: self.ty as f64,
+        }
+    }
 
-        // TODO: this logic is duplicated in //viewers/editor
-        let scale = min_dim / model_width / 2.0;
-        let tx = width / 2.0 - model_width * scale / 2.0;
-        let ty = height / 2.0 - model_width * scale / 2.0;
+    fn set_view(&mut self, view: viewport::View) {
+        self.scale = view.scale as f32;
+        self.tx = view.tx as f32;
+        self.ty = view.ty as f32;
+    }
 
-        let min_scale = min_dim / model_width / 2.0;
-        let max_scale = 100.0;
+    /**
+     * Update the region of the screen that the map is drawn in; call this every frame. When the
+     * region changes, e.g. because the window was resized, the zoom limits are recomputed. The map
+     * is also re-fit to the new region, but only if the user hasn't moved the camera yet.
+     */
+    pub fn set_region(&mut self, rect: egui::Rect) {
+        if rect.width() <= 0.0 || rect.height() <= 0.0 {
+            return;
+        }
+        let region = viewport::Region::new(
+            rect.min.x as f64,
+            rect.min.y as f64,
+            rect.width() as f64,
+            rect.height() as f64,
+        );
+        if self.region == Some(region) {
+            return;
+        }
+        self.region = Some(region);
+        self.min_scale = region.min_scale(self.model_width as f64) as f32;
+
+        if !self.interacted {
+            self.set_view(viewport::View::fit(self.model_width as f64, &region));
+        } else if self.scale < self.min_scale {
+            self.set_view(
+                self.view()
+                    .zoom_about(self.min_scale as f64, region.center()),
+            );
+        }
+    }
 
-        Self {
-            scale,
-            tx,
-            ty,
-            min_scale,
-            max_scale,
+    /// Fit the whole map into the region it is drawn in, as when the app was started.
+    pub fn reset(&mut self) {
+        self.interacted = false;
+        if let Some(region) = self.region {
+            self.set_view(viewport::View::fit(self.model_width as f64, &region));
         }
     }
 
+    pub fn pan_by(&mut self, (dx, dy): (f32, f32)) {
+        self.tx += dx;
+        self.ty += dy;
+        self.interacted = true;
+    }
+
+    /// Zoom to the given scale (clamped to the zoom limits), centered on the given screen position.
+    pub fn zoom_about(&mut self, scale: f32, (mx, my): (f32, f32)) {
+        self.set_view(self.view().clamped_zoom_about(
+            scale as f64,
+            (self.min_scale as f64, self.max_scale as f64),
+            (mx as f64, my as f64),
+        ));
+        self.interacted = true;
+    }
+
     pub fn to_screen_uf(&self, (x, y): (u64, u64)) -> (f32, f32) {
         self.to_screen_ff((x as f32, y as f32))
     }
@@ -762,11 +2957,83 @@ impl PanState {
     }
 }
 
+/**
+ * Query results and selections that refer to addresses, handles, or agents of the current map, so
+ * that they can all be cleared at once when the engine is replaced.
+ */
+pub(crate) struct TransientState {
+    pub route_query: RouteQuery,
+    pub isochrone_query: IsochroneQuery,
+    pub congestion_analysis: CongestionAnalysis,
+    pub station_catchments: StationCatchmentAnalysis,
+    pub commute_flows: CommuteFlows,
+    pub calibration: Calibration,
+    pub agent_detail: AgentDetail,
+    pub segment_detail: Option<SegmentSelection>,
+    /// how long the segment detail panel closes segments for
+    pub closure_hours: u64,
+    /// the speed limit being entered in the segment detail panel, in the units that were
+    /// displayed when editing started
+    pub speed_limit_edit: Option<(SegmentSelection, crate::format::Units, f64)>,
+    pub planned_changes: PlannedChanges,
+}
+
+impl TransientState {
+    fn new() -> Self {
+        Self {
+            route_query: RouteQuery::new(),
+            isochrone_query: IsochroneQuery::new(),
+            congestion_analysis: CongestionAnalysis::new(),
+            station_catchments: StationCatchmentAnalysis::new(),
+            commute_flows: CommuteFlows::new(),
+            calibration: Calibration::new(),
+            agent_detail: AgentDetail::new(),
+            segment_detail: None,
+            closure_hours: 4,
+            speed_limit_edit: None,
+            planned_changes: PlannedChanges::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// The quadtree addresses that the queries and selections refer to.
+    pub fn addresses(&self) -> impl Iterator<Item = quadtree::Address> + '_ {
+        let isochrone = match &self.isochrone_query.state {
+            IsochroneQueryState::NotRoutable { focus, nearest, .. } => vec![*focus, *nearest],
+            IsochroneQueryState::Calculated { isochrone_map, .. } => {
+                vec![isochrone_map.isochrone.focus]
+            }
+            _ => vec![],
+        };
+        let agent_detail = match self.agent_detail {
+            AgentDetail::Query { address } => Some(address),
+            _ => None,
+        };
+        self.route_query
+            .start_address
+            .into_iter()
+            .chain(self.route_query.stop_address)
+            .chain(isochrone)
+            .chain(agent_detail)
+    }
+}
+
 pub(crate) struct RouteQuery {
     pub start_address: Option<quadtree::Address>,
     pub stop_address: Option<quadtree::Address>,
-    pub has_car: bool,
+    /// driving implies that the route starts with a car
+    pub allowed_modes: route::AllowedModes,
     pub current_routes: Vec<route::Route>,
+    /// how long each of the current routes takes across the traffic history
+    pub current_reliability: Vec<route::ReliabilityStats>,
+    /// the distance and time along each of the current routes, see Route::time_profile
+    pub current_profiles: Vec<Vec<route::ProfileSample>>,
+    /// whether to break down the cost of each leg of the queried route
+    pub debug: bool,
+    pub current_debug: Option<route::RouteDebug>,
 }
 
 impl RouteQuery {
@@ -774,8 +3041,12 @@ impl RouteQuery {
         Self {
             start_address: None,
             stop_address: None,
-            has_car: true,
+            allowed_modes: route::AllowedModes::ALL,
             current_routes: Vec::new(),
+            current_reliability: Vec::new(),
+            current_profiles: Vec::new(),
+            debug: false,
+            current_debug: None,
         }
     }
 }
@@ -787,6 +3058,11 @@ pub(crate) struct IsochroneQuery {
     pub(crate) max_travel_time: f64,
     /// time difference between steps in the displayed map, in minutes
     pub(crate) quantization_step: f64,
+    /// whether to also calculate the isochrone for someone with reduced mobility
+    pub(crate) accessibility: bool,
+    pub(crate) reduced_mobility: route::MobilityProfile,
+    /// whether to display the reduced mobility isochrone instead of the standard one
+    pub(crate) show_reduced: bool,
 }
 
 impl IsochroneQuery {
@@ -796,6 +3072,179 @@ impl IsochroneQuery {
             mode: route::Mode::Walking,
             max_travel_time: 2.0 * 60.0, // two hours, in minutes
             quantization_step: 20.0,
+            accessibility: false,
+            reduced_mobility: route::MobilityProfile {
+                walking_speed: 0.6,
+                transfer_penalty: 120.0,
+            },
+            show_reduced: false,
+        }
+    }
+
+    /// The isochrone map that is currently drawn, if any.
+    pub fn displayed_map(&self) -> Option<&route::IsochroneMap> {
+        match &self.state {
+            IsochroneQueryState::Calculated {
+                reduced: Some(reduced),
+                ..
+            } if self.show_reduced => Some(reduced.as_ref()),
+            IsochroneQueryState::Calculated { isochrone_map, .. } => Some(isochrone_map),
+            _ => None,
+        }
+    }
+
+    /**
+     * The travel time at the far end of the color scale, in minutes. This is clamped to the
+     * farthest reachable point, rounded up to a whole step, so that there are no empty steps at
+     * the top of the scale.
+     */
+    pub fn display_max_travel_time(&self, isochrone_map: &route::IsochroneMap) -> f64 {
+        let step = self.quantization_step.max(1.0);
+        match isochrone_map.isochrone.max_reachable_travel_time() {
+            Some(reachable) => {
+                let reachable = (reachable / 60.0 / step).ceil().max(1.0) * step;
+                self.max_travel_time.min(reachable)
+            }
+            None => self.max_travel_time,
+        }
+    }
+}
+
+pub(crate) struct BenchmarkControls {
+    /// simulated hours to run for
+    pub(crate) hours: u64,
+    pub(crate) running: Option<engine::Benchmark>,
+    /// the report from the most recent run
+    pub(crate) report: Option<engine::BenchmarkReport>,
+}
+
+impl BenchmarkControls {
+    fn new() -> Self {
+        Self {
+            hours: 24,
+            running: None,
+            report: None,
+        }
+    }
+}
+
+/// A new path for a metro line that is being picked on the map, one junction at a time.
+pub(crate) struct PathEdit {
+    pub(crate) metro_line: metro::MetroLineHandle,
+    pub(crate) junctions: Vec<network::JunctionHandle>,
+    /// the orphan stations near the path, and the junctions that they were found for
+    adoptable: Option<(Vec<network::JunctionHandle>, Vec<metro::AdoptableStation>)>,
+}
+
+impl PathEdit {
+    pub(crate) fn new(
+        metro_line: metro::MetroLineHandle,
+        junctions: Vec<network::JunctionHandle>,
+    ) -> Self {
+        Self {
+            metro_line,
+            junctions,
+            adoptable: None,
+        }
+    }
+
+    /**
+     * The orphan stations near the path picked so far. Finding orphan stations visits every tile,
+     * so they are only found again when the path changes.
+     */
+    fn adoptable_stations(&mut self, engine: &engine::Engine) -> &[metro::AdoptableStation] {
+        let current =
+            matches!(&self.adoptable, Some((junctions, _)) if *junctions == self.junctions);
+        if !current {
+            let adoptable = self
+                .segments(&engine.state.railways)
+                .ok()
+                .and_then(|segments| {
+                    engine
+                        .adoptable_stations(&segments, engine::ORPHAN_STATION_ADOPTION_RADIUS)
+                        .ok()
+                })
+                .unwrap_or_default();
+            self.adoptable = Some((self.junctions.clone(), adoptable));
+        }
+        &self.adoptable.as_ref().unwrap().1
+    }
+
+    /**
+     * Make an orphan station a stop on the segment it is next to, and add the station's new
+     * junction to the path between the ends of the segment.
+     */
+    fn adopt(
+        &mut self,
+        engine: &mut engine::Engine,
+        adoptable: &metro::AdoptableStation,
+    ) -> Result<(), String> {
+        let segment = engine
+            .state
+            .railways
+            .try_segment(adoptable.segment)
+            .ok_or_else(|| {
+                format!(
+                    "Railway segment #{} no longer exists",
+                    adoptable.segment.inner()
+                )
+            })?;
+        let ends = (segment.start_junction(), segment.end_junction());
+        let junction = engine
+            .adopt_orphan_station(adoptable.segment, adoptable.orphan.station.address)
+            .map_err(|e| e.to_string())?;
+        if let Some(i) = self
+            .junctions
+            .windows(2)
+            .position(|pair| (pair[0], pair[1]) == ends || (pair[1], pair[0]) == ends)
+        {
+            self.junctions.insert(i + 1, junction);
+        }
+        self.adoptable = None;
+        Ok(())
+    }
+
+    /// The railway segments connecting each pair of consecutive junctions, in either direction.
+    fn segments(&self, railways: &metro::Railways) -> Result<Vec<network::SegmentHandle>, String> {
+        if self.junctions.len() < 2 {
+            return Err("Pick at least two junctions".to_string());
+        }
+        self.junctions
+            .windows(2)
+            .map(|pair| {
+                railways
+                    .segments()
+                    .values()
+                    .find(|segment| {
+                        segment.change_state.is_staged_active()
+                            && ((segment.start_junction() == pair[0]
+                                && segment.end_junction() == pair[1])
+                                || (segment.start_junction() == pair[1]
+                                    && segment.end_junction() == pair[0]))
+                    })
+                    .map(|segment| segment.id)
+                    .ok_or_else(|| {
+                        format!(
+                            "No railway segment between junctions #{} and #{}",
+                            pair[0].inner(),
+                            pair[1].inner()
+                        )
+                    })
+            })
+            .collect()
+    }
+}
+
+pub(crate) struct PlannedChanges {
+    pub(crate) error: Option<String>,
+    pub(crate) path_edit: Option<PathEdit>,
+}
+
+impl PlannedChanges {
+    fn new() -> Self {
+        Self {
+            error: None,
+            path_edit: None,
         }
     }
 }
@@ -808,8 +3257,20 @@ pub(crate) enum IsochroneQueryState {
     /// calculation in progress (might be slow)
     #[allow(dead_code)]
     Calculating,
+    /// the selected tile can't be routed from, e.g. because it is in the water
+    NotRoutable {
+        focus: quadtree::Address,
+        /// the nearest point that can be routed from instead
+        nearest: quadtree::Address,
+        /// in meters
+        distance: f64,
+    },
     /// calculation finished, isochrone visible
-    Calculated { isochrone_map: route::IsochroneMap },
+    Calculated {
+        isochrone_map: route::IsochroneMap,
+        /// the same isochrone for the reduced mobility profile, if it is being compared
+        reduced: Option<Box<route::IsochroneMap>>,
+    },
 }
 
 #[derive(Debug, enum_iterator::IntoEnumIterator, PartialEq, Copy, Clone)]
@@ -821,12 +3282,21 @@ pub(crate) enum CongestionType {
 }
 
 impl CongestionType {
-    fn label(&self) -> &'static str {
+    /// The key of the name of this type; see Strings.
+    fn key(&self) -> &'static str {
+        match self {
+            Self::HighwaySegments => "congestion-highways",
+            Self::MetroSegments => "congestion-metros",
+            Self::LocalRoads => "congestion-local-roads",
+            Self::Parking => "congestion-parking",
+        }
+    }
+
+    /// The key of what each item contributing to the statistics is; see Strings.
+    fn items_key(&self) -> &'static str {
         match self {
-            Self::HighwaySegments => "Highways",
-            Self::MetroSegments => "Metros",
-            Self::LocalRoads => "Local roads",
-            Self::Parking => "Parking",
+            Self::HighwaySegments | Self::MetroSegments => "congestion-segments",
+            Self::LocalRoads | Self::Parking => "congestion-zones",
         }
     }
 }
@@ -839,20 +3309,21 @@ pub(crate) enum CongestionHistoricalQuantity {
 }
 
 impl CongestionHistoricalQuantity {
-    fn label(&self) -> &'static str {
+    /// The key of the name of this quantity; see Strings.
+    fn key(&self) -> &'static str {
         match self {
-            Self::Sum => "Sum",
-            Self::Mean => "Mean",
-            Self::Rms => "RMS",
+            Self::Sum => "quantity-sum",
+            Self::Mean => "quantity-mean",
+            Self::Rms => "quantity-rms",
         }
     }
 
-    fn get<K>(&self, congestion_stats: route::CongestionIterator<'_, K>) -> f32 {
+    fn get<K>(&self, congestion_stats: route::CongestionIterator<'_, K>) -> route::Stats {
         use route::CongestionStats;
         match self {
-            Self::Sum => congestion_stats.sum() as f32,
-            Self::Mean => congestion_stats.mean() as f32,
-            Self::Rms => congestion_stats.rms() as f32,
+            Self::Sum => congestion_stats.sum(),
+            Self::Mean => congestion_stats.mean(),
+            Self::Rms => congestion_stats.rms(),
         }
     }
 }
@@ -873,6 +3344,96 @@ impl CongestionAnalysis {
     }
 }
 
+pub(crate) struct StationCatchmentAnalysis {
+    /// color each station by the number of people within walking distance
+    pub show: bool,
+    pub max_walk_minutes: f64,
+    pub catchments: Option<std::sync::Arc<engine::StationCatchments>>,
+}
+
+impl StationCatchmentAnalysis {
+    fn new() -> Self {
+        Self {
+            show: false,
+            max_walk_minutes: 10.0,
+            catchments: None,
+        }
+    }
+}
+
+pub(crate) struct CommuteFlows {
+    /// draw desire lines between the zones with the most commutes yesterday
+    pub show: bool,
+    /// how many of the largest flows to draw
+    pub top_n: usize,
+}
+
+impl CommuteFlows {
+    fn new() -> Self {
+        Self {
+            show: false,
+            top_n: 50,
+        }
+    }
+}
+
+pub(crate) struct Calibration {
+    pub observations_path: String,
+    /// written by the importer, needed for observations given by OSM way; empty if not used
+    pub id_map_path: String,
+    /// color observed highway segments by how busy they are in the simulation relative to reality
+    pub show: bool,
+    pub report: Option<engine::CalibrationReport>,
+    pub error: Option<String>,
+}
+
+impl Calibration {
+    fn new() -> Self {
+        Self {
+            observations_path: "observations.json".to_string(),
+            id_map_path: String::new(),
+            show: false,
+            report: None,
+            error: None,
+        }
+    }
+}
+
+pub(crate) struct Timeline {
+    /// how many upcoming triggers to show
+    pub count: usize,
+}
+
+impl Timeline {
+    fn new() -> Self {
+        Self { count: 20 }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum SegmentSelection {
+    Highway(network::SegmentHandle),
+    Railway(network::SegmentHandle),
+}
+
+impl SegmentSelection {
+    fn network_segment(&self) -> engine::NetworkSegment {
+        match self {
+            Self::Highway(id) => engine::NetworkSegment::Highway(*id),
+            Self::Railway(id) => engine::NetworkSegment::Railway(*id),
+        }
+    }
+}
+
+impl std::fmt::Display for SegmentSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Highway(id) => write!(f, "highway segment {}", id.inner()),
+            Self::Railway(id) => write!(f, "railway segment {}", id.inner()),
+        }
+    }
+}
+
 pub(crate) enum AgentDetail {
     /// no selection
     Empty,
@@ -890,9 +3451,419 @@ impl AgentDetail {
     }
 }
 
-fn format_duration<'a>(
-    duration: f32,
-) -> Option<chrono::format::DelayedFormat<chrono::format::strftime::StrftimeItems<'a>>> {
-    chrono::NaiveTime::from_num_seconds_from_midnight_opt(duration as u32, 0)
-        .map(|datetime| datetime.format("%H:%M:%S"))
+fn alert_color(severity: engine::Severity) -> egui::Color32 {
+    match severity {
+        engine::Severity::Warning => egui::Color32::YELLOW,
+        engine::Severity::Critical => egui::Color32::RED,
+    }
+}
+
+fn describe_staged_change(
+    change: &engine::StagedChange,
+    strings: &crate::strings::Strings,
+) -> String {
+    use engine::StagedChange;
+
+    match change {
+        StagedChange::AddHighwaySegment(id) => {
+            strings.format("stage-add-highway-segment", &[("id", &id.inner())])
+        }
+        StagedChange::RemoveHighwaySegment(id) => {
+            strings.format("stage-remove-highway-segment", &[("id", &id.inner())])
+        }
+        StagedChange::AddRailwaySegment(id) => {
+            strings.format("stage-add-railway-segment", &[("id", &id.inner())])
+        }
+        StagedChange::RemoveRailwaySegment(id) => {
+            strings.format("stage-remove-railway-segment", &[("id", &id.inner())])
+        }
+        StagedChange::AddMetroLine(metro_line) => strings.format(
+            "stage-add-metro-line",
+            &[
+                ("line", &metro_line.data.name),
+                ("count", &metro_line.segments.len()),
+            ],
+        ),
+        StagedChange::RerouteMetroLine(reroute) => strings.format(
+            "stage-reroute-metro-line",
+            &[
+                ("id", &reroute.metro_line.inner()),
+                ("count", &reroute.segments.len()),
+            ],
+        ),
+    }
+}
+
+/// The key of the name of the edge in the route legs table; see Strings.
+fn edge_key(edge: &route::Edge) -> &'static str {
+    match edge {
+        route::Edge::MetroSegment { .. } => "leg-metro",
+        route::Edge::MetroEmbark { .. } => "leg-board-metro",
+        route::Edge::MetroDisembark { .. } => "leg-leave-metro",
+        route::Edge::Highway { .. } => "leg-highway",
+        route::Edge::HighwayRamp { .. } => "leg-ramp",
+        route::Edge::ModeSegment {
+            mode: route::Mode::Walking,
+            ..
+        } => "leg-walk",
+        route::Edge::ModeSegment { .. } => "leg-local-roads",
+        route::Edge::ModeTransition { .. } => "leg-change-mode",
+        _ => "leg-other",
+    }
+}
+
+/// A table with the cost components of each leg of a route, one row per edge.
+fn draw_route_legs(
+    ui: &mut egui::Ui,
+    id: &str,
+    route: &route::Route,
+    components: &[route::CostComponents],
+    highlight: &[usize],
+    strings: &crate::strings::Strings,
+) {
+    egui::Grid::new(id).striped(true).show(ui, |ui| {
+        ui.label(strings.get("leg"));
+        ui.label(strings.get("leg-base"));
+        ui.label(strings.get("leg-congestion"));
+        ui.label(strings.get("leg-parking"));
+        ui.label(strings.get("leg-junctions"));
+        ui.label(strings.get("leg-total"));
+        ui.end_row();
+
+        for (i, (edge, components)) in route.edges.iter().zip(components).enumerate() {
+            let name = strings.get(edge_key(edge));
+            if highlight.contains(&i) {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    strings.format("leg-diverges", &[("leg", &name)]),
+                );
+            } else {
+                ui.label(name);
+            }
+            ui.label(format!("{:.0}", components.base_time));
+            if components.congestion_factor > 1.0 {
+                ui.label(format!(
+                    "x{:.2} (+{:.0} s)",
+                    components.congestion_factor,
+                    components.congestion_delay()
+                ));
+            } else {
+                ui.label("");
+            }
+            ui.label(format!("{:.0}", components.parking_search));
+            ui.label(format!("{:.0}", components.intersection_delay));
+            ui.label(format!("{:.0}", components.total()));
+            ui.end_row();
+        }
+    });
+}
+
+fn draw_route_debug(
+    ui: &mut egui::Ui,
+    debug: &route::RouteDebug,
+    strings: &crate::strings::Strings,
+) {
+    let total = |components: &[route::CostComponents]| -> f64 {
+        components.iter().map(|components| components.total()).sum()
+    };
+
+    match debug.traffic_time {
+        Some(_) => ui.label(strings.get("weighted-predicted-traffic")),
+        None => ui.label(strings.get("weighted-free-flow")),
+    };
+
+    egui::CollapsingHeader::new(strings.format(
+        "chosen-route",
+        &[("seconds", &format!("{:.0}", total(&debug.components)))],
+    ))
+    .id_source("route_debug_chosen")
+    .show(ui, |ui| {
+        let highlight: Vec<_> = debug.divergences.iter().map(|d| d.edge).collect();
+        draw_route_legs(
+            ui,
+            "route_debug_chosen_legs",
+            &debug.route,
+            &debug.components,
+            &highlight,
+            strings,
+        );
+    });
+
+    match &debug.free_flow_route {
+        Some(free_flow_route) => {
+            egui::CollapsingHeader::new(strings.format(
+                "free-flow-route",
+                &[(
+                    "seconds",
+                    &format!("{:.0}", total(&debug.free_flow_components)),
+                )],
+            ))
+            .id_source("route_debug_free_flow")
+            .show(ui, |ui| {
+                let highlight: Vec<_> = debug.divergences.iter().map(|d| d.other_edge).collect();
+                draw_route_legs(
+                    ui,
+                    "route_debug_free_flow_legs",
+                    free_flow_route,
+                    &debug.free_flow_components,
+                    &highlight,
+                    strings,
+                );
+            });
+        }
+        None => {
+            ui.label(strings.get("same-as-free-flow"));
+        }
+    }
+}
+
+/// the number of bars in the speed chart of a route
+const SPEED_CHART_BUCKETS: usize = 48;
+
+/**
+ * The average speed over each of `buckets` equal stretches of a route's time profile, in m/s, along
+ * with the length of each stretch in meters. Time spent without moving, e.g. waiting for a metro,
+ * counts against the stretch that it happens in.
+ */
+fn speed_buckets(profile: &[route::ProfileSample], buckets: usize) -> (Vec<f64>, f64) {
+    let total = profile.last().map_or(0.0, |sample| sample.dist);
+    if total <= 0.0 || buckets == 0 {
+        return (vec![], 0.0);
+    }
+    let width = total / buckets as f64;
+    let bucket = |dist: f64| ((dist / width) as usize).min(buckets - 1);
+
+    let mut times = vec![0.0; buckets];
+    for pair in profile.windows(2) {
+        let (prev, next) = (&pair[0], &pair[1]);
+        let dt = next.time - prev.time;
+        let dd = next.dist - prev.dist;
+        if dd <= 0.0 {
+            times[bucket(prev.dist)] += dt;
+            continue;
+        }
+        // split the time between the stretches that the interval overlaps
+        for (i, time) in times
+            .iter_mut()
+            .enumerate()
+            .take(bucket(next.dist) + 1)
+            .skip(bucket(prev.dist))
+        {
+            let (start, end) = (i as f64 * width, (i + 1) as f64 * width);
+            let overlap = next.dist.min(end) - prev.dist.max(start);
+            if overlap > 0.0 {
+                *time += dt * overlap / dd;
+            }
+        }
+    }
+
+    let speeds = times
+        .iter()
+        .map(|time| if *time > 0.0 { width / time } else { 0.0 })
+        .collect();
+    (speeds, width)
+}
+
+/// A chart of the speed along a route, with distance along the route going to the right.
+fn speed_chart(
+    profile: &[route::ProfileSample],
+    units: crate::format::Units,
+) -> crate::chart::Chart<f32> {
+    let (speeds, width) = speed_buckets(profile, SPEED_CHART_BUCKETS);
+    let mut chart = crate::chart::Chart::new(
+        speeds
+            .iter()
+            .map(|speed| units.speed_value(*speed) as f32)
+            .collect(),
+    );
+    chart.with_labels(move |i, speed| {
+        format!(
+            "{:.1}–{:.1} {}: {:.0} {}",
+            units.distance_value(i as f64 * width),
+            units.distance_value((i + 1) as f64 * width),
+            units.distance_unit(),
+            speed,
+            units.speed_unit()
+        )
+    });
+    chart
+}
+
+/// The time profile of a route as CSV, with the speed since the previous row.
+fn profile_csv(profile: &[route::ProfileSample]) -> String {
+    use std::fmt::Write;
+
+    let mut csv = String::from("distance_m,time_s,speed_m_s,mode\n");
+    let mut prev = None;
+    for sample in profile {
+        let speed = prev
+            .and_then(|prev| sample.speed_since(prev))
+            .map_or_else(String::new, |speed| format!("{:.3}", speed));
+        let mode = sample
+            .mode
+            .map_or_else(|| String::from("metro"), |mode| mode.to_string());
+        writeln!(
+            csv,
+            "{:.3},{:.3},{},{}",
+            sample.dist, sample.time, speed, mode
+        )
+        .unwrap();
+        prev = Some(sample);
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::*;
+
+    fn engine(max_depth: u32) -> engine::Engine {
+        engine::Engine::new(state::Config {
+            max_depth,
+            people_per_sim: 1.0,
+            min_tile_size: 100,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        })
+    }
+
+    /// Change every field away from its default, referring to things in the given engine.
+    fn select_everything(transient: &mut TransientState, engine: &mut engine::Engine) {
+        let address = quadtree::Address::from_xy(0, 0, engine.state.config.max_depth);
+        let railways = &mut engine.state.railways;
+        let start = railways.add_junction((0.0, 0.0), metro::RailwayJunction::new(None));
+        let end = railways.add_junction((1.0, 1.0), metro::RailwayJunction::new(None));
+        let segment = railways.add_segment(metro::RailwaySegment::new(None), start, end, None);
+
+        transient.route_query.start_address = Some(address);
+        transient.route_query.stop_address = Some(address);
+        transient.route_query.allowed_modes = route::AllowedModes::transit_only();
+        transient.route_query.debug = true;
+        transient.isochrone_query.state = IsochroneQueryState::NotRoutable {
+            focus: address,
+            nearest: address,
+            distance: 1.0,
+        };
+        transient.isochrone_query.max_travel_time = 1.0;
+        transient.congestion_analysis.filter_visible = true;
+        transient.station_catchments.show = true;
+        transient.commute_flows.show = true;
+        transient.commute_flows.top_n = 1;
+        transient.calibration.show = true;
+        transient.calibration.error = Some("error".to_string());
+        transient.agent_detail = AgentDetail::Query { address };
+        transient.segment_detail = Some(SegmentSelection::Railway(segment));
+        transient.closure_hours = 24;
+        transient.speed_limit_edit = Some((
+            SegmentSelection::Railway(segment),
+            crate::format::Units::Imperial,
+            30.0,
+        ));
+        transient.planned_changes.error = Some("error".to_string());
+    }
+
+    fn assert_reset(transient: &TransientState) {
+        // destructure so that new fields have to be checked here too
+        let TransientState {
+            route_query,
+            isochrone_query,
+            congestion_analysis,
+            station_catchments,
+            commute_flows,
+            calibration,
+            agent_detail,
+            segment_detail,
+            closure_hours,
+            speed_limit_edit,
+            planned_changes,
+        } = transient;
+
+        let default = TransientState::new();
+        assert_eq!(route_query.start_address, None);
+        assert_eq!(route_query.stop_address, None);
+        assert_eq!(route_query.allowed_modes, default.route_query.allowed_modes);
+        assert!(route_query.current_routes.is_empty());
+        assert!(route_query.current_reliability.is_empty());
+        assert!(route_query.current_profiles.is_empty());
+        assert!(!route_query.debug);
+        assert!(route_query.current_debug.is_none());
+        assert!(matches!(isochrone_query.state, IsochroneQueryState::Empty));
+        assert_eq!(
+            isochrone_query.max_travel_time,
+            default.isochrone_query.max_travel_time
+        );
+        assert!(!congestion_analysis.filter_visible);
+        assert!(!station_catchments.show);
+        assert!(station_catchments.catchments.is_none());
+        assert!(!commute_flows.show);
+        assert_eq!(commute_flows.top_n, default.commute_flows.top_n);
+        assert!(!calibration.show);
+        assert!(calibration.report.is_none());
+        assert!(calibration.error.is_none());
+        assert!(matches!(agent_detail, AgentDetail::Empty));
+        assert_eq!(*segment_detail, None);
+        assert_eq!(*closure_hours, default.closure_hours);
+        assert_eq!(*speed_limit_edit, None);
+        assert!(planned_changes.error.is_none());
+        assert!(planned_changes.path_edit.is_none());
+        assert_eq!(transient.addresses().count(), 0);
+    }
+
+    #[test]
+    fn transient_state_reset() {
+        let mut transient = TransientState::new();
+        assert_reset(&transient);
+
+        select_everything(&mut transient, &mut engine(3));
+        assert_eq!(transient.addresses().count(), 5);
+        transient.reset();
+        assert_reset(&transient);
+    }
+
+    #[test]
+    fn replace_engine() {
+        let mut app = App::new(engine(3));
+        select_everything(&mut app.transient, &mut app.engine);
+        app.overlay.blurred_field = Some("missing".to_string());
+
+        app.replace_engine(engine(5));
+        assert_reset(&app.transient);
+        assert_eq!(app.overlay.blurred_field, None);
+        assert_eq!(app.pan.model_width, app.engine.state.qtree.width() as f32);
+    }
+
+    #[test]
+    fn route_profile_speeds() {
+        let sample = |dist, time, mode| route::ProfileSample { dist, time, mode };
+        // walk 100 meters, wait 100 seconds for a train, then ride 300 meters
+        let profile = [
+            sample(0.0, 0.0, Some(route::Mode::Walking)),
+            sample(100.0, 100.0, Some(route::Mode::Walking)),
+            sample(100.0, 200.0, None),
+            sample(400.0, 230.0, None),
+        ];
+
+        let (speeds, width) = speed_buckets(&profile, 4);
+        assert_eq!(width, 100.0);
+        // the wait happens at the start of the second stretch, so it slows that one down
+        assert_eq!(speeds, vec![1.0, 100.0 / 110.0, 10.0, 10.0]);
+        assert_eq!(speed_buckets(&profile[..1], 4), (vec![], 0.0));
+
+        assert_eq!(
+            profile_csv(&profile),
+            "distance_m,time_s,speed_m_s,mode\n\
+             0.000,0.000,,walking\n\
+             100.000,100.000,1.000,walking\n\
+             100.000,200.000,0.000,metro\n\
+             400.000,230.000,10.000,metro\n"
+        );
+    }
 }
@@ -1,31 +1,99 @@
-use anyhow::Result;
-
+use engine::Error;
 use state::{BranchState, LeafState};
 
 use crate::app::App;
 
+type Result<T> = std::result::Result<T, Error>;
+
+/// how close (in pixels) a click needs to be to a segment to select it
+const SEGMENT_PICK_THRESHOLD: f32 = 8.0;
+
+/// station names are shown once the station circle is at least this big, in pixels
+const STATION_LABEL_MIN_RADIUS: f32 = 8.0;
+/// highway refs and metro line names are shown at this zoom and closer
+const NETWORK_LABEL_MIN_SCALE: f32 = 0.5;
+/// names of points of interest are shown once their tile is at least this wide, in pixels
+const POI_LABEL_MIN_WIDTH: f32 = 64.0;
+/// the size of the cells used to declutter labels, in pixels
+const LABEL_CELL_SIZE: f32 = 64.0;
+const LABEL_FONT_SIZE: f32 = 12.0;
+
 impl App {
     pub(crate) fn get_bounding_box(&self, ui: &egui::Ui) -> quadtree::Rect {
         let max_rect = ui.clip_rect();
-        let (x1, y1) = self.pan.to_model_fu(max_rect.min.into());
-        let (x2, y2) = self.pan.to_model_fu(max_rect.max.into());
-
-        quadtree::Rect::corners(x1, y1, x2, y2)
+        let (x1, y1) = self.pan.to_model_ff(max_rect.min.into());
+        let (x2, y2) = self.pan.to_model_ff(max_rect.max.into());
+
+        // NOTE: Rect is half-open, so round the max corner up; truncating it would drop tiles that
+        // are only partially visible along the right and bottom edges of the viewport
+        quadtree::Rect::corners(
+            x1.floor() as u64,
+            y1.floor() as u64,
+            x2.ceil() as u64,
+            y2.ceil() as u64,
+        )
     }
 
     pub(crate) fn draw_content(&mut self, ui: &mut egui::Ui) -> Result<()> {
+        let _span = tracing::debug_span!("draw_content").entered();
+
         let (response, painter) =
             ui.allocate_painter(ui.available_size(), egui::Sense::click_and_drag());
+        // NOTE: this is the region next to the side panel, in points, so it accounts for both the
+        // panel width and the display scale factor
+        self.pan.set_region(response.rect);
+        self.draw_route_estimate(&response)?;
+        self.diagnostics.cursor = response
+            .hover_pos()
+            .map(|pos| self.pan.to_model_ff(pos.into()));
         self.handle_input(response);
 
         let bounding_box = self.get_bounding_box(ui);
 
-        let mut qtree_visitor = DrawQtreeVisitor::new(self, &painter);
-        self.engine
-            .state
-            .qtree
-            .visit_rect(&mut qtree_visitor, &bounding_box)?;
+        let time = self
+            .replay_time()
+            .unwrap_or(self.engine.time_state.current_time);
+        let time_of_day = {
+            use chrono::Timelike;
+            self.engine
+                .time_state
+                .date_time(time)
+                .num_seconds_from_midnight()
+        };
+        self.theme_state
+            .update(time_of_day, self.display_options.time_of_day);
+        let theme = self.theme().clone();
+        painter.rect_filled(
+            painter.clip_rect(),
+            egui::Rounding::none(),
+            theme.background,
+        );
+
+        let max_depth = self.engine.state.config.max_depth;
+        debug_assert!(
+            self.transient.addresses().all(|address| {
+                address.max_depth() == max_depth && address.depth() <= max_depth as usize
+            }),
+            "queries refer to a different map; see App::reset_transient_state"
+        );
 
+        self.overlay
+            .update(&self.engine, self.display_options.overlay_depth);
+        if self.transient.station_catchments.show {
+            self.update_station_catchments();
+        }
+
+        let mut qtree_visitor = DrawQtreeVisitor::new(self, &painter);
+        let max_depth = self.max_visible_depth();
+        tracing::debug_span!("draw_tiles").in_scope(|| {
+            self.engine.state.qtree.visit_rect_to_depth(
+                &mut qtree_visitor,
+                &bounding_box,
+                max_depth,
+            )
+        })?;
+
+        let pois = std::mem::take(&mut qtree_visitor.pois);
         self.diagnostics.tiles = qtree_visitor.visited;
 
         // 5 pixel resolution
@@ -34,8 +102,13 @@ impl App {
             0.2,
         );
 
+        // NOTE: borrowing only these fields lets us update the diagnostics below
+        #[cfg(feature = "replay")]
+        let world_state = self.replay.world_state(&self.engine);
+        #[cfg(not(feature = "replay"))]
+        let world_state = &self.engine.world_state;
         let traffic = match self.overlay.field {
-            Some(crate::field_overlay::FieldType::Traffic) => Some(&self.engine.world_state),
+            Some(crate::field_overlay::FieldType::Traffic) => Some(world_state),
             _ => None,
         };
 
@@ -44,8 +117,15 @@ impl App {
         self.diagnostics.agents = 0;
 
         for highway_segment in self.engine.state.highways.segments().values() {
+            // staged changes are drawn separately below
+            if highway_segment.change_state.is_staged_change() {
+                continue;
+            }
             if bounding_box.intersects(&highway_segment.bounds) {
-                let mut spline_visitor = DrawSplineVisitor::new(self, &painter, traffic);
+                let mut spline_visitor = match highway_segment.change_state.closed_until() {
+                    Some(_) => DrawSplineVisitor::planned(self, &painter, theme.closed),
+                    None => DrawSplineVisitor::new(self, &painter, traffic),
+                };
                 highway_segment.visit_spline(&mut spline_visitor, spline_scale, &bounding_box)?;
                 self.diagnostics.highway_vertices += spline_visitor.visited;
             }
@@ -62,7 +142,7 @@ impl App {
                         painter.circle(
                             pos,
                             self.scale_point(4.0, 2.0),
-                            egui::Color32::from_gray(255),
+                            theme.foreground,
                             egui::Stroke::none(),
                         );
                     }
@@ -78,7 +158,7 @@ impl App {
                     painter.circle(
                         pos,
                         self.scale_point(4.0, 4.0),
-                        egui::Color32::from_gray(255),
+                        theme.foreground,
                         egui::Stroke::none(),
                     );
                 }
@@ -86,6 +166,9 @@ impl App {
         }
 
         for railway_segment in self.engine.state.railways.segments().values() {
+            if railway_segment.change_state.is_staged_change() {
+                continue;
+            }
             if bounding_box.intersects(&railway_segment.bounds) {
                 let metro_lines = self
                     .engine
@@ -93,7 +176,10 @@ impl App {
                     .metros
                     .railway_segment_metro_lines(railway_segment.id);
                 if self.display_options.show_all_railways || !metro_lines.is_empty() {
-                    let mut spline_visitor = DrawSplineVisitor::new(self, &painter, traffic);
+                    let mut spline_visitor = match railway_segment.change_state.closed_until() {
+                        Some(_) => DrawSplineVisitor::planned(self, &painter, theme.closed),
+                        None => DrawSplineVisitor::new(self, &painter, traffic),
+                    };
                     railway_segment.visit_spline(
                         &mut spline_visitor,
                         spline_scale,
@@ -104,8 +190,172 @@ impl App {
             }
         }
 
+        if self.transient.station_catchments.show && self.pan.scale >= 2.0 {
+            if let Some(catchments) = self.transient.station_catchments.catchments.clone() {
+                let max_people = catchments.max_people() as f32;
+                for catchment in &catchments.stations {
+                    let (x, y) = catchment.station.address.to_xy_f64();
+                    if bounding_box.contains(x as u64, y as u64) {
+                        let pos = egui::Pos2::from(self.pan.to_screen_ff((x as f32, y as f32)));
+                        let scale = palette::scale(catchment.people as f32, 0.0, max_people);
+                        painter.circle(
+                            pos,
+                            self.scale_point(2.0, 8.0),
+                            crate::field_overlay::palette_color(
+                                self.display_options.palette,
+                                palette::HueRamp::Field,
+                                scale,
+                                1.0,
+                            ),
+                            (1.0, theme.foreground),
+                        );
+                    }
+                }
+            }
+        }
+
+        if self.transient.commute_flows.show {
+            self.draw_desire_lines(&painter, &bounding_box);
+        }
+
+        if let (true, Some(report)) = (
+            self.transient.calibration.show,
+            &self.transient.calibration.report,
+        ) {
+            for calibration in &report.segments {
+                let segment = match self
+                    .engine
+                    .state
+                    .highways
+                    .segments()
+                    .get(&calibration.segment)
+                {
+                    Some(segment) if bounding_box.intersects(&segment.bounds) => segment,
+                    _ => continue,
+                };
+                let points = segment
+                    .keys()
+                    .iter()
+                    .map(|key| {
+                        egui::Pos2::from(self.pan.to_screen_ff((key.x as f32, key.y as f32)))
+                    })
+                    .collect();
+                painter.add(egui::Shape::line(
+                    points,
+                    (5.0, theme.calibration(calibration.ratio())),
+                ));
+            }
+        }
+
+        if let Some(handle) = self.engine.open_change_set() {
+            let preview = self.engine.preview(handle)?;
+            for (highway_segment, kind) in &preview.highway_segments {
+                if bounding_box.intersects(&highway_segment.bounds) {
+                    let mut spline_visitor =
+                        DrawSplineVisitor::planned(self, &painter, theme.planned(*kind));
+                    highway_segment.visit_spline(
+                        &mut spline_visitor,
+                        spline_scale,
+                        &bounding_box,
+                    )?;
+                }
+            }
+            for (railway_segment, kind) in &preview.railway_segments {
+                if bounding_box.intersects(&railway_segment.bounds) {
+                    let mut spline_visitor =
+                        DrawSplineVisitor::planned(self, &painter, theme.planned(*kind));
+                    railway_segment.visit_spline(
+                        &mut spline_visitor,
+                        spline_scale,
+                        &bounding_box,
+                    )?;
+                }
+            }
+            for metro_line in &preview.metro_lines {
+                let color = metro_line.data.color;
+                let color = egui::Color32::from_rgb(color.red, color.green, color.blue);
+                for id in &metro_line.segments {
+                    let railway_segment = self.engine.state.railways.segment(*id);
+                    if bounding_box.intersects(&railway_segment.bounds) {
+                        let mut spline_visitor = DrawSplineVisitor::planned(self, &painter, color);
+                        railway_segment.visit_spline(
+                            &mut spline_visitor,
+                            spline_scale,
+                            &bounding_box,
+                        )?;
+                    }
+                }
+            }
+            for reroute in &preview.rerouted_metro_lines {
+                let color = self
+                    .engine
+                    .state
+                    .metros
+                    .metro_line(reroute.metro_line)
+                    .data
+                    .color;
+                let color = egui::Color32::from_rgb(color.red, color.green, color.blue);
+                for id in &reroute.segments {
+                    let railway_segment = self.engine.state.railways.segment(*id);
+                    if bounding_box.intersects(&railway_segment.bounds) {
+                        let mut spline_visitor = DrawSplineVisitor::planned(self, &painter, color);
+                        railway_segment.visit_spline(
+                            &mut spline_visitor,
+                            spline_scale,
+                            &bounding_box,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        // draw the junctions picked so far for a metro line's new path
+        if let Some(path_edit) = &self.transient.planned_changes.path_edit {
+            let points: Vec<_> = path_edit
+                .junctions
+                .iter()
+                .filter_map(|id| self.engine.state.railways.junctions().get(id))
+                .map(|junction| {
+                    let (x, y) = (junction.location.x as f32, junction.location.y as f32);
+                    egui::Pos2::from(self.pan.to_screen_ff((x, y)))
+                })
+                .collect();
+            for point in &points {
+                painter.circle_filled(*point, 5.0, theme.highlight);
+            }
+            painter.add(egui::Shape::line(points, (2.0, theme.highlight)));
+        }
+
+        if let Some(selection) = self.transient.segment_detail {
+            let keys = match selection {
+                crate::app::SegmentSelection::Highway(id) => self
+                    .engine
+                    .state
+                    .highways
+                    .segments()
+                    .get(&id)
+                    .map(|segment| segment.keys()),
+                crate::app::SegmentSelection::Railway(id) => self
+                    .engine
+                    .state
+                    .railways
+                    .segments()
+                    .get(&id)
+                    .map(|segment| segment.keys()),
+            };
+            if let Some(keys) = keys {
+                let points = keys
+                    .iter()
+                    .map(|key| {
+                        egui::Pos2::from(self.pan.to_screen_ff((key.x as f32, key.y as f32)))
+                    })
+                    .collect();
+                painter.add(egui::Shape::line(points, (6.0, theme.highlight)));
+            }
+        }
+
         // draw route from the query interface
-        for route in &self.route_query.current_routes {
+        for route in &self.transient.route_query.current_routes {
             if bounding_box.intersects(&route.bounds) {
                 let mut route_visitor = DrawSplineVisitor::new(self, &painter, traffic);
                 route.visit_spline(
@@ -117,9 +367,27 @@ impl App {
             }
         }
 
-        // only render routes if the simulation is slow enough to see them and we are zoomed in
-        // sufficiently far
-        if self.engine.time_state.should_render_motion() && self.pan.scale >= 2.0 {
+        if self.replay_open() {
+            if self.display_options.show_agents && self.pan.scale >= 2.0 {
+                for (_, (x, y)) in self.replay_agent_positions() {
+                    if bounding_box.contains(x as u64, y as u64) {
+                        let pos = egui::Pos2::from(self.pan.to_screen_ff((x, y)));
+                        painter.circle(
+                            pos,
+                            self.scale_point(2.0, 5.0),
+                            theme.agent,
+                            egui::Stroke::none(),
+                        );
+                        self.diagnostics.agents += 1;
+                    }
+                }
+            }
+        } else if self.display_options.show_agents
+            && self.engine.time_state.should_render_motion()
+            && self.pan.scale >= 2.0
+        {
+            // only render routes if the simulation is slow enough to see them and we are zoomed
+            // in sufficiently far
             for agent in self.engine.agents.values() {
                 if let agent::AgentState::Route(route_state) = &agent.state {
                     // NOTE: this draws a lot more than needed, but it also avoids computing the
@@ -133,7 +401,7 @@ impl App {
                             painter.circle(
                                 pos,
                                 self.scale_point(2.0, 5.0),
-                                egui::Color32::from_rgb(0, 0, 255),
+                                theme.agent,
                                 egui::Stroke::none(),
                             );
                             self.diagnostics.agents += 1;
@@ -143,7 +411,15 @@ impl App {
             }
         }
 
-        if let crate::app::AgentDetail::Selected { id } = &self.agent_detail {
+        tracing::debug_span!("draw_vacancy_markers")
+            .in_scope(|| self.draw_vacancy_markers(&painter, &bounding_box))?;
+
+        if self.display_options.show_labels {
+            tracing::debug_span!("draw_labels")
+                .in_scope(|| self.draw_labels(&painter, &bounding_box, &pois));
+        }
+
+        if let crate::app::AgentDetail::Selected { id } = &self.transient.agent_detail {
             let agent = self.engine.agents.get(id).expect("missing agent");
             if let agent::AgentState::Route(route_state) = &agent.state {
                 if let Some(key) =
@@ -151,33 +427,179 @@ impl App {
                 {
                     let (x, y) = key.position;
                     let pos = egui::Pos2::from(self.pan.to_screen_ff((x, y)));
-                    painter.circle(
-                        pos,
-                        5.0,
-                        egui::Color32::from_rgb(255, 0, 0),
-                        egui::Stroke::none(),
-                    );
+                    painter.circle(pos, 5.0, theme.selected_agent, egui::Stroke::none());
                 }
             }
         }
 
+        tracing::trace!(
+            tiles = self.diagnostics.tiles,
+            metro_vertices = self.diagnostics.metro_vertices,
+            highway_vertices = self.diagnostics.highway_vertices,
+            agents = self.diagnostics.agents,
+            "drew content"
+        );
+
         Ok(())
     }
 
-    fn scale_point(&self, scale_cutoff: f32, max_size: f32) -> f32 {
-        max_size.min(self.pan.scale / scale_cutoff)
+    /**
+     * Draw markers for the kinds of vacancies selected in the overlay options, keeping track of
+     * where they were drawn so that they can be clicked.
+     */
+    fn draw_vacancy_markers(
+        &mut self,
+        painter: &egui::Painter,
+        bounding_box: &quadtree::Rect,
+    ) -> Result<()> {
+        use enum_iterator::IntoEnumIterator;
+
+        let mut placed = std::mem::take(&mut self.overlay.placed_markers);
+        placed.clear();
+        let limits = crate::vacancy_markers::MarkerLimits::default();
+        for kind in crate::vacancy_markers::VacancyKind::into_enum_iter() {
+            if self.overlay.vacancy_markers.contains(&kind) {
+                crate::vacancy_markers::place_markers(
+                    &self.engine.state.qtree,
+                    kind,
+                    bounding_box,
+                    self.pan.scale,
+                    &limits,
+                    &mut placed,
+                )?;
+            }
+        }
+
+        let font_id = egui::FontId::proportional(LABEL_FONT_SIZE);
+        for marker in &placed.markers {
+            let color = self.theme().vacancy(marker.kind);
+            let center = egui::Pos2::from(self.pan.to_screen_ff(marker.center()));
+            let half = marker.half_size(self.pan.scale);
+            painter.rect_stroke(
+                egui::Rect::from_center_size(center, egui::vec2(half, half) * 2.0),
+                egui::Rounding::none(),
+                (1.5, color),
+            );
+            if marker.aggregate {
+                let galley =
+                    painter.layout_no_wrap(marker.count.to_string(), font_id.clone(), color);
+                painter.galley(center - galley.size() / 2.0, galley);
+            }
+        }
+
+        self.overlay.placed_markers = placed;
+        Ok(())
     }
 
-    fn update_scale(&mut self, scale: f32, mx: f32, my: f32) {
-        let new_scale = f32::max(f32::min(scale, self.pan.max_scale), self.pan.min_scale);
+    /**
+     * Draw names for stations, highways, metro lines, and the given points of interest that are on
+     * screen, dropping labels that would overlap ones with higher priority.
+     */
+    fn draw_labels(
+        &self,
+        painter: &egui::Painter,
+        bounding_box: &quadtree::Rect,
+        pois: &[(egui::Pos2, String)],
+    ) {
+        use crate::labels::{Label, LabelPriority};
+
+        let font_id = egui::FontId::proportional(LABEL_FONT_SIZE);
+        let color = self.theme().foreground;
+        let to_screen = |(x, y): (f64, f64)| -> egui::Pos2 {
+            self.pan.to_screen_ff((x as f32, y as f32)).into()
+        };
+        let make_label = |priority, center, angle, text: &str| {
+            let galley = painter.layout_no_wrap(text.to_string(), font_id.clone(), color);
+            Label {
+                priority,
+                center,
+                size: galley.size(),
+                angle,
+                content: galley,
+            }
+        };
 
-        // zoom centered on mouse
-        self.pan.tx =
-            (mx * self.pan.scale - mx * new_scale + self.pan.tx * new_scale) / self.pan.scale;
-        self.pan.ty =
-            (my * self.pan.scale - my * new_scale + self.pan.ty * new_scale) / self.pan.scale;
+        let mut labels = Vec::new();
+
+        for junction in self.engine.state.railways.junctions().values() {
+            if let Some(station) = &junction.data.station {
+                let radius = station.address.width() as f32 * self.pan.scale / 4.0;
+                let (x, y) = station.address.to_center_f64();
+                if radius >= STATION_LABEL_MIN_RADIUS && bounding_box.contains(x as u64, y as u64) {
+                    // just below the station circle
+                    let center = to_screen((x, y)) + egui::vec2(0.0, radius + LABEL_FONT_SIZE);
+                    labels.push(make_label(
+                        LabelPriority::Station,
+                        center,
+                        0.0,
+                        &station.name,
+                    ));
+                }
+            }
+        }
 
-        self.pan.scale = new_scale;
+        if self.pan.scale >= NETWORK_LABEL_MIN_SCALE {
+            for segment in self.engine.state.highways.segments().values() {
+                if segment.data.refs.is_empty()
+                    || segment.change_state.is_staged_change()
+                    || !bounding_box.intersects(&segment.bounds)
+                {
+                    continue;
+                }
+                let points: Vec<_> = segment
+                    .keys()
+                    .iter()
+                    .map(|key| to_screen((key.x, key.y)))
+                    .collect();
+                if let Some((center, angle)) = crate::labels::path_midpoint(&points) {
+                    let label = make_label(
+                        LabelPriority::HighwayRef,
+                        center,
+                        angle,
+                        &segment.data.refs.join(" / "),
+                    );
+                    // don't let the label run past a short segment
+                    let length: f32 = points.windows(2).map(|p| p[0].distance(p[1])).sum();
+                    if label.size.x <= length {
+                        labels.push(label);
+                    }
+                }
+            }
+
+            let railways = &self.engine.state.railways;
+            for metro_line in self.engine.state.metros.metro_lines().values() {
+                let junctions: Vec<_> = metro_line.junctions(railways).collect();
+                let termini = junctions.first().into_iter().chain(junctions.last());
+                for junction in termini {
+                    let (x, y) = railways.junction(*junction).location.into();
+                    if bounding_box.contains(x as u64, y as u64) {
+                        // just above the terminus, so that it doesn't cover a station name
+                        let center = to_screen((x, y)) - egui::vec2(0.0, LABEL_FONT_SIZE * 1.5);
+                        labels.push(make_label(
+                            LabelPriority::MetroLine,
+                            center,
+                            0.0,
+                            &metro_line.data.name,
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (center, name) in pois {
+            labels.push(make_label(LabelPriority::Poi, *center, 0.0, name));
+        }
+
+        for label in crate::labels::declutter(labels, LABEL_CELL_SIZE) {
+            painter.add(egui::epaint::TextShape {
+                angle: label.angle,
+                ..egui::epaint::TextShape::new(label.text_pos(), label.content)
+            });
+        }
+    }
+
+    fn scale_point(&self, scale_cutoff: f32, max_size: f32) -> f32 {
+        max_size.min(self.pan.scale / scale_cutoff)
     }
 
     fn handle_input(&mut self, response: egui::Response) {
@@ -187,21 +609,17 @@ impl App {
 
             let scale = self.pan.scale * 1.1_f32.powf(-scroll_delta / 10.0);
             if let Some(pos) = { response.ctx.input().pointer.interact_pos() } {
-                self.update_scale(scale, pos.x, pos.y);
+                self.pan.zoom_about(scale, pos.into());
             };
         }
 
         if let Some(multitouch) = { response.ctx.multi_touch() } {
             // mobile
 
-            let delta = multitouch.translation_delta;
-            self.pan.tx += delta.x;
-            self.pan.ty += delta.y;
-
-            let gesture_center = multitouch.average_pos;
+            self.pan.pan_by(multitouch.translation_delta.into());
 
             let scale = self.pan.scale * multitouch.zoom_delta;
-            self.update_scale(scale, gesture_center.x, gesture_center.y);
+            self.pan.zoom_about(scale, multitouch.average_pos.into());
         } else if response.dragged() {
             // desktop
 
@@ -209,9 +627,7 @@ impl App {
             // but we prefer to use the multitouch measurement since it accounts for
             // all active touches, not just one.
 
-            let delta = response.drag_delta();
-            self.pan.tx += delta.x;
-            self.pan.ty += delta.y;
+            self.pan.pan_by(response.drag_delta().into());
         }
 
         if response.clicked() {
@@ -219,49 +635,218 @@ impl App {
                 let (mx, my) = self.pan.to_model_fu(pos.into());
                 let address = self.engine.state.qtree.get_address(mx, my);
 
-                if let crate::app::AgentDetail::Querying = self.agent_detail {
+                if let crate::app::AgentDetail::Querying = self.transient.agent_detail {
                     match address {
                         Ok(address) => {
-                            self.agent_detail = crate::app::AgentDetail::Query { address }
+                            self.transient.agent_detail = crate::app::AgentDetail::Query { address }
                         }
-                        Err(_) => self.agent_detail = crate::app::AgentDetail::Empty,
+                        Err(_) => self.transient.agent_detail = crate::app::AgentDetail::Empty,
                     }
-                } else if let crate::app::IsochroneQueryState::Querying = self.isochrone_query.state
+                } else if self.transient.planned_changes.path_edit.is_some() {
+                    let junction = self.pick_railway_junction(self.pan.to_model_ff(pos.into()));
+                    if let (Some(junction), Some(path_edit)) =
+                        (junction, &mut self.transient.planned_changes.path_edit)
+                    {
+                        if path_edit.junctions.last() != Some(&junction) {
+                            path_edit.junctions.push(junction);
+                        }
+                    }
+                } else if let crate::app::IsochroneQueryState::Querying =
+                    self.transient.isochrone_query.state
                 {
                     match address {
-                        Ok(address) => {
-                            match self
-                                .engine
-                                .query_isochrone_map(address, self.isochrone_query.mode)
-                            {
-                                Ok(isochrone_map) => {
-                                    // TODO: perform asynchronously, and use intermediary "calculating" state
-                                    self.isochrone_query.state =
-                                        crate::app::IsochroneQueryState::Calculated {
-                                            isochrone_map,
-                                        }
-                                }
-                                Err(err) => {
-                                    eprintln!("Error calculating isochrone map: {}", err);
-                                    self.isochrone_query.state =
-                                        crate::app::IsochroneQueryState::Empty;
-                                }
-                            };
-                        }
+                        Ok(address) => self.query_isochrone(address),
                         Err(_) => {
-                            self.isochrone_query.state = crate::app::IsochroneQueryState::Empty
+                            self.transient.isochrone_query.state =
+                                crate::app::IsochroneQueryState::Empty
                         }
                     }
+                } else if let Some(marker) =
+                    self.overlay
+                        .placed_markers
+                        .at(pos, self.pan.scale, |point| self.pan.to_screen_ff(point))
+                {
+                    if marker.aggregate {
+                        // zoom in until the branch is big enough to show its tiles
+                        let scale = self.pan.scale * 4.0;
+                        self.pan.zoom_about(scale, pos.into());
+                    } else {
+                        self.transient.agent_detail = crate::app::AgentDetail::Query {
+                            address: marker.address,
+                        };
+                    }
+                } else {
+                    self.transient.segment_detail =
+                        self.pick_segment(self.pan.to_model_ff(pos.into()));
                 }
             }
         }
     }
+
+    /**
+     * Draw straight lines between the zones with the most commutes yesterday, wider for more
+     * commutes. Lines that don't pass through the view are skipped.
+     */
+    fn draw_desire_lines(&self, painter: &egui::Painter, bounding_box: &quadtree::Rect) {
+        let matrix = match self.engine.od_matrix() {
+            Some(matrix) => matrix,
+            None => return,
+        };
+        let flows = matrix.top_flows(self.transient.commute_flows.top_n);
+        let max_trips = match flows.first() {
+            Some(flow) => flow.trips,
+            None => return,
+        };
+
+        for flow in &flows {
+            let (x1, y1) = matrix.zone_center(flow.origin);
+            let (x2, y2) = matrix.zone_center(flow.destination);
+            let bounds = quadtree::Rect::corners(
+                x1.min(x2) as u64,
+                y1.min(y2) as u64,
+                x1.max(x2).ceil() as u64 + 1,
+                y1.max(y2).ceil() as u64 + 1,
+            );
+            if !bounds.intersects(bounding_box) {
+                continue;
+            }
+
+            // log scale, so that smaller flows are still visible next to the largest one
+            let scale = (flow.trips as f32).ln_1p() / (max_trips as f32).ln_1p();
+            let from = egui::Pos2::from(self.pan.to_screen_ff((x1 as f32, y1 as f32)));
+            let to = egui::Pos2::from(self.pan.to_screen_ff((x2 as f32, y2 as f32)));
+            painter.line_segment(
+                [from, to],
+                (
+                    1.0 + 5.0 * scale,
+                    crate::field_overlay::palette_color(
+                        self.display_options.palette,
+                        palette::HueRamp::Field,
+                        scale,
+                        0.8,
+                    ),
+                ),
+            );
+        }
+    }
+
+    /**
+     * With a route start selected, show the estimated travel time to the hovered tile next to the
+     * cursor. The estimate is cheap enough to update on every mouse move, but it is approximate.
+     */
+    fn draw_route_estimate(&mut self, response: &egui::Response) -> Result<()> {
+        let start = match self.transient.route_query.start_address {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+        let end = match response.hover_pos() {
+            Some(pos) => {
+                let (x, y) = self.pan.to_model_fu(pos.into());
+                match self.engine.state.qtree.get_address(x, y) {
+                    Ok(end) => end,
+                    Err(_) => return Ok(()),
+                }
+            }
+            None => return Ok(()),
+        };
+
+        // the same modes that the route query panel would use
+        let profile = if self
+            .transient
+            .route_query
+            .allowed_modes
+            .contains(route::Mode::Driving)
+        {
+            engine::ModeProfile::Driving
+        } else {
+            engine::ModeProfile::Transit
+        };
+        let label = match self.engine.estimate_route_cost(start, end, profile)? {
+            Some(cost) => format!("≈ {} min (estimate)", (cost / 60.0).round()),
+            None => String::from("No route (estimate)"),
+        };
+        egui::show_tooltip_at_pointer(&response.ctx, egui::Id::new("route_estimate"), |ui| {
+            ui.label(label);
+        });
+
+        Ok(())
+    }
+
+    /// Find the visible highway or railway segment closest to the given point, if any is in range.
+    fn pick_segment(&self, (x, y): (f32, f32)) -> Option<crate::app::SegmentSelection> {
+        let point = (x as f64, y as f64).into();
+        let max_distance = (SEGMENT_PICK_THRESHOLD / self.pan.scale) as f64;
+
+        let highway = self
+            .engine
+            .state
+            .highways
+            .nearest_segment(point, max_distance, |_| true)
+            .map(|(segment, distance)| {
+                (crate::app::SegmentSelection::Highway(segment.id), distance)
+            });
+        // only consider railways that are actually drawn
+        let railway = self
+            .engine
+            .state
+            .railways
+            .nearest_segment(point, max_distance, |segment| {
+                self.display_options.show_all_railways
+                    || !self
+                        .engine
+                        .state
+                        .metros
+                        .railway_segment_metro_lines(segment.id)
+                        .is_empty()
+            })
+            .map(|(segment, distance)| {
+                (crate::app::SegmentSelection::Railway(segment.id), distance)
+            });
+
+        highway
+            .into_iter()
+            .chain(railway)
+            .min_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).unwrap())
+            .map(|(selection, _)| selection)
+    }
+}
+
+impl App {
+    /// Find the railway junction closest to the given point that a metro line could run through.
+    fn pick_railway_junction(&self, (x, y): (f32, f32)) -> Option<network::JunctionHandle> {
+        use cgmath::InnerSpace;
+
+        let max_distance = (SEGMENT_PICK_THRESHOLD / self.pan.scale) as f64;
+        let point = network::Key::new(x as f64, y as f64);
+
+        self.engine
+            .state
+            .railways
+            .junctions()
+            .values()
+            .filter(|junction| junction.change_state.is_staged_active())
+            .map(|junction| (junction.id, (junction.location - point).magnitude()))
+            .filter(|(_, distance)| *distance <= max_distance)
+            .min_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).unwrap())
+            .map(|(id, _)| id)
+    }
+
+    /// The depth of the smallest tiles that are drawn at least min_tile_size wide.
+    fn max_visible_depth(&self) -> u32 {
+        let qtree = &self.engine.state.qtree;
+        let min_tile_size = self.display_options.min_tile_size as f32;
+        (0..qtree.max_depth())
+            .find(|depth| (qtree.width() >> depth) as f32 * self.pan.scale < min_tile_size)
+            .unwrap_or_else(|| qtree.max_depth())
+    }
 }
 
 struct DrawQtreeVisitor<'a, 'b> {
     app: &'a App,
     painter: &'b egui::Painter,
     visited: u64,
+    /// named points of interest that are big enough to label, by the center of their tile
+    pois: Vec<(egui::Pos2, String)>,
 }
 
 impl<'a, 'b> DrawQtreeVisitor<'a, 'b> {
@@ -270,6 +855,7 @@ impl<'a, 'b> DrawQtreeVisitor<'a, 'b> {
             app,
             painter,
             visited: 0,
+            pois: Vec::new(),
         }
     }
 
@@ -303,33 +889,67 @@ impl<'a, 'b> DrawQtreeVisitor<'a, 'b> {
         let width = data.width as f32 * self.app.pan.scale;
         let threshold = self.app.display_options.field_resolution as f32;
         if is_leaf || (width >= threshold && width < threshold * 2.0) {
-            // if we have selected an isochrone, draw that instead of the field
-            let hue = if let crate::app::IsochroneQueryState::Calculated { isochrone_map } =
-                &self.app.isochrone_query.state
-            {
-                let (x, y) = data.center();
-                let travel_time = isochrone_map.get_travel_time(x, y) / 60.0; // convert seconds to minutes
-                let max = self.app.isochrone_query.max_travel_time as f32;
-
-                // quantize
-                let step = self.app.isochrone_query.quantization_step.max(1.0);
-                // adding the 1.0 allows including times that are at the threshold
-                let quantized = ((travel_time / step + 1.0).floor() - 1.0) * step;
-
-                Some(crate::field_overlay::calc_hue(
-                    // reverse direction to make shorter times "good" and longer times "bad"
-                    max - quantized as f32,
-                    0.0,
-                    max,
-                ))
-            } else if let Some(field) = self.app.overlay.field {
-                Some(field.hue(&self.app.engine, fields, data))
-            } else {
-                None
+            let palette_color = |ramp, scale| {
+                crate::field_overlay::palette_color(
+                    self.app.display_options.palette,
+                    ramp,
+                    scale,
+                    self.app.theme().field_alpha,
+                )
             };
 
-            if let Some(hue) = hue {
-                let color = egui::color::Hsva::new(hue, 0.8, 0.8, 0.5);
+            // if we have selected an isochrone, draw that instead of the field
+            let color =
+                if let Some(isochrone_map) = self.app.transient.isochrone_query.displayed_map() {
+                    let (x, y) = data.center();
+                    // convert minutes to seconds
+                    let isochrone_query = &self.app.transient.isochrone_query;
+                    let quantized = isochrone_map.quantized(
+                        isochrone_query.quantization_step.max(1.0) * 60.0,
+                        isochrone_query.display_max_travel_time(isochrone_map) * 60.0,
+                    );
+                    let max = quantized.max() as f32;
+
+                    // reverse direction to make shorter times "good" and longer times "bad"
+                    Some(match quantized.get_travel_time(x, y) {
+                        route::QuantizedTravelTime::Within(travel_time) => palette_color(
+                            palette::HueRamp::Field,
+                            palette::scale(max - travel_time as f32, 0.0, max),
+                        ),
+                        route::QuantizedTravelTime::BeyondMax => {
+                            palette_color(palette::HueRamp::Field, 0.0)
+                        }
+                        route::QuantizedTravelTime::Unreachable => self.app.theme().unreachable,
+                    })
+                } else if let Some(name) = &self.app.overlay.blurred_field {
+                    // sample the smooth raster instead of the per-tile fields
+                    self.app.engine.blurred_field(name).and_then(|field| {
+                        let (x, y) = data.center();
+                        let max = field.max_value() as f32;
+                        field.value_at(x, y).map(|value| {
+                            palette_color(
+                                palette::HueRamp::Field,
+                                palette::scale(value as f32, 0.0, max),
+                            )
+                        })
+                    })
+                } else {
+                    self.app.overlay.field.map(|field| {
+                        let overlay = &self.app.overlay;
+                        let scale = overlay.field_samples.get(data).unwrap_or_else(|| {
+                            field.scale(
+                                &self.app.engine,
+                                self.app.world_state(),
+                                &overlay.agent_counts,
+                                fields,
+                                data,
+                            )
+                        });
+                        palette_color(field.hue_ramp(), scale)
+                    })
+                };
+
+            if let Some(color) = color {
                 let rect = self.get_full_rect(data);
                 self.painter
                     .rect_filled(rect, egui::Rounding::none(), color);
@@ -339,31 +959,35 @@ impl<'a, 'b> DrawQtreeVisitor<'a, 'b> {
 }
 
 impl<'a, 'b>
-    quadtree::Visitor<
-        BranchState<engine::FieldsState>,
-        LeafState<engine::FieldsState>,
-        anyhow::Error,
-    > for DrawQtreeVisitor<'a, 'b>
+    quadtree::Visitor<BranchState<engine::FieldsState>, LeafState<engine::FieldsState>, Error>
+    for DrawQtreeVisitor<'a, 'b>
 {
     fn visit_branch_pre(
         &mut self,
         _branch: &BranchState<engine::FieldsState>,
-        data: &quadtree::VisitData,
+        _data: &quadtree::VisitData,
     ) -> Result<bool> {
-        let should_descend =
-            data.width as f32 * self.app.pan.scale >= self.app.display_options.min_tile_size as f32;
+        Ok(true)
+    }
 
-        if !should_descend && self.app.overlay.field.is_none() {
+    fn visit_cutoff_branch(
+        &mut self,
+        branch: &BranchState<engine::FieldsState>,
+        data: &quadtree::VisitData,
+    ) -> Result<()> {
+        // too small to draw individually
+        if !self.app.overlay.is_active() {
             let full_rect = self.get_full_rect(data);
-            self.painter.rect_filled(
-                full_rect,
-                egui::Rounding::none(),
-                egui::Color32::from_gray(100),
-            );
+            let color = self
+                .app
+                .theme()
+                .cutoff_with_water(branch.fields.terrain.water_fraction());
+            self.painter
+                .rect_filled(full_rect, egui::Rounding::none(), color);
             self.visited += 1;
         }
-
-        Ok(should_descend)
+        self.maybe_draw_field(&branch.fields, data, false);
+        Ok(())
     }
 
     fn visit_leaf(
@@ -374,41 +998,48 @@ impl<'a, 'b>
         let width = data.width as f32 * self.app.pan.scale;
         let rect = self.get_rect(data);
         let full_rect = self.get_full_rect(data);
+        let theme = self.app.theme();
 
         use tiles::Tile::*;
         match &leaf.tile {
             WaterTile(tiles::WaterTile {}) => {
-                self.painter.rect_filled(
-                    full_rect,
-                    egui::Rounding::none(),
-                    egui::Color32::from_rgb(0, 0, 150),
-                );
+                self.painter
+                    .rect_filled(full_rect, egui::Rounding::none(), theme.water);
             }
             HousingTile(tiles::HousingTile { .. }) => {
-                self.painter.circle_filled(
-                    rect.center(),
-                    width / 8.0,
-                    egui::Color32::from_gray(255),
-                );
+                self.painter
+                    .circle_filled(rect.center(), width / 8.0, theme.foreground);
             }
             WorkplaceTile(tiles::WorkplaceTile { .. }) => {
                 self.painter.add(regular_poly::<3>(
                     rect.center().into(),
                     width / 6.0,
                     -std::f32::consts::FRAC_PI_2,
-                    egui::Color32::from_gray(255),
+                    theme.foreground,
                     egui::Stroke::none(),
                 ));
             }
-            MetroStationTile(tiles::MetroStationTile { .. }) => {
-                self.painter.circle_stroke(
-                    rect.center(),
-                    width / 4.0,
-                    (1.0, egui::Color32::from_gray(255)),
-                );
+            MetroStationTile(tiles::MetroStationTile { ids, .. }) => {
+                if ids.is_empty() {
+                    // orphan stations aren't served by any metro line yet
+                    dashed_circle(
+                        self.painter,
+                        rect.center(),
+                        width / 4.0,
+                        (1.0, theme.foreground).into(),
+                    );
+                } else {
+                    self.painter
+                        .circle_stroke(rect.center(), width / 4.0, (1.0, theme.foreground));
+                }
             }
             _ => (),
         }
+        if let Some(poi) = &leaf.poi {
+            if width >= POI_LABEL_MIN_WIDTH && !poi.name.is_empty() {
+                self.pois.push((rect.center(), poi.name.clone()));
+            }
+        }
         self.visited += 1;
 
         self.maybe_draw_field(&leaf.fields, data, true);
@@ -426,6 +1057,19 @@ impl<'a, 'b>
     }
 }
 
+/// A circle outline with every other arc left out.
+fn dashed_circle(painter: &egui::Painter, center: egui::Pos2, radius: f32, stroke: egui::Stroke) {
+    use std::f32::consts::PI;
+    const DASHES: usize = 8;
+    let point = |i: usize| {
+        let theta = PI * i as f32 / DASHES as f32;
+        center + radius * egui::vec2(theta.cos(), theta.sin())
+    };
+    for i in 0..DASHES {
+        painter.line_segment([point(2 * i), point(2 * i + 1)], stroke);
+    }
+}
+
 fn regular_poly<const N: usize>(
     (x, y): (f32, f32),
     radius: f32,
@@ -454,6 +1098,12 @@ struct DrawSplineVisitor<'a, 'b, 'c> {
     painter: &'b egui::Painter,
 
     traffic: Option<&'c route::WorldStateImpl>,
+    /// if set, draw a dashed line in this color to indicate a planned change
+    planned: Option<egui::Color32>,
+    /// whether the segment being visited is dashed, e.g. for tunnels
+    dashed: bool,
+    /// if set, draw a thin outline in this color around the segment being visited
+    casing: Option<egui::Color32>,
 
     visited: u64,
 }
@@ -468,6 +1118,21 @@ impl<'a, 'b, 'c> DrawSplineVisitor<'a, 'b, 'c> {
             app,
             painter,
             traffic,
+            planned: None,
+            dashed: false,
+            casing: None,
+            visited: 0,
+        }
+    }
+
+    fn planned(app: &'a App, painter: &'b egui::Painter, color: egui::Color32) -> Self {
+        Self {
+            app,
+            painter,
+            traffic: None,
+            planned: Some(color),
+            dashed: false,
+            casing: None,
             visited: 0,
         }
     }
@@ -486,19 +1151,33 @@ impl<'a, 'b, 'c> DrawSplineVisitor<'a, 'b, 'c> {
             .pan
             .to_screen_ff((vertex.x as f32, vertex.y as f32));
 
-        let (color, line_width) = match traffic_factor {
-            Some(traffic_factor) => {
+        let (color, line_width) = match (self.planned, traffic_factor) {
+            (Some(planned), _) => (planned, 3.0),
+            (None, Some(traffic_factor)) => {
                 let scaled = (traffic_factor - 1.0).min(5.0) / 5.0;
                 let line_width_factor = 2.0 + 2.0 * scaled as f32;
-                let hue = crate::field_overlay::traffic_hue(traffic_factor);
-                let color = egui::color::Hsva::new(hue, 1.0, 1.0, 1.0).into();
+                let color = crate::field_overlay::palette_color(
+                    self.app.display_options.palette,
+                    palette::HueRamp::Traffic,
+                    crate::field_overlay::traffic_scale(traffic_factor),
+                    1.0,
+                );
                 (color, line_width * line_width_factor)
             }
-            None => (*color, line_width),
+            (None, None) => (*color, line_width),
         };
 
-        if let Some(prev) = prev {
+        // leave every other gap empty so that planned changes are dashed
+        let gap = (self.planned.is_some() || self.dashed) && self.visited % 2 == 1;
+
+        if let (Some(prev), false) = (prev, gap) {
             let prev_point = self.app.pan.to_screen_ff((prev.x as f32, prev.y as f32));
+            if let (Some(casing), None) = (self.casing, self.planned) {
+                self.painter.line_segment(
+                    [prev_point.into(), point.into()],
+                    (line_width + 2.0, casing),
+                );
+            }
             self.painter
                 .line_segment([prev_point.into(), point.into()], (line_width, color));
         }
@@ -509,11 +1188,8 @@ impl<'a, 'b, 'c> DrawSplineVisitor<'a, 'b, 'c> {
 }
 
 impl<'a, 'b, 'c>
-    spline_util::SplineVisitor<
-        network::Segment<metro::RailwaySegment>,
-        cgmath::Vector2<f64>,
-        anyhow::Error,
-    > for DrawSplineVisitor<'a, 'b, 'c>
+    spline_util::SplineVisitor<network::Segment<metro::RailwaySegment>, cgmath::Vector2<f64>, Error>
+    for DrawSplineVisitor<'a, 'b, 'c>
 {
     fn visit(
         &mut self,
@@ -536,13 +1212,31 @@ impl<'a, 'b, 'c>
                     let color = metro_line.data.color;
                     egui::Color32::from_rgb(color.red, color.green, color.blue)
                 } else {
-                    egui::Color32::from_rgb(255, 255, 255)
+                    self.app.theme().foreground
                 }
             }
             Some(_) => {
                 // when we're drawing traffic, make all metro lines white so that we can distinguish
                 // traffic colors from metro line colors
-                egui::Color32::from_gray(255)
+                self.app.theme().foreground
+            }
+        };
+        let color = match segment.data.alignment {
+            metro::RailwayAlignment::AtGrade => {
+                self.dashed = false;
+                self.casing = None;
+                color
+            }
+            metro::RailwayAlignment::Elevated => {
+                self.dashed = false;
+                self.casing = Some(self.app.theme().cutoff);
+                color
+            }
+            metro::RailwayAlignment::Tunnel => {
+                // tunnels are out of sight, so they recede into the background
+                self.dashed = true;
+                self.casing = None;
+                color.linear_multiply(0.5)
             }
         };
         self.visit(&color, 2.0, None, vertex, t, prev)
@@ -553,7 +1247,7 @@ impl<'a, 'b, 'c>
     spline_util::SplineVisitor<
         network::Segment<highway::HighwaySegment>,
         cgmath::Vector2<f64>,
-        anyhow::Error,
+        Error,
     > for DrawSplineVisitor<'a, 'b, 'c>
 {
     fn visit(
@@ -567,7 +1261,7 @@ impl<'a, 'b, 'c>
         use route::WorldState;
 
         self.visit(
-            &egui::Color32::from_gray(204),
+            &self.app.theme().highway,
             1.0,
             self.traffic.map(|t| {
                 segment.congested_travel_factor(
@@ -583,7 +1277,7 @@ impl<'a, 'b, 'c>
     }
 }
 
-impl<'a, 'b, 'c> route::SplineVisitor<route::Route, route::RouteKey, anyhow::Error>
+impl<'a, 'b, 'c> route::SplineVisitor<route::Route, route::RouteKey, Error>
     for DrawSplineVisitor<'a, 'b, 'c>
 {
     fn visit(
@@ -595,7 +1289,7 @@ impl<'a, 'b, 'c> route::SplineVisitor<route::Route, route::RouteKey, anyhow::Err
     ) -> Result<()> {
         let (x, y) = key.position;
         self.visit(
-            &egui::Color32::from_rgb(0, 0, 255),
+            &self.app.theme().route,
             5.0,
             None,
             (x as f64, y as f64).into(),
@@ -1,13 +1,35 @@
 #[derive(clap::Parser, Debug)]
 struct Args {
     load: std::path::PathBuf,
+    /// replay file to open on startup, recorded from the same map
+    #[clap(long)]
+    replay: Option<std::path::PathBuf>,
+    /// one of high, medium, low, or auto
+    #[clap(long, default_value_t)]
+    quality: app::QualityPreset,
 }
 
 fn main() {
     use clap::Parser;
     let args = Args::parse();
 
-    let app = app::App::load_file(args.load);
+    match paths::Paths::resolve().migrate_legacy_saves() {
+        Ok(copied) if !copied.is_empty() => {
+            println!("Copied {} old save(s) to the save directory", copied.len())
+        }
+        Ok(_) => (),
+        Err(err) => eprintln!("Failed to copy old saves: {}", err),
+    }
+
+    let mut app = app::App::load_file(args.load, args.quality).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    if let Some(replay) = args.replay {
+        if let Err(err) = app.open_replay(&replay) {
+            app.report_error(err);
+        }
+    }
 
     app::bootstrap(app, false);
 }
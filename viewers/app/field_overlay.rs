@@ -1,3 +1,7 @@
+use std::collections::HashMap;
+
+use state::{BranchState, LeafState};
+
 use uom::si::time::{day, hour};
 use uom::si::u64::Time;
 
@@ -19,6 +23,8 @@ pub(crate) enum FieldType {
     WorkplaceHappinessHome,
     CommuteDurationHome,
     CarOwnership,
+    ChildrenShare,
+    SeniorsShare,
 
     // employment-related
     Employment,
@@ -27,6 +33,11 @@ pub(crate) enum FieldType {
     JobVacancy,
     WorkplaceHappinessWork,
     CommuteDurationWork,
+    OfficeJobs,
+    RetailJobs,
+    IndustrialJobs,
+    EducationJobs,
+    HealthcareJobs,
 
     // land value-related
     TileCreationTimeOldest,
@@ -43,6 +54,7 @@ pub(crate) enum FieldType {
     // dynamic
     Traffic,
     Parking,
+    AgentDensity,
 }
 
 impl FieldType {
@@ -56,6 +68,8 @@ impl FieldType {
             Self::WorkplaceHappinessHome => "Workplace happiness (home)",
             Self::CommuteDurationHome => "Commute duration (home)",
             Self::CarOwnership => "Car ownership",
+            Self::ChildrenShare => "Children share",
+            Self::SeniorsShare => "Seniors share",
 
             Self::Employment => "Employment",
             Self::TotalJobs => "Total jobs",
@@ -63,6 +77,11 @@ impl FieldType {
             Self::JobVacancy => "Job vacancy",
             Self::WorkplaceHappinessWork => "Workplace happiness (work)",
             Self::CommuteDurationWork => "Commute duration (work)",
+            Self::OfficeJobs => "Office jobs",
+            Self::RetailJobs => "Retail jobs",
+            Self::IndustrialJobs => "Industrial jobs",
+            Self::EducationJobs => "Education jobs",
+            Self::HealthcareJobs => "Healthcare jobs",
 
             Self::TileCreationTimeOldest => "Tile creation time (oldest)",
             Self::TileCreationTimeNewest => "Tile creation time (newest)",
@@ -76,9 +95,25 @@ impl FieldType {
 
             Self::Traffic => "Traffic",
             Self::Parking => "Parking",
+            Self::AgentDensity => "Agent density",
         }
     }
 
+    /**
+     * Whether the field changes without the fields being updated, e.g. with the traffic or the
+     * current time, so it can't be sampled ahead of time.
+     */
+    fn is_dynamic(&self) -> bool {
+        matches!(
+            self,
+            Self::TileCreationTimeOldest
+                | Self::TileCreationTimeNewest
+                | Self::Traffic
+                | Self::Parking
+                | Self::AgentDensity
+        )
+    }
+
     fn min(&self, engine: &engine::Engine) -> f32 {
         match self {
             Self::TileCreationTimeOldest | Self::TileCreationTimeNewest => {
@@ -98,6 +133,8 @@ impl FieldType {
             Self::WorkplaceHappinessHome => 1.0,
             Self::CommuteDurationHome => *COMMUTE_DURATION_MAX_SCALE,
             Self::CarOwnership => 1.0,
+            // neither is usually much more than a third of the people in an area
+            Self::ChildrenShare | Self::SeniorsShare => 0.5,
 
             Self::Employment => 0.3,
             Self::TotalJobs => 0.3,
@@ -105,6 +142,11 @@ impl FieldType {
             Self::JobVacancy => 0.5,
             Self::WorkplaceHappinessWork => 1.0,
             Self::CommuteDurationWork => *COMMUTE_DURATION_MAX_SCALE,
+            Self::OfficeJobs
+            | Self::RetailJobs
+            | Self::IndustrialJobs
+            | Self::EducationJobs
+            | Self::HealthcareJobs => 0.3,
 
             Self::TileCreationTimeOldest | Self::TileCreationTimeNewest => {
                 engine.time_state.current_time as f32
@@ -116,12 +158,15 @@ impl FieldType {
 
             Self::Traffic => 0.0,
             Self::Parking => 40.0,
+            Self::AgentDensity => 0.3,
         }
     }
 
     fn value(
         &self,
         engine: &engine::Engine,
+        world_state: &route::WorldStateImpl,
+        agent_counts: &AgentCounts,
         fields: &engine::FieldsState,
         data: &quadtree::VisitData,
     ) -> f32 {
@@ -134,6 +179,8 @@ impl FieldType {
             Self::WorkplaceHappinessHome => fields.population.workplace_happiness.value as f32,
             Self::CommuteDurationHome => fields.population.commute_duration.value as f32,
             Self::CarOwnership => fields.population.car_ownership.value as f32,
+            Self::ChildrenShare => fields.population.age_share(agent::AgeBucket::Child) as f32,
+            Self::SeniorsShare => fields.population.age_share(agent::AgeBucket::Senior) as f32,
 
             Self::Employment => fields.employment.workers.density() as f32,
             Self::TotalJobs => fields.employment.jobs.density() as f32,
@@ -141,6 +188,11 @@ impl FieldType {
             Self::JobVacancy => fields.employment.job_vacancy() as f32,
             Self::WorkplaceHappinessWork => fields.employment.workplace_happiness.value as f32,
             Self::CommuteDurationWork => fields.employment.commute_duration.value as f32,
+            Self::OfficeJobs => Self::jobs_density(fields, tiles::Industry::Office),
+            Self::RetailJobs => Self::jobs_density(fields, tiles::Industry::Retail),
+            Self::IndustrialJobs => Self::jobs_density(fields, tiles::Industry::Industrial),
+            Self::EducationJobs => Self::jobs_density(fields, tiles::Industry::Education),
+            Self::HealthcareJobs => Self::jobs_density(fields, tiles::Industry::Healthcare),
 
             Self::TileCreationTimeOldest => fields
                 .raw_land_value
@@ -162,46 +214,658 @@ impl FieldType {
 
             Self::Traffic => {
                 use route::WorldState;
-                let travelers = engine
-                    .world_state
-                    .get_local_road_zone_travelers(data.x, data.y);
+                let travelers = world_state.get_local_road_zone_travelers(data.x, data.y);
                 route::local_traffic::congested_travel_factor(&engine.state.config, travelers)
                     as f32
             }
             Self::Parking => {
                 use route::WorldState;
-                engine.world_state.get_parking(data.x as f64, data.y as f64) as f32
+                world_state.get_parking(data.x as f64, data.y as f64) as f32
             }
+            Self::AgentDensity => agent_counts.density(data),
         }
     }
 
-    pub fn hue(
+    fn jobs_density(fields: &engine::FieldsState, industry: tiles::Industry) -> f32 {
+        fields.employment.jobs_by_industry[industry].density() as f32
+    }
+
+    /// The hue ramp that this field was drawn with before palettes were introduced.
+    pub fn hue_ramp(&self) -> palette::HueRamp {
+        match self {
+            Self::Traffic => palette::HueRamp::TrafficField,
+            _ => palette::HueRamp::Field,
+        }
+    }
+
+    /// The value of this field for the given tile, normalized to [0, 1] for palette lookup.
+    pub fn scale(
         &self,
         engine: &engine::Engine,
+        world_state: &route::WorldStateImpl,
+        agent_counts: &AgentCounts,
         fields: &engine::FieldsState,
         data: &quadtree::VisitData,
     ) -> f32 {
+        let value = self.value(engine, world_state, agent_counts, fields, data);
         match self {
-            Self::Traffic => traffic_hue(self.value(engine, fields, data) as f64),
+            Self::Traffic => traffic_scale(value as f64),
             _ => {
                 let max = self.max(engine);
                 let min = self.min(engine);
-                calc_hue(self.value(engine, fields, data), min, max)
+                palette::scale(value, min, max)
             }
         }
     }
 }
 
-pub fn calc_hue(val: f32, min: f32, max: f32) -> f32 {
-    if max > min {
-        // ranges from 0.0 (reddish) to 0.5 (blueish)
-        (f32::min(f32::max(val, min), max) - min) / (max - min) * 0.5
-    } else {
-        0.0
+/**
+ * Nodes deeper than this aren't sampled, since the samples are stored for every possible node down
+ * to this depth. Their values are computed while drawing instead. The depth can be lowered further
+ * to save memory; see QualitySettings::overlay_depth.
+ */
+pub(crate) const MAX_SAMPLED_DEPTH: u32 = 10;
+
+/**
+ * The normalized value of a field for every quadtree node, so that drawing the overlay only takes
+ * a lookup per tile instead of computing the field and its normalization each frame. The samples
+ * are taken again when the selected field changes or the fields are updated. Dynamic fields (see
+ * FieldType::is_dynamic) are never sampled, since they would have to be sampled every frame.
+ */
+#[derive(Debug, Default)]
+pub(crate) struct FieldSamples {
+    /// the field, the fields version, and the depth that the samples were taken for
+    key: Option<(FieldType, u64, u32)>,
+    /// indexed by node_index; NaN for nodes that don't exist
+    scales: Vec<f32>,
+}
+
+impl FieldSamples {
+    /**
+     * Sample the field down to the given depth, capped at MAX_SAMPLED_DEPTH, if it isn't already
+     * sampled that way for the current fields.
+     */
+    pub fn update(&mut self, engine: &engine::Engine, field: Option<FieldType>, max_depth: u32) {
+        let max_depth = max_depth
+            .min(MAX_SAMPLED_DEPTH)
+            .min(engine.state.qtree.max_depth());
+        let key = field
+            .filter(|field| !field.is_dynamic())
+            .map(|field| (field, engine.fields_version(), max_depth));
+        if key == self.key {
+            return;
+        }
+        let _span = tracing::debug_span!("FieldSamples::update").entered();
+
+        self.key = key;
+        self.scales.clear();
+        if let Some((field, _, max_depth)) = key {
+            self.scales.resize(depth_offset(max_depth + 1), f32::NAN);
+            let mut visitor = SampleVisitor {
+                engine,
+                field,
+                agent_counts: &AgentCounts::default(),
+                max_depth,
+                scales: &mut self.scales,
+            };
+            engine.state.qtree.visit(&mut visitor).unwrap();
+        }
     }
+
+    /**
+     * The sampled value for the given node, or None if the field isn't sampled, the node is too
+     * deep, or the node was added since the samples were taken.
+     */
+    pub fn get(&self, data: &quadtree::VisitData) -> Option<f32> {
+        let (_, _, max_depth) = self.key?;
+        if data.depth > max_depth {
+            return None;
+        }
+        self.scales
+            .get(node_index(data))
+            .copied()
+            .filter(|scale| !scale.is_nan())
+    }
+}
+
+/// The number of possible nodes above the given depth.
+fn depth_offset(depth: u32) -> usize {
+    ((1 << (2 * depth)) - 1) / 3
+}
+
+/**
+ * The nodes are stored by depth, and then in Z-order, which is the order that they are visited
+ * in. Drawing visits nearby nodes one after another, so this keeps the lookups close together.
+ */
+fn node_index(data: &quadtree::VisitData) -> usize {
+    // NOTE: the width is a power of two, and shifting is much faster than dividing
+    let shift = data.width.trailing_zeros();
+    let (x, y) = (data.x >> shift, data.y >> shift);
+    depth_offset(data.depth) + (spread_bits(y) << 1 | spread_bits(x)) as usize
+}
+
+/// Moves each of the low 16 bits into every other bit, e.g. 0b111 becomes 0b10101.
+fn spread_bits(value: u64) -> u64 {
+    let mut value = value & 0xffff;
+    value = (value | (value << 8)) & 0x00ff_00ff;
+    value = (value | (value << 4)) & 0x0f0f_0f0f;
+    value = (value | (value << 2)) & 0x3333_3333;
+    (value | (value << 1)) & 0x5555_5555
 }
 
-pub fn traffic_hue(traffic_factor: f64) -> f32 {
+struct SampleVisitor<'a> {
+    engine: &'a engine::Engine,
+    field: FieldType,
+    agent_counts: &'a AgentCounts,
+    max_depth: u32,
+    scales: &'a mut Vec<f32>,
+}
+
+impl<'a> SampleVisitor<'a> {
+    fn sample(&mut self, fields: &engine::FieldsState, data: &quadtree::VisitData) {
+        self.scales[node_index(data)] = self.field.scale(
+            self.engine,
+            &self.engine.world_state,
+            self.agent_counts,
+            fields,
+            data,
+        );
+    }
+}
+
+impl<'a> quadtree::Visitor<BranchState<engine::FieldsState>, LeafState<engine::FieldsState>, ()>
+    for SampleVisitor<'a>
+{
+    fn visit_branch_pre(
+        &mut self,
+        branch: &BranchState<engine::FieldsState>,
+        data: &quadtree::VisitData,
+    ) -> Result<bool, ()> {
+        // branches are drawn in place of their leaves when the leaves are too small
+        self.sample(&branch.fields, data);
+        Ok(data.depth < self.max_depth)
+    }
+
+    fn visit_leaf(
+        &mut self,
+        leaf: &LeafState<engine::FieldsState>,
+        data: &quadtree::VisitData,
+    ) -> Result<(), ()> {
+        self.sample(&leaf.fields, data);
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &BranchState<engine::FieldsState>,
+        _data: &quadtree::VisitData,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+/**
+ * The number of agents currently in each quadtree node, including its descendants. This is
+ * recomputed from the live agent positions rather than stored in the fields, because agents move
+ * around much more often than the fields are updated.
+ */
+#[derive(Debug, Default)]
+pub(crate) struct AgentCounts {
+    counts: HashMap<quadtree::Address, usize>,
+}
+
+impl AgentCounts {
+    pub fn new(engine: &engine::Engine) -> Self {
+        let mut counts = HashMap::new();
+        for (_, (x, y)) in engine.live_agent_positions() {
+            // NOTE: positions along a route can stray slightly outside of the map
+            if let Ok(address) = engine.state.qtree.get_address(x as u64, y as u64) {
+                for address in std::iter::successors(Some(address), |a| a.parent()) {
+                    *counts.entry(address).or_insert(0) += 1;
+                }
+            }
+        }
+        Self { counts }
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+
+    fn density(&self, data: &quadtree::VisitData) -> f32 {
+        let count = self.counts.get(&data.address).copied().unwrap_or(0);
+        count as f32 / (data.width * data.width) as f32
+    }
+}
+
+/// Ranges from 1.0 for free-flowing traffic to 0.0 for heavy congestion.
+pub fn traffic_scale(traffic_factor: f64) -> f32 {
     let scaled = (traffic_factor - 1.0).min(5.0) / 5.0;
-    (1.0 / 3.0 - (scaled / 3.0)) as f32
+    (1.0 - scaled) as f32
+}
+
+pub fn palette_color(
+    palette: palette::Palette,
+    ramp: palette::HueRamp,
+    scale: f32,
+    alpha: f32,
+) -> egui::Color32 {
+    // premultiply in linear space, which is how egui converts Hsva colors
+    let (r, g, b) = palette.linear_color(scale, ramp);
+    egui::Rgba::from_rgba_premultiplied(r * alpha, g * alpha, b * alpha, alpha).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use enum_iterator::IntoEnumIterator;
+    use rand::{Rng, SeedableRng};
+
+    use crate::field_overlay::*;
+
+    fn split_all(engine: &mut engine::Engine, address: quadtree::Address, max_depth: u32) {
+        if address.depth() == max_depth as usize {
+            return;
+        }
+        engine
+            .state
+            .qtree
+            .split(
+                address,
+                BranchState::default(),
+                quadtree::QuadMap::each(LeafState::<engine::FieldsState>::default),
+            )
+            .unwrap();
+        for quadrant in quadtree::QUADRANTS {
+            split_all(engine, address.child(quadrant), max_depth);
+        }
+    }
+
+    /// A fully split map with housing and workplaces scattered around, and the fields computed.
+    fn generate_map(max_depth: u32) -> engine::Engine {
+        let mut engine = engine::Engine::new(state::Config {
+            max_depth,
+            people_per_sim: 1.0,
+            min_tile_size: 100,
+            industry_weights: Default::default(),
+            scheduling: Default::default(),
+            travel_diary: Default::default(),
+            traffic_history: Default::default(),
+            railways: Default::default(),
+            workplace_happiness: Default::default(),
+            leisure: Default::default(),
+            intersection_delay: Default::default(),
+            archetypes: Default::default(),
+            trigger_cadence: Default::default(),
+        });
+        split_all(
+            &mut engine,
+            quadtree::Address::from((vec![], max_depth)),
+            max_depth,
+        );
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let width = engine.state.qtree.width();
+        for _ in 0..width * 2 {
+            let address = engine
+                .state
+                .qtree
+                .get_address(rng.gen_range(0..width), rng.gen_range(0..width))
+                .unwrap();
+            engine.state.qtree.get_leaf_mut(address).unwrap().tile = if rng.gen_bool(0.5) {
+                tiles::HousingTile {
+                    density: rng.gen_range(1..10),
+                    agents: vec![],
+                }
+                .into()
+            } else {
+                tiles::WorkplaceTile {
+                    density: rng.gen_range(1..10),
+                    agents: vec![],
+                    industry: tiles::Industry::Retail,
+                }
+                .into()
+            };
+        }
+        engine.update_fields().unwrap();
+        engine
+    }
+
+    fn visit_data(engine: &engine::Engine, address: quadtree::Address) -> quadtree::VisitData {
+        let (x, y) = address.to_xy();
+        let depth = address.depth() as u32;
+        quadtree::VisitData {
+            address,
+            depth,
+            x,
+            y,
+            width: engine.state.qtree.width() >> depth,
+        }
+    }
+
+    /// The value of the field at each node, computed the same way as drawing without samples.
+    struct ScaleVisitor<'a> {
+        engine: &'a engine::Engine,
+        field: FieldType,
+        scales: Vec<(quadtree::VisitData, f32)>,
+    }
+
+    impl<'a> ScaleVisitor<'a> {
+        fn visit(engine: &'a engine::Engine, field: FieldType) -> Vec<(quadtree::VisitData, f32)> {
+            let mut visitor = Self {
+                engine,
+                field,
+                scales: Vec::new(),
+            };
+            engine.state.qtree.visit(&mut visitor).unwrap();
+            visitor.scales
+        }
+
+        fn scale(&mut self, fields: &engine::FieldsState, data: &quadtree::VisitData) {
+            let scale = self.field.scale(
+                self.engine,
+                &self.engine.world_state,
+                &AgentCounts::default(),
+                fields,
+                data,
+            );
+            self.scales.push((data.clone(), scale));
+        }
+    }
+
+    impl<'a> quadtree::Visitor<BranchState<engine::FieldsState>, LeafState<engine::FieldsState>, ()>
+        for ScaleVisitor<'a>
+    {
+        fn visit_branch_pre(
+            &mut self,
+            branch: &BranchState<engine::FieldsState>,
+            data: &quadtree::VisitData,
+        ) -> Result<bool, ()> {
+            self.scale(&branch.fields, data);
+            Ok(true)
+        }
+
+        fn visit_leaf(
+            &mut self,
+            leaf: &LeafState<engine::FieldsState>,
+            data: &quadtree::VisitData,
+        ) -> Result<(), ()> {
+            self.scale(&leaf.fields, data);
+            Ok(())
+        }
+
+        fn visit_branch_post(
+            &mut self,
+            _branch: &BranchState<engine::FieldsState>,
+            _data: &quadtree::VisitData,
+        ) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn hue_palette_matches_traffic_hue() {
+        // the traffic color before palettes were introduced
+        fn traffic_hue(traffic_factor: f64) -> f32 {
+            let scaled = (traffic_factor - 1.0).min(5.0) / 5.0;
+            (1.0 / 3.0 - (scaled / 3.0)) as f32
+        }
+
+        for i in 0..=700 {
+            let traffic_factor = 1.0 + i as f64 * 0.01;
+            let expected: egui::Color32 =
+                egui::color::Hsva::new(traffic_hue(traffic_factor), 1.0, 1.0, 1.0).into();
+            let actual = palette_color(
+                palette::Palette::Hue,
+                palette::HueRamp::Traffic,
+                traffic_scale(traffic_factor),
+                1.0,
+            );
+            assert_eq!(actual, expected, "traffic factor {}", traffic_factor);
+
+            // the traffic field overlay used the same hue, but muted and translucent
+            let expected: egui::Color32 =
+                egui::color::Hsva::new(traffic_hue(traffic_factor), 0.8, 0.8, 0.5).into();
+            let field = FieldType::Traffic;
+            let actual = palette_color(
+                palette::Palette::Hue,
+                field.hue_ramp(),
+                traffic_scale(traffic_factor),
+                0.5,
+            );
+            assert_eq!(actual, expected, "traffic factor {}", traffic_factor);
+        }
+    }
+
+    #[test]
+    fn hue_palette_matches_field_hue() {
+        // the field and isochrone color before palettes were introduced
+        fn calc_hue(val: f32, min: f32, max: f32) -> f32 {
+            if max > min {
+                (f32::min(f32::max(val, min), max) - min) / (max - min) * 0.5
+            } else {
+                0.0
+            }
+        }
+
+        let engine = generate_map(4);
+        for field in FieldType::into_enum_iter() {
+            if field == FieldType::Traffic {
+                continue;
+            }
+            let (min, max) = (field.min(&engine), field.max(&engine));
+            let width = engine.state.qtree.width();
+            for (x, y) in (0..width).flat_map(|x| (0..width).map(move |y| (x, y))) {
+                let address = engine.state.qtree.get_address(x, y).unwrap();
+                let data = visit_data(&engine, address);
+                let fields = &engine.state.qtree.get_leaf(address).unwrap().fields;
+                let value = field.value(
+                    &engine,
+                    &engine.world_state,
+                    &AgentCounts::default(),
+                    fields,
+                    &data,
+                );
+                let scale = field.scale(
+                    &engine,
+                    &engine.world_state,
+                    &AgentCounts::default(),
+                    fields,
+                    &data,
+                );
+                let expected: egui::Color32 =
+                    egui::color::Hsva::new(calc_hue(value, min, max), 0.8, 0.8, 0.5).into();
+                let actual = palette_color(palette::Palette::Hue, field.hue_ramp(), scale, 0.5);
+                assert_eq!(actual, expected, "{:?} {:?}", field, data);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_match() {
+        let engine = generate_map(4);
+        let mut samples = FieldSamples::default();
+
+        for field in FieldType::into_enum_iter() {
+            samples.update(&engine, Some(field), MAX_SAMPLED_DEPTH);
+            for (data, scale) in ScaleVisitor::visit(&engine, field) {
+                if field.is_dynamic() {
+                    assert_eq!(samples.get(&data), None);
+                } else {
+                    assert_eq!(samples.get(&data), Some(scale), "{:?} {:?}", field, data);
+                }
+            }
+        }
+
+        samples.update(&engine, None, MAX_SAMPLED_DEPTH);
+        let root = visit_data(&engine, quadtree::Address::from((vec![], 4)));
+        assert_eq!(samples.get(&root), None);
+    }
+
+    #[test]
+    fn samples_invalidated() {
+        let mut engine = generate_map(3);
+        let mut samples = FieldSamples::default();
+        let field = FieldType::TotalHousing;
+        samples.update(&engine, Some(field), MAX_SAMPLED_DEPTH);
+
+        let address = engine.state.qtree.get_address(0, 0).unwrap();
+        engine.state.qtree.get_leaf_mut(address).unwrap().tile = tiles::HousingTile {
+            density: 1000,
+            agents: vec![],
+        }
+        .into();
+        let data = visit_data(&engine, address);
+        let before = samples.get(&data).unwrap();
+
+        // nothing changes until the fields are updated
+        samples.update(&engine, Some(field), MAX_SAMPLED_DEPTH);
+        assert_eq!(samples.get(&data), Some(before));
+        engine.update_fields().unwrap();
+        samples.update(&engine, Some(field), MAX_SAMPLED_DEPTH);
+        let fields = &engine.state.qtree.get_leaf(address).unwrap().fields;
+        let after = field.scale(
+            &engine,
+            &engine.world_state,
+            &AgentCounts::default(),
+            fields,
+            &data,
+        );
+        assert_ne!(after, before);
+        assert_eq!(samples.get(&data), Some(after));
+    }
+
+    #[test]
+    fn samples_depth() {
+        let engine = generate_map(4);
+        let mut samples = FieldSamples::default();
+        let field = FieldType::TotalHousing;
+        let root = visit_data(&engine, quadtree::Address::from((vec![], 4)));
+        let leaf = visit_data(&engine, engine.state.qtree.get_address(0, 0).unwrap());
+
+        samples.update(&engine, Some(field), 2);
+        assert!(samples.get(&root).is_some());
+        assert_eq!(samples.get(&leaf), None);
+
+        // changing the depth takes the samples again
+        samples.update(&engine, Some(field), 4);
+        assert!(samples.get(&leaf).is_some());
+    }
+
+    /// Adds up the overlay colors of every node that would be drawn, like the draw loop.
+    struct DrawVisitor<'a> {
+        engine: &'a engine::Engine,
+        field: FieldType,
+        samples: Option<&'a FieldSamples>,
+        total: f32,
+    }
+
+    impl<'a> DrawVisitor<'a> {
+        fn draw(&mut self, fields: &engine::FieldsState, data: &quadtree::VisitData) {
+            let scale = self
+                .samples
+                .and_then(|samples| samples.get(data))
+                .unwrap_or_else(|| {
+                    self.field.scale(
+                        self.engine,
+                        &self.engine.world_state,
+                        &AgentCounts::default(),
+                        fields,
+                        data,
+                    )
+                });
+            let (r, g, b) = palette::Palette::default().color(scale, self.field.hue_ramp());
+            self.total += (r as f32 + g as f32 + b as f32) * scale;
+        }
+    }
+
+    impl<'a> quadtree::Visitor<BranchState<engine::FieldsState>, LeafState<engine::FieldsState>, ()>
+        for DrawVisitor<'a>
+    {
+        fn visit_branch_pre(
+            &mut self,
+            _branch: &BranchState<engine::FieldsState>,
+            _data: &quadtree::VisitData,
+        ) -> Result<bool, ()> {
+            Ok(true)
+        }
+
+        fn visit_leaf(
+            &mut self,
+            leaf: &LeafState<engine::FieldsState>,
+            data: &quadtree::VisitData,
+        ) -> Result<(), ()> {
+            self.draw(&leaf.fields, data);
+            Ok(())
+        }
+
+        fn visit_branch_post(
+            &mut self,
+            _branch: &BranchState<engine::FieldsState>,
+            _data: &quadtree::VisitData,
+        ) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn visit_cutoff_branch(
+            &mut self,
+            branch: &BranchState<engine::FieldsState>,
+            data: &quadtree::VisitData,
+        ) -> Result<(), ()> {
+            self.draw(&branch.fields, data);
+            Ok(())
+        }
+    }
+
+    /**
+     * Compares the cost of coloring the overlay for a dense viewport, where every leaf is visible,
+     * with and without samples. The tiles aren't painted, so this is only the part of the frame
+     * that the samples replace. Run with `--release -- --ignored --nocapture`.
+     *
+     * On a depth 8 map, visiting the nodes dominates and the two are within noise of each other,
+     * so the samples mostly help for fields that are more expensive to compute than these.
+     */
+    #[test]
+    #[ignore]
+    fn overlay_benchmark() {
+        const FRAMES: u32 = 20;
+        const MAX_DEPTH: u32 = 8;
+
+        let engine = generate_map(MAX_DEPTH);
+        let mut samples = FieldSamples::default();
+        for field in [FieldType::Population, FieldType::LandValue] {
+            let frames = |samples: Option<&FieldSamples>| {
+                let mut visitor = DrawVisitor {
+                    engine: &engine,
+                    field,
+                    samples,
+                    total: 0.0,
+                };
+                let start = std::time::Instant::now();
+                for _ in 0..FRAMES {
+                    let bounds = quadtree::Rect::corners(0, 0, 256, 256);
+                    engine
+                        .state
+                        .qtree
+                        .visit_rect_to_depth(&mut visitor, &bounds, MAX_DEPTH)
+                        .unwrap();
+                }
+                (start.elapsed() / FRAMES, visitor.total)
+            };
+
+            let (on_the_fly, expected) = frames(None);
+            let start = std::time::Instant::now();
+            samples.update(&engine, Some(field), MAX_SAMPLED_DEPTH);
+            let sampling = start.elapsed();
+            let (sampled, total) = frames(Some(&samples));
+            assert_eq!(total, expected);
+
+            println!(
+                "{}: {:?} per frame on the fly, {:?} per frame sampled, {:?} to sample",
+                field.label(),
+                on_the_fly,
+                sampled,
+                sampling
+            );
+        }
+    }
 }
@@ -0,0 +1,224 @@
+//! Formatting of the quantities shown in the UI. Distances and speeds are shown in the units that
+//! the user picked in the display options; durations are the same either way. Any words come from
+//! the Strings, so that they can be translated.
+
+use crate::strings::Strings;
+
+const METERS_PER_KILOMETER: f64 = 1000.0;
+const METERS_PER_MILE: f64 = 1609.344;
+const METERS_PER_FOOT: f64 = 0.3048;
+/// shorter distances are shown in meters or feet
+const MIN_LONG_DISTANCE: f64 = 0.1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, enum_iterator::IntoEnumIterator)]
+pub(crate) enum Units {
+    #[default]
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    /// The key of the name of these units; see Strings.
+    pub fn key(&self) -> &'static str {
+        match self {
+            Self::Metric => "units-metric",
+            Self::Imperial => "units-imperial",
+        }
+    }
+
+    /// The unit of long distances, e.g. for the length of a route.
+    pub fn distance_unit(&self) -> &'static str {
+        match self {
+            Self::Metric => "km",
+            Self::Imperial => "mi",
+        }
+    }
+
+    pub fn speed_unit(&self) -> &'static str {
+        match self {
+            Self::Metric => "km/h",
+            Self::Imperial => "mph",
+        }
+    }
+
+    /// The given distance in meters, in kilometers or miles.
+    pub fn distance_value(&self, meters: f64) -> f64 {
+        match self {
+            Self::Metric => meters / METERS_PER_KILOMETER,
+            Self::Imperial => meters / METERS_PER_MILE,
+        }
+    }
+
+    /// The given speed in m/s, in km/h or mph.
+    pub fn speed_value(&self, meters_per_second: f64) -> f64 {
+        self.distance_value(meters_per_second * 3600.0)
+    }
+
+    /// The given speed in km/h or mph, in m/s; the inverse of speed_value.
+    pub fn meters_per_second(&self, value: f64) -> f64 {
+        let meters_per_unit = match self {
+            Self::Metric => METERS_PER_KILOMETER,
+            Self::Imperial => METERS_PER_MILE,
+        };
+        value * meters_per_unit / 3600.0
+    }
+}
+
+/**
+ * e.g. "1.25 km", or "350 m" for distances under a tenth of a kilometer. Imperial distances under a
+ * tenth of a mile are shown in feet.
+ */
+pub(crate) fn distance(meters: f64, units: Units, strings: &Strings) -> String {
+    let long = units.distance_value(meters);
+    if !long.is_finite() {
+        return strings.get("not-available").to_string();
+    }
+    if long.abs() >= MIN_LONG_DISTANCE {
+        return format!("{:.2} {}", long, units.distance_unit());
+    }
+    match units {
+        Units::Metric => format!("{:.0} m", meters),
+        Units::Imperial => format!("{:.0} ft", meters / METERS_PER_FOOT),
+    }
+}
+
+/// e.g. "50 km/h"
+pub(crate) fn speed(meters_per_second: f64, units: Units, strings: &Strings) -> String {
+    let value = units.speed_value(meters_per_second);
+    if !value.is_finite() {
+        return strings.get("not-available").to_string();
+    }
+    format!("{:.0} {}", value, units.speed_unit())
+}
+
+/**
+ * e.g. "01:02:03". Durations of a day or more keep counting hours, e.g. "30:00:00". Fractions of a
+ * second are dropped.
+ */
+pub(crate) fn duration(seconds: f64, strings: &Strings) -> String {
+    if !seconds.is_finite() || seconds < 0.0 {
+        return strings.get("not-available").to_string();
+    }
+    clock(seconds as u64)
+}
+
+fn clock(seconds: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+/// Like duration, but with whole days split out, e.g. "1d 06:00:00", for offsets from now.
+pub(crate) fn offset(seconds: u64) -> String {
+    let days = seconds / 86400;
+    let time = clock(seconds % 86400);
+    if days > 0 {
+        format!("{}d {}", days, time)
+    } else {
+        time
+    }
+}
+
+/// e.g. "2022-06-01 12:34 UTC", or "never" for maps that were last saved without metadata
+pub(crate) fn timestamp(timestamp: Option<u64>, strings: &Strings) -> String {
+    timestamp
+        .and_then(|seconds| chrono::NaiveDateTime::from_timestamp_opt(seconds as i64, 0))
+        .map_or_else(
+            || strings.get("timestamp-never").to_string(),
+            |datetime| datetime.format("%Y-%m-%d %H:%M UTC").to_string(),
+        )
+}
+
+/// e.g. "23–41 min (typically 29)", from the fastest time to the p90 time, and the median.
+pub(crate) fn reliability(stats: &route::ReliabilityStats, strings: &Strings) -> String {
+    let minutes = |seconds: f64| (seconds / 60.0).round();
+    strings.format(
+        "reliability-range",
+        &[
+            ("min", &minutes(stats.min)),
+            ("max", &minutes(stats.p90)),
+            ("median", &minutes(stats.median)),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::*;
+    use crate::strings::Strings;
+
+    #[test]
+    fn durations() {
+        let strings = Strings::default();
+        assert_eq!(duration(0.0, &strings), "00:00:00");
+        assert_eq!(duration(45.9, &strings), "00:00:45");
+        assert_eq!(duration(3723.0, &strings), "01:02:03");
+        assert_eq!(duration(86399.0, &strings), "23:59:59");
+        // past a day, which used to wrap around
+        assert_eq!(duration(30.0 * 3600.0, &strings), "30:00:00");
+        assert_eq!(duration(-1.0, &strings), "n/a");
+        assert_eq!(duration(f64::NAN, &strings), "n/a");
+
+        assert_eq!(offset(59), "00:00:59");
+        assert_eq!(offset(30 * 3600), "1d 06:00:00");
+    }
+
+    #[test]
+    fn metric() {
+        let strings = Strings::default();
+        let units = Units::Metric;
+        assert_eq!(distance(0.0, units, &strings), "0 m");
+        assert_eq!(distance(99.6, units, &strings), "100 m");
+        assert_eq!(distance(1250.0, units, &strings), "1.25 km");
+        assert_eq!(distance(42_200.0, units, &strings), "42.20 km");
+        assert_eq!(speed(0.0, units, &strings), "0 km/h");
+        assert_eq!(speed(13.9, units, &strings), "50 km/h");
+        assert!((units.meters_per_second(36.0) - 10.0).abs() < 1e-9);
+        assert_eq!(speed(f64::INFINITY, units, &strings), "n/a");
+    }
+
+    #[test]
+    fn imperial() {
+        let strings = Strings::default();
+        let units = Units::Imperial;
+        assert_eq!(distance(0.0, units, &strings), "0 ft");
+        assert_eq!(distance(100.0, units, &strings), "328 ft");
+        assert_eq!(distance(METERS_PER_MILE * 1.5, units, &strings), "1.50 mi");
+        assert_eq!(speed(0.0, units, &strings), "0 mph");
+        // 30 m/s is about 67 mph
+        assert_eq!(speed(30.0, units, &strings), "67 mph");
+        assert!((units.speed_value(0.44704) - 1.0).abs() < 1e-9);
+        assert!((units.meters_per_second(1.0) - 0.44704).abs() < 1e-9);
+        assert_eq!(distance(f64::NAN, units, &strings), "n/a");
+    }
+
+    #[test]
+    fn words() {
+        let stats = route::ReliabilityStats {
+            planned: 1500.0,
+            min: 1380.0,
+            median: 1740.0,
+            p90: 2460.0,
+            max: 3000.0,
+            samples: 10,
+        };
+
+        let strings = Strings::default();
+        assert_eq!(timestamp(None, &strings), "never");
+        assert_eq!(timestamp(Some(0), &strings), "1970-01-01 00:00 UTC");
+        assert_eq!(reliability(&stats, &strings), "23–41 min (typically 29)");
+
+        let strings = Strings::from_bundle(concat!(
+            "not-available = k. A.\n",
+            "timestamp-never = nie\n",
+            "reliability-range = { $min }–{ $max } Min. (meist { $median })\n",
+        ))
+        .unwrap();
+        assert_eq!(duration(f64::NAN, &strings), "k. A.");
+        assert_eq!(timestamp(None, &strings), "nie");
+        assert_eq!(reliability(&stats, &strings), "23–41 Min. (meist 29)");
+    }
+}
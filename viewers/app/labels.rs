@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+/// Labels that are drawn first win when they would overlap, so the order of the variants matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum LabelPriority {
+    Station,
+    HighwayRef,
+    MetroLine,
+    Poi,
+}
+
+/// A label that is ready to be placed, in screen coordinates.
+#[derive(Debug, Clone)]
+pub(crate) struct Label<T> {
+    pub priority: LabelPriority,
+    pub center: egui::Pos2,
+    /// the size of the text before rotating it
+    pub size: egui::Vec2,
+    /// clockwise rotation in radians
+    pub angle: f32,
+    /// whatever is needed to draw the label, e.g. the laid out text
+    pub content: T,
+}
+
+impl<T> Label<T> {
+    /// The axis-aligned screen rect that the rotated text covers.
+    pub fn bounds(&self) -> egui::Rect {
+        let (sin, cos) = self.angle.sin_cos();
+        let half = egui::vec2(
+            (self.size.x * cos).abs() + (self.size.y * sin).abs(),
+            (self.size.x * sin).abs() + (self.size.y * cos).abs(),
+        ) / 2.0;
+        egui::Rect::from_min_max(self.center - half, self.center + half)
+    }
+
+    /**
+     * The position of the top left corner of the text, which is what egui rotates the text around,
+     * such that the rotated text is centered on the label's center.
+     */
+    pub fn text_pos(&self) -> egui::Pos2 {
+        let (sin, cos) = self.angle.sin_cos();
+        let half = self.size / 2.0;
+        self.center - egui::vec2(half.x * cos - half.y * sin, half.x * sin + half.y * cos)
+    }
+}
+
+/**
+ * Keeps track of the screen space taken by labels that have already been placed, so that later
+ * labels can be dropped instead of drawn on top of them. Placed rects are bucketed into a coarse
+ * grid so that each check only looks at nearby labels.
+ */
+#[derive(Debug)]
+pub(crate) struct LabelGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<egui::Rect>>,
+}
+
+impl LabelGrid {
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "cell size must be positive");
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell_range(&self, rect: egui::Rect) -> impl Iterator<Item = (i32, i32)> {
+        let cell = |v: f32| (v / self.cell_size).floor() as i32;
+        let (x1, y1) = (cell(rect.min.x), cell(rect.min.y));
+        let (x2, y2) = (cell(rect.max.x), cell(rect.max.y));
+        (x1..=x2).flat_map(move |x| (y1..=y2).map(move |y| (x, y)))
+    }
+
+    /// Claim the rect if it doesn't overlap anything placed before. Returns whether it was placed.
+    pub fn try_place(&mut self, rect: egui::Rect) -> bool {
+        let overlaps = self.cell_range(rect).any(|cell| {
+            self.cells
+                .get(&cell)
+                .into_iter()
+                .flatten()
+                .any(|other| other.intersects(rect))
+        });
+        if overlaps {
+            return false;
+        }
+        for cell in self.cell_range(rect).collect::<Vec<_>>() {
+            self.cells.entry(cell).or_default().push(rect);
+        }
+        true
+    }
+}
+
+/**
+ * Greedily choose the labels to draw: higher priority labels are placed first, and any label that
+ * would overlap one that was already placed is dropped. Labels of the same priority keep their
+ * order.
+ */
+pub(crate) fn declutter<T>(mut labels: Vec<Label<T>>, cell_size: f32) -> Vec<Label<T>> {
+    labels.sort_by_key(|label| label.priority);
+    let mut grid = LabelGrid::new(cell_size);
+    labels
+        .into_iter()
+        .filter(|label| grid.try_place(label.bounds()))
+        .collect()
+}
+
+/**
+ * The point halfway along a polyline, by length, and the direction of the polyline there as a
+ * clockwise angle in screen coordinates. The angle is flipped as needed so that text drawn along it
+ * is never upside down. Returns None if the polyline has no length.
+ */
+pub(crate) fn path_midpoint(points: &[egui::Pos2]) -> Option<(egui::Pos2, f32)> {
+    let total: f32 = points
+        .windows(2)
+        .map(|pair| pair[0].distance(pair[1]))
+        .sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut remaining = total / 2.0;
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let length = a.distance(b);
+        if length > 0.0 && remaining <= length {
+            let dir = (b - a) / length;
+            let mut angle = dir.y.atan2(dir.x);
+            if angle > std::f32::consts::FRAC_PI_2 {
+                angle -= std::f32::consts::PI;
+            } else if angle < -std::f32::consts::FRAC_PI_2 {
+                angle += std::f32::consts::PI;
+            }
+            return Some((a + dir * remaining, angle));
+        }
+        remaining -= length;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::labels::*;
+
+    fn label(priority: LabelPriority, x: f32, y: f32, content: &str) -> Label<String> {
+        Label {
+            priority,
+            center: egui::pos2(x, y),
+            size: egui::vec2(40.0, 10.0),
+            angle: 0.0,
+            content: content.to_string(),
+        }
+    }
+
+    fn contents(labels: &[Label<String>]) -> Vec<&str> {
+        labels.iter().map(|label| label.content.as_str()).collect()
+    }
+
+    #[test]
+    fn grid_rejects_overlaps() {
+        let mut grid = LabelGrid::new(16.0);
+        let rect =
+            |x: f32, y: f32| egui::Rect::from_min_size(egui::pos2(x, y), egui::vec2(40.0, 10.0));
+        assert!(grid.try_place(rect(0.0, 0.0)));
+        assert!(!grid.try_place(rect(30.0, 5.0)));
+        assert!(grid.try_place(rect(50.0, 0.0)));
+        // far apart in a different cell, and across the origin
+        assert!(grid.try_place(rect(-100.0, -100.0)));
+        assert!(!grid.try_place(rect(-90.0, -95.0)));
+    }
+
+    #[test]
+    fn declutter_by_priority() {
+        let labels = vec![
+            label(LabelPriority::MetroLine, 0.0, 0.0, "line"),
+            label(LabelPriority::HighwayRef, 20.0, 0.0, "US-101"),
+            label(LabelPriority::Station, 30.0, 5.0, "station"),
+            label(LabelPriority::HighwayRef, 200.0, 0.0, "I-80"),
+            label(LabelPriority::MetroLine, 0.0, 100.0, "other line"),
+        ];
+
+        // the station beats the overlapping highway ref, which in turn would have beaten the line
+        assert_eq!(
+            contents(&declutter(labels, 32.0)),
+            vec!["station", "I-80", "other line"]
+        );
+    }
+
+    #[test]
+    fn rotated_bounds() {
+        let mut label = label(LabelPriority::HighwayRef, 100.0, 100.0, "US-101");
+        let bounds = label.bounds();
+        assert_eq!(bounds.center(), egui::pos2(100.0, 100.0));
+        assert_eq!(bounds.size(), egui::vec2(40.0, 10.0));
+
+        label.angle = std::f32::consts::FRAC_PI_2;
+        let size = label.bounds().size();
+        assert!(
+            (size.x - 10.0).abs() < 1e-3 && (size.y - 40.0).abs() < 1e-3,
+            "{:?}",
+            size
+        );
+
+        // the text is rotated around its top left corner, which ends up to the top right
+        let pos = label.text_pos();
+        assert!(
+            (pos.x - 105.0).abs() < 1e-3 && (pos.y - 80.0).abs() < 1e-3,
+            "{:?}",
+            pos
+        );
+    }
+
+    #[test]
+    fn midpoint_along_path() {
+        let points = [
+            egui::pos2(0.0, 0.0),
+            egui::pos2(10.0, 0.0),
+            egui::pos2(10.0, 30.0),
+        ];
+        let (pos, angle) = path_midpoint(&points).unwrap();
+        assert_eq!(pos, egui::pos2(10.0, 10.0));
+        assert!((angle - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+
+        // text along a path going right to left is flipped to stay upright
+        let (pos, angle) = path_midpoint(&[egui::pos2(10.0, 0.0), egui::pos2(0.0, 0.0)]).unwrap();
+        assert_eq!(pos, egui::pos2(5.0, 0.0));
+        assert!(angle.abs() < 1e-6);
+
+        assert!(path_midpoint(&[egui::pos2(1.0, 1.0)]).is_none());
+        assert!(path_midpoint(&[egui::pos2(1.0, 1.0), egui::pos2(1.0, 1.0)]).is_none());
+    }
+}
@@ -3,6 +3,16 @@ mod bootstrap;
 mod chart;
 mod content;
 mod field_overlay;
+mod format;
+mod labels;
+mod profiling;
+mod quality;
+#[cfg(feature = "replay")]
+mod replay;
+mod strings;
+mod theme;
+mod vacancy_markers;
 
 pub use app::App;
 pub use bootstrap::bootstrap;
+pub use quality::QualityPreset;
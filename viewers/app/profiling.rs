@@ -0,0 +1,110 @@
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{reload, Registry};
+
+type CaptureLayer = tracing_chrome::ChromeLayer<Registry>;
+type CaptureHandle = reload::Handle<Option<CaptureLayer>, Registry>;
+
+lazy_static::lazy_static! {
+    /**
+     * Handle for swapping the capture layer in and out of the global subscriber. The layer is None
+     * unless a capture is running, which disables every span, so tracing is free when it's off.
+     *
+     * This is None if some other subscriber was installed first.
+     */
+    static ref CAPTURE_HANDLE: Option<CaptureHandle> = {
+        let (layer, handle) = reload::Layer::new(None);
+        tracing::subscriber::set_global_default(Registry::default().with(layer))
+            .ok()
+            .map(|()| handle)
+    };
+}
+
+/**
+ * Captures the spans emitted by the engine and the app into a trace file in the Chrome tracing
+ * format, which can be opened in chrome://tracing or https://ui.perfetto.dev.
+ */
+pub(crate) struct Profiler {
+    pub path: String,
+    capture: Option<tracing_chrome::FlushGuard>,
+    error: Option<String>,
+}
+
+impl Profiler {
+    pub fn new(paths: &paths::Paths) -> Self {
+        Self {
+            path: paths
+                .dir(paths::Dir::Output)
+                .join("trace.json")
+                .to_string_lossy()
+                .into_owned(),
+            capture: None,
+            error: None,
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    pub fn start_capture(&mut self) -> anyhow::Result<()> {
+        let handle = CAPTURE_HANDLE
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("another tracing subscriber is already installed"))?;
+        // the capture would still write to the old file until it is stopped
+        self.stop_capture()?;
+
+        paths::create_parent(std::path::Path::new(&self.path))?;
+        let file = std::fs::File::create(&self.path)?;
+        let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+            .writer(file)
+            .include_args(true)
+            .build();
+        handle.reload(Some(layer))?;
+        self.capture = Some(guard);
+        Ok(())
+    }
+
+    /// Stop capturing, finishing the trace file. Does nothing if no capture is running.
+    pub fn stop_capture(&mut self) -> anyhow::Result<()> {
+        if let Some(guard) = self.capture.take() {
+            if let Some(handle) = CAPTURE_HANDLE.as_ref() {
+                handle.reload(None)?;
+            }
+            // NOTE: dropping the guard writes the end of the trace file
+            drop(guard);
+        }
+        Ok(())
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Trace file:");
+            ui.add_enabled(
+                !self.is_capturing(),
+                egui::TextEdit::singleline(&mut self.path),
+            );
+        });
+
+        if self.is_capturing() {
+            ui.horizontal(|ui| {
+                if ui.button("Stop capture").clicked() {
+                    self.error = self.stop_capture().err().map(|e| e.to_string());
+                }
+                ui.label("Capturing...");
+            });
+        } else if ui.button("Start capture").clicked() {
+            self.error = self.start_capture().err().map(|e| e.to_string());
+        }
+
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        // make sure the trace file is complete if the app exits while capturing
+        let _ = self.stop_capture();
+    }
+}
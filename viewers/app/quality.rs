@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+
+/**
+ * How much detail to draw, and how much memory the traffic model may use. Phones can't keep up
+ * with the full detail on large maps, so Auto picks a level based on the frame rate instead.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, enum_iterator::IntoEnumIterator)]
+pub enum QualityPreset {
+    #[default]
+    High,
+    Medium,
+    Low,
+    Auto,
+}
+
+impl QualityPreset {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::High => "High",
+            Self::Medium => "Medium",
+            Self::Low => "Low",
+            Self::Auto => "Auto",
+        }
+    }
+
+    /// The name used for this preset on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::High => "high",
+            Self::Medium => "medium",
+            Self::Low => "low",
+            Self::Auto => "auto",
+        }
+    }
+
+    /// The level to use when loading a map, and to start from for Auto.
+    pub fn initial_level(&self) -> QualityLevel {
+        match self {
+            Self::High => QualityLevel::High,
+            Self::Medium | Self::Auto => QualityLevel::Medium,
+            Self::Low => QualityLevel::Low,
+        }
+    }
+}
+
+impl std::fmt::Display for QualityPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for QualityPreset {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        use enum_iterator::IntoEnumIterator;
+
+        Self::into_enum_iter()
+            .find(|preset| preset.name() == name)
+            .ok_or_else(|| format!("unknown quality preset: {}", name))
+    }
+}
+
+/// A concrete level of detail, which the fixed presets map to and Auto moves between.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QualityLevel {
+    Low,
+    Medium,
+    High,
+}
+
+/// The display options that a quality level controls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QualitySettings {
+    pub min_tile_size: u32,
+    pub spline_resolution: u32,
+    pub field_resolution: u32,
+    /// whether to draw agents moving along their routes
+    pub show_agents: bool,
+    /// the depth down to which field overlays are sampled ahead of time
+    pub overlay_depth: u32,
+}
+
+impl QualityLevel {
+    pub fn settings(&self) -> QualitySettings {
+        match self {
+            Self::High => QualitySettings {
+                min_tile_size: 2,
+                spline_resolution: 5,
+                field_resolution: 10,
+                show_agents: true,
+                overlay_depth: 10,
+            },
+            Self::Medium => QualitySettings {
+                min_tile_size: 4,
+                spline_resolution: 10,
+                field_resolution: 20,
+                show_agents: true,
+                overlay_depth: 8,
+            },
+            Self::Low => QualitySettings {
+                min_tile_size: 8,
+                spline_resolution: 20,
+                field_resolution: 40,
+                show_agents: false,
+                overlay_depth: 6,
+            },
+        }
+    }
+
+    /**
+     * Limits on the traffic model, which can only be applied when a map is loaded. High uses
+     * whatever the map asks for.
+     */
+    pub fn load_overrides(&self) -> engine::LoadOverrides {
+        match self {
+            Self::High => engine::LoadOverrides::default(),
+            Self::Medium => engine::LoadOverrides {
+                max_traffic_history_snapshots: Some(24),
+                min_traffic_grid_block_size: Some(1000),
+            },
+            Self::Low => engine::LoadOverrides {
+                max_traffic_history_snapshots: Some(12),
+                min_traffic_grid_block_size: Some(2000),
+            },
+        }
+    }
+
+    fn lower(&self) -> Option<Self> {
+        match self {
+            Self::High => Some(Self::Medium),
+            Self::Medium => Some(Self::Low),
+            Self::Low => None,
+        }
+    }
+
+    fn higher(&self) -> Option<Self> {
+        match self {
+            Self::High => None,
+            Self::Medium => Some(Self::High),
+            Self::Low => Some(Self::Medium),
+        }
+    }
+}
+
+/// the length of the window that the frame rate is averaged over, in seconds
+const FRAME_WINDOW: f64 = 2.0;
+/// step down a level when the average frame rate drops below this
+const SLOW_FRAME_RATE: f64 = 24.0;
+/// step up a level when the average frame rate stays above this for long enough
+const FAST_FRAME_RATE: f64 = 50.0;
+/// how long the frame rate needs to stay fast before stepping up, in seconds
+const STEP_UP_DELAY: f64 = 10.0;
+const MAX_STEP_UP_DELAY: f64 = 160.0;
+
+/**
+ * Moves between quality levels based on the frame rate averaged over a rolling window. Stepping
+ * down happens as soon as a full window is slow, but stepping up takes much longer, and the
+ * thresholds are far apart, so the level doesn't flip back and forth around a single frame rate.
+ * Each time the level has to come back down, it waits twice as long before trying to step up again.
+ */
+#[derive(Debug)]
+pub(crate) struct AutoQuality {
+    level: QualityLevel,
+    /// the durations of the frames in the current window, in seconds
+    frames: VecDeque<f64>,
+    /// the total duration of the frames in the window
+    window: f64,
+    /// how long the frame rate has been fast, in seconds
+    fast_for: f64,
+    step_up_delay: f64,
+}
+
+impl AutoQuality {
+    pub fn new(level: QualityLevel) -> Self {
+        Self {
+            level,
+            frames: VecDeque::new(),
+            window: 0.0,
+            fast_for: 0.0,
+            step_up_delay: STEP_UP_DELAY,
+        }
+    }
+
+    pub fn level(&self) -> QualityLevel {
+        self.level
+    }
+
+    /// The average frame rate over the window, once a whole window has been seen.
+    pub fn frame_rate(&self) -> Option<f64> {
+        (self.window >= FRAME_WINDOW).then(|| self.frames.len() as f64 / self.window)
+    }
+
+    /**
+     * Record a frame that took the given number of seconds. Returns the new level if it changed,
+     * in which case the window starts over, since the old frames were drawn at the old level.
+     */
+    pub fn update(&mut self, elapsed: f64) -> Option<QualityLevel> {
+        if !(elapsed.is_finite() && elapsed > 0.0) {
+            return None;
+        }
+        self.frames.push_back(elapsed);
+        self.window += elapsed;
+        while let Some(oldest) = self.frames.front() {
+            if self.window - oldest < FRAME_WINDOW {
+                break;
+            }
+            self.window -= oldest;
+            self.frames.pop_front();
+        }
+
+        let frame_rate = self.frame_rate()?;
+        let next = if frame_rate < SLOW_FRAME_RATE {
+            self.fast_for = 0.0;
+            let lower = self.level.lower();
+            if lower.is_some() {
+                self.step_up_delay = (self.step_up_delay * 2.0).min(MAX_STEP_UP_DELAY);
+            }
+            lower
+        } else if frame_rate > FAST_FRAME_RATE {
+            self.fast_for += elapsed;
+            if self.fast_for >= self.step_up_delay {
+                self.level.higher()
+            } else {
+                None
+            }
+        } else {
+            self.fast_for = 0.0;
+            None
+        };
+
+        if let Some(level) = next {
+            self.level = level;
+            self.frames.clear();
+            self.window = 0.0;
+            self.fast_for = 0.0;
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quality::*;
+
+    /// Feed the controller the given frame rate for the given number of seconds, and return the
+    /// levels that it stepped to.
+    fn feed(auto: &mut AutoQuality, frame_rate: f64, seconds: f64) -> Vec<QualityLevel> {
+        let frames = (frame_rate * seconds) as usize;
+        (0..frames)
+            .filter_map(|_| auto.update(1.0 / frame_rate))
+            .collect()
+    }
+
+    #[test]
+    fn preset_round_trip() {
+        use enum_iterator::IntoEnumIterator;
+
+        for preset in QualityPreset::into_enum_iter() {
+            assert_eq!(preset.to_string().parse(), Ok(preset));
+        }
+        assert!("ultra".parse::<QualityPreset>().is_err());
+    }
+
+    #[test]
+    fn steps_down() {
+        let mut auto = AutoQuality::new(QualityLevel::High);
+
+        // a single slow frame isn't enough
+        assert_eq!(auto.update(0.5), None);
+        assert_eq!(feed(&mut auto, 60.0, 5.0), vec![]);
+
+        assert_eq!(
+            feed(&mut auto, 15.0, 10.0),
+            vec![QualityLevel::Medium, QualityLevel::Low]
+        );
+        assert_eq!(auto.level(), QualityLevel::Low);
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut auto = AutoQuality::new(QualityLevel::Medium);
+
+        // in between the thresholds, nothing changes
+        assert_eq!(feed(&mut auto, 35.0, 60.0), vec![]);
+
+        // stepping up takes a while
+        assert_eq!(feed(&mut auto, 60.0, STEP_UP_DELAY / 2.0), vec![]);
+        assert_eq!(
+            feed(&mut auto, 60.0, STEP_UP_DELAY),
+            vec![QualityLevel::High]
+        );
+
+        // if that turns out to be too slow, it takes longer to try again
+        assert_eq!(feed(&mut auto, 20.0, 3.0), vec![QualityLevel::Medium]);
+        assert_eq!(feed(&mut auto, 60.0, STEP_UP_DELAY * 1.5), vec![]);
+        assert_eq!(
+            feed(&mut auto, 60.0, STEP_UP_DELAY),
+            vec![QualityLevel::High]
+        );
+    }
+}
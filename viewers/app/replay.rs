@@ -0,0 +1,197 @@
+use crate::app::App;
+
+pub(crate) struct ReplayControls {
+    pub(crate) path: String,
+    pub(crate) state: Option<ReplayState>,
+    pub(crate) error: Option<String>,
+}
+
+impl ReplayControls {
+    pub(crate) fn new(paths: &paths::Paths) -> Self {
+        Self {
+            path: paths
+                .dir(paths::Dir::Output)
+                .join("traffic.replay")
+                .to_string_lossy()
+                .into_owned(),
+            state: None,
+            error: None,
+        }
+    }
+
+    /// The current replay frame if a replay is open, otherwise the engine's current world state.
+    pub(crate) fn world_state<'a>(
+        &'a self,
+        engine: &'a engine::Engine,
+    ) -> &'a route::WorldStateImpl {
+        self.state
+            .as_ref()
+            .and_then(|replay| replay.replay.world_state_at(replay.time))
+            .unwrap_or(&engine.world_state)
+    }
+}
+
+pub(crate) struct ReplayState {
+    pub(crate) replay: engine::Replay,
+    /// the simulation time being shown, which is independent of the engine's time
+    pub(crate) time: u64,
+    pub(crate) playing: bool,
+}
+
+impl ReplayState {
+    fn advance(&mut self, time_step: u64) {
+        if let Some((_, end)) = self.replay.time_range() {
+            self.time = u64::min(self.time + time_step, end);
+            if self.time == end {
+                self.playing = false;
+            }
+        }
+    }
+}
+
+impl App {
+    /**
+     * Open a replay file. Until it is closed, the engine is paused and the traffic overlays and
+     * agents are drawn from the recorded frames instead.
+     */
+    pub fn open_replay(&mut self, path: &std::path::Path) -> Result<(), engine::Error> {
+        let replay = engine::Replay::load_file(path)?;
+        let (start, _) = replay
+            .time_range()
+            .ok_or_else(|| engine::Error::ReplayError("replay is empty".to_string()))?;
+        self.engine.time_state.paused = true;
+        self.replay.state = Some(ReplayState {
+            replay,
+            time: start,
+            playing: false,
+        });
+        Ok(())
+    }
+
+    pub(crate) fn close_replay(&mut self) {
+        self.replay.state = None;
+    }
+
+    pub(crate) fn replay_open(&self) -> bool {
+        self.replay.state.is_some()
+    }
+
+    /// Play the open replay forward, if any. Returns whether a replay is open.
+    pub(crate) fn advance_replay(&mut self, elapsed: f64) -> bool {
+        match &mut self.replay.state {
+            Some(replay) => {
+                if replay.playing {
+                    let rate = self.engine.time_state.playback_rate as f64;
+                    replay.advance((rate * elapsed) as u64);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The simulation time being shown by the open replay, if any.
+    pub(crate) fn replay_time(&self) -> Option<u64> {
+        self.replay.state.as_ref().map(|replay| replay.time)
+    }
+
+    /// The positions of the agents in the current frame of the open replay, if any.
+    pub(crate) fn replay_agent_positions(&self) -> Vec<(u64, (f32, f32))> {
+        match &self.replay.state {
+            Some(replay) => replay.replay.agent_positions_at(replay.time),
+            None => vec![],
+        }
+    }
+
+    /// The world state to draw, which is the current replay frame if a replay is open.
+    pub(crate) fn world_state(&self) -> &route::WorldStateImpl {
+        self.replay.world_state(&self.engine)
+    }
+
+    pub(crate) fn draw_replay(&mut self, ui: &mut egui::Ui) {
+        ui.label("Replay file:");
+        ui.text_edit_singleline(&mut self.replay.path);
+        let path = std::path::PathBuf::from(&self.replay.path);
+
+        match self.engine.recorder() {
+            Some(recorder) => {
+                let megabytes = recorder.bytes_written() as f64 / 1e6;
+                if recorder.is_full() {
+                    ui.label(format!(
+                        "Recording stopped, the replay file is full ({:.1} MB)",
+                        megabytes
+                    ));
+                } else {
+                    ui.label(format!("Recording ({:.1} MB)", megabytes));
+                }
+                if ui.button("Stop recording").clicked() {
+                    self.replay.error = self.engine.stop_recording().err().map(|e| e.to_string());
+                }
+            }
+            None => {
+                if ui
+                    .add_enabled(
+                        self.replay.state.is_none(),
+                        egui::Button::new("Start recording"),
+                    )
+                    .clicked()
+                {
+                    self.replay.error = paths::create_parent(&path)
+                        .map_err(engine::Error::from)
+                        .and_then(|()| {
+                            self.engine
+                                .start_recording(&path, engine::ReplayConfig::default())
+                        })
+                        .err()
+                        .map(|e| e.to_string());
+                }
+            }
+        }
+
+        ui.separator();
+
+        match &mut self.replay.state {
+            Some(replay) => {
+                let (start, end) = replay.replay.time_range().unwrap_or_default();
+                ui.label(format!(
+                    "{} traffic snapshots, {} agent keyframes",
+                    replay.replay.timestamps().count(),
+                    replay.replay.keyframe_timestamps().count()
+                ));
+                if replay.replay.is_truncated() {
+                    ui.label("The replay file was cut short; its last record is missing");
+                }
+                let mut time_state = self.engine.time_state.clone();
+                time_state.current_time = replay.time;
+                ui.label(time_state.pretty_current_date_time());
+                ui.add(egui::Slider::new(&mut replay.time, start..=end).show_value(false));
+                if ui
+                    .button(if replay.playing { "Pause" } else { "Play" })
+                    .clicked()
+                {
+                    replay.playing = !replay.playing;
+                }
+                if ui.button("Close replay").clicked() {
+                    self.close_replay();
+                    // selections may refer to agents that only existed in the replay
+                    self.reset_transient_state();
+                }
+            }
+            None => {
+                if ui
+                    .add_enabled(
+                        self.engine.recorder().is_none(),
+                        egui::Button::new("Open replay"),
+                    )
+                    .clicked()
+                {
+                    self.replay.error = self.open_replay(&path).err().map(|e| e.to_string());
+                }
+            }
+        }
+
+        if let Some(error) = &self.replay.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    }
+}
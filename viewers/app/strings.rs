@@ -0,0 +1,461 @@
+//! The text shown in the UI, looked up by key so that it can be translated. English is compiled in,
+//! and a bundle in the config directory can replace any of it. Bundles use a subset of the Fluent
+//! syntax: one `key = value` per line, `#` comments, and `{ $name }` placeholders.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// the name of the bundle in the config directory that replaces the English text
+pub(crate) const BUNDLE_NAME: &str = "strings.ftl";
+
+const ENGLISH: &str = "
+# panels
+panel-time = Time
+panel-overlay = Overlay
+panel-stats = Stats
+panel-display-options = Display options
+panel-diagnostics = Diagnostics
+panel-alerts = Alerts
+panel-route-query = Query routes
+panel-isochrone = Isochrone
+panel-congestion = Congestion analysis
+panel-catchments = Station catchments
+panel-commute-flows = Commute flows
+panel-calibration = Calibration
+panel-timeline = Upcoming events
+panel-agent-detail = Agent detail
+panel-replay = Replay
+panel-planned-changes = Planned changes
+window-segment-detail = Segment detail
+benchmark-running = Benchmark running...
+dismiss = Dismiss
+more-alerts = (+{ $count } more)
+not-available = n/a
+clear = Clear
+copy-csv = Copy CSV
+copy-report = Copy report
+pick-tile = Pick tile
+waiting-for-tile = <waiting for tile selection>
+calculating = Calculating...
+timestamp-never = never
+reliability-range = { $min }–{ $max } min (typically { $median })
+trigger-cadences = Trigger cadences:
+
+# time
+current-time = Current time: { $time }
+time-of-day = Time of day: { $band }
+playback-rate = Playback rate:
+pause = Pause
+resume = Resume
+upcoming-scenario-events = Upcoming scenario events ({ $count }):
+skip-minute = +1min
+skip-hour = +1hr
+skip-six-hours = +6hrs
+skip-day = +1day
+
+# alerts
+pause-on-critical-alerts = Pause on critical alerts
+dismiss-all = Dismiss all
+parking-errors = Parking errors: { $count }
+edge-counting-errors = Edge counting errors: { $count }
+no-alerts = No alerts
+
+# stats
+refresh = Refresh
+stats-as-of = As of { $time }
+stats-population = Population: { $count }
+stats-employment = Employment: { $count }
+stats-employment-rate = Employment rate: { $percent }%
+stats-agents = Agents: { $count }
+stats-employed-agents = Employed agents: { $count }
+stats-traveling = Traveling: { $count }
+stats-parked-cars = Parked cars: { $count }
+stats-drift = Not yet in fields: { $people } people, { $employed } employed
+stats-drift-hover = The fields are updated once a day
+job-quits = Job quits, by main reason:
+job-quits-unexplained = No main reason: { $count }
+
+# display options
+reset-view = Reset view
+about-map = About this map
+quality = Quality:
+current-quality-level = Current level: { $level }
+quality-reload-note = Traffic model limits apply the next time a map is loaded.
+min-tile-size = Min tile size:
+spline-resolution = Spline resolution:
+field-resolution = Field resolution:
+overlay-depth = Overlay sampling depth:
+show-agents = Show moving agents
+show-all-railways = Show all railways
+show-railway-junctions = Show railway junctions
+show-highway-junctions = Show highway junctions
+show-labels = Show labels
+palette = Palette:
+display-time-of-day = Time of day:
+follow-the-clock = Follow the clock
+units = Units:
+units-metric = Metric
+units-imperial = Imperial
+
+# overlay
+overlay-none = None
+markers = Markers:
+markers-dropped = { $count } more markers not shown; zoom in to see them
+blurred-fields = Blurred fields:
+
+# diagnostics
+frame-rate = Frame rate: { $rate }
+drawn-tiles = Tiles: { $count }
+metro-vertices = Metro vertices: { $count }
+highway-vertices = Highway vertices: { $count }
+drawn-agents = Agents (drawn): { $count }
+total-agents = Agents (total): { $count }
+graph-nodes = Graph nodes: { $count }
+graph-edges = Graph edges: { $count }
+terminal-nodes = Terminal nodes ({ $mode }): { $count }
+parking-areas = Parking areas: { $count }
+failed-to-work-today = Failed to route to work today: { $count }
+failed-to-work-total = Failed to route to work (total): { $count }
+teleported-home = Teleported home: { $count }
+routes-aborted = Routes aborted: { $count }
+routes-requeried = Routes requeried: { $count }
+stuck-agents-recovered = Stuck agents recovered: { $count }
+route-queries = Route queries: { $count }
+route-query-p95 = Route query p95: { $duration }
+route-deadline-misses = Route deadline misses: { $count } ({ $percent }%)
+blocked-on-route-queries = Blocked on route queries: { $duration }
+route-start-deadline = Route start deadline: { $deadline } s
+route-start-deadline-suggested = Route start deadline: { $deadline } s (suggested: { $suggested } s)
+cursor-coords = Coords: { $x }, { $y }
+cursor-coords-none = Coords: { $none }
+show-lat-lon = Show latitude/longitude
+cursor-not-over-map = Cursor is not over the map
+estimate-memory = Estimate memory use
+memory-subsystem = Subsystem
+memory-items = Items
+memory-size = Size
+memory-total = Total
+repair-bounds = Repair out-of-bounds locations
+bounds-ok = Everything is inside the map
+bounds-repaired = Moved { $count } things inside the map:
+stop-benchmark = Stop benchmark
+benchmark-hours = Benchmark duration (hours):
+benchmark = Benchmark
+
+# about this map
+untitled-map = Untitled map
+map-author = By { $author }
+map-created = Created: { $timestamp }
+map-modified = Modified: { $timestamp }
+map-origin = Origin: { $origin }
+map-tile-size = { $distance } per tile
+not-georeferenced = Not georeferenced
+
+# route queries
+route-start = [a] Start: { $x }, { $y }
+route-no-start = [a] No start selected
+route-stop = [z] Stop: { $x }, { $y }
+route-no-stop = [z] No stop selected
+route-modes = Modes:
+route-debug = Debug
+route-debug-hover = Break down the cost of each leg of the route
+pick-random = Pick random
+pick-random-disabled = The map needs both housing and workplaces
+current-routes = Current routes:
+route-duration = Route #{ $route } duration: { $duration }
+route-reliability = Route #{ $route } usually takes { $reliability }
+route-reliability-hover = Fastest to 90th percentile time across the traffic history
+route-length = Route #{ $route } length: { $distance }
+route-speed = Speed along the route:
+export-csv = Export CSV
+recompute = Recompute
+stale-network = (stale — network changed)
+stale-traffic = (stale — traffic changed)
+route-profile-saved = Saved route profile to { $path }
+route-profile-failed = Failed to save route profile: { $error }
+weighted-predicted-traffic = Weighted with predicted traffic
+weighted-free-flow = Weighted for free-flow traffic
+chosen-route = Chosen route: { $seconds } s
+free-flow-route = Free-flow route, with traffic: { $seconds } s
+same-as-free-flow = Same as the free-flow route
+leg = Leg
+leg-base = Base (s)
+leg-congestion = Congestion
+leg-parking = Parking (s)
+leg-junctions = Junctions (s)
+leg-total = Total (s)
+leg-diverges = { $leg } (diverges)
+leg-metro = Metro
+leg-board-metro = Board metro
+leg-leave-metro = Leave metro
+leg-highway = Highway
+leg-ramp = Ramp
+leg-walk = Walk
+leg-local-roads = Local roads
+leg-change-mode = Change mode
+leg-other = Other
+
+# isochrones
+compare-reduced-mobility = Compare with reduced mobility
+walking-speed-multiplier = Walking speed multiplier:
+transfer-penalty = Transfer penalty (seconds):
+isochrone-focus = Focus: ({ $x }, { $y })
+isochrone-mode = Mode: { $mode }
+mobility-standard = Standard
+mobility-reduced = Reduced mobility
+reachable-stops = Reachable stops within max travel time: { $standard } standard, { $reduced } reduced mobility
+max-travel-time = Max travel time (minutes):
+quantization-step = Quantization step (minutes):
+everything-reachable = Everything reachable is within { $minutes } minutes
+cannot-route-from = Can't route from ({ $x }, { $y })
+use-nearest-routable = Use nearest routable point ({ $distance } away)?
+
+# congestion analysis
+filter-visible = Filter visible
+historical-congestion = Historical congestion
+current-histogram = Current histogram
+chart-scale = Scale: { $scale }
+congestion-current = Current: { $value } over { $count } { $items }
+congestion-with-traffic = { $count } { $items } with traffic
+congestion-highways = Highways
+congestion-metros = Metros
+congestion-local-roads = Local roads
+congestion-parking = Parking
+congestion-segments = segments
+congestion-zones = zones
+quantity-sum = Sum
+quantity-mean = Mean
+quantity-rms = RMS
+
+# station catchments
+color-stations-by-catchment = Color stations by catchment
+max-walking-time = Max walking time (minutes):
+no-served-stations = No stations are served by metro lines
+catchment-station = Station
+catchment-people = People
+catchment-jobs = Jobs
+catchment-unique-people = Unique people
+catchment-unique-jobs = Unique jobs
+
+# commute flows
+show-desire-lines = Show desire lines
+show-desire-lines-hover = Straight lines between the zones with the most commutes yesterday
+number-of-lines = Number of lines:
+no-commutes = No commutes were completed yesterday
+commutes-yesterday = Yesterday: { $trips } commutes between { $pairs } pairs of zones
+
+# calibration
+observations-file = Observations file:
+importer-id-map = Importer id map (optional):
+compare = Compare
+color-segments-by-ratio = Color segments by simulated/observed
+calibration-aggregate = MAPE: { $mape }, RMSE: { $rmse } vehicles/h over { $samples } samples
+calibration-unresolved = { $count } observations did not match any highway segment
+calibration-segment = Segment
+calibration-mape = MAPE
+calibration-rmse = RMSE
+calibration-ratio = Ratio
+
+# upcoming events
+triggers-to-show = Triggers to show:
+no-upcoming-triggers = No upcoming triggers
+timeline-horizon = Now to +{ $offset }
+
+# planned changes
+nothing-planned = Nothing planned yet
+unstage = Remove
+editing-path = Editing path of { $line }: click junctions in order ({ $count } picked)
+undo-last = Undo last
+apply = Apply
+cancel = Cancel
+commit = Commit
+discard = Discard
+start-planning = Start planning
+stage-add-highway-segment = Add highway segment #{ $id }
+stage-remove-highway-segment = Remove highway segment #{ $id }
+stage-add-railway-segment = Add railway segment #{ $id }
+stage-remove-railway-segment = Remove railway segment #{ $id }
+stage-add-metro-line = Add metro line { $line } ({ $count } segments)
+stage-reroute-metro-line = Reroute metro line #{ $id } ({ $count } segments)
+unserved-station = { $station } is unserved, { $distance } from the path
+add-as-stop = Add as stop
+
+# segment detail
+highway-segment = Highway segment #{ $id }
+highway-segment-removed = Highway segment #{ $id } no longer exists
+railway-segment = Railway segment #{ $id }
+railway-segment-removed = Railway segment #{ $id } no longer exists
+segment-name = Name: { $name }
+segment-refs = Refs: { $refs }
+segment-lanes = Lanes: { $lanes }
+segment-lanes-default = Lanes: { $none } (assuming { $lanes })
+speed-limit = Speed limit: { $speed }
+speed-limit-default = Speed limit: { $none } (assuming { $speed })
+speed-limit-none = Speed limit: { $none }
+segment-length = Length: { $distance }
+segment-state = State: { $state }
+segment-alignment = Alignment: { $alignment }
+construction-cost = Construction cost: { $cost }
+free-flow-time = Free-flow travel time: { $duration }
+current-travelers = Current travelers: { $travelers }
+predicted-travelers = Predicted travelers: { $travelers }
+congested-factor = Congested factor: { $factor }
+no-metro-lines = Not used by any metro lines
+metro-lines = Metro lines:
+metro-line-time = { $line } (free-flow travel time: { $duration })
+new-speed-limit = New speed limit:
+set-speed-limit = Set
+remove-speed-limit = Remove limit
+timetables-changed = Timetables of { $count } metro line(s) changed
+edit-path = Edit path
+copy-handle-id = Copy handle id
+closed-until = Closed until { $time }
+close-for-hours = Close for (hours):
+close-segment = Close
+plan-removal = Plan removal
+
+# agent detail
+agent-button = Agent #{ $id }
+tile-industry = Industry: { $industry }
+tile-agents = Selected tile has { $count } agent(s):
+tile-no-agents = Selected tile has no agents
+agent-heading = Agent #{ $id }:
+agent-age = Age: { $age }
+agent-education = Education: { $degree } ({ $years } years)
+agent-at-tile = Status: at ({ $x }, { $y })
+agent-status = Status: { $status }
+agent-status-unknown = Status: unknown
+agent-home = Home: ({ $x }, { $y })
+agent-work = Work: ({ $x }, { $y })
+agent-no-work = Work: { $none }
+housing-stickiness = Housing stickiness: { $score }/1.00
+workplace-stickiness = Workplace stickiness: { $score }/1.00
+workplace-happiness = Workplace happiness score: { $score }/1.00 (quits below { $threshold })
+happiness-component = { $component } ({ $percent }% of score)
+show-commute = Show commute
+watch-log = Watch log
+stop-watching-log = Stop watching log
+agent-archetype = Archetype: { $name }
+commute-tolerance = Commute length tolerance: { $minutes } minutes
+average-commute = Average commute: { $duration }
+commute-reliability = Current commute usually takes { $reliability }
+";
+
+lazy_static::lazy_static! {
+    static ref ENGLISH_STRINGS: HashMap<String, String> =
+        parse(ENGLISH).expect("invalid English strings");
+}
+
+/// Parse a bundle into a map from keys to values.
+fn parse(source: &str) -> Result<HashMap<String, String>, String> {
+    let mut strings = HashMap::new();
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                strings.insert(key.trim().to_string(), value.trim().to_string());
+            }
+            _ => return Err(format!("line {}: expected `key = value`", i + 1)),
+        }
+    }
+    Ok(strings)
+}
+
+/**
+ * The UI text. Keys that the loaded bundle leaves out fall back to English, and keys that English
+ * doesn't have either are shown as they are, so that missing text is easy to spot.
+ */
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Strings {
+    /// shared, so that the strings can be cloned for each frame
+    overrides: Arc<HashMap<String, String>>,
+}
+
+impl Strings {
+    /// Strings that replace the given English ones, from the text of a bundle.
+    pub fn from_bundle(source: &str) -> Result<Self, String> {
+        let overrides = parse(source)?;
+        for key in overrides.keys() {
+            if !ENGLISH_STRINGS.contains_key(key) {
+                tracing::warn!(key = key.as_str(), "unknown key in strings bundle");
+            }
+        }
+        Ok(Self {
+            overrides: Arc::new(overrides),
+        })
+    }
+
+    /// The bundle in the config directory, if there is one, or else English.
+    pub fn load(paths: &paths::Paths) -> Result<Self, String> {
+        let path = paths.find_config(BUNDLE_NAME);
+        match std::fs::read_to_string(&path) {
+            Ok(source) => {
+                Self::from_bundle(&source).map_err(|err| format!("{}: {}", path.display(), err))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(format!("{}: {}", path.display(), err)),
+        }
+    }
+
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.overrides
+            .get(key)
+            .or_else(|| ENGLISH_STRINGS.get(key))
+            .map_or(key, |value| value.as_str())
+    }
+
+    /// The text for the key, with each `{ $name }` replaced by the matching argument.
+    pub fn format(&self, key: &str, args: &[(&str, &dyn std::fmt::Display)]) -> String {
+        let mut text = self.get(key).to_string();
+        for (name, value) in args {
+            text = text.replace(&format!("{{ ${} }}", name), &value.to_string());
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::strings::*;
+
+    #[test]
+    fn english_defaults() {
+        let strings = Strings::default();
+        assert_eq!(strings.get("panel-time"), "Time");
+        assert_eq!(
+            strings.format("route-length", &[("route", &2), ("distance", &"1.25 km")]),
+            "Route #2 length: 1.25 km"
+        );
+        // missing values are filled in too, so that bundles can translate them
+        assert_eq!(
+            strings.format(
+                "segment-lanes-default",
+                &[("none", &strings.get("not-available")), ("lanes", &2)]
+            ),
+            "Lanes: n/a (assuming 2)"
+        );
+        // missing keys are shown as they are
+        assert_eq!(strings.get("no-such-key"), "no-such-key");
+    }
+
+    #[test]
+    fn bundle_overrides() {
+        let strings = Strings::from_bundle(
+            "# partial German bundle\n\npanel-time = Zeit\ncurrent-time = Uhrzeit: { $time }\n",
+        )
+        .unwrap();
+        assert_eq!(strings.get("panel-time"), "Zeit");
+        assert_eq!(
+            strings.format("current-time", &[("time", &42)]),
+            "Uhrzeit: 42"
+        );
+        // everything else is still English
+        assert_eq!(strings.get("panel-stats"), "Stats");
+
+        assert!(Strings::from_bundle("panel-time Zeit").is_err());
+        assert!(Strings::from_bundle(" = Zeit").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use egui::Color32;
+
+/// A part of the day, which sets the mood of the map.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, enum_iterator::IntoEnumIterator)]
+pub(crate) enum DayBand {
+    Dawn,
+    Day,
+    Dusk,
+    Night,
+}
+
+impl DayBand {
+    /// the hour of the day at which each band starts, in order starting with dawn
+    const STARTS: [(u32, DayBand); 4] = [
+        (5, DayBand::Dawn),
+        (7, DayBand::Day),
+        (18, DayBand::Dusk),
+        (20, DayBand::Night),
+    ];
+
+    /// The band containing the given time of day, in seconds since midnight.
+    pub fn at(seconds_from_midnight: u32) -> Self {
+        let hour = (seconds_from_midnight / 3600) % 24;
+        Self::STARTS
+            .iter()
+            .rev()
+            .find(|(start, _)| hour >= *start)
+            // before dawn, it's still the night that started the day before
+            .map_or(Self::Night, |(_, band)| *band)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Dawn => "Dawn",
+            Self::Day => "Day",
+            Self::Dusk => "Dusk",
+            Self::Night => "Night",
+        }
+    }
+}
+
+/**
+ * All of the colors used to draw the map. The day theme matches the egui dark theme that the rest
+ * of the app uses, and the other bands are tinted versions of it.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Theme {
+    pub background: Color32,
+    pub water: Color32,
+    /// tiles, junctions, stations, labels, and railways without metro lines
+    pub foreground: Color32,
+    /// branches that are too small to draw individually
+    pub cutoff: Color32,
+    pub highway: Color32,
+    pub agent: Color32,
+    pub selected_agent: Color32,
+    pub route: Color32,
+    /// selected segments and the path being drawn for a metro line
+    pub highlight: Color32,
+    /// tiles that can't be reached at all in an isochrone, to tell them apart from tiles beyond
+    /// the max
+    pub unreachable: Color32,
+    pub vacant_housing: Color32,
+    pub unfilled_jobs: Color32,
+    pub planned_add: Color32,
+    pub planned_remove: Color32,
+    /// segments that are temporarily closed, e.g. because of an incident
+    pub closed: Color32,
+    /// the opacity of the field overlays
+    pub field_alpha: f32,
+}
+
+impl Theme {
+    pub fn day() -> Self {
+        Self {
+            background: Color32::from_gray(27),
+            water: Color32::from_rgb(0, 0, 150),
+            foreground: Color32::from_gray(255),
+            cutoff: Color32::from_gray(100),
+            highway: Color32::from_gray(204),
+            agent: Color32::from_rgb(0, 0, 255),
+            selected_agent: Color32::from_rgb(255, 0, 0),
+            route: Color32::from_rgb(0, 0, 255),
+            highlight: Color32::from_rgb(255, 255, 0),
+            unreachable: Color32::from_rgba_premultiplied(20, 20, 20, 160),
+            vacant_housing: Color32::from_rgb(0, 200, 255),
+            unfilled_jobs: Color32::from_rgb(255, 150, 0),
+            planned_add: Color32::from_rgb(0, 200, 255),
+            planned_remove: Color32::from_rgb(255, 64, 64),
+            closed: Color32::from_rgb(220, 0, 0),
+            field_alpha: 0.5,
+        }
+    }
+
+    pub fn for_band(band: DayBand) -> Self {
+        let day = Self::day();
+        match band {
+            DayBand::Day => day,
+            DayBand::Dawn => Self {
+                background: tint(day.background, Color32::from_rgb(80, 50, 60), 0.25),
+                water: tint(day.water, Color32::from_rgb(120, 60, 140), 0.2),
+                ..day
+            },
+            DayBand::Dusk => Self {
+                background: tint(day.background, Color32::from_rgb(90, 50, 20), 0.25),
+                water: tint(day.water, Color32::from_rgb(100, 40, 60), 0.25),
+                field_alpha: 0.4,
+                ..day
+            },
+            DayBand::Night => Self {
+                background: tint(day.background, Color32::from_rgb(0, 0, 30), 0.6),
+                water: tint(day.water, Color32::from_rgb(0, 0, 40), 0.5),
+                cutoff: tint(day.cutoff, Color32::from_rgb(20, 20, 50), 0.4),
+                highway: tint(day.highway, Color32::from_rgb(40, 40, 80), 0.3),
+                // commutes stand out against the dark map
+                agent: Color32::from_rgb(110, 170, 255),
+                route: Color32::from_rgb(110, 170, 255),
+                field_alpha: 0.3,
+                ..day
+            },
+        }
+    }
+
+    /**
+     * Blue where the simulation sees less traffic than was observed and red where it sees more,
+     * fully saturated at half or double. Gray if nothing was observed.
+     */
+    pub fn calibration(&self, ratio: Option<f64>) -> Color32 {
+        let ratio = match ratio {
+            Some(ratio) => ratio,
+            None => return Color32::from_gray(128),
+        };
+        let scale = ratio.max(f64::MIN_POSITIVE).log2().clamp(-1.0, 1.0);
+        let fade = (255.0 * (1.0 - scale.abs())) as u8;
+        if scale < 0.0 {
+            Color32::from_rgb(fade, fade, 255)
+        } else {
+            Color32::from_rgb(255, fade, fade)
+        }
+    }
+
+    /// The color of a branch that is too small to draw, shading towards water so that coastlines
+    /// stay visible when zoomed out.
+    pub fn cutoff_with_water(&self, water_fraction: f64) -> Color32 {
+        tint(
+            self.cutoff,
+            self.water,
+            water_fraction.clamp(0.0, 1.0) as f32,
+        )
+    }
+
+    pub fn planned(&self, kind: engine::ChangeKind) -> Color32 {
+        match kind {
+            engine::ChangeKind::Add => self.planned_add,
+            engine::ChangeKind::Remove => self.planned_remove,
+        }
+    }
+
+    pub fn vacancy(&self, kind: crate::vacancy_markers::VacancyKind) -> Color32 {
+        match kind {
+            crate::vacancy_markers::VacancyKind::Housing => self.vacant_housing,
+            crate::vacancy_markers::VacancyKind::Jobs => self.unfilled_jobs,
+        }
+    }
+}
+
+/// Move the color the given fraction of the way towards the tint, keeping its alpha.
+fn tint(color: Color32, tint: Color32, amount: f32) -> Color32 {
+    let mix = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * amount).round() as u8;
+    Color32::from_rgba_premultiplied(
+        mix(color.r(), tint.r()),
+        mix(color.g(), tint.g()),
+        mix(color.b(), tint.b()),
+        color.a(),
+    )
+}
+
+/// The theme for the current frame.
+#[derive(Debug, Clone)]
+pub(crate) struct ThemeState {
+    pub band: DayBand,
+    pub theme: Theme,
+}
+
+impl Default for ThemeState {
+    fn default() -> Self {
+        Self {
+            band: DayBand::Day,
+            theme: Theme::day(),
+        }
+    }
+}
+
+impl ThemeState {
+    /**
+     * Follow the given time of day, in seconds since midnight, unless a band is chosen manually,
+     * e.g. for taking screenshots. Only rebuilds the theme when the band changes.
+     */
+    pub fn update(&mut self, seconds_from_midnight: u32, manual: Option<DayBand>) {
+        let band = manual.unwrap_or_else(|| DayBand::at(seconds_from_midnight));
+        if band != self.band {
+            self.band = band;
+            self.theme = Theme::for_band(band);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::theme::*;
+
+    fn hours(hours: u32, minutes: u32) -> u32 {
+        hours * 3600 + minutes * 60
+    }
+
+    #[test]
+    fn day_bands() {
+        assert_eq!(DayBand::at(hours(5, 0)), DayBand::Dawn);
+        assert_eq!(DayBand::at(hours(6, 59)), DayBand::Dawn);
+        assert_eq!(DayBand::at(hours(7, 0)), DayBand::Day);
+        assert_eq!(DayBand::at(hours(12, 0)), DayBand::Day);
+        assert_eq!(DayBand::at(hours(17, 59)), DayBand::Day);
+        assert_eq!(DayBand::at(hours(18, 0)), DayBand::Dusk);
+        assert_eq!(DayBand::at(hours(20, 0)), DayBand::Night);
+    }
+
+    #[test]
+    fn night_wraps_around_midnight() {
+        assert_eq!(DayBand::at(hours(23, 59)), DayBand::Night);
+        assert_eq!(DayBand::at(0), DayBand::Night);
+        assert_eq!(DayBand::at(hours(4, 59)), DayBand::Night);
+        // a full day later is the same band
+        assert_eq!(DayBand::at(hours(24 + 6, 0)), DayBand::Dawn);
+    }
+
+    #[test]
+    fn cutoff_with_water() {
+        let theme = Theme::day();
+        assert_eq!(theme.cutoff_with_water(0.0), theme.cutoff);
+        assert_eq!(theme.cutoff_with_water(1.0), theme.water);
+        let half = theme.cutoff_with_water(0.5);
+        assert!(half.b() > theme.cutoff.b() && half.b() < theme.water.b());
+    }
+
+    #[test]
+    fn theme_state() {
+        let mut state = ThemeState::default();
+        state.update(hours(12, 0), None);
+        assert_eq!(state.theme, Theme::day());
+
+        state.update(hours(22, 0), None);
+        assert_eq!(state.band, DayBand::Night);
+        assert_ne!(state.theme.background, Theme::day().background);
+        assert!(state.theme.field_alpha < Theme::day().field_alpha);
+
+        // the manual band wins over the clock
+        state.update(hours(22, 0), Some(DayBand::Day));
+        assert_eq!(state.theme, Theme::day());
+    }
+}
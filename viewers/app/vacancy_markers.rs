@@ -0,0 +1,427 @@
+use state::{BranchState, LeafState};
+
+type Qtree = quadtree::Quadtree<BranchState<engine::FieldsState>, LeafState<engine::FieldsState>>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, enum_iterator::IntoEnumIterator)]
+pub(crate) enum VacancyKind {
+    Housing,
+    Jobs,
+}
+
+impl VacancyKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Housing => "Vacant housing",
+            Self::Jobs => "Unfilled jobs",
+        }
+    }
+
+    pub fn count(&self, fields: &engine::FieldsState) -> usize {
+        match self {
+            Self::Housing => fields.population.empty_housing(),
+            Self::Jobs => fields.employment.unfilled_jobs(),
+        }
+    }
+}
+
+/**
+ * Controls how many vacancy markers are drawn. Markers are only drawn for tiles that are big enough
+ * to see on screen, and branches with a lot of vacancies are drawn as a single marker until they
+ * take up enough of the screen that their tiles can be told apart.
+ */
+#[derive(Debug, Clone)]
+pub(crate) struct MarkerLimits {
+    /// individual tiles must be at least this many pixels wide to get their own marker
+    pub min_tile_pixels: f32,
+    /// branches with more vacancies than this are collapsed into one marker...
+    pub collapse_threshold: usize,
+    /// ...unless they are at least this many pixels wide
+    pub collapse_max_pixels: f32,
+    /// at most this many markers are drawn per frame
+    pub max_markers: usize,
+}
+
+impl Default for MarkerLimits {
+    fn default() -> Self {
+        Self {
+            min_tile_pixels: 4.0,
+            collapse_threshold: 50,
+            collapse_max_pixels: 128.0,
+            max_markers: 500,
+        }
+    }
+}
+
+/// What to do with a branch that overlaps the viewport.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum BranchDecision {
+    /// nothing vacant inside
+    Skip,
+    /// draw one marker for the whole branch
+    Collapse,
+    /// look at the children instead
+    Descend,
+}
+
+impl MarkerLimits {
+    /// Decide how to draw a branch with the given vacancy count, which is drawn this many pixels wide.
+    pub fn branch_decision(&self, count: usize, pixels: f32) -> BranchDecision {
+        if count == 0 {
+            BranchDecision::Skip
+        } else if pixels / 2.0 < self.min_tile_pixels
+            || (count > self.collapse_threshold && pixels < self.collapse_max_pixels)
+        {
+            BranchDecision::Collapse
+        } else {
+            BranchDecision::Descend
+        }
+    }
+}
+
+/// A marker for one tile, or for a whole branch if it was collapsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct VacancyMarker {
+    pub kind: VacancyKind,
+    pub address: quadtree::Address,
+    pub x: u64,
+    pub y: u64,
+    pub width: u64,
+    pub count: usize,
+    /// whether this stands in for a branch rather than a single tile
+    pub aggregate: bool,
+}
+
+impl VacancyMarker {
+    /// The center of the tile or branch, in model coordinates.
+    pub fn center(&self) -> (f32, f32) {
+        let half = self.width as f32 / 2.0;
+        (self.x as f32 + half, self.y as f32 + half)
+    }
+
+    /// Half the width of the square drawn for this marker, in pixels, which grows with the count.
+    pub fn half_size(&self, scale: f32) -> f32 {
+        let size = 3.0 + (self.count as f32).sqrt() * 1.5;
+        if self.aggregate {
+            size.max(8.0)
+        } else {
+            // stay inside the tile
+            size.min(self.width as f32 * scale / 2.0)
+        }
+    }
+}
+
+/// The markers to draw in the viewport, along with how many were dropped to stay under the limit.
+#[derive(Debug, Default)]
+pub(crate) struct PlacedMarkers {
+    pub markers: Vec<VacancyMarker>,
+    pub dropped: usize,
+}
+
+impl PlacedMarkers {
+    pub fn clear(&mut self) {
+        self.markers.clear();
+        self.dropped = 0;
+    }
+
+    /// The marker drawn at the given screen position, if any, given where each one is drawn.
+    pub fn at(
+        &self,
+        pos: egui::Pos2,
+        scale: f32,
+        to_screen: impl Fn((f32, f32)) -> (f32, f32),
+    ) -> Option<&VacancyMarker> {
+        // markers are drawn in order, so the last one is on top
+        self.markers.iter().rev().find(|marker| {
+            let center = egui::Pos2::from(to_screen(marker.center()));
+            let half = marker.half_size(scale);
+            egui::Rect::from_center_size(center, egui::vec2(half, half) * 2.0).contains(pos)
+        })
+    }
+}
+
+struct MarkerVisitor<'a> {
+    kind: VacancyKind,
+    /// pixels per model unit
+    scale: f32,
+    limits: &'a MarkerLimits,
+    placed: &'a mut PlacedMarkers,
+}
+
+impl<'a> MarkerVisitor<'a> {
+    fn place(&mut self, data: &quadtree::VisitData, count: usize, aggregate: bool) {
+        if self.placed.markers.len() >= self.limits.max_markers {
+            self.placed.dropped += 1;
+            return;
+        }
+        self.placed.markers.push(VacancyMarker {
+            kind: self.kind,
+            address: data.address,
+            x: data.x,
+            y: data.y,
+            width: data.width,
+            count,
+            aggregate,
+        });
+    }
+}
+
+impl<'a>
+    quadtree::Visitor<
+        BranchState<engine::FieldsState>,
+        LeafState<engine::FieldsState>,
+        quadtree::Error,
+    > for MarkerVisitor<'a>
+{
+    fn visit_branch_pre(
+        &mut self,
+        branch: &BranchState<engine::FieldsState>,
+        data: &quadtree::VisitData,
+    ) -> Result<bool, quadtree::Error> {
+        let count = self.kind.count(&branch.fields);
+        let pixels = data.width as f32 * self.scale;
+        Ok(match self.limits.branch_decision(count, pixels) {
+            BranchDecision::Skip => false,
+            BranchDecision::Collapse => {
+                self.place(data, count, true);
+                false
+            }
+            BranchDecision::Descend => true,
+        })
+    }
+
+    fn visit_leaf(
+        &mut self,
+        leaf: &LeafState<engine::FieldsState>,
+        data: &quadtree::VisitData,
+    ) -> Result<(), quadtree::Error> {
+        let count = self.kind.count(&leaf.fields);
+        if count > 0 {
+            self.place(data, count, false);
+        }
+        Ok(())
+    }
+
+    fn visit_branch_post(
+        &mut self,
+        _branch: &BranchState<engine::FieldsState>,
+        _data: &quadtree::VisitData,
+    ) -> Result<(), quadtree::Error> {
+        Ok(())
+    }
+}
+
+/**
+ * Add markers for the vacancies of the given kind within the bounds, using the branch aggregates to
+ * avoid visiting every tile in dense areas.
+ */
+pub(crate) fn place_markers(
+    qtree: &Qtree,
+    kind: VacancyKind,
+    bounds: &quadtree::Rect,
+    scale: f32,
+    limits: &MarkerLimits,
+    placed: &mut PlacedMarkers,
+) -> Result<(), quadtree::Error> {
+    qtree.visit_rect(
+        &mut MarkerVisitor {
+            kind,
+            scale,
+            limits,
+            placed,
+        },
+        bounds,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vacancy_markers::*;
+
+    const MAX_DEPTH: u32 = 3;
+
+    fn housing(total: usize, people: usize) -> engine::FieldsState {
+        let mut fields = engine::FieldsState::default();
+        fields.population.housing.total = total;
+        fields.population.people.total = people;
+        fields
+    }
+
+    /**
+     * A fully split tree where the leaves have the given vacant housing, keyed by position, and
+     * the branches have the sums of their children like the real field aggregates.
+     */
+    fn synthetic_tree(vacant: &[((u64, u64), usize)]) -> Qtree {
+        let mut qtree = Qtree::new(
+            LeafState {
+                id: 0,
+                tile: tiles::EmptyTile {}.into(),
+                fields: Default::default(),
+                creation_time: 0,
+                poi: None,
+            },
+            MAX_DEPTH,
+        );
+        split_all(&mut qtree, quadtree::Address::from((vec![], MAX_DEPTH)));
+
+        for ((x, y), count) in vacant {
+            let address = qtree.get_address(*x, *y).unwrap();
+            qtree.get_leaf_mut(address).unwrap().fields = housing(*count, 0);
+            for branch in std::iter::successors(address.parent(), |a| a.parent()) {
+                let fields = &mut qtree.get_branch_mut(branch).unwrap().fields;
+                *fields = housing(fields.population.housing.total + count, 0);
+            }
+        }
+        qtree
+    }
+
+    fn split_all(qtree: &mut Qtree, address: quadtree::Address) {
+        if address.depth() == MAX_DEPTH as usize {
+            return;
+        }
+        qtree
+            .split(
+                address,
+                BranchState::default(),
+                quadtree::QuadMap::each(|| LeafState {
+                    id: 0,
+                    tile: tiles::EmptyTile {}.into(),
+                    fields: Default::default(),
+                    creation_time: 0,
+                    poi: None,
+                }),
+            )
+            .unwrap();
+        for quadrant in quadtree::QUADRANTS {
+            split_all(qtree, address.child(quadrant));
+        }
+    }
+
+    fn place(qtree: &Qtree, scale: f32, limits: &MarkerLimits) -> PlacedMarkers {
+        let mut placed = PlacedMarkers::default();
+        place_markers(
+            qtree,
+            VacancyKind::Housing,
+            &quadtree::Rect::corners(0, 0, 8, 8),
+            scale,
+            limits,
+            &mut placed,
+        )
+        .unwrap();
+        placed
+    }
+
+    #[test]
+    fn branch_decisions() {
+        let limits = MarkerLimits::default();
+        assert_eq!(limits.branch_decision(0, 1000.0), BranchDecision::Skip);
+        assert_eq!(limits.branch_decision(0, 1.0), BranchDecision::Skip);
+        // the children would be too small to mark individually
+        assert_eq!(limits.branch_decision(1, 6.0), BranchDecision::Collapse);
+        assert_eq!(limits.branch_decision(1, 8.0), BranchDecision::Descend);
+        // lots of vacancies in a small area, until the branch is big enough on screen
+        assert_eq!(limits.branch_decision(51, 100.0), BranchDecision::Collapse);
+        assert_eq!(limits.branch_decision(50, 100.0), BranchDecision::Descend);
+        assert_eq!(limits.branch_decision(51, 128.0), BranchDecision::Descend);
+    }
+
+    #[test]
+    fn markers_for_tiles() {
+        let qtree = synthetic_tree(&[((0, 0), 3), ((5, 6), 1)]);
+        let placed = place(&qtree, 32.0, &MarkerLimits::default());
+        assert_eq!(placed.dropped, 0);
+        let markers: Vec<_> = placed
+            .markers
+            .iter()
+            .map(|marker| ((marker.x, marker.y), marker.count, marker.aggregate))
+            .collect();
+        assert_eq!(markers, vec![((0, 0), 3, false), ((5, 6), 1, false)]);
+
+        // occupied housing isn't vacant
+        let mut qtree = qtree;
+        let address = qtree.get_address(0, 0).unwrap();
+        qtree.get_leaf_mut(address).unwrap().fields = housing(3, 3);
+        for branch in std::iter::successors(address.parent(), |a| a.parent()) {
+            let fields = &mut qtree.get_branch_mut(branch).unwrap().fields;
+            fields.population.people.total += 3;
+        }
+        let placed = place(&qtree, 32.0, &MarkerLimits::default());
+        assert_eq!(placed.markers.len(), 1);
+        assert_eq!(placed.markers[0].address, qtree.get_address(5, 6).unwrap());
+    }
+
+    #[test]
+    fn collapse_when_zoomed_out() {
+        let qtree = synthetic_tree(&[((0, 0), 3), ((1, 1), 2), ((5, 6), 1)]);
+
+        // tiles are 1.5 pixels wide, so each quadrant of the map gets one marker
+        let placed = place(&qtree, 1.5, &MarkerLimits::default());
+        let markers: Vec<_> = placed
+            .markers
+            .iter()
+            .map(|marker| ((marker.x, marker.y, marker.width), marker.count))
+            .collect();
+        assert_eq!(markers, vec![((0, 0, 4), 5), ((4, 4, 4), 1)]);
+        assert!(placed.markers.iter().all(|marker| marker.aggregate));
+
+        // the whole map is tiny
+        let placed = place(&qtree, 0.5, &MarkerLimits::default());
+        assert_eq!(placed.markers.len(), 1);
+        assert_eq!((placed.markers[0].width, placed.markers[0].count), (8, 6));
+    }
+
+    #[test]
+    fn collapse_dense_branches() {
+        let qtree = synthetic_tree(&[((0, 0), 40), ((1, 0), 40), ((6, 6), 10)]);
+        let limits = MarkerLimits {
+            collapse_max_pixels: 100.0,
+            ..Default::default()
+        };
+
+        // the dense quadrant is collapsed, but the sparse one still gets individual markers
+        let placed = place(&qtree, 16.0, &limits);
+        let markers: Vec<_> = placed
+            .markers
+            .iter()
+            .map(|marker| ((marker.x, marker.y, marker.width), marker.count))
+            .collect();
+        assert_eq!(markers, vec![((0, 0, 4), 80), ((6, 6, 1), 10)]);
+        assert!(placed.markers[0].aggregate);
+        assert!(!placed.markers[1].aggregate);
+
+        // zoomed in far enough, every tile is shown
+        let placed = place(&qtree, 64.0, &limits);
+        assert_eq!(placed.markers.len(), 3);
+        assert!(placed.markers.iter().all(|marker| !marker.aggregate));
+    }
+
+    #[test]
+    fn max_markers_per_frame() {
+        let vacant: Vec<_> = (0..8).map(|x| ((x, 3), 1)).collect();
+        let qtree = synthetic_tree(&vacant);
+        let limits = MarkerLimits {
+            max_markers: 5,
+            ..Default::default()
+        };
+        let placed = place(&qtree, 32.0, &limits);
+        assert_eq!(placed.markers.len(), 5);
+        assert_eq!(placed.dropped, 3);
+    }
+
+    #[test]
+    fn marker_at_position() {
+        let qtree = synthetic_tree(&[((0, 0), 4), ((4, 4), 4)]);
+        let placed = place(&qtree, 32.0, &MarkerLimits::default());
+        let to_screen = |(x, y): (f32, f32)| (x * 32.0, y * 32.0);
+
+        let at = |x, y| {
+            placed
+                .at(egui::pos2(x, y), 32.0, to_screen)
+                .map(|marker| (marker.x, marker.y))
+        };
+        assert_eq!(at(16.0, 16.0), Some((0, 0)));
+        assert_eq!(at(150.0, 148.0), Some((4, 4)));
+        // between the markers, and in the corner of a tile outside of its marker
+        assert_eq!(at(100.0, 100.0), None);
+        assert_eq!(at(1.0, 1.0), None);
+    }
+}
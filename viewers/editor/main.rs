@@ -1,9 +1,20 @@
+use std::collections::{BTreeMap, HashSet};
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
 static DEFAULT_WINDOW_SIZE: (f64, f64) = (1920.0, 1080.0);
 static WINDOW_TITLE: &str = "Metro Simulator";
-static DEFAULT_CONFIG: &str = "configs/debug.toml";
+static DEFAULT_CONFIG: &str = "debug.toml";
+
+/// sent by the content widget to itself when its size changes, so that the view can be re-fit
+const CONTENT_RESIZED: druid::Selector<druid::Size> =
+    druid::Selector::new("metro_simulator.content_resized");
+
+/// sent by widgets to modify the engine; see Editor
+const EDIT: druid::Selector<Edit> = druid::Selector::new("metro_simulator.edit");
+
+/// bulk operations on selections with at least this many leaves must be confirmed
+const CONFIRM_SELECTION_SIZE: usize = 1000;
 
 #[derive(clap::Parser, Debug)]
 struct Args {
@@ -13,27 +24,56 @@ struct Args {
 
 fn main() {
     use clap::Parser;
+    use engine::ErrorContext;
     let args = Args::parse();
 
+    // e.g. RUST_LOG=editor=trace prints how much of the map each paint visits
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+
+    let paths = paths::Paths::resolve();
+    match paths.migrate_legacy_saves() {
+        Ok(copied) if !copied.is_empty() => {
+            println!("Copied {} old save(s) to the save directory", copied.len())
+        }
+        Ok(_) => (),
+        Err(err) => eprintln!("Failed to copy old saves: {}", err),
+    }
+
     let window = druid::WindowDesc::new(build_root_widget())
         .title(WINDOW_TITLE)
         .window_size(DEFAULT_WINDOW_SIZE);
 
-    let engine = Arc::new(Mutex::new(match args.load {
-        Some(path) => engine::Engine::load_file(&path).unwrap(),
-        None => engine::Engine::new(
-            state::Config::load_file(&std::path::PathBuf::from(DEFAULT_CONFIG)).unwrap(),
-        ),
-    }));
+    let engine = match args.load {
+        Some(path) => engine::Engine::load_file(&path),
+        None => {
+            let config = paths.find_config(DEFAULT_CONFIG);
+            state::Config::load_file(&config)
+                .map(engine::Engine::new)
+                .map_err(state::Error::from)
+                .context(format!("failed to load config from {}", config.display()))
+        }
+    };
+    let mut engine = engine.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
 
-    // TODO: re-run this when the qtree updates
-    engine.lock().unwrap().update_fields().unwrap();
+    engine.update_fields().unwrap();
 
     let state = State {
-        engine: engine.clone(),
-        metro_lines: MetroLinesState::new(engine.clone()),
-        content: ContentState::new(engine),
+        map: MapView::new(&engine.state),
+        metadata: MetadataView::new(&engine.state.metadata),
+        metro_lines: metro_line_views(&engine, &druid::im::Vector::new()),
+        content: ContentState::new(engine.state.qtree.width() as f64),
         current_leaf: None,
+        selection: None,
+        bulk_housing_density: 1.0,
+        bulk_split_depth: 1.0,
+        select_tile_type: tile_types()[1].1,
+        status: String::new(),
+        clipboard_depth: None,
         current_field: FieldType::None,
         show_qtree: true,
         show_metros: true,
@@ -45,16 +85,313 @@ fn main() {
     };
 
     druid::AppLauncher::with_window(window)
+        .delegate(Editor {
+            engine,
+            clipboard: None,
+            paths,
+        })
         .launch(state)
         .unwrap();
 }
 
+/// A modification to the engine, which is applied by Editor.
+#[derive(Debug, Clone)]
+enum Edit {
+    SetLeafData {
+        address: quadtree::Address,
+        data: String,
+    },
+    SplitTile(quadtree::Address),
+    /// apply to every leaf in the current selection; the label is only used for reporting
+    Selection {
+        label: String,
+        op: SelectionOp,
+    },
+    /// copy the smallest node that contains the current selection
+    Copy,
+    /// paste the copied node at the given address, which must be the same size
+    Paste(quadtree::Address),
+    AddMetroLine,
+    RenameMetroLine {
+        id: metro::MetroLineHandle,
+        name: String,
+    },
+    SetMetadata {
+        name: String,
+        author: String,
+        description: String,
+    },
+    Save,
+}
+
+#[derive(Debug, Clone)]
+enum SelectionOp {
+    Bulk(state::BulkOp),
+    ClearAgents,
+}
+
+/**
+ * Owns the engine. Widgets only ever read from the view-models in State, which are cheap to clone,
+ * and send an EDIT command to make changes. Each edit is applied here, and then the view-models that
+ * it might affect are rebuilt. Since nothing else can reach the engine, there is no need for locking
+ * while painting or handling events.
+ */
+struct Editor {
+    engine: engine::Engine,
+    /// the region copied with Edit::Copy, if any
+    clipboard: Option<engine::CopiedRegion>,
+    /// where maps are saved
+    paths: paths::Paths,
+}
+
+impl druid::AppDelegate<State> for Editor {
+    fn command(
+        &mut self,
+        _ctx: &mut druid::DelegateCtx,
+        _target: druid::Target,
+        cmd: &druid::Command,
+        state: &mut State,
+        _env: &druid::Env,
+    ) -> druid::Handled {
+        match cmd.get(EDIT) {
+            Some(edit) => {
+                self.apply(edit.clone(), state);
+                druid::Handled::Yes
+            }
+            None => druid::Handled::No,
+        }
+    }
+}
+
+impl Editor {
+    fn apply(&mut self, edit: Edit, state: &mut State) {
+        let _span = tracing::debug_span!("edit", ?edit).entered();
+
+        let engine = &mut self.engine;
+        // pasting can bring along highways and railways, everything else only changes tiles
+        let changed = match edit {
+            Edit::Paste(_) => MapChange::TilesAndNetworks,
+            _ => MapChange::Tiles,
+        };
+        let result = match edit {
+            Edit::SetLeafData { address, data } => {
+                engine.set_leaf_data(address, &data, state::SerdeFormat::Toml)
+            }
+            Edit::SplitTile(address) => {
+                use state::{BranchState, LeafState};
+                engine.split_tile(
+                    address,
+                    BranchState::default(),
+                    quadtree::QuadMap::each(LeafState::default),
+                )
+            }
+            Edit::Selection { label, op } => {
+                let selection = match &mut state.selection {
+                    Some(selection) => selection,
+                    None => return,
+                };
+                let addresses: Vec<_> = selection.addresses.iter().copied().collect();
+                let report = match op {
+                    SelectionOp::Bulk(op) => engine.bulk_apply(&addresses, &op),
+                    SelectionOp::ClearAgents => engine.clear_agents(&addresses),
+                };
+                for (address, err) in &report.errors {
+                    println!("Error updating leaf {:?}: {}", address.to_vec(), err);
+                }
+                selection.status = format!(
+                    "{}: updated {} leaves, {} errors",
+                    label,
+                    report.applied.len(),
+                    report.errors.len()
+                );
+                Ok(())
+            }
+            Edit::Copy => {
+                let selection = match &mut state.selection {
+                    Some(selection) => selection,
+                    None => return,
+                };
+                let max_depth = engine.state.config.max_depth;
+                let address =
+                    enclosing_address(&selection.rect(engine.state.qtree.width()), max_depth);
+                // agents stay where they are; highways and railways are cut at the edge
+                match engine.copy_region(address, state::RegionPolicy::Clip, false) {
+                    Ok(copied) => {
+                        selection.status = format!(
+                            "Copied {}x{} region, ctrl-click to paste",
+                            address.width(),
+                            address.width()
+                        );
+                        self.clipboard = Some(copied);
+                        state.clipboard_depth = Some(address.depth() as u32);
+                    }
+                    Err(err) => state.status = err.to_string(),
+                }
+                return;
+            }
+            Edit::Paste(address) => match &self.clipboard {
+                Some(copied) => engine.paste_region(address, copied).map(|_| ()),
+                None => return,
+            },
+            Edit::AddMetroLine => {
+                engine.state.metros.add_metro_line(
+                    metro::MetroLineData {
+                        color: (255, 255, 255).into(),
+                        name: String::from("Metro Line"),
+                        schedule: metro::Schedule::fixed_frequency(300),
+                        // TODO: default metro speed specified here as 35 m/s, or 79 mph
+                        speed_limit: 35,
+                    },
+                    vec![],
+                    &engine.state.railways,
+                );
+                state.metro_lines = metro_line_views(engine, &state.metro_lines);
+                return;
+            }
+            Edit::RenameMetroLine { id, name } => {
+                // only the metro line list shows names, and it is already up to date; the line may
+                // have been removed since the name was edited, in which case there is nothing to do
+                if let Some(metro_line) = engine.state.metros.try_metro_line_mut(id) {
+                    metro_line.data.name = name;
+                }
+                return;
+            }
+            Edit::SetMetadata {
+                name,
+                author,
+                description,
+            } => {
+                let metadata = &mut engine.state.metadata;
+                metadata.name = name;
+                metadata.author = author;
+                metadata.description = description;
+                state.metadata = MetadataView::new(metadata);
+                return;
+            }
+            Edit::Save => {
+                let timestamp = chrono::offset::Local::now();
+                let name = format!(
+                    "metro_simulator_{}.json",
+                    timestamp.format("%Y-%m-%d_%H-%M-%S"),
+                );
+                let result = self
+                    .paths
+                    .file(paths::Dir::Saves, &name)
+                    .map_err(engine::Error::from)
+                    .and_then(|path| engine.dump_file(&path).map(|()| path));
+                match result {
+                    Ok(path) => println!("Saved to {}", path.display()),
+                    Err(err) => state.status = err.to_string(),
+                }
+                // saving updates the modified time
+                state.metadata = MetadataView::new(&engine.state.metadata);
+                return;
+            }
+        };
+
+        match result.and_then(|()| engine.update_fields()) {
+            Ok(()) => self.refresh(state, changed),
+            Err(err) => {
+                state.status = err.to_string();
+                // the edit may have partially succeeded
+                self.refresh(state, changed);
+            }
+        }
+    }
+
+    /// Rebuild the view-models that depend on the map.
+    fn refresh(&self, state: &mut State, changed: MapChange) {
+        let _span = tracing::debug_span!("refresh", ?changed).entered();
+
+        state.map = state.map.refresh(&self.engine.state, changed);
+
+        // leaves may have been split or changed type
+        if let Some(current_leaf) = &state.current_leaf {
+            state.current_leaf = current_leaf.refresh(&state.map.qtree);
+        }
+        if let Some(selection) = &mut state.selection {
+            selection.refresh(&state.map.qtree);
+        }
+        state.metro_lines = metro_line_views(&self.engine, &state.metro_lines);
+    }
+}
+
+/// The parts of the map that an edit can change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MapChange {
+    Tiles,
+    TilesAndNetworks,
+}
+
+type MapTree = quadtree::Quadtree<
+    state::BranchState<engine::FieldsState>,
+    state::LeafState<engine::FieldsState>,
+>;
+
+/**
+ * Snapshot of the parts of the map that the widgets draw and hit-test against. Each part is shared
+ * with the previous snapshot unless the edit could have changed it, so that e.g. editing a tile
+ * doesn't copy the highways and railways.
+ */
+#[derive(Clone)]
+struct MapView {
+    qtree: Arc<MapTree>,
+    railways: Arc<metro::Railways>,
+    highways: Arc<highway::Highways>,
+}
+
+impl MapView {
+    fn new(map: &state::State<engine::FieldsState>) -> Self {
+        Self {
+            qtree: Arc::new(map.qtree.clone()),
+            railways: Arc::new(map.railways.clone()),
+            highways: Arc::new(map.highways.clone()),
+        }
+    }
+
+    fn refresh(&self, map: &state::State<engine::FieldsState>, changed: MapChange) -> Self {
+        match changed {
+            MapChange::Tiles => Self {
+                qtree: Arc::new(map.qtree.clone()),
+                ..self.clone()
+            },
+            MapChange::TilesAndNetworks => Self::new(map),
+        }
+    }
+}
+
+impl druid::Data for MapView {
+    fn same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.qtree, &other.qtree)
+            && Arc::ptr_eq(&self.railways, &other.railways)
+            && Arc::ptr_eq(&self.highways, &other.highways)
+    }
+}
+
+impl std::fmt::Debug for MapView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MapView").finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug, Clone, druid::Data, druid::Lens)]
 struct State {
-    engine: Arc<Mutex<engine::Engine>>,
-    metro_lines: MetroLinesState,
+    /// the map as of the last edit, which is only replaced by Editor
+    map: MapView,
+    metadata: MetadataView,
+    metro_lines: druid::im::Vector<MetroLineView>,
     content: ContentState,
     current_leaf: Option<CurrentLeafState>,
+    selection: Option<SelectionState>,
+    /// parameters for the bulk operations in the selection panel
+    bulk_housing_density: f64,
+    bulk_split_depth: f64,
+    /// the type of tile for "select all of type"
+    select_tile_type: std::mem::Discriminant<tiles::Tile>,
+    /// the most recent error, shown in the status line until it is dismissed
+    status: String,
+    /// the depth at which the copied region fits, if anything has been copied
+    clipboard_depth: Option<u32>,
     current_field: FieldType,
     show_qtree: bool,
     show_metros: bool,
@@ -66,6 +403,37 @@ struct State {
 }
 
 fn build_root_widget() -> impl druid::Widget<State> {
+    druid::widget::Flex::column()
+        .with_flex_child(build_main_widget(), 1.0)
+        .with_child(build_status_line())
+}
+
+/// Shows the most recent error instead of crashing the editor.
+fn build_status_line() -> impl druid::Widget<State> {
+    use druid::WidgetExt;
+    druid::widget::Either::new(
+        |state: &State, _env: &druid::Env| state.status.is_empty(),
+        druid::widget::Flex::row(),
+        druid::widget::Flex::row()
+            .with_flex_child(
+                druid::widget::Label::dynamic(|state: &State, _env: &druid::Env| {
+                    state.status.clone()
+                })
+                .with_text_color(druid::Color::rgb8(255, 80, 80))
+                .expand_width(),
+                1.0,
+            )
+            .with_child(druid::widget::Button::new("Dismiss").on_click(
+                |_ctx: &mut druid::EventCtx, state: &mut State, _env: &druid::Env| {
+                    state.status.clear();
+                },
+            ))
+            .padding((20.0, 5.0))
+            .background(druid::Color::grey(0.1)),
+    )
+}
+
+fn build_main_widget() -> impl druid::Widget<State> {
     use druid::WidgetExt;
     druid::widget::Flex::row()
         .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
@@ -90,7 +458,15 @@ fn build_root_widget() -> impl druid::Widget<State> {
         .with_flex_child(Content {}, 1.0)
         .with_child(
             druid::widget::Flex::column()
-                .with_child(build_menu_panel().expand().padding((20.0, 20.0)))
+                .with_flex_child(build_menu_panel().expand().padding((20.0, 20.0)), 1.0)
+                .with_default_spacer()
+                .with_child(
+                    build_metadata_panel()
+                        .lens(State::metadata)
+                        .padding((20.0, 20.0)),
+                )
+                .with_default_spacer()
+                .with_flex_child(build_selection_panel().expand().padding((20.0, 20.0)), 1.0)
                 .fix_width(300.0)
                 .background(druid::Color::grey(0.2))
                 .expand_height(),
@@ -117,6 +493,7 @@ fn tile_types() -> Vec<(&'static str, std::mem::Discriminant<tiles::Tile>)> {
                 &tiles::WorkplaceTile {
                     density: 1,
                     agents: vec![],
+                    industry: tiles::Industry::Office,
                 }
                 .into(),
             ),
@@ -134,6 +511,21 @@ fn build_detail_panel() -> impl druid::Widget<CurrentLeafState> {
                 format!("Tile type: {}", state.leaf.tile.name())
             },
         ))
+        .with_child(druid::widget::Label::dynamic(
+            |state: &CurrentLeafState, _env: &druid::Env| match &state.leaf.tile {
+                tiles::Tile::WorkplaceTile(tiles::WorkplaceTile { industry, .. }) => {
+                    format!("Industry: {}", industry.name())
+                }
+                _ => String::new(),
+            },
+        ))
+        .with_child(druid::widget::Label::dynamic(
+            // points of interest are edited along with the rest of the leaf data below
+            |state: &CurrentLeafState, _env: &druid::Env| match &state.leaf.poi {
+                Some(poi) => format!("Point of interest: {} ({})", poi.name, poi.kind.name()),
+                None => String::new(),
+            },
+        ))
         .with_default_spacer()
         .with_child(druid::widget::Label::dynamic(
             |state: &CurrentLeafState, _env: &druid::Env| {
@@ -157,30 +549,11 @@ fn build_detail_panel() -> impl druid::Widget<CurrentLeafState> {
         .with_child(druid::widget::RadioGroup::new(tile_types()).lens(CurrentLeafState::tile_type))
         .with_default_spacer()
         .with_child(druid::widget::Button::new("Update").on_click(
-            |_ctx: &mut druid::EventCtx, state: &mut CurrentLeafState, _env: &druid::Env| {
-                // NOTE: we need to do some juggling to adhere to borrowing rules
-                let mut update = false;
-                {
-                    let mut engine = state.engine.lock().unwrap();
-                    match engine.state.set_leaf_data(
-                        *state.address,
-                        &state.edited_data,
-                        state::SerdeFormat::Toml,
-                    ) {
-                        Ok(()) => {
-                            // update with new state
-                            update = true;
-                        }
-                        Err(err) => println!("Error updating leaf: {:?}", err),
-                    }
-                }
-                if update {
-                    // TODO: this is gross
-                    let engine1 = state.engine.clone();
-                    let engine2 = state.engine.clone();
-                    let engine = engine1.lock().unwrap();
-                    *state = CurrentLeafState::new(*state.address, &engine, engine2);
-                }
+            |ctx: &mut druid::EventCtx, state: &mut CurrentLeafState, _env: &druid::Env| {
+                ctx.submit_command(EDIT.with(Edit::SetLeafData {
+                    address: *state.address,
+                    data: state.edited_data.clone(),
+                }));
             },
         ))
 }
@@ -189,66 +562,33 @@ fn build_metro_lines_panel() -> impl druid::Widget<State> {
     use druid::WidgetExt;
     druid::widget::Flex::column()
         .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
-        .with_child(
-            druid::widget::Button::new("New Metro Line")
-                .on_click(
-                    |_ctx: &mut druid::EventCtx,
-                     _state: &mut MetroLinesState,
-                     _env: &druid::Env| {
-                        // let mut engine = state.engine.lock().unwrap();
-                        // // TODO: default metro speed specified here as 35 m/s, or 79 mph
-                        // let id =
-                        //     engine
-                        //         .state
-                        //         .add_metro_line(String::from("Metro Line"), None, 35, None);
-
-                        // state.states.insert(id, MetroLineState::new());
-
-                        // // TODO: this isn't quite enough to make it fully refresh
-                        // ctx.children_changed();
-                        // ctx.request_layout();
-                        // ctx.request_paint();
-                    },
-                )
-                .lens(State::metro_lines),
-        )
+        .with_child(druid::widget::Button::new("New Metro Line").on_click(
+            |ctx: &mut druid::EventCtx, _state: &mut State, _env: &druid::Env| {
+                ctx.submit_command(EDIT.with(Edit::AddMetroLine));
+            },
+        ))
         .with_default_spacer()
         .with_child(
             druid::widget::List::new(|| {
                 druid::widget::Flex::row()
+                    .with_child(druid::widget::Checkbox::new("").lens(MetroLineView::visible))
                     .with_child(
-                        druid::widget::Checkbox::new("")
-                            .lens(MetroLineState::visible)
-                            .lens(MetroLineData::state),
-                    )
-                    .with_child(
-                        druid::widget::Painter::new(|_ctx, _data: &MetroLineData, _env| {
-                            // use druid::RenderContext;
-                            // let metro_line = data.metro_line.lock().unwrap();
-                            // let center = (ctx.size().width / 2.0, ctx.size().height / 2.0);
-                            // let circle = druid::kurbo::Circle::new(
-                            //     center,
-                            //     f64::min(ctx.size().width, ctx.size().height) / 4.0,
-                            // );
-                            // let color = metro_line.color;
-                            // ctx.fill(
-                            //     circle,
-                            //     &druid::Color::rgb8(color.red, color.green, color.blue),
-                            // );
+                        druid::widget::Painter::new(|ctx, data: &MetroLineView, _env| {
+                            use druid::RenderContext;
+                            let center = (ctx.size().width / 2.0, ctx.size().height / 2.0);
+                            let circle = druid::kurbo::Circle::new(
+                                center,
+                                f64::min(ctx.size().width, ctx.size().height) / 4.0,
+                            );
+                            let (red, green, blue) = data.color;
+                            ctx.fill(circle, &druid::Color::rgb8(red, green, blue));
                         })
                         .fix_size(20.0, 20.0),
                     )
                     .with_child(
                         druid::widget::TextBox::new()
-                            .lens(druid::lens::Map::new(
-                                |data: &MetroLineData| {
-                                    data.metro_line.lock().unwrap().data.name.clone()
-                                },
-                                |data: &mut MetroLineData, inner: String| {
-                                    let mut metro_line = data.metro_line.lock().unwrap();
-                                    metro_line.data.name = inner;
-                                },
-                            ))
+                            .lens(MetroLineView::name)
+                            .controller(RenameMetroLine)
                             .scroll()
                             .horizontal()
                             .disable_scrollbars()
@@ -267,16 +607,15 @@ fn build_menu_panel() -> impl druid::Widget<State> {
 
     druid::widget::Flex::column()
         .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
-        .with_child(druid::widget::Button::new("Save").on_click(
+        .with_child(druid::widget::Button::new("Reset view").on_click(
             |_ctx: &mut druid::EventCtx, state: &mut State, _env: &druid::Env| {
-                let engine = state.engine.lock().unwrap();
-                let timestamp = chrono::offset::Local::now();
-                let path = format!(
-                    "/tmp/metro_simulator_{}.json",
-                    timestamp.format("%Y-%m-%d_%H-%M-%S"),
-                );
-                engine.dump_file(&std::path::PathBuf::from(&path)).unwrap();
-                println!("Saved to {}", path);
+                state.content.reset();
+            },
+        ))
+        .with_default_spacer()
+        .with_child(druid::widget::Button::new("Save").on_click(
+            |ctx: &mut druid::EventCtx, _state: &mut State, _env: &druid::Env| {
+                ctx.submit_command(EDIT.with(Edit::Save));
             },
         ))
         .with_default_spacer()
@@ -315,6 +654,225 @@ fn build_menu_panel() -> impl druid::Widget<State> {
         )
 }
 
+fn build_metadata_panel() -> impl druid::Widget<MetadataView> {
+    use druid::WidgetExt;
+
+    fn text_box(
+        label: &str,
+        text_box: druid::widget::TextBox<String>,
+        lens: impl druid::Lens<MetadataView, String> + 'static,
+    ) -> impl druid::Widget<MetadataView> {
+        druid::widget::Flex::column()
+            .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+            .with_child(druid::widget::Label::new(label))
+            .with_child(text_box.fix_width(250.0).lens(lens))
+    }
+
+    druid::widget::Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .with_child(text_box(
+            "Map name:",
+            druid::widget::TextBox::new(),
+            MetadataView::name,
+        ))
+        .with_child(text_box(
+            "Author:",
+            druid::widget::TextBox::new(),
+            MetadataView::author,
+        ))
+        .with_child(text_box(
+            "Description:",
+            druid::widget::TextBox::multiline(),
+            MetadataView::description,
+        ))
+        .with_default_spacer()
+        .with_child(druid::widget::Button::new("Update metadata").on_click(
+            |ctx: &mut druid::EventCtx, state: &mut MetadataView, _env: &druid::Env| {
+                ctx.submit_command(EDIT.with(Edit::SetMetadata {
+                    name: state.name.clone(),
+                    author: state.author.clone(),
+                    description: state.description.clone(),
+                }));
+            },
+        ))
+        .with_default_spacer()
+        .with_child(druid::widget::Label::dynamic(
+            |state: &MetadataView, _env: &druid::Env| {
+                format!(
+                    "Created: {}\nModified: {}",
+                    format_timestamp(state.saved.created),
+                    format_timestamp(state.saved.modified)
+                )
+            },
+        ))
+        .with_child(druid::widget::Label::dynamic(
+            |state: &MetadataView, _env: &druid::Env| match &state.saved.georeference {
+                Some(georeference) => format!(
+                    "Origin: {}\n{} m per tile",
+                    georeference::format_lat_lon((
+                        georeference.origin_lat,
+                        georeference.origin_lon
+                    )),
+                    georeference.meters_per_tile
+                ),
+                None => String::from("Not georeferenced"),
+            },
+        ))
+}
+
+/// e.g. "2022-06-01 12:34 UTC", or "never" for maps that were last saved without metadata
+fn format_timestamp(timestamp: Option<u64>) -> String {
+    timestamp
+        .and_then(|seconds| chrono::NaiveDateTime::from_timestamp_opt(seconds as i64, 0))
+        .map_or_else(
+            || String::from("never"),
+            |datetime| datetime.format("%Y-%m-%d %H:%M UTC").to_string(),
+        )
+}
+
+/**
+ * Apply a bulk operation to the current selection. Large selections are only modified once the
+ * same operation is clicked a second time.
+ */
+fn apply_to_selection(ctx: &mut druid::EventCtx, state: &mut State, label: &str, op: SelectionOp) {
+    let selection = match &mut state.selection {
+        Some(selection) => selection,
+        None => return,
+    };
+    if selection.addresses.len() >= CONFIRM_SELECTION_SIZE
+        && selection.pending.as_deref() != Some(label)
+    {
+        selection.status = format!(
+            "\"{}\" will modify {} leaves, click again to confirm",
+            label,
+            selection.addresses.len()
+        );
+        selection.pending = Some(label.to_string());
+        return;
+    }
+    selection.pending = None;
+    ctx.submit_command(EDIT.with(Edit::Selection {
+        label: label.to_string(),
+        op,
+    }));
+}
+
+fn build_selection_panel() -> impl druid::Widget<State> {
+    use druid::WidgetExt;
+
+    fn bulk_button(
+        label: &'static str,
+        op: impl Fn(&State) -> state::BulkOp + 'static,
+    ) -> impl druid::Widget<State> {
+        druid::widget::Button::new(label).on_click(
+            move |ctx: &mut druid::EventCtx, state: &mut State, _env: &druid::Env| {
+                let op = SelectionOp::Bulk(op(state));
+                apply_to_selection(ctx, state, label, op);
+            },
+        )
+    }
+
+    fn select_type_button(label: &str, whole_map: bool) -> impl druid::Widget<State> {
+        druid::widget::Button::new(label).on_click(
+            move |ctx: &mut druid::EventCtx, state: &mut State, _env: &druid::Env| {
+                let (start, end) = if whole_map {
+                    (
+                        (0.0, 0.0),
+                        (state.content.model_width, state.content.model_width),
+                    )
+                } else {
+                    let view = state.content.view();
+                    (view.to_model((0.0, 0.0)), view.to_model(state.content.size))
+                };
+                let mut selection = SelectionState::of_type(start, end, state.select_tile_type);
+                selection.refresh(&state.map.qtree);
+                state.selection = Some(selection);
+                ctx.request_paint();
+            },
+        )
+    }
+
+    druid::widget::Flex::column()
+        .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+        .with_child(druid::widget::Label::new(
+            "Shift-drag to select, Escape to clear",
+        ))
+        .with_default_spacer()
+        .with_child(druid::widget::Button::new("Copy").on_click(
+            |ctx: &mut druid::EventCtx, _state: &mut State, _env: &druid::Env| {
+                ctx.submit_command(EDIT.with(Edit::Copy));
+            },
+        ))
+        .with_default_spacer()
+        .with_child(druid::widget::RadioGroup::new(tile_types()).lens(State::select_tile_type))
+        .with_child(
+            druid::widget::Flex::row()
+                .with_child(select_type_button("Select all visible", false))
+                .with_child(select_type_button("Select all on map", true)),
+        )
+        .with_default_spacer()
+        .with_child(druid::widget::Label::dynamic(
+            |state: &State, _env: &druid::Env| match &state.selection {
+                Some(selection) => {
+                    let mut text = format!("Selected: {} leaves", selection.addresses.len());
+                    for (name, count) in selection.counts.iter() {
+                        text.push_str(&format!("\n  {}: {}", name, count));
+                    }
+                    text
+                }
+                None => "Nothing selected".to_string(),
+            },
+        ))
+        .with_default_spacer()
+        .with_child(bulk_button("Set to water", |_| state::BulkOp::SetWater))
+        .with_default_spacer()
+        .with_child(bulk_button("Set to empty", |_| state::BulkOp::SetEmpty))
+        .with_default_spacer()
+        .with_child(
+            druid::widget::Flex::row()
+                .with_child(bulk_button("Set housing density", |state| {
+                    state::BulkOp::SetHousingDensity(state.bulk_housing_density as usize)
+                }))
+                .with_child(druid::widget::Label::dynamic(
+                    |state: &State, _env: &druid::Env| format!("{}", state.bulk_housing_density),
+                ))
+                .with_child(
+                    druid::widget::Stepper::new()
+                        .with_range(0.0, 1000.0)
+                        .lens(State::bulk_housing_density),
+                ),
+        )
+        .with_default_spacer()
+        .with_child(
+            druid::widget::Flex::row()
+                .with_child(bulk_button("Split to depth", |state| {
+                    state::BulkOp::SplitToDepth(state.bulk_split_depth as u32)
+                }))
+                .with_child(druid::widget::Label::dynamic(
+                    |state: &State, _env: &druid::Env| format!("{}", state.bulk_split_depth),
+                ))
+                .with_child(
+                    druid::widget::Stepper::new()
+                        // depths beyond the max depth of the map are reported as errors
+                        .with_range(0.0, 32.0)
+                        .lens(State::bulk_split_depth),
+                ),
+        )
+        .with_default_spacer()
+        .with_child(druid::widget::Button::new("Clear agents").on_click(
+            |ctx: &mut druid::EventCtx, state: &mut State, _env: &druid::Env| {
+                apply_to_selection(ctx, state, "Clear agents", SelectionOp::ClearAgents);
+            },
+        ))
+        .with_default_spacer()
+        .with_child(druid::widget::Label::dynamic(
+            |state: &State, _env: &druid::Env| match &state.selection {
+                Some(selection) => selection.status.clone(),
+                None => String::new(),
+            },
+        ))
+}
+
 fn build_empty_panel() -> impl druid::Widget<()> {
     druid::widget::Flex::column()
 }
@@ -346,79 +904,81 @@ fn field_data_to_color(
     value.map(|val| druid::Color::hlca(120.0 * val, 80.0, 80.0, 0.5))
 }
 
+/// The map metadata being edited, along with what it was when last applied or saved.
 #[derive(Debug, Clone, druid::Data, druid::Lens)]
-struct MetroLineState {
-    visible: bool,
-}
-
-impl MetroLineState {
-    fn new() -> Self {
-        MetroLineState { visible: false }
-    }
-}
-
-#[derive(Debug, Clone, druid::Data, druid::Lens)]
-struct MetroLinesState {
-    engine: Arc<Mutex<engine::Engine>>,
-    states: druid::im::HashMap<metro::MetroLineHandle, MetroLineState>,
+struct MetadataView {
+    name: String,
+    author: String,
+    description: String,
+    saved: Rc<state::MapMetadata>,
 }
 
-impl MetroLinesState {
-    fn new(engine: Arc<Mutex<engine::Engine>>) -> Self {
-        let mut states = druid::im::HashMap::new();
-
-        {
-            let engine = engine.lock().unwrap();
-            for id in engine.state.metros.metro_lines().keys() {
-                states.insert(*id, MetroLineState::new());
-            }
+impl MetadataView {
+    fn new(metadata: &state::MapMetadata) -> Self {
+        Self {
+            name: metadata.name.clone(),
+            author: metadata.author.clone(),
+            description: metadata.description.clone(),
+            saved: Rc::new(metadata.clone()),
         }
-
-        Self { engine, states }
     }
 }
 
+/// The parts of a metro line that are shown in the metro line list.
 #[derive(Debug, Clone, druid::Data, druid::Lens)]
-struct MetroLineData {
-    metro_line: Arc<Mutex<metro::MetroLine>>,
-    state: MetroLineState,
+struct MetroLineView {
+    #[data(same_fn = "PartialEq::eq")]
+    id: metro::MetroLineHandle,
+    name: String,
+    color: (u8, u8, u8),
+    visible: bool,
 }
 
-impl MetroLineData {
-    fn new(metro_line: &metro::MetroLine, state: &MetroLineState) -> Self {
-        Self {
-            metro_line: Arc::new(Mutex::new(metro_line.clone())),
-            state: state.clone(),
-        }
-    }
+/// Build the metro line list, keeping whether each line was visible in the old list.
+fn metro_line_views(
+    engine: &engine::Engine,
+    old: &druid::im::Vector<MetroLineView>,
+) -> druid::im::Vector<MetroLineView> {
+    engine
+        .state
+        .metros
+        .metro_lines()
+        .iter()
+        .map(|(id, metro_line)| MetroLineView {
+            id: *id,
+            name: metro_line.data.name.clone(),
+            color: metro_line.data.color.into(),
+            visible: old
+                .iter()
+                .find(|view| view.id == *id)
+                .map_or(false, |view| view.visible),
+        })
+        .collect()
 }
 
-impl druid::widget::ListIter<MetroLineData> for MetroLinesState {
-    fn for_each(&self, mut cb: impl FnMut(&MetroLineData, usize)) {
-        let engine = self.engine.lock().unwrap();
+/// Sends an edit to rename the metro line whenever its name is changed in the text box.
+struct RenameMetroLine;
 
-        for (i, (id, metro_line)) in engine.state.metros.metro_lines().iter().enumerate() {
-            // TODO: this clone is disgusting
-            let data = MetroLineData::new(metro_line, &self.states[id]);
-            cb(&data, i);
+impl<W: druid::Widget<MetroLineView>> druid::widget::Controller<MetroLineView, W>
+    for RenameMetroLine
+{
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut druid::EventCtx,
+        event: &druid::Event,
+        data: &mut MetroLineView,
+        env: &druid::Env,
+    ) {
+        let old_name = data.name.clone();
+        child.event(ctx, event, data, env);
+        if data.name != old_name {
+            ctx.submit_command(EDIT.with(Edit::RenameMetroLine {
+                id: data.id,
+                name: data.name.clone(),
+            }));
         }
     }
-
-    fn for_each_mut(&mut self, mut _cb: impl FnMut(&mut MetroLineData, usize)) {
-        let mut _engine = self.engine.lock().unwrap();
-
-        // for (i, (id, metro_line)) in engine.state.metros.metro_lines().iter_mut().enumerate() {
-        //     // TODO: this double clone is even more disgusting
-        //     let mut data = MetroLineData::new(metro_line, &self.states[id]);
-        //     cb(&mut data, i);
-        //     *metro_line = data.metro_line.lock().unwrap().clone();
-        //     *self.states.get_mut(id).unwrap() = data.state;
-        // }
-    }
-
-    fn data_len(&self) -> usize {
-        self.engine.lock().unwrap().state.metros.metro_lines().len()
-    }
 }
 
 #[derive(Debug, Clone, druid::Data, druid::Lens)]
@@ -428,30 +988,121 @@ struct CurrentLeafState {
     tile_type: std::mem::Discriminant<tiles::Tile>,
     data: String,
     edited_data: String,
-
-    engine: Arc<Mutex<engine::Engine>>,
 }
 
 impl CurrentLeafState {
-    fn new(
-        address: quadtree::Address,
-        engine: &engine::Engine,
-        engine_clone: Arc<Mutex<engine::Engine>>,
-    ) -> Self {
-        let leaf = engine.state.qtree.get_leaf(address).unwrap();
-        let data = engine
-            .state
-            .get_leaf_data(address, state::SerdeFormat::Toml)
-            .unwrap();
+    fn new(address: quadtree::Address, qtree: &MapTree) -> Self {
+        let leaf = qtree.get_leaf(address).unwrap();
+        let data = leaf.data(state::SerdeFormat::Toml).unwrap();
         Self {
             address: Rc::new(address),
             leaf: Rc::new(leaf.clone()),
             tile_type: std::mem::discriminant(&leaf.tile),
             data: data.clone(),
             edited_data: data,
-            engine: engine_clone,
         }
     }
+
+    /**
+     * Look up the leaf again after an edit, or None if it is no longer a leaf. Unsaved changes are
+     * kept unless the leaf itself changed.
+     */
+    fn refresh(&self, qtree: &MapTree) -> Option<Self> {
+        qtree.get_leaf(*self.address).ok()?;
+        let new = Self::new(*self.address, qtree);
+        Some(if new.data == self.data {
+            Self {
+                edited_data: self.edited_data.clone(),
+                ..new
+            }
+        } else {
+            new
+        })
+    }
+}
+
+#[derive(Debug, Clone, druid::Data, druid::Lens)]
+struct SelectionState {
+    /// corners of the selection rectangle, in model coordinates so that it survives pan and zoom
+    start: (f64, f64),
+    end: (f64, f64),
+    /// whether the rectangle is still being dragged out
+    dragging: bool,
+    /// if set, only leaves with this type of tile in the rectangle are selected
+    tile_type: Option<std::mem::Discriminant<tiles::Tile>>,
+    addresses: Rc<HashSet<quadtree::Address>>,
+    /// number of selected leaves of each tile type
+    counts: Rc<Vec<(&'static str, usize)>>,
+    /// outcome of the last bulk operation
+    status: String,
+    /// bulk operation on a large selection that is waiting to be confirmed
+    pending: Option<String>,
+}
+
+impl SelectionState {
+    fn new(start: (f64, f64)) -> Self {
+        Self {
+            start,
+            end: start,
+            dragging: true,
+            tile_type: None,
+            addresses: Rc::new(HashSet::new()),
+            counts: Rc::new(Vec::new()),
+            status: String::new(),
+            pending: None,
+        }
+    }
+
+    /// Select all leaves with the given type of tile in the rectangle between two corners.
+    fn of_type(
+        start: (f64, f64),
+        end: (f64, f64),
+        tile_type: std::mem::Discriminant<tiles::Tile>,
+    ) -> Self {
+        Self {
+            end,
+            dragging: false,
+            tile_type: Some(tile_type),
+            ..Self::new(start)
+        }
+    }
+
+    fn rect(&self, model_width: u64) -> quadtree::Rect {
+        let clamp = |v: f64| (v.max(0.0) as u64).min(model_width);
+        quadtree::Rect::corners(
+            clamp(self.start.0.min(self.end.0)),
+            clamp(self.start.1.min(self.end.1)),
+            clamp(self.start.0.max(self.end.0).ceil()),
+            clamp(self.start.1.max(self.end.1).ceil()),
+        )
+    }
+
+    /// Look up the leaves that are currently in the selection rectangle.
+    fn refresh(&mut self, qtree: &MapTree) {
+        use tiles::TileType;
+
+        let rect = self.rect(qtree.width());
+        let addresses = state::leaves_in_rect(qtree, &rect, self.tile_type);
+        let mut counts = BTreeMap::new();
+        for address in &addresses {
+            let leaf = qtree.get_leaf(*address).unwrap();
+            *counts.entry(leaf.tile.name()).or_insert(0) += 1;
+        }
+        self.addresses = Rc::new(addresses.into_iter().collect());
+        self.counts = Rc::new(counts.into_iter().collect());
+    }
+}
+
+/// The smallest node of the quadtree that contains the whole rect.
+fn enclosing_address(rect: &quadtree::Rect, max_depth: u32) -> quadtree::Address {
+    (0..=max_depth)
+        .rev()
+        .map(|depth| quadtree::Address::from_xy_depth(rect.min_x, rect.min_y, depth, max_depth))
+        .find(|address| {
+            let bounds = address.bounds();
+            bounds.max_x >= rect.max_x && bounds.max_y >= rect.max_y
+        })
+        .unwrap()
 }
 
 #[derive(Debug, Clone, druid::Data, druid::Lens)]
@@ -463,36 +1114,75 @@ struct ContentState {
     min_scale: f64,
     max_scale: f64,
 
+    model_width: f64,
+    /// size of the content widget, which isn't known until it is laid out
+    size: (f64, f64),
+    /// whether the user has panned or zoomed since the map was last fit to the content widget
+    interacted: bool,
+
     mouse_pos: Option<druid::Point>,
 }
 
 impl ContentState {
-    pub fn new(engine: Arc<Mutex<engine::Engine>>) -> Self {
-        let (mut width, height) = DEFAULT_WINDOW_SIZE as (f64, f64);
-        // account for the two side panels
-        // TODO: make this less gross
-        width -= 600.0;
+    pub fn new(model_width: f64) -> Self {
+        // NOTE: the real values are computed once the content widget is laid out; see set_size
+        Self {
+            scale: 1.0,
+            tx: 0.0,
+            ty: 0.0,
+            min_scale: 0.0,
+            max_scale: 100.0,
+            model_width,
+            size: (0.0, 0.0),
+            interacted: false,
+            mouse_pos: None,
+        }
+    }
 
-        let min_dim = f64::min(width, height);
-        let model_width = engine.lock().unwrap().state.qtree.width() as f64;
+    fn region(&self) -> viewport::Region {
+        // the content widget has its own coordinate space, so the side panels don't matter here
+        viewport::Region::new(0.0, 0.0, self.size.0, self.size.1)
+    }
 
-        let scale = min_dim / model_width / 2.0;
-        let tx = width / 2.0 - model_width * scale / 2.0;
-        let ty = height / 2.0 - model_width * scale / 2.0;
+    fn view(&self) -> viewport::View {
+        viewport::View {
+            scale: self.scale,
+            tx: self.tx,
+            ty: self.ty,
+        }
+    }
 
-        let min_scale = min_dim / model_width / 2.0;
-        let max_scale = 100.0;
+    fn set_view(&mut self, view: viewport::View) {
+        self.scale = view.scale;
+        self.tx = view.tx;
+        self.ty = view.ty;
+    }
 
-        Self {
-            scale,
-            tx,
-            ty,
-            min_scale,
-            max_scale,
-            mouse_pos: None,
+    /**
+     * Update the size of the content widget. The zoom limits are recomputed, and the map is re-fit
+     * to the new size if the user hasn't moved the camera yet.
+     */
+    pub fn set_size(&mut self, size: druid::Size) {
+        if size.width <= 0.0 || size.height <= 0.0 {
+            return;
+        }
+        self.size = (size.width, size.height);
+        let region = self.region();
+        self.min_scale = region.min_scale(self.model_width);
+
+        if !self.interacted {
+            self.set_view(viewport::View::fit(self.model_width, &region));
+        } else if self.scale < self.min_scale {
+            self.set_view(self.view().zoom_about(self.min_scale, region.center()));
         }
     }
 
+    /// Fit the whole map into the content widget, as when the editor was started.
+    pub fn reset(&mut self) {
+        self.interacted = false;
+        self.set_view(viewport::View::fit(self.model_width, &self.region()));
+    }
+
     pub fn to_screen(&self, (x, y): (u64, u64)) -> (f64, f64) {
         self.to_screenf((x as f64, y as f64))
     }
@@ -521,7 +1211,44 @@ impl druid::Widget<State> for Content {
     ) {
         let content = &mut state.content;
         use druid::Event::*;
+        if let MouseDown(_) = event {
+            // needed to receive key events
+            ctx.request_focus();
+        }
+        let dragging_selection = matches!(&state.selection, Some(selection) if selection.dragging);
         match event {
+            MouseDown(mouse) if mouse.buttons.has_left() && mouse.mods.shift() => {
+                let pos = content.view().to_model(mouse.pos.into());
+                state.selection = Some(SelectionState::new(pos));
+                ctx.request_paint();
+            }
+            MouseDown(mouse) if mouse.buttons.has_left() && mouse.mods.ctrl() => {
+                let (mx, my) = content.to_model(mouse.pos.into());
+                let w = state.map.qtree.width();
+                if let (Some(depth), true) = (state.clipboard_depth, mx < w && my < w) {
+                    let max_depth = state.map.qtree.max_depth();
+                    let address = quadtree::Address::from_xy_depth(mx, my, depth, max_depth);
+                    ctx.submit_command(EDIT.with(Edit::Paste(address)));
+                }
+            }
+            MouseMove(mouse) if dragging_selection => {
+                if let Some(selection) = &mut state.selection {
+                    selection.end = content.view().to_model(mouse.pos.into());
+                }
+                ctx.request_paint();
+            }
+            MouseUp(mouse) if dragging_selection => {
+                if let Some(selection) = &mut state.selection {
+                    selection.end = content.view().to_model(mouse.pos.into());
+                    selection.dragging = false;
+                    selection.refresh(&state.map.qtree);
+                }
+                ctx.request_paint();
+            }
+            KeyDown(key) if key.key == druid::KbKey::Escape => {
+                state.selection = None;
+                ctx.request_paint();
+            }
             MouseDown(mouse) | MouseUp(mouse) if mouse.buttons.has_left() => {
                 content.mouse_pos = None;
             }
@@ -530,73 +1257,40 @@ impl druid::Widget<State> for Content {
                     let diff = mouse.pos - old;
                     content.tx += diff.x;
                     content.ty += diff.y;
+                    content.interacted = true;
                     ctx.request_paint();
                 }
                 content.mouse_pos = Some(mouse.pos)
             }
             Wheel(mouse) => {
-                let new_scale = f64::max(
-                    f64::min(
-                        content.scale * 1.1_f64.powf(-mouse.wheel_delta.y / 10.0),
-                        content.max_scale,
-                    ),
-                    content.min_scale,
+                // zoom centered on mouse
+                let view = content.view().clamped_zoom_about(
+                    content.scale * 1.1_f64.powf(-mouse.wheel_delta.y / 10.0),
+                    (content.min_scale, content.max_scale),
+                    mouse.pos.into(),
                 );
-
-                let mx = mouse.pos.x;
-                let my = mouse.pos.y;
-
-                // Zoom centered on mouse
-                content.tx =
-                    (mx * content.scale - mx * new_scale + content.tx * new_scale) / content.scale;
-                content.ty =
-                    (my * content.scale - my * new_scale + content.ty * new_scale) / content.scale;
-
-                content.scale = new_scale;
+                content.set_view(view);
+                content.interacted = true;
+                ctx.request_paint();
+            }
+            Command(command) if command.is(CONTENT_RESIZED) => {
+                content.set_size(*command.get_unchecked(CONTENT_RESIZED));
                 ctx.request_paint();
             }
             MouseDown(mouse) if mouse.buttons.has_right() => {
-                let mut engine = state.engine.lock().unwrap();
                 let (mx, my) = content.to_model(mouse.pos.into());
-                let w = engine.state.qtree.width();
+                let w = state.map.qtree.width();
                 if mx > 0 && mx < w && my > 0 && my < w {
-                    let address = engine.state.qtree.get_address(mx, my).unwrap();
-                    if address.depth() < engine.state.qtree.max_depth() as usize {
-                        use state::{BranchState, LeafState};
-                        engine
-                            .state
-                            .qtree
-                            .split(
-                                address,
-                                BranchState::default(),
-                                quadtree::QuadMap::new(
-                                    LeafState::default(),
-                                    LeafState::default(),
-                                    LeafState::default(),
-                                    LeafState::default(),
-                                ),
-                            )
-                            .unwrap();
-                        if let Some(current_leaf) = &state.current_leaf {
-                            if *current_leaf.address == address {
-                                state.current_leaf = None;
-                            }
-                        }
-                        ctx.request_paint();
-                    }
+                    let address = state.map.qtree.get_address(mx, my).unwrap();
+                    ctx.submit_command(EDIT.with(Edit::SplitTile(address)));
                 }
             }
             MouseDown(mouse) if mouse.buttons.has_middle() => {
-                let engine = state.engine.lock().unwrap();
                 let (mx, my) = content.to_model(mouse.pos.into());
-                let w = engine.state.qtree.width();
+                let w = state.map.qtree.width();
                 if mx > 0 && mx < w && my > 0 && my < w {
-                    let address = engine.state.qtree.get_address(mx, my).unwrap();
-                    state.current_leaf = Some(CurrentLeafState::new(
-                        address,
-                        &engine,
-                        state.engine.clone(),
-                    ));
+                    let address = state.map.qtree.get_address(mx, my).unwrap();
+                    state.current_leaf = Some(CurrentLeafState::new(address, &state.map.qtree));
                 } else {
                     state.current_leaf = None;
                 }
@@ -608,11 +1302,15 @@ impl druid::Widget<State> for Content {
 
     fn lifecycle(
         &mut self,
-        _ctx: &mut druid::LifeCycleCtx<'_, '_>,
-        _event: &druid::LifeCycle,
+        ctx: &mut druid::LifeCycleCtx<'_, '_>,
+        event: &druid::LifeCycle,
         _state: &State,
         _env: &druid::Env,
     ) {
+        if let druid::LifeCycle::Size(size) = event {
+            // the state can't be modified here, so handle this as an event instead
+            ctx.submit_command(CONTENT_RESIZED.with(*size).to(ctx.widget_id()));
+        }
     }
 
     fn update(
@@ -642,7 +1340,9 @@ impl druid::Widget<State> for Content {
     fn paint(&mut self, ctx: &mut druid::PaintCtx, state: &State, env: &druid::Env) {
         use itertools::Itertools;
 
-        let engine = state.engine.lock().unwrap();
+        let _span = tracing::debug_span!("paint").entered();
+
+        let map = &state.map;
 
         let (x1, y1) = state.content.to_model((0.0, 0.0));
         let (x2, y2) = state.content.to_model(ctx.size().into());
@@ -656,11 +1356,10 @@ impl druid::Widget<State> for Content {
             visited: 0,
         };
         if state.show_qtree {
-            engine
-                .state
-                .qtree
-                .visit_rect(&mut qtree_visitor, &bounding_box)
-                .unwrap();
+            if let Err(err) = map.qtree.visit_rect(&mut qtree_visitor, &bounding_box) {
+                // the state can't be modified while painting, so the error can only be logged
+                tracing::error!(%err, "failed to paint tiles");
+            }
         }
 
         // 5 pixel resolution
@@ -670,12 +1369,14 @@ impl druid::Widget<State> for Content {
         let mut metro_total_visited = 0;
 
         if state.show_metros {
-            for (_id, segment) in engine.state.railways.segments().iter().sorted() {
+            for (_id, segment) in map.railways.segments().iter().sorted() {
                 let mut spline_visitor =
                     PaintSplineVisitor::new(ctx, env, state, state.show_metro_directions);
-                segment
-                    .visit_spline(&mut spline_visitor, spline_scale, &bounding_box)
-                    .unwrap();
+                if let Err(err) =
+                    segment.visit_spline(&mut spline_visitor, spline_scale, &bounding_box)
+                {
+                    tracing::error!(%err, "failed to paint railway segment");
+                }
                 metro_total_visited += &spline_visitor.visited;
 
                 // TODO: show metro keys in new system
@@ -685,20 +1386,22 @@ impl druid::Widget<State> for Content {
         let mut highway_total_visited = 0;
 
         if state.show_highways {
-            for (_, highway_segment) in engine.state.highways.segments().iter().sorted() {
+            for (_, highway_segment) in map.highways.segments().iter().sorted() {
                 let mut spline_visitor =
                     PaintSplineVisitor::new(ctx, env, state, state.show_highway_directions);
-                highway_segment
-                    .visit_spline(&mut spline_visitor, spline_scale, &bounding_box)
-                    .unwrap();
+                if let Err(err) =
+                    highway_segment.visit_spline(&mut spline_visitor, spline_scale, &bounding_box)
+                {
+                    tracing::error!(%err, "failed to paint highway segment");
+                }
                 highway_total_visited += &spline_visitor.visited;
 
                 if state.show_highway_keys {
                     let mut key_visitor = PaintHighwayKeysVisitor { ctx, env, state };
 
-                    highway_segment
-                        .visit_keys(&mut key_visitor, &bounding_box)
-                        .unwrap();
+                    if let Err(err) = highway_segment.visit_keys(&mut key_visitor, &bounding_box) {
+                        tracing::error!(%err, "failed to paint highway keys");
+                    }
 
                     // draw start and end
                     let keys = highway_segment.keys();
@@ -724,9 +1427,22 @@ impl druid::Widget<State> for Content {
             }
         }
 
-        println!(
-            "qtree: {}, metros: {}, highways: {}",
-            qtree_visited, metro_total_visited, highway_total_visited
+        // selections by type are shown by highlighting the selected leaves instead
+        if let Some(selection) = state.selection.as_ref().filter(|s| s.tile_type.is_none()) {
+            use druid::RenderContext;
+
+            let rect = druid::Rect::from_points(
+                state.content.to_screenf(selection.start),
+                state.content.to_screenf(selection.end),
+            );
+            ctx.stroke(rect, &druid::Color::rgb8(255, 200, 0), 1.0);
+        }
+
+        tracing::trace!(
+            qtree = qtree_visited,
+            metros = metro_total_visited,
+            highways = highway_total_visited,
+            "painted"
         );
     }
 }
@@ -782,22 +1498,30 @@ impl<'a, 'b, 'c, 'd, 'e, 'f>
     quadtree::Visitor<
         state::BranchState<engine::FieldsState>,
         state::LeafState<engine::FieldsState>,
-        anyhow::Error,
+        engine::Error,
     > for PaintQtreeVisitor<'a, 'b, 'c, 'd, 'e, 'f>
 {
     fn visit_branch_pre(
         &mut self,
-        _branch: &state::BranchState<engine::FieldsState>,
+        branch: &state::BranchState<engine::FieldsState>,
         data: &quadtree::VisitData,
-    ) -> anyhow::Result<bool> {
+    ) -> Result<bool, engine::Error> {
         let should_descend = data.width as f64 * self.state.content.scale >= 5.0;
 
         if !should_descend {
             use druid::RenderContext;
 
-            // draw a rectangle to indicate that there's stuff here
+            // draw a rectangle to indicate that there's stuff here, shaded by how much of it is
+            // water so that coastlines stay visible
+            let water = branch.fields.terrain.water_fraction();
+            let mix = |land: u8, water_value: u8| {
+                (land as f64 + (water_value as f64 - land as f64) * water).round() as u8
+            };
             let full_rect = self.get_full_rect(data);
-            self.ctx.fill(full_rect, &druid::Color::grey8(100));
+            self.ctx.fill(
+                full_rect,
+                &druid::Color::rgb8(mix(100, 0), mix(100, 0), mix(100, 150)),
+            );
         }
 
         Ok(should_descend)
@@ -807,7 +1531,7 @@ impl<'a, 'b, 'c, 'd, 'e, 'f>
         &mut self,
         leaf: &state::LeafState<engine::FieldsState>,
         data: &quadtree::VisitData,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), engine::Error> {
         use druid::RenderContext;
 
         let width = data.width as f64 * self.state.content.scale;
@@ -837,13 +1561,27 @@ impl<'a, 'b, 'c, 'd, 'e, 'f>
                 );
                 self.ctx.fill(&triangle[..], &druid::Color::grey8(255));
             }
-            MetroStationTile(tiles::MetroStationTile { .. }) => {
+            MetroStationTile(tiles::MetroStationTile { ids, .. }) => {
                 let circle = druid::kurbo::Circle::new(rect.center(), width / 4.0);
-                self.ctx.stroke(circle, &druid::Color::grey8(255), 1.0);
+                if ids.is_empty() {
+                    // orphan stations aren't served by any metro line yet
+                    let style = druid::piet::StrokeStyle::new().dash_pattern(&[3.0, 3.0]);
+                    self.ctx
+                        .stroke_styled(circle, &druid::Color::grey8(255), 1.0, &style);
+                } else {
+                    self.ctx.stroke(circle, &druid::Color::grey8(255), 1.0);
+                }
             }
             _ => (),
         }
 
+        if let Some(selection) = &self.state.selection {
+            if selection.addresses.contains(&data.address) {
+                self.ctx
+                    .fill(full_rect, &druid::Color::rgba8(255, 200, 0, 60));
+            }
+        }
+
         self.maybe_draw_field(&leaf.fields, data, true);
 
         self.visited += 1;
@@ -855,7 +1593,7 @@ impl<'a, 'b, 'c, 'd, 'e, 'f>
         &mut self,
         branch: &state::BranchState<engine::FieldsState>,
         data: &quadtree::VisitData,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), engine::Error> {
         self.maybe_draw_field(&branch.fields, data, false);
         Ok(())
     }
@@ -914,7 +1652,7 @@ impl<'a, 'b, 'c, 'd, 'e, 'f> PaintSplineVisitor<'a, 'b, 'c, 'd, 'e, 'f> {
         vertex: cgmath::Vector2<f64>,
         _t: f64,
         prev: Option<cgmath::Vector2<f64>>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), engine::Error> {
         use druid::RenderContext;
 
         let point = (
@@ -965,7 +1703,7 @@ impl<'a, 'b, 'c, 'd, 'e, 'f>
     spline_util::SplineVisitor<
         network::Segment<metro::RailwaySegment>,
         cgmath::Vector2<f64>,
-        anyhow::Error,
+        engine::Error,
     > for PaintSplineVisitor<'a, 'b, 'c, 'd, 'e, 'f>
 {
     fn visit(
@@ -974,7 +1712,7 @@ impl<'a, 'b, 'c, 'd, 'e, 'f>
         vertex: cgmath::Vector2<f64>,
         t: f64,
         prev: Option<cgmath::Vector2<f64>>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), engine::Error> {
         // TODO: display metro line colors with new system
         let color = druid::Color::grey8(255);
         self.visit(&color, 2.0, vertex, t, prev)
@@ -985,7 +1723,7 @@ impl<'a, 'b, 'c, 'd, 'e, 'f>
     spline_util::SplineVisitor<
         network::Segment<highway::HighwaySegment>,
         cgmath::Vector2<f64>,
-        anyhow::Error,
+        engine::Error,
     > for PaintSplineVisitor<'a, 'b, 'c, 'd, 'e, 'f>
 {
     fn visit(
@@ -994,7 +1732,7 @@ impl<'a, 'b, 'c, 'd, 'e, 'f>
         vertex: cgmath::Vector2<f64>,
         t: f64,
         prev: Option<cgmath::Vector2<f64>>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), engine::Error> {
         self.visit(&druid::Color::grey8(204), 1.0, vertex, t, prev)
     }
 }
@@ -1006,14 +1744,14 @@ struct PaintHighwayKeysVisitor<'a, 'b, 'c, 'd, 'e, 'f> {
     state: &'f State,
 }
 
-impl<'a, 'b, 'c, 'd, 'e, 'f> network::KeyVisitor<highway::HighwaySegment, anyhow::Error>
+impl<'a, 'b, 'c, 'd, 'e, 'f> network::KeyVisitor<highway::HighwaySegment, engine::Error>
     for PaintHighwayKeysVisitor<'a, 'b, 'c, 'd, 'e, 'f>
 {
     fn visit(
         &mut self,
         _segment: &network::Segment<highway::HighwaySegment>,
         key: &network::Key,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), engine::Error> {
         use druid::RenderContext;
 
         let point = (